@@ -0,0 +1,61 @@
+//! Times wyag's core operations on a synthetic repository. Run with
+//! `cargo bench --features bench`. The repository's size can be set with the
+//! `WYAG_BENCH_COMMITS` and `WYAG_BENCH_FILES` environment variables.
+
+use std::{hint::black_box, time::Instant};
+
+use wyag::{
+    bench::SyntheticRepo,
+    index::Index,
+    object::{Blob, GitObject},
+    workdir::WorkPathBuf,
+};
+
+/// Runs `f` once to warm up, then `iterations` times, and prints the average time.
+fn measure<T>(name: &str, iterations: u32, mut f: impl FnMut() -> T) {
+    black_box(f());
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+
+    println!("{name:<24}{:>12.2?}", start.elapsed() / iterations);
+}
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name).ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn main() {
+    let commits = env_or("WYAG_BENCH_COMMITS", 20);
+    let files = env_or("WYAG_BENCH_FILES", 2000);
+    let temp_dir = std::env::temp_dir().join(format!("wyag-bench-{}", std::process::id()));
+
+    let start = Instant::now();
+    let mut synthetic = SyntheticRepo::generate(&temp_dir, commits, files).unwrap();
+    println!("Generated {commits} commits of {files} files in {:.2?}", start.elapsed());
+
+    let wd = synthetic.repo.workdir();
+    let index_data = std::fs::read(wd.git_path("index")).unwrap();
+    measure("Index::parse", 100, || Index::parse(&index_data).unwrap());
+    measure("Index::serialize", 100, || synthetic.index.serialize().unwrap());
+
+    let blob_hash = synthetic.index.entries.values().next().unwrap().hash;
+    measure("GitObject::read", 1000, || GitObject::read(wd, &blob_hash).unwrap());
+    let mut count = 0;
+    measure("GitObject::write", 1000, || {
+        count += 1;
+        let blob = Blob::deserialize(format!("new blob {count}\n").into_bytes()).unwrap();
+        GitObject::Blob(blob).write(wd).unwrap()
+    });
+
+    let root = WorkPathBuf::root();
+    measure("status enumeration", 10, || synthetic.index.list_unstaged_changes(wd, &root, false).unwrap());
+
+    measure("commit creation", 10, || synthetic.commit_changes().unwrap());
+
+    std::fs::remove_dir_all(&temp_dir).unwrap();
+}
@@ -0,0 +1,249 @@
+use std::io::Write;
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+    object::{Blob, Tree, TreeEntry, FileMode},
+};
+
+/// The size of a tar block. Headers and file contents are padded to a multiple of this.
+const BLOCK_SIZE: usize = 512;
+
+/// The size of a tar record. The archive is padded to a multiple of this, same as git.
+const RECORD_SIZE: usize = 20 * BLOCK_SIZE;
+
+/// Writes the contents of `tree` to `out` as a tar archive.
+///
+/// Every path is preceded by `prefix` (e.g. `project/`). If `paths` isn't empty, only those
+/// files and directories (relative to `tree`) are included. `mtime` is the modification
+/// time recorded for every entry, in seconds since the Unix epoch.
+pub fn write_tar<W>(wd: &WorkDir, tree: &Tree, prefix: &str, paths: &[WorkPathBuf], mtime: i64, mut out: W) -> Result<()>
+where
+    W: Write
+{
+    let mut writer = TarWriter {
+        wd,
+        prefix,
+        paths,
+        mtime,
+        matched: vec![false; paths.len()],
+        written: 0,
+    };
+
+    if prefix.ends_with('/') {
+        writer.write_header(&mut out, "", 0o775, 0, b'5', "")?;
+    }
+    writer.write_tree(tree, &WorkPathBuf::root(), &mut out)?;
+
+    if let Some(index) = writer.matched.iter().position(|matched| !matched) {
+        return Err(ArchiveError::PathNotFound(paths[index].clone()).into());
+    }
+
+    // The archive ends with two empty blocks, then is padded to a whole record
+    let end_len = 2 * BLOCK_SIZE;
+    let padding = (RECORD_SIZE - (writer.written + end_len) % RECORD_SIZE) % RECORD_SIZE;
+    out.write_all(&vec![0; end_len + padding])?;
+
+    Ok(())
+}
+
+struct TarWriter<'a> {
+    wd: &'a WorkDir,
+    prefix: &'a str,
+    paths: &'a [WorkPathBuf],
+    mtime: i64,
+    /// Whether each of `paths` has been found in the tree.
+    matched: Vec<bool>,
+    /// The number of bytes written so far.
+    written: usize,
+}
+
+impl TarWriter<'_> {
+    fn write_tree<W: Write>(&mut self, tree: &Tree, dir: &WorkPath, out: &mut W) -> Result<()> {
+        for (name, entry) in &tree.entries {
+            let path = dir.to_owned().join(name);
+            let (selected, contains_selected) = self.check_path(&path);
+            if !selected && !contains_selected {
+                continue;
+            }
+
+            if entry.is_gitlink() {
+                // Same as git, a submodule is archived as an empty directory
+                if selected {
+                    self.write_header(out, &format!("{path}/"), 0o775, 0, b'5', "")?;
+                }
+            }
+            else if entry.is_dir() {
+                self.write_header(out, &format!("{path}/"), 0o775, 0, b'5', "")?;
+                let subtree = Tree::read(self.wd, &entry.hash)?;
+                self.write_tree(&subtree, &path, out)?;
+            }
+            else if selected {
+                self.write_file(out, &path, entry)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Determines whether `path` was requested (or is inside a requested directory), and
+    /// whether it's a directory containing a requested path.
+    fn check_path(&mut self, path: &WorkPath) -> (bool, bool) {
+        if self.paths.is_empty() {
+            return (true, false);
+        }
+
+        let mut selected = false;
+        let mut contains_selected = false;
+        for (requested, matched) in self.paths.iter().zip(self.matched.iter_mut()) {
+            if is_ancestor_or_self(requested, path) {
+                selected = true;
+                *matched = true;
+            }
+            else if is_ancestor_or_self(path, requested) {
+                contains_selected = true;
+            }
+        }
+
+        (selected, contains_selected)
+    }
+
+    fn write_file<W: Write>(&mut self, out: &mut W, path: &WorkPath, entry: &TreeEntry) -> Result<()> {
+        let data = Blob::read(self.wd, &entry.hash)?.serialize_into();
+
+        match entry.mode {
+            FileMode::Symlink => {
+                // The blob holds the target
+                let target = String::from_utf8_lossy(&data);
+                self.write_header(out, path.as_str(), 0o777, 0, b'2', &target)?;
+            },
+            mode => {
+                let mode = match mode {
+                    FileMode::Executable => 0o775,
+                    _ => 0o664,
+                };
+                self.write_header(out, path.as_str(), mode, data.len(), b'0', "")?;
+                out.write_all(&data)?;
+
+                let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+                out.write_all(&vec![0; padding])?;
+                self.written += data.len() + padding;
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Writes a ustar header block.
+    fn write_header<W: Write>(&mut self, out: &mut W, path: &str, mode: u32, size: usize, kind: u8, link: &str) -> Result<()> {
+        let full_path = format!("{}{path}", self.prefix);
+        let (path_prefix, name) = split_ustar_path(&full_path)
+            .ok_or_else(|| ArchiveError::PathTooLong(full_path.clone()))?;
+        if link.len() > 100 {
+            return Err(ArchiveError::PathTooLong(link.to_owned()).into());
+        }
+
+        let mut header = [0u8; BLOCK_SIZE];
+        header[0..name.len()].copy_from_slice(name.as_bytes());
+        write_octal(&mut header[100..108], mode as u64);
+        write_octal(&mut header[108..116], 0); // uid
+        write_octal(&mut header[116..124], 0); // gid
+        write_octal(&mut header[124..136], size as u64);
+        write_octal(&mut header[136..148], self.mtime.max(0) as u64);
+        header[156] = kind;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[265..269].copy_from_slice(b"root");
+        header[297..301].copy_from_slice(b"root");
+        header[345..345 + path_prefix.len()].copy_from_slice(path_prefix.as_bytes());
+
+        // The checksum is computed as if the checksum field were all spaces
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+        write_octal(&mut header[148..155], checksum as u64);
+
+        out.write_all(&header)?;
+        self.written += BLOCK_SIZE;
+
+        Ok(())
+    }
+}
+
+/// Returns true if `ancestor` is `path` or one of the directories containing it.
+fn is_ancestor_or_self(ancestor: &WorkPath, path: &WorkPath) -> bool {
+    ancestor.is_empty()
+        || path.as_str() == ancestor.as_str()
+        || path.as_str().strip_prefix(ancestor.as_str()).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Splits a path into the ustar prefix (up to 155 bytes) and name (up to 100 bytes)
+/// fields. Returns `None` if the path is too long to be split.
+fn split_ustar_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+
+    // Split at a slash, ignoring a trailing one (which marks a directory)
+    let search = path.strip_suffix('/').unwrap_or(path);
+    search.match_indices('/')
+        .map(|(index, _)| index)
+        .filter(|index| *index <= 155 && path.len() - index - 1 <= 100)
+        .map(|index| (&path[..index], &path[index + 1..]))
+        .next()
+}
+
+/// Writes `value` as a zero-padded, null-terminated octal number filling `field`.
+fn write_octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{value:0width$o}");
+    field[..width].copy_from_slice(digits.as_bytes());
+    field[width] = 0;
+}
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("The path `{0}` did not match any files")]
+    PathNotFound(WorkPathBuf),
+    #[error("The path `{0}` is too long to store in a tar archive")]
+    PathTooLong(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_long_paths() {
+        assert_eq!(split_ustar_path("a/b"), Some(("", "a/b")));
+
+        let dir = "d".repeat(120);
+        let path = format!("{dir}/file");
+        assert_eq!(split_ustar_path(&path), Some((dir.as_str(), "file")));
+
+        let too_long = "f".repeat(101);
+        assert_eq!(split_ustar_path(&too_long), None);
+    }
+
+    #[test]
+    fn octal_fields() {
+        let mut field = [0xff; 8];
+        write_octal(&mut field, 0o664);
+        assert_eq!(&field, b"0000664\0");
+    }
+
+    #[test]
+    fn ancestor_paths() {
+        let src = WorkPathBuf::try_from("src").unwrap();
+        let lib = WorkPathBuf::try_from("src/lib.rs").unwrap();
+        let other = WorkPathBuf::try_from("src2").unwrap();
+
+        assert!(is_ancestor_or_self(&src, &lib));
+        assert!(is_ancestor_or_self(&lib, &lib));
+        assert!(!is_ancestor_or_self(&lib, &src));
+        assert!(!is_ancestor_or_self(&src, &other));
+        assert!(is_ancestor_or_self(&WorkPathBuf::root(), &src));
+    }
+}
@@ -0,0 +1,358 @@
+use std::{collections::HashMap, fmt, fs};
+
+use anyhow::Context;
+
+use crate::{
+    Result,
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+};
+
+/// The name of the files that assign attributes to paths.
+const ATTRIBUTES_FILE_NAME: &str = ".gitattributes";
+
+/// The value of an attribute for a particular path.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AttrValue {
+    /// The attribute is set (`text`).
+    Set,
+    /// The attribute is unset (`-text`).
+    Unset,
+    /// The attribute has a value (`eol=lf`).
+    Value(String),
+    /// No rule applies to the attribute, or it was reset (`!text`).
+    Unspecified,
+}
+
+impl fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Set => write!(f, "set"),
+            Self::Unset => write!(f, "unset"),
+            Self::Value(value) => write!(f, "{value}"),
+            Self::Unspecified => write!(f, "unspecified"),
+        }
+    }
+}
+
+/// A single attribute assignment, e.g. `-diff` or `eol=crlf`.
+type Assignment = (String, AttrValue);
+
+/// A line of a `.gitattributes` file: a pattern and the attributes it assigns.
+struct AttrRule {
+    pattern: String,
+    assignments: Vec<Assignment>,
+}
+
+/// The parsed contents of a `.gitattributes` file.
+struct AttrFile {
+    /// The directory containing the file. Patterns are relative to it.
+    dir: WorkPathBuf,
+    rules: Vec<AttrRule>,
+}
+
+/// The attributes assigned to a path by the repository's `.gitattributes` files and
+/// `.git/info/attributes`.
+pub struct PathAttributes {
+    /// The specified attributes, in the order they were first assigned.
+    values: Vec<Assignment>,
+}
+
+impl PathAttributes {
+    /// Resolves the attributes of `path`.
+    ///
+    /// Files deeper in the tree take precedence over the root `.gitattributes`, and
+    /// `.git/info/attributes` takes precedence over all of them. Within a file, later lines
+    /// take precedence over earlier ones.
+    pub fn resolve(wd: &WorkDir, path: &WorkPath) -> Result<Self> {
+        let mut macros = builtin_macros();
+        let mut files = Vec::new();
+
+        // Macros may only be defined at the top level
+        let root = WorkPathBuf::root();
+        let root_path = wd.as_path().join(ATTRIBUTES_FILE_NAME);
+        files.extend(AttrFile::read(&root_path, &root, Some(&mut macros))?);
+
+        for (index, _) in path.as_str().match_indices('/') {
+            let dir = WorkPath::from_canonical(&path.as_str()[..index])
+                .expect("ancestor of a canonical path should be canonical");
+            let file_path = wd.abs_path(dir).join(ATTRIBUTES_FILE_NAME);
+            files.extend(AttrFile::read(&file_path, dir, None)?);
+        }
+
+        let info_path = wd.git_path("info/attributes");
+        files.extend(AttrFile::read(&info_path, &root, Some(&mut macros))?);
+
+        let mut values: Vec<Assignment> = Vec::new();
+        let mut assign = |name: &str, value: &AttrValue| {
+            match values.iter_mut().find(|(existing, _)| existing == name) {
+                Some((_, existing)) => *existing = value.clone(),
+                None => values.push((name.to_owned(), value.clone())),
+            }
+        };
+
+        for file in &files {
+            for rule in file.rules.iter().filter(|rule| file.matches(&rule.pattern, path)) {
+                for (name, value) in &rule.assignments {
+                    assign(name, value);
+
+                    if *value == AttrValue::Set {
+                        for (name, value) in macros.get(name).into_iter().flatten() {
+                            assign(name, value);
+                        }
+                    }
+                }
+            }
+        }
+
+        values.retain(|(_, value)| *value != AttrValue::Unspecified);
+
+        Ok(Self { values })
+    }
+
+    /// Returns the value of the attribute `name`.
+    pub fn get(&self, name: &str) -> &AttrValue {
+        self.values.iter()
+            .find(|(existing, _)| existing == name)
+            .map_or(&AttrValue::Unspecified, |(_, value)| value)
+    }
+
+    /// Iterates over the attributes that are specified for the path.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &AttrValue)> {
+        self.values.iter().map(|(name, value)| (name.as_str(), value))
+    }
+}
+
+impl AttrFile {
+    /// Reads the attributes file at `abs_path`, which applies to paths in `dir`. Returns
+    /// `None` if there is no such file. Macro definitions are added to `macros` if given,
+    /// or ignored otherwise.
+    fn read(abs_path: &std::path::Path, dir: &WorkPath, mut macros: Option<&mut HashMap<String, Vec<Assignment>>>) -> Result<Option<Self>> {
+        if !abs_path.is_file() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(abs_path)
+            .with_context(|| format!("Failed to read attributes file at `{abs_path:?}`"))?;
+        let mut rules = Vec::new();
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+            let pattern = tokens.next().expect("line should not be empty");
+            let assignments: Vec<Assignment> = tokens.map(parse_assignment).collect();
+
+            if let Some(name) = pattern.strip_prefix("[attr]") {
+                if let Some(macros) = macros.as_deref_mut() {
+                    macros.insert(name.to_owned(), assignments);
+                }
+            }
+            else {
+                rules.push(AttrRule {
+                    pattern: pattern.to_owned(),
+                    assignments,
+                });
+            }
+        }
+
+        Ok(Some(Self {
+            dir: dir.to_owned(),
+            rules,
+        }))
+    }
+
+    /// Returns true if `pattern` (from this file) matches `path`.
+    ///
+    /// A pattern without a slash matches the file name at any depth. Otherwise, it matches
+    /// the whole path relative to the directory containing this file.
+    fn matches(&self, pattern: &str, path: &WorkPath) -> bool {
+        let rel_path = match path.strip_prefix(&self.dir) {
+            Some(rel_path) => rel_path.as_str(),
+            None => return false,
+        };
+
+        // Directory patterns never match files
+        if pattern.ends_with('/') {
+            return false;
+        }
+
+        if pattern.contains('/') {
+            let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+            wildmatch(pattern.as_bytes(), rel_path.as_bytes())
+        }
+        else {
+            let file_name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+            wildmatch(pattern.as_bytes(), file_name.as_bytes())
+        }
+    }
+}
+
+/// The macros git defines without any configuration.
+fn builtin_macros() -> HashMap<String, Vec<Assignment>> {
+    HashMap::from([
+        ("binary".to_owned(), vec![
+            ("diff".to_owned(), AttrValue::Unset),
+            ("merge".to_owned(), AttrValue::Unset),
+            ("text".to_owned(), AttrValue::Unset),
+        ]),
+    ])
+}
+
+/// Parses an attribute assignment like `text`, `-text`, `!text`, or `eol=lf`.
+fn parse_assignment(token: &str) -> Assignment {
+    if let Some(name) = token.strip_prefix('-') {
+        (name.to_owned(), AttrValue::Unset)
+    }
+    else if let Some(name) = token.strip_prefix('!') {
+        (name.to_owned(), AttrValue::Unspecified)
+    }
+    else if let Some((name, value)) = token.split_once('=') {
+        (name.to_owned(), AttrValue::Value(value.to_owned()))
+    }
+    else {
+        (token.to_owned(), AttrValue::Set)
+    }
+}
+
+/// Matches `text` against the glob `pattern`, where `*` and `?` don't match `/` but `**`
+/// matches any number of directories.
+pub(crate) fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match_glob(pattern, text, true)
+}
+
+/// Matches `text` against the glob `pattern` like `fnmatch` without `FNM_PATHNAME`, so
+/// `/` is an ordinary character that `*` and `?` match too.
+pub(crate) fn fnmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match_glob(pattern, text, false)
+}
+
+/// Matches a glob, treating `/` specially (as [`wildmatch`] does) if `pathname` is set.
+fn match_glob(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pathname && pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[2..];
+            if let Some(after_slash) = rest.strip_prefix(b"/") {
+                // `**/` also matches no directories at all
+                if match_glob(after_slash, text, pathname) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|len| match_glob(rest, &text[len..], pathname))
+        },
+        Some(b'*') => {
+            let max_len = match pathname {
+                true => text.iter().position(|ch| *ch == b'/').unwrap_or(text.len()),
+                false => text.len(),
+            };
+            (0..=max_len).any(|len| match_glob(&pattern[1..], &text[len..], pathname))
+        },
+        Some(b'?') => matches!(text.first(), Some(ch) if !pathname || *ch != b'/') && match_glob(&pattern[1..], &text[1..], pathname),
+        Some(b'[') => {
+            let Some(ch) = text.first() else {
+                return false;
+            };
+            match match_class(&pattern[1..], *ch) {
+                Some((true, rest)) => match_glob(rest, &text[1..], pathname),
+                Some((false, _)) => false,
+                // An unterminated class is a literal `[`
+                None => *ch == b'[' && match_glob(&pattern[1..], &text[1..], pathname),
+            }
+        },
+        Some(b'\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && match_glob(&pattern[2..], &text[1..], pathname),
+        Some(expected) => text.first() == Some(expected) && match_glob(&pattern[1..], &text[1..], pathname),
+    }
+}
+
+/// Matches `ch` against a character class like `a-z]` (the part after the `[`). Returns
+/// whether it matched and the rest of the pattern, or `None` if the class isn't terminated.
+fn match_class(pattern: &[u8], ch: u8) -> Option<(bool, &[u8])> {
+    let (negated, mut rest) = match pattern.first() {
+        Some(b'!' | b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match rest {
+            [] => return None,
+            [b']', after @ ..] if !first => return Some((matched != negated, after)),
+            [low, b'-', high, after @ ..] if *high != b']' => {
+                matched |= (*low..=*high).contains(&ch);
+                rest = after;
+            },
+            [expected, after @ ..] => {
+                matched |= *expected == ch;
+                rest = after;
+            },
+        }
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildmatch_globs() {
+        assert!(wildmatch(b"*.txt", b"a.txt"));
+        assert!(!wildmatch(b"*.txt", b"dir/a.txt"));
+        assert!(wildmatch(b"**/a.txt", b"a.txt"));
+        assert!(wildmatch(b"**/a.txt", b"x/y/a.txt"));
+        assert!(wildmatch(b"docs/**", b"docs/a/b.md"));
+        assert!(wildmatch(b"a?c", b"abc"));
+        assert!(!wildmatch(b"a?c", b"a/c"));
+        assert!(wildmatch(b"[a-c]x", b"bx"));
+        assert!(!wildmatch(b"[!a-c]x", b"bx"));
+        assert!(wildmatch(b"\\*", b"*"));
+    }
+
+    #[test]
+    fn fnmatch_globs() {
+        assert!(fnmatch(b"*.txt", b"dir/a.txt"));
+        assert!(fnmatch(b"a?c", b"a/c"));
+        assert!(!fnmatch(b"**/a.txt", b"a.txt"));
+        assert!(fnmatch(b"**/a.txt", b"x/y/a.txt"));
+        assert!(fnmatch(b"[a-c]x", b"bx"));
+    }
+
+    #[test]
+    fn parse_assignments() {
+        assert_eq!(parse_assignment("text"), ("text".to_owned(), AttrValue::Set));
+        assert_eq!(parse_assignment("-diff"), ("diff".to_owned(), AttrValue::Unset));
+        assert_eq!(parse_assignment("!eol"), ("eol".to_owned(), AttrValue::Unspecified));
+        assert_eq!(parse_assignment("eol=crlf"), ("eol".to_owned(), AttrValue::Value("crlf".to_owned())));
+    }
+
+    #[test]
+    fn resolve_applies_precedence_and_macros() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.join(".git/info")).unwrap();
+        fs::create_dir_all(temp_dir.join("sub")).unwrap();
+        fs::write(temp_dir.join(".gitattributes"), "*.txt text eol=lf\n*.png binary\n").unwrap();
+        fs::write(temp_dir.join("sub/.gitattributes"), "*.txt eol=crlf\n/a.txt !text\n").unwrap();
+        fs::write(temp_dir.join(".git/info/attributes"), "b.txt custom=yes\n").unwrap();
+
+        let wd = WorkDir::new(temp_dir.path()).unwrap();
+        let path = WorkPathBuf::try_from("sub/a.txt").unwrap();
+        let attrs = PathAttributes::resolve(&wd, &path).unwrap();
+        assert_eq!(*attrs.get("text"), AttrValue::Unspecified);
+        assert_eq!(*attrs.get("eol"), AttrValue::Value("crlf".to_owned()));
+
+        let path = WorkPathBuf::try_from("b.txt").unwrap();
+        let attrs = PathAttributes::resolve(&wd, &path).unwrap();
+        assert_eq!(*attrs.get("text"), AttrValue::Set);
+        assert_eq!(*attrs.get("custom"), AttrValue::Value("yes".to_owned()));
+
+        let path = WorkPathBuf::try_from("img/logo.png").unwrap();
+        let attrs = PathAttributes::resolve(&wd, &path).unwrap();
+        assert_eq!(*attrs.get("binary"), AttrValue::Set);
+        assert_eq!(*attrs.get("diff"), AttrValue::Unset);
+        assert_eq!(*attrs.get("text"), AttrValue::Unset);
+    }
+}
@@ -0,0 +1,100 @@
+//! Synthetic repositories for benchmarks, so that a change meant to make wyag faster can
+//! be measured on a repository of a known shape. Only built with the `bench` feature.
+
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::{
+    Result,
+    index::Index,
+    object::{Commit, ObjectHash, ObjectMetadata},
+    repo::Repository,
+    workdir::WorkPathBuf,
+};
+
+/// How many files go in each directory.
+const FILES_PER_DIR: usize = 100;
+
+/// A generated repository with a known number of files and commits.
+pub struct SyntheticRepo {
+    pub repo: Repository,
+    pub index: Index,
+    /// The path of each file, relative to the working directory.
+    pub files: Vec<WorkPathBuf>,
+    root: PathBuf,
+    /// How many commits have been made, so each one can change the files differently.
+    commits: usize,
+}
+
+impl SyntheticRepo {
+    /// Creates a repository in the empty directory `dir` with `files` files, spread over
+    /// directories of 100, and `commits` commits. The first commit adds every file, and each
+    /// later commit changes a tenth of them.
+    pub fn generate(dir: &Path, commits: usize, files: usize) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let repo = Repository::init(dir)?;
+        let root = repo.workdir().as_path().to_owned();
+        let files = (0..files)
+            .map(|i| WorkPathBuf::try_from(format!("dir{}/file{i}.txt", i / FILES_PER_DIR)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut synthetic = Self {
+            repo,
+            index: Index::new(None),
+            files,
+            root,
+            commits: 0,
+        };
+        for _ in 0..commits {
+            synthetic.commit_changes()?;
+        }
+
+        Ok(synthetic)
+    }
+
+    /// Changes some of the files (all of them, the first time), stages them, and commits.
+    /// Returns the new commit's hash.
+    pub fn commit_changes(&mut self) -> Result<ObjectHash> {
+        let round = self.commits;
+        for (i, path) in self.files.iter().enumerate() {
+            if round == 0 || (i + round).is_multiple_of(10) {
+                let abs_path = self.root.join(path);
+                fs::create_dir_all(abs_path.parent().expect("file should be in a directory"))?;
+                fs::write(abs_path, format!("file {i}, version {round}\n"))?;
+            }
+        }
+
+        let wd = self.repo.workdir();
+        self.index.add(wd, &self.root, true)?;
+        self.index.write(wd)?;
+
+        let meta = ObjectMetadata {
+            author_name: "Bench".to_owned(),
+            author_email: "bench@example.com".to_owned(),
+            committer_name: "Bench".to_owned(),
+            committer_email: "bench@example.com".to_owned(),
+            message: format!("Commit {round}\n"),
+            timestamp: Some(1_700_000_000 + round as i64),
+            timezone: "+0000".to_owned(),
+            author_date: None,
+            signer: None,
+        };
+        let hash = Commit::create(&self.index, wd, meta)?;
+        self.commits += 1;
+
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_commits() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let synthetic = SyntheticRepo::generate(&temp_dir.join("repo"), 3, 250).unwrap();
+
+        assert_eq!(synthetic.index.entries.len(), 250);
+        assert!(temp_dir.join("repo/dir2/file249.txt").is_file());
+    }
+}
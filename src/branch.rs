@@ -1,17 +1,25 @@
-use std::{fs, collections::VecDeque};
+use std::fs;
 
 use thiserror::Error;
 
 use crate::{
     Result,
+    consts::{HEAD, BRANCH_CATEGORY, BRANCH_PREFIX, SYMREF_PREFIX},
+    history,
     refs::{self, RefError},
     workdir::WorkDir,
-    object::{ObjectHash, GitObject, ObjectFormat}
+    index::Index,
+    object::{ObjectHash, Tree}
 };
 
 /// A branch of the repository. Can be a name or a hash (when the repo's HEAD is detached).
 pub enum Branch {
     Named(String),
+    /// A branch with no commits yet. The ref will be created by the first commit.
+    Unborn(String),
+    /// A ref outside of `refs/heads`, such as a remote-tracking branch. It's treated like a
+    /// detached HEAD, so committing doesn't move the ref.
+    Ref(String),
     Headless(ObjectHash),
 }
 
@@ -19,39 +27,56 @@ impl Branch {
     /// Returns the commit hash, if any, at the tip of this branch.
     pub fn tip(&self, wd: &WorkDir) -> Result<Option<ObjectHash>> {
         match self {
-            Branch::Named(name) => match refs::resolve(wd, "heads", name) {
-                Ok(hash) => Ok(Some(hash)),
-                Err(err) => match err.downcast_ref::<RefError>() {
-                    Some(RefError::Nonexistent(_)) => Ok(None),
-                    Some(_) | None => Err(err),
-                }
-            },
+            Branch::Named(name) => optional_ref(refs::resolve(wd, BRANCH_CATEGORY, name)),
+            Branch::Unborn(_) => Ok(None),
+            Branch::Ref(ref_path) => optional_ref(refs::resolve_path(wd, ref_path)),
             Branch::Headless(hash) => Ok(Some(*hash))
         }
     }
 }
 
+/// Converts the result of resolving a ref into `None` if the ref doesn't exist.
+fn optional_ref(result: Result<ObjectHash>) -> Result<Option<ObjectHash>> {
+    match result {
+        Ok(hash) => Ok(Some(hash)),
+        Err(err) => match err.downcast_ref::<RefError>() {
+            Some(RefError::Nonexistent(_)) => Ok(None),
+            Some(_) | None => Err(err),
+        }
+    }
+}
+
 /// Determines the branch pointed to by the repo's HEAD.
 pub fn get_current(wd: &WorkDir) -> Result<Branch> {
-    let head_path = wd.git_path("HEAD");
+    let head_path = wd.git_path(HEAD);
     let head_contents = fs::read_to_string(head_path)?;
     let head_contents = head_contents.trim();
 
     // HEAD should either be a ref or a commit hash
-    if !head_contents.starts_with("ref: ") {
+    if !head_contents.starts_with(SYMREF_PREFIX) {
         let commit_hash = ObjectHash::try_from(head_contents)?;
         Ok(Branch::Headless(commit_hash))
     }
-    else if let Some(branch_name) = head_contents.strip_prefix("ref: refs/heads/") {
+    else if let Some(branch_name) = head_contents.strip_prefix(SYMREF_PREFIX).and_then(|target| target.strip_prefix(BRANCH_PREFIX)) {
         if branch_name.is_empty() {
             return Err(BranchError::UnrecognizedHeadRef(head_contents.to_owned()).into());
         }
 
-        Ok(Branch::Named(String::from(branch_name)))
+        if exists(branch_name, wd)? {
+            Ok(Branch::Named(String::from(branch_name)))
+        }
+        else {
+            Ok(Branch::Unborn(String::from(branch_name)))
+        }
     }
     else {
-        // Could be a remote ref which is currently unsupported
-        Err(BranchError::UnrecognizedHeadRef(head_contents.to_owned()).into())
+        // Some other ref, e.g. a remote-tracking branch
+        match head_contents.strip_prefix(SYMREF_PREFIX) {
+            Some(ref_path) if ref_path.starts_with("refs/") && !ref_path.ends_with('/') => {
+                Ok(Branch::Ref(String::from(ref_path)))
+            },
+            Some(_) | None => Err(BranchError::UnrecognizedHeadRef(head_contents.to_owned()).into()),
+        }
     }
 }
 
@@ -62,7 +87,7 @@ pub fn create(name: &str, wd: &WorkDir, commit_hash: &ObjectHash) -> Result<()>
         return Err(BranchError::AlreadyExists(name.to_owned()).into());
     }
 
-    refs::create(wd, "heads", name, commit_hash)?;
+    refs::create(wd, BRANCH_CATEGORY, name, commit_hash)?;
 
     Ok(())
 }
@@ -80,7 +105,7 @@ pub fn delete(name: &str, wd: &WorkDir) -> Result<()> {
             return Err(BranchError::PossiblyUnmerged(name.to_owned()).into());
         }
 
-        refs::delete(wd, "heads", name)
+        refs::delete(wd, BRANCH_CATEGORY, name)
     }
     else {
         Err(BranchError::PossiblyUnmerged(name.to_owned()).into())
@@ -89,7 +114,7 @@ pub fn delete(name: &str, wd: &WorkDir) -> Result<()> {
 
 /// Moves the tip of the branch called `name` to the commit identified by `commit_hash`.
 pub fn update(name: &str, wd: &WorkDir, commit_hash: &ObjectHash) -> Result<()> {
-    refs::create(wd, "heads", name, commit_hash)?;
+    refs::create(wd, BRANCH_CATEGORY, name, commit_hash)?;
 
     Ok(())
 }
@@ -97,12 +122,12 @@ pub fn update(name: &str, wd: &WorkDir, commit_hash: &ObjectHash) -> Result<()>
 /// Moves the tip of the current branch to the commit identified by `commit_hash`.
 pub fn update_current(wd: &WorkDir, commit_hash: &ObjectHash) -> Result<()> {
     match get_current(wd)? {
-        Branch::Named(branch_name) => {
+        Branch::Named(branch_name) | Branch::Unborn(branch_name) => {
             update(&branch_name, wd, commit_hash)?;
         },
-        Branch::Headless(_) => {
-            let head_path = wd.git_path("HEAD");
-            std::fs::write(head_path, format!("{commit_hash}\n"))?;
+        Branch::Ref(_) | Branch::Headless(_) => {
+            // HEAD becomes detached rather than moving a ref that isn't a local branch
+            wd.write_git_file(HEAD, format!("{commit_hash}\n").as_bytes())?;
         },
     };
 
@@ -110,26 +135,82 @@ pub fn update_current(wd: &WorkDir, commit_hash: &ObjectHash) -> Result<()> {
 }
 
 /// Switches the HEAD ref to the branch called `name`.
+/// 
+/// The branch must exist, unless it's the current (unborn) branch.
 pub fn switch(wd: &WorkDir, branch: &Branch) -> Result<()> {
-    let head_path = wd.git_path("HEAD");
-    match branch {
+    let head_contents = match branch {
         Branch::Named(branch_name) => {
-            if !exists(branch_name, wd)? {
+            if !exists(branch_name, wd)? && !is_current(branch_name, wd)? {
                 return Err(BranchError::Nonexistent(branch_name.clone()).into());
             }
-            std::fs::write(head_path, format!("ref: refs/heads/{branch_name}\n"))?;
+            format!("{SYMREF_PREFIX}{BRANCH_PREFIX}{branch_name}\n")
         },
-        Branch::Headless(commit_hash) => {
-            std::fs::write(head_path, format!("{commit_hash}\n"))?;
+        Branch::Unborn(branch_name) => format!("{SYMREF_PREFIX}{BRANCH_PREFIX}{branch_name}\n"),
+        Branch::Ref(ref_path) => format!("{SYMREF_PREFIX}{ref_path}\n"),
+        Branch::Headless(commit_hash) => format!("{commit_hash}\n"),
+    };
+
+    wd.write_git_file(HEAD, head_contents.as_bytes())
+}
+
+/// Switches the HEAD ref to `branch` and makes `index` match its tip, without touching the
+/// working tree, like `git symbolic-ref` followed by `git read-tree`. Entries that are
+/// unchanged keep their stats. The index file is removed if the branch is unborn, since an
+/// index can't be written without entries.
+pub fn switch_without_checkout(wd: &WorkDir, index: &mut Index, branch: &Branch) -> Result<()> {
+    let tree = match (branch.tip(wd)?, branch) {
+        (Some(tip), _) => Tree::read_tree_ish(wd, &tip)?,
+        (None, Branch::Named(name)) if !is_current(name, wd)? => {
+            return Err(BranchError::Nonexistent(name.clone()).into());
         },
+        (None, _) => Tree::empty(),
     };
 
-    Ok(())
+    let mut entries = tree.to_index(wd, Some(index.version))?.entries;
+    for (path, entry) in &mut entries {
+        if let Some(current) = index.entries.get(path) {
+            if current.hash == entry.hash && current.stats.get_mode().ok() == entry.stats.get_mode().ok() {
+                *entry = current.clone();
+            }
+        }
+    }
+    index.entries = entries;
+    index.conflict_stages.clear();
+
+    // HEAD is updated last, so it's left alone if the index can't be written
+    if index.entries.is_empty() {
+        match fs::remove_file(wd.git_path("index")) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {},
+        }
+    }
+    else {
+        index.write(wd)?;
+    }
+    switch(wd, branch)
+}
+
+/// Switches the HEAD ref to a new, unborn branch called `name`. The branch will be
+/// created by the next commit, which will have no parents.
+pub fn switch_orphan(wd: &WorkDir, name: &str) -> Result<()> {
+    if exists(name, wd)? {
+        return Err(BranchError::AlreadyExists(name.to_owned()).into());
+    }
+
+    switch(wd, &Branch::Unborn(name.to_owned()))
+}
+
+/// Returns true if HEAD points to the branch called `name`.
+fn is_current(name: &str, wd: &WorkDir) -> Result<bool> {
+    match get_current(wd)? {
+        Branch::Named(current_name) | Branch::Unborn(current_name) => Ok(current_name == name),
+        Branch::Ref(_) | Branch::Headless(_) => Ok(false),
+    }
 }
 
 /// Returns true if the branch called `name` exists.
 pub fn exists(name: &str, wd: &WorkDir) -> Result<bool> {
-    match refs::resolve(wd, "heads", name) {
+    match refs::resolve(wd, BRANCH_CATEGORY, name) {
         Ok(_) => Ok(true),
         Err(err) => match err.downcast_ref::<RefError>() {
             Some(RefError::Nonexistent(_)) => Ok(false),
@@ -140,27 +221,10 @@ pub fn exists(name: &str, wd: &WorkDir) -> Result<bool> {
 
 /// Determines if the branch `name` has been merged into `into_branch`.
 pub fn is_merged(name: &str, into_branch: &str, wd: &WorkDir) -> Result<bool> {
-    let our_tip = refs::resolve(wd, "heads", name)?;
-    let their_tip = refs::resolve(wd, "heads", into_branch)?;
-    
-    // Conduct a breadth-first search for our_tip in the commit graph of into_branch
-    let mut open_hashes = VecDeque::new();
-    open_hashes.push_back(their_tip);
-
-    while let Some(hash) = open_hashes.pop_front() {
-        if hash == our_tip {
-            return Ok(true);
-        }
-
-        let commit = match GitObject::read(wd, &hash)? {
-            GitObject::Commit(commit) => commit,
-            object => return Err(BranchError::BrokenCommitGraph(object.get_format()).into()),
-        };
-
-        open_hashes.extend(commit.parents());
-    }
+    let our_tip = refs::resolve(wd, BRANCH_CATEGORY, name)?;
+    let their_tip = refs::resolve(wd, BRANCH_CATEGORY, into_branch)?;
 
-    Ok(false)
+    history::is_ancestor(wd, &our_tip, &their_tip)
 }
 
 #[derive(Error, Debug)]
@@ -173,8 +237,6 @@ pub enum BranchError {
     CheckedOut(String),
     #[error("Cannot delete the branch `{0}` because it may contain unmerged changes")]
     PossiblyUnmerged(String),
-    #[error("The HEAD ref `{0}` was not recognized (remotes are unsupported)")]
+    #[error("The HEAD ref `{0}` was not recognized")]
     UnrecognizedHeadRef(String),
-    #[error("The commit graph contains a {0}")]
-    BrokenCommitGraph(ObjectFormat),
 }
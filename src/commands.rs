@@ -1,13 +1,15 @@
 use std::{
-    path::PathBuf,
-    collections::HashSet,
+    path::{Path, PathBuf},
+    collections::{BTreeMap, HashSet},
 };
-use anyhow::bail;
-use clap::{Parser, Subcommand, Args};
+use anyhow::{bail, Context};
+use clap::{CommandFactory, Parser, Subcommand, Args};
+use thiserror::Error;
 
 use crate::{
     Result,
-    repo::Repository,
+    consts::{HEAD, BRANCH_PREFIX, TAG_PREFIX, REMOTE_PREFIX, CHERRY_PICK_HEAD, REVERT_HEAD, MERGE_MSG, COMMIT_EDITMSG},
+    repo::{Repository, RepoError, InProgress},
     object::{
         ObjectError,
         GitObject,
@@ -16,42 +18,194 @@ use crate::{
         Commit,
         Tag,
         ObjectMetadata, Tree,
+        TreeChange,
+        RevWalk,
+        ObjectStore,
+        SshSigner,
     },
+    archive,
+    copy_objects,
+    attributes::PathAttributes,
+    date::{self, DateStyle},
+    pathspec::Pathspec,
+    pretty,
+    prune,
+    recover,
     refs,
-    index::{UnstagedChange, StagedChange, Index},
+    reflog,
+    sequencer::{self, Sequencer, SequencerOptions, SequencerStatus, Operation, Step, StepOutcome},
+    server_info,
+    sparse_checkout::SparseCheckout,
+    stash,
+    summary::RepoSummary,
+    whitespace::{self, WhitespaceRules},
+    index::{Index, IndexEntry, IndexError, UnstagedChange, StagedChange},
+    diff::{self, DiffOptions, DiffAlgorithm, DiffDriver, FilePatch, PatchSide, PatchOptions},
+    merge::{self, MergeOptions, MergeError, ConflictStyle, Favor, FastForward, MergeTool, ConflictVersions},
+    docs::{self, DocsError, HelpTopic},
+    editor,
+    history,
+    hooks,
+    messages::{Message, ChangeKind, Verbosity},
     branch,
-    workdir::{WorkDir, WorkPathBuf},
+    remote::RemoteLocation,
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+    worktree::{self, LinkedWorktree},
 };
 
+/// Prints informational output, e.g. progress and hints, unless `verbosity` is
+/// [`Verbosity::Quiet`] (`--quiet`).
+macro_rules! info {
+    ($verbosity:expr) => {
+        if $verbosity > Verbosity::Quiet {
+            println!();
+        }
+    };
+    ($verbosity:expr, $($arg:tt)*) => {
+        if $verbosity > Verbosity::Quiet {
+            println!($($arg)*);
+        }
+    };
+}
+
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, disable_help_subcommand = true)]
 pub struct Cli {
+    /// Only print the command's output and errors. Must come before the command.
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Print more, as if the command's own --verbose was given. Must come before the command.
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// Refuse to open a repository with an invalid HEAD, a corrupt index, or invalid core
+    /// config values, listing every problem instead. Must come before the command.
+    #[arg(long)]
+    pub strict: bool,
     #[command(subcommand)]
     pub command: Commands
 }
 
+impl Cli {
+    /// Returns the verbosity chosen with `--quiet` or `--verbose`.
+    pub fn verbosity(&self) -> Verbosity {
+        match (self.quiet, self.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, true) => Verbosity::Verbose,
+            (false, false) => Verbosity::Normal,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
    Add(AddArgs),
+   Archive(ArchiveArgs),
    Branch(BranchArgs),
    CatFile(CatFileArgs),
+   CheckAttr(CheckAttrArgs),
    Checkout(CheckoutArgs),
+   CherryPick(CherryPickArgs),
    Commit(CommitArgs),
+   CopyObjects(CopyObjectsArgs),
+   Diff(DiffArgs),
+   ForEachRef(ForEachRefArgs),
+   Fsck(FsckArgs),
+   #[command(hide = true)]
+   GenerateDocs(GenerateDocsArgs),
    HashObject(HashObjectArgs),
+   Help(HelpArgs),
    Init(InitArgs),
    Log(LogArgs),
    LsFiles(LsFilesArgs),
    LsTree(LsTreeArgs),
    Merge(MergeArgs),
+   MergeBase(MergeBaseArgs),
+   MergeFile(MergeFileArgs),
+   Mergetool(MergetoolArgs),
+   Prune(PruneArgs),
+   ReadTree(ReadTreeArgs),
+   Recover(RecoverArgs),
+   Reflog(ReflogArgs),
+   Refs(RefsArgs),
    Restore(RestoreArgs),
+   Revert(RevertArgs),
+   RevList(RevListArgs),
    RevParse(RevParseArgs),
    Rm(RmArgs),
    ShowRef(ShowRefArgs),
+   Stash(StashArgs),
+   Stats(StatsArgs),
    Status(StatusArgs),
+   #[command(alias = "info")]
+   Summary(SummaryArgs),
    Switch(SwitchArgs),
    Tag(TagArgs),
+   UpdateServerInfo(UpdateServerInfoArgs),
+   VerifyCommit(VerifyCommitArgs),
+   VerifyTag(VerifyTagArgs),
+   Worktree(WorktreeArgs),
+}
+
+/// Lets a command read its paths from a file, for lists too long to pass as arguments.
+#[derive(Args, Default)]
+pub struct PathspecFileArgs {
+    /// Read the paths from this file, one per line. Use `-` to read from stdin.
+    #[arg(long)]
+    pub pathspec_from_file: Option<PathBuf>,
+    /// The paths in the --pathspec-from-file are separated by NUL characters instead of
+    /// newlines.
+    #[arg(long, requires = "pathspec_from_file")]
+    pub pathspec_file_nul: bool,
+}
+
+impl PathspecFileArgs {
+    /// Returns `paths` from the command line followed by the paths read from the file, if
+    /// one was given. Fails if there are none at all.
+    pub fn read_paths(&self, paths: impl IntoIterator<Item = PathBuf>) -> Result<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = paths.into_iter().collect();
+
+        if let Some(file) = &self.pathspec_from_file {
+            let contents = match file.as_os_str() == "-" {
+                true => {
+                    let mut contents = Vec::new();
+                    std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut contents)?;
+                    contents
+                },
+                false => std::fs::read(file)
+                    .with_context(|| format!("Failed to read pathspec file {file:?}"))?,
+            };
+            let separator = match self.pathspec_file_nul {
+                true => b'\0',
+                false => b'\n',
+            };
+
+            for path in contents.split(|byte| *byte == separator) {
+                let path = match self.pathspec_file_nul {
+                    true => path,
+                    false => path.strip_suffix(b"\r").unwrap_or(path),
+                };
+                if !path.is_empty() {
+                    let path = std::str::from_utf8(path)
+                        .context("Paths in the pathspec file must be valid UTF-8")?;
+                    paths.push(PathBuf::from(path));
+                }
+            }
+        }
+
+        if paths.is_empty() {
+            bail!("No paths were given.");
+        }
+
+        Ok(paths)
+    }
 }
 
+/// Ends a command with a particular exit status without printing an error, e.g. when
+/// `diff --exit-code` finds differences.
+#[derive(Error, Debug)]
+#[error("exit status {0}")]
+pub struct ExitStatus(pub u8);
+
 #[derive(clap::ValueEnum, Clone)]
 pub enum ClapObjectFormat {
     Commit,
@@ -73,23 +227,120 @@ impl From<ClapObjectFormat> for ObjectFormat {
     }
 }
 
+#[derive(clap::ValueEnum, Clone)]
+pub enum ClapDiffAlgorithm {
+    Default,
+    Myers,
+    Minimal,
+    Patience,
+    Histogram,
+}
+
+impl From<ClapDiffAlgorithm> for DiffAlgorithm {
+    fn from(value: ClapDiffAlgorithm) -> Self {
+        use ClapDiffAlgorithm::*;
+
+        match value {
+            Default | Myers | Minimal => DiffAlgorithm::Myers,
+            Patience => DiffAlgorithm::Patience,
+            Histogram => DiffAlgorithm::Histogram,
+        }
+    }
+}
+
 /// Adds files to the staging index
 #[derive(Args)]
 pub struct AddArgs {
-    /// The file or directory to stage
-    pub path: PathBuf,
+    /// The files or directories to stage. Globs like `src/*.rs` also match untracked files.
+    #[arg(required_unless_present = "pathspec_from_file", conflicts_with = "pathspec_from_file")]
+    pub paths: Vec<PathBuf>,
+    /// Allow adding files that are ignored.
+    #[arg(short, long)]
+    pub force: bool,
+    /// Allow adding files outside of the sparse checkout.
+    #[arg(long)]
+    pub sparse: bool,
+    #[command(flatten)]
+    pub pathspec_file: PathspecFileArgs,
 }
 
 pub fn cmd_add(args: AddArgs) -> Result<()> {
     let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
     let mut index = repo.index()?;
 
     if !index.ext_data.is_empty() {
-        eprintln!("Warning: index contains unsupported extensions.");
+        eprintln!("{}", Message::UnsupportedIndexExtensions);
+    }
+
+    let sparse = match args.sparse {
+        true => SparseCheckout::all(),
+        false => SparseCheckout::load(repo.workdir())?,
+    };
+    let wd = repo.workdir();
+    let pathspec = Pathspec::new(wd, args.pathspec_file.read_paths(args.paths)?)?;
+    for path in pathspec.expand(wd, &mut index, true)? {
+        for nested in index.add_with(wd, wd.abs_path(&path), args.force, &sparse)? {
+            eprintln!("{}", Message::NestedRepoSkipped(&nested));
+        }
     }
+    index.write(wd)?;
+
+    Ok(())
+}
+
+/// Creates a tar archive of the files in a tree
+#[derive(Args)]
+pub struct ArchiveArgs {
+    /// Prepend this to every path in the archive, e.g. `project/`.
+    #[arg(long, default_value = "")]
+    pub prefix: String,
+    /// Write the archive to this file instead of stdout.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// The tree to archive (or a commit or tag that leads to one). Use `rev:dir` to archive
+    /// a single directory with paths relative to it.
+    pub tree_ish: String,
+    /// Only include these files and directories.
+    pub paths: Vec<String>,
+}
+
+pub fn cmd_archive(args: ArchiveArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
 
-    index.add(repo.workdir(), &args.path)?;
-    index.write(repo.workdir())?;
+    let hash = GitObject::find_as(wd, &args.tree_ish, ObjectFormat::Tree)?;
+    let tree = Tree::read(wd, &hash)?;
+
+    // Use the commit time if there is a commit, same as git
+    let rev = args.tree_ish.split_once(':')
+        .map_or(args.tree_ish.as_str(), |(rev, _)| rev);
+    let commit_time = GitObject::find(wd, rev)
+        .and_then(|hash| GitObject::peel(wd, &hash, ObjectFormat::Commit))
+        .and_then(|hash| Commit::read(wd, &hash))
+        .ok()
+        .and_then(|commit| commit.committer_time());
+    let mtime = commit_time.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .expect("System time should be later than the Unix epoch")
+            .as_secs() as i64
+    });
+
+    let paths = args.paths.iter()
+        .map(|path| WorkPathBuf::try_from(path.trim_end_matches('/')))
+        .collect::<Result<Vec<_>>>()?;
+
+    match args.output {
+        Some(path) => {
+            let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            archive::write_tar(wd, &tree, &args.prefix, &paths, mtime, file)?;
+        },
+        None => {
+            let stdout = std::io::stdout().lock();
+            archive::write_tar(wd, &tree, &args.prefix, &paths, mtime, stdout)?;
+        },
+    }
 
     Ok(())
 }
@@ -100,25 +351,26 @@ pub struct BranchArgs {
     #[arg(short, long)]
     pub delete: bool,
     pub branch_name: Option<String>,
-    #[arg(default_value = "HEAD")]
+    #[arg(default_value = HEAD)]
     pub start_point: String,
 }
 
 pub fn cmd_branch(args: BranchArgs) -> Result<()> {
     let repo = Repository::find(".")?;
     if let Some(branch_name) = args.branch_name {
+        let _lock = repo.lock()?;
         if args.delete {
             branch::delete(&branch_name, repo.workdir())?;
         }
         else {
-            let hash = GitObject::find(repo.workdir(), &args.start_point)?;
+            let hash = GitObject::find_as(repo.workdir(), &args.start_point, ObjectFormat::Commit)?;
             branch::create(&branch_name, repo.workdir(), &hash)?;
         }
     }
     else {
-        refs::list(repo.workdir())?.iter()
-            .filter_map(|(name, _)| name.strip_prefix("refs/heads/"))
-            .for_each(|name| println!("{name}"));
+        for branch_ref in refs::iter(repo.workdir(), BRANCH_PREFIX)? {
+            println!("{}", branch_ref?.short_name());
+        }
     }
 
     Ok(())
@@ -128,19 +380,102 @@ pub fn cmd_branch(args: BranchArgs) -> Result<()> {
 #[derive(Args)]
 pub struct CatFileArgs {
     /// The type of object to display
-    #[arg(id = "TYPE")]
-    pub object_type: ClapObjectFormat,
+    #[arg(id = "TYPE", required_unless_present = "textconv")]
+    pub object_type: Option<ClapObjectFormat>,
 
     /// The object to display
-    pub object: String,
+    #[arg(required_unless_present = "textconv")]
+    pub object: Option<String>,
+
+    /// Print the object as stored. This is the default, and is accepted because git's
+    /// `cat-file -p` shows commits and tags the same way.
+    #[arg(short = 'p')]
+    pub print: bool,
+
+    /// Show commits and tags with their dates decoded and their messages set apart,
+    /// as `log --pretty` does, instead of as stored.
+    #[arg(long, conflicts_with = "print")]
+    pub rendered: bool,
+
+    /// Show the blob `<rev>:<path>` converted to text by the textconv command of the diff
+    /// driver for the path, as `diff` shows it. Without one, the blob is shown as is.
+    #[arg(long, value_name = "REV:PATH", conflicts_with_all = ["TYPE", "object", "print", "rendered"])]
+    pub textconv: Option<String>,
 }
 
 pub fn cmd_cat_file(args: CatFileArgs) -> Result<()> {
     let repo = Repository::find(".")?;
-    let hash = GitObject::find(repo.workdir(), &args.object)?;
+
+    if let Some(object) = &args.textconv {
+        let wd = repo.workdir();
+        let Some((_, path)) = object.split_once(':') else {
+            bail!("--textconv needs an object named by <rev>:<path>, not `{object}`");
+        };
+        let hash = GitObject::find_as(wd, object, ObjectFormat::Blob)?;
+        let data = GitObject::read(wd, &hash)?.serialize();
+
+        let attrs = PathAttributes::resolve(wd, &WorkPathBuf::try_from(path)?)?;
+        let text = DiffDriver::for_attributes(&repo, &attrs)?.textconv(&data)?;
+        std::io::Write::write_all(&mut std::io::stdout(), &text.unwrap_or(data))?;
+        return Ok(());
+    }
+
+    let object = args.object.expect("clap should require an object without --textconv");
+    let hash = GitObject::find(repo.workdir(), &object)?;
     let object = GitObject::read(repo.workdir(), &hash)?;
 
-    println!("{}", String::from_utf8_lossy(&object.serialize()));
+    match object {
+        GitObject::Commit(commit) if args.rendered => print!("{}", pretty::format_commit(&hash, &commit, &DateStyle::Default)),
+        GitObject::Tag(tag) if args.rendered => print!("{}", pretty::format_tag(&tag, &DateStyle::Default)),
+        object => println!("{}", String::from_utf8_lossy(&object.serialize())),
+    }
+
+    Ok(())
+}
+
+/// Displays the attributes assigned to paths by .gitattributes
+#[derive(Args)]
+pub struct CheckAttrArgs {
+    /// Display every attribute that is specified for each path.
+    #[arg(short, long)]
+    pub all: bool,
+    /// The attributes to display, followed by the paths (unless the paths come after `--`).
+    /// With `--all`, these are all paths.
+    pub attributes: Vec<String>,
+    #[arg(last = true)]
+    pub paths: Vec<String>,
+}
+
+pub fn cmd_check_attr(mut args: CheckAttrArgs) -> Result<()> {
+    // Without `--`, the first argument is the attribute and the rest are paths
+    if args.all {
+        args.paths.splice(0..0, args.attributes.drain(..));
+    }
+    else if args.paths.is_empty() && !args.attributes.is_empty() {
+        args.paths = args.attributes.split_off(1);
+    }
+
+    if args.paths.is_empty() || (!args.all && args.attributes.is_empty()) {
+        bail!("Specify at least one attribute (or --all) and at least one path");
+    }
+
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+
+    for path in &args.paths {
+        let attrs = PathAttributes::resolve(wd, &wd.canonicalize_path(path)?)?;
+
+        if args.all {
+            for (name, value) in attrs.iter() {
+                println!("{path}: {name}: {value}");
+            }
+        }
+        else {
+            for name in &args.attributes {
+                println!("{path}: {name}: {}", attrs.get(name));
+            }
+        }
+    }
 
     Ok(())
 }
@@ -150,168 +485,1708 @@ pub fn cmd_cat_file(args: CatFileArgs) -> Result<()> {
 pub struct CheckoutArgs { }
 
 pub fn cmd_checkout(_args: CheckoutArgs) -> Result<()> {
-    println!("wyag does not support the checkout command.");
-    println!("If you want to switch branches, use the switch command.");
-    println!("If you want to restore working directory files, use the restore command.");
+    println!("{}", Message::CheckoutUnsupported);
 
     Ok(())
 }
 
-/// Commits staged changes to the current branch.
+/// Apply the changes introduced by existing commits to the current branch, committing each
+/// one. Stops if there are conflicts, to be resolved and then continued.
 #[derive(Args)]
-pub struct CommitArgs {
-    /// A message to attach to the tag.
-    #[arg(short, default_value = "")]
-    pub message: String,
+pub struct CherryPickArgs {
+    /// Append "(cherry picked from commit ...)" to each commit message.
+    #[arg(short = 'x')]
+    pub record_origin: bool,
+    /// For merge commits, the parent (numbered from 1) to take the changes relative to.
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub mainline: Option<u32>,
+    #[command(flatten)]
+    pub action: SequencerActionArgs,
+    /// The commits to apply, in order. A range `A..B` is the commits reachable from B but not
+    /// from A, oldest first.
+    #[arg(required_unless_present = "action", conflicts_with = "action")]
+    pub commits: Vec<String>,
 }
 
-pub fn cmd_commit(args: CommitArgs) -> Result<()> {
-    let repo = Repository::find(".")?;
-    let index = repo.index()?;
-    let meta = ObjectMetadata::new(&repo, args.message)?;
+/// Ways to handle a cherry-pick or revert that stopped.
+#[derive(Args)]
+pub struct SequencerActionArgs {
+    /// Commit the resolved conflicts, then apply the remaining commits.
+    #[arg(long = "continue", group = "action")]
+    pub resume: bool,
+    /// Discard the changes from the commit that stopped, then apply the remaining commits.
+    #[arg(long, group = "action")]
+    pub skip: bool,
+    /// Cancel the operation and return to the commit that was checked out when it started.
+    #[arg(long, group = "action")]
+    pub abort: bool,
+}
 
-    let hash = Commit::create(&index, repo.workdir(), meta)?;
-    println!("{hash}");
+pub fn cmd_cherry_pick(args: CherryPickArgs, verbosity: Verbosity) -> Result<()> {
+    let options = SequencerOptions {
+        record_origin: args.record_origin,
+        mainline: args.mainline.map(|n| n as usize),
+    };
 
-    Ok(())
+    run_sequencer_command(Operation::CherryPick, &args.action, &args.commits, options, verbosity)
 }
 
-/// Computes object hash and optionally creates a blob from a file.
-#[derive(Args)]
-pub struct HashObjectArgs {
-    /// Actually write the object into the database
-    #[arg(short, long)]
-    pub write: bool,
+/// Starts a cherry-pick or revert of `revs`, or continues, skips, or aborts the one that
+/// stopped.
+fn run_sequencer_command(operation: Operation, action: &SequencerActionArgs, revs: &[String], options: SequencerOptions, verbosity: Verbosity) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
+    let wd = repo.workdir();
+    let mut index = repo.index()?;
+    let head = branch::get_current(wd)?.tip(wd)?;
+    let head_tree = match &head {
+        Some(head) => Tree::read_tree_ish(wd, head)?,
+        None => Tree::empty(),
+    };
+    let (step_action, in_progress) = match operation {
+        Operation::Revert => (sequencer::Action::Revert, InProgress::Revert),
+        _ => (sequencer::Action::Pick, InProgress::CherryPick),
+    };
 
-    /// The type of the object
-    #[arg(id = "type", short, long, default_value = "blob")]
-    pub format: ClapObjectFormat,
+    let status = if action.abort {
+        let sequencer = Sequencer::load_expecting(wd, operation)?;
+        let original_tree = match &sequencer.original_head {
+            Some(hash) => Tree::read_tree_ish(wd, hash)?,
+            None => Tree::empty(),
+        };
+        index.reset_to_tree(wd, &original_tree)?;
+        index.write(wd)?;
+        if let Some(original_head) = &sequencer.original_head {
+            branch::update_current(wd, original_head)?;
+        }
+        remove_pick_state(wd)?;
+        sequencer.abort(wd)?;
+        return Ok(());
+    }
+    else if action.skip {
+        let mut sequencer = Sequencer::load_expecting(wd, operation)?;
+        index.reset_to_tree(wd, &head_tree)?;
+        index.write(wd)?;
+        remove_pick_state(wd)?;
+        let options = sequencer.options;
+        sequencer.skip(wd, |step| apply_step(&repo, &mut index, step, &options, verbosity))?
+    }
+    else if action.resume {
+        let mut sequencer = Sequencer::load_expecting(wd, operation)?;
+        if index.conflicted_paths().next().is_some() {
+            bail!("Cannot continue: resolve the conflicts and add the files first.");
+        }
 
-    /// Path to read the object from
-    pub path: PathBuf,
-}
+        // Commit the resolution, unless the user already did
+        if wd.git_path(pick_head_name(step_action)).is_file() {
+            let staged_changes = index.list_staged_changes(wd, head.as_ref(), &WorkPathBuf::root())?;
+            if !staged_changes.is_empty() {
+                let message = std::fs::read_to_string(wd.git_path(MERGE_MSG))
+                    .with_context(|| format!("Failed to read the message for the {operation}"))?;
+                let stopped_at = std::fs::read_to_string(wd.git_path(pick_head_name(step_action)))?;
+                let stopped_at = ObjectHash::try_from(stopped_at.trim())?;
+                let meta = step_metadata(&repo, step_action, &Commit::read(wd, &stopped_at)?, message)?;
+                let hash = Commit::create(&index, wd, meta)?;
+                println!("{hash}");
+            }
+            remove_pick_state(wd)?;
+        }
 
-pub fn cmd_hash_object(args: HashObjectArgs) -> Result<()> {
-    let object = GitObject::from_path(args.path, args.format.into())?;
-    let hash = if args.write {
-        let repo = Repository::find(".")?;
-        object.write(repo.workdir())?
+        let options = sequencer.options;
+        sequencer.resume(wd, |step| apply_step(&repo, &mut index, step, &options, verbosity))?
     }
     else {
-        object.hash()
+        let is_stopped = [CHERRY_PICK_HEAD, REVERT_HEAD].iter().any(|name| wd.git_path(name).is_file());
+        if Sequencer::is_in_progress(wd) || is_stopped {
+            let sequencer = Sequencer::load(wd)?;
+            let operation = sequencer.map_or(operation, |sequencer| sequencer.operation);
+            return Err(sequencer::SequencerError::InProgress(operation).into());
+        }
+        ensure_clean(wd, &index, head.as_ref(), &operation.to_string())?;
+
+        let mut steps = Vec::new();
+        for rev in revs {
+            let mut commits = match rev.split_once("..") {
+                Some((exclude, include)) => {
+                    let resolve = |rev: &str| match rev {
+                        "" => GitObject::find_as(wd, HEAD, ObjectFormat::Commit),
+                        rev => GitObject::find_as(wd, rev, ObjectFormat::Commit),
+                    };
+                    Commit::list_range(wd, Some(&resolve(exclude)?), &resolve(include)?)?
+                },
+                None => vec![GitObject::find_as(wd, rev, ObjectFormat::Commit)?],
+            };
+            // Like git, undo the newest commit first
+            if operation == Operation::Revert {
+                commits.reverse();
+            }
+            for hash in commits {
+                // Check every commit up front, rather than stopping partway through
+                let commit = Commit::read(wd, &hash)?;
+                mainline_parent(&commit, &hash, options.mainline)?;
+                let subject = commit.subject().to_owned();
+                steps.push(Step { action: step_action, commit: hash, subject });
+            }
+        }
+        if steps.is_empty() {
+            bail!("No commits to {operation}.");
+        }
+
+        let mut sequencer = Sequencer::start(wd, operation, head, options, steps)?;
+        sequencer.run(wd, |step| apply_step(&repo, &mut index, step, &options, verbosity))?
     };
 
-    println!("{hash}");
+    if let SequencerStatus::Stopped(step) = status {
+        eprintln!("{}", Message::CouldNotApply { action: step.action, commit: &step.commit.to_string()[..7], subject: &step.subject });
+        for hint in in_progress.hints(true) {
+            eprintln!("hint: {hint}");
+        }
+        return Err(ExitStatus(1).into());
+    }
 
     Ok(())
 }
 
-/// Creates a new git repository.
-#[derive(Args)]
-pub struct InitArgs {
-    /// Where to create the repository.
-    pub path: Option<PathBuf>,
+/// Applies the changes from the commit in `step` to the current branch with a three-way
+/// merge, or undoes them for a revert. The result is committed, unless there are conflicts.
+fn apply_step(repo: &Repository, index: &mut Index, step: &Step, options: &SequencerOptions, verbosity: Verbosity) -> Result<StepOutcome> {
+    let wd = repo.workdir();
+    let commit = Commit::read(wd, &step.commit)?;
+    let short_hash = &step.commit.to_string()[..7];
+
+    let parent = mainline_parent(&commit, &step.commit, options.mainline)?;
+    let parent_tree = match &parent {
+        Some(parent) => Tree::read_tree_ish(wd, parent)?,
+        None => Tree::empty(),
+    };
+    let commit_tree = Tree::read(wd, commit.tree())?;
+    let ours = match branch::get_current(wd)?.tip(wd)? {
+        Some(head) => Tree::read_tree_ish(wd, &head)?,
+        None => Tree::empty(),
+    };
+
+    // A revert applies the changes from the commit to its parent instead
+    let label = format!("{short_hash} ({})", step.subject);
+    let parent_label = format!("parent of {label}");
+    let (base, theirs, base_label, theirs_label) = match step.action {
+        sequencer::Action::Pick => (parent_tree, commit_tree, parent_label, label),
+        sequencer::Action::Revert => (commit_tree, parent_tree, label, parent_label),
+    };
+
+    let merge_options = MergeOptions {
+        ours_label: HEAD.to_owned(),
+        base_label,
+        theirs_label,
+        diff: DiffOptions {
+            algorithm: DiffAlgorithm::from_config(repo)?,
+            ..DiffOptions::default()
+        },
+        ..MergeOptions::default()
+    };
+    let merge = merge::merge_trees(repo, &base, &ours, &theirs, &merge_options)?;
+
+    // Make sure no untracked files will be lost
+    let added: Vec<WorkPathBuf> = merge.changes.iter()
+        .filter(|change| matches!(change, TreeChange::Added { .. }))
+        .map(|change| change.path().to_owned())
+        .collect();
+    index.check_untracked_collisions(wd, &theirs, &added)?;
+
+    merge.apply(wd, index)?;
+    index.write(wd)?;
+
+    let message = match step.action {
+        sequencer::Action::Pick if options.record_origin => sequencer::append_origin_line(commit.message(), &step.commit),
+        sequencer::Action::Pick => commit.message().to_owned(),
+        sequencer::Action::Revert => {
+            let mainline_parent = parent.filter(|_| options.mainline.is_some());
+            sequencer::revert_message(&step.subject, &step.commit, mainline_parent.as_ref())
+        },
+    };
+
+    if !merge.conflicts.is_empty() {
+        for conflict in &merge.conflicts {
+            eprintln!("{}", Message::MergeConflict { kind: conflict.kind, path: &conflict.path });
+        }
+        wd.write_git_file(pick_head_name(step.action), format!("{}\n", step.commit).as_bytes())?;
+        wd.write_git_file(MERGE_MSG, message.as_bytes())?;
+        return Ok(StepOutcome::Stopped);
+    }
+
+    if merge.changes.is_empty() {
+        match step.action {
+            sequencer::Action::Pick => info!(verbosity, "Skipping {short_hash}... {}: its changes are already on this branch.", step.subject),
+            sequencer::Action::Revert => info!(verbosity, "Skipping {short_hash}... {}: its changes are not on this branch.", step.subject),
+        }
+        return Ok(StepOutcome::Applied);
+    }
+
+    let hash = Commit::create(index, wd, step_metadata(repo, step.action, &commit, message)?)?;
+    println!("{hash}");
+
+    Ok(StepOutcome::Applied)
 }
 
-pub fn cmd_init(args: InitArgs) -> Result<()> {
-    let path = args.path.unwrap_or(PathBuf::from("."));
-    Repository::init(&path)?;
-    
-    println!("Successfully initialized git repository at {}", path.to_string_lossy());
+/// Returns the metadata for the commit made by a step that applied `commit`. A picked
+/// commit keeps its original author and author date, while the current user commits it
+/// now. A revert is authored by the current user.
+fn step_metadata(repo: &Repository, action: sequencer::Action, commit: &Commit, message: String) -> Result<ObjectMetadata> {
+    let mut meta = ObjectMetadata::new(repo, message)?;
+    if action == sequencer::Action::Pick {
+        meta.preserve_author(commit)?;
+    }
 
-    Ok(())
+    Ok(meta)
 }
 
-/// Display history of a given commit.
-#[derive(Args)]
-pub struct LogArgs {
-    /// The commit to start at.
-    #[arg(default_value = "HEAD")]
-    pub commit: String,
+/// Returns the parent that the changes in `commit` are relative to: its only parent, or the
+/// one numbered `mainline` for a merge. Fails if a merge has no `mainline` or vice versa.
+fn mainline_parent(commit: &Commit, hash: &ObjectHash, mainline: Option<usize>) -> Result<Option<ObjectHash>> {
+    let short_hash = &hash.to_string()[..7];
+
+    match (commit.parents(), mainline) {
+        ([], None) => Ok(None),
+        ([parent], None) => Ok(Some(*parent)),
+        (_, None) => bail!("Commit {short_hash} is a merge but no -m option was given."),
+        ([] | [_], Some(_)) => bail!("A mainline was given but commit {short_hash} is not a merge."),
+        (parents, Some(n)) => match n.checked_sub(1).and_then(|i| parents.get(i)) {
+            Some(parent) => Ok(Some(*parent)),
+            None => bail!("Commit {short_hash} does not have parent {n}."),
+        },
+    }
 }
 
-pub fn cmd_log(args: LogArgs) -> Result<()> {
-    let repo = Repository::find(".")?;
+/// Returns the name of the file that records the commit a stopped step was applying.
+fn pick_head_name(action: sequencer::Action) -> &'static str {
+    match action {
+        sequencer::Action::Pick => CHERRY_PICK_HEAD,
+        sequencer::Action::Revert => REVERT_HEAD,
+    }
+}
 
-    println!("digraph wyaglog{{");
-    let hash = GitObject::find(repo.workdir(), &args.commit)?;
-    log_graphviz(repo.workdir(), &hash, &mut HashSet::new())?;
-    println!("}}");
+/// Removes the files that record a stopped cherry-pick or revert.
+fn remove_pick_state(wd: &WorkDir) -> Result<()> {
+    for name in [CHERRY_PICK_HEAD, REVERT_HEAD, MERGE_MSG] {
+        let path = wd.git_path(name);
+        if path.is_file() {
+            std::fs::remove_file(path)?;
+        }
+    }
 
     Ok(())
 }
 
-fn log_graphviz(wd: &WorkDir, hash: &ObjectHash, seen: &mut HashSet<ObjectHash>) -> Result<()> {
-    if seen.contains(hash) {
-        return Ok(());
+/// Commits staged changes to the current branch.
+#[derive(Args)]
+pub struct CommitArgs {
+    /// The commit message. Without it, the message is written in an editor, starting from
+    /// commit.template if it's set.
+    #[arg(short)]
+    pub message: Option<String>,
+
+    /// Sign the commit with the SSH key in user.signingKey. Defaults to commit.gpgSign.
+    #[arg(short = 'S', long = "gpg-sign")]
+    pub sign: bool,
+
+    /// Commit the working tree contents of these tracked files instead of what's staged.
+    pub paths: Vec<PathBuf>,
+
+    /// Commit only the given paths, leaving other staged changes staged. This is the
+    /// default when paths are given.
+    #[arg(short, long, conflicts_with = "include")]
+    pub only: bool,
+
+    /// Stage the given paths and commit them along with everything already staged.
+    #[arg(short, long)]
+    pub include: bool,
+
+    /// Replace the current commit instead of adding a new one. Its message is reused
+    /// unless `-m` is given.
+    #[arg(long)]
+    pub amend: bool,
+
+    /// Amend the current commit even if a remote-tracking branch already contains it.
+    #[arg(long, requires = "amend")]
+    pub force: bool,
+
+    /// Record this person as the author instead of user.name and user.email, in the form
+    /// `Name <email>`. The committer is unchanged.
+    #[arg(long)]
+    pub author: Option<String>,
+
+    /// Record this date, e.g. `2024-01-31T12:00:00+01:00`, `1706698800 +0100`, or `yesterday`.
+    #[arg(long)]
+    pub date: Option<String>,
+}
+
+pub fn cmd_commit(args: CommitArgs, verbosity: Verbosity) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+    let mut meta = ObjectMetadata::new(&repo, String::new())?;
+    if args.sign || repo.get_config_bool("commit", "gpgSign") == Some(true) {
+        meta.signer = Some(SshSigner::from_config(&repo)?);
+    }
+    if let Some(author) = &args.author {
+        meta.set_author(author)?;
+    }
+    if let Some(date) = &args.date {
+        meta.set_date(date)?;
     }
-    seen.insert(*hash);
 
-    match GitObject::read(wd, hash)? {
-        GitObject::Commit(commit) => {
-            for parent_hash in commit.parents() {
-                println!("c_{hash} -> c_{parent_hash}");
-                log_graphviz(wd, parent_hash, seen)?;
+    let rules = WhitespaceRules::from_config(&repo)?;
+    let head = branch::get_current(wd)?.tip(wd)?;
+    if args.paths.is_empty() && args.include {
+        bail!("No paths with --include/--only does not make sense.");
+    }
+    if !args.paths.is_empty() && !args.include && repo.in_progress()?.contains(&InProgress::Merge) {
+        bail!("Cannot do a partial commit during a merge.");
+    }
+
+    let amended = match (args.amend, &head) {
+        (true, Some(head)) => {
+            if !args.force {
+                ensure_unpublished(wd, head)?;
             }
+            Some((*head, Commit::read(wd, head)?))
         },
-        object => return Err(branch::BranchError::BrokenCommitGraph(object.get_format()).into()),
+        (true, None) => bail!("You have nothing to amend."),
+        (false, _) => None,
     };
 
+    // The editor can stay open indefinitely, so the lock is only taken once the message is
+    // written. HEAD must not have moved in the meantime.
+    let message = prepare_commit_message(&repo, args.message, amended.as_ref())?;
+    let _lock = repo.lock()?;
+    if branch::get_current(wd)?.tip(wd)? != head {
+        bail!("HEAD changed while the commit message was being written. Please try again.");
+    }
+    let mut index = repo.index()?;
+
+    // A partial commit is built from HEAD plus the given paths, but the paths are staged
+    // in the real index too
+    let mut partial_index = None;
+    if !args.paths.is_empty() {
+        let mut head_index = match &head {
+            Some(head) => Tree::read_from_commit(wd, head)?.to_index(wd, None)?,
+            None => Index::new(None),
+        };
+
+        for path in &args.paths {
+            let path = wd.canonicalize_path(path)?;
+            let files = index.add_tracked(wd, &head_index, &path)?;
+            if !args.include {
+                for file in files {
+                    head_index.add(wd, wd.abs_path(&file), true)?;
+                }
+            }
+        }
+
+        if !args.include {
+            partial_index = Some(head_index);
+        }
+    }
+    let commit_index = partial_index.as_ref().unwrap_or(&index);
+
+    // Whitespace errors don't prevent the commit, but they're worth pointing out
+    for (path, error) in whitespace::check_staged(wd, commit_index, head.as_ref(), &rules)? {
+        eprintln!("{}", Message::WhitespaceWarning { path: &path, line: error.line, kind: error.kind });
+    }
+
+    meta.message = message;
+    let hash = match amended.as_ref().map(|(_, commit)| commit) {
+        Some(amended) => Commit::create_with_parents(commit_index, wd, meta, amended.parents().to_vec())?,
+        None => Commit::create(commit_index, wd, meta)?,
+    };
+    if !args.paths.is_empty() {
+        index.write(wd)?;
+    }
+    println!("{hash}");
+
+    // wyag can't write packfiles, so the best it can do is suggest running git gc
+    if verbosity > Verbosity::Quiet && repo.needs_gc()? {
+        eprintln!("{}", Message::AutoPackAdvice);
+    }
+
     Ok(())
 }
 
-/// List all the files in the staging index.
-#[derive(Args)]
-pub struct LsFilesArgs { }
+/// Works out the message for a new commit: the one given with `-m`, the amended commit's,
+/// or one written in the editor (starting from MERGE_MSG or commit.template). The
+/// prepare-commit-msg hook, if there is one, can change the message before it's edited.
+fn prepare_commit_message(repo: &Repository, message: Option<String>, amended: Option<&(ObjectHash, Commit)>) -> Result<String> {
+    let wd = repo.workdir();
+    let merge_msg_path = wd.git_path(MERGE_MSG);
+    let template = match repo.get_config("commit", "template") {
+        Some(path) => {
+            let path = match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+                (Some(rest), Some(home)) => Path::new(&home).join(rest),
+                _ => wd.as_path().join(path),
+            };
+            let template = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read the commit template at `{path:?}`"))?;
+            Some(template)
+        },
+        None => None,
+    };
 
-pub fn cmd_ls_files(_args: LsFilesArgs) -> Result<()> {
-    let repo = Repository::find(".")?;
-    let index = repo.index()?;
+    // The source is passed to the hook, as in git, along with the hash of an amended commit
+    // whose message is reused
+    let mut amended_hash = None;
+    let (initial, source, edit) = match (message, amended) {
+        (Some(message), _) => (message, Some("message"), false),
+        (None, Some((hash, commit))) => {
+            amended_hash = Some(hash.to_string());
+            (commit.message().to_owned(), Some("commit"), false)
+        },
+        (None, None) if merge_msg_path.is_file() => (std::fs::read_to_string(&merge_msg_path)?, Some("merge"), true),
+        (None, None) => match &template {
+            Some(template) => (template.clone(), Some("template"), true),
+            None => (String::new(), None, true),
+        },
+    };
 
-    if !index.ext_data.is_empty() {
-        eprintln!("Warning: index contains unsupported extensions.");
+    // Without an editor or a hook, the message is used exactly as given
+    if !edit && hooks::find(repo, hooks::PREPARE_COMMIT_MSG).is_none() {
+        return Ok(initial);
+    }
+
+    let path = wd.git_path(COMMIT_EDITMSG);
+    let mut contents = initial;
+    if edit {
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push('\n');
+        contents.push_str(editor::COMMIT_INSTRUCTIONS);
+    }
+    wd.write_git_file(COMMIT_EDITMSG, contents.as_bytes())?;
+
+    let path_arg = path.to_string_lossy();
+    let hook_args: Vec<&str> = std::iter::once(path_arg.as_ref())
+        .chain(source)
+        .chain(amended_hash.as_deref())
+        .collect();
+    hooks::run(repo, hooks::PREPARE_COMMIT_MSG, &hook_args)?;
+
+    let message = std::fs::read_to_string(&path)
+        .context("Failed to read the commit message")?;
+    if !edit {
+        return Ok(message);
+    }
+
+    editor::edit_file(repo, &path)?;
+    let message = editor::cleanup_message(&std::fs::read_to_string(&path)?);
+    if message.is_empty() {
+        bail!("Aborting commit due to empty commit message.");
     }
+    if source == Some("template") && template.is_some_and(|template| editor::cleanup_message(&template) == message) {
+        bail!("Aborting commit; you did not edit the message.");
+    }
+
+    Ok(message)
+}
+
+/// Fails if the commit `hash` is in a remote-tracking branch, because replacing it would
+/// rewrite history that has already been pushed.
+fn ensure_unpublished(wd: &WorkDir, hash: &ObjectHash) -> Result<()> {
+    for remote_ref in refs::iter(wd, REMOTE_PREFIX)? {
+        let remote_ref = remote_ref?;
+        // e.g. `origin/HEAD`, which just names another remote-tracking branch
+        if matches!(remote_ref.target, refs::RefTarget::Symbolic(_)) {
+            continue;
+        }
 
-    for (path, entry) in index.entries {
-        println!("{} {}", entry.hash, path);
+        if history::is_ancestor(wd, hash, &remote_ref.resolve(wd)?)? {
+            bail!(
+                "Commit {} is already in {}, so amending it would rewrite published history. Use --force to amend anyway.",
+                &hash.to_string()[..7],
+                remote_ref.short_name(),
+            );
+        }
     }
 
-    Ok(())
+    Ok(())
+}
+
+/// Copy the objects and refs of another repository on this machine, hard-linking them
+/// where possible.
+#[derive(Args)]
+pub struct CopyObjectsArgs {
+    /// The path or `file://` URL of the repository to copy from.
+    pub source: String,
+
+    /// Copy branches to refs/remotes/<ORIGIN>/.
+    #[arg(short, long, default_value = "origin")]
+    pub origin: String,
+
+    /// Also copy the objects of every alternate, then remove the alternates so the
+    /// repository is self-contained.
+    #[arg(long)]
+    pub dissociate: bool,
+}
+
+pub fn cmd_copy_objects(args: CopyObjectsArgs, verbosity: Verbosity) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
+    let source = match RemoteLocation::parse(repo.workdir(), &args.source)? {
+        RemoteLocation::Local(path) => Repository::find(path)?,
+        RemoteLocation::Network(url) => bail!("Can only copy objects from a repository on this machine, not `{url}`"),
+    };
+
+    let stats = copy_objects::copy_objects(repo.workdir(), source.workdir(), &args.origin, args.dissociate)?;
+    info!(verbosity, "{}", Message::ObjectsCopied {
+        objects: stats.objects,
+        packs: stats.packs,
+        linked: stats.linked,
+        refs: stats.refs,
+    });
+    for tag in stats.skipped_tags {
+        eprintln!("{}", Message::SkippedTag(&tag));
+    }
+
+    Ok(())
+}
+
+/// Shows changes between the index and the working tree, or between HEAD and the index.
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Show the changes staged for the next commit instead.
+    #[arg(long, visible_alias = "staged")]
+    pub cached: bool,
+    /// The number of unchanged lines to show around each change. Defaults to diff.context,
+    /// or 3.
+    #[arg(short = 'U', long = "unified")]
+    pub context: Option<usize>,
+    /// Ignore whitespace when comparing lines.
+    #[arg(short = 'w', long)]
+    pub ignore_all_space: bool,
+    /// Ignore changes in the amount of whitespace.
+    #[arg(short = 'b', long)]
+    pub ignore_space_change: bool,
+    /// The diff algorithm to use. Defaults to diff.algorithm.
+    #[arg(long, value_enum)]
+    pub diff_algorithm: Option<ClapDiffAlgorithm>,
+    /// Exit with status 1 if there are differences, and 0 otherwise.
+    #[arg(long)]
+    pub exit_code: bool,
+    /// Don't show the differences. Implies --exit-code.
+    #[arg(long)]
+    pub quiet: bool,
+    /// The commit (or tree) to compare the working tree to, or the index with --cached. A lone
+    /// argument is taken as a revision if it resolves to one, and as a path otherwise.
+    pub rev: Option<String>,
+    /// The file or directory to compare. Defaults to the whole working tree.
+    pub path: Option<PathBuf>,
+    /// The file or directory to compare, given after `--` so it's never taken as a revision.
+    #[arg(last = true, conflicts_with = "path")]
+    pub after_separator: Option<PathBuf>,
+}
+
+pub fn cmd_diff(args: DiffArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+    let separated = args.after_separator.is_some();
+    let (rev, path) = match (args.rev, args.path.or(args.after_separator)) {
+        // Without `--`, a lone argument that isn't a revision is a path
+        (Some(rev), None) if !separated && GitObject::find(wd, &rev).is_err() => (None, PathBuf::from(rev)),
+        (rev, path) => (rev, path.unwrap_or_else(|| PathBuf::from("."))),
+    };
+    let path = wd.canonicalize_path(path)?;
+    let mut index = repo.index()?;
+    index.expand_to_path(wd, &path)?;
+
+    // Compared to a revision, the working tree is the index as if every tracked file were added
+    let worktree_vs_rev = rev.is_some() && !args.cached;
+    if worktree_vs_rev {
+        index.apply_worktree_changes(wd, &path)?;
+    }
+
+    let context = match args.context {
+        Some(context) => context,
+        None => repo.get_config_int("diff", "context")?.map_or(3, |context| context as usize),
+    };
+    let algorithm = match args.diff_algorithm {
+        Some(algorithm) => algorithm.into(),
+        None => DiffAlgorithm::from_config(&repo)?,
+    };
+    let options = PatchOptions {
+        context,
+        diff: DiffOptions {
+            ignore_all_space: args.ignore_all_space,
+            ignore_space_change: args.ignore_space_change,
+            algorithm,
+        },
+    };
+
+    let index_side = |path: &WorkPathBuf| -> Result<Option<PatchSide>> {
+        index.entries.get(path)
+            .map(|entry| PatchSide::from_blob(wd, entry.stats.get_mode()?, entry.hash))
+            .transpose()
+    };
+
+    let mut patches = Vec::new();
+    if args.cached || worktree_vs_rev {
+        let base_tree = match &rev {
+            Some(rev) => Some(Tree::read(wd, &GitObject::find_as(wd, rev, ObjectFormat::Tree)?)?),
+            None => branch::get_current(wd)?.tip(wd)?
+                .map(|hash| Tree::read_tree_ish(wd, &hash))
+                .transpose()?,
+        };
+
+        for change in index.list_changes_from_tree(wd, base_tree.as_ref(), &path)? {
+            let path = match change {
+                StagedChange::Created { path }
+                | StagedChange::Deleted { path }
+                | StagedChange::Modified { path } => path,
+            };
+            let old_entry = match &base_tree {
+                Some(tree) => tree.find_entry(wd, &path)?,
+                None => None,
+            };
+            let old = old_entry
+                .map(|entry| PatchSide::from_blob(wd, entry.mode, entry.hash))
+                .transpose()?;
+            // The hashes of modified files were never written, so read the files themselves
+            let new = match index.entries.get(&path) {
+                Some(entry) if worktree_vs_rev && !entry.is_gitlink() => Some(PatchSide {
+                    mode: entry.stats.get_mode()?,
+                    hash: entry.hash,
+                    data: wd.read_worktree_file(&path)?,
+                }),
+                _ => index_side(&path)?,
+            };
+
+            patches.push(FilePatch { path, old, new });
+        }
+    }
+    else {
+        for change in index.list_unstaged_changes(wd, &path, false)? {
+            match change {
+                // Untracked files aren't shown
+                UnstagedChange::Created { .. } | UnstagedChange::NestedRepo { .. } => (),
+                UnstagedChange::Deleted { path } => {
+                    let old = index_side(&path)?;
+                    patches.push(FilePatch { path, old, new: None });
+                },
+                UnstagedChange::Modified { path, stats, hash } => {
+                    let old = index_side(&path)?;
+                    let new = Some(PatchSide {
+                        mode: stats.get_mode()?,
+                        hash,
+                        data: wd.read_worktree_file(&path)?,
+                    });
+                    patches.push(FilePatch { path, old, new });
+                },
+                // A case-only rename is the same file, so only its contents are compared
+                UnstagedChange::Renamed { old_path, path, stats, hash } => {
+                    let old = index_side(&old_path)?;
+                    if old.as_ref().is_some_and(|old| old.hash == hash) {
+                        continue;
+                    }
+                    let new = Some(PatchSide {
+                        mode: stats.get_mode()?,
+                        hash,
+                        data: wd.read_worktree_file(&path)?,
+                    });
+                    patches.push(FilePatch { path: old_path, old, new });
+                },
+            }
+        }
+    }
+
+    patches.sort_by(|a, b| a.path.cmp(&b.path));
+    let has_differences = !patches.is_empty();
+
+    if !args.quiet {
+        let mut stdout = std::io::stdout().lock();
+        for mut patch in patches {
+            let attrs = PathAttributes::resolve(wd, &patch.path)?;
+            let driver = DiffDriver::for_attributes(&repo, &attrs)?;
+            patch.textconv(&driver)?;
+            patch.write(&mut stdout, &options, &driver)?;
+        }
+    }
+
+    if (args.exit_code || args.quiet) && has_differences {
+        return Err(ExitStatus(1).into());
+    }
+
+    Ok(())
+}
+
+/// Output information about each ref.
+#[derive(Args)]
+pub struct ForEachRefArgs {
+    /// How to display each ref, e.g. `%(refname:short) %(objectname)`.
+    #[arg(long)]
+    pub format: Option<String>,
+
+    /// The field to sort by. Prefix with `-` to sort in descending order. Can be repeated,
+    /// in which case the last key takes precedence.
+    #[arg(long)]
+    pub sort: Vec<String>,
+
+    /// Stop after showing this many refs.
+    #[arg(long)]
+    pub count: Option<usize>,
+
+    /// Only show refs that match one of these patterns (a prefix like `refs/heads` or a glob).
+    pub patterns: Vec<String>,
+}
+
+pub fn cmd_for_each_ref(args: ForEachRefArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+
+    let format = refs::RefFormat::parse(args.format.as_deref().unwrap_or(refs::RefFormat::DEFAULT))?;
+    let sort_keys = args.sort.iter()
+        .map(|key| refs::RefSortKey::parse(key))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut matching_refs = Vec::new();
+    for git_ref in refs::iter(wd, "refs/")? {
+        let git_ref = git_ref?;
+        let is_match = args.patterns.is_empty()
+            || args.patterns.iter().any(|pattern| refs::matches_pattern(&git_ref.name, pattern));
+        if is_match {
+            matching_refs.push(git_ref);
+        }
+    }
+
+    refs::sort_refs(wd, &mut matching_refs, &sort_keys)?;
+
+    let count = args.count.unwrap_or(usize::MAX);
+    for git_ref in matching_refs.iter().take(count) {
+        println!("{}", format.format(wd, git_ref)?);
+    }
+
+    Ok(())
+}
+
+/// Verifies the loose objects in the repository.
+#[derive(Args)]
+pub struct FsckArgs { }
+
+pub fn cmd_fsck(_args: FsckArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+
+    let mut error_count = 0;
+    let mut links = vec![];
+    for hash in GitObject::loose_hashes(wd)? {
+        let (format, data) = match GitObject::read_raw(wd, &hash) {
+            Ok(object) => object,
+            Err(err) => {
+                println!("{}", Message::FsckUnreadable { hash: &hash, err: &err });
+                error_count += 1;
+                continue;
+            },
+        };
+
+        let actual_hash = ObjectHash::for_object(format, &data);
+        if actual_hash != hash {
+            println!("{}", Message::FsckHashMismatch { hash: &hash, actual: &actual_hash });
+            error_count += 1;
+            continue;
+        }
+
+        if format == ObjectFormat::Tree {
+            for problem in Tree::check(&data) {
+                println!("{}", Message::FsckTreeProblem { hash: &hash, problem });
+                error_count += usize::from(problem.is_error());
+            }
+        }
+
+        match GitObject::deserialize(data, format).and_then(|object| object.references()) {
+            Ok(references) => links.extend(references.into_iter().map(|target| (format, hash, target))),
+            Err(err) => {
+                println!("{}", Message::FsckInvalid { format, hash: &hash, err: &err });
+                error_count += 1;
+            },
+        }
+    }
+
+    // Every object that's referred to should exist, whether it's loose, packed, or in an alternate
+    let targets: Vec<_> = links.iter().map(|(_, _, target)| *target).collect();
+    let missing: HashSet<_> = ObjectStore::open(wd)?.missing(&targets).into_iter().collect();
+    for (format, hash, target) in links {
+        if missing.contains(&target) {
+            println!("{}", Message::FsckBrokenLink { format, hash: &hash, target: &target });
+            error_count += 1;
+        }
+    }
+
+    if error_count > 0 {
+        bail!("Found {error_count} errors in the object store");
+    }
+
+    Ok(())
+}
+
+/// Writes man pages for wyag, each of its commands, and each help topic.
+#[derive(Args)]
+pub struct GenerateDocsArgs {
+    /// The directory to write the pages to.
+    #[arg(default_value = "docs/man")]
+    pub out_dir: PathBuf,
+}
+
+pub fn cmd_generate_docs(args: GenerateDocsArgs) -> Result<()> {
+    docs::generate_man_pages(&mut Cli::command(), &args.out_dir)
+}
+
+/// Show help for a command, or read about a topic like revision syntax.
+#[derive(Args)]
+pub struct HelpArgs {
+    /// The command or topic. Lists the commands and topics if omitted.
+    pub topic: Option<String>,
+}
+
+pub fn cmd_help(args: HelpArgs) -> Result<()> {
+    let mut cli = Cli::command();
+    cli.build();
+
+    let Some(name) = args.topic else {
+        cli.print_long_help()?;
+        println!("\nHelp topics:");
+        for topic in HelpTopic::ALL {
+            println!("  {:<12}{}", topic.name(), topic.summary());
+        }
+        return Ok(());
+    };
+
+    if let Some(topic) = HelpTopic::find(&name) {
+        print!("{}", topic.text());
+    }
+    else if let Some(command) = cli.find_subcommand_mut(&name) {
+        command.print_long_help()?;
+    }
+    else {
+        return Err(DocsError::UnknownTopic(name).into());
+    }
+
+    Ok(())
+}
+
+/// Computes object hash and optionally creates a blob from a file.
+#[derive(Args)]
+pub struct HashObjectArgs {
+    /// Actually write the object into the database
+    #[arg(short, long)]
+    pub write: bool,
+
+    /// The type of the object
+    #[arg(id = "type", short, long, default_value = "blob")]
+    pub format: ClapObjectFormat,
+
+    /// Don't check that the object is well-formed. Useful for testing how broken objects
+    /// are handled
+    #[arg(long)]
+    pub literally: bool,
+
+    /// Path to read the object from
+    pub path: PathBuf,
+}
+
+pub fn cmd_hash_object(args: HashObjectArgs) -> Result<()> {
+    let format = args.format.into();
+    let data = std::fs::read(&args.path)?;
+    if !args.literally {
+        GitObject::validate(format, &data)?;
+    }
+
+    // The bytes are stored as given, even if parsing and serializing would change them
+    let hash = if args.write {
+        let repo = Repository::find(".")?;
+        GitObject::write_raw(repo.workdir(), format, &data)?
+    }
+    else {
+        ObjectHash::for_object(format, &data)
+    };
+
+    println!("{hash}");
+
+    Ok(())
+}
+
+/// Creates a new git repository.
+#[derive(Args)]
+pub struct InitArgs {
+    /// Where to create the repository.
+    pub path: Option<PathBuf>,
+    /// Put the git directory here instead, and make `.git` a file that points to it.
+    #[arg(long, value_name = "GIT_DIR")]
+    pub separate_git_dir: Option<PathBuf>,
+}
+
+pub fn cmd_init(args: InitArgs, verbosity: Verbosity) -> Result<()> {
+    let path = args.path.unwrap_or(PathBuf::from("."));
+    match &args.separate_git_dir {
+        Some(git_dir) => Repository::init_separate(&path, git_dir)?,
+        None => Repository::init(&path)?,
+    };
+    
+    info!(verbosity, "{}", Message::Initialized(&path));
+
+    Ok(())
+}
+
+/// Display history of a given commit as a graphviz graph.
+#[derive(Args)]
+pub struct LogArgs {
+    #[command(flatten)]
+    pub walk: RevWalkArgs,
+    /// List each commit on one line as its abbreviated hash and subject, instead of as a graph.
+    #[arg(long)]
+    pub oneline: bool,
+    /// List each commit with its author, date, and full message, like git's default format,
+    /// instead of as a graph.
+    #[arg(long, conflicts_with = "oneline")]
+    pub pretty: bool,
+    /// How --pretty shows dates: `default`, `relative`, `iso`, `short`, `raw`, or
+    /// `format:<strftime format>`.
+    #[arg(long, value_name = "STYLE", default_value = "default")]
+    pub date: DateStyle,
+    /// The commits to start at, or ranges like `A..B` (the commits in B but not A) and `A...B`
+    /// (the commits in either but not both).
+    #[arg(default_value = HEAD)]
+    pub revisions: Vec<String>,
+}
+
+/// Options for listing a range of commits.
+#[derive(Args)]
+pub struct RevWalkArgs {
+    /// Only list commits that descend from the start of the range and lead to its end.
+    #[arg(long)]
+    pub ancestry_path: bool,
+    /// Also list the excluded commits that are parents of listed ones.
+    #[arg(long)]
+    pub boundary: bool,
+    /// Only list commits made at or after this date, e.g. `2024-01-31` or `2 weeks ago`.
+    #[arg(long, visible_alias = "after", value_name = "DATE")]
+    pub since: Option<String>,
+    /// Only list commits made at or before this date.
+    #[arg(long, visible_alias = "before", value_name = "DATE")]
+    pub until: Option<String>,
+}
+
+pub fn cmd_log(args: LogArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+    let walked = rev_walk(wd, &args.revisions, &args.walk)?.run(wd)?;
+
+    if args.oneline {
+        for hash in &walked.commits {
+            println!("{} {}", &hash.to_string()[..7], Commit::read(wd, hash)?.subject());
+        }
+        if args.walk.boundary {
+            for hash in &walked.boundary {
+                println!("- {} {}", &hash.to_string()[..7], Commit::read(wd, hash)?.subject());
+            }
+        }
+        return Ok(());
+    }
+
+    if args.pretty {
+        let mut hashes = walked.commits.iter().collect::<Vec<_>>();
+        if args.walk.boundary {
+            hashes.extend(&walked.boundary);
+        }
+        for (n, hash) in hashes.into_iter().enumerate() {
+            if n > 0 {
+                println!();
+            }
+            print!("{}", pretty::format_commit(hash, &Commit::read(wd, hash)?, &args.date));
+        }
+        return Ok(());
+    }
+
+    let mut shown: HashSet<&ObjectHash> = walked.commits.iter().collect();
+    if args.walk.boundary {
+        shown.extend(&walked.boundary);
+    }
+
+    println!("digraph wyaglog{{");
+    for hash in &walked.commits {
+        for parent_hash in Commit::read(wd, hash)?.parents() {
+            if shown.contains(parent_hash) {
+                println!("c_{hash} -> c_{parent_hash}");
+            }
+        }
+    }
+    println!("}}");
+
+    Ok(())
+}
+
+/// Lists the commits selected by `revisions`, e.g. `main`, `^v1.0`, or `v1.0..main`.
+fn rev_walk(wd: &WorkDir, revisions: &[String], args: &RevWalkArgs) -> Result<RevWalk> {
+    let parse_date = |date: &str| date::approxidate(date, date::now())
+        .with_context(|| format!("Invalid date `{date}`"));
+
+    let mut walk = RevWalk::new();
+    walk.ancestry_path = args.ancestry_path;
+    walk.since = args.since.as_deref().map(parse_date).transpose()?;
+    walk.until = args.until.as_deref().map(parse_date).transpose()?;
+    for revision in revisions {
+        walk.push_revision(wd, revision)?;
+    }
+
+    Ok(walk)
+}
+
+/// List all the files in the staging index.
+#[derive(Args)]
+pub struct LsFilesArgs {
+    /// Fail if any of the paths doesn't match a file in the index.
+    #[arg(long)]
+    pub error_unmatch: bool,
+    /// End each record with NUL instead of a newline, and print paths without quoting.
+    #[arg(short = 'z')]
+    pub nul_terminated: bool,
+    /// Only list files at or under these paths.
+    pub paths: Vec<PathBuf>,
+}
+
+pub fn cmd_ls_files(args: LsFilesArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+    let index = repo.index()?;
+
+    if !index.ext_data.is_empty() {
+        eprintln!("{}", Message::UnsupportedIndexExtensions);
+    }
+
+    let filters = args.paths.iter()
+        .map(|path| wd.canonicalize_path(path))
+        .collect::<Result<Vec<_>>>()?;
+    // A path inside a sparse directory matches the directory's entry
+    let matches = |filter: &WorkPathBuf, path: &WorkPathBuf, is_sparse_dir: bool| {
+        filter.is_empty()
+            || filter == path
+            || path.as_str().starts_with(&format!("{filter}/"))
+            || (is_sparse_dir && filter.as_str().starts_with(&format!("{path}/")))
+    };
+
+    if args.error_unmatch {
+        for filter in &filters {
+            let found = index.entries.iter()
+                .any(|(path, entry)| matches(filter, path, entry.is_sparse_dir()));
+            if !found {
+                return Err(IndexError::PathNotTracked(filter.clone()).into());
+            }
+        }
+    }
+
+    for (path, entry) in &index.entries {
+        if !filters.is_empty() && !filters.iter().any(|filter| matches(filter, path, entry.is_sparse_dir())) {
+            continue;
+        }
+
+        let mut record = format!("{} ", entry.hash).into_bytes();
+        record.extend(record_path(path, args.nul_terminated));
+        if entry.is_sparse_dir() {
+            record.push(b'/');
+        }
+        print_record(&record, args.nul_terminated)?;
+    }
+
+    Ok(())
+}
+
+/// Pretty-print a tree object.
+#[derive(Args)]
+pub struct LsTreeArgs {
+    /// The tree object (or a commit or tag that leads to one) to display.
+    pub object: String,
+    /// End each record with NUL instead of a newline, and print paths without quoting.
+    #[arg(short = 'z')]
+    pub nul_terminated: bool,
+}
+
+pub fn cmd_ls_tree(args: LsTreeArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let hash = GitObject::find_as(repo.workdir(), &args.object, ObjectFormat::Tree)?;
+    let tree = Tree::read(repo.workdir(), &hash)?;
+
+    for (path, entry) in tree.sorted_entries() {
+        // A gitlink's commit is in the submodule's repository, so it can't be read
+        let format = match entry.is_gitlink() {
+            true => "commit".to_owned(),
+            false => GitObject::read(repo.workdir(), &entry.hash)?.get_format().to_string(),
+        };
+        let mut record = format!("{} {} {}\t", entry.mode, format, entry.hash).into_bytes();
+        record.extend(record_path(path, args.nul_terminated));
+        print_record(&record, args.nul_terminated)?;
+    }
+
+    Ok(())
+}
+
+/// Join another commit's history into the current branch. Only fast-forwards are supported,
+/// though they can be recorded as merge commits with --no-ff.
+#[derive(Args)]
+pub struct MergeArgs {
+    /// Fast-forward when possible, and create a merge commit otherwise. This is the default
+    /// unless merge.ff is set.
+    #[arg(long, group = "fast_forward")]
+    pub ff: bool,
+    /// Create a merge commit even when fast-forwarding is possible.
+    #[arg(long, group = "fast_forward")]
+    pub no_ff: bool,
+    /// Refuse to merge unless fast-forwarding is possible.
+    #[arg(long, group = "fast_forward")]
+    pub ff_only: bool,
+    /// The message for the merge commit. Defaults to naming the merged branch or commit.
+    #[arg(short, long)]
+    pub message: Option<String>,
+    /// The branch or commit to merge into the current branch.
+    pub commit: String,
+}
+
+pub fn cmd_merge(args: MergeArgs, verbosity: Verbosity) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
+    let wd = repo.workdir();
+
+    let policy = match (args.ff, args.no_ff, args.ff_only) {
+        (true, _, _) => FastForward::Allow,
+        (_, true, _) => FastForward::Never,
+        (_, _, true) => FastForward::Only,
+        _ => FastForward::from_config(&repo)?,
+    };
+
+    let theirs = GitObject::find_as(wd, &args.commit, ObjectFormat::Commit)?;
+    let current = branch::get_current(wd)?;
+    let head = current.tip(wd)?;
+
+    if let Some(head) = &head {
+        if history::is_ancestor(wd, &theirs, head)? {
+            info!(verbosity, "Already up to date.");
+            return Ok(());
+        }
+        if !history::is_ancestor(wd, head, &theirs)? {
+            return Err(match policy {
+                FastForward::Only => MergeError::NotFastForward,
+                FastForward::Allow | FastForward::Never => MergeError::Diverged,
+            }.into());
+        }
+    }
+
+    // Whether it fast-forwards or not, the result has the same tree as the merged commit
+    let mut index = repo.index()?;
+    check_out_commit(wd, &mut index, head.as_ref(), &theirs, "merge")?;
+
+    match head {
+        Some(head) if policy == FastForward::Never => {
+            let message = args.message.unwrap_or_else(|| {
+                let kind = match branch::exists(&args.commit, wd) {
+                    Ok(true) => "branch",
+                    _ => "commit",
+                };
+                match &current {
+                    branch::Branch::Named(name) if name != "main" && name != "master" => {
+                        format!("Merge {kind} '{}' into {name}", args.commit)
+                    },
+                    _ => format!("Merge {kind} '{}'", args.commit),
+                }
+            });
+            let meta = ObjectMetadata::new(&repo, message)?;
+            let hash = Commit::create_with_parents(&index, wd, meta, vec![head, theirs])?;
+            println!("{hash}");
+        },
+        _ => {
+            if let Some(head) = head {
+                info!(verbosity, "Updating {}..{}", &head.to_string()[..7], &theirs.to_string()[..7]);
+            }
+            info!(verbosity, "Fast-forward");
+            branch::update_current(wd, &theirs)?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Find the best common ancestor of two commits, or check whether one is an ancestor of the
+/// other.
+#[derive(Args)]
+pub struct MergeBaseArgs {
+    /// Exit with status 0 if the first commit is an ancestor of the second, or 1 if not,
+    /// without printing anything.
+    #[arg(long)]
+    pub is_ancestor: bool,
+
+    /// Print every best common ancestor instead of just one.
+    #[arg(short, long, conflicts_with = "is_ancestor")]
+    pub all: bool,
+
+    pub first: String,
+    pub second: String,
+}
+
+pub fn cmd_merge_base(args: MergeBaseArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+    let first = GitObject::find_as(wd, &args.first, ObjectFormat::Commit)?;
+    let second = GitObject::find_as(wd, &args.second, ObjectFormat::Commit)?;
+
+    if args.is_ancestor {
+        return match history::is_ancestor(wd, &first, &second)? {
+            true => Ok(()),
+            false => Err(ExitStatus(1).into()),
+        };
+    }
+
+    let bases = Commit::merge_bases(wd, &first, &second)?;
+    if bases.is_empty() {
+        return Err(ExitStatus(1).into());
+    }
+    let count = match args.all {
+        true => bases.len(),
+        false => 1,
+    };
+    for base in &bases[..count] {
+        println!("{base}");
+    }
+
+    Ok(())
+}
+
+/// Merges the changes from base to other into current (a three-way file merge)
+#[derive(Args)]
+pub struct MergeFileArgs {
+    /// Print the result instead of overwriting the current file.
+    #[arg(short = 'p', long)]
+    pub stdout: bool,
+    /// Show the base version in conflicts.
+    #[arg(long)]
+    pub diff3: bool,
+    /// Resolve conflicts using the current version.
+    #[arg(long, group = "favor")]
+    pub ours: bool,
+    /// Resolve conflicts using the other version.
+    #[arg(long, group = "favor")]
+    pub theirs: bool,
+    /// Resolve conflicts by using both versions.
+    #[arg(long, group = "favor")]
+    pub union: bool,
+    /// The length of the conflict markers.
+    #[arg(long, default_value_t = 7)]
+    pub marker_size: usize,
+    /// Ignore whitespace when comparing lines.
+    #[arg(short = 'w', long)]
+    pub ignore_all_space: bool,
+    /// Ignore changes in the amount of whitespace.
+    #[arg(short = 'b', long)]
+    pub ignore_space_change: bool,
+    /// The diff algorithm to use. Defaults to `diff.algorithm` when run in a repository.
+    #[arg(long, value_enum)]
+    pub diff_algorithm: Option<ClapDiffAlgorithm>,
+    /// Labels for the current, base, and other versions in conflicts. Defaults to the
+    /// file names.
+    #[arg(short = 'L', num_args = 1, action = clap::ArgAction::Append)]
+    pub labels: Vec<String>,
+    pub current: PathBuf,
+    pub base: PathBuf,
+    pub other: PathBuf,
+}
+
+pub fn cmd_merge_file(args: MergeFileArgs) -> Result<()> {
+    if args.labels.len() > 3 {
+        bail!("Too many labels (expected at most 3)");
+    }
+
+    let label = |index: usize, path: &PathBuf| {
+        args.labels.get(index)
+            .cloned()
+            .unwrap_or_else(|| path.to_string_lossy().into_owned())
+    };
+    let favor = match (args.ours, args.theirs, args.union) {
+        (true, _, _) => Some(Favor::Ours),
+        (_, true, _) => Some(Favor::Theirs),
+        (_, _, true) => Some(Favor::Union),
+        _ => None,
+    };
+    let algorithm = match args.diff_algorithm {
+        Some(algorithm) => algorithm.into(),
+        None => match Repository::find(".") {
+            Ok(repo) => DiffAlgorithm::from_config(&repo)?,
+            Err(_) => DiffAlgorithm::default(),
+        },
+    };
+    let options = MergeOptions {
+        style: if args.diff3 { ConflictStyle::Diff3 } else { ConflictStyle::Merge },
+        favor,
+        marker_size: args.marker_size,
+        ours_label: label(0, &args.current),
+        base_label: label(1, &args.base),
+        theirs_label: label(2, &args.other),
+        diff: DiffOptions {
+            ignore_all_space: args.ignore_all_space,
+            ignore_space_change: args.ignore_space_change,
+            algorithm,
+        },
+    };
+
+    let current = std::fs::read(&args.current)?;
+    let base = std::fs::read(&args.base)?;
+    let other = std::fs::read(&args.other)?;
+    let result = merge::merge_blobs(&base, &current, &other, &options);
+
+    if args.stdout {
+        std::io::Write::write_all(&mut std::io::stdout(), &result.data)?;
+    }
+    else {
+        std::fs::write(&args.current, &result.data)?;
+    }
+
+    if result.conflicts > 0 {
+        eprintln!("{}", Message::ConflictsFound(result.conflicts));
+        // Like git, the exit status is the number of conflicts
+        return Err(ExitStatus(result.conflicts.min(127) as u8).into());
+    }
+
+    Ok(())
+}
+
+/// Resolve conflicts by running a merge tool on each conflicted file, staging the files it
+/// resolves.
+#[derive(Args)]
+pub struct MergetoolArgs {
+    /// The tool to use instead of merge.tool. It's run with the command in
+    /// mergetool.<tool>.cmd.
+    #[arg(short, long)]
+    pub tool: Option<String>,
+    /// Only resolve conflicts at or under these paths.
+    pub paths: Vec<PathBuf>,
+}
+
+pub fn cmd_mergetool(args: MergetoolArgs, verbosity: Verbosity) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
+    let wd = repo.workdir();
+    let mut index = repo.index()?;
+
+    let filters = args.paths.iter()
+        .map(|path| wd.canonicalize_path(path))
+        .collect::<Result<Vec<_>>>()?;
+    let conflicted: Vec<WorkPathBuf> = index.conflicted_paths()
+        .filter(|path| filters.is_empty() || filters.iter().any(|filter| {
+            filter.is_empty() || filter == *path || path.as_str().starts_with(&format!("{filter}/"))
+        }))
+        .cloned()
+        .collect();
+    if conflicted.is_empty() {
+        info!(verbosity, "No files need merging");
+        return Ok(());
+    }
+
+    let tool = MergeTool::from_config(&repo, args.tool.as_deref())?;
+
+    let mut unresolved = 0;
+    for path in &conflicted {
+        let versions = ConflictVersions::from_index(wd, &index, path)?;
+        if versions.ours.is_none() || versions.theirs.is_none() {
+            info!(verbosity, "{}", Message::MergetoolSkipped(path));
+            unresolved += 1;
+            continue;
+        }
+
+        info!(verbosity, "{}", Message::MergetoolMerging(path));
+        if tool.run(wd, path, &versions)? {
+            index.insert_entry(path.clone(), IndexEntry::from_worktree_file(wd, path)?);
+        }
+        else {
+            eprintln!("{}", Message::MergetoolFailed(path));
+            unresolved += 1;
+        }
+    }
+    index.write(wd)?;
+
+    if unresolved > 0 {
+        return Err(ExitStatus(1).into());
+    }
+
+    Ok(())
+}
+
+/// Deletes loose objects that can't be reached from any ref, reflog, or the index.
+#[derive(Args)]
+pub struct PruneArgs {
+    /// Only prune objects last written before this, e.g. `2.weeks.ago` or `now`. Defaults
+    /// to gc.pruneExpire (or 2 weeks).
+    #[arg(long)]
+    pub expire: Option<String>,
+
+    /// List the objects that would be pruned without deleting them.
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// List the objects that are pruned.
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+pub fn cmd_prune(args: PruneArgs, verbosity: Verbosity) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
+    let expire = args.expire.as_deref()
+        .or_else(|| repo.get_config("gc", "pruneExpire"))
+        .unwrap_or(prune::DEFAULT_EXPIRE);
+    let cutoff = date::parse_expiry(expire, date::now())
+        .with_context(|| format!("Invalid expiry time `{expire}`"))?;
+
+    let pruned = prune::prune(&repo, cutoff, args.dry_run)?;
+    if args.dry_run || args.verbose || verbosity == Verbosity::Verbose {
+        for hash in pruned {
+            println!("{hash}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace the index with the contents of a tree, or with the three-way merge of three
+/// trees. The working tree isn't changed.
+#[derive(Args)]
+pub struct ReadTreeArgs {
+    /// Merge instead of replacing: with one tree, keep the stats of unchanged entries, and
+    /// with three (base, ours, and theirs), merge the paths only one side changed and leave
+    /// the rest unmerged.
+    #[arg(short = 'm')]
+    pub merge: bool,
+    /// The tree-ish to read, or with -m, the base, ours, and theirs.
+    #[arg(required = true, num_args = 1..=3)]
+    pub trees: Vec<String>,
+}
+
+pub fn cmd_read_tree(args: ReadTreeArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
+    let wd = repo.workdir();
+
+    let trees = args.trees.iter()
+        .map(|tree| Tree::read_tree_ish(wd, &GitObject::find(wd, tree)?))
+        .collect::<Result<Vec<_>>>()?;
+    let mut index = index_or_empty(&repo)?;
+
+    let read = match (args.merge, &trees[..]) {
+        (false, [tree]) => tree.to_index(wd, Some(index.version))?,
+        (true, [tree]) => merge::merge_index(wd, &index, tree, tree, tree)?.index,
+        (true, [base, ours, theirs]) => merge::merge_index(wd, &index, base, ours, theirs)?.index,
+        (false, _) => bail!("Only one tree can be read without -m."),
+        (true, _) => bail!("Merging needs one tree or three (base, ours, and theirs)."),
+    };
+    index.entries = read.entries;
+    index.conflict_stages = read.conflict_stages;
+    index.write(wd)?;
+
+    Ok(())
+}
+
+/// Reads the index, or starts an empty one if the index file is missing.
+fn index_or_empty(repo: &Repository) -> Result<Index> {
+    match repo.index() {
+        Ok(index) => Ok(index),
+        Err(err) if matches!(err.downcast_ref(), Some(RepoError::IndexMissing)) => Ok(Index::new(None)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Find commits that no branch points to any more, e.g. after deleting a branch or
+/// resetting, and show how the reflogs say they were lost.
+#[derive(Args)]
+pub struct RecoverArgs {
+    /// Create a branch named `recovered/<hash>` at each lost commit.
+    #[arg(long)]
+    pub create_branches: bool,
+}
+
+pub fn cmd_recover(args: RecoverArgs, verbosity: Verbosity) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+
+    let _lock = args.create_branches.then(|| repo.lock()).transpose()?;
+    let lost_commits = recover::find_lost_commits(wd)?;
+    if lost_commits.is_empty() {
+        println!("{}", Message::NoLostCommits);
+        return Ok(());
+    }
+
+    for lost in &lost_commits {
+        let short_hash = &lost.hash.to_string()[..7];
+        println!("{short_hash} {}", lost.commit.subject());
+        for (selector, entry) in &lost.reflog_entries {
+            println!("    {selector}: {}", entry.message);
+        }
+
+        if args.create_branches {
+            let name = format!("recovered/{short_hash}");
+            match branch::exists(&name, wd)? {
+                true => info!(verbosity, "    Branch {name} already exists"),
+                false => {
+                    branch::create(&name, wd, &lost.hash)?;
+                    info!(verbosity, "    Created branch {name}");
+                },
+            }
+        }
+    }
+
+    if !args.create_branches {
+        info!(verbosity);
+        info!(verbosity, "Run `wyag recover --create-branches` to create a branch for each of these commits.");
+    }
+
+    Ok(())
+}
+
+/// Manage reflog information.
+#[derive(Args)]
+pub struct ReflogArgs {
+    #[command(subcommand)]
+    pub command: ReflogCommand,
+}
+
+#[derive(Subcommand)]
+pub enum ReflogCommand {
+    /// Remove reflog entries older than the expiry time.
+    Expire(ReflogExpireArgs),
+    /// Remove individual reflog entries, e.g. `main@{2}`.
+    Delete(ReflogDeleteArgs),
+}
+
+#[derive(Args)]
+pub struct ReflogExpireArgs {
+    /// Remove entries older than this, e.g. `30.days.ago`, `now`, or `never`.
+    /// Defaults to gc.reflogExpire (or 90 days).
+    #[arg(long)]
+    pub expire: Option<String>,
+
+    /// Process the reflogs of all refs.
+    #[arg(long)]
+    pub all: bool,
+
+    /// The refs whose reflogs should be processed.
+    pub refs: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct ReflogDeleteArgs {
+    /// The entries to remove, e.g. `main@{2}` (0 is the most recent entry).
+    #[arg(required = true)]
+    pub entries: Vec<String>,
+}
+
+pub fn cmd_reflog(args: ReflogArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+
+    match args.command {
+        ReflogCommand::Expire(args) => {
+            let expire = args.expire.as_deref()
+                .unwrap_or_else(|| reflog::configured_expiry(&repo));
+            let cutoff = date::parse_expiry(expire, date::now())
+                .with_context(|| format!("Invalid expiry time `{expire}`"))?;
+
+            let ref_names = match args.all {
+                true => reflog::list(wd)?,
+                false => args.refs.iter()
+                    .map(|name| reflog::full_name(wd, name))
+                    .collect(),
+            };
+
+            for ref_name in ref_names {
+                reflog::expire(wd, &ref_name, cutoff)?;
+            }
+        },
+        ReflogCommand::Delete(args) => {
+            // Delete from the highest number down so earlier deletions don't shift later ones
+            let mut selectors = args.entries.iter()
+                .map(|entry| {
+                    let (name, n) = reflog::parse_selector(entry)?;
+                    Ok((reflog::full_name(wd, name), n))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            selectors.sort_unstable_by(|a, b| b.cmp(a));
+            selectors.dedup();
+
+            for (ref_name, n) in selectors {
+                reflog::delete(wd, &ref_name, n)?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Save or restore the state of every ref.
+#[derive(Args)]
+pub struct RefsArgs {
+    #[command(subcommand)]
+    pub command: RefsCommand,
+}
+
+#[derive(Subcommand)]
+pub enum RefsCommand {
+    /// Write a snapshot of HEAD and every ref, one `<hash> <name>` line each (`ref: <name>`
+    /// in place of the hash for symbolic refs).
+    Export(RefsExportArgs),
+    /// Point HEAD and the refs in a snapshot written by `refs export` to the targets it
+    /// records, all at once. The objects they point to must already be in the repository.
+    Import(RefsImportArgs),
+}
+
+#[derive(Args)]
+pub struct RefsExportArgs {
+    /// Write the snapshot to this file instead of stdout.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
 }
 
-/// Pretty-print a tree object.
 #[derive(Args)]
-pub struct LsTreeArgs {
-    /// The tree object to display.
-    pub object: String,
+pub struct RefsImportArgs {
+    /// Delete refs that aren't in the snapshot.
+    #[arg(long)]
+    pub prune: bool,
+    /// The snapshot to import. Use `-` to read from stdin.
+    pub file: PathBuf,
 }
 
-pub fn cmd_ls_tree(args: LsTreeArgs) -> Result<()> {
+pub fn cmd_refs(args: RefsArgs) -> Result<()> {
     let repo = Repository::find(".")?;
-    let hash = GitObject::find(repo.workdir(), &args.object)?;
-    let tree = Tree::read(repo.workdir(), &hash)?;
+    let wd = repo.workdir();
+
+    match args.command {
+        RefsCommand::Export(args) => {
+            let snapshot = refs::RefSnapshot::capture(wd)?.to_string();
+            match args.output {
+                Some(path) => std::fs::write(&path, snapshot)
+                    .with_context(|| format!("Failed to write refs snapshot {path:?}"))?,
+                None => print!("{snapshot}"),
+            }
+        },
+        RefsCommand::Import(args) => {
+            let text = match args.file.as_os_str() == "-" {
+                true => std::io::read_to_string(std::io::stdin().lock())?,
+                false => std::fs::read_to_string(&args.file)
+                    .with_context(|| format!("Failed to read refs snapshot {:?}", args.file))?,
+            };
+            let snapshot = refs::RefSnapshot::parse(&text)?;
 
-    for (path, entry) in &tree.entries {
-        let object = GitObject::read(repo.workdir(), &entry.hash)?;
-        println!("{:0>6} {} {}\t{}", entry.mode, object.get_format(), entry.hash, path);
+            let _lock = repo.lock()?;
+            snapshot.restore(wd, args.prune)?;
+        },
     }
 
     Ok(())
 }
 
-
-#[derive(Args)]
-pub struct MergeArgs { }
-
-pub fn cmd_merge(_args: MergeArgs) -> Result<()> {
-    todo!("not implemented")
-}
-
-/// Replace files in the working tree (or index) with those from the index (or commit).
+/// Replace files in the working tree (or index) with those from the index (or a tree-ish).
 /// Uncommitted changes may be discarded!
 #[derive(Args)]
 pub struct RestoreArgs {
-    /// The source of the files to restore. Defaults to HEAD if --staged, otherwise to the index.
+    /// The source of the files to restore (any commit, tag, or tree, such as `HEAD~2` or `main:src`).
+    /// Defaults to HEAD if --staged, otherwise to the index.
     #[arg(short, long)]
     pub source: Option<String>,
     /// Update the index to match the source.
@@ -320,8 +2195,15 @@ pub struct RestoreArgs {
     /// Update the working directory to match the source. This is the default unless --staged is present.
     #[arg(short='W', long)]
     pub worktree: bool,
-    /// The file or directory to restore.
-    pub path: PathBuf,
+    /// Delete tracked files at the given paths that aren't present in the source. By default, only
+    /// files present in the source are written and nothing else is deleted.
+    #[arg(long)]
+    pub no_overlay: bool,
+    /// The files or directories to restore. Globs like `src/*.rs` match tracked files.
+    #[arg(required_unless_present = "pathspec_from_file", conflicts_with = "pathspec_from_file")]
+    pub paths: Vec<PathBuf>,
+    #[command(flatten)]
+    pub pathspec_file: PathspecFileArgs,
 }
 
 pub fn cmd_restore(mut args: RestoreArgs) -> Result<()> {
@@ -330,39 +2212,117 @@ pub fn cmd_restore(mut args: RestoreArgs) -> Result<()> {
         args.worktree = true;
     }
     else if args.source.is_none() {
-        args.source = Some("HEAD".to_owned());
+        args.source = Some(HEAD.to_owned());
     }
 
     let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
     let wd = repo.workdir();
-    let path = wd.canonicalize_path(&args.path)?;
+    let source_hash = args.source.as_ref()
+        .map(|source| GitObject::find_as(wd, source, ObjectFormat::Tree))
+        .transpose()?;
+    // Which files are tracked is decided before --staged updates the index
+    let mut original_index = repo.index()?;
+    let paths = Pathspec::new(wd, args.pathspec_file.read_paths(args.paths)?)?
+        .expand(wd, &mut original_index, false)?;
 
     // Update index
     if args.staged {
-        let source = args.source.as_ref().expect("Source should default to HEAD when --staged is set");
-        let commit_hash = GitObject::find(wd, source)?;
-        let tree = Tree::read_from_commit(wd, &commit_hash)?;
-        let index = tree.to_index(wd, None)?;
+        let source_hash = source_hash.as_ref().expect("Source should default to HEAD when --staged is set");
+        let tree = Tree::read_tree_ish(wd, source_hash)?;
+        let mut index = repo.index()?;
+        for path in &paths {
+            index.restore_from_tree(wd, &tree, path)?;
+        }
         index.write(wd)?;
     }
 
     // Update working directory . . .
     if args.worktree {
-        if let Some(source) = args.source {
-            // . . . from commit
-            let commit_hash = GitObject::find(wd, &source)?;
-            Tree::restore_from_commit(wd, &commit_hash, &path)?;
+        if let Some(source_hash) = source_hash {
+            // . . . from tree-ish
+            let tree = Tree::read_tree_ish(wd, &source_hash)?;
+            original_index.check_untracked_collisions(wd, &tree, &paths)?;
+
+            for path in &paths {
+                Tree::restore_from_tree_ish(wd, &original_index, &source_hash, path, !args.no_overlay)?;
+            }
         }
         else {
             // . . . from index
             let index = repo.index()?;
-            index.restore(wd, &path)?;
+            for path in &paths {
+                index.restore(wd, path)?;
+            }
         }
     }
     
     Ok(())
 }
 
+/// Create commits that undo the changes introduced by existing commits. Stops if there are
+/// conflicts, to be resolved and then continued.
+#[derive(Args)]
+pub struct RevertArgs {
+    /// For merge commits, the parent (numbered from 1) to go back to. The changes the other
+    /// parents brought in are undone.
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub mainline: Option<u32>,
+    #[command(flatten)]
+    pub action: SequencerActionArgs,
+    /// The commits to revert. A range `A..B` is the commits reachable from B but not from A,
+    /// which are reverted newest first.
+    #[arg(required_unless_present = "action", conflicts_with = "action")]
+    pub commits: Vec<String>,
+}
+
+pub fn cmd_revert(args: RevertArgs, verbosity: Verbosity) -> Result<()> {
+    let options = SequencerOptions {
+        record_origin: false,
+        mainline: args.mainline.map(|n| n as usize),
+    };
+
+    run_sequencer_command(Operation::Revert, &args.action, &args.commits, options, verbosity)
+}
+
+/// List commits in reverse chronological order.
+#[derive(Args)]
+pub struct RevListArgs {
+    #[command(flatten)]
+    pub walk: RevWalkArgs,
+    /// Also list the trees and blobs the commits refer to, except the ones the excluded
+    /// commits have, along with their paths.
+    #[arg(long)]
+    pub objects: bool,
+    /// The commits to start at, or ranges like `A..B` (the commits in B but not A) and `A...B`
+    /// (the commits in either but not both).
+    #[arg(required = true)]
+    pub revisions: Vec<String>,
+}
+
+pub fn cmd_rev_list(args: RevListArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+    let walk = rev_walk(wd, &args.revisions, &args.walk)?;
+    let walked = walk.run(wd)?;
+
+    for hash in &walked.commits {
+        println!("{hash}");
+    }
+    if args.walk.boundary {
+        for hash in &walked.boundary {
+            println!("-{hash}");
+        }
+    }
+    if args.objects {
+        for object in walk.objects(wd, &walked)? {
+            println!("{} {}", object.hash, object.path);
+        }
+    }
+
+    Ok(())
+}
+
 /// Determines which object hash a name refers to (if any).
 #[derive(Args)]
 pub struct RevParseArgs {
@@ -399,20 +2359,29 @@ pub fn cmd_rev_parse(args: RevParseArgs) -> Result<()> {
 /// Removes files from the staging index and file system
 #[derive(Args)]
 pub struct RmArgs {
-    /// The file or directory to remove. Must match index and branch tip.
-    pub path: PathBuf,
+    /// The files or directories to remove, or globs like `src/*.rs`. Must match index and
+    /// branch tip.
+    #[arg(required_unless_present = "pathspec_from_file", conflicts_with = "pathspec_from_file")]
+    pub paths: Vec<PathBuf>,
+    #[command(flatten)]
+    pub pathspec_file: PathspecFileArgs,
 }
 
 pub fn cmd_rm(args: RmArgs) -> Result<()> {
     let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
     let mut index = repo.index()?;
 
     if !index.ext_data.is_empty() {
-        eprintln!("Warning: index contains unsupported extensions.");
+        eprintln!("{}", Message::UnsupportedIndexExtensions);
     }
 
-    index.remove(repo.workdir(), &args.path)?;
-    index.write(repo.workdir())?;
+    let wd = repo.workdir();
+    let pathspec = Pathspec::new(wd, args.pathspec_file.read_paths(args.paths)?)?;
+    for path in pathspec.expand(wd, &mut index, false)? {
+        index.remove(wd, wd.abs_path(&path))?;
+    }
+    index.write(wd)?;
 
     Ok(())
 }
@@ -423,10 +2392,9 @@ pub struct ShowRefArgs { }
 
 pub fn cmd_show_ref(_args: ShowRefArgs) -> Result<()> {
     let repo = Repository::find(".")?;
-    let refs = refs::list(repo.workdir())?;
-
-    for (name, hash) in refs {
-        println!("{hash} {name}");
+    for git_ref in refs::iter(repo.workdir(), "refs/")? {
+        let git_ref = git_ref?;
+        println!("{} {}", git_ref.resolve(repo.workdir())?, git_ref.name);
     }
 
     Ok(())
@@ -435,111 +2403,433 @@ pub fn cmd_show_ref(_args: ShowRefArgs) -> Result<()> {
 /// List staged and unstaged changes 
 #[derive(Args)]
 pub struct StatusArgs {
+    /// Print one `XY path` line per changed file in git's stable format, for scripts.
+    /// Prints nothing if there are no changes.
+    #[arg(long)]
+    pub porcelain: bool,
+    /// End each --porcelain record with NUL instead of a newline, and print paths without
+    /// quoting.
+    #[arg(short = 'z', requires = "porcelain")]
+    pub nul_terminated: bool,
+    /// List the differences between the working tree and this commit (or tree) instead,
+    /// as if every tracked file were staged. Untracked files are left out.
+    #[arg(long, value_name = "REV", conflicts_with = "porcelain")]
+    pub compare: Option<String>,
     /// The file or directory to compare
     #[arg(default_value = ".")]
     pub path: PathBuf,
 }
 
 pub fn cmd_status(args: StatusArgs) -> Result<()> {
-    let (staged_changes, unstaged_changes) = {
+    let (staged_changes, renames, unstaged_changes) = {
         let repo = Repository::find(".")?;
         let wd = repo.workdir();
         let path = wd.canonicalize_path(args.path)?;
-        let index = repo.index()?;
+        let mut index = repo.index()?;
+        index.expand_to_path(wd, &path)?;
         let commit_hash = branch::get_current(wd)?.tip(wd)?;
 
-        let staged_changes = index.list_staged_changes(wd, commit_hash.as_ref(), &path)?;
+        if let Some(rev) = &args.compare {
+            let base_tree = Tree::read(wd, &GitObject::find_as(wd, rev, ObjectFormat::Tree)?)?;
+            index.apply_worktree_changes(wd, &path)?;
+            print_comparison_status(rev, index.list_changes_from_tree(wd, Some(&base_tree), &path)?);
+            return Ok(());
+        }
+
+        if args.porcelain {
+            for record in porcelain_status(wd, &index, commit_hash.as_ref(), &path, args.nul_terminated)? {
+                print_record(&record, args.nul_terminated)?;
+            }
+            return Ok(());
+        }
+
+        let conflicted_paths: Vec<_> = index.conflicted_paths().cloned().collect();
+
+        for operation in repo.in_progress()? {
+            println!("{}", operation.description());
+            for hint in operation.hints(!conflicted_paths.is_empty()) {
+                println!("  ({hint})");
+            }
+        }
+
+        if !conflicted_paths.is_empty() {
+            println!("{}", Message::UnmergedPaths);
+            println!("{}", Message::UnmergedPathsHint);
+            for path in &conflicted_paths {
+                println!("{}", Message::Change { kind: ChangeKind::Unmerged, path });
+            }
+        }
+
+        let mut staged_changes = index.list_staged_changes(wd, commit_hash.as_ref(), &path)?;
+        let renames = index.detect_staged_renames(wd, commit_hash.as_ref(), &mut staged_changes)?;
         let unstaged_changes = index.list_unstaged_changes(wd, &path, false)?;
         
-        (staged_changes, unstaged_changes)
+        (staged_changes, renames, unstaged_changes)
     };
 
-    if !staged_changes.is_empty() {
-        println!("Changes staged for commit:");
-        for change in staged_changes {
-            match change {
-                StagedChange::Created { path } =>  println!("created:   {path}"),
-                StagedChange::Modified { path } => println!("modified:  {path}"),
-                StagedChange::Deleted { path } =>  println!("deleted:   {path}"),
+    if !staged_changes.is_empty() || !renames.is_empty() {
+        println!("{}", Message::ChangesStaged);
+        for change in &staged_changes {
+            println!("{}", staged_change_message(change));
+        }
+        for rename in renames {
+            let (old_path, path) = match rename.is_dir {
+                true => (format!("{}/", rename.old_path), format!("{}/", rename.new_path)),
+                false => (rename.old_path.to_string(), rename.new_path.to_string()),
             };
+            println!("{}", Message::Rename { old_path: &old_path, path: &path });
         }
     }
     else {
-        println!("No changes staged for commit");
+        println!("{}", Message::NoChangesStaged);
     }
 
     if !unstaged_changes.is_empty() {
-        println!("Changes not staged for commit:");
-        for change in unstaged_changes {
-            match change {
-                UnstagedChange::Created { path, .. } => println!("created:   {path}"),
-                UnstagedChange::Modified { path, ..} => println!("modified:  {path}"),
-                UnstagedChange::Deleted { path }     => println!("deleted:   {path}"),
+        println!("{}", Message::ChangesNotStaged);
+        for change in &unstaged_changes {
+            let message = match change {
+                UnstagedChange::Created { path, .. } => Message::Change { kind: ChangeKind::Created, path },
+                UnstagedChange::Modified { path, ..} => Message::Change { kind: ChangeKind::Modified, path },
+                UnstagedChange::Deleted { path }     => Message::Change { kind: ChangeKind::Deleted, path },
+                UnstagedChange::NestedRepo { path } => Message::NestedRepo(path),
+                UnstagedChange::Renamed { old_path, path, .. } => {
+                    // Formatted first so paths with escaped bytes are quoted
+                    println!("{}", Message::Rename { old_path: &old_path.to_string(), path: &path.to_string() });
+                    continue;
+                },
             };
+            println!("{message}");
+        }
+    }
+    else {
+        println!("{}", Message::NoUnstagedChanges);
+    }
+
+    Ok(())
+}
+
+/// Prints the changes between `rev` and the working tree for `status --compare`.
+fn print_comparison_status(rev: &str, changes: Vec<StagedChange>) {
+    if changes.is_empty() {
+        println!("{}", Message::NoChangesSince(rev));
+        return;
+    }
+
+    println!("{}", Message::ChangesSince(rev));
+    for change in &changes {
+        println!("{}", staged_change_message(change));
+    }
+}
+
+/// Describes a staged change as a line in a list of changes.
+fn staged_change_message(change: &StagedChange) -> Message<'_> {
+    match change {
+        StagedChange::Created { path } =>  Message::Change { kind: ChangeKind::Created, path },
+        StagedChange::Modified { path } => Message::Change { kind: ChangeKind::Modified, path },
+        StagedChange::Deleted { path } =>  Message::Change { kind: ChangeKind::Deleted, path },
+    }
+}
+
+/// Lists the changes under `path` in the format of `git status --porcelain` (version 1).
+/// Each changed path gets one record, with a code for its staged change and one for its
+/// unstaged change. Untracked files come last. Renames aren't detected, since scripts can
+/// pair deletions and creations themselves. With `nul_terminated`, paths are left unquoted
+/// for records that end in NUL.
+fn porcelain_status(wd: &WorkDir, index: &Index, commit_hash: Option<&ObjectHash>, path: &WorkPathBuf, nul_terminated: bool) -> Result<Vec<Vec<u8>>> {
+    let mut codes: BTreeMap<WorkPathBuf, [char; 2]> = BTreeMap::new();
+    // Nested repositories are listed as directories
+    let mut untracked: Vec<(WorkPathBuf, bool)> = vec![];
+
+    for change in index.list_staged_changes(wd, commit_hash, path)? {
+        let (path, code) = match change {
+            StagedChange::Created { path } => (path, 'A'),
+            StagedChange::Modified { path } => (path, 'M'),
+            StagedChange::Deleted { path } => (path, 'D'),
+        };
+        codes.entry(path).or_insert([' '; 2])[0] = code;
+    }
+    for change in index.list_unstaged_changes(wd, path, false)? {
+        match change {
+            UnstagedChange::Created { path, .. } => untracked.push((path, false)),
+            UnstagedChange::NestedRepo { path } => untracked.push((path, true)),
+            UnstagedChange::Modified { path, .. } => codes.entry(path).or_insert([' '; 2])[1] = 'M',
+            UnstagedChange::Deleted { path } => codes.entry(path).or_insert([' '; 2])[1] = 'D',
+            UnstagedChange::Renamed { old_path, path, .. } => {
+                codes.entry(old_path).or_insert([' '; 2])[1] = 'D';
+                untracked.push((path, false));
+            },
         }
     }
+    for path in index.conflicted_paths() {
+        codes.insert(path.clone(), ['U'; 2]);
+    }
+
+    untracked.sort();
+    let records = codes.into_iter()
+        .map(|(path, [staged, unstaged])| {
+            let mut record = format!("{staged}{unstaged} ").into_bytes();
+            record.extend(record_path(&path, nul_terminated));
+            record
+        })
+        .chain(untracked.into_iter().map(|(path, is_dir)| {
+            let mut record = b"?? ".to_vec();
+            record.extend(record_path(&path, nul_terminated));
+            if is_dir {
+                record.push(b'/');
+            }
+            record
+        }))
+        .collect();
+
+    Ok(records)
+}
+
+/// Formats a path for a record. Records that end in NUL (`-z`) get the path's raw bytes,
+/// since they can't be confused by special characters. Otherwise, the path is quoted if needed.
+fn record_path(path: &WorkPath, nul_terminated: bool) -> Vec<u8> {
+    match nul_terminated {
+        true => path.to_bytes().into_owned(),
+        false => path.to_string().into_bytes(),
+    }
+}
+
+/// Prints a record followed by NUL for `-z`, or by a newline otherwise.
+fn print_record(record: &[u8], nul_terminated: bool) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    std::io::Write::write_all(&mut stdout, record)?;
+    std::io::Write::write_all(&mut stdout, if nul_terminated { b"\0" } else { b"\n" })?;
+
+    Ok(())
+}
+
+/// Inspect stashed changes.
+#[derive(Args)]
+pub struct StashArgs {
+    #[command(subcommand)]
+    pub command: StashCommand,
+}
+
+#[derive(Subcommand)]
+pub enum StashCommand {
+    /// List the stash entries, most recent first.
+    List,
+    /// Show the changes in a stash entry compared to the commit it was stashed on.
+    Show(StashShowArgs),
+    /// Remove a stash entry.
+    Drop(StashDropArgs),
+}
+
+#[derive(Args)]
+pub struct StashShowArgs {
+    /// Show the changes as a patch instead of a diffstat.
+    #[arg(short, long)]
+    pub patch: bool,
+    /// The entry to show, e.g. `1` or `stash@{1}`. Defaults to the most recent.
+    pub stash: Option<String>,
+}
+
+#[derive(Args)]
+pub struct StashDropArgs {
+    /// The entry to remove, e.g. `1` or `stash@{1}`. Defaults to the most recent.
+    pub stash: Option<String>,
+}
+
+pub fn cmd_stash(args: StashArgs, verbosity: Verbosity) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+
+    match args.command {
+        StashCommand::List => {
+            for (n, entry) in stash::list(wd)?.iter().enumerate() {
+                println!("stash@{{{n}}}: {}", entry.message);
+            }
+        },
+        StashCommand::Drop(args) => {
+            let n = args.stash.as_deref().map_or(Ok(0), stash::parse_entry)?;
+            let _lock = repo.lock()?;
+            let hash = stash::drop_entry(wd, n)?;
+            info!(verbosity, "{}", Message::StashDropped { n, hash: &hash });
+        },
+        StashCommand::Show(args) => {
+            let n = args.stash.as_deref().map_or(Ok(0), stash::parse_entry)?;
+            let commit = Commit::read(wd, &stash::get(wd, n)?)?;
+            let base_tree = match commit.parents().first() {
+                Some(base) => Tree::read_tree_ish(wd, base)?,
+                None => Tree::empty(),
+            };
+            let stash_tree = Tree::read(wd, commit.tree())?;
+
+            let mut patches = Vec::new();
+            for change in Tree::diff(wd, &base_tree, &stash_tree)? {
+                let old = base_tree.find_entry(wd, change.path())?
+                    .map(|entry| PatchSide::from_blob(wd, entry.mode, entry.hash))
+                    .transpose()?;
+                let (path, new) = match change {
+                    TreeChange::Added { path, entry } | TreeChange::Modified { path, entry } => {
+                        let new = PatchSide::from_blob(wd, entry.mode, entry.hash)?;
+                        (path, Some(new))
+                    },
+                    TreeChange::Deleted { path } => (path, None),
+                };
+                patches.push(FilePatch { path, old, new });
+            }
+
+            let context = repo.get_config_int("diff", "context")?.map_or(3, |context| context as usize);
+            let options = PatchOptions {
+                context,
+                diff: DiffOptions {
+                    algorithm: DiffAlgorithm::from_config(&repo)?,
+                    ..DiffOptions::default()
+                },
+            };
+
+            let mut stdout = std::io::stdout().lock();
+            let mut stats = Vec::new();
+            for mut patch in patches {
+                let attrs = PathAttributes::resolve(wd, &patch.path)?;
+                let driver = DiffDriver::for_attributes(&repo, &attrs)?;
+                patch.textconv(&driver)?;
+                match args.patch {
+                    true => patch.write(&mut stdout, &options, &driver)?,
+                    false => stats.push(patch.stat(&options.diff, &driver)),
+                }
+            }
+            if !args.patch {
+                diff::write_stat(&mut stdout, &stats, diff::STAT_WIDTH)?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Print counts of objects, packs, refs, index entries, and working directory files.
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Print the counts as a JSON object.
+    #[arg(long)]
+    pub json: bool,
+}
+
+pub fn cmd_stats(args: StatsArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let stats = repo.stats()?;
+
+    if args.json {
+        println!("{}", stats.to_json());
+    }
     else {
-        println!("No unstaged changes");
+        print!("{stats}");
     }
 
     Ok(())
 }
 
+/// Print statistics about the repository.
+#[derive(Args)]
+pub struct SummaryArgs { }
+
+pub fn cmd_summary(_args: SummaryArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let summary = RepoSummary::collect(repo.workdir())?;
+
+    print!("{summary}");
+
+    Ok(())
+}
+
 /// Updates HEAD, index, and working directory to match the branch or commit.
 #[derive(Args)]
 pub struct SwitchArgs {
     /// Switch to a detached HEAD state.
     #[arg(long)]
     pub detach: bool,
+    /// Switch to a new branch with no commits. The index and working directory are left as-is.
+    #[arg(long, conflicts_with = "detach")]
+    pub orphan: bool,
+    /// Update HEAD and the index, but leave the working directory as-is. Uncommitted changes
+    /// aren't checked, so the working directory then shows up as changes to the new commit.
+    #[arg(long, conflicts_with = "orphan")]
+    pub no_checkout: bool,
     /// The branch or commit (if --detach) to switch to.
     pub branch_or_commit: String,
 }
 
 pub fn cmd_switch(args: SwitchArgs) -> Result<()> {
     let repo = Repository::find(".")?;
+    let _lock = repo.lock()?;
     let wd = repo.workdir();
-    let path = WorkPathBuf::root();
-    
-    // Ensure clean working directory
-    {
-        let index = repo.index()?;
-        let commit_hash = branch::get_current(wd)?.tip(wd)?;
-        
-        let staged_changes = index.list_staged_changes(wd, commit_hash.as_ref(), &path)?;
-        if !staged_changes.is_empty() {
-            bail!("Cannot switch branches: index has staged changes.");
-        }
 
-        let unstaged_changes = index.list_unstaged_changes(wd, &path, false)?;
-        if !unstaged_changes.is_empty() {
-            bail!("Cannot switch branches: working directory has unstaged changes.");
-        }
+    if args.orphan {
+        return branch::switch_orphan(wd, &args.branch_or_commit);
     }
 
-    // Update HEAD
-    if args.detach {
-        let commit_hash = GitObject::find(wd, &args.branch_or_commit)?;
-        let branch = branch::Branch::Headless(commit_hash);
-        branch::switch(wd, &branch)?;
+    let branch = if args.detach {
+        let commit_hash = GitObject::find_as(wd, &args.branch_or_commit, ObjectFormat::Commit)?;
+        branch::Branch::Headless(commit_hash)
     }
     else {
-        let branch = branch::Branch::Named(args.branch_or_commit);
-        branch::switch(wd, &branch)?;
-    }
+        branch::Branch::Named(args.branch_or_commit)
+    };
 
-    // Update working directory
-    if let Some(hash) = branch::get_current(wd)?.tip(wd)? {
-        Tree::restore_from_commit(wd, &hash, &WorkPathBuf::root())?;
+    if args.no_checkout {
+        return branch::switch_without_checkout(wd, &mut index_or_empty(&repo)?, &branch);
     }
-    else {
-        bail!("Cannot switch branches: branch has no tip");
+
+    // An unborn branch has nothing to check out, so just update HEAD
+    let Some(new_tip) = branch.tip(wd)? else {
+        return branch::switch(wd, &branch);
+    };
+
+    let mut index = repo.index()?;
+    let current_tip = branch::get_current(wd)?.tip(wd)?;
+
+    // HEAD is updated last, so it's left alone if the checkout is refused
+    check_out_commit(wd, &mut index, current_tip.as_ref(), &new_tip, "switch branches")?;
+    branch::switch(wd, &branch)?;
+
+    Ok(())
+}
+
+/// Updates the working tree and index from commit `from` (or nothing, on an unborn branch)
+/// to commit `to`, touching only the paths that differ. Refuses if there are uncommitted
+/// changes or untracked files would be overwritten. `action` names what's being done in
+/// the error, e.g. `switch branches`.
+fn check_out_commit(wd: &WorkDir, index: &mut Index, from: Option<&ObjectHash>, to: &ObjectHash, action: &str) -> Result<()> {
+    let path = WorkPathBuf::root();
+
+    // Untracked files are fine unless they're in the way, which is checked below
+    ensure_clean(wd, index, from, action)?;
+
+    let new_tree = Tree::read_tree_ish(wd, to)?;
+    let old_tree = match from {
+        Some(hash) => Tree::read_tree_ish(wd, hash)?,
+        None => Tree::empty(),
+    };
+
+    // Make sure no untracked files will be lost
+    index.check_untracked_collisions(wd, &new_tree, &[path])?;
+
+    let changes = Tree::diff(wd, &old_tree, &new_tree)?;
+    index.checkout_changes(wd, &changes)?;
+    index.write(wd)
+}
+
+/// Fails if the index or working tree has changes to tracked files compared to the commit
+/// `head`. `action` names what's being done in the error, e.g. `switch branches`.
+fn ensure_clean(wd: &WorkDir, index: &Index, head: Option<&ObjectHash>, action: &str) -> Result<()> {
+    let path = WorkPathBuf::root();
+
+    let staged_changes = index.list_staged_changes(wd, head, &path)?;
+    if !staged_changes.is_empty() {
+        bail!("Cannot {action}: index has staged changes.");
     }
 
-    // Update index
-    {
-        let mut index = Index::new(None);
-        index.add(wd, &path)?;
-        index.write(wd)?;
+    let unstaged_changes = index.list_unstaged_changes(wd, &path, false)?;
+    let has_tracked_changes = unstaged_changes.iter()
+        .any(|change| !matches!(change, UnstagedChange::Created { .. } | UnstagedChange::NestedRepo { .. }));
+    if has_tracked_changes {
+        bail!("Cannot {action}: working directory has unstaged changes.");
     }
 
     Ok(())
@@ -560,17 +2850,23 @@ pub struct TagArgs {
     pub name: Option<String>,
 
     /// The object the new tag will point to.
-    #[arg(default_value = "HEAD")]
+    #[arg(default_value = HEAD)]
     pub object: String,
 
     /// A message to attach to the tag.
     #[arg(short, default_value = "")]
     pub message: String,
+
+    /// Create a signed annotated tag using the SSH key in user.signingKey. Defaults to
+    /// tag.gpgSign for annotated tags.
+    #[arg(short, long)]
+    pub sign: bool,
 }
 
 pub fn cmd_tag(args: TagArgs) -> Result<()> {
     if let Some(name) = args.name {
         let repo = Repository::find(".")?;
+        let _lock = repo.lock()?;
 
         if args.delete {
             Tag::delete(repo.workdir(), &name)?;
@@ -578,9 +2874,14 @@ pub fn cmd_tag(args: TagArgs) -> Result<()> {
         else{
             // Create a tag
             let hash = GitObject::find(repo.workdir(), &args.object)?;
-            let meta = ObjectMetadata::new(&repo, args.message)?;
+            let mut meta = ObjectMetadata::new(&repo, args.message)?;
+            let sign = args.sign
+                || (args.annotate && repo.get_config_bool("tag", "gpgSign") == Some(true));
+            if sign {
+                meta.signer = Some(SshSigner::from_config(&repo)?);
+            }
 
-            if args.annotate {
+            if args.annotate || sign {
                 Tag::create(repo.workdir(), &name, &hash, meta)?;
             }
             else {
@@ -591,15 +2892,188 @@ pub fn cmd_tag(args: TagArgs) -> Result<()> {
     else {
         // List existing tags
         let repo = Repository::find(".")?;
-        let refs = refs::list(repo.workdir())?;
-        let tag_names = refs.iter()
-            .filter(|(name, _)| name.starts_with("refs/tags/"))
-            .map(|(name, _)| &name["refs/tags/".len()..]);
-
-        for tag_name in tag_names {
-            println!("{tag_name}");
+        for tag_ref in refs::iter(repo.workdir(), TAG_PREFIX)? {
+            println!("{}", tag_ref?.short_name());
         }
     }
 
     Ok(())
 }
+
+/// Writes info/refs and objects/info/packs so the repository can be fetched over the
+/// dumb HTTP protocol.
+#[derive(Args)]
+pub struct UpdateServerInfoArgs { }
+
+pub fn cmd_update_server_info(_args: UpdateServerInfoArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    server_info::update(repo.workdir())
+}
+
+/// Check the SSH signature of a commit.
+#[derive(Args)]
+pub struct VerifyCommitArgs {
+    /// The commit to verify.
+    pub commit: String,
+}
+
+pub fn cmd_verify_commit(args: VerifyCommitArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let hash = GitObject::find_as(repo.workdir(), &args.commit, ObjectFormat::Commit)?;
+    let commit = Commit::read(repo.workdir(), &hash)?;
+
+    let (payload, signature) = commit.signed_payload()?;
+    let principal = crate::object::verify_signature(&repo, &payload, &signature)?;
+    println!("{}", Message::GoodSignature(&principal));
+
+    Ok(())
+}
+
+/// Check the SSH signature of an annotated tag.
+#[derive(Args)]
+pub struct VerifyTagArgs {
+    /// The tag to verify.
+    pub tag: String,
+}
+
+pub fn cmd_verify_tag(args: VerifyTagArgs) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let hash = GitObject::find(repo.workdir(), &args.tag)?;
+    let tag = match GitObject::read(repo.workdir(), &hash)? {
+        GitObject::Tag(tag) => tag,
+        object => return Err(ObjectError::UnexpectedFormat {
+            format: object.get_format(),
+            expected: ObjectFormat::Tag,
+        }.into()),
+    };
+
+    let (payload, signature) = tag.signed_payload()?;
+    let principal = crate::object::verify_signature(&repo, &payload, &signature)?;
+    println!("{}", Message::GoodSignature(&principal));
+
+    Ok(())
+}
+
+/// Manage the linked worktrees made by `git worktree add`.
+#[derive(Args)]
+pub struct WorktreeArgs {
+    #[command(subcommand)]
+    pub command: WorktreeCommand,
+}
+
+#[derive(Subcommand)]
+pub enum WorktreeCommand {
+    /// Remove the records of worktrees that no longer exist.
+    Prune(WorktreePruneArgs),
+    /// Keep a worktree from being pruned or moved, e.g. if it's on a removable drive.
+    Lock(WorktreeLockArgs),
+    /// Let a locked worktree be pruned or moved again.
+    Unlock(WorktreeUnlockArgs),
+    /// Move a worktree to a new location.
+    Move(WorktreeMoveArgs),
+}
+
+#[derive(Args)]
+pub struct WorktreePruneArgs {
+    /// Only show what would be removed.
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+    /// Show what's removed.
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+#[derive(Args)]
+pub struct WorktreeLockArgs {
+    /// Why the worktree is locked.
+    #[arg(long)]
+    pub reason: Option<String>,
+    /// The path of the worktree, or the end of it if that's unique (e.g. its directory name).
+    pub worktree: PathBuf,
+}
+
+#[derive(Args)]
+pub struct WorktreeUnlockArgs {
+    /// The path of the worktree, or the end of it if that's unique (e.g. its directory name).
+    pub worktree: PathBuf,
+}
+
+#[derive(Args)]
+pub struct WorktreeMoveArgs {
+    /// The path of the worktree, or the end of it if that's unique (e.g. its directory name).
+    pub worktree: PathBuf,
+    /// Where to move the worktree. If this is an existing directory, the worktree is moved
+    /// into it.
+    pub new_path: PathBuf,
+}
+
+pub fn cmd_worktree(args: WorktreeArgs, verbosity: Verbosity) -> Result<()> {
+    let repo = Repository::find(".")?;
+    let wd = repo.workdir();
+
+    // Nothing is changed by a dry run, so it doesn't need the lock
+    let changes = !matches!(args.command, WorktreeCommand::Prune(WorktreePruneArgs { dry_run: true, .. }));
+    let _lock = changes.then(|| repo.lock()).transpose()?;
+    match args.command {
+        WorktreeCommand::Prune(args) => {
+            for (worktree, reason) in worktree::prune(wd, args.dry_run)? {
+                if args.dry_run || args.verbose || verbosity == Verbosity::Verbose {
+                    let admin_dir = format!("{}/{}", worktree::WORKTREES_DIR, worktree.id);
+                    eprintln!("{}", Message::PruningWorktree { admin_dir: &admin_dir, reason });
+                }
+            }
+        },
+        WorktreeCommand::Lock(args) => {
+            let mut worktree = LinkedWorktree::find(wd, &args.worktree)?;
+            worktree.lock(wd, args.reason.as_deref().unwrap_or_default())?;
+        },
+        WorktreeCommand::Unlock(args) => {
+            LinkedWorktree::find(wd, &args.worktree)?.unlock(wd)?;
+        },
+        WorktreeCommand::Move(args) => {
+            LinkedWorktree::find(wd, &args.worktree)?.move_to(wd, &args.new_path)?;
+        },
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::{prelude::*, TempDir};
+
+    use super::*;
+
+    #[test]
+    fn porcelain_status_is_empty_for_clean_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        temp_dir.child(".git/objects").create_dir_all().unwrap();
+        temp_dir.child(".git/refs/heads").create_dir_all().unwrap();
+        temp_dir.child(".git/HEAD").write_str("ref: refs/heads/main\n").unwrap();
+        temp_dir.child(".git/config").write_str("[core]\nrepositoryformatversion = 0\n").unwrap();
+        let mut repo = Repository::find(temp_dir.path()).unwrap();
+        repo.set_config("user", "name", "Jo".to_owned());
+        repo.set_config("user", "email", "jo@example.com".to_owned());
+        let wd = repo.workdir();
+        temp_dir.child("a.txt").write_str("a\n").unwrap();
+        let mut index = Index::new(None);
+        index.add(wd, temp_dir.child("a.txt"), false).unwrap();
+        index.write(wd).unwrap();
+        let head = Commit::create(&index, wd, ObjectMetadata::new(&repo, "first\n".to_owned()).unwrap()).unwrap();
+        let root = wd.canonicalize_path(temp_dir.path()).unwrap();
+
+        assert_eq!(porcelain_status(wd, &index, Some(&head), &root, false).unwrap(), Vec::<Vec<u8>>::new());
+
+        temp_dir.child("a.txt").write_str("changed\n").unwrap();
+        temp_dir.child("b.txt").write_str("b\n").unwrap();
+        assert_eq!(porcelain_status(wd, &index, Some(&head), &root, false).unwrap(), [b" M a.txt".to_vec(), b"?? b.txt".to_vec()]);
+    }
+
+    #[test]
+    fn nul_terminated_records_keep_raw_paths() {
+        let path = WorkPathBuf::try_from(b"caf\xe9\nb.txt".as_slice()).unwrap();
+
+        assert_eq!(record_path(&path, true), b"caf\xe9\nb.txt");
+        assert_eq!(record_path(&path, false), path.to_string().into_bytes());
+    }
+}
@@ -0,0 +1,33 @@
+//! Names of the refs and ref directories git gives special meaning to.
+
+/// The current branch, or the current commit if HEAD is detached.
+pub const HEAD: &str = "HEAD";
+/// Where HEAD was before a command that moved it a long way, e.g. a merge.
+pub const ORIG_HEAD: &str = "ORIG_HEAD";
+/// The commit being merged into HEAD while a merge is in progress.
+pub const MERGE_HEAD: &str = "MERGE_HEAD";
+/// The commit being cherry-picked while a cherry-pick is in progress.
+pub const CHERRY_PICK_HEAD: &str = "CHERRY_PICK_HEAD";
+/// The commit being reverted while a revert is in progress.
+pub const REVERT_HEAD: &str = "REVERT_HEAD";
+/// The message for the commit that will conclude the operation in progress.
+pub const MERGE_MSG: &str = "MERGE_MSG";
+/// The message of the commit being made, while it's edited or prepared by a hook.
+pub const COMMIT_EDITMSG: &str = "COMMIT_EDITMSG";
+
+/// The category of local branches, as passed to [`refs::create`](crate::refs::create).
+pub const BRANCH_CATEGORY: &str = "heads";
+/// The category of tags.
+pub const TAG_CATEGORY: &str = "tags";
+/// The category of remote-tracking branches.
+pub const REMOTE_CATEGORY: &str = "remotes";
+
+/// The start of every local branch's full name, e.g. `refs/heads/main`.
+pub const BRANCH_PREFIX: &str = "refs/heads/";
+/// The start of every tag's full name.
+pub const TAG_PREFIX: &str = "refs/tags/";
+/// The start of every remote-tracking branch's full name.
+pub const REMOTE_PREFIX: &str = "refs/remotes/";
+
+/// What the contents of a symbolic ref start with, followed by the name of its target.
+pub const SYMREF_PREFIX: &str = "ref: ";
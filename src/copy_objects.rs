@@ -0,0 +1,260 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    consts::{BRANCH_PREFIX, REMOTE_CATEGORY, TAG_CATEGORY, TAG_PREFIX},
+    object::{ObjectStore, forget_listing},
+    refs::{self, RefTarget},
+    workdir::WorkDir,
+};
+
+#[derive(Error, Debug)]
+pub enum CopyError {
+    #[error("Can't copy the objects of `{0}` into itself")]
+    SameRepository(PathBuf),
+}
+
+/// What [`copy_objects`] copied.
+#[derive(Default, Debug)]
+pub struct CopyStats {
+    /// The number of loose objects that weren't already present.
+    pub objects: usize,
+    /// The number of packfiles that weren't already present.
+    pub packs: usize,
+    /// How many of those objects and packs were hard-linked rather than copied.
+    pub linked: usize,
+    /// The number of refs that were created or updated.
+    pub refs: usize,
+    /// The tags that already exist here and point somewhere else, so they were left alone.
+    pub skipped_tags: Vec<String>,
+}
+
+/// Copies the loose objects, packs, and refs of `source`, a repository on the same machine,
+/// into the repository at `wd`. Like git's local clone, files are hard-linked when both
+/// repositories are on the same filesystem, which is safe because objects never change.
+///
+/// Branches are copied to `refs/remotes/<remote>/`, and tags are copied unless a tag with
+/// the same name already points somewhere else.
+///
+/// The source's alternates are added to this repository's, since the copied objects may
+/// depend on them. With `dissociate`, the objects in every alternate (this repository's
+/// own included) are copied as well and the alternates file is deleted, so the repository
+/// no longer relies on any other.
+pub fn copy_objects(wd: &WorkDir, source: &WorkDir, remote: &str, dissociate: bool) -> Result<CopyStats> {
+    wd.check_writable()?;
+    let objects_dir = wd.git_path("objects");
+    if same_dir(&objects_dir, &source.git_path("objects")) {
+        return Err(CopyError::SameRepository(source.as_path().to_owned()).into());
+    }
+
+    let own_alternates = ObjectStore::open(wd)?.dirs()[1..].to_vec();
+    let source_dirs = ObjectStore::open(source)?.dirs().to_vec();
+    let (source_dir, source_alternates) = source_dirs.split_first()
+        .expect("object store should include the repository's own directory");
+
+    let mut stats = CopyStats::default();
+    copy_dir(source_dir, &objects_dir, &mut stats)?;
+
+    let alternates_path = objects_dir.join("info/alternates");
+    if dissociate {
+        for dir in source_alternates.iter().chain(&own_alternates) {
+            if !same_dir(dir, &objects_dir) {
+                copy_dir(dir, &objects_dir, &mut stats)?;
+            }
+        }
+        if alternates_path.is_file() {
+            fs::remove_file(&alternates_path)?;
+        }
+    }
+    else {
+        let new_alternates: Vec<_> = source_alternates.iter()
+            .filter(|dir| !same_dir(dir, &objects_dir) && !own_alternates.iter().any(|own| same_dir(own, dir)))
+            .collect();
+        if !new_alternates.is_empty() {
+            let mut contents = fs::read_to_string(&alternates_path).unwrap_or_default();
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                contents.push('\n');
+            }
+            for dir in new_alternates {
+                contents.push_str(&format!("{}\n", dir.display()));
+            }
+            wd.write_git_file("objects/info/alternates", contents.as_bytes())?;
+        }
+    }
+
+    copy_refs(wd, source, remote, &mut stats)?;
+
+    Ok(stats)
+}
+
+/// Copies the loose objects and packs in the object directory `from` that aren't in `to`.
+fn copy_dir(from: &Path, to: &Path, stats: &mut CopyStats) -> Result<()> {
+    for dir in fs::read_dir(from)? {
+        let dir = dir?;
+        let dir_name = dir.file_name().to_string_lossy().into_owned();
+        if dir_name.len() != 2 || !dir_name.bytes().all(|ch| ch.is_ascii_hexdigit()) || !dir.file_type()?.is_dir() {
+            continue;
+        }
+
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            let file_name = file.file_name();
+            let is_object = file_name.len() == 38
+                && file_name.to_string_lossy().bytes().all(|ch| ch.is_ascii_hexdigit());
+            let dest = to.join(&dir_name).join(&file_name);
+            if !is_object || dest.exists() {
+                continue;
+            }
+
+            fs::create_dir_all(to.join(&dir_name))?;
+            link_or_copy(&file.path(), &dest, stats)?;
+            forget_listing(&dest);
+            stats.objects += 1;
+        }
+    }
+
+    let pack_dir = from.join("pack");
+    if !pack_dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(&pack_dir)? {
+        let index_path = entry?.path();
+        let pack_path = index_path.with_extension("pack");
+        if index_path.extension().is_none_or(|ext| ext != "idx") || !pack_path.is_file() {
+            continue;
+        }
+
+        let dest = to.join("pack").join(index_path.file_name().expect("pack index should have a file name"));
+        if dest.exists() {
+            continue;
+        }
+
+        // The index goes last, since a pack without one is ignored
+        fs::create_dir_all(to.join("pack"))?;
+        link_or_copy(&pack_path, &dest.with_extension("pack"), stats)?;
+        link_or_copy(&index_path, &dest, stats)?;
+        stats.packs += 1;
+    }
+
+    Ok(())
+}
+
+/// Hard-links `to` to `from`, or copies it if they're on different filesystems. A copy is
+/// made under a temporary name first, so nobody sees a partial object.
+fn link_or_copy(from: &Path, to: &Path, stats: &mut CopyStats) -> Result<()> {
+    if fs::hard_link(from, to).is_ok() {
+        stats.linked += 1;
+        return Ok(());
+    }
+
+    let mut temp_path = to.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::copy(from, &temp_path)?;
+    fs::rename(&temp_path, to)?;
+
+    Ok(())
+}
+
+/// Copies the branches of `source` as remote-tracking branches and its tags as tags.
+fn copy_refs(wd: &WorkDir, source: &WorkDir, remote: &str, stats: &mut CopyStats) -> Result<()> {
+    for source_ref in refs::iter(source, "refs/")? {
+        let source_ref = source_ref?;
+        let RefTarget::Direct(hash) = source_ref.target else {
+            continue;
+        };
+
+        if let Some(branch) = source_ref.name.strip_prefix(BRANCH_PREFIX) {
+            let name = format!("{remote}/{branch}");
+            if refs::resolve(wd, REMOTE_CATEGORY, &name).ok() != Some(hash) {
+                refs::create(wd, REMOTE_CATEGORY, &name, &hash)?;
+                stats.refs += 1;
+            }
+        }
+        else if let Some(tag) = source_ref.name.strip_prefix(TAG_PREFIX) {
+            match refs::resolve(wd, TAG_CATEGORY, tag) {
+                Ok(existing) if existing == hash => {},
+                Ok(_) => stats.skipped_tags.push(tag.to_owned()),
+                Err(_) => {
+                    refs::create(wd, TAG_CATEGORY, tag, &hash)?;
+                    stats.refs += 1;
+                },
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns true if both paths name the same existing directory.
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::object::{GitObject, ObjectFormat};
+
+    fn init(temp_dir: &assert_fs::TempDir, name: &str) -> WorkDir {
+        crate::repo::Repository::init(temp_dir.join(name)).unwrap();
+        WorkDir::new(temp_dir.join(name)).unwrap()
+    }
+
+    #[test]
+    fn copies_objects_and_refs() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let source = init(&temp_dir, "source");
+        let wd = init(&temp_dir, "dest");
+        let hash = GitObject::write_raw(&source, ObjectFormat::Blob, b"shared").unwrap();
+        refs::create(&source, "heads", "main", &hash).unwrap();
+        refs::create(&source, "tags", "v1", &hash).unwrap();
+        let other = GitObject::write_raw(&wd, ObjectFormat::Blob, b"mine").unwrap();
+        refs::create(&wd, "tags", "v1", &other).unwrap();
+
+        let stats = copy_objects(&wd, &source, "origin", false).unwrap();
+
+        assert_eq!(stats.objects, 1);
+        assert_eq!(stats.refs, 1);
+        assert_eq!(stats.skipped_tags, ["v1"]);
+        assert!(ObjectStore::open(&wd).unwrap().contains(&hash));
+        assert_eq!(refs::resolve(&wd, "remotes", "origin/main").unwrap(), hash);
+        assert_eq!(refs::resolve(&wd, "tags", "v1").unwrap(), other);
+
+        // Copying again finds nothing new
+        let stats = copy_objects(&wd, &source, "origin", false).unwrap();
+        assert_eq!((stats.objects, stats.refs), (0, 0));
+    }
+
+    #[test]
+    fn dissociate_copies_alternates() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let shared = init(&temp_dir, "shared");
+        let source = init(&temp_dir, "source");
+        let wd = init(&temp_dir, "dest");
+        let hash = GitObject::write_raw(&shared, ObjectFormat::Blob, b"borrowed").unwrap();
+        let alternate = format!("{}\n", shared.git_path("objects").display());
+        source.write_git_file("objects/info/alternates", alternate.as_bytes()).unwrap();
+
+        copy_objects(&wd, &source, "origin", false).unwrap();
+        assert_eq!(fs::read_to_string(wd.git_path("objects/info/alternates")).unwrap(), alternate);
+
+        copy_objects(&wd, &source, "origin", true).unwrap();
+        assert!(!wd.git_path("objects/info/alternates").exists());
+        assert!(wd.git_path("objects").join(hash.to_path()).is_file());
+    }
+
+    #[test]
+    fn rejects_same_repository() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let wd = init(&temp_dir, "repo");
+
+        let err = copy_objects(&wd, &wd, "origin", false).unwrap_err();
+        assert!(matches!(err.downcast::<CopyError>().unwrap(), CopyError::SameRepository(_)));
+    }
+}
@@ -0,0 +1,376 @@
+//! Parsing and formatting dates the way git does. Dates are stored in objects as seconds
+//! since the Unix epoch with the timezone offset they were recorded in, e.g.
+//! `1673643222 -0800`.
+
+use std::{
+    str::FromStr,
+    time::SystemTime,
+};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// How a date is shown (`--date`).
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum DateStyle {
+    /// `Fri Jan 13 12:53:42 2023 -0800`
+    #[default]
+    Default,
+    /// `3 days ago`
+    Relative,
+    /// `2023-01-13 12:53:42 -0800`
+    Iso,
+    /// `2023-01-13`
+    Short,
+    /// `1673643222 -0800`
+    Raw,
+    /// A strftime-like format, e.g. `format:%Y/%m/%d`. Supports `%a`, `%b`, `%d`, `%e`,
+    /// `%H`, `%M`, `%S`, `%y`, `%Y`, `%m`, `%z`, `%s`, and `%%`.
+    Format(String),
+}
+
+impl FromStr for DateStyle {
+    type Err = String;
+
+    fn from_str(style: &str) -> Result<Self, Self::Err> {
+        match style {
+            "default" => Ok(Self::Default),
+            "relative" => Ok(Self::Relative),
+            "iso" | "iso8601" => Ok(Self::Iso),
+            "short" => Ok(Self::Short),
+            "raw" => Ok(Self::Raw),
+            style => match style.strip_prefix("format:") {
+                Some(format) => Ok(Self::Format(format.to_owned())),
+                None => Err(format!("unknown date format `{style}`")),
+            },
+        }
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Parses an exact date into a Unix timestamp and a timezone offset like `+0100`. Accepts
+/// git's internal format (`1700000000 +0100`, optionally prefixed with `@`) or ISO 8601
+/// (`2024-01-31T12:00:00+01:00` or `2024-01-31 12:00:00 +0100`). The timezone defaults
+/// to UTC. A timestamp so large that its local time can't be represented is rejected.
+pub fn parse(date: &str) -> Option<(i64, String)> {
+    let date = date.trim();
+
+    // Internal format
+    let raw = date.strip_prefix('@').unwrap_or(date);
+    let (seconds, zone) = raw.split_once(' ').unwrap_or((raw, "+0000"));
+    if let Ok(timestamp) = seconds.parse::<i64>() {
+        let (offset, zone) = parse_zone(zone)?;
+        timestamp.checked_add(offset)?;
+        return Some((timestamp, zone));
+    }
+
+    // ISO 8601: the date and time, then an optional zone with or without a space
+    let (day, rest) = date.split_once(['T', ' '])?;
+    let time_len = rest.find(['Z', '+', '-', ' ']).unwrap_or(rest.len());
+    let (time, zone) = rest.split_at(time_len);
+    let (offset, zone) = match zone.trim() {
+        "" | "Z" => (0, "+0000".to_owned()),
+        zone => parse_zone(zone)?,
+    };
+
+    let [hour, minute, second] = parse_fields(time, ':')?;
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let timestamp = parse_day(day)?.checked_add(hour * 3600 + minute * 60 + second - offset)?;
+    Some((timestamp, zone))
+}
+
+/// Parses a date the way git's approxidate does for options like `--since`, returning a
+/// Unix timestamp. Besides the exact dates [`parse`] accepts, this understands a day
+/// (`2024-01-31`, at midnight UTC), `now`, `today`, `yesterday`, and relative dates like
+/// `2 weeks ago` or `3.days.ago`, counted back from `now`.
+pub fn approxidate(date: &str, now: i64) -> Option<i64> {
+    if let Some((timestamp, _)) = parse(date) {
+        return Some(timestamp);
+    }
+
+    let date = date.trim().to_ascii_lowercase();
+    match date.as_str() {
+        "now" | "today" => return Some(now),
+        "yesterday" => return now.checked_sub(86400),
+        _ => (),
+    }
+    if let Some(timestamp) = parse_day(&date) {
+        return Some(timestamp);
+    }
+
+    let mut words = date.split([' ', '.']).filter(|word| !word.is_empty());
+    let count: i64 = words.next()?.parse().ok()?;
+    let unit = words.next()?;
+    if words.next()? != "ago" || words.next().is_some() {
+        return None;
+    }
+    let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        "month" => 30 * 86400,
+        "year" => 365 * 86400,
+        _ => return None,
+    };
+
+    now.checked_sub(count.checked_mul(seconds)?)
+}
+
+/// Parses an expiry time like `gc.reflogExpire` the way git does, returning the Unix
+/// timestamp before which things expire. Besides the dates [`approxidate`] accepts,
+/// `never` (or `false`) means nothing expires and `all` means everything does.
+pub fn parse_expiry(value: &str, now: i64) -> Option<i64> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "never" | "false" => Some(i64::MIN),
+        "all" => Some(i64::MAX),
+        _ => approxidate(value, now),
+    }
+}
+
+/// Formats a timestamp recorded in `zone` (e.g. `-0800`) in the given style. Dates are
+/// shown in the timezone they were recorded in. An invalid zone is treated as UTC.
+pub fn format(timestamp: i64, zone: &str, style: &DateStyle) -> String {
+    match style {
+        DateStyle::Default => format_with(timestamp, zone, "%a %b %e %H:%M:%S %Y %z"),
+        DateStyle::Relative => format_relative(timestamp, now()),
+        DateStyle::Iso => format_with(timestamp, zone, "%Y-%m-%d %H:%M:%S %z"),
+        DateStyle::Short => format_with(timestamp, zone, "%Y-%m-%d"),
+        DateStyle::Raw => format!("{timestamp} {zone}"),
+        DateStyle::Format(format) => format_with(timestamp, zone, format),
+    }
+}
+
+/// Describes how long before `now` the timestamp was, e.g. `3 days ago`, rounding the
+/// way git does.
+pub fn format_relative(timestamp: i64, now: i64) -> String {
+    let plural = |count: i64, unit: &str| match count {
+        1 => format!("1 {unit}"),
+        count => format!("{count} {unit}s"),
+    };
+
+    let seconds = now.saturating_sub(timestamp);
+    if seconds < 0 {
+        return "in the future".to_owned();
+    }
+    if seconds < 90 {
+        return format!("{} ago", plural(seconds, "second"));
+    }
+    let minutes = seconds.saturating_add(30) / 60;
+    if minutes < 90 {
+        return format!("{} ago", plural(minutes, "minute"));
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return format!("{} ago", plural(hours, "hour"));
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return format!("{} ago", plural(days, "day"));
+    }
+    if days < 70 {
+        return format!("{} ago", plural((days + 3) / 7, "week"));
+    }
+    if days < 365 {
+        return format!("{} ago", plural((days + 15) / 30, "month"));
+    }
+    if days < 5 * 365 {
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        return match months {
+            0 => format!("{} ago", plural(years, "year")),
+            months => format!("{}, {} ago", plural(years, "year"), plural(months, "month")),
+        };
+    }
+
+    format!("{} ago", plural((days + 183) / 365, "year"))
+}
+
+/// Formats a timestamp with a strftime-like format. Unknown specifiers are kept as-is.
+fn format_with(timestamp: i64, zone: &str, format: &str) -> String {
+    let local = timestamp.saturating_add(parse_zone(zone).map_or(0, |(offset, _)| offset));
+    let days = local.div_euclid(86400);
+    let seconds = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+
+    let mut formatted = String::new();
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            formatted.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('a') => formatted.push_str(WEEKDAYS[days.rem_euclid(7) as usize]),
+            Some('b') => formatted.push_str(MONTHS[month as usize - 1]),
+            Some('d') => formatted.push_str(&format!("{day:02}")),
+            Some('e') => formatted.push_str(&day.to_string()),
+            Some('H') => formatted.push_str(&format!("{:02}", seconds / 3600)),
+            Some('M') => formatted.push_str(&format!("{:02}", seconds / 60 % 60)),
+            Some('S') => formatted.push_str(&format!("{:02}", seconds % 60)),
+            Some('y') => formatted.push_str(&format!("{:02}", year.rem_euclid(100))),
+            Some('Y') => formatted.push_str(&year.to_string()),
+            Some('m') => formatted.push_str(&format!("{month:02}")),
+            Some('z') => formatted.push_str(zone),
+            Some('s') => formatted.push_str(&timestamp.to_string()),
+            Some('%') => formatted.push('%'),
+            Some(other) => {
+                formatted.push('%');
+                formatted.push(other);
+            },
+            None => formatted.push('%'),
+        }
+    }
+
+    formatted
+}
+
+/// Parses a timezone like `+0100` or `-07:00` into its offset in seconds and its
+/// normalized form.
+fn parse_zone(zone: &str) -> Option<(i64, String)> {
+    let sign = match zone.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = zone[1..].replace(':', "");
+    if digits.len() != 4 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some((sign * (hours * 3600 + minutes * 60), format!("{}{digits}", &zone[..1])))
+}
+
+/// Parses a day like `2024-01-31` into the timestamp of its midnight in UTC.
+fn parse_day(day: &str) -> Option<i64> {
+    let [year, month, day] = parse_fields(day, '-')?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    days_from_civil(year, month, day)?.checked_mul(86400)
+}
+
+/// Splits `text` into exactly three numbers, e.g. `2024-01-31` or `12:00:00`.
+fn parse_fields(text: &str, separator: char) -> Option<[i64; 3]> {
+    let mut fields = text.split(separator).map(|field| field.parse().ok());
+    let parsed = [fields.next()??, fields.next()??, fields.next()??];
+
+    fields.next().is_none().then_some(parsed)
+}
+
+/// Counts the days from 1970-01-01 to the given date in the proleptic Gregorian calendar.
+/// Returns `None` if the year is too far away for the count to fit in an `i64`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+    // Howard Hinnant's algorithm, which counts years from March so leap days come last
+    let year = if month <= 2 { year.checked_sub(1)? } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era.checked_mul(146097)?.checked_add(day_of_era - 719468)
+}
+
+/// Converts a count of days since 1970-01-01 into a year, month, and day. The inverse of
+/// [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(parse("1700000000 +0100"), Some((1700000000, "+0100".to_owned())));
+        assert_eq!(parse("@1700000000"), Some((1700000000, "+0000".to_owned())));
+        assert_eq!(parse("1970-01-02T00:00:00Z"), Some((86400, "+0000".to_owned())));
+        assert_eq!(parse("2024-02-29 12:30:00 +0100"), Some((1709206200, "+0100".to_owned())));
+        assert_eq!(parse("2024-02-29T12:30:00-07:00"), Some((1709235000, "-0700".to_owned())));
+        assert_eq!(parse("2024-13-01T00:00:00"), None);
+        assert_eq!(parse("yesterday"), None);
+        assert_eq!(parse("9223372036854775807 +1400"), None);
+        assert_eq!(parse("-9223372036854775808 -0100"), None);
+        assert_eq!(parse("9223372036854775807 -0100"), Some((i64::MAX, "-0100".to_owned())));
+    }
+
+    #[test]
+    fn parses_approximate_dates() {
+        let now = 1709206200;
+        assert_eq!(approxidate("1700000000 +0100", now), Some(1700000000));
+        assert_eq!(approxidate("2024-02-29", now), Some(1709164800));
+        assert_eq!(approxidate("now", now), Some(now));
+        assert_eq!(approxidate("Yesterday", now), Some(now - 86400));
+        assert_eq!(approxidate("2 weeks ago", now), Some(now - 14 * 86400));
+        assert_eq!(approxidate("1.hour.ago", now), Some(now - 3600));
+        assert_eq!(approxidate("2 fortnights ago", now), None);
+        assert_eq!(approxidate("soon", now), None);
+        assert_eq!(approxidate("999999999999999999 years ago", now), None);
+        assert_eq!(approxidate("99999999999999999-01-01", now), None);
+        assert_eq!(parse("99999999999999999-01-01T00:00:00"), None);
+    }
+
+    #[test]
+    fn parses_expiry_times() {
+        let now = 1_000_000;
+        assert_eq!(parse_expiry("now", now), Some(now));
+        assert_eq!(parse_expiry("90.days.ago", now), Some(now - 90 * 86400));
+        assert_eq!(parse_expiry("1 hour ago", now), Some(now - 3600));
+        assert_eq!(parse_expiry("never", now), Some(i64::MIN));
+        assert_eq!(parse_expiry("all", now), Some(i64::MAX));
+        assert_eq!(parse_expiry("999999999999999999 years ago", now), None);
+        assert_eq!(parse_expiry("soon", now), None);
+    }
+
+    #[test]
+    fn formats_dates_in_their_zone() {
+        assert_eq!(format(0, "+0000", &DateStyle::Default), "Thu Jan 1 00:00:00 1970 +0000");
+        assert_eq!(format(1673643222, "-0800", &DateStyle::Default), "Fri Jan 13 12:53:42 2023 -0800");
+        assert_eq!(format(1709206200, "+0100", &DateStyle::Iso), "2024-02-29 12:30:00 +0100");
+        assert_eq!(format(1709206200, "+0100", &DateStyle::Short), "2024-02-29");
+        assert_eq!(format(1709206200, "+0100", &DateStyle::Raw), "1709206200 +0100");
+        assert_eq!(format(1709206200, "+0100", &"format:%d/%m/%y %%z".parse().unwrap()), "29/02/24 %z");
+
+        // Timestamps at the limits are clamped instead of overflowing
+        assert_eq!(format(i64::MAX, "+1400", &DateStyle::Raw), "9223372036854775807 +1400");
+        assert!(format(i64::MAX, "+1400", &DateStyle::Iso).ends_with(" +1400"));
+        assert!(format(i64::MIN, "-1400", &DateStyle::Default).ends_with(" -1400"));
+    }
+
+    #[test]
+    fn formats_relative_dates() {
+        let now = 1709206200;
+        assert_eq!(format_relative(now - 1, now), "1 second ago");
+        assert_eq!(format_relative(now - 3 * 3600, now), "3 hours ago");
+        assert_eq!(format_relative(now - 3 * 86400, now), "3 days ago");
+        assert_eq!(format_relative(now - 30 * 86400, now), "4 weeks ago");
+        assert_eq!(format_relative(now - 400 * 86400, now), "1 year, 1 month ago");
+        assert_eq!(format_relative(now + 60, now), "in the future");
+        assert_eq!(format_relative(i64::MIN, now), format_relative(i64::MIN + 1, now));
+        assert_eq!(format_relative(i64::MAX, i64::MIN), "in the future");
+    }
+}
@@ -0,0 +1,661 @@
+use std::{borrow::Cow, collections::HashMap, hash::Hash};
+
+use thiserror::Error;
+
+use crate::{Result, repo::Repository};
+
+mod driver;
+pub use driver::{DiffDriver, DiffDriverError};
+
+mod unified;
+pub use unified::{FilePatch, PatchSide, PatchOptions, write_hunks};
+
+mod stat;
+pub use stat::{FileStat, StatCounts, STAT_WIDTH, write_stat};
+
+/// The longest list of occurrences of a line that the histogram algorithm will consider.
+/// Lines that are more common than this are only matched by the fallback to Myers.
+const MAX_CHAIN_LEN: usize = 64;
+
+/// A region where two sequences differ: `old_len` elements of the old sequence starting at
+/// `old_start` were replaced by `new_len` elements of the new sequence starting at `new_start`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Edit {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+impl Edit {
+    /// The index just past the end of the region in the old sequence.
+    pub fn old_end(&self) -> usize {
+        self.old_start + self.old_len
+    }
+
+    /// The index just past the end of the region in the new sequence.
+    pub fn new_end(&self) -> usize {
+        self.new_start + self.new_len
+    }
+}
+
+/// Options that control how lines are compared.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct DiffOptions {
+    /// Ignore whitespace when comparing lines (`-w`).
+    pub ignore_all_space: bool,
+    /// Ignore changes in the amount of whitespace, including whitespace at the end of a
+    /// line (`-b`).
+    pub ignore_space_change: bool,
+    pub algorithm: DiffAlgorithm,
+}
+
+/// The algorithm used to find the differences between two sequences.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DiffAlgorithm {
+    /// The basic greedy algorithm by Eugene Myers, which finds a minimal diff.
+    #[default]
+    Myers,
+    /// Matches lines that are unique on both sides first, then diffs the gaps between
+    /// them. This tends to keep code moves and function boundaries intact.
+    Patience,
+    /// An extension of patience that matches the least common lines first, so it also
+    /// works well when there are few unique lines.
+    Histogram,
+}
+
+impl DiffAlgorithm {
+    /// Parses an algorithm name as accepted by `--diff-algorithm` and `diff.algorithm`.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "default" | "myers" | "minimal" => Ok(Self::Myers),
+            "patience" => Ok(Self::Patience),
+            "histogram" => Ok(Self::Histogram),
+            name => Err(DiffError::UnknownAlgorithm(name.to_owned()).into()),
+        }
+    }
+
+    /// Reads the algorithm from `diff.algorithm`, falling back to Myers.
+    pub fn from_config(repo: &Repository) -> Result<Self> {
+        match repo.get_config("diff", "algorithm") {
+            Some(name) => Self::parse(name),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+impl DiffOptions {
+    /// Converts `line` into the form used to compare it with other lines.
+    pub fn normalize<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.ignore_all_space {
+            let normalized: Vec<u8> = line.iter()
+                .copied()
+                .filter(|byte| !byte.is_ascii_whitespace())
+                .collect();
+            Cow::Owned(normalized)
+        }
+        else if self.ignore_space_change {
+            let mut normalized = Vec::with_capacity(line.len());
+            let mut in_space = false;
+            for byte in line.trim_ascii_end() {
+                if byte.is_ascii_whitespace() {
+                    in_space = true;
+                    continue;
+                }
+                if in_space {
+                    normalized.push(b' ');
+                    in_space = false;
+                }
+                normalized.push(*byte);
+            }
+            Cow::Owned(normalized)
+        }
+        else {
+            Cow::Borrowed(line)
+        }
+    }
+
+    /// Returns true if any whitespace option is set.
+    pub fn ignores_whitespace(&self) -> bool {
+        self.ignore_all_space || self.ignore_space_change
+    }
+
+    /// Returns true if lines `a` and `b` are considered equal.
+    pub fn lines_equal(&self, a: &[u8], b: &[u8]) -> bool {
+        self.normalize(a) == self.normalize(b)
+    }
+}
+
+/// Splits `data` into lines, keeping the line endings. The last line may not have one.
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|byte| *byte == b'\n').collect()
+}
+
+/// Finds the edits that transform the lines `old` into the lines `new`, comparing lines
+/// according to `options`.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]], options: &DiffOptions) -> Vec<Edit> {
+    if !options.ignores_whitespace() {
+        return diff_with(old, new, options.algorithm);
+    }
+
+    let old: Vec<_> = old.iter().map(|line| options.normalize(line)).collect();
+    let new: Vec<_> = new.iter().map(|line| options.normalize(line)).collect();
+
+    diff_with(&old, &new, options.algorithm)
+}
+
+/// Finds a minimal set of edits that transform `old` into `new` using Myers' algorithm.
+/// The edits are in order and don't overlap.
+pub fn diff<T: Eq + Hash>(old: &[T], new: &[T]) -> Vec<Edit> {
+    diff_with(old, new, DiffAlgorithm::Myers)
+}
+
+/// Finds the edits that transform `old` into `new` using `algorithm`. The edits are in
+/// order and don't overlap.
+pub fn diff_with<T: Eq + Hash>(old: &[T], new: &[T], algorithm: DiffAlgorithm) -> Vec<Edit> {
+    let mut old_changed = vec![false; old.len()];
+    let mut new_changed = vec![false; new.len()];
+    // Same as git, only Myers trims the common ends of the whole sequences. The others
+    // look for matching elements in the whole sequences, so e.g. a line that also appears
+    // in the common ends isn't considered unique by patience.
+    match algorithm {
+        DiffAlgorithm::Myers => diff_range(algorithm, old, new, &mut old_changed, &mut new_changed),
+        DiffAlgorithm::Patience => patience(old, new, &mut old_changed, &mut new_changed),
+        DiffAlgorithm::Histogram => histogram(old, new, &mut old_changed, &mut new_changed),
+    }
+
+    compact(old, &mut old_changed, &new_changed);
+    compact(new, &mut new_changed, &old_changed);
+
+    collect_edits(&old_changed, &new_changed)
+}
+
+/// Marks the elements of `old` that are deleted and the elements of `new` that are
+/// inserted, using `algorithm` after trimming the common ends.
+fn diff_range<T: Eq + Hash>(algorithm: DiffAlgorithm, old: &[T], new: &[T], old_changed: &mut [bool], new_changed: &mut [bool]) {
+    // Matching elements at the ends are never part of an edit, so trim them off first
+    let prefix_len = old.iter()
+        .zip(new)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix_len = old[prefix_len..].iter().rev()
+        .zip(new[prefix_len..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old = &old[prefix_len..old.len() - suffix_len];
+    let new = &new[prefix_len..new.len() - suffix_len];
+    let old_changed = &mut old_changed[prefix_len..prefix_len + old.len()];
+    let new_changed = &mut new_changed[prefix_len..prefix_len + new.len()];
+
+    if old.is_empty() || new.is_empty() {
+        old_changed.fill(true);
+        new_changed.fill(true);
+        return;
+    }
+
+    match algorithm {
+        DiffAlgorithm::Myers => myers(old, new, old_changed, new_changed),
+        DiffAlgorithm::Patience => patience(old, new, old_changed, new_changed),
+        DiffAlgorithm::Histogram => histogram(old, new, old_changed, new_changed),
+    }
+}
+
+/// Marks changed elements using the patience algorithm: the longest increasing sequence
+/// of elements that occur exactly once in both `old` and `new` is matched, and the gaps
+/// between them are diffed recursively. Falls back to Myers if there are no such elements.
+fn patience<T: Eq + Hash>(old: &[T], new: &[T], old_changed: &mut [bool], new_changed: &mut [bool]) {
+    // For each element: (count in old, count in new, index in old, index in new)
+    let mut occurrences: HashMap<&T, (usize, usize, usize, usize)> = HashMap::new();
+    for (i, item) in old.iter().enumerate() {
+        let entry = occurrences.entry(item).or_default();
+        entry.0 += 1;
+        entry.2 = i;
+    }
+    for (j, item) in new.iter().enumerate() {
+        if let Some(entry) = occurrences.get_mut(item) {
+            entry.1 += 1;
+            entry.3 = j;
+        }
+    }
+
+    let mut unique: Vec<(usize, usize)> = occurrences.into_values()
+        .filter(|&(old_count, new_count, _, _)| old_count == 1 && new_count == 1)
+        .map(|(_, _, i, j)| (i, j))
+        .collect();
+    unique.sort_unstable();
+
+    let anchors = longest_increasing_run(&unique);
+    if anchors.is_empty() {
+        myers(old, new, old_changed, new_changed);
+        return;
+    }
+
+    let (mut i, mut j) = (0, 0);
+    for (anchor_i, anchor_j) in anchors {
+        diff_range(
+            DiffAlgorithm::Patience,
+            &old[i..anchor_i],
+            &new[j..anchor_j],
+            &mut old_changed[i..anchor_i],
+            &mut new_changed[j..anchor_j],
+        );
+        (i, j) = (anchor_i + 1, anchor_j + 1);
+    }
+    diff_range(DiffAlgorithm::Patience, &old[i..], &new[j..], &mut old_changed[i..], &mut new_changed[j..]);
+}
+
+/// Finds the longest subsequence of `pairs` (which are sorted by their first element) whose
+/// second elements are increasing, using patience sorting.
+fn longest_increasing_run(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // tails[k] is the index of the smallest pair that ends a run of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; pairs.len()];
+
+    for (index, &(_, j)) in pairs.iter().enumerate() {
+        let k = tails.partition_point(|&tail| pairs[tail].1 < j);
+        if k > 0 {
+            previous[index] = Some(tails[k - 1]);
+        }
+
+        if k == tails.len() {
+            tails.push(index);
+        }
+        else {
+            tails[k] = index;
+        }
+    }
+
+    let mut run = Vec::with_capacity(tails.len());
+    let mut next = tails.last().copied();
+    while let Some(index) = next {
+        run.push(pairs[index]);
+        next = previous[index];
+    }
+    run.reverse();
+
+    run
+}
+
+/// Marks changed elements using the histogram algorithm: the longest common region that
+/// contains the least frequent elements of `old` is matched, and the regions on either
+/// side are diffed recursively. Falls back to Myers if every common element is too frequent.
+fn histogram<T: Eq + Hash>(old: &[T], new: &[T], old_changed: &mut [bool], new_changed: &mut [bool]) {
+    let mut occurrences: HashMap<&T, Vec<usize>> = HashMap::new();
+    for (i, item) in old.iter().enumerate() {
+        occurrences.entry(item).or_default().push(i);
+    }
+
+    // The best region so far as (old start, new start, length), and the smallest number of
+    // occurrences of any element in it
+    let mut best: Option<(usize, usize, usize)> = None;
+    let mut best_count = MAX_CHAIN_LEN + 1;
+
+    let mut j = 0;
+    while j < new.len() {
+        let mut next_j = j + 1;
+
+        let candidates = occurrences.get(&new[j])
+            .filter(|positions| positions.len() <= best_count);
+        for &i in candidates.into_iter().flatten() {
+            let (mut old_start, mut new_start) = (i, j);
+            while old_start > 0 && new_start > 0 && old[old_start - 1] == new[new_start - 1] {
+                old_start -= 1;
+                new_start -= 1;
+            }
+            let (mut old_end, mut new_end) = (i + 1, j + 1);
+            while old_end < old.len() && new_end < new.len() && old[old_end] == new[new_end] {
+                old_end += 1;
+                new_end += 1;
+            }
+            next_j = next_j.max(new_end);
+
+            let count = old[old_start..old_end].iter()
+                .map(|item| occurrences[item].len())
+                .min()
+                .unwrap_or(usize::MAX);
+            let len = old_end - old_start;
+            if best.is_none_or(|(_, _, best_len)| best_len < len) || count < best_count {
+                best = Some((old_start, new_start, len));
+                best_count = count;
+            }
+        }
+
+        j = next_j;
+    }
+
+    let Some((i, j, len)) = best else {
+        myers(old, new, old_changed, new_changed);
+        return;
+    };
+
+    histogram(&old[..i], &new[..j], &mut old_changed[..i], &mut new_changed[..j]);
+    histogram(&old[i + len..], &new[j + len..], &mut old_changed[i + len..], &mut new_changed[j + len..]);
+}
+
+/// A run of changed elements in one sequence, `start..end`. It may be empty.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    /// Finds the first group in `changed`.
+    fn first(changed: &[bool]) -> Self {
+        let end = changed.iter().take_while(|is_changed| **is_changed).count();
+        Self { start: 0, end }
+    }
+
+    /// Moves to the next group. Returns false if this is the last group.
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+
+        self.start = self.end + 1;
+        self.end = self.start;
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+
+        true
+    }
+
+    /// Moves to the previous group. Returns false if this is the first group.
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+
+        self.end = self.start - 1;
+        self.start = self.end;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+
+        true
+    }
+
+    /// Shifts the group down by one element if the first changed element matches the one
+    /// after the group, merging with the following group if they meet. Returns false if
+    /// the group can't be shifted.
+    fn slide_down<T: Eq>(&mut self, items: &[T], changed: &mut [bool]) -> bool {
+        if self.end == items.len() || items[self.start] != items[self.end] {
+            return false;
+        }
+
+        changed[self.start] = false;
+        changed[self.end] = true;
+        self.start += 1;
+        self.end += 1;
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+
+        true
+    }
+
+    /// Shifts the group up by one element if the last changed element matches the one
+    /// before the group, merging with the preceding group if they meet. Returns false if
+    /// the group can't be shifted.
+    fn slide_up<T: Eq>(&mut self, items: &[T], changed: &mut [bool]) -> bool {
+        if self.start == 0 || items[self.start - 1] != items[self.end - 1] {
+            return false;
+        }
+
+        self.start -= 1;
+        self.end -= 1;
+        changed[self.start] = true;
+        changed[self.end] = false;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+
+        true
+    }
+}
+
+/// Shifts each group of changes in `items` as far down as possible (which often produces
+/// a more natural diff), unless it can be lined up with a group of changes in the other
+/// sequence instead. This follows git's implementation so that the diffs match.
+fn compact<T: Eq>(items: &[T], changed: &mut [bool], other_changed: &[bool]) {
+    let mut group = Group::first(changed);
+    let mut other = Group::first(other_changed);
+
+    loop {
+        if group.end > group.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+
+            loop {
+                let group_size = group.end - group.start;
+                end_matching_other = None;
+
+                while group.slide_up(items, changed) {
+                    other.previous(other_changed);
+                }
+                earliest_end = group.end;
+                if other.end > other.start {
+                    end_matching_other = Some(group.end);
+                }
+
+                while group.slide_down(items, changed) {
+                    other.next(other_changed);
+                    if other.end > other.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+
+                // Sliding may have merged groups, in which case the bigger group might
+                // be able to slide further
+                if group_size == group.end - group.start {
+                    break;
+                }
+            }
+
+            if group.end != earliest_end && end_matching_other.is_some() {
+                while other.end == other.start {
+                    group.slide_up(items, changed);
+                    other.previous(other_changed);
+                }
+            }
+        }
+
+        if !group.next(changed) {
+            break;
+        }
+        other.next(other_changed);
+    }
+}
+
+/// Marks the elements of `old` that are deleted and the elements of `new` that are
+/// inserted by a shortest edit script.
+fn myers<T: Eq>(old: &[T], new: &[T], old_changed: &mut [bool], new_changed: &mut [bool]) {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    if max == 0 {
+        return;
+    }
+
+    // v[k + offset] is the furthest x reached on diagonal k (where k = x - y)
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            }
+            else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk backwards through the trace to recover the path
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let v = &trace[d as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[(k - 1 + offset) as usize] < v[(k + 1 + offset) as usize]) {
+            k + 1
+        }
+        else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+        }
+
+        if x == prev_x {
+            new_changed[prev_y as usize] = true;
+        }
+        else {
+            old_changed[prev_x as usize] = true;
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+}
+
+/// Groups runs of changed elements into edits.
+fn collect_edits(old_changed: &[bool], new_changed: &[bool]) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < old_changed.len() || j < new_changed.len() {
+        let old_is_changed = old_changed.get(i).copied().unwrap_or(false);
+        let new_is_changed = new_changed.get(j).copied().unwrap_or(false);
+        if !old_is_changed && !new_is_changed {
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let (old_start, new_start) = (i, j);
+        while old_changed.get(i).copied().unwrap_or(false) {
+            i += 1;
+        }
+        while new_changed.get(j).copied().unwrap_or(false) {
+            j += 1;
+        }
+
+        edits.push(Edit {
+            old_start,
+            old_len: i - old_start,
+            new_start,
+            new_len: j - new_start,
+        });
+    }
+
+    edits
+}
+
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error("Unknown diff algorithm `{0}` (expected myers, minimal, patience, or histogram)")]
+    UnknownAlgorithm(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(old_start: usize, old_len: usize, new_start: usize, new_len: usize) -> Edit {
+        Edit { old_start, old_len, new_start, new_len }
+    }
+
+    #[test]
+    fn diff_finds_minimal_edits() {
+        let old: Vec<char> = "abcabba".chars().collect();
+        let new: Vec<char> = "cbabac".chars().collect();
+        let edits = diff(&old, &new);
+
+        let edit_len: usize = edits.iter().map(|edit| edit.old_len + edit.new_len).sum();
+        assert_eq!(edit_len, 5);
+
+        // Applying the edits to old produces new
+        let mut result = Vec::new();
+        let mut old_pos = 0;
+        for edit in &edits {
+            result.extend_from_slice(&old[old_pos..edit.old_start]);
+            result.extend_from_slice(&new[edit.new_start..edit.new_end()]);
+            old_pos = edit.old_end();
+        }
+        result.extend_from_slice(&old[old_pos..]);
+        assert_eq!(result, new);
+    }
+
+    #[test]
+    fn diff_lines_with_prefix_and_suffix() {
+        let old = split_lines(b"a\nb\nc\nd\n");
+        let new = split_lines(b"a\nx\nc\nd\ne");
+        assert_eq!(diff(&old, &new), [edit(1, 1, 1, 1), edit(4, 0, 4, 1)]);
+
+        assert_eq!(diff::<u8>(&[], &[]), []);
+        assert_eq!(diff(&old, &[]), [edit(0, 4, 0, 0)]);
+
+        // Ambiguous insertions are shifted as far down as possible
+        let old = split_lines(b"a\nb\n");
+        let new = split_lines(b"a\nb\na\nb\n");
+        assert_eq!(diff(&old, &new), [edit(2, 0, 2, 2)]);
+    }
+
+    #[test]
+    fn diff_ignoring_whitespace() {
+        let old = split_lines(b"fn  main() {\nx\n}\n");
+        let new = split_lines(b"fn main() { \n    x\n}\n");
+        assert_eq!(diff_lines(&old, &new, &DiffOptions::default()).len(), 1);
+
+        let options = DiffOptions { ignore_space_change: true, ..DiffOptions::default() };
+        assert_eq!(diff_lines(&old, &new, &options), [edit(1, 1, 1, 1)]);
+
+        let options = DiffOptions { ignore_all_space: true, ..DiffOptions::default() };
+        assert_eq!(diff_lines(&old, &new, &options), []);
+        assert!(options.lines_equal(b"a b\n", b"ab"));
+    }
+
+    #[test]
+    fn diff_with_each_algorithm() {
+        let old = split_lines(b"foo\n}\nx();\n\nfoo\nbar\nif (z) {\n");
+        let new = split_lines(b"foo\n\n}\nif (z) {\nx();\nfoo\n");
+
+        // Same results as git
+        assert_eq!(
+            diff_with(&old, &new, DiffAlgorithm::Myers),
+            [edit(1, 0, 1, 1), edit(2, 0, 3, 1), edit(3, 1, 5, 0), edit(5, 2, 6, 0)],
+        );
+        assert_eq!(
+            diff_with(&old, &new, DiffAlgorithm::Patience),
+            [edit(1, 2, 1, 0), edit(4, 2, 2, 1), edit(7, 0, 4, 2)],
+        );
+        assert_eq!(
+            diff_with(&old, &new, DiffAlgorithm::Histogram),
+            [edit(1, 2, 1, 0), edit(4, 2, 2, 1), edit(7, 0, 4, 2)],
+        );
+
+        assert_eq!(DiffAlgorithm::parse("minimal").unwrap(), DiffAlgorithm::Myers);
+        assert!(DiffAlgorithm::parse("fastest").is_err());
+    }
+}
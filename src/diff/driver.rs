@@ -0,0 +1,207 @@
+use std::{io::Write, process::Command};
+
+use regex::bytes::Regex;
+use thiserror::Error;
+
+use crate::{
+    Result,
+    repo::Repository,
+    attributes::{AttrValue, PathAttributes},
+};
+
+/// The longest function header shown after a hunk header, as in git.
+const MAX_FUNCNAME_LEN: usize = 80;
+
+/// Function header patterns for some languages, used when `diff.<name>.xfuncname` isn't
+/// set. These are the same as git's.
+const BUILTIN_FUNCNAMES: &[(&str, &str)] = &[
+    ("python", r"^[ \t]*((class|(async[ \t]+)?def)[ \t].*)$"),
+    ("rust", r#"^[\t ]*((pub(\([^\)]+\))?[\t ]+)?((async|const|unsafe|extern([\t ]+"[^"]+"))[\t ]+)?(struct|enum|union|mod|trait|fn|impl|macro_rules!)[< \t]+[^;]*)$"#),
+];
+
+/// How the changes to a file are shown, chosen by its `diff` attribute.
+#[derive(Default)]
+pub struct DiffDriver {
+    /// Show "Binary files differ" instead of a patch (`-diff` or `diff.<name>.binary`).
+    pub binary: bool,
+    /// A command that converts the file to text for comparison (`diff.<name>.textconv`).
+    pub textconv: Option<String>,
+    /// The patterns that find function headers, each paired with whether it's negated.
+    /// When there are none, lines that start with a letter, `_`, or `$` are used.
+    funcname: Vec<(Regex, bool)>,
+}
+
+impl DiffDriver {
+    /// Chooses the driver for a file with attributes `attrs`.
+    pub fn for_attributes(repo: &Repository, attrs: &PathAttributes) -> Result<Self> {
+        match attrs.get("diff") {
+            AttrValue::Unset => Ok(Self {
+                binary: true,
+                ..Self::default()
+            }),
+            AttrValue::Value(name) => Self::named(repo, name),
+            AttrValue::Set | AttrValue::Unspecified => Ok(Self::default()),
+        }
+    }
+
+    /// Looks up a driver configured in `diff.<name>`. As in git, unknown drivers behave
+    /// like the default one, except that some languages have built-in function headers.
+    pub fn named(repo: &Repository, name: &str) -> Result<Self> {
+        let section = format!(r#"diff "{name}""#);
+        let patterns = repo.get_config(&section, "xfuncname")
+            .or_else(|| repo.get_config(&section, "funcname"))
+            .or_else(|| {
+                BUILTIN_FUNCNAMES.iter()
+                    .find(|(builtin, _)| *builtin == name)
+                    .map(|(_, patterns)| *patterns)
+            });
+
+        let mut driver = match patterns {
+            Some(patterns) => Self::with_funcname(patterns)?,
+            None => Self::default(),
+        };
+        driver.binary = repo.get_config_bool(&section, "binary").unwrap_or(false);
+        driver.textconv = repo.get_config(&section, "textconv").map(str::to_owned);
+
+        Ok(driver)
+    }
+
+    /// Creates a driver that finds function headers with `patterns`, which has one regex
+    /// per line. A line that matches a regex prefixed with `!` isn't a function header. If
+    /// a regex has a capture group, only the first group is shown.
+    pub fn with_funcname(patterns: &str) -> Result<Self> {
+        let funcname = patterns.lines()
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| {
+                let (pattern, negated) = match pattern.strip_prefix('!') {
+                    Some(pattern) => (pattern, true),
+                    None => (pattern, false),
+                };
+                let regex = Regex::new(pattern)
+                    .map_err(|err| DiffDriverError::InvalidPattern {
+                        pattern: pattern.to_owned(),
+                        reason: err.to_string(),
+                    })?;
+
+                Ok((regex, negated))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            binary: false,
+            textconv: None,
+            funcname,
+        })
+    }
+
+    /// Converts `data` to text with the textconv command, if the driver has one. As in
+    /// git, the data is written to a temporary file whose path is passed to the command
+    /// through the shell, and the command's output is the text.
+    pub fn textconv(&self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(command) = &self.textconv else {
+            return Ok(None);
+        };
+
+        // Created exclusively with a random name, and removed when dropped
+        let mut temp_file = tempfile::Builder::new().prefix("wyag_textconv_").tempfile()?;
+        temp_file.write_all(data)?;
+        temp_file.flush()?;
+        let output = Command::new("sh")
+            .args(["-c", &format!("{command} \"$@\""), command])
+            .arg(temp_file.path())
+            .output();
+        temp_file.close()?;
+
+        let failed = |reason: String| DiffDriverError::TextconvFailed {
+            command: command.clone(),
+            reason,
+        };
+        let output = output.map_err(|err| failed(err.to_string()))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(failed(format!("{}: {}", output.status, stderr.trim_end())).into());
+        }
+
+        Ok(Some(output.stdout))
+    }
+
+    /// Returns the function header to show for `line`, or `None` if it doesn't start a
+    /// function.
+    pub fn funcname<'a>(&self, line: &'a [u8]) -> Option<&'a [u8]> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+
+        let header = if self.funcname.is_empty() {
+            match line.first() {
+                Some(byte) if byte.is_ascii_alphabetic() || *byte == b'_' || *byte == b'$' => line,
+                _ => return None,
+            }
+        }
+        else {
+            let (captures, negated) = self.funcname.iter()
+                .find_map(|(regex, negated)| Some((regex.captures(line)?, *negated)))?;
+            if negated {
+                return None;
+            }
+
+            captures.get(1)
+                .or_else(|| captures.get(0))?
+                .as_bytes()
+        };
+
+        let header = header.trim_ascii_end();
+        Some(&header[..header.len().min(MAX_FUNCNAME_LEN)])
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DiffDriverError {
+    #[error("Invalid function header pattern `{pattern}`: {reason}")]
+    InvalidPattern {
+        pattern: String,
+        reason: String,
+    },
+    #[error("Failed to run textconv command `{command}`: {reason}")]
+    TextconvFailed {
+        command: String,
+        reason: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_function_headers() {
+        let driver = DiffDriver::default();
+        assert_eq!(driver.funcname(b"int main(void) {  \n"), Some(&b"int main(void) {"[..]));
+        assert_eq!(driver.funcname(b"    return 0;\n"), None);
+
+        let (_, rust) = BUILTIN_FUNCNAMES.iter().find(|(name, _)| *name == "rust").unwrap();
+        let driver = DiffDriver::with_funcname(rust).unwrap();
+        assert_eq!(driver.funcname(b"    pub fn new() -> Self {\n"), Some(&b"pub fn new() -> Self {"[..]));
+        assert_eq!(driver.funcname(b"use std::fs;\n"), None);
+
+        let driver = DiffDriver::with_funcname("!^static\n^([a-z]+) .*$").unwrap();
+        assert_eq!(driver.funcname(b"static int x;\n"), None);
+        assert_eq!(driver.funcname(b"sub greet {\n"), Some(&b"sub"[..]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn textconv_runs_command_on_temp_file() {
+        assert_eq!(DiffDriver::default().textconv(b"data").unwrap(), None);
+
+        let driver = DiffDriver {
+            textconv: Some("tr a-z A-Z <".to_owned()),
+            ..DiffDriver::default()
+        };
+        assert_eq!(driver.textconv(b"\x00binary\n").unwrap(), Some(b"\x00BINARY\n".to_vec()));
+
+        let driver = DiffDriver {
+            textconv: Some("false".to_owned()),
+            ..DiffDriver::default()
+        };
+        assert!(driver.textconv(b"data").is_err());
+    }
+}
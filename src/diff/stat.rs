@@ -0,0 +1,257 @@
+use std::io::{self, Write};
+
+use super::{DiffDriver, DiffOptions, FilePatch};
+
+/// The total width of a diffstat line, which git uses when the output isn't a terminal.
+pub const STAT_WIDTH: usize = 80;
+
+/// How much a single file changed, as shown by `--stat`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FileStat {
+    /// The path as git would print it (quoted if necessary).
+    pub name: String,
+    pub counts: StatCounts,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StatCounts {
+    Text {
+        insertions: usize,
+        deletions: usize,
+    },
+    /// Binary files are summarized by their sizes in bytes instead of lines.
+    Binary {
+        old_size: usize,
+        new_size: usize,
+    },
+}
+
+impl FilePatch {
+    /// Counts the lines added and removed by the patch, using `driver` to decide whether the
+    /// file is binary.
+    pub fn stat(&self, options: &DiffOptions, driver: &DiffDriver) -> FileStat {
+        let old_data = self.old.as_ref().map_or(&[][..], |side| &side.data);
+        let new_data = self.new.as_ref().map_or(&[][..], |side| &side.data);
+
+        let counts = match self.is_binary(driver) {
+            true => StatCounts::Binary {
+                old_size: old_data.len(),
+                new_size: new_data.len(),
+            },
+            false => {
+                let old_lines = super::split_lines(old_data);
+                let new_lines = super::split_lines(new_data);
+                let edits = super::diff_lines(&old_lines, &new_lines, options);
+                StatCounts::Text {
+                    insertions: edits.iter().map(|edit| edit.new_len).sum(),
+                    deletions: edits.iter().map(|edit| edit.old_len).sum(),
+                }
+            },
+        };
+
+        FileStat { name: self.path.to_string(), counts }
+    }
+}
+
+/// Writes `stats` the way `git diff --stat` does: a line per file with its number of changed
+/// lines and a graph of `+` and `-` scaled to fit in `width` columns, followed by a summary.
+pub fn write_stat<W: Write>(out: &mut W, stats: &[FileStat], width: usize) -> io::Result<()> {
+    let mut max_change = 0;
+    let mut max_name_len = 0;
+    let mut bin_width = 0;
+    let mut number_width = 0;
+    for stat in stats {
+        max_name_len = max_name_len.max(stat.name.chars().count());
+        match stat.counts {
+            StatCounts::Text { insertions, deletions } => max_change = max_change.max(insertions + deletions),
+            StatCounts::Binary { old_size, new_size } => {
+                // "Bin XXX -> YYY bytes", with the counts of other files aligned with "Bin"
+                bin_width = bin_width.max(14 + decimal_width(old_size) + decimal_width(new_size));
+                number_width = 3;
+            },
+        }
+    }
+    let number_width = number_width.max(decimal_width(max_change));
+    let (name_width, graph_width) = column_widths(width, max_name_len, max_change, bin_width, number_width);
+
+    let mut total_insertions = 0;
+    let mut total_deletions = 0;
+    for stat in stats {
+        write_name(out, &stat.name, name_width)?;
+
+        let (insertions, deletions) = match stat.counts {
+            StatCounts::Binary { old_size, new_size } => {
+                write!(out, " {:>number_width$}", "Bin")?;
+                if old_size != 0 || new_size != 0 {
+                    write!(out, " {old_size} -> {new_size} bytes")?;
+                }
+                writeln!(out)?;
+                continue;
+            },
+            StatCounts::Text { insertions, deletions } => (insertions, deletions),
+        };
+        total_insertions += insertions;
+        total_deletions += deletions;
+
+        let (mut plus, mut minus) = (insertions, deletions);
+        if graph_width <= max_change {
+            let mut total = scale_linear(plus + minus, graph_width, max_change);
+            if total < 2 && plus > 0 && minus > 0 {
+                total = 2;
+            }
+            if plus < minus {
+                plus = scale_linear(plus, graph_width, max_change);
+                minus = total - plus;
+            }
+            else {
+                minus = scale_linear(minus, graph_width, max_change);
+                plus = total - minus;
+            }
+        }
+
+        let changed = insertions + deletions;
+        let space = if changed > 0 { " " } else { "" };
+        writeln!(out, " {changed:>number_width$}{space}{}{}", "+".repeat(plus), "-".repeat(minus))?;
+    }
+
+    write_summary(out, stats.len(), total_insertions, total_deletions)
+}
+
+/// Divides `width` between the file names and the graph, in the same way as git. Names are
+/// shortened only when the graph can't be narrowed enough to fit them.
+fn column_widths(width: usize, max_name_len: usize, max_change: usize, bin_width: usize, number_width: usize) -> (usize, usize) {
+    // Leave room for at least 10 columns of names and 6 of graph
+    let width = width.max(16 + 6 + number_width);
+    // " | " around the number, a space after it, and an empty column at the end
+    let fixed = number_width + 6;
+
+    let mut name_width = max_name_len;
+    let mut graph_width = match max_change + 4 > bin_width {
+        true => max_change,
+        false => bin_width - 4,
+    };
+
+    if name_width + fixed + graph_width > width {
+        let max_graph_width = (width * 3 / 8).saturating_sub(fixed);
+        if graph_width > max_graph_width {
+            graph_width = max_graph_width.max(6);
+        }
+
+        if name_width > width - fixed - graph_width {
+            name_width = width - fixed - graph_width;
+        }
+        else {
+            graph_width = width - fixed - name_width;
+        }
+    }
+
+    (name_width, graph_width)
+}
+
+/// Writes ` name |`, padding the name to `width` or replacing its start with `...` (up to
+/// a directory boundary if possible) if it's too long.
+fn write_name<W: Write>(out: &mut W, name: &str, width: usize) -> io::Result<()> {
+    let len = name.chars().count();
+    if len <= width {
+        return write!(out, " {name:<width$} |");
+    }
+
+    let width = width.saturating_sub(3);
+    let start = name.char_indices()
+        .nth(len - width)
+        .map_or(name.len(), |(i, _)| i);
+    let mut tail = &name[start..];
+    if let Some(slash) = tail.find('/') {
+        tail = &tail[slash..];
+    }
+
+    write!(out, " ...{tail:<width$} |")
+}
+
+/// Writes the last line of a diffstat, e.g. ` 2 files changed, 3 insertions(+)`.
+fn write_summary<W: Write>(out: &mut W, files: usize, insertions: usize, deletions: usize) -> io::Result<()> {
+    if files == 0 {
+        return writeln!(out, " 0 files changed");
+    }
+
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    write!(out, " {files} file{} changed", plural(files))?;
+    if insertions > 0 || deletions == 0 {
+        write!(out, ", {insertions} insertion{}(+)", plural(insertions))?;
+    }
+    if deletions > 0 || insertions == 0 {
+        write!(out, ", {deletions} deletion{}(-)", plural(deletions))?;
+    }
+
+    writeln!(out)
+}
+
+/// Scales `n` from the range 0..=`max` to 0..=`width`, keeping any nonzero count visible.
+fn scale_linear(n: usize, width: usize, max: usize) -> usize {
+    match n {
+        0 => 0,
+        n => 1 + n * (width - 1) / max,
+    }
+}
+
+fn decimal_width(n: usize) -> usize {
+    n.to_string().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(name: &str, insertions: usize, deletions: usize) -> FileStat {
+        FileStat {
+            name: name.to_owned(),
+            counts: StatCounts::Text { insertions, deletions },
+        }
+    }
+
+    fn render(stats: &[FileStat]) -> String {
+        let mut out = Vec::new();
+        write_stat(&mut out, stats, STAT_WIDTH).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_counts_and_summary() {
+        let stats = [text("README.md", 3, 1), text("src/main.rs", 0, 2)];
+        assert_eq!(render(&stats), concat!(
+            " README.md   | 4 +++-\n",
+            " src/main.rs | 2 --\n",
+            " 2 files changed, 3 insertions(+), 3 deletions(-)\n",
+        ));
+
+        assert_eq!(render(&[text("a", 1, 0)]), " a | 1 +\n 1 file changed, 1 insertion(+)\n");
+        assert_eq!(render(&[text("a", 0, 0)]), " a | 0\n 1 file changed, 0 insertions(+), 0 deletions(-)\n");
+    }
+
+    #[test]
+    fn scales_large_changes() {
+        let out = render(&[text("big", 300, 100), text("small", 1, 1)]);
+        assert_eq!(out.lines().next().unwrap(), format!(" big   | 400 {}{}", "+".repeat(49), "-".repeat(17)));
+        assert_eq!(out.lines().nth(1).unwrap(), " small |   2 +-");
+    }
+
+    #[test]
+    fn shortens_long_names() {
+        let name = format!("{}/file.txt", "directory".repeat(10));
+        let out = render(&[text(&name, 10, 0)]);
+        assert_eq!(out.lines().next().unwrap(), format!(" .../file.txt{} | 10 {}", " ".repeat(50), "+".repeat(10)));
+    }
+
+    #[test]
+    fn shows_binary_sizes() {
+        let stats = [
+            FileStat { name: "image.png".to_owned(), counts: StatCounts::Binary { old_size: 12, new_size: 345 } },
+            text("a", 2, 0),
+        ];
+        assert_eq!(render(&stats), concat!(
+            " image.png | Bin 12 -> 345 bytes\n",
+            " a         |   2 ++\n",
+            " 2 files changed, 2 insertions(+)\n",
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+use std::io::{self, Write};
+
+use crate::{
+    Result,
+    object::{Blob, ObjectHash, FileMode},
+    workdir::{WorkDir, WorkPathBuf},
+};
+
+use super::{DiffDriver, DiffOptions, Edit};
+
+/// Options for formatting a patch.
+#[derive(Clone, Copy, Debug)]
+pub struct PatchOptions {
+    /// The number of unchanged lines shown around each change (`-U`).
+    pub context: usize,
+    pub diff: DiffOptions,
+}
+
+impl Default for PatchOptions {
+    fn default() -> Self {
+        Self {
+            context: 3,
+            diff: DiffOptions::default(),
+        }
+    }
+}
+
+/// The changes to a single file, in the format of `git diff`.
+pub struct FilePatch {
+    pub path: WorkPathBuf,
+    /// The old version of the file, or `None` if it was created.
+    pub old: Option<PatchSide>,
+    /// The new version of the file, or `None` if it was deleted.
+    pub new: Option<PatchSide>,
+}
+
+/// One version of a file in a [`FilePatch`].
+pub struct PatchSide {
+    pub mode: FileMode,
+    pub hash: ObjectHash,
+    pub data: Vec<u8>,
+}
+
+impl PatchSide {
+    /// Reads the blob `hash`. Same as git, a submodule is shown as the commit it has checked
+    /// out instead.
+    pub fn from_blob(wd: &WorkDir, mode: FileMode, hash: ObjectHash) -> Result<Self> {
+        let data = match mode {
+            FileMode::Gitlink => format!("Subproject commit {hash}\n").into_bytes(),
+            _ => Blob::read(wd, &hash)?.serialize_into(),
+        };
+        Ok(Self { mode, hash, data })
+    }
+}
+
+impl FilePatch {
+    /// Replaces both versions of the file with text from the driver's textconv command, if
+    /// it has one. Submodules are left alone. The hashes still identify the original blobs.
+    pub fn textconv(&mut self, driver: &DiffDriver) -> Result<()> {
+        for side in [&mut self.old, &mut self.new].into_iter().flatten() {
+            if side.mode == FileMode::Gitlink {
+                continue;
+            }
+            if let Some(text) = driver.textconv(&side.data)? {
+                side.data = text;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the patch to `out`, using `driver` to decide whether the file is binary and
+    /// to find function headers for the hunks.
+    pub fn write<W: Write>(&self, out: &mut W, options: &PatchOptions, driver: &DiffDriver) -> io::Result<()> {
+        let path = &self.path;
+        writeln!(out, "diff --git {} {}", path.to_string_with_prefix("a/"), path.to_string_with_prefix("b/"))?;
+
+        match (&self.old, &self.new) {
+            (None, Some(new)) => writeln!(out, "new file mode {}", new.mode)?,
+            (Some(old), None) => writeln!(out, "deleted file mode {}", old.mode)?,
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                writeln!(out, "old mode {}", old.mode)?;
+                writeln!(out, "new mode {}", new.mode)?;
+                if old.hash == new.hash {
+                    return Ok(());
+                }
+            },
+            _ => (),
+        }
+
+        let abbrev = |side: &Option<PatchSide>| match side {
+            Some(side) => side.hash.to_string()[..7].to_owned(),
+            None => "0000000".to_owned(),
+        };
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) if old.mode == new.mode => {
+                writeln!(out, "index {}..{} {}", abbrev(&self.old), abbrev(&self.new), old.mode)?;
+            },
+            _ => writeln!(out, "index {}..{}", abbrev(&self.old), abbrev(&self.new))?,
+        }
+
+        let old_data = self.old.as_ref().map_or(&[][..], |side| &side.data);
+        let new_data = self.new.as_ref().map_or(&[][..], |side| &side.data);
+        let old_name = match self.old {
+            Some(_) => path.to_string_with_prefix("a/"),
+            None => "/dev/null".to_owned(),
+        };
+        let new_name = match self.new {
+            Some(_) => path.to_string_with_prefix("b/"),
+            None => "/dev/null".to_owned(),
+        };
+
+        if self.is_binary(driver) {
+            return writeln!(out, "Binary files {old_name} and {new_name} differ");
+        }
+        if old_data.is_empty() && new_data.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(out, "--- {old_name}")?;
+        writeln!(out, "+++ {new_name}")?;
+
+        let old_lines = super::split_lines(old_data);
+        let new_lines = super::split_lines(new_data);
+        let edits = super::diff_lines(&old_lines, &new_lines, &options.diff);
+
+        write_hunks(out, &old_lines, &new_lines, &edits, options.context, driver)
+    }
+
+    /// Returns true if either side should be treated as binary: its driver says so, or it
+    /// contains a NUL byte.
+    pub(super) fn is_binary(&self, driver: &DiffDriver) -> bool {
+        driver.binary || [&self.old, &self.new].into_iter()
+            .flatten()
+            .any(|side| side.data.contains(&0))
+    }
+}
+
+/// Writes `edits` as unified diff hunks with `context` unchanged lines around each change.
+/// Changes that are close enough for their context to touch are combined into one hunk.
+pub fn write_hunks<W: Write>(out: &mut W, old: &[&[u8]], new: &[&[u8]], edits: &[Edit], context: usize, driver: &DiffDriver) -> io::Result<()> {
+    let mut first = 0;
+    while first < edits.len() {
+        let mut last = first;
+        while last + 1 < edits.len() && edits[last + 1].old_start - edits[last].old_end() <= 2 * context {
+            last += 1;
+        }
+
+        let before = context.min(edits[first].old_start).min(edits[first].new_start);
+        let after = context.min(old.len() - edits[last].old_end());
+        let old_start = edits[first].old_start - before;
+        let new_start = edits[first].new_start - before;
+        let old_end = edits[last].old_end() + after;
+        let new_end = edits[last].new_end() + after;
+
+        write!(
+            out,
+            "@@ -{} +{} @@",
+            format_range(old_start, old_end - old_start),
+            format_range(new_start, new_end - new_start),
+        )?;
+        let funcname = old[..old_start].iter()
+            .rev()
+            .find_map(|line| driver.funcname(line));
+        if let Some(funcname) = funcname {
+            out.write_all(b" ")?;
+            out.write_all(funcname)?;
+        }
+        writeln!(out)?;
+
+        let mut pos = old_start;
+        for edit in &edits[first..=last] {
+            write_lines(out, b' ', &old[pos..edit.old_start])?;
+            write_lines(out, b'-', &old[edit.old_start..edit.old_end()])?;
+            write_lines(out, b'+', &new[edit.new_start..edit.new_end()])?;
+            pos = edit.old_end();
+        }
+        write_lines(out, b' ', &old[pos..old_end])?;
+
+        first = last + 1;
+    }
+
+    Ok(())
+}
+
+/// Formats the range of lines in a hunk header, counting from 1. An empty range is
+/// identified by the line before it.
+fn format_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        len => format!("{},{len}", start + 1),
+    }
+}
+
+fn write_lines<W: Write>(out: &mut W, prefix: u8, lines: &[&[u8]]) -> io::Result<()> {
+    for line in lines {
+        out.write_all(&[prefix])?;
+        out.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            out.write_all(b"\n\\ No newline at end of file\n")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+
+    fn hunks(old: &[u8], new: &[u8], context: usize) -> String {
+        let old = diff::split_lines(old);
+        let new = diff::split_lines(new);
+        let edits = diff::diff(&old, &new);
+
+        let mut out = Vec::new();
+        write_hunks(&mut out, &old, &new, &edits, context, &DiffDriver::default()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_hunks_with_context() {
+        let old = b"fn a() {\n    1\n    2\n    3\n    4\n    5\n    6\n    7\n}\n";
+        let new = b"fn a() {\n    1\n    two\n    3\n    4\n    5\n    6\n    seven\n}\n";
+
+        assert_eq!(hunks(old, new, 1), "\
+@@ -2,3 +2,3 @@ fn a() {
+     1
+-    2
++    two
+     3
+@@ -7,3 +7,3 @@ fn a() {
+     6
+-    7
++    seven
+ }
+");
+        assert!(hunks(old, new, 2).starts_with("@@ -1,9 +1,9 @@\n fn a() {\n"));
+        assert_eq!(hunks(b"a\nb", b"a\nc", 0), "@@ -2 +2 @@ a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n");
+        assert_eq!(hunks(b"", b"a\n", 3), "@@ -0,0 +1 @@\n+a\n");
+    }
+}
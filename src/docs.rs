@@ -0,0 +1,305 @@
+//! Long-form help topics for `wyag help <topic>`, and man pages generated from the
+//! command definitions.
+
+use std::{fs, io::Write, path::Path};
+
+use clap::{Arg, Command};
+use thiserror::Error;
+
+use crate::Result;
+
+/// A help topic that isn't about a single command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HelpTopic {
+    Revisions,
+    Pathspec,
+    Ignore,
+}
+
+impl HelpTopic {
+    pub const ALL: [HelpTopic; 3] = [Self::Revisions, Self::Pathspec, Self::Ignore];
+
+    /// Finds the topic called `name`.
+    pub fn find(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|topic| topic.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Revisions => "revisions",
+            Self::Pathspec => "pathspec",
+            Self::Ignore => "ignore",
+        }
+    }
+
+    /// A one-line description for the list of topics.
+    pub fn summary(&self) -> &'static str {
+        match self {
+            Self::Revisions => "How to name commits and other objects",
+            Self::Pathspec => "How commands interpret file paths",
+            Self::Ignore => "How untracked files are ignored",
+        }
+    }
+
+    /// The full text of the topic. Paragraphs are separated by blank lines, and lines that
+    /// start with two spaces are examples.
+    pub fn text(&self) -> &'static str {
+        match self {
+            Self::Revisions => REVISIONS,
+            Self::Pathspec => PATHSPEC,
+            Self::Ignore => IGNORE,
+        }
+    }
+}
+
+const REVISIONS: &str = "\
+Commands that take a commit, tree, or other object accept a revision: a name, followed by
+any number of suffixes, optionally followed by a path.
+
+The name can be a full hash or an abbreviation of at least 4 characters, HEAD, a local
+branch, a remote-tracking branch (e.g. origin/main), or a tag. It's an error if a name
+could mean more than one object.
+
+Suffixes move from one object to another:
+
+  <rev>~<n>     the nth generation ancestor, following first parents (~ alone means ~1)
+  <rev>^<n>     the nth parent (^ alone means ^1, and ^0 is the commit itself)
+  <rev>^{type}  follow tags (and commits to trees) until an object of that type
+  <rev>^{}      follow tags until something else
+
+A path selects an entry in the revision's tree:
+
+  <rev>:<path>  the blob or tree at <path>, e.g. HEAD~2:src/lib.rs
+
+log and rev-list take ranges too, which list the commits reachable from some revisions but
+not others:
+
+  <rev>         the commits reachable from <rev>
+  ^<rev>        leave out the commits reachable from <rev>
+  <a>..<b>      the same as ^<a> <b> (either side can be left out to mean HEAD)
+  <a>...<b>     the commits reachable from either side but not both
+
+Both sides are walked together, newest first, and the walk stops once everything left is
+reachable from a left-out commit, so a short range is quick even in a long history. A
+commit with a clock set earlier than its parents' can make it stop too soon.
+
+Reflog entries (main@{1}) and searches (:/text) aren't supported.
+";
+
+const PATHSPEC: &str = "\
+Commands that take paths interpret them relative to the current directory. A path must be
+inside the working tree, and naming a directory means every file under it.
+
+add, rm, and restore also accept globs. Quote them so the shell doesn't expand them:
+
+  '*.rs'        files ending in .rs, in the current directory or any directory under it
+  'src/*.rs'    files ending in .rs anywhere under src
+  'doc?.md'     ? matches any one character
+  '[ab].txt'    a.txt or b.txt
+
+As in git, * and ? match / too. A glob also names the path it spells out, so '[ab].txt'
+includes a file called [ab].txt if there is one.
+
+A glob matches tracked files, and for add, untracked files that aren't ignored. It's an
+error if it matches nothing. Pathspec magic (e.g. :(exclude)) isn't supported.
+
+Commands that accept many paths can read them from a file instead:
+
+  --pathspec-from-file <file>  one path per line, or - to read from stdin
+  --pathspec-file-nul          paths are separated by NUL characters instead
+";
+
+const IGNORE: &str = "\
+Untracked files can be ignored so that status doesn't list them and add skips them. Files
+that are already tracked are never ignored.
+
+Patterns are read from .gitignore files in any directory, which apply to the paths under
+that directory, and from .git/info/exclude, which applies to the whole repository. A
+deeper .gitignore takes precedence, and within a file, later lines take precedence.
+
+  # comment     blank lines and lines starting with # are skipped
+  *.log         a pattern without a slash matches the file name at any depth
+  build/        a trailing slash only matches directories
+  /todo.txt     a pattern with a slash matches the path from the .gitignore's directory
+  doc/**/*.pdf  ** matches any number of directories; * and ? never match a slash
+  !keep.log     a leading ! re-includes paths that an earlier pattern ignored
+
+A file inside an ignored directory can't be re-included. Use add -f to add an ignored file.
+";
+
+#[derive(Error, Debug)]
+pub enum DocsError {
+    #[error("No help topic or command named `{0}`")]
+    UnknownTopic(String),
+}
+
+/// Writes a man page for `command` and each of its visible subcommands to `out_dir`, along
+/// with a page for each help topic. Commands are in section 1, e.g. `wyag-add.1`, and topics
+/// are in section 7, e.g. `wyag-revisions.7`.
+pub fn generate_man_pages(command: &mut Command, out_dir: &Path) -> Result<()> {
+    command.build();
+    fs::create_dir_all(out_dir)?;
+
+    let name = command.get_name().to_owned();
+    write_man_page(command, &name, out_dir)?;
+    for subcommand in command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()) {
+        let page_name = format!("{name}-{}", subcommand.get_name());
+        write_man_page(subcommand, &page_name, out_dir)?;
+    }
+
+    for topic in HelpTopic::ALL {
+        let page_name = format!("{name}-{}", topic.name());
+        let mut page = Vec::new();
+        writeln!(page, ".TH {} 7", page_name.to_uppercase())?;
+        writeln!(page, ".SH NAME")?;
+        writeln!(page, "{} \\- {}", escape(&page_name), escape(topic.summary()))?;
+        writeln!(page, ".SH DESCRIPTION")?;
+        write_paragraphs(&mut page, topic.text())?;
+        fs::write(out_dir.join(format!("{page_name}.7")), page)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the man page for `command` to `<page_name>.1` in `out_dir`.
+fn write_man_page(command: &Command, page_name: &str, out_dir: &Path) -> Result<()> {
+    let mut page = Vec::new();
+    writeln!(page, ".TH {} 1", page_name.to_uppercase())?;
+
+    writeln!(page, ".SH NAME")?;
+    match command.get_about() {
+        Some(about) => writeln!(page, "{} \\- {}", escape(page_name), escape(&about.to_string()))?,
+        None => writeln!(page, "{}", escape(page_name))?,
+    }
+
+    writeln!(page, ".SH SYNOPSIS")?;
+    let usage = command.clone().render_usage().to_string();
+    writeln!(page, "{}", escape(usage.trim_start_matches("Usage: ")))?;
+
+    if let Some(about) = command.get_long_about().or(command.get_about()) {
+        writeln!(page, ".SH DESCRIPTION")?;
+        write_paragraphs(&mut page, &about.to_string())?;
+    }
+
+    let args: Vec<&Arg> = command.get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .collect();
+    if !args.is_empty() {
+        writeln!(page, ".SH OPTIONS")?;
+        for arg in args {
+            writeln!(page, ".TP")?;
+            writeln!(page, "{}", arg_label(arg))?;
+            if let Some(help) = arg.get_long_help().or(arg.get_help()) {
+                writeln!(page, "{}", escape(help.to_string().trim()))?;
+            }
+        }
+    }
+
+    let subcommands: Vec<&Command> = command.get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .collect();
+    if !subcommands.is_empty() {
+        writeln!(page, ".SH COMMANDS")?;
+        for subcommand in subcommands {
+            writeln!(page, ".TP")?;
+            writeln!(page, "\\fB{}\\fR", escape(subcommand.get_name()))?;
+            if let Some(about) = subcommand.get_about() {
+                writeln!(page, "{}", escape(&about.to_string()))?;
+            }
+        }
+    }
+
+    fs::write(out_dir.join(format!("{page_name}.1")), page)?;
+
+    Ok(())
+}
+
+/// Formats how an argument is written, e.g. `\fB\-U\fR, \fB\-\-unified\fR \fI<CONTEXT>\fR`.
+fn arg_label(arg: &Arg) -> String {
+    let value_names = arg.get_value_names()
+        .map(|names| names.iter().map(|name| format!("<{name}>")).collect::<Vec<_>>())
+        .unwrap_or_else(|| vec![format!("<{}>", arg.get_id().as_str().to_uppercase())]);
+    let values = escape(&value_names.join(" "));
+
+    if arg.is_positional() {
+        return format!("\\fI{values}\\fR");
+    }
+
+    let mut flags = vec![];
+    if let Some(short) = arg.get_short() {
+        flags.push(format!("\\fB{}\\fR", escape(&format!("-{short}"))));
+    }
+    if let Some(long) = arg.get_long() {
+        flags.push(format!("\\fB{}\\fR", escape(&format!("--{long}"))));
+    }
+    let mut label = flags.join(", ");
+    if arg.get_action().takes_values() {
+        label.push_str(&format!(" \\fI{values}\\fR"));
+    }
+
+    label
+}
+
+/// Writes `text` as roff paragraphs. Lines that start with two spaces are kept as they are.
+fn write_paragraphs(page: &mut Vec<u8>, text: &str) -> Result<()> {
+    for paragraph in text.trim().split("\n\n") {
+        if paragraph.lines().all(|line| line.starts_with("  ")) {
+            writeln!(page, ".PP\n.nf")?;
+            for line in paragraph.lines() {
+                writeln!(page, "{}", escape(line))?;
+            }
+            writeln!(page, ".fi")?;
+        }
+        else {
+            writeln!(page, ".PP")?;
+            for line in paragraph.lines() {
+                writeln!(page, "{}", escape(line.trim_start()))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Escapes `text` so that roff shows it as is.
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+
+            // A line starting with a period or apostrophe would be a request
+            match line.starts_with(['.', '\'']) {
+                true => format!("\\&{line}"),
+                false => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_pages_for_commands_and_topics() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let mut command = Command::new("tool")
+            .about("Does things")
+            .subcommand(Command::new("run")
+                .about("Runs it")
+                .arg(Arg::new("dry_run").long("dry-run").short('n').help("Don't run it").action(clap::ArgAction::SetTrue))
+                .arg(Arg::new("file").help(".hidden files too")))
+            .subcommand(Command::new("secret").hide(true));
+
+        generate_man_pages(&mut command, temp_dir.path()).unwrap();
+
+        let page = fs::read_to_string(temp_dir.join("tool-run.1")).unwrap();
+        assert!(page.starts_with(".TH TOOL-RUN 1\n.SH NAME\ntool\\-run \\- Runs it\n"));
+        assert!(page.contains(".TP\n\\fB\\-n\\fR, \\fB\\-\\-dry\\-run\\fR\nDon't run it\n"));
+        assert!(page.contains(".TP\n\\fI<FILE>\\fR\n\\&.hidden files too\n"));
+        assert!(temp_dir.join("tool.1").is_file());
+        assert!(temp_dir.join("tool-revisions.7").is_file());
+        assert!(!temp_dir.join("tool-secret.1").exists());
+    }
+}
@@ -0,0 +1,108 @@
+//! Writing messages in the user's editor, as `git commit` does when it isn't given `-m`.
+
+use std::{
+    path::Path,
+    process::Command,
+};
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    repo::Repository,
+};
+
+/// The instructions added below a message that's about to be edited.
+pub const COMMIT_INSTRUCTIONS: &str = "\
+# Please enter the commit message for your changes. Lines starting
+# with '#' will be ignored, and an empty message aborts the commit.
+";
+
+/// Returns the command that edits files, in git's order of preference: `GIT_EDITOR`,
+/// core.editor, `VISUAL`, `EDITOR`, and finally `vi`.
+pub fn editor_command(repo: &Repository) -> String {
+    let from_env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+    from_env("GIT_EDITOR")
+        .or_else(|| repo.get_config("core", "editor").map(str::to_owned))
+        .or_else(|| from_env("VISUAL"))
+        .or_else(|| from_env("EDITOR"))
+        .unwrap_or_else(|| "vi".to_owned())
+}
+
+/// Opens the file at `path` in the user's editor and waits for it to be closed. The editor
+/// is a shell command, so it can include arguments (e.g. `code --wait`). As in git, the
+/// editor `:` leaves the file as it is.
+pub fn edit_file(repo: &Repository, path: &Path) -> Result<()> {
+    let editor = editor_command(repo);
+    if editor == ":" {
+        return Ok(());
+    }
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(path)
+        .status()
+        .map_err(|err| EditorError::CouldNotRun { editor: editor.clone(), reason: err.to_string() })?;
+
+    if !status.success() {
+        return Err(EditorError::Failed(editor).into());
+    }
+
+    Ok(())
+}
+
+/// Cleans up an edited message the way git's default `strip` mode does: comment lines and
+/// trailing whitespace are removed, runs of blank lines become one, and there are no blank
+/// lines at the start or end.
+pub fn cleanup_message(message: &str) -> String {
+    let mut cleaned = String::new();
+    let mut pending_blank = false;
+
+    for line in message.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            pending_blank = !cleaned.is_empty();
+            continue;
+        }
+
+        if pending_blank {
+            cleaned.push('\n');
+            pending_blank = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+
+    cleaned
+}
+
+#[derive(Error, Debug)]
+pub enum EditorError {
+    #[error("Failed to run the editor `{editor}`: {reason}")]
+    CouldNotRun {
+        editor: String,
+        reason: String,
+    },
+    #[error("The editor `{0}` failed")]
+    Failed(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_up_messages() {
+        let edited = format!("\n\nFix the thing  \n\n\n\nIt was broken.\n# not this\n\n{COMMIT_INSTRUCTIONS}");
+        assert_eq!(cleanup_message(&edited), "Fix the thing\n\nIt was broken.\n");
+        assert_eq!(cleanup_message(COMMIT_INSTRUCTIONS), "");
+        assert_eq!(cleanup_message("No newline"), "No newline\n");
+    }
+}
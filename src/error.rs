@@ -0,0 +1,83 @@
+use thiserror::Error as ThisError;
+
+use crate::{
+    branch::BranchError,
+    index::IndexError,
+    object::ObjectError,
+    refs::RefError,
+    repo::RepoError,
+    workdir::WorkDirError,
+};
+
+/// The ways a library call can fail, for consumers that want to match on them.
+///
+/// Functions return an [`anyhow::Error`] (see [`crate::Result`]) so that context can be
+/// added along the way. `Error::from` finds the module-specific error inside one. Anything
+/// else, like an I/O error, is [`Error::Other`]. More variants may be added, but existing
+/// ones won't change.
+#[derive(ThisError, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error(transparent)]
+    Object(#[from] ObjectError),
+    #[error(transparent)]
+    Index(#[from] IndexError),
+    #[error(transparent)]
+    Ref(#[from] RefError),
+    #[error(transparent)]
+    Branch(#[from] BranchError),
+    #[error(transparent)]
+    Repo(#[from] RepoError),
+    #[error(transparent)]
+    WorkDir(#[from] WorkDirError),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Error {
+    /// Takes the module-specific error out of `err`, even if context was added to it. The
+    /// context is lost in that case.
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<ObjectError>() {
+            Ok(err) => return Self::Object(err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<IndexError>() {
+            Ok(err) => return Self::Index(err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<RefError>() {
+            Ok(err) => return Self::Ref(err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<BranchError>() {
+            Ok(err) => return Self::Branch(err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<RepoError>() {
+            Ok(err) => return Self::Repo(err),
+            Err(err) => err,
+        };
+        match err.downcast::<WorkDirError>() {
+            Ok(err) => Self::WorkDir(err),
+            Err(err) => Self::Other(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn finds_module_errors_under_context() {
+        let err = Err::<(), _>(IndexError::EmptyIndex)
+            .context("Failed to write the index")
+            .unwrap_err();
+        assert!(matches!(Error::from(err), Error::Index(IndexError::EmptyIndex)));
+
+        let err = anyhow::Error::from(std::io::Error::other("disk full"));
+        assert!(matches!(Error::from(err), Error::Other(_)));
+    }
+}
@@ -0,0 +1,45 @@
+//! Entry points for fuzzing the parsers, e.g. from a cargo-fuzz target like
+//! `fuzz_target!(|data: &[u8]| wyag::fuzz::index(data));`. Only built with the `fuzzing`
+//! feature.
+//!
+//! Each function parses `data` as the named format and throws the result away. Any input,
+//! however malformed, should parse or fail with an error, so a panic is a bug.
+
+use crate::{
+    index::Index,
+    kvlm,
+    object::{Commit, PackIndex, Tag, Tree},
+};
+
+/// Parses an index file, including its extensions.
+pub fn index(data: &[u8]) {
+    let _ = Index::parse(data);
+}
+
+/// Parses the body of a tree object.
+pub fn tree(data: &[u8]) {
+    let _ = Tree::deserialize(data.to_vec());
+}
+
+/// Parses the body of a commit object.
+pub fn commit(data: &[u8]) {
+    let _ = Commit::deserialize(data.to_vec());
+}
+
+/// Parses the body of a tag object.
+pub fn tag(data: &[u8]) {
+    let _ = Tag::deserialize(data.to_vec());
+}
+
+/// Parses a key-value list with message, the format shared by commits and tags. Input that
+/// isn't UTF-8 is skipped, since the parser takes a `&str`.
+pub fn kvlm(data: &[u8]) {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = kvlm::parse(text);
+    }
+}
+
+/// Parses a pack's `.idx` file.
+pub fn pack_index(data: &[u8]) {
+    let _ = PackIndex::parse(data);
+}
@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+
+use crate::{
+    Result,
+    object::{CommitNode, ObjectHash},
+    workdir::WorkDir,
+};
+
+/// Returns true if `ancestor` is `descendant` or one of its ancestors.
+///
+/// A commit's generation number is always greater than its ancestors', so the search never
+/// goes below the generation of `ancestor`. Generations come from the commit-graph file
+/// when it has them, and are otherwise computed once per process, so repeated queries over
+/// the same history (e.g. checking each branch in turn) only walk it once.
+pub fn is_ancestor(wd: &WorkDir, ancestor: &ObjectHash, descendant: &ObjectHash) -> Result<bool> {
+    if ancestor == descendant {
+        return Ok(true);
+    }
+
+    let min_generation = CommitNode::generation(wd, ancestor)?;
+    if CommitNode::generation(wd, descendant)? <= min_generation {
+        return Ok(false);
+    }
+
+    let mut pending = vec![*descendant];
+    let mut seen = HashSet::new();
+    while let Some(hash) = pending.pop() {
+        if hash == *ancestor {
+            return Ok(true);
+        }
+        if !seen.insert(hash) {
+            continue;
+        }
+
+        // Parents at or below the ancestor's generation can only lead to it if they are it
+        for parent in &CommitNode::get(wd, &hash)?.parents {
+            let generation = CommitNode::generation(wd, parent)?;
+            if generation > min_generation || parent == ancestor {
+                pending.push(*parent);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{commit, temp_workdir};
+
+    #[test]
+    fn finds_ancestors_across_merges() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        let root = commit(&wd, "root", &[]);
+        let a = commit(&wd, "a", &[root]);
+        let b = commit(&wd, "b", &[a]);
+        let side = commit(&wd, "side", &[root]);
+        let merge = commit(&wd, "merge", &[side, b]);
+
+        assert!(is_ancestor(&wd, &merge, &merge).unwrap());
+        assert!(is_ancestor(&wd, &root, &merge).unwrap());
+        assert!(is_ancestor(&wd, &a, &merge).unwrap());
+        assert!(is_ancestor(&wd, &side, &merge).unwrap());
+        assert!(!is_ancestor(&wd, &merge, &a).unwrap());
+        assert!(!is_ancestor(&wd, &side, &b).unwrap());
+        assert!(!is_ancestor(&wd, &b, &side).unwrap());
+    }
+}
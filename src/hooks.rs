@@ -0,0 +1,81 @@
+//! Hooks: programs in `.git/hooks` (or the directory in core.hooksPath) that git runs at
+//! certain points of a command, which can change what it does or stop it.
+
+use std::{
+    path::PathBuf,
+    process::Command,
+};
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    repo::Repository,
+};
+
+pub const PREPARE_COMMIT_MSG: &str = "prepare-commit-msg";
+
+/// Returns the path of the hook called `name`, if there is one. As in git, a hook that
+/// isn't executable is ignored.
+pub fn find(repo: &Repository, name: &str) -> Option<PathBuf> {
+    let wd = repo.workdir();
+    let path = match repo.get_config("core", "hooksPath") {
+        Some(dir) => wd.as_path().join(dir).join(name),
+        None => wd.git_path("hooks").join(name),
+    };
+    if !path.is_file() {
+        return None;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = path.metadata().ok()?.permissions().mode();
+        if mode & 0o111 == 0 {
+            return None;
+        }
+    }
+
+    Some(path)
+}
+
+/// Runs the hook called `name` with `args` from the root of the working tree. Returns false
+/// if there's no such hook, or fails if the hook does.
+pub fn run(repo: &Repository, name: &str, args: &[&str]) -> Result<bool> {
+    let Some(path) = find(repo, name) else {
+        return Ok(false);
+    };
+
+    // Hooks are usually shell scripts, which Windows can't run on its own
+    let mut command = match cfg!(windows) {
+        true => {
+            let mut command = Command::new("sh");
+            command.arg(&path);
+            command
+        },
+        false => Command::new(&path),
+    };
+    let status = command
+        .args(args)
+        .current_dir(repo.workdir().as_path())
+        .status()
+        .map_err(|err| HookError::CouldNotRun { name: name.to_owned(), reason: err.to_string() })?;
+
+    if !status.success() {
+        return Err(HookError::Failed(name.to_owned()).into());
+    }
+
+    Ok(true)
+}
+
+#[derive(Error, Debug)]
+pub enum HookError {
+    #[error("Failed to run the {name} hook: {reason}")]
+    CouldNotRun {
+        name: String,
+        reason: String,
+    },
+    #[error("The {0} hook failed")]
+    Failed(String),
+}
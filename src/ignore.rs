@@ -0,0 +1,231 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use anyhow::Context;
+
+use crate::{
+    Result,
+    attributes::wildmatch,
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+};
+
+/// The name of the files that list paths to ignore.
+pub const IGNORE_FILE_NAME: &str = ".gitignore";
+
+/// A line of a `.gitignore` file.
+struct IgnoreRule {
+    pattern: String,
+    /// The pattern started with `!`, so it re-includes paths.
+    negated: bool,
+    /// The pattern ended with `/`, so it only matches directories.
+    dir_only: bool,
+}
+
+/// The parsed contents of a `.gitignore` file or `.git/info/exclude`. The same syntax is
+/// used by `.git/info/sparse-checkout`.
+pub(crate) struct IgnoreFile {
+    /// The directory containing the file. Patterns are relative to it.
+    dir: WorkPathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+/// Decides which untracked paths are ignored, using the repository's `.gitignore` files
+/// and `.git/info/exclude`. Ignore files are read the first time they're needed.
+pub struct IgnoreRules {
+    root: PathBuf,
+    exclude: Option<IgnoreFile>,
+    /// The `.gitignore` file in each directory seen so far, or `None` if it has none.
+    files: HashMap<WorkPathBuf, Option<IgnoreFile>>,
+    enabled: bool,
+}
+
+impl IgnoreRules {
+    /// Reads `.git/info/exclude` for the repository at `wd`.
+    pub fn load(wd: &WorkDir) -> Result<Self> {
+        Ok(Self {
+            root: wd.as_path().to_owned(),
+            exclude: IgnoreFile::read(&wd.git_path("info/exclude"), &WorkPathBuf::root())?,
+            files: HashMap::new(),
+            enabled: true,
+        })
+    }
+
+    /// Creates rules that don't ignore anything, as for `add -f`.
+    pub fn none() -> Self {
+        Self {
+            root: PathBuf::new(),
+            exclude: None,
+            files: HashMap::new(),
+            enabled: false,
+        }
+    }
+
+    /// Returns true if `path` is ignored. A path is ignored if it's matched by an ignore
+    /// rule, or if any directory containing it is, because a file in an ignored directory
+    /// can't be re-included.
+    pub fn is_ignored(&mut self, path: &WorkPath, is_dir: bool) -> Result<bool> {
+        if !self.enabled || path.is_empty() {
+            return Ok(false);
+        }
+
+        for (index, _) in path.as_str().match_indices('/') {
+            let dir = WorkPath::from_canonical(&path.as_str()[..index])
+                .expect("ancestor of a canonical path should be canonical");
+            if self.matches(dir, true)? {
+                return Ok(true);
+            }
+        }
+
+        self.matches(path, is_dir)
+    }
+
+    /// Checks the rules that apply to `path` itself, ignoring its parents. Files deeper in
+    /// the tree take precedence over the root `.gitignore`, which takes precedence over
+    /// `.git/info/exclude`. Within a file, later lines take precedence over earlier ones.
+    fn matches(&mut self, path: &WorkPath, is_dir: bool) -> Result<bool> {
+        let mut dirs = vec![WorkPath::from_canonical("").expect("empty path should be canonical")];
+        dirs.extend(path.as_str().match_indices('/').map(|(index, _)| {
+            WorkPath::from_canonical(&path.as_str()[..index])
+                .expect("ancestor of a canonical path should be canonical")
+        }));
+
+        for dir in dirs.into_iter().rev() {
+            if !self.files.contains_key(dir) {
+                let file_path = self.root.join(dir.to_fs_path()).join(IGNORE_FILE_NAME);
+                let file = IgnoreFile::read(&file_path, dir)?;
+                self.files.insert(dir.to_owned(), file);
+            }
+
+            if let Some(ignored) = self.files[dir].as_ref().and_then(|file| file.matches(path, is_dir)) {
+                return Ok(ignored);
+            }
+        }
+
+        Ok(self.exclude.as_ref()
+            .and_then(|file| file.matches(path, is_dir))
+            .unwrap_or(false))
+    }
+}
+
+impl IgnoreFile {
+    /// Reads the ignore file at `abs_path`, which applies to paths in `dir`. Returns `None`
+    /// if there is no such file.
+    fn read(abs_path: &Path, dir: &WorkPath) -> Result<Option<Self>> {
+        if !abs_path.is_file() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(abs_path)
+            .with_context(|| format!("Failed to read ignore file at `{abs_path:?}`"))?;
+
+        Ok(Some(Self::parse(&contents, dir)))
+    }
+
+    /// Parses the contents of an ignore file that applies to paths in `dir`.
+    pub(crate) fn parse(contents: &str, dir: &WorkPath) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            rules: contents.lines().filter_map(parse_rule).collect(),
+        }
+    }
+
+    /// Returns whether the last rule that matches `path` ignores it, or `None` if no rule
+    /// matches.
+    ///
+    /// A pattern without a slash (other than a trailing one) matches the file name at any
+    /// depth. Otherwise, it matches the whole path relative to the directory containing
+    /// this file.
+    pub(crate) fn matches(&self, path: &WorkPath, is_dir: bool) -> Option<bool> {
+        let rel_path = path.strip_prefix(&self.dir)?.as_str();
+        let file_name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+
+        self.rules.iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.dir_only)
+            .find(|rule| match rule.pattern.contains('/') {
+                true => {
+                    let pattern = rule.pattern.strip_prefix('/').unwrap_or(&rule.pattern);
+                    wildmatch(pattern.as_bytes(), rel_path.as_bytes())
+                },
+                false => wildmatch(rule.pattern.as_bytes(), file_name.as_bytes()),
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Parses a line of an ignore file. Returns `None` for blank lines and comments.
+fn parse_rule(line: &str) -> Option<IgnoreRule> {
+    // Trailing spaces are dropped unless they're escaped
+    let mut line = line.trim_end_matches(['\r', '\n']);
+    while line.ends_with(' ') && !line.ends_with("\\ ") {
+        line = &line[..line.len() - 1];
+    }
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (line, negated) = match line.strip_prefix('!') {
+        Some(line) => (line, true),
+        None => (line.strip_prefix('\\').filter(|line| line.starts_with(['#', '!'])).unwrap_or(line), false),
+    };
+    let (pattern, dir_only) = match line.strip_suffix('/') {
+        Some(pattern) => (pattern, true),
+        None => (line, false),
+    };
+    if pattern.is_empty() {
+        return None;
+    }
+
+    Some(IgnoreRule {
+        pattern: pattern.to_owned(),
+        negated,
+        dir_only,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rules() {
+        assert!(parse_rule("# comment").is_none());
+        assert!(parse_rule("   ").is_none());
+
+        let rule = parse_rule("!build/  ").unwrap();
+        assert_eq!(rule.pattern, "build");
+        assert!(rule.negated && rule.dir_only);
+
+        let rule = parse_rule("\\#notes").unwrap();
+        assert_eq!(rule.pattern, "#notes");
+        assert!(!rule.negated);
+    }
+
+    #[test]
+    fn applies_precedence_and_parent_dirs() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.join(".git/info")).unwrap();
+        fs::create_dir_all(temp_dir.join("sub")).unwrap();
+        fs::write(temp_dir.join(".gitignore"), "*.log\nbuild/\n/root-only\n").unwrap();
+        fs::write(temp_dir.join("sub/.gitignore"), "!keep.log\n").unwrap();
+        fs::write(temp_dir.join(".git/info/exclude"), "secret\n").unwrap();
+
+        let wd = WorkDir::new(temp_dir.path()).unwrap();
+        let mut rules = IgnoreRules::load(&wd).unwrap();
+        let mut is_ignored = |path: &str, is_dir| {
+            rules.is_ignored(&WorkPathBuf::try_from(path).unwrap(), is_dir).unwrap()
+        };
+
+        assert!(is_ignored("a.log", false));
+        assert!(is_ignored("sub/a.log", false));
+        assert!(!is_ignored("sub/keep.log", false));
+        assert!(is_ignored("build", true));
+        assert!(!is_ignored("build", false));
+        assert!(is_ignored("sub/build/out.txt", false));
+        assert!(is_ignored("root-only", false));
+        assert!(!is_ignored("sub/root-only", false));
+        assert!(is_ignored("sub/secret", false));
+        assert!(!is_ignored("src/main.rs", false));
+
+        assert!(!IgnoreRules::none().is_ignored(&WorkPathBuf::try_from("a.log").unwrap(), false).unwrap());
+    }
+}
@@ -1,17 +1,19 @@
 use std::{
-    fs::OpenOptions,
-    io::{BufRead, Seek, Write},
+    io::Write,
     path::Path,
     collections::BTreeMap,
 };
 
-use anyhow::Context;
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, WriteBytesExt};
 
 use crate::{
     Result,
-    object::{ObjectHash, Blob},
+    object::{ObjectHash, Blob, Tree, TreeEntry, FileMode, compare_names},
     workdir::{WorkDir, WorkPathBuf, WorkPath}, branch,
+    ignore::IgnoreRules,
+    pathspec::Pathspec,
+    sparse_checkout::SparseCheckout,
+    trace::{self, Category},
 };
 
 mod error;
@@ -27,27 +29,116 @@ pub mod diff;
 pub use diff::UnstagedChange;
 pub use diff::StagedChange;
 
+mod rename;
+pub use rename::Rename;
+
+pub mod reader;
+pub use reader::{IndexReader, IndexEntries};
+
+pub mod ewah;
+pub use ewah::EwahBitmap;
+
+pub mod split;
+pub use split::SplitIndex;
+
+mod sparse;
+
+mod checkout;
+
 /// Data on a single file stored in the index.
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct IndexEntry {
     pub stats: FileStats,
     pub hash: ObjectHash,
     pub flags: EntryFlags,
 }
 
+impl IndexEntry {
+    /// Constructs a gitlink entry for the submodule at `path`, which has `commit_hash`
+    /// checked out.
+    pub fn gitlink(path: &WorkPath, commit_hash: ObjectHash) -> Self {
+        Self {
+            stats: FileStats::from_mode_and_size(FileMode::Gitlink, 0),
+            hash: commit_hash,
+            flags: EntryFlags::new(path.to_bytes()),
+        }
+    }
+
+    /// Constructs an entry for the file at `path` in the working directory from its current
+    /// stats and contents, which are stored as a blob. This is the entry [`Index::add`]
+    /// would record for it.
+    pub fn from_worktree_file(wd: &WorkDir, path: &WorkPath) -> Result<Self> {
+        let metadata = std::fs::symlink_metadata(wd.abs_path(path))?;
+        let stats = FileStats::from_metadata(&metadata)?;
+        let hash = Index::hash_worktree_file(wd, path, &metadata, true)?;
+
+        Ok(Self {
+            stats,
+            hash,
+            flags: EntryFlags::new(path.to_bytes()),
+        })
+    }
+
+    /// Sets the merge stage: 0 for a normal entry, or 1 (base), 2 (ours), or 3 (theirs) for
+    /// a conflicted one.
+    pub fn with_stage(mut self, stage: u16) -> Result<Self> {
+        if stage > 3 {
+            return Err(IndexError::InvalidStage(stage).into());
+        }
+        self.flags.set_stage(stage);
+
+        Ok(self)
+    }
+
+    /// Constructs an entry for the blob or gitlink `entry` of a tree, for recording a
+    /// version of `path` that isn't in the working directory, such as a conflict stage.
+    pub fn from_tree_entry(path: &WorkPath, entry: &TreeEntry) -> Self {
+        Self {
+            stats: FileStats::from_mode_and_size(entry.mode, 0),
+            hash: entry.hash,
+            flags: EntryFlags::new(path.to_bytes()),
+        }
+    }
+
+    /// Returns true if this entry is a gitlink (a submodule).
+    pub fn is_gitlink(&self) -> bool {
+        self.stats.mode == FileMode::Gitlink.bits()
+    }
+
+    /// The length stored in the flags for an entry at `path`. Sparse directory entries are
+    /// written with a trailing slash, which counts.
+    fn name_len_for(&self, path: &WorkPath) -> u16 {
+        let len = match self.is_sparse_dir() && !path.is_empty() {
+            true => path.to_bytes().len() + 1,
+            false => path.to_bytes().len(),
+        };
+
+        std::cmp::min(len, 0xFFF) as u16
+    }
+}
+
 /// The index file (or staging area) that git uses to prepare the next commit.
 /// 
 /// See [the git docs](https://github.com/git/git/blob/master/Documentation/gitformat-index.txt)
 /// for detailed information.
 /// 
 /// This representation supports version 1-3. It does not support version 4.
-/// Extensions are not supported.
+/// The split index (`link`) and sparse index (`sdir`) extensions are supported.
+/// Other extensions are not.
 pub struct Index {
     pub version: u32,
+    /// One entry per path. A conflicted path is represented by one of its stages (ours if
+    /// there is one), and all of them are in `conflict_stages`.
     pub entries: BTreeMap<WorkPathBuf, IndexEntry>,
+    /// Every stage (1 for the base, 2 for ours, 3 for theirs) of each conflicted path, in
+    /// order. Inserting or removing the path's entry resolves the conflict.
+    pub conflict_stages: BTreeMap<WorkPathBuf, Vec<IndexEntry>>,
     pub ext_data: Vec<u8>,
+    pub split: Option<SplitIndex>,
 }
 
-pub type IndexRange<'a> = std::collections::btree_map::Range<'a, WorkPathBuf, IndexEntry>;
+/// An index extension's signature and data.
+type Extension<'a> = ([u8; 4], &'a [u8]);
 
 impl Index {
 
@@ -59,129 +150,148 @@ impl Index {
         Index {
             version: version.unwrap_or(2),
             entries: BTreeMap::new(),
+            conflict_stages: BTreeMap::new(),
             ext_data: Vec::new(),
+            split: None,
         }
     }
 
-    /// Constructs an `Index` from a byte stream.
-    pub fn parse<R>(reader: &mut R) -> Result<Index>
-    where
-        R: BufRead + Seek
-    {
-        // Validate signature
-        {
-            let mut signature = [0u8; 4];
-            reader.read_exact(&mut signature)?;
+    /// Constructs an `Index` from the bytes of an index file.
+    pub fn parse(data: &[u8]) -> Result<Index> {
+        let _timer = trace::start(Category::IndexParse);
+        let (version, mut entries_iter) = Self::parse_entries(data)?;
+        let mut parsed_entries: Vec<_> = entries_iter.by_ref().collect::<Result<_>>()?;
 
-            if signature != Self::INDEX_SIGNATURE {
-                return Err(IndexError::Corrupt {
-                    problem: format!("invalid signature {signature:?} (expected {:?})", Self::INDEX_SIGNATURE),
-                }.into());
+        // Any remaining data is for extensions
+        let mut ext_data = Vec::new();
+        let mut split = None;
+        let (extensions, _) = Self::split_extensions(entries_iter.rest())?;
+        for (signature, ext) in extensions {
+            if signature == SplitIndex::SIGNATURE {
+                split = Some(SplitIndex::parse_link(ext, &mut parsed_entries)?);
+            }
+            else if signature == Self::SPARSE_SIGNATURE {
+                // Nothing to do: sparse directory entries are recognized by their mode
+            }
+            else {
+                ext_data.extend(signature);
+                ext_data.write_u32::<BigEndian>(ext.len() as u32)?;
+                ext_data.extend(ext);
             }
         }
 
-        // Signature is followed by version number
-        let version = reader.read_u32::<BigEndian>()?;
-        if version > 3 {
-            return Err(IndexError::UnsupportedVersion(version).into());
-        }
-
-        // Version number is followed by the number of entries
-        let entry_count = reader.read_u32::<BigEndian>()?;
-
-        // Parse entries
+        // Paths are copied here, now that the entries are about to become mutable
         let mut entries = BTreeMap::new();
-        for _ in 0..entry_count {
-            let (path, entry) = Self::parse_next_entry(reader)?;
+        let mut conflict_stages: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (path, entry) in parsed_entries {
+            if path.is_empty() {
+                return Err(IndexError::Corrupt {
+                    problem: "empty path".to_owned()
+                }.into());
+            }
+            let path = path.into_owned();
+
+            // A conflicted path has an entry per stage, in order
+            if entry.flags.get_stage() != 0 {
+                conflict_stages.entry(path).or_default().push(entry);
+                continue;
+            }
             entries.insert(path, entry);
         }
-
-        // Any remaining data is for extensions
-        let mut ext_data = Vec::new();
-        reader.read_to_end(&mut ext_data)?;
+        for (path, stages) in &conflict_stages {
+            if let Some(entry) = Self::representative_stage(stages) {
+                entries.insert(path.clone(), entry.clone());
+            }
+        }
 
         Ok(Index {
             version,
             entries,
+            conflict_stages,
             ext_data,
+            split,
         })
     }
 
-    /// Parses one index entry from `reader`.
-    fn parse_next_entry<R>(reader: &mut R) -> Result<(WorkPathBuf, IndexEntry)>
-    where
-        R: BufRead + Seek
-    {
-        let start_pos = reader.stream_position()?; // used to calculate entry length later
-
-        // Entry begins with file stats
-        let stats = FileStats {
-            ctime_s: reader.read_u32::<BigEndian>()?,
-            ctime_ns: reader.read_u32::<BigEndian>()?,
-            mtime_s: reader.read_u32::<BigEndian>()?,
-            mtime_ns: reader.read_u32::<BigEndian>()?,
-            dev: reader.read_u32::<BigEndian>()?,
-            ino: reader.read_u32::<BigEndian>()?,
-            mode: reader.read_u32::<BigEndian>()?,
-            uid: reader.read_u32::<BigEndian>()?,
-            gid: reader.read_u32::<BigEndian>()?,
-            size: reader.read_u32::<BigEndian>()?,
-        };
-
-        // Stats are followed by the object hash
-        let hash = {
-            let mut raw = [0u8; 20];
-            reader.read_exact(&mut raw)?;
+    /// Reads the SHA-1 checksum at the end of an index file. Returns `None` if there is
+    /// none (older versions of wyag didn't write one), or the stored checksum and the hash
+    /// of the rest of the file, which should be the same.
+    pub fn checksum(data: &[u8]) -> Result<Option<(ObjectHash, ObjectHash)>> {
+        let (_, mut entries) = Self::parse_entries(data)?;
+        for entry in entries.by_ref() {
+            entry?;
+        }
 
-            ObjectHash { raw }
+        let (_, checksum) = Self::split_extensions(entries.rest())?;
+        let Some(checksum) = checksum else {
+            return Ok(None);
         };
+        let stored = ObjectHash::try_from(checksum)?;
+        let actual = ObjectHash::new(&data[..data.len() - checksum.len()]);
 
-        // Hash is followed by 2-4 bytes of flags
-        let flags = {
-            let basic_flags = reader.read_u16::<BigEndian>()?;
+        Ok(Some((stored, actual)))
+    }
 
-            let has_ext_flags = (basic_flags & flags::MASK_EXTENDED) != 0;
-            let ext_flags = match has_ext_flags {
-                true => Some(reader.read_u16::<BigEndian>()?),
-                false => None,
+    /// Splits the data following the index entries into extensions. Each extension
+    /// is a 4-byte signature followed by a 4-byte length and the extension data.
+    /// The trailing checksum is returned separately, if present.
+    fn split_extensions(mut data: &[u8]) -> Result<(Vec<Extension<'_>>, Option<&[u8]>)> {
+        const CHECKSUM_SIZE: usize = 20;
+        const EXT_HEADER_SIZE: usize = 8;
+
+        let mut extensions = vec![];
+        while data.len() >= EXT_HEADER_SIZE {
+            let mut reader = IndexReader::new(data);
+            let signature: [u8; 4] = reader.take(4)?.try_into().expect("Slice should be 4 bytes");
+            let len = reader.read_u32()? as usize;
+
+            // Index files written by older versions of wyag have no checksum, so the only
+            // way to recognize one is that it doesn't parse as an extension
+            let ext = match reader.take(len) {
+                Ok(ext) => ext,
+                Err(_) if data.len() == CHECKSUM_SIZE => break,
+                Err(err) => return Err(err),
             };
 
-            EntryFlags {
-                basic_flags,
-                ext_flags,
-            }
-        };
+            extensions.push((signature, ext));
+            data = &data[EXT_HEADER_SIZE + len..];
+        }
 
-        // Flags are followed by a null-terminated path
-        let path = {
-            let mut bytes = vec![];
-            if reader.read_until(0, &mut bytes)? < 2 {
-                // should have read at least one byte + null terminator
+        match data.len() {
+            0 => Ok((extensions, None)),
+            CHECKSUM_SIZE => Ok((extensions, Some(data))),
+            len => Err(IndexError::Corrupt {
+                problem: format!("{len} bytes of unrecognized data after the last extension"),
+            }.into()),
+        }
+    }
+
+    /// Parses the header of an index file and returns its version along with an
+    /// iterator over its entries. The entries borrow from `data`.
+    pub fn parse_entries(data: &[u8]) -> Result<(u32, IndexEntries<'_>)> {
+        let mut reader = IndexReader::new(data);
+
+        // Validate signature
+        {
+            let signature = reader.take(4)?;
+
+            if signature != Self::INDEX_SIGNATURE {
                 return Err(IndexError::Corrupt {
-                    problem: "empty path".to_owned()
+                    problem: format!("invalid signature {signature:?} (expected {:?})", Self::INDEX_SIGNATURE),
                 }.into());
             }
+        }
 
-            let bytes = &bytes[..bytes.len() - 1]; // drop the null terminator
-            
-            WorkPathBuf::try_from(bytes)
-                .with_context(|| "Invalid path in index".to_owned())?
-        };
-
-        // Each entry ends with 0-7 additional NULL bytes to maintain 8-byte alignment
-        {
-            let entry_len: usize = (reader.stream_position()? - start_pos)
-                .try_into()
-                .expect("Entry length should not exceed usize::MAX");
-            let padding = Self::calc_padding_len(entry_len, true);
-            reader.seek(std::io::SeekFrom::Current(padding as i64))?;
+        // Signature is followed by version number
+        let version = reader.read_u32()?;
+        if version > 3 {
+            return Err(IndexError::UnsupportedVersion(version).into());
         }
 
-        Ok((path, IndexEntry {
-            stats,
-            hash,
-            flags,
-        }))
+        // Version number is followed by the number of entries
+        let entry_count = reader.read_u32()?;
+
+        Ok((version, IndexEntries::new(reader, entry_count)))
     }
 
     /// Calculates the number of null bytes that should follow an index entry of
@@ -198,15 +308,117 @@ impl Index {
         }
     }
 
-    pub fn entries_in_dir(&self, dir: &WorkPath) -> IndexRange {
-        if dir.is_empty() {
-            return self.entries.range::<WorkPathBuf, std::ops::RangeFull>(..);
+    pub fn entries_in_dir(&self, dir: &WorkPath) -> impl Iterator<Item = (&WorkPathBuf, &IndexEntry)> + '_ {
+        match dir.is_empty() {
+            true => self.entries_with_prefix(None, vec![]),
+            false => self.entries_with_prefix(Some(dir), [&dir.to_bytes()[..], b"/"].concat()),
         }
+    }
 
-        let range_start = std::ops::Bound::Excluded(format!("{dir}/"));
-        let range_end = std::ops::Bound::Excluded(format!("{dir}0"));
+    /// Lists the entries whose paths start with the bytes `prefix`, in sorted order. The
+    /// search starts at `start`, which must not sort after `prefix`.
+    fn entries_with_prefix(&self, start: Option<&WorkPath>, prefix: Vec<u8>) -> impl Iterator<Item = (&WorkPathBuf, &IndexEntry)> + '_ {
+        use std::ops::Bound;
+
+        // Everything before the first match sorts before `prefix`
+        let start = start.map_or(Bound::Unbounded, Bound::Included);
+        let skip_prefix = prefix.clone();
+        self.entries.range::<WorkPath, _>((start, Bound::Unbounded))
+            .skip_while(move |(path, _)| *path.to_bytes() < *skip_prefix)
+            .take_while(move |(path, _)| path.to_bytes().starts_with(&prefix))
+    }
+
+    /// Lists the entries named by `pathspec`, in sorted order. Only the entries starting
+    /// with the literal part of each path or glob are visited.
+    pub fn entries_matching<'a>(&'a self, pathspec: &Pathspec) -> Vec<(&'a WorkPathBuf, &'a IndexEntry)> {
+        let mut entries: Vec<_> = pathspec.prefixes()
+            .flat_map(|prefix| {
+                let start = WorkPathBuf::try_from(prefix.trim_end_matches('/')).ok()
+                    .filter(|start| prefix.as_bytes().starts_with(&start.to_bytes()));
+                self.entries_with_prefix(start.as_deref(), prefix.as_bytes().to_vec())
+            })
+            .filter(|(path, _)| pathspec.matches(path))
+            .collect();
+        entries.sort_by_key(|(path, _)| *path);
+        entries.dedup_by_key(|(path, _)| *path);
+
+        entries
+    }
 
-        self.entries.range((range_start, range_end))
+    /// Returns the entry at `path` for modification. Changing the entry's flags directly is
+    /// up to the caller to keep consistent; prefer removing and re-inserting it.
+    pub fn get_mut(&mut self, path: &WorkPath) -> Option<&mut IndexEntry> {
+        self.entries.get_mut(path)
+    }
+
+    /// Inserts `entry` at `path`, returning the entry it replaced, if any. Any conflict
+    /// stages at `path` are replaced too.
+    ///
+    /// The entry's name length is set to match `path`, so an entry can be moved by removing
+    /// it and inserting it elsewhere. Like git, the index is upgraded to version 3 if the
+    /// entry uses extended flags, which version 2 can't store.
+    pub fn insert_entry(&mut self, path: WorkPathBuf, mut entry: IndexEntry) -> Option<IndexEntry> {
+        entry.flags.set_name_len(entry.name_len_for(&path));
+        if entry.flags.get_extended() && self.version < 3 {
+            self.version = 3;
+        }
+
+        self.conflict_stages.remove(&path);
+        self.entries.insert(path, entry)
+    }
+
+    /// Removes and returns the entry at `path`, if any, along with any conflict stages.
+    pub fn remove_entry(&mut self, path: &WorkPath) -> Option<IndexEntry> {
+        self.conflict_stages.remove(path);
+        self.entries.remove(path)
+    }
+
+    /// Records a conflict at `path`, replacing its entry with `stages`: the base, ours, and
+    /// theirs versions, any of which may be missing. If they all are, the path is removed.
+    pub fn insert_conflict(&mut self, path: WorkPathBuf, stages: [Option<IndexEntry>; 3]) {
+        let mut recorded = Vec::new();
+        for (stage, entry) in (1..).zip(stages) {
+            if let Some(mut entry) = entry {
+                entry.flags.set_name_len(entry.name_len_for(&path));
+                entry.flags.set_stage(stage);
+                recorded.push(entry);
+            }
+        }
+
+        match Self::representative_stage(&recorded).cloned() {
+            Some(entry) => {
+                self.entries.insert(path.clone(), entry);
+                self.conflict_stages.insert(path, recorded);
+            },
+            None => {
+                self.remove_entry(&path);
+            },
+        }
+    }
+
+    /// Picks the stage that stands for a conflicted path in `entries`: ours, or else theirs,
+    /// or else the base.
+    fn representative_stage(stages: &[IndexEntry]) -> Option<&IndexEntry> {
+        [2, 3, 1].into_iter()
+            .find_map(|stage| stages.iter().find(|entry| entry.flags.get_stage() == stage))
+    }
+
+    /// Returns the entry for `stage` (1 for the base, 2 for ours, 3 for theirs) of the
+    /// conflict at `path`, if there is one.
+    pub fn conflict_stage(&self, path: &WorkPath, stage: u16) -> Option<&IndexEntry> {
+        self.stages_at(path)
+            .iter()
+            .find(|entry| entry.flags.get_stage() == stage && stage != 0)
+    }
+
+    /// Returns the entries stored in the index file for `path`: every stage if it's
+    /// conflicted, or just its entry.
+    fn stages_at(&self, path: &WorkPath) -> &[IndexEntry] {
+        match (self.entries.get(path), self.conflict_stages.get(path)) {
+            (Some(entry), Some(stages)) if entry.flags.get_stage() != 0 => stages,
+            (Some(entry), _) => std::slice::from_ref(entry),
+            (None, _) => &[],
+        }
     }
 
     /// Converts the index into a sequence of bytes.
@@ -214,51 +426,82 @@ impl Index {
         let min_size = self.size_lower_bound();
         let mut data: Vec<u8> = Vec::with_capacity(min_size);
 
-        // Serialize header
-        data.write_all(&Self::INDEX_SIGNATURE)?;
-        data.write_u32::<BigEndian>(self.version)?;
-        data.write_u32::<BigEndian>(self.entries.len() as u32)?;
-
-        // Serialize entries
-        for (path, entry) in &self.entries {
-            let start_len = data.len();
-
-            // File stats
-            data.write_u32::<BigEndian>(entry.stats.ctime_s)?;
-            data.write_u32::<BigEndian>(entry.stats.ctime_ns)?;
-            data.write_u32::<BigEndian>(entry.stats.mtime_s)?;
-            data.write_u32::<BigEndian>(entry.stats.mtime_ns)?;
-            data.write_u32::<BigEndian>(entry.stats.dev)?;
-            data.write_u32::<BigEndian>(entry.stats.ino)?;
-            data.write_u32::<BigEndian>(entry.stats.mode)?;
-            data.write_u32::<BigEndian>(entry.stats.uid)?;
-            data.write_u32::<BigEndian>(entry.stats.gid)?;
-            data.write_u32::<BigEndian>(entry.stats.size)?;
-            
-            // Hash
-            data.write_all(&entry.hash.raw)?;
-
-            // Flags
-            data.write_u16::<BigEndian>(entry.flags.basic_flags)?;
-            if let Some(ext_flags) = entry.flags.ext_flags {
-                data.write_u16::<BigEndian>(ext_flags)?;
-            }
-
-            // Path
-            data.write_all(path.as_bytes())?;
+        let entry_count = self.entries.keys().map(|path| self.stages_at(path).len()).sum();
+        self.serialize_header(&mut data, entry_count)?;
 
-            // Padding
-            let len = data.len() - start_len;
-            let padding = Self::calc_padding_len(len, false);
-            data.write_all(&[0; 8][..padding])?;
+        for (path, _) in self.sorted_entries() {
+            let path_bytes = path.to_bytes();
+            for entry in self.stages_at(path) {
+                Self::serialize_entry(&mut data, &path_bytes, entry)?;
+            }
         }
 
         // Extensions
         // data.extend(&index.ext_data);
+        self.serialize_sparse_ext(&mut data)?;
 
         Ok(data)
     }
 
+    /// Returns the entries in the order git stores them, where each sparse directory sorts
+    /// by its name with the trailing `/`. So `docs.txt` comes before the sparse `docs/`.
+    fn sorted_entries(&self) -> Vec<(&WorkPathBuf, &IndexEntry)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|(a_path, a), (b_path, b)| {
+            compare_names(&a_path.to_bytes(), a.is_sparse_dir(), &b_path.to_bytes(), b.is_sparse_dir())
+        });
+
+        entries
+    }
+
+    /// Appends the index header to `data`.
+    fn serialize_header(&self, data: &mut Vec<u8>, entry_count: usize) -> Result<()> {
+        data.write_all(&Self::INDEX_SIGNATURE)?;
+        data.write_u32::<BigEndian>(self.version)?;
+        data.write_u32::<BigEndian>(entry_count as u32)?;
+
+        Ok(())
+    }
+
+    /// Appends one index entry to `data`.
+    fn serialize_entry(data: &mut Vec<u8>, path: &[u8], entry: &IndexEntry) -> Result<()> {
+        let start_len = data.len();
+
+        // File stats
+        data.write_u32::<BigEndian>(entry.stats.ctime_s)?;
+        data.write_u32::<BigEndian>(entry.stats.ctime_ns)?;
+        data.write_u32::<BigEndian>(entry.stats.mtime_s)?;
+        data.write_u32::<BigEndian>(entry.stats.mtime_ns)?;
+        data.write_u32::<BigEndian>(entry.stats.dev)?;
+        data.write_u32::<BigEndian>(entry.stats.ino)?;
+        data.write_u32::<BigEndian>(entry.stats.mode)?;
+        data.write_u32::<BigEndian>(entry.stats.uid)?;
+        data.write_u32::<BigEndian>(entry.stats.gid)?;
+        data.write_u32::<BigEndian>(entry.stats.size)?;
+        
+        // Hash
+        data.write_all(&entry.hash.raw)?;
+
+        // Flags
+        data.write_u16::<BigEndian>(entry.flags.basic_flags)?;
+        if let Some(ext_flags) = entry.flags.ext_flags {
+            data.write_u16::<BigEndian>(ext_flags)?;
+        }
+
+        // Path (sparse directory entries end with a slash)
+        data.write_all(path)?;
+        if entry.is_sparse_dir() && !path.is_empty() {
+            data.write_all(b"/")?;
+        }
+
+        // Padding
+        let len = data.len() - start_len;
+        let padding = Self::calc_padding_len(len, false);
+        data.write_all(&[0; 8][..padding])?;
+
+        Ok(())
+    }
+
     /// Calculates the lower bound on the number of bytes the index will
     /// be serialized into.
     /// 
@@ -289,47 +532,145 @@ impl Index {
     /// Adds the file or directory at `path` to the index.
     /// 
     /// If `path` is a directory, files in the index that no longer exist
-    /// will be removed. Subdirectories will be added recursively.
-    pub fn add<P>(&mut self, wd: &WorkDir, path: P) -> Result<()>
+    /// will be removed. Subdirectories will be added recursively, skipping ignored files.
+    ///
+    /// Naming an untracked path that is ignored is an error unless `force` is true, in
+    /// which case ignored files are added too. Files outside of the sparse checkout can't
+    /// be added; see [`Index::add_with`].
+    ///
+    /// Nested repositories found in a directory are skipped and returned, so the caller can
+    /// warn about them. Naming a path in one is an error.
+    pub fn add<P>(&mut self, wd: &WorkDir, path: P, force: bool) -> Result<Vec<WorkPathBuf>>
+    where
+        P: AsRef<Path>
+    {
+        self.add_with(wd, path, force, &SparseCheckout::load(wd)?)
+    }
+
+    /// Like [`Index::add`], but with `sparse` deciding which files can be added. Tracked
+    /// files outside of it are left alone, and adding any other file there is an error.
+    /// Use [`SparseCheckout::all`] to add files regardless, as with `add --sparse`.
+    pub fn add_with<P>(&mut self, wd: &WorkDir, path: P, force: bool, sparse: &SparseCheckout) -> Result<Vec<WorkPathBuf>>
     where
         P: AsRef<Path>
     {
         let path = wd.canonicalize_path(path)?;
-        let changes = self.list_unstaged_changes(wd, &path, true)?;
+        self.expand_to_path(wd, &path)?;
 
+        let mut ignores = match force {
+            true => IgnoreRules::none(),
+            false => IgnoreRules::load(wd)?,
+        };
+        let is_tracked = self.entries.contains_key(&path) || self.entries_in_dir(&path).next().is_some();
+        if !is_tracked && ignores.is_ignored(&path, wd.abs_path(&path).is_dir())? {
+            return Err(IndexError::PathIgnored(vec![path]).into());
+        }
+
+        // A nested repository's files belong to it, not to this one
+        if let Some(nested) = self.nested_repo_containing(wd, &path) {
+            return Err(IndexError::NestedRepository(nested).into());
+        }
+        let changes = self.list_unstaged_changes_with(wd, &path, true, &mut ignores, sparse)?;
+
+        // Naming a tracked file outside of the sparse checkout doesn't list it as changed
+        let mut outside: Vec<WorkPathBuf> = changes.iter()
+            .filter_map(|change| match change {
+                UnstagedChange::Created { path, .. } | UnstagedChange::Renamed { path, .. } => Some(path),
+                _ => None,
+            })
+            .filter(|path| !sparse.contains(path))
+            .cloned()
+            .collect();
+        if self.entries.contains_key(&path) && !sparse.contains(&path) {
+            outside.push(path.clone());
+        }
+        if !outside.is_empty() {
+            return Err(IndexError::OutsideSparseCheckout(outside).into());
+        }
+
+        let mut nested_repos = vec![];
         for change in changes.into_iter() {
             match change {
                 UnstagedChange::Created { path, stats, hash } => {
-                    let flags = EntryFlags::new(path.as_str());
-                    self.entries.insert(path, IndexEntry {
+                    let flags = EntryFlags::new(path.to_bytes());
+                    self.insert_entry(path, IndexEntry {
                         stats,
                         hash,
                         flags,
                     });
                 },
                 UnstagedChange::Deleted { path } => {
-                    self.entries.remove(&path);
+                    self.remove_entry(&path);
                 },
                 UnstagedChange::Modified { path, stats, hash } => {
-                    let entry = self.entries.get_mut(&path).expect("Path should already exist in index");
+                    let entry = self.get_mut(&path).expect("Path should already exist in index");
                     entry.stats = stats;
                     entry.hash = hash;
+                    // Adding a conflicted file marks it resolved
+                    entry.flags.set_stage(0);
+                    self.conflict_stages.remove(&path);
+                },
+                UnstagedChange::NestedRepo { path } => nested_repos.push(path),
+                UnstagedChange::Renamed { old_path, path, stats, hash } => {
+                    // The entry moves to the new key so the index matches the file's new name
+                    self.remove_entry(&old_path);
+                    let flags = EntryFlags::new(path.to_bytes());
+                    self.insert_entry(path, IndexEntry {
+                        stats,
+                        hash,
+                        flags,
+                    });
                 },
             };
         }
 
-        Ok(())
+        Ok(nested_repos)
+    }
+
+    /// Stages the current contents of the files at or under `path` that are tracked by this
+    /// index or by `head` (usually the index of the HEAD commit), including deletions.
+    /// Unlike [`Index::add`], untracked files are left alone. Returns the staged files.
+    pub fn add_tracked(&mut self, wd: &WorkDir, head: &Index, path: &WorkPathBuf) -> Result<Vec<WorkPathBuf>> {
+        self.expand_to_path(wd, path)?;
+
+        let mut files: Vec<WorkPathBuf> = self.expected_keys_for_path(path).into_iter()
+            .chain(head.expected_keys_for_path(path))
+            .cloned()
+            .collect();
+        files.sort();
+        files.dedup();
+        if files.is_empty() {
+            return Err(IndexError::PathNotTracked(path.clone()).into());
+        }
+
+        // Tracked files outside of the sparse checkout aren't expected to be there
+        let sparse = SparseCheckout::load(wd)?;
+        files.retain(|file| sparse.contains(file));
+        for file in &files {
+            self.add_with(wd, wd.abs_path(file), true, &sparse)?;
+        }
+
+        Ok(files)
+    }
+
+    /// Lists the paths with unresolved merge conflicts.
+    pub fn conflicted_paths(&self) -> impl Iterator<Item = &WorkPathBuf> {
+        self.entries.iter()
+            .filter(|(_, entry)| entry.flags.get_stage() != 0)
+            .map(|(path, _)| path)
     }
 
     /// Removes the file or directory at `path` from the index and deletes it from the file system.
     /// 
     /// The index and working directory are required to match the tip of the current branch.
-    /// Subdirectories are removed recursively.
+    /// Subdirectories are removed recursively, but only tracked files are deleted. Directories
+    /// are removed once they're empty.
     pub fn remove<P>(&mut self, wd: &WorkDir, path: P) -> Result<()>
     where
         P: AsRef<Path>
     {
         let path = wd.canonicalize_path(path)?;
+        self.expand_to_path(wd, &path)?;
 
         // Abort if there are staged or unstaged changes
         {
@@ -347,77 +688,125 @@ impl Index {
             }
         }
 
-        // Delete files and remove them from the index
-        if path.as_ref().is_dir() {
-            std::fs::remove_dir_all(&path)?;
-        }
-        else {
-            std::fs::remove_file(&path)?;
-        }
-
-        if self.entries.contains_key(&path) {
-            self.entries.remove(&path);
-        }
-        else {
-            let keys_to_remove: Vec<_> =
-                self.entries_in_dir(&path)
+        // Delete tracked files and remove them from the index. Untracked files are left
+        // alone, along with the directories that contain them.
+        let tracked: Vec<WorkPathBuf> = match self.entries.contains_key(&path) {
+            true => vec![path.clone()],
+            false => self.entries_in_dir(&path)
                 .map(|(key, _)| key)
                 .cloned()
-                .collect();
+                .collect(),
+        };
+        if tracked.is_empty() {
+            return Err(IndexError::PathNotTracked(path).into());
+        }
 
-            for key in keys_to_remove {
-                self.entries.remove(&key);
-            }
+        wd.remove_tracked(&tracked)?;
+        for key in tracked {
+            self.remove_entry(&key);
         }
 
         Ok(())
     }
 
     /// Updates the working directory at path `target` to match the index.
-    /// The existing file or directory at `target` (if any) will be deleted.
+    /// Entries outside of the sparse checkout (skip-worktree) are not restored.
+    /// 
+    /// Untracked files are left alone. Since every tracked file is in the index, there's
+    /// nothing else to delete, so unlike [`Tree::restore_from_tree_ish`], there is no
+    /// distinction between overlay and no-overlay mode.
     pub fn restore(&self, wd: &WorkDir, target: &WorkPath) -> Result<()> {
-        let abs_path = wd.as_path().join(target);
-        wd.remove_path(target)?;
+        let abs_path = wd.abs_path(target);
 
         if let Some(entry) = self.entries.get(target) {
-            // Case 1: restore file
+            if entry.flags.get_skip_worktree() {
+                return Ok(());
+            }
+
+            // Case 1: restore submodule (which is only a placeholder directory, since
+            // submodules aren't supported)
+            if entry.is_gitlink() {
+                wd.create_worktree_dir(target)?;
+                return Ok(());
+            }
+
+            if abs_path.is_dir() {
+                wd.remove_path(target)?;
+            }
+
+            // Case 2: restore file
             let blob = Blob::read(wd, &entry.hash)?;
-            std::fs::write(abs_path, blob.serialize_into())?;
+            wd.write_worktree_file(target, &blob.serialize_into(), FileMode::from_bits(entry.stats.mode)?)?;
         }
         else {
-            // Case 2: possibly restore directory
-            let entries = self.entries_in_dir(target);
+            // Case 3: possibly restore directory
+            let entries = self.entries_in_dir(target)
+                .filter(|(_, entry)| !entry.flags.get_skip_worktree());
             for (entry_path, entry) in entries {
                 if let Some(dir) = entry_path.parent() {
-                    let dir_path = wd.as_path().join(dir);
-                    std::fs::create_dir_all(&dir_path)?;
+                    wd.create_worktree_dir(dir)?;
+                }
+
+                if entry.is_gitlink() {
+                    wd.create_worktree_dir(entry_path)?;
+                    continue;
                 }
 
                 let blob = Blob::read(wd, &entry.hash)?;
-                let file_path = wd.as_path().join(entry_path);
-                std::fs::write(file_path, blob.serialize_into())?;
+                if wd.abs_path(entry_path).is_dir() {
+                    wd.remove_path(entry_path)?;
+                }
+                wd.write_worktree_file(entry_path, &blob.serialize_into(), FileMode::from_bits(entry.stats.mode)?)?;
             }
         }
 
         Ok(())
     }
 
+    /// Updates the index at path `target` to match `tree`. Entries at `target` that
+    /// don't exist in `tree` are removed.
+    pub fn restore_from_tree(&mut self, wd: &WorkDir, tree: &Tree, target: &WorkPath) -> Result<()> {
+        self.expand_to_path(wd, target)?;
+
+        if self.remove_entry(target).is_none() {
+            let keys_to_remove: Vec<_> =
+                self.entries_in_dir(target)
+                .map(|(key, _)| key)
+                .cloned()
+                .collect();
+
+            for key in keys_to_remove {
+                self.remove_entry(&key);
+            }
+        }
+
+        tree.add_path_to_index(wd, self, target)
+    }
+
     /// Overwrites the repo's index file with this index.
+    /// 
+    /// If the index is in split mode, most entries are written to a shared index instead.
+    /// An index with conflicts is written whole, since only one stage of each conflicted
+    /// path can be shared.
     pub fn write(&self, wd: &WorkDir) -> Result<()> {
+        let _timer = trace::start(Category::IndexWrite);
         if self.entries.is_empty() {
             return Err(IndexError::EmptyIndex.into());
         }
 
-        let mut options = OpenOptions::new();
-        options.write(true)
-            .create(true)
-            .truncate(true);
-        let mut index_file = wd.open_git_file("index", Some(&options))?;
-    
+        if let Some(split) = self.split.as_ref().filter(|_| self.conflict_stages.is_empty()) {
+            return self.write_split(wd, split);
+        }
+
         let data = self.serialize()?;
-        index_file.write_all(&data)?;
+        self.write_data(wd, data)
+    }
 
-        Ok(())
+    /// Overwrites the repo's index file with `data`, followed by its SHA-1 checksum.
+    fn write_data(&self, wd: &WorkDir, mut data: Vec<u8>) -> Result<()> {
+        let checksum = ObjectHash::new(&data);
+        data.extend(checksum.raw);
+        wd.write_git_file("index", &data)
     }
 
 }
@@ -426,6 +815,7 @@ impl Index {
 mod tests {
     use super::*;
     use std::collections::HashSet;
+    use crate::test_util::temp_workdir;
 
     fn fake_entry() -> IndexEntry {
         IndexEntry {
@@ -474,12 +864,53 @@ mod tests {
         assert_eq!(padding, 0);
     }
 
+    #[test]
+    fn parse_round_trip() {
+        let mut index = Index::new(None);
+        insert_fake_entry(&mut index, "main.rs");
+        insert_fake_entry(&mut index, "hello/world.rs");
+
+        let data = index.serialize().unwrap();
+        let parsed = Index::parse(&data).unwrap();
+
+        assert_eq!(parsed.version, 2);
+        let keys: Vec<_> = parsed.entries.keys().map(|key| key.as_str()).collect();
+        assert_eq!(keys, vec!["hello/world.rs", "main.rs"]);
+        assert!(parsed.ext_data.is_empty());
+    }
+
+    #[test]
+    fn parse_borrows_canonical_paths() {
+        let mut index = Index::new(None);
+        insert_fake_entry(&mut index, "hello/world.rs");
+
+        let data = index.serialize().unwrap();
+        let (_, mut entries) = Index::parse_entries(&data).unwrap();
+        let (path, _) = entries.next().unwrap().unwrap();
+
+        assert!(matches!(path, std::borrow::Cow::Borrowed(_)));
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn parse_rejects_truncated_entry() {
+        let mut index = Index::new(None);
+        insert_fake_entry(&mut index, "main.rs");
+
+        let data = index.serialize().unwrap();
+        let result = Index::parse(&data[..data.len() - 10]);
+
+        assert!(result.is_err());
+    }
+
     #[test]
     fn entries_in_extant_dir() {
         let mut index = Index {
             version: 0,
             entries: BTreeMap::new(),
-            ext_data: vec![]
+            conflict_stages: BTreeMap::new(),
+            ext_data: vec![],
+            split: None,
         };
         insert_fake_entry(&mut index, "main.rs");
         insert_fake_entry(&mut index, "goodbye/world.rs");
@@ -489,7 +920,10 @@ mod tests {
         insert_fake_entry(&mut index, "hello/world/bar/baz.rs");
         insert_fake_entry(&mut index, "hello/world/~~~~~");
         insert_fake_entry(&mut index, "hello/world0");
+        insert_fake_entry(&mut index, "hello/world.txt");
         insert_fake_entry(&mut index, "hello/friend.rs");
+        let escaped = WorkPathBuf::try_from(&b"hello/world/\x80"[..]).unwrap();
+        index.entries.insert(escaped.clone(), fake_entry());
 
         let dir = WorkPathBuf::try_from("hello/world").unwrap();
         let entries: HashSet<_> = index.entries_in_dir(&dir)
@@ -503,6 +937,7 @@ mod tests {
             "hello/world/bar/foo.rs".try_into().unwrap(),
             "hello/world/bar/baz.rs".try_into().unwrap(),
             "hello/world/~~~~~".try_into().unwrap(),
+            escaped,
         ].into();
 
         assert_eq!(entries, expected);
@@ -513,7 +948,9 @@ mod tests {
         let mut index = Index {
             version: 0,
             entries: BTreeMap::new(),
-            ext_data: vec![]
+            conflict_stages: BTreeMap::new(),
+            ext_data: vec![],
+            split: None,
         };
         insert_fake_entry(&mut index, "main.rs");
         insert_fake_entry(&mut index, "goodbye/world.rs");
@@ -525,4 +962,240 @@ mod tests {
 
         assert!(entries.next().is_none());
     }
+
+    #[test]
+    fn entries_matching_pathspec() {
+        let (temp_dir, wd) = temp_workdir();
+        let mut index = Index::new(None);
+        for path in ["main.rs", "hello/world.rs", "hello/world/foo.rs", "hello/worlds.rs", "lib/a.rs", "lib/a.txt", "lib/b/c.rs"] {
+            insert_fake_entry(&mut index, path);
+        }
+
+        let pathspec = Pathspec::new(&wd, [
+            temp_dir.join("hello/world"),
+            temp_dir.join("lib/*.rs"),
+            temp_dir.join("hello/*.rs"),
+        ]).unwrap();
+        let paths: Vec<_> = index.entries_matching(&pathspec).into_iter()
+            .map(|(path, _)| path.as_str())
+            .collect();
+
+        assert_eq!(paths, ["hello/world.rs", "hello/world/foo.rs", "hello/worlds.rs", "lib/a.rs", "lib/b/c.rs"]);
+    }
+
+    #[test]
+    fn gitlinks_are_checked_out_as_placeholders() {
+        let (temp_dir, wd) = temp_workdir();
+
+        // The commit is in the submodule's repository, so reading it would fail
+        let path = WorkPathBuf::try_from("lib").unwrap();
+        let mut tree = Tree::empty();
+        tree.entries.insert(path.clone(), crate::object::TreeEntry {
+            mode: FileMode::Gitlink,
+            hash: ObjectHash::new(b"not in this repository"),
+        });
+
+        let index = tree.to_index(&wd, None).unwrap();
+        assert!(index.entries[&path].is_gitlink());
+
+        index.restore(&wd, &WorkPathBuf::root()).unwrap();
+        assert!(temp_dir.join("lib").is_dir());
+    }
+
+    #[test]
+    fn case_only_rename_moves_index_entry() {
+        let (temp_dir, mut wd) = temp_workdir();
+        std::fs::write(temp_dir.join("foo.txt"), "foo").unwrap();
+        wd.set_settings(crate::workdir::Settings {
+            ignore_case: true,
+            ..Default::default()
+        });
+
+        let mut index = Index::new(None);
+        insert_fake_entry(&mut index, "Foo.txt");
+        let changes = index.list_unstaged_changes(&wd, &WorkPathBuf::root(), false).unwrap();
+        assert!(matches!(
+            &changes[..],
+            [UnstagedChange::Renamed { old_path, path, .. }] if old_path.as_str() == "Foo.txt" && path.as_str() == "foo.txt"
+        ));
+
+        index.add(&wd, temp_dir.path(), false).unwrap();
+        let paths: Vec<_> = index.entries.keys().map(WorkPathBuf::as_str).collect();
+        assert_eq!(paths, ["foo.txt"]);
+    }
+
+    #[test]
+    fn nested_repo_is_listed_whole_and_not_added() {
+        let (temp_dir, wd) = temp_workdir();
+        std::fs::create_dir_all(temp_dir.join("vendor/lib/.git")).unwrap();
+        std::fs::write(temp_dir.join("vendor/lib/lib.rs"), "lib").unwrap();
+        std::fs::write(temp_dir.join("main.rs"), "main").unwrap();
+
+        let mut index = Index::new(None);
+        let changes = index.list_unstaged_changes(&wd, &WorkPathBuf::root(), false).unwrap();
+        let mut paths: Vec<_> = changes.iter()
+            .map(|change| match change {
+                UnstagedChange::Created { path, .. } => format!("created {path}"),
+                UnstagedChange::NestedRepo { path } => format!("nested {path}"),
+                _ => panic!("only untracked files are expected"),
+            })
+            .collect();
+        paths.sort();
+        assert_eq!(paths, ["created main.rs", "nested vendor/lib"]);
+
+        // Adding a directory skips the nested repository, but naming a path in it fails
+        for path in ["vendor/lib", "vendor/lib/lib.rs"] {
+            assert!(matches!(
+                index.add(&wd, temp_dir.join(path), false).unwrap_err().downcast_ref(),
+                Some(IndexError::NestedRepository(nested)) if nested.as_str() == "vendor/lib"
+            ));
+        }
+        let skipped = index.add(&wd, temp_dir.path(), false).unwrap();
+        assert_eq!(skipped, [WorkPathBuf::try_from("vendor/lib").unwrap()]);
+        let paths: Vec<_> = index.entries.keys().map(WorkPathBuf::as_str).collect();
+        assert_eq!(paths, ["main.rs"]);
+    }
+
+    #[test]
+    fn insert_entry_keeps_flags_consistent() {
+        let (temp_dir, wd) = temp_workdir();
+        std::fs::write(temp_dir.join("main.c"), "main").unwrap();
+
+        let path = WorkPathBuf::try_from("main.c").unwrap();
+        let entry = IndexEntry::from_worktree_file(&wd, &path).unwrap();
+        assert_eq!(entry.hash, crate::object::GitObject::Blob(Blob::deserialize(b"main".to_vec()).unwrap()).hash());
+        assert!(matches!(
+            entry.clone().with_stage(4).unwrap_err().downcast_ref(),
+            Some(IndexError::InvalidStage(4))
+        ));
+
+        // Moving the entry updates its name length
+        let mut index = Index::new(None);
+        index.insert_entry(path.clone(), entry);
+        let entry = index.remove_entry(&path).unwrap();
+        let new_path = WorkPathBuf::try_from("src/main.c").unwrap();
+        index.insert_entry(new_path.clone(), entry.with_stage(2).unwrap());
+        assert_eq!(index.entries[&new_path].flags.get_name_len(), 10);
+        assert_eq!(index.conflicted_paths().collect::<Vec<_>>(), [&new_path]);
+        assert_eq!(index.version, 2);
+
+        // Extended flags need version 3
+        let dir = WorkPathBuf::try_from("lib").unwrap();
+        index.insert_entry(dir.clone(), IndexEntry::sparse_dir(&dir, ObjectHash::new([1])));
+        assert_eq!(index.entries[&dir].flags.get_name_len(), 4);
+        assert_eq!(index.version, 3);
+
+        index.get_mut(&new_path).unwrap().flags.set_stage(0);
+        assert_eq!(index.conflicted_paths().count(), 0);
+    }
+
+    #[test]
+    fn add_skips_ignored_files_unless_forced() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.join(".git")).unwrap();
+        std::fs::create_dir(temp_dir.join("build")).unwrap();
+        std::fs::write(temp_dir.join(".gitignore"), "build/\n*.log\n").unwrap();
+        std::fs::write(temp_dir.join("build/out.o"), "out").unwrap();
+        std::fs::write(temp_dir.join("debug.log"), "log").unwrap();
+        std::fs::write(temp_dir.join("main.c"), "main").unwrap();
+        let wd = WorkDir::new(temp_dir.path()).unwrap();
+
+        let mut index = Index::new(None);
+        index.add(&wd, temp_dir.path(), false).unwrap();
+        let paths: Vec<_> = index.entries.keys().map(WorkPathBuf::as_str).collect();
+        assert_eq!(paths, [".gitignore", "main.c"]);
+
+        let err = index.add(&wd, temp_dir.join("build"), false).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(IndexError::PathIgnored(paths)) if paths[0].as_str() == "build"));
+
+        index.add(&wd, temp_dir.join("debug.log"), true).unwrap();
+        assert!(index.entries.contains_key(&WorkPathBuf::try_from("debug.log").unwrap()));
+
+        // Once tracked, an ignored file can be updated without -f
+        std::fs::write(temp_dir.join("debug.log"), "more log").unwrap();
+        index.add(&wd, temp_dir.join("debug.log"), false).unwrap();
+    }
+
+    #[test]
+    fn add_tracked_skips_untracked_files() {
+        let (temp_dir, wd) = temp_workdir();
+        std::fs::create_dir(temp_dir.join("src")).unwrap();
+        std::fs::write(temp_dir.join("src/lib.rs"), "lib").unwrap();
+        std::fs::write(temp_dir.join("src/new.rs"), "new").unwrap();
+
+        let mut index = Index::new(None);
+        let mut head = Index::new(None);
+        insert_fake_entry(&mut index, "src/lib.rs");
+        insert_fake_entry(&mut head, "src/gone.rs");
+
+        let files = index.add_tracked(&wd, &head, &WorkPathBuf::try_from("src").unwrap()).unwrap();
+        let files: Vec<_> = files.iter().map(WorkPathBuf::as_str).collect();
+        assert_eq!(files, ["src/gone.rs", "src/lib.rs"]);
+        let paths: Vec<_> = index.entries.keys().map(WorkPathBuf::as_str).collect();
+        assert_eq!(paths, ["src/lib.rs"]);
+        assert_ne!(index.entries[&WorkPathBuf::try_from("src/lib.rs").unwrap()].hash, fake_entry().hash);
+
+        assert!(index.add_tracked(&wd, &head, &WorkPathBuf::try_from("docs").unwrap()).is_err());
+    }
+
+    #[test]
+    fn symlink_keeps_its_mode_without_core_symlinks() {
+        let (temp_dir, mut wd) = temp_workdir();
+        wd.set_settings(crate::workdir::Settings {
+            symlinks: false,
+            ..Default::default()
+        });
+
+        let blob = crate::object::GitObject::Blob(Blob::deserialize(b"target.txt".to_vec()).unwrap());
+        let entry = crate::object::TreeEntry { mode: FileMode::Symlink, hash: blob.write(&wd).unwrap() };
+        let path = WorkPathBuf::try_from("link").unwrap();
+        let mut index = Index::new(None);
+        index.checkout_changes(&wd, &[crate::object::TreeChange::Added { path: path.clone(), entry }]).unwrap();
+
+        assert!(std::fs::symlink_metadata(temp_dir.join("link")).unwrap().is_file());
+        assert_eq!(std::fs::read(temp_dir.join("link")).unwrap(), b"target.txt");
+        assert_eq!(index.entries[&path].stats.get_mode().unwrap(), FileMode::Symlink);
+
+        // Touching the file makes its stats differ, but it's still the same symlink
+        std::fs::write(temp_dir.join("link"), "target.txt").unwrap();
+        assert!(index.list_unstaged_changes(&wd, &WorkPathBuf::root(), false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sparse_checkout_limits_status_and_add() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.join(".git/info")).unwrap();
+        std::fs::write(temp_dir.join(".git/info/sparse-checkout"), "/*\n!/*/\n/src/\n").unwrap();
+        std::fs::create_dir_all(temp_dir.join("src")).unwrap();
+        std::fs::create_dir_all(temp_dir.join("docs")).unwrap();
+        std::fs::write(temp_dir.join("src/lib.rs"), "lib").unwrap();
+        std::fs::write(temp_dir.join("docs/new.md"), "new").unwrap();
+        let mut wd = WorkDir::new(temp_dir.path()).unwrap();
+        wd.set_settings(crate::workdir::Settings {
+            sparse_checkout: true,
+            sparse_checkout_cone: true,
+            ..Default::default()
+        });
+
+        // A tracked file outside of the cone isn't expected to be there
+        let mut index = Index::new(None);
+        insert_fake_entry(&mut index, "docs/guide.md");
+        let changes = index.list_unstaged_changes(&wd, &WorkPathBuf::root(), false).unwrap();
+        assert!(!changes.iter().any(|change| matches!(change, UnstagedChange::Deleted { .. })));
+
+        let err = index.add(&wd, temp_dir.join("docs"), false).unwrap_err();
+        assert!(matches!(
+            err.downcast::<IndexError>().unwrap(),
+            IndexError::OutsideSparseCheckout(paths) if paths == [WorkPathBuf::try_from("docs/new.md").unwrap()]
+        ));
+
+        index.add(&wd, temp_dir.join("src"), false).unwrap();
+        let paths: Vec<_> = index.entries.keys().map(WorkPathBuf::as_str).collect();
+        assert_eq!(paths, ["docs/guide.md", "src/lib.rs"]);
+
+        // Without the sparse checkout, the missing file counts as deleted
+        index.add_with(&wd, temp_dir.join("docs"), false, &SparseCheckout::all()).unwrap();
+        let paths: Vec<_> = index.entries.keys().map(WorkPathBuf::as_str).collect();
+        assert_eq!(paths, ["docs/new.md", "src/lib.rs"]);
+    }
 }
@@ -0,0 +1,134 @@
+use std::{collections::BTreeSet, fs};
+
+use crate::{
+    Result,
+    object::{Blob, Tree, TreeChange, TreeEntry},
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+};
+
+use super::{Index, IndexEntry, EntryFlags, FileStats, StagedChange, UnstagedChange};
+
+impl Index {
+    /// Applies `changes` (usually the diff between the current commit and the one being
+    /// checked out) to the working directory and the index. Paths that aren't mentioned
+    /// are left alone, so their files and index stats are preserved.
+    ///
+    /// Paths outside of the sparse checkout are only updated in the index.
+    pub fn checkout_changes(&mut self, wd: &WorkDir, changes: &[TreeChange]) -> Result<()> {
+        // A path that can't be written fails the checkout before anything has changed
+        for change in changes {
+            if let TreeChange::Added { path, .. } | TreeChange::Modified { path, .. } = change {
+                wd.check_worktree_path(path)?;
+            }
+        }
+
+        // Deletions go first, in case a file is being replaced by a directory or vice versa
+        for change in changes {
+            if let TreeChange::Deleted { path } = change {
+                let skip_worktree = self.prepare_checkout_path(wd, path)?;
+                self.remove_entry(path);
+
+                if !skip_worktree {
+                    wd.remove_tracked([path])?;
+                }
+            }
+        }
+
+        for change in changes {
+            match change {
+                TreeChange::Added { path, entry } | TreeChange::Modified { path, entry } => {
+                    let skip_worktree = self.prepare_checkout_path(wd, path)?;
+                    let index_entry = match skip_worktree {
+                        true => Self::sparse_child(path, entry),
+                        false => Self::checkout_file(wd, path, entry)?,
+                    };
+                    self.insert_entry(path.to_owned(), index_entry);
+                },
+                TreeChange::Deleted { .. } => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Discards every staged and unstaged change to tracked files, including conflicts, so
+    /// that the index and working directory match `tree`. Untracked files are left alone.
+    pub fn reset_to_tree(&mut self, wd: &WorkDir, tree: &Tree) -> Result<()> {
+        let root = WorkPathBuf::root();
+        let mut paths: BTreeSet<WorkPathBuf> = self.conflicted_paths().cloned().collect();
+
+        for change in self.list_changes_from_tree(wd, Some(tree), &root)? {
+            match change {
+                StagedChange::Created { path }
+                | StagedChange::Deleted { path }
+                | StagedChange::Modified { path } => paths.insert(path),
+            };
+        }
+        for change in self.list_unstaged_changes(wd, &root, false)? {
+            match change {
+                UnstagedChange::Created { .. } | UnstagedChange::NestedRepo { .. } => (),
+                UnstagedChange::Deleted { path } | UnstagedChange::Modified { path, .. } => {
+                    paths.insert(path);
+                },
+                UnstagedChange::Renamed { old_path, path, .. } => {
+                    paths.insert(old_path);
+                    paths.insert(path);
+                },
+            }
+        }
+
+        let changes = paths.into_iter()
+            .map(|path| Ok(match tree.find_entry(wd, &path)? {
+                Some(entry) => TreeChange::Modified { path, entry },
+                None => TreeChange::Deleted { path },
+            }))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.checkout_changes(wd, &changes)
+    }
+
+    /// Expands any sparse directories containing `path`. Returns true if `path` is outside
+    /// of the sparse checkout.
+    fn prepare_checkout_path(&mut self, wd: &WorkDir, path: &WorkPath) -> Result<bool> {
+        let in_sparse_dir = self.has_sparse_ancestor(path);
+        self.expand_to_path(wd, path)?;
+
+        let skip_worktree = in_sparse_dir
+            || self.entries.get(path).is_some_and(|entry| entry.flags.get_skip_worktree());
+
+        Ok(skip_worktree)
+    }
+
+    /// Writes the blob `entry` to the working directory at `path` and returns the
+    /// corresponding index entry.
+    fn checkout_file(wd: &WorkDir, path: &WorkPath, entry: &TreeEntry) -> Result<IndexEntry> {
+        let abs_path = wd.abs_path(path);
+        if let Some(dir_path) = abs_path.parent() {
+            fs::create_dir_all(dir_path)?;
+        }
+        if entry.is_gitlink() {
+            // Submodules aren't supported, so only a placeholder directory is created
+            if abs_path.is_file() {
+                wd.remove_path(path)?;
+            }
+            wd.create_worktree_dir(path)?;
+            return Ok(IndexEntry::gitlink(path, entry.hash));
+        }
+        if abs_path.is_dir() {
+            wd.remove_path(path)?;
+        }
+
+        let blob = Blob::read(wd, &entry.hash)?;
+        wd.write_worktree_file(path, &blob.serialize_into(), entry.mode)?;
+
+        // The tree's mode is recorded even if the file system can't show it
+        let mut stats = FileStats::from_metadata(&fs::symlink_metadata(&abs_path)?)?;
+        stats.mode = entry.mode.bits();
+
+        Ok(IndexEntry {
+            stats,
+            hash: entry.hash,
+            flags: EntryFlags::new(path.to_bytes()),
+        })
+    }
+}
@@ -1,13 +1,16 @@
 use std::{
-    collections::HashSet,
-    fs::File
+    collections::{HashMap, HashSet},
+    fs::{File, Metadata},
 };
 
 use crate::{
     Result,
-    index::{Index, FileStats},
+    index::{Index, IndexEntry, IndexError, FileStats},
+    ignore::IgnoreRules,
+    sparse_checkout::SparseCheckout,
+    trace::{self, Category},
     workdir::{WorkDir, WorkPathBuf, WorkPath},
-    object::{GitObject, ObjectHash, Tree, ObjectFormat, TreeEntry},
+    object::{GitObject, ObjectHash, Tree, ObjectFormat, TreeEntry, Blob, FileMode},
 };
 
 /// A change to a file in the working directory relative to the index.
@@ -25,6 +28,19 @@ pub enum UnstagedChange {
         stats: FileStats,
         hash: ObjectHash,
     },
+    /// A tracked file whose name only changed case. These are only detected when
+    /// `core.ignoreCase` is set.
+    Renamed {
+        old_path: WorkPathBuf,
+        path: WorkPathBuf,
+        stats: FileStats,
+        hash: ObjectHash,
+    },
+    /// An untracked directory with its own repository, which is listed as a whole instead
+    /// of file by file.
+    NestedRepo {
+        path: WorkPathBuf,
+    },
 }
 
 /// A change to a file in the index relative to a commit.
@@ -59,18 +75,26 @@ impl Index {
 
     /// Compares the index to the file or directory at `path` and enumerates the differences.
     /// If `write` is true, new/modified files will be stored in the repo at `wd`.
+    /// Untracked files that are ignored aren't listed, and neither are tracked files outside
+    /// of the sparse checkout.
     pub fn list_unstaged_changes(&self, wd: &WorkDir, path: &WorkPathBuf, write: bool) -> Result<Vec<UnstagedChange>> {
-        // cd to the working directory to reduce the amount of path manipulation required
-        let prev_working_dir = std::env::current_dir()?;
-        std::env::set_current_dir(wd.as_path())?;
+        self.list_unstaged_changes_with(wd, path, write, &mut IgnoreRules::load(wd)?, &SparseCheckout::load(wd)?)
+    }
+
+    /// Like [`Index::list_unstaged_changes`], but with `ignores` deciding which untracked
+    /// files are left out and `sparse` deciding which tracked files are.
+    pub(super) fn list_unstaged_changes_with(&self, wd: &WorkDir, path: &WorkPathBuf, write: bool, ignores: &mut IgnoreRules, sparse: &SparseCheckout) -> Result<Vec<UnstagedChange>> {
+        let _timer = trace::start(Category::DirWalk);
 
         // Create a "checklist" of matching paths in the index to mark off as they are found in the file system
+        // Entries outside of the sparse checkout aren't expected to be there
         let mut expected = self.expected_keys_for_path(path);
+        expected.retain(|key| !self.entries[*key].flags.get_skip_worktree() && sparse.contains(key));
         let mut changes = vec![];
 
         // Compare to the file system
         if path.is_empty() {
-            for entry in std::fs::read_dir(".")? {
+            for entry in std::fs::read_dir(wd.as_path())? {
                 let path = match WorkPathBuf::try_from(entry?.file_name()) {
                     Ok(val) => val,
                     Err(err) => match err.downcast_ref::<crate::workdir::WorkDirError>() {
@@ -78,11 +102,11 @@ impl Index {
                         Some(_) | None => return Err(err),
                     },
                 };
-                self.unstaged_compare_path(wd, path, &mut changes, &mut expected, write)?;
+                self.unstaged_compare_path(wd, path, &mut changes, &mut expected, write, ignores)?;
             }
         }
         else {
-            self.unstaged_compare_path(wd, path.clone(), &mut changes, &mut expected, write)?;
+            self.unstaged_compare_path(wd, path.clone(), &mut changes, &mut expected, write, ignores)?;
         }
         
         // Any files that we didn't see while enumerating the file system must have been deleted
@@ -93,64 +117,159 @@ impl Index {
             changes.extend(deletions);
         }
 
-        // Don't forget to restore the original working directory
-        std::env::set_current_dir(prev_working_dir)?;
+        if wd.settings().ignore_case {
+            changes = Self::detect_case_renames(changes);
+        }
 
         Ok(changes)
     }
 
     /// Lists new/modified file(s) at `path`, appending them to `changes` and removing them from `expected`.
-    fn unstaged_compare_path(&self, wd: &WorkDir, path: WorkPathBuf, changes: &mut Vec<UnstagedChange>, expected: &mut HashSet<&WorkPathBuf>, write: bool) -> Result<()> {
+    fn unstaged_compare_path(&self, wd: &WorkDir, path: WorkPathBuf, changes: &mut Vec<UnstagedChange>, expected: &mut HashSet<&WorkPathBuf>, write: bool, ignores: &mut IgnoreRules) -> Result<()> {
         if self.is_path_ignored(&path) {
             return Ok(());
         }
 
-        if path.as_ref().is_file() {
+        // Anything inside a sparse directory is outside of the sparse checkout
+        if self.entries.get(&path).is_some_and(IndexEntry::is_sparse_dir) {
+            return Ok(());
+        }
+
+        // The contents of a submodule belong to its own repository
+        if self.entries.get(&path).is_some_and(IndexEntry::is_gitlink) {
+            expected.remove(&path);
+            return Ok(());
+        }
+
+        // Symlinks count as files, whatever they point to
+        let metadata = std::fs::symlink_metadata(wd.abs_path(&path)).ok();
+        let is_file = metadata.as_ref().is_some_and(|meta| meta.is_file() || meta.is_symlink());
+        let is_dir = metadata.as_ref().is_some_and(|meta| meta.is_dir());
+
+        if is_file {
+            // Tracked files are never ignored, but ones outside of the sparse checkout were
+            // left off the checklist and aren't compared
+            let is_tracked = self.entries.contains_key(&path);
+            if (is_tracked && !expected.contains(&path)) || (!is_tracked && ignores.is_ignored(&path, false)?) {
+                return Ok(());
+            }
+
             // Mark this path seen and compare to the index
             expected.remove(&path);
             if let Some(change) = self.unstaged_compare_file(wd, &path, write)? {
                 changes.push(change);
             }
         }
-        else if path.as_ref().is_dir() {
+        else if is_dir {
+            // An ignored directory still has to be searched for tracked files
+            if self.entries_in_dir(&path).next().is_none() && ignores.is_ignored(&path, true)? {
+                return Ok(());
+            }
+
+            if self.is_nested_repo(wd, &path) {
+                changes.push(UnstagedChange::NestedRepo { path });
+                return Ok(());
+            }
+
             // Recurse on each path in the directory
-            for entry in std::fs::read_dir(path)? {
-                let path = WorkPathBuf::try_from(entry?.path())?;
-                self.unstaged_compare_path(wd, path, changes, expected, write)?;
+            for entry in std::fs::read_dir(wd.abs_path(&path))? {
+                // A .git in a subdirectory belongs to a submodule or nested repository
+                let entry = entry?;
+                if entry.file_name() == ".git" {
+                    continue;
+                }
+
+                let path = path.join(&WorkPathBuf::try_from(entry.file_name())?);
+                self.unstaged_compare_path(wd, path, changes, expected, write, ignores)?;
             }
         }
 
         Ok(())
     }
 
+    /// Returns true if `dir` is a nested repository: a directory below the root with its own
+    /// `.git`, which this index doesn't track as a submodule or track any files in (as it
+    /// would if the directory was committed before it became a repository).
+    fn is_nested_repo(&self, wd: &WorkDir, dir: &WorkPath) -> bool {
+        !dir.is_empty()
+            && wd.abs_path(dir).join(".git").exists()
+            && !self.entries.contains_key(dir)
+            && self.entries_in_dir(dir).next().is_none()
+    }
+
+    /// Returns the outermost nested repository (see [`UnstagedChange::NestedRepo`]) that
+    /// `path` is in or is, if any.
+    pub fn nested_repo_containing(&self, wd: &WorkDir, path: &WorkPath) -> Option<WorkPathBuf> {
+        let mut nested = None;
+        let mut dir = Some(path);
+        while let Some(current) = dir {
+            if self.is_nested_repo(wd, current) {
+                nested = Some(current.to_owned());
+            }
+            dir = current.parent();
+        }
+
+        nested
+    }
+
+    /// Pairs each created file with a deleted one whose path only differs in case. On a
+    /// case-insensitive file system, these are the same file, renamed (e.g. from `Foo.txt`
+    /// to `foo.txt`).
+    fn detect_case_renames(changes: Vec<UnstagedChange>) -> Vec<UnstagedChange> {
+        let mut deleted: HashMap<String, WorkPathBuf> = changes.iter()
+            .filter_map(|change| match change {
+                UnstagedChange::Deleted { path } => Some((path.as_str().to_lowercase(), path.clone())),
+                _ => None,
+            })
+            .collect();
+        let mut renamed = HashSet::new();
+
+        let changes: Vec<_> = changes.into_iter()
+            .map(|change| match change {
+                UnstagedChange::Created { path, stats, hash } => {
+                    match deleted.remove(&path.as_str().to_lowercase()) {
+                        Some(old_path) => {
+                            renamed.insert(old_path.clone());
+                            UnstagedChange::Renamed { old_path, path, stats, hash }
+                        },
+                        None => UnstagedChange::Created { path, stats, hash },
+                    }
+                },
+                change => change,
+            })
+            .collect();
+
+        changes.into_iter()
+            .filter(|change| !matches!(change, UnstagedChange::Deleted { path } if renamed.contains(path)))
+            .collect()
+    }
+
     /// Determines if the file at `path` is new or has been modified.
     fn unstaged_compare_file(&self, wd: &WorkDir, path: &WorkPath, write: bool) -> Result<Option<UnstagedChange>> {
-        let file = File::open(path)?;
-        let stats = FileStats::from_file(&file)?;
+        let metadata = std::fs::symlink_metadata(wd.abs_path(path))?;
+        let mut stats = FileStats::from_metadata(&metadata)?;
 
         if let Some(entry) = self.entries.get(path) {
             // File already exists in the index
+            stats.mode = Self::worktree_mode(entry, &stats, wd);
 
-            // We can skip it if its stats haven't changed, or if
-            // it's been explicitly marked valid by the user
-            if entry.flags.get_assume_valid()
-                || stats == entry.stats
+            // We can skip it if its stats haven't changed, if it's been
+            // explicitly marked valid by the user, or if it's outside
+            // of the sparse checkout. A conflicted file always needs adding.
+            let conflicted = entry.flags.get_stage() != 0;
+            if !conflicted && (entry.flags.get_assume_valid()
+                || entry.flags.get_skip_worktree()
+                || entry.stats.matches(&stats, wd.settings()))
             {
                 return Ok(None);
             }
 
             // The stats have changed, so we'll check the file's contents
-            let object = GitObject::from_stream(file, ObjectFormat::Blob)?;
-            let hash = if write {
-                object.write(wd)?
-            }
-            else {
-                object.hash()
-            };
+            let hash = Self::hash_worktree_file(wd, path, &metadata, write)?;
             
             // Even if the stats are different, this file doesn't count if its
             // contents haven't changed
-            if hash == entry.hash {
+            if hash == entry.hash && !conflicted {
                 return Ok(None);
             }
 
@@ -163,14 +282,7 @@ impl Index {
         }
         else {
             // New file
-
-            let object = GitObject::from_stream(file, ObjectFormat::Blob)?;
-            let hash = if write {
-                object.write(wd)?
-            }
-            else {
-                object.hash()
-            };
+            let hash = Self::hash_worktree_file(wd, path, &metadata, write)?;
 
             Ok(Some(UnstagedChange::Created {
                 path: path.to_owned(),
@@ -180,22 +292,75 @@ impl Index {
         }
     }
 
+    /// Returns the mode to record for the file described by `stats`, which `entry` tracks.
+    /// The working directory can't show every mode: the executable bit isn't read from
+    /// the file system, and without `core.symlinks`, a symlink is checked out as a file
+    /// containing its target. In those cases, the entry's mode is kept.
+    fn worktree_mode(entry: &IndexEntry, stats: &FileStats, wd: &WorkDir) -> u32 {
+        match (entry.stats.get_mode(), stats.get_mode()) {
+            (Ok(FileMode::Executable), Ok(FileMode::Regular)) => entry.stats.mode,
+            (Ok(FileMode::Symlink), Ok(FileMode::Regular)) if !wd.settings().symlinks => entry.stats.mode,
+            _ => stats.mode,
+        }
+    }
+
+    /// Computes the blob hash of the file at `path`, which `metadata` describes. A symlink
+    /// is hashed as its target. If `write` is true, the blob is also stored in the repo.
+    pub(super) fn hash_worktree_file(wd: &WorkDir, path: &WorkPath, metadata: &Metadata, write: bool) -> Result<ObjectHash> {
+        if !metadata.is_symlink() {
+            return Self::hash_file(wd, File::open(wd.abs_path(path))?, write);
+        }
+
+        let object = GitObject::Blob(Blob::deserialize(wd.read_worktree_file(path)?)?);
+        match write {
+            true => object.write(wd),
+            false => Ok(object.hash()),
+        }
+    }
+
+    /// Computes the blob hash of `file`. If `write` is true, the blob is also stored in the repo.
+    /// 
+    /// Files larger than `core.bigFileThreshold` are streamed rather than read into memory.
+    pub(super) fn hash_file(wd: &WorkDir, mut file: File, write: bool) -> Result<ObjectHash> {
+        if file.metadata()?.len() > wd.settings().big_file_threshold {
+            return Blob::stream_file(wd, &mut file, write);
+        }
+
+        let object = GitObject::from_stream(file, ObjectFormat::Blob)?;
+        if write {
+            object.write(wd)
+        }
+        else {
+            Ok(object.hash())
+        }
+    }
+
     /// Compares the index to the commit tree identified by `commit_hash` and enumerates the differences.
     /// 
     /// If `commit_hash` is `None`, all entries in the index will be considered created.
     pub fn list_staged_changes(&self, wd: &WorkDir, commit_hash: Option<&ObjectHash>, path: &WorkPathBuf) -> Result<Vec<StagedChange>> {
-        // Create a "checklist" of matching paths in the index to mark off as they are found in the commit tree
+        let root_tree = commit_hash
+            .map(|commit_hash| Tree::read_from_commit(wd, commit_hash))
+            .transpose()?;
+
+        self.list_changes_from_tree(wd, root_tree.as_ref(), path)
+    }
+
+    /// Compares the index to `root_tree` and enumerates the differences, as with
+    /// [`Index::list_staged_changes`], but with any tree as the base.
+    ///
+    /// If `root_tree` is `None`, all entries in the index will be considered created.
+    pub fn list_changes_from_tree(&self, wd: &WorkDir, root_tree: Option<&Tree>, path: &WorkPathBuf) -> Result<Vec<StagedChange>> {
+        // Create a "checklist" of matching paths in the index to mark off as they are found in the tree
         let mut expected = self.expected_keys_for_path(path);
         let mut changes = vec![];
 
-        if let Some(commit_hash) = commit_hash {
-            let root_tree = Tree::read_from_commit(wd, commit_hash)?;
-
+        if let Some(root_tree) = root_tree {
             // If no path was provided, start at the root. Otherwise, find the tree that contains
             // the entry associated with that path
             if path.is_empty() {
-                for (name, entry) in root_tree.entries {
-                    self.staged_compare_path(wd, (name, &entry), &mut changes, &mut expected)?;
+                for (name, entry) in &root_tree.entries {
+                    self.staged_compare_path(wd, (name.clone(), entry), &mut changes, &mut expected)?;
                 }
             }
             else if let Some(entry) = root_tree.find_entry(wd, path)? {
@@ -216,11 +381,43 @@ impl Index {
         Ok(changes)
     }
 
+    /// Updates the hashes of the entries under `path` to match the files in the working
+    /// directory, and removes the entries whose files were deleted, as if every tracked file
+    /// had been added. The index can then stand in for the working tree, e.g. to compare it
+    /// to a commit. Untracked files are left out, and no objects are written.
+    pub fn apply_worktree_changes(&mut self, wd: &WorkDir, path: &WorkPathBuf) -> Result<()> {
+        for change in self.list_unstaged_changes(wd, path, false)? {
+            match change {
+                UnstagedChange::Created { .. } | UnstagedChange::NestedRepo { .. } => (),
+                UnstagedChange::Deleted { path } => {
+                    self.remove_entry(&path);
+                },
+                UnstagedChange::Modified { path, hash, .. }
+                | UnstagedChange::Renamed { old_path: path, hash, .. } => {
+                    if let Some(entry) = self.entries.get_mut(&path) {
+                        entry.hash = hash;
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
     /// Lists new/modified file(s) in `tree_entry`, appending them to `changes` and removing them from `expected`.
     /// `path` is the path to this entry relative to the working directory. `tree_entry` may represent a file (blob)
     /// or a directory (tree).
     fn staged_compare_path(&self, wd: &WorkDir, (path, tree_entry): (WorkPathBuf, &TreeEntry), changes: &mut Vec<StagedChange>, expected: &mut HashSet<&WorkPathBuf>) -> Result<()> {
-        if tree_entry.is_dir() {
+        let sparse_entry = self.entries.get(&path).filter(|entry| entry.is_sparse_dir());
+
+        if let (true, Some(sparse_entry)) = (tree_entry.is_dir(), sparse_entry) {
+            // A sparse directory can be compared without reading the subtree
+            expected.remove(&path);
+            if sparse_entry.hash != tree_entry.hash {
+                changes.push(StagedChange::Modified { path });
+            }
+        }
+        else if tree_entry.is_dir() {
             // Load the subtree from the repo and recurse on each entry
             let subtree = Tree::read(wd, &tree_entry.hash)?;
             for (name, entry) in subtree.entries {
@@ -261,10 +458,84 @@ impl Index {
         }
     }
 
-    /// Determines if `path` should be excluded from the index.
-    /// 
-    /// Currently, this just ignores files or directories named .git, but eventually
-    /// it should observe the repo's .gitignore file.
+    /// Finds untracked files in the working directory that would be overwritten (or deleted)
+    /// by restoring `target` from `tree`. Files whose contents already match `tree` are not
+    /// counted.
+    pub fn list_untracked_collisions(&self, wd: &WorkDir, tree: &Tree, target: &WorkPath) -> Result<Vec<WorkPathBuf>> {
+        let mut collisions = vec![];
+
+        if target.is_empty() {
+            self.untracked_collisions_in_tree(wd, tree, target, &mut collisions)?;
+        }
+        else if let Some(entry) = tree.find_entry(wd, target)? {
+            self.untracked_collisions_at(wd, target.to_owned(), &entry, &mut collisions)?;
+        }
+
+        Ok(collisions)
+    }
+
+    /// Returns an error listing the untracked files that would be overwritten by restoring
+    /// each of `targets` from `tree`, if there are any.
+    pub fn check_untracked_collisions(&self, wd: &WorkDir, tree: &Tree, targets: &[WorkPathBuf]) -> Result<()> {
+        let mut collisions = vec![];
+        for target in targets {
+            collisions.extend(self.list_untracked_collisions(wd, tree, target)?);
+        }
+
+        if !collisions.is_empty() {
+            return Err(IndexError::UntrackedWouldBeOverwritten(collisions).into());
+        }
+
+        Ok(())
+    }
+
+    /// Lists collisions for each entry in `tree`, which will be restored at `prefix`.
+    fn untracked_collisions_in_tree(&self, wd: &WorkDir, tree: &Tree, prefix: &WorkPath, collisions: &mut Vec<WorkPathBuf>) -> Result<()> {
+        for (name, entry) in &tree.entries {
+            let path = prefix.to_owned().join(name);
+            self.untracked_collisions_at(wd, path, entry, collisions)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lists collisions for the file or directory `tree_entry`, which will be restored at `path`.
+    fn untracked_collisions_at(&self, wd: &WorkDir, path: WorkPathBuf, tree_entry: &TreeEntry, collisions: &mut Vec<WorkPathBuf>) -> Result<()> {
+        let abs_path = wd.abs_path(&path);
+
+        if abs_path.is_dir() {
+            if tree_entry.is_gitlink() {
+                // A submodule's directory is left as it is
+            }
+            else if tree_entry.is_dir() {
+                let subtree = Tree::read(wd, &tree_entry.hash)?;
+                self.untracked_collisions_in_tree(wd, &subtree, &path, collisions)?;
+            }
+            else {
+                // The directory will be replaced by a file, taking any untracked files with it
+                let untracked = self.list_unstaged_changes(wd, &path, false)?
+                    .into_iter()
+                    .filter_map(|change| match change {
+                        UnstagedChange::Created { path, .. } | UnstagedChange::NestedRepo { path } => Some(path),
+                        _ => None,
+                    });
+                collisions.extend(untracked);
+            }
+        }
+        else if abs_path.is_file() && !self.entries.contains_key(&path) {
+            let is_same_file = !tree_entry.is_dir()
+                && Self::hash_file(wd, File::open(&abs_path)?, false)? == tree_entry.hash;
+
+            if !is_same_file {
+                collisions.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Determines if `path` can never be added to the index. Paths matched by ignore rules
+    /// are handled separately, since they can still be tracked.
     fn is_path_ignored(&self, path: &WorkPath) -> bool {
         path.file_name() == ".git"
     }
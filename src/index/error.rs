@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::{object::ObjectHash, workdir::WorkPathBuf};
+
 #[derive(Error, Debug)]
 pub enum IndexError {
     #[error("Index is corrupt: {problem}")]
@@ -10,6 +12,31 @@ pub enum IndexError {
     UnsupportedVersion(u32),
     #[error("There are uncommited changes in the index or working directory")]
     UncommittedChanges,
+    #[error("`{0}` did not match any tracked files")]
+    PathNotTracked(WorkPathBuf),
     #[error("An empty index cannot be saved.")]
     EmptyIndex,
+    #[error("The shared index file for {0} is missing")]
+    SharedIndexMissing(ObjectHash),
+    #[error("Invalid merge stage {0} (must be 0-3)")]
+    InvalidStage(u16),
+    #[error("`{0}` is not a sparse directory")]
+    NotSparseDir(WorkPathBuf),
+    #[error(
+        "The following untracked working tree files would be overwritten:\n{}\nPlease move or remove them first.",
+        .0.iter().map(|path| format!("    {path}")).collect::<Vec<_>>().join("\n")
+    )]
+    UntrackedWouldBeOverwritten(Vec<WorkPathBuf>),
+    #[error(
+        "The following paths are ignored by one of your .gitignore files:\n{}\nUse -f if you really want to add them.",
+        .0.iter().map(|path| path.as_str()).collect::<Vec<_>>().join("\n")
+    )]
+    PathIgnored(Vec<WorkPathBuf>),
+    #[error(
+        "The following paths are outside of your sparse-checkout definition, so they will not be updated in the index:\n{}\nUse --sparse if you really want to add them.",
+        .0.iter().map(|path| path.as_str()).collect::<Vec<_>>().join("\n")
+    )]
+    OutsideSparseCheckout(Vec<WorkPathBuf>),
+    #[error("`{0}` is a separate repository, so its files can't be added. To record it as a submodule, use `git submodule add`, or ignore it in .gitignore.")]
+    NestedRepository(WorkPathBuf),
 }
@@ -0,0 +1,206 @@
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+use crate::Result;
+
+use super::IndexError;
+
+/// A compressed bitmap in git's EWAH format, used by index extensions to mark
+/// positions in another list of entries.
+///
+/// See [the git docs](https://github.com/git/git/blob/master/Documentation/technical/bitmap-format.txt)
+/// for a description of the encoding.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct EwahBitmap {
+    bits: Vec<bool>,
+}
+
+/// Number of bits used to store the running length in a marker word.
+const RUNNING_LEN_BITS: u32 = 32;
+const RUNNING_LEN_MASK: u64 = (1 << RUNNING_LEN_BITS) - 1;
+
+impl EwahBitmap {
+    /// Constructs a bitmap of `len` bits, all of which are unset.
+    pub fn new(len: usize) -> Self {
+        Self { bits: vec![false; len] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    pub fn get(&self, pos: usize) -> bool {
+        self.bits.get(pos).copied().unwrap_or(false)
+    }
+
+    pub fn set(&mut self, pos: usize) {
+        if pos >= self.bits.len() {
+            self.bits.resize(pos + 1, false);
+        }
+        self.bits[pos] = true;
+    }
+
+    /// Returns the number of set bits.
+    pub fn count_ones(&self) -> usize {
+        self.bits.iter().filter(|bit| **bit).count()
+    }
+
+    /// Parses a bitmap from the start of `data`. Returns the bitmap and the number
+    /// of bytes consumed.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize)> {
+        let corrupt = |problem: &str| IndexError::Corrupt {
+            problem: format!("invalid ewah bitmap ({problem})"),
+        };
+
+        if data.len() < 8 {
+            return Err(corrupt("too short").into());
+        }
+
+        let bit_len = BigEndian::read_u32(&data[0..4]) as usize;
+        let word_count = BigEndian::read_u32(&data[4..8]) as usize;
+        let total_len = word_count.checked_mul(8)
+            .and_then(|len| len.checked_add(12))
+            .ok_or_else(|| corrupt("too many words"))?;
+
+        if data.len() < total_len {
+            return Err(corrupt("truncated").into());
+        }
+
+        let mut words = data[8..8 + word_count * 8]
+            .chunks_exact(8)
+            .map(BigEndian::read_u64);
+
+        // Literal words hold 64 bits each, so the words present bound what's needed up front.
+        // Only runs can ask for more, and those are reserved as they're found.
+        let mut bits = Vec::with_capacity(bit_len.min(word_count * 64));
+        while let Some(marker) = words.next() {
+            let run_bit = (marker & 1) != 0;
+            let run_len = ((marker >> 1) & RUNNING_LEN_MASK) as usize;
+            let literal_count = (marker >> (1 + RUNNING_LEN_BITS)) as usize;
+
+            let run_bits = run_len.checked_mul(64)
+                .filter(|len| bits.len() + len <= bit_len + 64)
+                .ok_or_else(|| corrupt("run exceeds bitmap length"))?;
+            bits.try_reserve(run_bits).map_err(|_| corrupt("run is too long to hold"))?;
+            bits.resize(bits.len() + run_bits, run_bit);
+
+            for _ in 0..literal_count {
+                let word = words.next().ok_or_else(|| corrupt("missing literal word"))?;
+                bits.extend((0..64).map(|i| word & (1 << i) != 0));
+            }
+        }
+
+        if bits.len() < bit_len {
+            return Err(corrupt("words don't cover bitmap length").into());
+        }
+        bits.truncate(bit_len);
+
+        Ok((Self { bits }, total_len))
+    }
+
+    /// Converts the bitmap into a sequence of bytes.
+    ///
+    /// The encoding used is simple rather than compact: a single marker word
+    /// followed by every word as a literal.
+    pub fn serialize(&self) -> Vec<u8> {
+        let literals: Vec<u64> = self.bits
+            .chunks(64)
+            .map(|chunk| chunk.iter()
+                .enumerate()
+                .filter(|(_, bit)| **bit)
+                .fold(0u64, |word, (i, _)| word | (1 << i)))
+            .collect();
+
+        let marker = (literals.len() as u64) << (1 + RUNNING_LEN_BITS);
+
+        let mut data = Vec::with_capacity(12 + 8 * (literals.len() + 1));
+        data.write_u32::<BigEndian>(self.bits.len() as u32).unwrap();
+        data.write_u32::<BigEndian>(literals.len() as u32 + 1).unwrap();
+        data.write_u64::<BigEndian>(marker).unwrap();
+        for word in literals {
+            data.write_u64::<BigEndian>(word).unwrap();
+        }
+        data.write_u32::<BigEndian>(0).unwrap(); // position of the last marker word
+
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut bitmap = EwahBitmap::new(150);
+        bitmap.set(0);
+        bitmap.set(63);
+        bitmap.set(64);
+        bitmap.set(149);
+
+        let data = bitmap.serialize();
+        let (parsed, len) = EwahBitmap::parse(&data).unwrap();
+
+        assert_eq!(len, data.len());
+        assert_eq!(parsed, bitmap);
+        assert_eq!(parsed.count_ones(), 4);
+    }
+
+    #[test]
+    fn parses_running_words() {
+        // 130 bits: a run of 2 words of ones, then 1 literal word with bit 1 set
+        let mut data = vec![];
+        data.write_u32::<BigEndian>(130).unwrap();
+        data.write_u32::<BigEndian>(2).unwrap();
+        data.write_u64::<BigEndian>((1 << 33) | (2 << 1) | 1).unwrap();
+        data.write_u64::<BigEndian>(0b10).unwrap();
+        data.write_u32::<BigEndian>(0).unwrap();
+
+        let (bitmap, _) = EwahBitmap::parse(&data).unwrap();
+
+        assert_eq!(bitmap.len(), 130);
+        assert!((0..128).all(|i| bitmap.get(i)));
+        assert!(!bitmap.get(128));
+        assert!(bitmap.get(129));
+    }
+
+    #[test]
+    fn rejects_truncated_bitmap() {
+        let data = EwahBitmap::new(100).serialize();
+        let result = EwahBitmap::parse(&data[..data.len() - 5]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_length_beyond_words() {
+        // Claims 4 billion bits but has no words to hold them
+        let mut data = vec![];
+        data.write_u32::<BigEndian>(u32::MAX).unwrap();
+        data.write_u32::<BigEndian>(0).unwrap();
+        data.write_u32::<BigEndian>(0).unwrap();
+
+        assert!(EwahBitmap::parse(&data).is_err());
+    }
+
+    #[test]
+    fn capacity_is_bounded_by_words() {
+        // Claims 4 billion bits, and has one literal word
+        let mut data = vec![];
+        data.write_u32::<BigEndian>(u32::MAX).unwrap();
+        data.write_u32::<BigEndian>(2).unwrap();
+        data.write_u64::<BigEndian>(1 << 33).unwrap();
+        data.write_u64::<BigEndian>(u64::MAX).unwrap();
+        data.write_u32::<BigEndian>(0).unwrap();
+
+        let result = EwahBitmap::parse(&data);
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(IndexError::Corrupt { problem }) if problem.contains("don't cover")
+        ));
+    }
+}
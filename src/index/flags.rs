@@ -30,10 +30,13 @@ pub(super) const MASK_EXT_INTENT_TO_ADD: u16 = 0b0010_0000_0000_0000;
 // pub(super) const MASK_EXT_UNUSED: u16     = 0b0001_1111_1111_1111;
 
 impl EntryFlags {
-    pub fn new(name: &str) -> EntryFlags {
+    pub fn new<N>(name: N) -> EntryFlags
+    where
+        N: AsRef<[u8]>,
+    {
         let mut flags = EntryFlags { basic_flags: 0, ext_flags: None };
 
-        let name_len = std::cmp::min(name.len(), 0xFFF);
+        let name_len = std::cmp::min(name.as_ref().len(), 0xFFF);
         flags.set_name_len(name_len.try_into().unwrap());
 
         flags
@@ -65,18 +68,18 @@ impl EntryFlags {
         self.ext_flags = None;
     }
 
-    pub fn get_stage(&self) {
-        match self.basic_flags & MASK_STAGE {
-            0b0000_0000_0000_0000 => (),
-            0b0001_0000_0000_0000 => (),
-            0b0010_0000_0000_0000 => (),
-            0b0011_0000_0000_0000 => (),
-            _ => (),
-        }
+    /// Returns the merge stage: 0 for a normal entry, or 1 (base), 2 (ours), or 3 (theirs)
+    /// for a conflicted one.
+    pub fn get_stage(&self) -> u16 {
+        (self.basic_flags & MASK_STAGE) >> 12
     }
 
-    pub fn set_stage(&mut self, _stage: ()) {
-        panic!("not implemented");
+    pub fn set_stage(&mut self, stage: u16) {
+        if stage > 3 {
+            panic!("Stage cannot be more than 3");
+        }
+
+        self.basic_flags = (self.basic_flags & !MASK_STAGE) | (stage << 12);
     }
 
     pub fn get_name_len(&self) -> u16 {
@@ -93,7 +96,7 @@ impl EntryFlags {
     }
 
     pub fn get_skip_worktree(&self) -> bool {
-        (self.ext_flags.unwrap() & MASK_EXT_SKIP_WORKTREE) != 0
+        self.ext_flags.is_some_and(|flags| (flags & MASK_EXT_SKIP_WORKTREE) != 0)
     }
 
     pub fn set_skip_worktree(&mut self) {
@@ -129,7 +132,19 @@ mod tests {
 
     #[test]
     fn name_len_is_limited_to_0xfff() {
-        let flags = EntryFlags::new(&str::repeat("a", 0x1000));
+        let flags = EntryFlags::new(str::repeat("a", 0x1000));
         assert_eq!(flags.get_name_len(), 0xFFF);
     }
+
+    #[test]
+    fn stage_does_not_clobber_other_flags() {
+        let mut flags = EntryFlags::new("hello_world.rs");
+        flags.set_assume_valid();
+        flags.set_stage(3);
+        assert_eq!(flags.get_stage(), 3);
+        flags.set_stage(0);
+        assert_eq!(flags.get_stage(), 0);
+        assert!(flags.get_assume_valid());
+        assert_eq!(flags.get_name_len(), 14);
+    }
 }
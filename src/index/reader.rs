@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+
+use anyhow::Context;
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{
+    Result,
+    object::ObjectHash,
+    workdir::{WorkPath, WorkPathBuf},
+};
+
+use super::{IndexEntry, IndexError, EntryFlags, FileStats, flags};
+
+/// A cursor over the bytes of a serialized index (usually a memory-mapped file).
+///
+/// Fields are sliced directly out of the underlying buffer rather than copied
+/// through a reader one at a time.
+pub struct IndexReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> IndexReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Returns the number of bytes that have been consumed so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Returns the unconsumed bytes.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    /// Consumes the next `len` bytes.
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| IndexError::Corrupt {
+                problem: format!("unexpected end of data at byte {}", self.pos),
+            })?;
+
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+
+        Ok(slice)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        Ok(BigEndian::read_u16(self.take(2)?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        Ok(BigEndian::read_u32(self.take(4)?))
+    }
+
+    /// Consumes bytes up to and including the next null byte. The null byte is not
+    /// included in the returned slice.
+    pub fn take_until_null(&mut self) -> Result<&'a [u8]> {
+        let len = self.rest()
+            .iter()
+            .position(|ch| *ch == 0)
+            .ok_or_else(|| IndexError::Corrupt {
+                problem: "unterminated path".to_owned(),
+            })?;
+
+        let slice = self.take(len)?;
+        self.pos += 1;
+
+        Ok(slice)
+    }
+}
+
+/// An iterator over the entries of a serialized index.
+///
+/// Paths are borrowed from the underlying buffer whenever they are already in
+/// canonical form, so nothing is copied until the entries are collected into an
+/// [`Index`](super::Index).
+pub struct IndexEntries<'a> {
+    reader: IndexReader<'a>,
+    remaining: u32,
+}
+
+impl<'a> IndexEntries<'a> {
+    pub(super) fn new(reader: IndexReader<'a>, count: u32) -> Self {
+        Self { reader, remaining: count }
+    }
+
+    /// Returns the bytes following the entries. Only meaningful once the iterator
+    /// has been exhausted.
+    pub fn rest(&self) -> &'a [u8] {
+        self.reader.rest()
+    }
+
+    /// Parses one index entry.
+    fn parse_next(&mut self) -> Result<(Cow<'a, WorkPath>, IndexEntry)> {
+        let reader = &mut self.reader;
+        let start_pos = reader.position(); // used to calculate entry length later
+
+        // Entry begins with file stats
+        let stats = FileStats {
+            ctime_s: reader.read_u32()?,
+            ctime_ns: reader.read_u32()?,
+            mtime_s: reader.read_u32()?,
+            mtime_ns: reader.read_u32()?,
+            dev: reader.read_u32()?,
+            ino: reader.read_u32()?,
+            mode: reader.read_u32()?,
+            uid: reader.read_u32()?,
+            gid: reader.read_u32()?,
+            size: reader.read_u32()?,
+        };
+
+        // Stats are followed by the object hash
+        let hash = ObjectHash::try_from(reader.take(20)?)?;
+
+        // Hash is followed by 2-4 bytes of flags
+        let flags = {
+            let basic_flags = reader.read_u16()?;
+
+            let has_ext_flags = (basic_flags & flags::MASK_EXTENDED) != 0;
+            let ext_flags = match has_ext_flags {
+                true => Some(reader.read_u16()?),
+                false => None,
+            };
+
+            EntryFlags {
+                basic_flags,
+                ext_flags,
+            }
+        };
+
+        // Flags are followed by a null-terminated path
+        let path = {
+            // Replacement entries in a split index have empty paths
+            let bytes = reader.take_until_null()?;
+
+            let borrowed = WorkPath::from_canonical_bytes(bytes);
+
+            match borrowed {
+                Some(path) => Cow::Borrowed(path),
+                None => Cow::Owned(
+                    WorkPathBuf::try_from(bytes)
+                        .with_context(|| "Invalid path in index".to_owned())?
+                ),
+            }
+        };
+
+        // Each entry ends with 0-7 additional NULL bytes to maintain 8-byte alignment
+        {
+            let entry_len = reader.position() - start_pos;
+            let padding = super::Index::calc_padding_len(entry_len, true);
+            reader.take(padding)?;
+        }
+
+        Ok((path, IndexEntry {
+            stats,
+            hash,
+            flags,
+        }))
+    }
+}
+
+impl<'a> Iterator for IndexEntries<'a> {
+    type Item = Result<(Cow<'a, WorkPath>, IndexEntry)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let result = self.parse_next();
+        self.remaining = match result {
+            Ok(_) => self.remaining - 1,
+            Err(_) => 0, // stop at the first error
+        };
+
+        Some(result)
+    }
+}
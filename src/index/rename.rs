@@ -0,0 +1,188 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    Result,
+    object::{ObjectHash, Tree},
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+};
+
+use super::{Index, StagedChange};
+
+/// A file or directory that was moved between a commit and the index.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Rename {
+    pub old_path: WorkPathBuf,
+    pub new_path: WorkPathBuf,
+    /// True if every file in the directory `old_path` was moved into `new_path`.
+    pub is_dir: bool,
+}
+
+impl Index {
+    /// Finds staged files that were moved without being changed, i.e. a deleted file and
+    /// a created file with the same contents. The matched changes are removed from
+    /// `changes`. Files that moved together from one directory to another are then
+    /// reported as a single directory rename.
+    pub fn detect_staged_renames(&self, wd: &WorkDir, commit_hash: Option<&ObjectHash>, changes: &mut Vec<StagedChange>) -> Result<Vec<Rename>> {
+        let Some(commit_hash) = commit_hash else {
+            return Ok(vec![]);
+        };
+        let root_tree = Tree::read_from_commit(wd, commit_hash)?;
+
+        // Deleted files, by the hash of their old contents
+        let mut deleted: HashMap<ObjectHash, Vec<WorkPathBuf>> = HashMap::new();
+        for change in changes.iter() {
+            if let StagedChange::Deleted { path } = change {
+                if let Some(entry) = root_tree.find_entry(wd, path)? {
+                    deleted.entry(entry.hash).or_default().push(path.clone());
+                }
+            }
+        }
+
+        let mut renames = vec![];
+        for change in changes.iter() {
+            let StagedChange::Created { path } = change else {
+                continue;
+            };
+            let Some(candidates) = self.entries.get(path)
+                .and_then(|entry| deleted.get_mut(&entry.hash))
+                .filter(|candidates| !candidates.is_empty())
+            else {
+                continue;
+            };
+
+            // If several files had these contents, prefer one with the same name
+            let i = candidates.iter()
+                .position(|old_path| old_path.file_name() == path.file_name())
+                .unwrap_or(0);
+            renames.push(Rename {
+                old_path: candidates.remove(i),
+                new_path: path.clone(),
+                is_dir: false,
+            });
+        }
+
+        changes.retain(|change| !renames.iter().any(|rename| match change {
+            StagedChange::Deleted { path } => *path == rename.old_path,
+            StagedChange::Created { path } => *path == rename.new_path,
+            StagedChange::Modified { .. } => false,
+        }));
+
+        Ok(self.group_dir_renames(renames, changes))
+    }
+
+    /// Replaces the renames of all files in a directory with one rename of the directory.
+    /// A directory only counts as moved if nothing is left of it: no tracked files remain
+    /// under its old path and none of its files were deleted.
+    fn group_dir_renames(&self, renames: Vec<Rename>, changes: &[StagedChange]) -> Vec<Rename> {
+        let mut by_dir: BTreeMap<(WorkPathBuf, WorkPathBuf), Vec<Rename>> = BTreeMap::new();
+        let mut result = vec![];
+
+        for rename in renames {
+            match moved_dirs(&rename.old_path, &rename.new_path) {
+                Some((old_dir, new_dir)) => {
+                    by_dir.entry((old_dir.to_owned(), new_dir.to_owned()))
+                        .or_default()
+                        .push(rename);
+                },
+                None => result.push(rename),
+            }
+        }
+
+        for ((old_dir, new_dir), renames) in by_dir {
+            let dir_prefix = format!("{old_dir}/");
+            let is_emptied = self.entries_in_dir(&old_dir).next().is_none()
+                && !changes.iter().any(|change| matches!(
+                    change,
+                    StagedChange::Deleted { path } if path.as_str().starts_with(&dir_prefix)
+                ));
+
+            if is_emptied {
+                result.push(Rename {
+                    old_path: old_dir,
+                    new_path: new_dir,
+                    is_dir: true,
+                });
+            }
+            else {
+                result.extend(renames);
+            }
+        }
+
+        result.sort_by(|a, b| a.old_path.cmp(&b.old_path));
+        result
+    }
+}
+
+/// Finds the directories that must have been moved for `old_path` to become `new_path`,
+/// by removing the longest run of trailing components they share. For example, moving
+/// `src/util/fmt.rs` to `lib/util/fmt.rs` moves `src` to `lib`. Returns `None` if the file
+/// was renamed rather than moved, or if it moved to or from the root.
+fn moved_dirs<'a>(old_path: &'a WorkPath, new_path: &'a WorkPath) -> Option<(&'a WorkPath, &'a WorkPath)> {
+    let mut dirs = None;
+    let (mut old_path, mut new_path) = (old_path, new_path);
+
+    while old_path.file_name() == new_path.file_name() {
+        match (old_path.parent(), new_path.parent()) {
+            (Some(old_parent), Some(new_parent)) if !old_parent.is_empty() && !new_parent.is_empty() => {
+                old_path = old_parent;
+                new_path = new_parent;
+                dirs = Some((old_path, new_path));
+            },
+            _ => break,
+        }
+    }
+
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{object::FileMode, index::{IndexEntry, EntryFlags, FileStats}};
+
+    fn path(path: &str) -> WorkPathBuf {
+        WorkPathBuf::try_from(path).unwrap()
+    }
+
+    #[test]
+    fn finds_moved_dirs() {
+        let moved = |old: &str, new: &str| {
+            let (old, new) = (path(old), path(new));
+            moved_dirs(&old, &new).map(|(old, new)| (old.as_str().to_owned(), new.as_str().to_owned()))
+        };
+
+        assert_eq!(moved("src/util/fmt.rs", "lib/util/fmt.rs"), Some(("src".to_owned(), "lib".to_owned())));
+        assert_eq!(moved("a/b/c.rs", "a/d/c.rs"), Some(("a/b".to_owned(), "a/d".to_owned())));
+        assert_eq!(moved("a/c.rs", "a/d.rs"), None);
+        assert_eq!(moved("a/c.rs", "c.rs"), None);
+        assert_eq!(moved("a/c.rs", "b/a/c.rs"), Some(("a".to_owned(), "b/a".to_owned())));
+    }
+
+    #[test]
+    fn groups_emptied_dirs() {
+        let mut index = Index::new(None);
+        for name in ["new/x", "new/sub/y", "other/z", "kept/a"] {
+            index.entries.insert(path(name), IndexEntry {
+                stats: FileStats::from_mode_and_size(FileMode::Regular, 0),
+                hash: ObjectHash::new(name),
+                flags: EntryFlags::new(name),
+            });
+        }
+        let rename = |old: &str, new: &str| Rename {
+            old_path: path(old),
+            new_path: path(new),
+            is_dir: false,
+        };
+
+        let renames = vec![
+            rename("old/x", "new/x"),
+            rename("old/sub/y", "new/sub/y"),
+            rename("kept/b", "other/z"),
+        ];
+        let grouped = index.group_dir_renames(renames, &[]);
+        assert_eq!(grouped, [
+            rename("kept/b", "other/z"),
+            Rename { is_dir: true, ..rename("old", "new") },
+        ]);
+    }
+}
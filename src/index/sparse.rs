@@ -0,0 +1,192 @@
+use std::io::Write;
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use crate::{
+    Result,
+    object::{ObjectHash, Tree, TreeEntry, FileMode},
+    workdir::{WorkDir, WorkPath},
+};
+
+use super::{Index, IndexEntry, IndexError, EntryFlags, FileStats};
+
+impl IndexEntry {
+    /// Constructs a sparse directory entry, which stands in for every file in the tree
+    /// `tree_hash` at `path`.
+    pub fn sparse_dir(path: &WorkPath, tree_hash: ObjectHash) -> Self {
+        let mut flags = EntryFlags::new([&path.to_bytes()[..], b"/"].concat());
+        flags.set_extended();
+        flags.set_skip_worktree();
+
+        Self {
+            stats: FileStats::from_mode_and_size(FileMode::Directory, 0),
+            hash: tree_hash,
+            flags,
+        }
+    }
+
+    /// Returns true if this entry is a sparse directory entry (a collapsed directory
+    /// outside of the sparse checkout).
+    pub fn is_sparse_dir(&self) -> bool {
+        self.stats.mode == FileMode::Directory.bits()
+    }
+}
+
+impl Index {
+    /// The signature of the extension that marks an index as sparse.
+    pub const SPARSE_SIGNATURE: [u8; 4] = *b"sdir";
+
+    /// Returns true if any directory in the index is collapsed into a single entry.
+    pub fn is_sparse(&self) -> bool {
+        self.entries.values().any(IndexEntry::is_sparse_dir)
+    }
+
+    /// Expands any sparse directory entries that contain `path`, so that `path` can be
+    /// looked up or modified directly. Directories alongside `path` stay collapsed.
+    pub fn expand_to_path(&mut self, wd: &WorkDir, path: &WorkPath) -> Result<()> {
+        let mut dirs = vec![];
+        let mut current = path;
+        while let Some(parent) = current.parent() {
+            if parent.is_empty() {
+                break;
+            }
+            dirs.push(parent);
+            current = parent;
+        }
+
+        // Expand from the top down, since each expansion creates the entry for the next
+        for dir in dirs.into_iter().rev() {
+            if self.entries.get(dir).is_some_and(IndexEntry::is_sparse_dir) {
+                self.expand_dir(wd, dir)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the sparse directory entry at `dir` with entries for its immediate children.
+    /// Subdirectories become sparse directory entries themselves.
+    pub fn expand_dir(&mut self, wd: &WorkDir, dir: &WorkPath) -> Result<()> {
+        let entry = match self.entries.remove(dir) {
+            Some(entry) if entry.is_sparse_dir() => entry,
+            Some(entry) => {
+                self.entries.insert(dir.to_owned(), entry);
+                return Err(IndexError::NotSparseDir(dir.to_owned()).into());
+            },
+            None => return Err(IndexError::NotSparseDir(dir.to_owned()).into()),
+        };
+
+        let tree = Tree::read(wd, &entry.hash)?;
+        for (name, tree_entry) in &tree.entries {
+            let path = dir.to_owned().join(name);
+            let child = Self::sparse_child(&path, tree_entry);
+            self.entries.insert(path, child);
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if a sparse directory entry contains `path`.
+    pub(super) fn has_sparse_ancestor(&self, path: &WorkPath) -> bool {
+        let mut current = path;
+        while let Some(parent) = current.parent() {
+            if parent.is_empty() {
+                return false;
+            }
+            if self.entries.get(parent).is_some_and(IndexEntry::is_sparse_dir) {
+                return true;
+            }
+            current = parent;
+        }
+
+        false
+    }
+
+    /// Constructs the index entry for a file or directory within a sparse directory.
+    pub(super) fn sparse_child(path: &WorkPath, tree_entry: &TreeEntry) -> IndexEntry {
+        if tree_entry.is_dir() {
+            return IndexEntry::sparse_dir(path, tree_entry.hash);
+        }
+
+        let mut flags = EntryFlags::new(path.to_bytes());
+        flags.set_extended();
+        flags.set_skip_worktree();
+
+        IndexEntry {
+            stats: FileStats::from_mode_and_size(tree_entry.mode, 0),
+            hash: tree_entry.hash,
+            flags,
+        }
+    }
+
+    /// Appends the sparse directory extension to `data` if the index has any sparse
+    /// directory entries. The extension has no content.
+    pub(super) fn serialize_sparse_ext(&self, data: &mut Vec<u8>) -> Result<()> {
+        if self.is_sparse() {
+            data.write_all(&Self::SPARSE_SIGNATURE)?;
+            data.write_u32::<BigEndian>(0)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workdir::WorkPathBuf;
+
+    #[test]
+    fn sparse_dir_round_trip() {
+        let mut index = Index::new(Some(3));
+        let dir = WorkPathBuf::try_from("docs").unwrap();
+        index.entries.insert(dir.clone(), IndexEntry::sparse_dir(&dir, ObjectHash::new([1])));
+
+        let data = index.serialize().unwrap();
+        let (_, mut entries) = Index::parse_entries(&data).unwrap();
+        let (path, _) = entries.next().unwrap().unwrap();
+        assert_eq!(path.to_string(), "docs"); // trailing slash is stripped when parsed
+        assert!(entries.rest().starts_with(&Index::SPARSE_SIGNATURE));
+
+        let parsed = Index::parse(&data).unwrap();
+        let entry = &parsed.entries[&dir];
+        assert!(entry.is_sparse_dir());
+        assert!(entry.flags.get_skip_worktree());
+        assert_eq!(entry.flags.get_name_len(), 5);
+        assert!(parsed.ext_data.is_empty());
+    }
+
+    #[test]
+    fn sparse_dirs_sort_with_trailing_slash() {
+        let mut index = Index::new(Some(3));
+        let dir = WorkPathBuf::try_from("docs").unwrap();
+        index.entries.insert(dir.clone(), IndexEntry::sparse_dir(&dir, ObjectHash::new([1])));
+        index.entries.insert("docs.txt".try_into().unwrap(), IndexEntry {
+            stats: FileStats::from_size(0),
+            hash: ObjectHash::new([]),
+            flags: EntryFlags::new("docs.txt"),
+        });
+
+        let data = index.serialize().unwrap();
+        let (_, entries) = Index::parse_entries(&data).unwrap();
+        let paths: Vec<_> = entries.map(|entry| entry.unwrap().0.to_string()).collect();
+        assert_eq!(paths, ["docs.txt", "docs"]);
+    }
+
+    #[test]
+    fn regular_index_has_no_sparse_ext() {
+        let mut index = Index::new(None);
+        let path = WorkPathBuf::try_from("main.rs").unwrap();
+        index.entries.insert(path, IndexEntry {
+            stats: FileStats::from_size(0),
+            hash: ObjectHash::new([]),
+            flags: EntryFlags::new("main.rs"),
+        });
+
+        assert!(!index.is_sparse());
+        let data = index.serialize().unwrap();
+        let (_, mut entries) = Index::parse_entries(&data).unwrap();
+        entries.next().unwrap().unwrap();
+        assert!(entries.rest().is_empty());
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    time::SystemTime,
+};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use crate::{
+    Result,
+    date,
+    object::ObjectHash,
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+};
+
+use super::{Index, IndexEntry, IndexError, ewah::EwahBitmap};
+
+/// State for an index written in split mode.
+///
+/// In split mode, most entries live in a shared index file (`.git/sharedindex.<hash>`)
+/// which rarely changes. The index file itself only records how the current entries
+/// differ from the shared ones, which keeps writes small in huge repositories.
+///
+/// See the "Split index" section of
+/// [the git docs](https://github.com/git/git/blob/master/Documentation/gitformat-index.txt).
+#[derive(Clone)]
+pub struct SplitIndex {
+    /// The hash that names the shared index file. All zeros if there is none.
+    pub base_hash: ObjectHash,
+    /// The entries of the shared index file.
+    base: BTreeMap<WorkPathBuf, IndexEntry>,
+    /// Data from the link extension that has not yet been applied to the shared entries.
+    pending: Option<PendingLink>,
+}
+
+#[derive(Clone, Default)]
+struct PendingLink {
+    delete: EwahBitmap,
+    replace: EwahBitmap,
+    replacements: Vec<IndexEntry>,
+}
+
+impl SplitIndex {
+    /// The signature of the extension that links an index to its shared index.
+    pub const SIGNATURE: [u8; 4] = *b"link";
+
+    /// If more than this percentage of the shared entries have changed, a new shared
+    /// index is written (mirrors git's `splitIndex.maxPercentChange` default).
+    const MAX_PERCENT_CHANGE: usize = 20;
+
+    /// How long a shared index that no index refers to is kept if
+    /// `splitIndex.sharedIndexExpire` isn't set. This is the same as git.
+    pub const DEFAULT_EXPIRE: &str = "2.weeks.ago";
+
+    /// The start of the name of each shared index file, which ends with its hash.
+    const BASE_FILE_PREFIX: &str = "sharedindex.";
+
+    /// Constructs a `SplitIndex` with no shared index. A shared index will be created
+    /// the next time the index is written.
+    pub fn new() -> Self {
+        Self {
+            base_hash: ObjectHash { raw: [0; 20] },
+            base: BTreeMap::new(),
+            pending: None,
+        }
+    }
+
+    /// Parses the data of a link extension. Replacement entries are removed from the
+    /// front of `entries`, as required by the format.
+    pub(super) fn parse_link(data: &[u8], entries: &mut Vec<(Cow<WorkPath>, IndexEntry)>) -> Result<Self> {
+        if data.len() < 20 {
+            return Err(IndexError::Corrupt {
+                problem: "link extension is too short".to_owned(),
+            }.into());
+        }
+
+        let base_hash = ObjectHash::try_from(&data[..20])?;
+        let data = &data[20..];
+
+        let pending = if data.is_empty() {
+            None
+        }
+        else {
+            let (delete, delete_len) = EwahBitmap::parse(data)?;
+            let (replace, _) = EwahBitmap::parse(&data[delete_len..])?;
+
+            let replacement_count = replace.count_ones();
+            if replacement_count > entries.len() {
+                return Err(IndexError::Corrupt {
+                    problem: format!("link extension replaces {replacement_count} entries, but only {} exist", entries.len()),
+                }.into());
+            }
+
+            let replacements = entries.drain(..replacement_count)
+                .map(|(_, entry)| entry)
+                .collect();
+
+            Some(PendingLink {
+                delete,
+                replace,
+                replacements,
+            })
+        };
+
+        Ok(Self {
+            base_hash,
+            base: BTreeMap::new(),
+            pending,
+        })
+    }
+
+    /// Returns true if this split index refers to a shared index file.
+    pub fn has_base(&self) -> bool {
+        self.base_hash.raw != [0; 20]
+    }
+
+    /// Returns the path to the shared index file relative to the .git directory.
+    fn base_file_name(hash: &ObjectHash) -> String {
+        format!("{}{hash}", Self::BASE_FILE_PREFIX)
+    }
+}
+
+impl Index {
+    /// Reads the shared index that this index refers to (if it was written in split
+    /// mode) and merges its entries into this one.
+    pub fn load_shared(&mut self, wd: &WorkDir) -> Result<()> {
+        let split = match self.split.as_mut() {
+            Some(split) if split.has_base() && split.base.is_empty() => split,
+            _ => return Ok(()),
+        };
+
+        let shared = {
+            let path = wd.git_path(SplitIndex::base_file_name(&split.base_hash));
+            let data = std::fs::read(&path)
+                .map_err(|_| IndexError::SharedIndexMissing(split.base_hash))?;
+            Index::parse(&data)?
+        };
+
+        let pending = split.pending.take().unwrap_or_default();
+        let mut replacements = pending.replacements.into_iter();
+        let mut merged = BTreeMap::new();
+
+        for (pos, (path, entry)) in shared.entries.iter().enumerate() {
+            if pending.replace.get(pos) {
+                let mut replacement = replacements.next()
+                    .ok_or_else(|| IndexError::Corrupt {
+                        problem: "link extension is missing a replacement entry".to_owned(),
+                    })?;
+                replacement.flags.set_name_len(entry.flags.get_name_len());
+                merged.insert(path.clone(), replacement);
+            }
+            else if !pending.delete.get(pos) {
+                merged.insert(path.clone(), entry.clone());
+            }
+        }
+
+        // Entries stored directly in this index are additions
+        merged.append(&mut self.entries);
+        self.entries = merged;
+        split.base = shared.entries;
+
+        Ok(())
+    }
+
+    /// Writes this index in split mode, creating a new shared index if none exists or
+    /// too many entries have changed since the last one was written.
+    pub(super) fn write_split(&self, wd: &WorkDir, split: &SplitIndex) -> Result<()> {
+        let mut delete = EwahBitmap::new(split.base.len());
+        let mut replace = EwahBitmap::new(split.base.len());
+        let mut replacements = vec![];
+
+        for (pos, (path, base_entry)) in split.base.iter().enumerate() {
+            match self.entries.get(path) {
+                None => delete.set(pos),
+                Some(entry) if entry != base_entry => {
+                    replace.set(pos);
+                    replacements.push(entry);
+                },
+                Some(_) => (),
+            }
+        }
+
+        let additions: Vec<_> = self.sorted_entries().into_iter()
+            .filter(|(path, _)| !split.base.contains_key(*path))
+            .collect();
+
+        let change_count = delete.count_ones() + replacements.len() + additions.len();
+        if !split.has_base() || change_count * 100 > split.base.len() * SplitIndex::MAX_PERCENT_CHANGE {
+            return self.write_new_shared(wd);
+        }
+
+        let mut data = Vec::new();
+        self.serialize_header(&mut data, replacements.len() + additions.len())?;
+
+        // Replaced entries come first and have empty paths
+        for entry in replacements {
+            let mut entry = entry.clone();
+            entry.flags.set_name_len(0);
+            Self::serialize_entry(&mut data, b"", &entry)?;
+        }
+
+        for (path, entry) in additions {
+            Self::serialize_entry(&mut data, &path.to_bytes(), entry)?;
+        }
+
+        Self::serialize_link(&mut data, &split.base_hash, &delete, &replace)?;
+        self.serialize_sparse_ext(&mut data)?;
+
+        self.write_data(wd, data)?;
+
+        // Git refreshes the shared index whenever it's used, so it only expires once unused
+        if let Ok(file) = fs::File::options().write(true).open(wd.git_path(SplitIndex::base_file_name(&split.base_hash))) {
+            file.set_modified(SystemTime::now())?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves every entry into a new shared index and writes an index that refers to it.
+    fn write_new_shared(&self, wd: &WorkDir) -> Result<()> {
+        let mut shared = self.serialize()?;
+        let base_hash = ObjectHash::new(&shared);
+        shared.write_all(&base_hash.raw)?;
+
+        wd.write_git_file(SplitIndex::base_file_name(&base_hash), &shared)?;
+
+        let mut data = Vec::new();
+        self.serialize_header(&mut data, 0)?;
+        Self::serialize_link(&mut data, &base_hash, &EwahBitmap::new(self.entries.len()), &EwahBitmap::new(self.entries.len()))?;
+        self.serialize_sparse_ext(&mut data)?;
+
+        self.write_data(wd, data)?;
+
+        expire_shared(wd, &base_hash)
+    }
+
+    /// Appends a link extension to `data`.
+    fn serialize_link(data: &mut Vec<u8>, base_hash: &ObjectHash, delete: &EwahBitmap, replace: &EwahBitmap) -> Result<()> {
+        let delete = delete.serialize();
+        let replace = replace.serialize();
+
+        data.write_all(&SplitIndex::SIGNATURE)?;
+        data.write_u32::<BigEndian>((20 + delete.len() + replace.len()) as u32)?;
+        data.write_all(&base_hash.raw)?;
+        data.write_all(&delete)?;
+        data.write_all(&replace)?;
+
+        Ok(())
+    }
+}
+
+/// Deletes the shared index files other than `keep` that haven't been used since
+/// `splitIndex.sharedIndexExpire` (two weeks ago by default).
+fn expire_shared(wd: &WorkDir, keep: &ObjectHash) -> Result<()> {
+    let cutoff = match wd.settings().shared_index_expire {
+        Some(cutoff) => cutoff,
+        None => date::parse_expiry(SplitIndex::DEFAULT_EXPIRE, date::now())
+            .expect("default expiry time should be valid"),
+    };
+    let keep = SplitIndex::base_file_name(keep);
+
+    for entry in fs::read_dir(wd.git_dir())? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let is_expired_base = name.to_str()
+            .is_some_and(|name| name.starts_with(SplitIndex::BASE_FILE_PREFIX) && name != keep);
+        if !is_expired_base {
+            continue;
+        }
+
+        let modified = entry.metadata()?.modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+        if modified <= cutoff {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+impl Default for SplitIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        index::{FileStats, EntryFlags},
+        test_util::temp_workdir,
+        workdir::Settings,
+    };
+
+    fn fake_entry(path: &str, hash_seed: u8) -> IndexEntry {
+        IndexEntry {
+            stats: FileStats::from_size(0),
+            hash: ObjectHash::new([hash_seed]),
+            flags: EntryFlags::new(path),
+        }
+    }
+
+    #[test]
+    fn link_round_trip() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        let mut base = Index::new(None);
+        for (i, path) in ["a.txt", "b.txt", "c.txt"].iter().enumerate() {
+            base.entries.insert(WorkPathBuf::try_from(*path).unwrap(), fake_entry(path, i as u8));
+        }
+        base.write_new_shared(&wd).unwrap();
+        let mut split = SplitIndex::new();
+        split.base_hash = ObjectHash::new(base.serialize().unwrap());
+        // Written through a lock file, like the index itself
+        let base_file_name = SplitIndex::base_file_name(&split.base_hash);
+        assert!(wd.git_path(&base_file_name).is_file());
+        assert!(!wd.git_path(format!("{base_file_name}.lock")).exists());
+
+        // Replace b.txt, delete c.txt, add d.txt
+        let mut index = Index::new(None);
+        index.entries.insert("a.txt".try_into().unwrap(), fake_entry("a.txt", 0));
+        index.entries.insert("b.txt".try_into().unwrap(), fake_entry("b.txt", 10));
+        index.entries.insert("d.txt".try_into().unwrap(), fake_entry("d.txt", 3));
+
+        let mut delete = EwahBitmap::new(3);
+        delete.set(2);
+        let mut replace = EwahBitmap::new(3);
+        replace.set(1);
+
+        let mut data = Vec::new();
+        index.serialize_header(&mut data, 2).unwrap();
+        let mut replacement = fake_entry("b.txt", 10);
+        replacement.flags.set_name_len(0);
+        Index::serialize_entry(&mut data, b"", &replacement).unwrap();
+        Index::serialize_entry(&mut data, b"d.txt", &fake_entry("d.txt", 3)).unwrap();
+        Index::serialize_link(&mut data, &split.base_hash, &delete, &replace).unwrap();
+
+        let mut parsed = Index::parse(&data).unwrap();
+        let parsed_split = parsed.split.as_mut().unwrap();
+        assert_eq!(parsed_split.base_hash, split.base_hash);
+        assert_eq!(parsed.entries.len(), 1); // only the addition until the base is merged
+
+        let pending = parsed.split.as_ref().unwrap().pending.as_ref().unwrap();
+        assert!(pending.delete.get(2));
+        assert!(pending.replace.get(1));
+        assert_eq!(pending.replacements.len(), 1);
+
+        parsed.load_shared(&wd).unwrap();
+        let hashes: Vec<_> = parsed.entries.iter()
+            .map(|(path, entry)| (path.to_string(), entry.hash))
+            .collect();
+        assert_eq!(hashes, [
+            ("a.txt".to_owned(), ObjectHash::new([0])),
+            ("b.txt".to_owned(), ObjectHash::new([10])),
+            ("d.txt".to_owned(), ObjectHash::new([3])),
+        ]);
+        assert_eq!(parsed.split.unwrap().base.len(), 3);
+    }
+
+    #[test]
+    fn expires_unused_shared_indexes() {
+        let (_temp_dir, mut wd) = temp_workdir();
+        wd.set_settings(Settings {
+            shared_index_expire: Some(2000),
+            ..Settings::default()
+        });
+
+        let [keep, old, recent] = [b"keep", b"old_", b"new_"].map(ObjectHash::new);
+        for (hash, modified) in [(keep, 1000), (old, 1000), (recent, 3000)] {
+            let path = wd.git_path(SplitIndex::base_file_name(&hash));
+            let file = fs::File::create(&path).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(modified)).unwrap();
+        }
+
+        expire_shared(&wd, &keep).unwrap();
+
+        assert!(wd.git_path(SplitIndex::base_file_name(&keep)).is_file());
+        assert!(!wd.git_path(SplitIndex::base_file_name(&old)).exists());
+        assert!(wd.git_path(SplitIndex::base_file_name(&recent)).is_file());
+    }
+}
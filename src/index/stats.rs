@@ -1,9 +1,9 @@
 use std::{
-    fs::File,
+    fs::{File, Metadata},
     time::SystemTime,
 };
 
-use crate::Result;
+use crate::{Result, object::FileMode, workdir::Settings};
 
 /// Stats for a file in the index, such as size and modification time.
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
@@ -36,8 +36,22 @@ impl FileStats {
         }
     }
 
+    /// Constructs stats for a file that only exists in the object store (e.g. one read
+    /// from a tree), where only the mode and size are known.
+    pub fn from_mode_and_size(mode: FileMode, size: u32) -> Self {
+        Self {
+            mode: mode.bits(),
+            ..Self::from_size(size)
+        }
+    }
+
     pub fn from_file(file: &File) -> Result<FileStats> {
-        let meta = file.metadata()?;
+        Self::from_metadata(&file.metadata()?)
+    }
+
+    /// Constructs stats from `meta`, which describes the link itself if it's from
+    /// [`std::fs::symlink_metadata`].
+    pub fn from_metadata(meta: &Metadata) -> Result<FileStats> {
 
         // ctime does NOT mean creation time on *nix, but git on windows
         // uses the creation time here
@@ -49,7 +63,8 @@ impl FileStats {
             .duration_since(SystemTime::UNIX_EPOCH)
             .expect("Timestamp should be after UNIX epoch");
 
-        let size: u32 = meta.len().try_into().expect("File size should fit into u32");
+        // Like git, only the low 32 bits of the size are stored
+        let size = meta.len() as u32;
 
         Ok(FileStats {
             ctime_s: created_time.as_secs().try_into().expect("Timestamp should fit into u32"),
@@ -58,14 +73,77 @@ impl FileStats {
             mtime_ns: modified_time.subsec_nanos(),
             dev: 0, // only used on *nix
             ino: 0, // only used on *nix
-            mode: 33188, // TODO figure out how git fills this field on Windows
+            // TODO figure out how git fills this field on Windows
+            mode: match meta.is_symlink() {
+                true => FileMode::Symlink.bits(),
+                false => 33188,
+            },
             uid: 0, // only used on *nix
             gid: 0, // only used on *nix
             size,
         })
     }
 
-    pub fn get_mode_string(&self) -> String {
-        format!("{:06o}", self.mode)
+    /// Returns true if a file whose stats were recorded as `self` still appears unchanged
+    /// now that its stats are `current`, so its contents don't need to be hashed again.
+    /// `core.trustctime` and `core.checkStat` (in `settings`) leave out the fields that
+    /// some file systems don't keep stable.
+    pub fn matches(&self, current: &FileStats, settings: &Settings) -> bool {
+        let minimal = settings.minimal_stat_check;
+        let ctime_matches = !settings.trust_ctime || (
+            self.ctime_s == current.ctime_s
+            && (minimal || self.ctime_ns == current.ctime_ns)
+        );
+        let inode_and_owner_match = minimal || (
+            self.dev == current.dev
+            && self.ino == current.ino
+            && self.uid == current.uid
+            && self.gid == current.gid
+        );
+
+        ctime_matches
+            && inode_and_owner_match
+            && self.mtime_s == current.mtime_s
+            && (minimal || self.mtime_ns == current.mtime_ns)
+            && self.mode == current.mode
+            && self.size == current.size
+    }
+
+    /// Interprets the mode bits, which fails if they aren't a mode git would record.
+    pub fn get_mode(&self) -> Result<FileMode> {
+        Ok(FileMode::from_bits(self.mode)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat_checks_respect_settings() {
+        let recorded = FileStats {
+            ctime_s: 10,
+            ctime_ns: 5,
+            mtime_s: 20,
+            mtime_ns: 5,
+            ino: 7,
+            ..FileStats::from_mode_and_size(FileMode::Regular, 3)
+        };
+        let default = Settings::default();
+        let no_ctime = Settings { trust_ctime: false, ..Settings::default() };
+        let minimal = Settings { minimal_stat_check: true, ..Settings::default() };
+
+        assert!(recorded.matches(&recorded, &default));
+
+        let ctime_changed = FileStats { ctime_s: 11, ..recorded };
+        assert!(!ctime_changed.matches(&recorded, &default));
+        assert!(ctime_changed.matches(&recorded, &no_ctime));
+
+        let copied = FileStats { ino: 8, mtime_ns: 0, ctime_ns: 0, ..recorded };
+        assert!(!copied.matches(&recorded, &default));
+        assert!(copied.matches(&recorded, &minimal));
+
+        let resized = FileStats { size: 4, ..recorded };
+        assert!(!resized.matches(&recorded, &minimal));
     }
 }
@@ -1,41 +1,137 @@
 pub type Result<T> = anyhow::Result<T>;
 
+pub mod error;
+pub use error::Error;
+
 pub mod commands;
 pub use commands::Cli;
 
+pub mod archive;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod attributes;
 pub mod branch;
+pub mod consts;
+pub mod copy_objects;
+pub mod date;
+pub mod diff;
+pub mod docs;
+pub mod editor;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+pub mod history;
+pub mod hooks;
+pub mod ignore;
 pub mod index;
 pub mod kvlm;
+pub mod merge;
+pub mod messages;
 pub mod object;
+pub mod pathspec;
+pub mod pretty;
+pub mod prune;
+pub mod recover;
+pub mod reflog;
+pub mod remote;
 pub mod refs;
 pub mod repo;
+pub mod sequencer;
+pub mod server_info;
+pub mod sparse_checkout;
+pub mod stash;
+pub mod summary;
+#[cfg(test)]
+mod test_util;
+pub mod trace;
+pub mod whitespace;
 pub mod workdir;
+pub mod worktree;
 
-pub fn run(cli: Cli) {
+/// Runs the command and returns the exit status: 0 on success, or 1 after printing the
+/// error on failure, unless the command chose its own status.
+pub fn run(cli: Cli) -> std::process::ExitCode {
+    use std::process::ExitCode;
     use commands::*;
 
+    let verbosity = cli.verbosity();
+
+    if cli.strict {
+        if let Err(err) = check_strict() {
+            eprintln!("{}", messages::Message::Error(&err));
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let start = std::time::Instant::now();
     let result = match cli.command {
         Commands::Add(args) => cmd_add(args),
+        Commands::Archive(args) => cmd_archive(args),
         Commands::Branch(args) => cmd_branch(args),
         Commands::CatFile(args) => cmd_cat_file(args),
+        Commands::CheckAttr(args) => cmd_check_attr(args),
         Commands::Checkout(args) => cmd_checkout(args),
-        Commands::Commit(args) => cmd_commit(args),
+        Commands::CherryPick(args) => cmd_cherry_pick(args, verbosity),
+        Commands::Commit(args) => cmd_commit(args, verbosity),
+        Commands::CopyObjects(args) => cmd_copy_objects(args, verbosity),
+        Commands::Diff(args) => cmd_diff(args),
+        Commands::ForEachRef(args) => cmd_for_each_ref(args),
+        Commands::Fsck(args) => cmd_fsck(args),
+        Commands::GenerateDocs(args) => cmd_generate_docs(args),
         Commands::HashObject(args) => cmd_hash_object(args),
-        Commands::Init(args) => cmd_init(args),
+        Commands::Help(args) => cmd_help(args),
+        Commands::Init(args) => cmd_init(args, verbosity),
         Commands::Log(args) => cmd_log(args),
         Commands::LsFiles(args) => cmd_ls_files(args),
         Commands::LsTree(args) => cmd_ls_tree(args),
-        Commands::Merge(args) => cmd_merge(args),
+        Commands::Merge(args) => cmd_merge(args, verbosity),
+        Commands::MergeBase(args) => cmd_merge_base(args),
+        Commands::MergeFile(args) => cmd_merge_file(args),
+        Commands::Mergetool(args) => cmd_mergetool(args, verbosity),
+        Commands::Prune(args) => cmd_prune(args, verbosity),
+        Commands::ReadTree(args) => cmd_read_tree(args),
+        Commands::Recover(args) => cmd_recover(args, verbosity),
+        Commands::Reflog(args) => cmd_reflog(args),
+        Commands::Refs(args) => cmd_refs(args),
         Commands::Restore(args) => cmd_restore(args),
+        Commands::Revert(args) => cmd_revert(args, verbosity),
+        Commands::RevList(args) => cmd_rev_list(args),
         Commands::RevParse(args) => cmd_rev_parse(args),
         Commands::Rm(args) => cmd_rm(args),
         Commands::ShowRef(args) => cmd_show_ref(args),
+        Commands::Stash(args) => cmd_stash(args, verbosity),
+        Commands::Stats(args) => cmd_stats(args),
         Commands::Status(args) => cmd_status(args),
+        Commands::Summary(args) => cmd_summary(args),
         Commands::Switch(args) => cmd_switch(args),
         Commands::Tag(args) => cmd_tag(args),
+        Commands::UpdateServerInfo(args) => cmd_update_server_info(args),
+        Commands::VerifyCommit(args) => cmd_verify_commit(args),
+        Commands::VerifyTag(args) => cmd_verify_tag(args),
+        Commands::Worktree(args) => cmd_worktree(args, verbosity),
     };
 
-    if let Err(err) = result {
-        eprintln!("{err}");
+    if trace::is_enabled() {
+        let command: Vec<_> = std::env::args().collect();
+        trace::report(&command.join(" "), start.elapsed());
+    }
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => match err.downcast_ref::<ExitStatus>() {
+            Some(ExitStatus(code)) => ExitCode::from(*code),
+            None => {
+                eprintln!("{}", messages::Message::Error(&err));
+                ExitCode::FAILURE
+            },
+        },
+    }
+}
+
+/// Checks the repository containing the current directory for `--strict`, before the
+/// command opens it. Commands that work outside of a repository still run outside one.
+fn check_strict() -> Result<()> {
+    match repo::Repository::find_strict(".") {
+        Err(err) if matches!(err.downcast_ref(), Some(repo::RepoError::UninitializedDirectory(_))) => Ok(()),
+        result => result.map(|_| ()),
     }
 }
@@ -1,7 +1,7 @@
 use clap::Parser;
 use wyag::{Cli, run};
 
-fn main() {
+fn main() -> std::process::ExitCode {
     let cli = Cli::parse();
-    run(cli);
+    run(cli)
 }
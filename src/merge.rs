@@ -0,0 +1,463 @@
+use thiserror::Error;
+
+use crate::{
+    Result,
+    diff::{self, DiffOptions, Edit},
+    repo::{Repository, RepoError},
+};
+
+mod driver;
+pub use driver::{MergeDriver, MergeDriverError};
+
+mod tree;
+pub use tree::{TreeMerge, Conflict, ConflictKind, merge_trees};
+
+mod index;
+pub use index::{IndexMerge, merge_index};
+
+mod tool;
+pub use tool::{MergeTool, MergeToolError, ConflictVersions};
+
+/// How conflicts are presented in the merged file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ConflictStyle {
+    /// Show our version and their version.
+    #[default]
+    Merge,
+    /// Also show the base version between them.
+    Diff3,
+}
+
+/// Resolves conflicts automatically instead of emitting conflict markers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Favor {
+    /// Use our version.
+    Ours,
+    /// Use their version.
+    Theirs,
+    /// Use both versions, ours first.
+    Union,
+}
+
+/// Whether merging a commit may fast-forward the current branch instead of creating a merge
+/// commit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum FastForward {
+    /// Fast-forward when possible, and create a merge commit otherwise.
+    #[default]
+    Allow,
+    /// Always create a merge commit (`--no-ff`).
+    Never,
+    /// Refuse to merge unless fast-forwarding is possible (`--ff-only`).
+    Only,
+}
+
+impl FastForward {
+    /// Reads the policy from `merge.ff`, which is either a boolean or `only`.
+    pub fn from_config(repo: &Repository) -> Result<Self> {
+        let Some(value) = repo.get_config("merge", "ff") else {
+            return Ok(Self::default());
+        };
+
+        match (value, repo.get_config_bool("merge", "ff")) {
+            (value, _) if value.eq_ignore_ascii_case("only") => Ok(Self::Only),
+            (_, Some(true)) => Ok(Self::Allow),
+            (_, Some(false)) => Ok(Self::Never),
+            (value, None) => Err(RepoError::InvalidConfigValue {
+                key: "merge.ff".to_owned(),
+                value: value.to_owned(),
+            }.into()),
+        }
+    }
+}
+
+/// Options for [`merge_blobs`].
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+    pub style: ConflictStyle,
+    pub favor: Option<Favor>,
+    /// The length of the conflict markers, e.g. 7 for `<<<<<<<`.
+    pub marker_size: usize,
+    pub ours_label: String,
+    pub base_label: String,
+    pub theirs_label: String,
+    /// How lines are compared. When whitespace is ignored, changes that only affect
+    /// whitespace don't cause conflicts, and our version of such lines is kept.
+    pub diff: DiffOptions,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self {
+            style: ConflictStyle::default(),
+            favor: None,
+            marker_size: 7,
+            ours_label: "ours".to_owned(),
+            base_label: "base".to_owned(),
+            theirs_label: "theirs".to_owned(),
+            diff: DiffOptions::default(),
+        }
+    }
+}
+
+/// The result of a three-way merge.
+pub struct MergeResult {
+    /// The merged contents, including conflict markers.
+    pub data: Vec<u8>,
+    /// The number of conflicts.
+    pub conflicts: usize,
+}
+
+/// A line, including the line ending.
+type Line<'a> = &'a [u8];
+
+/// A section of the merged file.
+enum Chunk<'a> {
+    /// Lines that neither side changed.
+    Unchanged(Vec<Line<'a>>),
+    /// Lines that one side changed, or that both sides changed the same way.
+    Changed(Vec<Line<'a>>),
+    Conflict {
+        ours: Vec<Line<'a>>,
+        base: Vec<Line<'a>>,
+        theirs: Vec<Line<'a>>,
+    },
+}
+
+/// Merges the changes from `base` to `ours` and from `base` to `theirs` line by line.
+///
+/// Changes that overlap (or touch) are conflicts unless both sides made the same change.
+/// Like git, the lines that both sides of a conflict have in common are moved out of it,
+/// and then conflicts separated by only a few lines (or lines without any letters or
+/// digits) are combined.
+pub fn merge_blobs(base: &[u8], ours: &[u8], theirs: &[u8], options: &MergeOptions) -> MergeResult {
+    let base_lines = diff::split_lines(base);
+    let our_lines = diff::split_lines(ours);
+    let their_lines = diff::split_lines(theirs);
+
+    let mut chunks = merge_lines(&base_lines, &our_lines, &their_lines, &options.diff);
+
+    // Showing the base alongside a refined conflict would be misleading
+    if options.style == ConflictStyle::Merge {
+        chunks = refine_conflicts(chunks, &options.diff);
+        chunks = combine_conflicts(chunks);
+    }
+
+    let mut writer = MergeWriter {
+        options,
+        data: Vec::new(),
+        conflicts: 0,
+    };
+    for chunk in chunks {
+        match chunk {
+            Chunk::Unchanged(lines) | Chunk::Changed(lines) => writer.write_lines(&lines),
+            Chunk::Conflict { ours, base, theirs } => writer.write_conflict(&ours, &base, &theirs),
+        }
+    }
+
+    MergeResult {
+        data: writer.data,
+        conflicts: writer.conflicts,
+    }
+}
+
+/// Divides the merged file into chunks that are unchanged, changed, or in conflict.
+/// Unchanged lines are taken from our version, same as git.
+fn merge_lines<'a>(base_lines: &[Line<'a>], our_lines: &[Line<'a>], their_lines: &[Line<'a>], options: &DiffOptions) -> Vec<Chunk<'a>> {
+    let our_edits = diff::diff_lines(base_lines, our_lines, options);
+    let their_edits = diff::diff_lines(base_lines, their_lines, options);
+
+    let mut chunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut base_pos = 0;
+    // The position in our version that corresponds to `base_pos`
+    let mut our_pos = 0;
+
+    loop {
+        let start = match (our_edits.get(i), their_edits.get(j)) {
+            (None, None) => break,
+            (Some(ours), None) => ours.old_start,
+            (None, Some(theirs)) => theirs.old_start,
+            (Some(ours), Some(theirs)) => ours.old_start.min(theirs.old_start),
+        };
+
+        // Grow the region until no more edits overlap it
+        let (first_ours, first_theirs) = (i, j);
+        let mut end = start;
+        loop {
+            if let Some(edit) = our_edits.get(i).filter(|edit| edit.old_start <= end) {
+                end = end.max(edit.old_end());
+                i += 1;
+            }
+            else if let Some(edit) = their_edits.get(j).filter(|edit| edit.old_start <= end) {
+                end = end.max(edit.old_end());
+                j += 1;
+            }
+            else {
+                break;
+            }
+        }
+
+        let unchanged_len = start - base_pos;
+        push_unchanged(&mut chunks, &our_lines[our_pos..our_pos + unchanged_len]);
+
+        let ours = side_lines(&our_edits[first_ours..i], start, end, our_lines);
+        let theirs = side_lines(&their_edits[first_theirs..j], start, end, their_lines);
+        our_pos += unchanged_len + ours.map_or(end - start, <[_]>::len);
+
+        chunks.push(match (ours, theirs) {
+            (Some(ours), None) => Chunk::Changed(ours.to_vec()),
+            (None, Some(theirs)) => Chunk::Changed(theirs.to_vec()),
+            (Some(ours), Some(theirs)) if lines_equal(ours, theirs, options) => Chunk::Changed(ours.to_vec()),
+            (Some(ours), Some(theirs)) => Chunk::Conflict {
+                ours: ours.to_vec(),
+                base: base_lines[start..end].to_vec(),
+                theirs: theirs.to_vec(),
+            },
+            (None, None) => unreachable!("a region should contain at least one edit"),
+        });
+
+        base_pos = end;
+    }
+
+    push_unchanged(&mut chunks, &our_lines[our_pos..]);
+
+    chunks
+}
+
+fn lines_equal(a: &[Line], b: &[Line], options: &DiffOptions) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| options.lines_equal(a, b))
+}
+
+/// Appends unchanged lines to `chunks`, extending the last chunk if it's also unchanged.
+fn push_unchanged<'a>(chunks: &mut Vec<Chunk<'a>>, lines: &[Line<'a>]) {
+    if lines.is_empty() {
+        return;
+    }
+
+    match chunks.last_mut() {
+        Some(Chunk::Unchanged(existing)) => existing.extend_from_slice(lines),
+        _ => chunks.push(Chunk::Unchanged(lines.to_vec())),
+    }
+}
+
+/// Splits each conflict into the lines that both sides have in common and smaller
+/// conflicts, by diffing our side against their side.
+fn refine_conflicts<'a>(chunks: Vec<Chunk<'a>>, options: &DiffOptions) -> Vec<Chunk<'a>> {
+    let mut refined = Vec::new();
+
+    for chunk in chunks {
+        let (ours, theirs) = match &chunk {
+            Chunk::Conflict { ours, theirs, .. } if !ours.is_empty() && !theirs.is_empty() => (ours, theirs),
+            _ => {
+                refined.push(chunk);
+                continue;
+            },
+        };
+
+        let mut our_pos = 0;
+        for edit in diff::diff_lines(ours, theirs, options) {
+            push_unchanged(&mut refined, &ours[our_pos..edit.old_start]);
+            refined.push(Chunk::Conflict {
+                ours: ours[edit.old_start..edit.old_end()].to_vec(),
+                base: Vec::new(),
+                theirs: theirs[edit.new_start..edit.new_end()].to_vec(),
+            });
+            our_pos = edit.old_end();
+        }
+        push_unchanged(&mut refined, &ours[our_pos..]);
+    }
+
+    refined
+}
+
+/// Combines conflicts that are separated by at most 3 unchanged lines, or by unchanged
+/// lines that don't contain any letters or digits.
+fn combine_conflicts(chunks: Vec<Chunk<'_>>) -> Vec<Chunk<'_>> {
+    let mut combined: Vec<Chunk> = Vec::new();
+
+    for chunk in chunks {
+        if let Chunk::Conflict { ours: next_ours, base: next_base, theirs: next_theirs } = &chunk {
+            if let [.., Chunk::Conflict { .. }, Chunk::Unchanged(gap)] = &combined[..] {
+                let is_close = gap.len() <= 3
+                    || !gap.iter().flat_map(|line| line.iter()).any(u8::is_ascii_alphanumeric);
+
+                if is_close {
+                    let Some(Chunk::Unchanged(gap)) = combined.pop() else {
+                        unreachable!("the last chunk should be unchanged lines");
+                    };
+                    let Some(Chunk::Conflict { ours, base, theirs }) = combined.last_mut() else {
+                        unreachable!("the second-to-last chunk should be a conflict");
+                    };
+                    ours.extend(gap.iter().chain(next_ours));
+                    base.extend(gap.iter().chain(next_base));
+                    theirs.extend(gap.iter().chain(next_theirs));
+                    continue;
+                }
+            }
+        }
+
+        combined.push(chunk);
+    }
+
+    combined
+}
+
+/// Finds the lines of one side that correspond to `base[start..end]`, given the side's
+/// edits within that region. Returns `None` if the side didn't change the region.
+fn side_lines<'a, 'b>(edits: &[Edit], start: usize, end: usize, lines: &'b [Line<'a>]) -> Option<&'b [Line<'a>]> {
+    let first = edits.first()?;
+    let last = edits.last()?;
+
+    let side_start = first.new_start - (first.old_start - start);
+    let side_end = last.new_end() + (end - last.old_end());
+
+    Some(&lines[side_start..side_end])
+}
+
+struct MergeWriter<'a> {
+    options: &'a MergeOptions,
+    data: Vec<u8>,
+    conflicts: usize,
+}
+
+impl MergeWriter<'_> {
+    fn write_lines(&mut self, lines: &[Line]) {
+        for line in lines {
+            self.data.extend_from_slice(line);
+        }
+    }
+
+    fn write_conflict(&mut self, ours: &[Line], base: &[Line], theirs: &[Line]) {
+        match self.options.favor {
+            Some(Favor::Ours) => return self.write_lines(ours),
+            Some(Favor::Theirs) => return self.write_lines(theirs),
+            Some(Favor::Union) => {
+                self.write_lines(ours);
+                self.end_line();
+                return self.write_lines(theirs);
+            },
+            None => (),
+        }
+
+        self.conflicts += 1;
+        self.write_marker('<', &self.options.ours_label);
+        self.write_lines(ours);
+        if self.options.style == ConflictStyle::Diff3 {
+            self.write_marker('|', &self.options.base_label);
+            self.write_lines(base);
+        }
+        self.write_marker('=', "");
+        self.write_lines(theirs);
+        self.write_marker('>', &self.options.theirs_label);
+    }
+
+    fn write_marker(&mut self, ch: char, label: &str) {
+        self.end_line();
+
+        let marker = ch.to_string().repeat(self.options.marker_size);
+        let line = match label {
+            "" => format!("{marker}\n"),
+            label => format!("{marker} {label}\n"),
+        };
+        self.data.extend_from_slice(line.as_bytes());
+    }
+
+    /// Makes sure the output ends with a newline (unless it's empty).
+    fn end_line(&mut self) {
+        if self.data.last().is_some_and(|byte| *byte != b'\n') {
+            self.data.push(b'\n');
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MergeError {
+    #[error("Not possible to fast-forward, aborting.")]
+    NotFastForward,
+    #[error("The histories have diverged, and only fast-forward merges are supported.")]
+    Diverged,
+    #[error("The index has unmerged entries. Resolve them first.")]
+    UnmergedIndex,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(base: &str, ours: &str, theirs: &str, options: &MergeOptions) -> (String, usize) {
+        let result = merge_blobs(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), options);
+        (String::from_utf8(result.data).unwrap(), result.conflicts)
+    }
+
+    #[test]
+    fn reads_fast_forward_policy() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.join(".git")).unwrap();
+        std::fs::write(temp_dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        for (value, expected) in [("", Some(FastForward::Allow)), ("only", Some(FastForward::Only)), ("false", Some(FastForward::Never)), ("sometimes", None)] {
+            let config = match value {
+                "" => "[core]\n\trepositoryformatversion = 0\n".to_owned(),
+                value => format!("[core]\n\trepositoryformatversion = 0\n[merge]\n\tff = {value}\n"),
+            };
+            std::fs::write(temp_dir.join(".git/config"), config).unwrap();
+            let repo = Repository::find(temp_dir.path()).unwrap();
+            assert_eq!(FastForward::from_config(&repo).ok(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn merges_separate_changes() {
+        let base = "a\nb\nc\nd\ne\n";
+        let ours = "A\nb\nc\nd\ne\n";
+        let theirs = "a\nb\nc\nd\nE\nf\n";
+
+        assert_eq!(merge(base, ours, theirs, &MergeOptions::default()), ("A\nb\nc\nd\nE\nf\n".to_owned(), 0));
+        assert_eq!(merge(base, ours, ours, &MergeOptions::default()), (ours.to_owned(), 0));
+    }
+
+    #[test]
+    fn marks_conflicts() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nx\nsame\nc\n";
+        let theirs = "a\ny\nsame\nc\n";
+
+        let expected = "a\n<<<<<<< ours\nx\n=======\ny\n>>>>>>> theirs\nsame\nc\n";
+        assert_eq!(merge(base, ours, theirs, &MergeOptions::default()), (expected.to_owned(), 1));
+
+        let options = MergeOptions {
+            style: ConflictStyle::Diff3,
+            marker_size: 3,
+            ..MergeOptions::default()
+        };
+        let expected = "a\n<<< ours\nx\nsame\n||| base\nb\n===\ny\nsame\n>>> theirs\nc\n";
+        assert_eq!(merge(base, ours, theirs, &options), (expected.to_owned(), 1));
+    }
+
+    #[test]
+    fn ignores_whitespace_changes() {
+        let base = "a\nb\nc\n";
+        let ours = "a \nb\nc\n";
+        let theirs = "a\nb  \nC\n";
+
+        let options = MergeOptions {
+            diff: DiffOptions { ignore_space_change: true, ..DiffOptions::default() },
+            ..MergeOptions::default()
+        };
+        assert_eq!(merge(base, ours, theirs, &options), ("a \nb\nC\n".to_owned(), 0));
+        assert_eq!(merge(base, ours, theirs, &MergeOptions::default()).1, 1);
+    }
+
+    #[test]
+    fn favors_a_side() {
+        let base = "a\n";
+        let ours = "b";
+        let theirs = "c\n";
+
+        let options = |favor| MergeOptions { favor: Some(favor), ..MergeOptions::default() };
+        assert_eq!(merge(base, ours, theirs, &options(Favor::Ours)), ("b".to_owned(), 0));
+        assert_eq!(merge(base, ours, theirs, &options(Favor::Theirs)), ("c\n".to_owned(), 0));
+        assert_eq!(merge(base, ours, theirs, &options(Favor::Union)), ("b\nc\n".to_owned(), 0));
+    }
+}
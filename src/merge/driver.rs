@@ -0,0 +1,247 @@
+use std::{fs, process::Command};
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    repo::Repository,
+    attributes::{AttrValue, PathAttributes},
+    workdir::WorkPath,
+};
+
+use super::{Favor, MergeOptions, MergeResult};
+
+/// How the contents of a file are merged, chosen by its `merge` attribute.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MergeDriver {
+    /// The usual line-based merge (`merge` or `merge=text`).
+    Text,
+    /// Take our version and report a conflict (`-merge` or `merge=binary`).
+    Binary,
+    /// Keep the lines from both sides instead of reporting conflicts (`merge=union`).
+    Union,
+    /// Run a command configured in `merge.<name>.driver`.
+    Custom {
+        name: String,
+        command: String,
+    },
+}
+
+impl MergeDriver {
+    /// Chooses the driver for a file with attributes `attrs`. When the `merge` attribute
+    /// is unspecified, `merge.default` is used, falling back to the text driver.
+    pub fn for_attributes(repo: &Repository, attrs: &PathAttributes) -> Result<Self> {
+        match attrs.get("merge") {
+            AttrValue::Set => Ok(Self::Text),
+            AttrValue::Unset => Ok(Self::Binary),
+            AttrValue::Value(name) => Self::named(repo, name),
+            AttrValue::Unspecified => match repo.get_config("merge", "default") {
+                Some(name) => Self::named(repo, name),
+                None => Ok(Self::Text),
+            },
+        }
+    }
+
+    /// Looks up a driver by name: either a built-in one or one configured in
+    /// `merge.<name>.driver`.
+    pub fn named(repo: &Repository, name: &str) -> Result<Self> {
+        match name {
+            "text" => Ok(Self::Text),
+            "binary" => Ok(Self::Binary),
+            "union" => Ok(Self::Union),
+            name => {
+                let section = format!(r#"merge "{name}""#);
+                match repo.get_config(&section, "driver") {
+                    Some(command) => Ok(Self::Custom {
+                        name: name.to_owned(),
+                        command: command.to_owned(),
+                    }),
+                    None => Err(MergeDriverError::UnknownDriver(name.to_owned()).into()),
+                }
+            },
+        }
+    }
+
+    /// Merges the three versions of the file at `path` with this driver.
+    pub fn merge(&self, path: &WorkPath, base: &[u8], ours: &[u8], theirs: &[u8], options: &MergeOptions) -> Result<MergeResult> {
+        match self {
+            Self::Text => Ok(super::merge_blobs(base, ours, theirs, options)),
+            Self::Binary => Ok(merge_binary(base, ours, theirs)),
+            Self::Union => {
+                let options = MergeOptions {
+                    favor: Some(Favor::Union),
+                    ..options.clone()
+                };
+                Ok(super::merge_blobs(base, ours, theirs, &options))
+            },
+            Self::Custom { name, command } => run_custom(name, command, path, base, ours, theirs, options),
+        }
+    }
+}
+
+/// Merges files that can't be merged line by line. Unless only one side changed the file
+/// (or both changed it the same way), the result is our version with a conflict.
+fn merge_binary(base: &[u8], ours: &[u8], theirs: &[u8]) -> MergeResult {
+    let (data, conflicts) = if ours == theirs || base == theirs {
+        (ours, 0)
+    }
+    else if base == ours {
+        (theirs, 0)
+    }
+    else {
+        (ours, 1)
+    };
+
+    MergeResult {
+        data: data.to_vec(),
+        conflicts,
+    }
+}
+
+/// Runs a custom merge driver.
+///
+/// The versions are written to temporary files, and the placeholders in `command` are
+/// replaced as in git: `%O` (base), `%A` (ours, which receives the result), `%B` (theirs),
+/// `%L` (marker size), `%P` (path), and `%S`, `%X`, `%Y` (the base, our, and their
+/// labels). Each value is single-quoted, as git does, so paths and labels can't inject
+/// shell syntax. A nonzero exit status means the driver left conflicts.
+fn run_custom(name: &str, command: &str, path: &WorkPath, base: &[u8], ours: &[u8], theirs: &[u8], options: &MergeOptions) -> Result<MergeResult> {
+    // A private directory, which is removed along with the files when dropped
+    let temp_dir = tempfile::Builder::new().prefix("wyag_merge_").tempdir()?;
+    let base_path = temp_dir.path().join("base");
+    let ours_path = temp_dir.path().join("ours");
+    let theirs_path = temp_dir.path().join("theirs");
+
+    fs::write(&base_path, base)?;
+    fs::write(&ours_path, ours)?;
+    fs::write(&theirs_path, theirs)?;
+
+    let mut expanded = String::new();
+    let mut chars = command.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            expanded.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('O') => expanded.push_str(&sq_quote(&base_path.to_string_lossy())),
+            Some('A') => expanded.push_str(&sq_quote(&ours_path.to_string_lossy())),
+            Some('B') => expanded.push_str(&sq_quote(&theirs_path.to_string_lossy())),
+            Some('L') => expanded.push_str(&options.marker_size.to_string()),
+            Some('P') => expanded.push_str(&sq_quote(path.as_str())),
+            Some('S') => expanded.push_str(&sq_quote(&options.base_label)),
+            Some('X') => expanded.push_str(&sq_quote(&options.ours_label)),
+            Some('Y') => expanded.push_str(&sq_quote(&options.theirs_label)),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            },
+            None => expanded.push('%'),
+        }
+    }
+
+    let status = Command::new("sh")
+        .args(["-c", &expanded])
+        .status()
+        .map_err(|err| MergeDriverError::DriverFailed {
+            name: name.to_owned(),
+            reason: err.to_string(),
+        });
+    let data = fs::read(&ours_path);
+    temp_dir.close()?;
+
+    Ok(MergeResult {
+        data: data?,
+        conflicts: usize::from(!status?.success()),
+    })
+}
+
+/// Quotes `value` for the shell the way git's `sq_quote_buf` does: inside single quotes,
+/// with each `'` (and `!`, for shells with history expansion) written as `'\''`.
+fn sq_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for ch in value.chars() {
+        match ch {
+            '\'' | '!' => {
+                quoted.push_str("'\\");
+                quoted.push(ch);
+                quoted.push('\'');
+            },
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('\'');
+
+    quoted
+}
+
+#[derive(Error, Debug)]
+pub enum MergeDriverError {
+    #[error("No merge driver named `{0}` (set merge.{0}.driver)")]
+    UnknownDriver(String),
+    #[error("Failed to run merge driver `{name}`: {reason}")]
+    DriverFailed {
+        name: String,
+        reason: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workdir::WorkPathBuf;
+
+    #[test]
+    fn builtin_drivers() {
+        let path = WorkPathBuf::try_from("file.bin").unwrap();
+        let options = MergeOptions::default();
+
+        let result = MergeDriver::Binary.merge(&path, b"a", b"a", b"b", &options).unwrap();
+        assert_eq!((result.data, result.conflicts), (b"b".to_vec(), 0));
+        let result = MergeDriver::Binary.merge(&path, b"a", b"b", b"c", &options).unwrap();
+        assert_eq!((result.data, result.conflicts), (b"b".to_vec(), 1));
+
+        let result = MergeDriver::Union.merge(&path, b"a\n", b"b\n", b"c\n", &options).unwrap();
+        assert_eq!((result.data, result.conflicts), (b"b\nc\n".to_vec(), 0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn custom_driver_substitutes_placeholders() {
+        let path = WorkPathBuf::try_from("CHANGELOG").unwrap();
+        let driver = MergeDriver::Custom {
+            name: "test".to_owned(),
+            command: "printf '%P %L ' > %A.tmp && cat %B >> %A.tmp && mv %A.tmp %A && false".to_owned(),
+        };
+
+        let result = driver.merge(&path, b"base", b"ours", b"theirs", &MergeOptions::default()).unwrap();
+        assert_eq!(result.data, b"CHANGELOG 7 theirs");
+        assert_eq!(result.conflicts, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn custom_driver_quotes_placeholders() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let marker = temp_dir.join("PWNED");
+        let path = WorkPathBuf::try_from(format!("x;touch {};'$(true)'!", marker.display()).as_str()).unwrap();
+        let driver = MergeDriver::Custom {
+            name: "test".to_owned(),
+            command: "printf '%%s' %P > %A".to_owned(),
+        };
+
+        let result = driver.merge(&path, b"base", b"ours", b"theirs", &MergeOptions::default()).unwrap();
+        assert_eq!(result.data, path.as_str().as_bytes());
+        assert_eq!(result.conflicts, 0);
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn sq_quote_escapes_quotes() {
+        assert_eq!(sq_quote("a b"), "'a b'");
+        assert_eq!(sq_quote("it's!"), r"'it'\''s'\!''");
+    }
+}
@@ -0,0 +1,157 @@
+//! The three-way merge of `read-tree -m`, which decides each path's index entry from the
+//! three trees alone. Contents aren't merged and the working tree isn't touched; that's left
+//! to whatever runs the merge.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    Result,
+    index::{Index, IndexEntry},
+    object::Tree,
+    workdir::{WorkDir, WorkPathBuf},
+};
+
+use super::MergeError;
+
+/// The result of [`merge_index`].
+pub struct IndexMerge {
+    pub index: Index,
+    /// The paths left unmerged, in order.
+    pub conflicts: Vec<WorkPathBuf>,
+}
+
+/// Merges the changes from `base` to `theirs` into `ours`, path by path, as git's trivial
+/// merge does: a path that only one side changed takes that side's entry, and a path that
+/// both sides changed the same way is merged too. Any other path is a conflict.
+///
+/// A conflict records the base, ours, and theirs entries that exist as stages 1, 2, and 3.
+/// A file that collides with a directory on the other side is a conflict as well.
+///
+/// Entries that match `current` keep its stats, so unchanged files don't look modified.
+/// Fails if `current` has conflicts of its own.
+pub fn merge_index(wd: &WorkDir, current: &Index, base: &Tree, ours: &Tree, theirs: &Tree) -> Result<IndexMerge> {
+    if current.conflicted_paths().next().is_some() {
+        return Err(MergeError::UnmergedIndex.into());
+    }
+
+    let base = base.to_index(wd, None)?;
+    let ours = ours.to_index(wd, None)?;
+    let theirs = theirs.to_index(wd, None)?;
+
+    let paths: BTreeSet<_> = base.entries.keys()
+        .chain(ours.entries.keys())
+        .chain(theirs.entries.keys())
+        .collect();
+
+    let mut merged = Vec::new();
+    let mut conflicts = BTreeSet::new();
+    for path in paths {
+        let base_entry = base.entries.get(path);
+        let our_entry = ours.entries.get(path);
+        let their_entry = theirs.entries.get(path);
+
+        let entry = if same_entry(our_entry, their_entry) || same_entry(base_entry, their_entry) {
+            our_entry
+        }
+        else if same_entry(base_entry, our_entry) {
+            their_entry
+        }
+        else {
+            conflicts.insert(path.clone());
+            our_entry.or(their_entry)
+        };
+
+        if let Some(entry) = entry {
+            merged.push((path.clone(), entry.clone()));
+        }
+    }
+
+    // A file can't stay where the other side has a directory
+    let files: BTreeSet<_> = merged.iter().map(|(path, _)| path.clone()).collect();
+    for (path, _) in &merged {
+        let mut dir = path.parent();
+        while let Some(current) = dir.filter(|dir| !dir.is_empty()) {
+            if files.contains(current) {
+                conflicts.insert(current.to_owned());
+            }
+            dir = current.parent();
+        }
+    }
+
+    let mut index = Index::new(Some(current.version));
+    for (path, entry) in merged {
+        if conflicts.contains(&path) {
+            let stages = [&base, &ours, &theirs].map(|side| side.entries.get(&path).cloned());
+            index.insert_conflict(path, stages);
+            continue;
+        }
+        let entry = match current.entries.get(&path) {
+            Some(current) if same_entry(Some(current), Some(&entry)) => current.clone(),
+            _ => entry,
+        };
+        index.insert_entry(path, entry);
+    }
+
+    Ok(IndexMerge { index, conflicts: conflicts.into_iter().collect() })
+}
+
+fn same_entry(a: Option<&IndexEntry>, b: Option<&IndexEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.hash == b.hash && a.stats.get_mode().ok() == b.stats.get_mode().ok(),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{
+        object::{Blob, FileMode, GitObject, TreeEntry},
+        test_util::temp_workdir,
+    };
+
+    fn tree(wd: &WorkDir, files: &[(&str, &str)]) -> Tree {
+        let entries: BTreeMap<_, _> = files.iter()
+            .map(|(name, data)| {
+                let blob = GitObject::Blob(Blob::deserialize(data.as_bytes().to_vec()).unwrap());
+                let entry = TreeEntry { mode: FileMode::Regular, hash: blob.write(wd).unwrap() };
+                (WorkPathBuf::try_from(*name).unwrap(), entry)
+            })
+            .collect();
+        Tree { entries }
+    }
+
+    #[test]
+    fn merges_trivially() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        let base = tree(&wd, &[("same", "1"), ("ours", "1"), ("theirs", "1"), ("both", "1"), ("gone", "1"), ("deleted_by_us", "1")]);
+        let ours = tree(&wd, &[("same", "1"), ("ours", "2"), ("theirs", "1"), ("both", "2"), ("added", "x")]);
+        let theirs = tree(&wd, &[("same", "1"), ("ours", "1"), ("theirs", "2"), ("both", "3"), ("added", "x"), ("deleted_by_us", "2")]);
+
+        let merge = merge_index(&wd, &Index::new(None), &base, &ours, &theirs).unwrap();
+        let entries: Vec<_> = merge.index.entries.iter()
+            .map(|(path, entry)| (path.as_str(), entry.flags.get_stage()))
+            .collect();
+        assert_eq!(entries, [("added", 0), ("both", 2), ("deleted_by_us", 3), ("ours", 0), ("same", 0), ("theirs", 0)]);
+        assert_eq!(merge.index.entries[&WorkPathBuf::try_from("ours").unwrap()].hash, ours.entries[&WorkPathBuf::try_from("ours").unwrap()].hash);
+        assert_eq!(merge.index.entries[&WorkPathBuf::try_from("theirs").unwrap()].hash, theirs.entries[&WorkPathBuf::try_from("theirs").unwrap()].hash);
+
+        let both = WorkPathBuf::try_from("both").unwrap();
+        let stages: Vec<_> = merge.index.conflict_stages[&both].iter()
+            .map(|entry| (entry.flags.get_stage(), entry.hash))
+            .collect();
+        assert_eq!(stages, [(1, base.entries[&both].hash), (2, ours.entries[&both].hash), (3, theirs.entries[&both].hash)]);
+        let deleted_by_us = WorkPathBuf::try_from("deleted_by_us").unwrap();
+        let stages: Vec<_> = merge.index.conflict_stages[&deleted_by_us].iter().map(|entry| entry.flags.get_stage()).collect();
+        assert_eq!(stages, [1, 3]);
+
+        let conflicts: Vec<_> = merge.conflicts.iter().map(WorkPathBuf::as_str).collect();
+        assert_eq!(conflicts, ["both", "deleted_by_us"]);
+
+        assert!(merge_index(&wd, &merge.index, &base, &ours, &theirs).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! Merge tools: programs for resolving a conflicted file by hand, configured in
+//! `mergetool.<name>.cmd` and chosen with merge.tool.
+
+use std::{
+    fs,
+    path::PathBuf,
+    process::Command,
+};
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    index::Index,
+    object::Blob,
+    repo::Repository,
+    workdir::{WorkDir, WorkPath},
+};
+
+/// A configured merge tool.
+pub struct MergeTool {
+    pub name: String,
+    /// A shell command, which can refer to the files as `$BASE`, `$LOCAL`, `$REMOTE`, and
+    /// `$MERGED`.
+    pub command: String,
+    /// Whether the exit status says if the conflict was resolved. Otherwise, it's resolved
+    /// if the tool changed the merged file.
+    pub trust_exit_code: bool,
+}
+
+/// The versions of a conflicted file. A version is `None` if that side doesn't have the file.
+pub struct ConflictVersions {
+    pub base: Option<Vec<u8>>,
+    pub ours: Option<Vec<u8>>,
+    pub theirs: Option<Vec<u8>>,
+}
+
+impl MergeTool {
+    /// Looks up the tool called `name`, or the one in merge.tool if no name is given.
+    pub fn from_config(repo: &Repository, name: Option<&str>) -> Result<Self> {
+        let name = match name.or_else(|| repo.get_config("merge", "tool")) {
+            Some(name) => name,
+            None => return Err(MergeToolError::NotConfigured.into()),
+        };
+
+        let section = format!(r#"mergetool "{name}""#);
+        let Some(command) = repo.get_config(&section, "cmd") else {
+            return Err(MergeToolError::UnknownTool(name.to_owned()).into());
+        };
+
+        Ok(Self {
+            name: name.to_owned(),
+            command: command.to_owned(),
+            trust_exit_code: repo.get_config_bool(&section, "trustExitCode").unwrap_or(false),
+        })
+    }
+
+    /// Runs the tool on the conflicted file at `path`. The versions are written next to it,
+    /// named like git's (e.g. `main_BASE_1234.rs`), and removed afterward. Returns true if
+    /// the tool resolved the conflict.
+    pub fn run(&self, wd: &WorkDir, path: &WorkPath, versions: &ConflictVersions) -> Result<bool> {
+        let temp_files = [
+            ("BASE", temp_path(path, "BASE"), &versions.base),
+            ("LOCAL", temp_path(path, "LOCAL"), &versions.ours),
+            ("REMOTE", temp_path(path, "REMOTE"), &versions.theirs),
+        ];
+        for (_, temp_path, data) in &temp_files {
+            let data = data.as_deref().unwrap_or_default();
+            fs::write(wd.as_path().join(temp_path), data)?;
+        }
+
+        let merged_path = wd.abs_path(path);
+        let before = fs::read(&merged_path).ok();
+
+        let mut command = Command::new("sh");
+        command.args(["-c", &self.command])
+            .current_dir(wd.as_path())
+            .env("MERGED", path.as_str());
+        for (name, temp_path, _) in &temp_files {
+            command.env(name, temp_path);
+        }
+        let status = command.status()
+            .map_err(|err| MergeToolError::CouldNotRun { name: self.name.clone(), reason: err.to_string() });
+
+        for (_, temp_path, _) in &temp_files {
+            fs::remove_file(wd.as_path().join(temp_path))?;
+        }
+
+        let resolved = match self.trust_exit_code {
+            true => status?.success(),
+            false => status?.success() && fs::read(&merged_path).ok() != before,
+        };
+
+        Ok(resolved)
+    }
+}
+
+impl ConflictVersions {
+    /// Reads the versions of the conflicted file at `path` from its stages in `index`.
+    pub fn from_index(wd: &WorkDir, index: &Index, path: &WorkPath) -> Result<Self> {
+        let read = |stage: u16| -> Result<Option<Vec<u8>>> {
+            match index.conflict_stage(path, stage) {
+                Some(entry) if !entry.is_gitlink() => Ok(Some(Blob::read(wd, &entry.hash)?.serialize_into())),
+                _ => Ok(None),
+            }
+        };
+
+        Ok(Self {
+            base: read(1)?,
+            ours: read(2)?,
+            theirs: read(3)?,
+        })
+    }
+}
+
+/// Names the file for one version of the file at `path`, relative to the root of the
+/// working tree: `src/main_BASE_1234.rs` for `src/main.rs`.
+fn temp_path(path: &WorkPath, label: &str) -> PathBuf {
+    let (dir, name) = match path.as_str().rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, path.as_str()),
+    };
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let name = format!("{stem}_{label}_{}{extension}", std::process::id());
+
+    match dir {
+        Some(dir) => PathBuf::from(dir).join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MergeToolError {
+    #[error("No merge tool configured (set merge.tool or use --tool)")]
+    NotConfigured,
+    #[error("No merge tool named `{0}` (set mergetool.{0}.cmd)")]
+    UnknownTool(String),
+    #[error("Failed to run merge tool `{name}`: {reason}")]
+    CouldNotRun {
+        name: String,
+        reason: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        index::IndexEntry,
+        object::{FileMode, GitObject, TreeEntry},
+        test_util::temp_workdir,
+        workdir::WorkPathBuf,
+    };
+
+    #[test]
+    fn names_temp_files_like_git() {
+        let name = |path: &str| {
+            let path = WorkPathBuf::try_from(path).unwrap();
+            temp_path(&path, "BASE").to_string_lossy().replace(&std::process::id().to_string(), "N")
+        };
+        assert_eq!(name("src/main.rs"), format!("src{}main_BASE_N.rs", std::path::MAIN_SEPARATOR));
+        assert_eq!(name("Makefile"), "Makefile_BASE_N");
+        assert_eq!(name(".gitignore"), ".gitignore_BASE_N");
+        assert_eq!(name("archive.tar.gz"), "archive.tar_BASE_N.gz");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_tool_on_versions() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let wd = WorkDir::new(temp_dir.path()).unwrap();
+        let path = WorkPathBuf::try_from("file.txt").unwrap();
+        fs::write(temp_dir.join("file.txt"), "<<<<<<< conflict\n").unwrap();
+
+        let versions = ConflictVersions {
+            base: None,
+            ours: Some(b"ours\n".to_vec()),
+            theirs: Some(b"theirs\n".to_vec()),
+        };
+        let tool = MergeTool {
+            name: "test".to_owned(),
+            command: r#"cat "$LOCAL" "$REMOTE" "$BASE" > "$MERGED""#.to_owned(),
+            trust_exit_code: false,
+        };
+        assert!(tool.run(&wd, &path, &versions).unwrap());
+        assert_eq!(fs::read_to_string(temp_dir.join("file.txt")).unwrap(), "ours\ntheirs\n");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        // Leaving the file alone doesn't resolve it, unless the exit status is trusted
+        let tool = MergeTool { command: "true".to_owned(), ..tool };
+        assert!(!tool.run(&wd, &path, &versions).unwrap());
+        let tool = MergeTool { trust_exit_code: true, ..tool };
+        assert!(tool.run(&wd, &path, &versions).unwrap());
+    }
+
+    #[test]
+    fn reads_versions_from_index_stages() {
+        let (_temp_dir, wd) = temp_workdir();
+        let path = WorkPathBuf::try_from("file.txt").unwrap();
+        let stage = |data: &str| {
+            let hash = GitObject::Blob(Blob::deserialize(data.as_bytes().to_vec()).unwrap()).write(&wd).unwrap();
+            Some(IndexEntry::from_tree_entry(&path, &TreeEntry { mode: FileMode::Regular, hash }))
+        };
+
+        let mut index = Index::new(None);
+        index.insert_conflict(path.clone(), [None, stage("ours\n"), stage("theirs\n")]);
+        let versions = ConflictVersions::from_index(&wd, &index, &path).unwrap();
+        assert_eq!(versions.base, None);
+        assert_eq!(versions.ours.as_deref(), Some(&b"ours\n"[..]));
+        assert_eq!(versions.theirs.as_deref(), Some(&b"theirs\n"[..]));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::{
+    Result,
+    attributes::PathAttributes,
+    index::{Index, IndexEntry},
+    object::{Blob, GitObject, Tree, TreeChange, TreeEntry},
+    repo::Repository,
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+};
+
+use super::{MergeDriver, MergeOptions};
+
+/// The result of merging the changes from `base` to `theirs` into `ours`.
+pub struct TreeMerge {
+    /// The changes to make to `ours`, including files that were merged cleanly.
+    pub changes: Vec<TreeChange>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A file that both sides changed in ways that couldn't be combined.
+pub struct Conflict {
+    pub path: WorkPathBuf,
+    pub kind: ConflictKind,
+    /// What to leave in the working tree: the merged contents with conflict markers, or the
+    /// version that wasn't deleted. `None` keeps our version.
+    pub worktree: Option<(TreeEntry, Vec<u8>)>,
+    /// The base, ours, and theirs versions, recorded as the conflict's stages in the index.
+    /// Directories are left out.
+    pub stages: [Option<TreeEntry>; 3],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictKind {
+    /// Both sides changed the contents of the file.
+    Content,
+    /// Both sides added the file with different contents.
+    AddAdd,
+    /// We deleted the file and they modified it.
+    DeletedByUs,
+    /// They deleted the file and we modified it.
+    DeletedByThem,
+    /// The sides have different kinds of entries, e.g. a file and a directory, so they
+    /// can't be merged at all.
+    DistinctTypes,
+}
+
+/// Merges the changes from `base` to `theirs` into `ours`, file by file. Files that only
+/// they changed are taken from `theirs`, and files that both sides changed are merged with
+/// their merge driver.
+pub fn merge_trees(repo: &Repository, base: &Tree, ours: &Tree, theirs: &Tree, options: &MergeOptions) -> Result<TreeMerge> {
+    let wd = repo.workdir();
+    let mut merge = TreeMerge {
+        changes: Vec::new(),
+        conflicts: Vec::new(),
+    };
+
+    for change in Tree::diff(wd, base, theirs)? {
+        let path = change.path().to_owned();
+        let base_entry = base.find_entry(wd, &path)?;
+        let our_entry = ours.find_entry(wd, &path)?;
+        let their_entry = match change {
+            TreeChange::Added { entry, .. } | TreeChange::Modified { entry, .. } => Some(entry),
+            TreeChange::Deleted { .. } => None,
+        };
+
+        if same_entry(&our_entry, &their_entry) {
+            continue;
+        }
+        if same_entry(&our_entry, &base_entry) {
+            merge.changes.push(match their_entry {
+                Some(entry) => match our_entry {
+                    Some(_) => TreeChange::Modified { path, entry },
+                    None => TreeChange::Added { path, entry },
+                },
+                None => TreeChange::Deleted { path },
+            });
+            continue;
+        }
+
+        let stages = [&base_entry, &our_entry, &their_entry]
+            .map(|entry| entry.clone().filter(|entry| !entry.is_dir()));
+        let (kind, worktree) = match (our_entry, their_entry) {
+            (Some(ours), Some(theirs)) if is_file(&ours) && is_file(&theirs) => {
+                let base_entry = base_entry.filter(is_file);
+                match merge_file(repo, &path, base_entry.as_ref(), &ours, &theirs, options)? {
+                    MergedFile::Clean(entry) => {
+                        merge.changes.push(TreeChange::Modified { path, entry });
+                        continue;
+                    },
+                    MergedFile::Conflicted(merged) => {
+                        let kind = if base_entry.is_some() { ConflictKind::Content } else { ConflictKind::AddAdd };
+                        (kind, Some(merged))
+                    },
+                }
+            },
+            (None, Some(theirs)) if is_file(&theirs) => {
+                let data = Blob::read(wd, &theirs.hash)?.serialize_into();
+                (ConflictKind::DeletedByUs, Some((theirs, data)))
+            },
+            (Some(ours), None) if is_file(&ours) => (ConflictKind::DeletedByThem, None),
+            _ => (ConflictKind::DistinctTypes, None),
+        };
+        merge.conflicts.push(Conflict { path, kind, worktree, stages });
+    }
+
+    Ok(merge)
+}
+
+impl TreeMerge {
+    /// Applies the merge to the working tree and `index`. Conflicted files are left in the
+    /// working tree (with conflict markers if they were merged), and the index records the
+    /// base, ours, and theirs versions of each as stages 1, 2, and 3.
+    pub fn apply(&self, wd: &WorkDir, index: &mut Index) -> Result<()> {
+        index.checkout_changes(wd, &self.changes)?;
+
+        for conflict in &self.conflicts {
+            let path = &conflict.path;
+            if let Some((entry, data)) = &conflict.worktree {
+                wd.check_worktree_path(path)?;
+                wd.write_worktree_file(path, data, entry.mode)?;
+            }
+
+            let stages = conflict.stages.each_ref()
+                .map(|entry| entry.as_ref().map(|entry| IndexEntry::from_tree_entry(path, entry)));
+            index.insert_conflict(path.to_owned(), stages);
+        }
+
+        Ok(())
+    }
+}
+
+enum MergedFile {
+    Clean(TreeEntry),
+    /// The entry and contents (with conflict markers) to leave in the working tree.
+    Conflicted((TreeEntry, Vec<u8>)),
+}
+
+/// Merges the contents of a file that both sides changed.
+fn merge_file(repo: &Repository, path: &WorkPath, base: Option<&TreeEntry>, ours: &TreeEntry, theirs: &TreeEntry, options: &MergeOptions) -> Result<MergedFile> {
+    let wd = repo.workdir();
+    let read = |entry: &TreeEntry| -> Result<Vec<u8>> {
+        Ok(Blob::read(wd, &entry.hash)?.serialize_into())
+    };
+
+    // A mode change on one side is kept, as long as the other side didn't change it too
+    let mode = match base {
+        Some(base) if ours.mode == base.mode => theirs.mode,
+        _ => ours.mode,
+    };
+    let base_data = base.map(read).transpose()?.unwrap_or_default();
+
+    let attrs = PathAttributes::resolve(wd, path)?;
+    let driver = MergeDriver::for_attributes(repo, &attrs)?;
+    let result = driver.merge(path, &base_data, &read(ours)?, &read(theirs)?, options)?;
+    let mode_conflict = ours.mode != theirs.mode && match base {
+        Some(base) => ours.mode != base.mode && theirs.mode != base.mode,
+        None => true,
+    };
+
+    match result.conflicts > 0 || mode_conflict {
+        true => Ok(MergedFile::Conflicted((TreeEntry { mode, hash: ours.hash }, result.data))),
+        false => {
+            let hash = GitObject::Blob(Blob::deserialize(result.data)?).write(wd)?;
+            Ok(MergedFile::Clean(TreeEntry { mode, hash }))
+        },
+    }
+}
+
+fn same_entry(a: &Option<TreeEntry>, b: &Option<TreeEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.hash == b.hash && a.mode == b.mode,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Returns true if the entry is a blob whose contents can be merged.
+fn is_file(entry: &TreeEntry) -> bool {
+    !entry.is_dir() && !entry.is_gitlink()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::object::FileMode;
+
+    fn tree(wd: &WorkDir, files: &[(&str, &str)]) -> Tree {
+        let entries: BTreeMap<_, _> = files.iter()
+            .map(|(name, data)| {
+                let blob = GitObject::Blob(Blob::deserialize(data.as_bytes().to_vec()).unwrap());
+                let entry = TreeEntry { mode: FileMode::Regular, hash: blob.write(wd).unwrap() };
+                (WorkPathBuf::try_from(*name).unwrap(), entry)
+            })
+            .collect();
+        Tree { entries }
+    }
+
+    fn read(wd: &WorkDir, entry: &TreeEntry) -> String {
+        String::from_utf8(Blob::read(wd, &entry.hash).unwrap().serialize_into()).unwrap()
+    }
+
+    #[test]
+    fn merges_changes_from_both_sides() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.join(".git")).unwrap();
+        std::fs::write(temp_dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(temp_dir.join(".git/config"), "[core]\n\trepositoryformatversion = 0\n").unwrap();
+        let repo = Repository::find(temp_dir.path()).unwrap();
+        let wd = repo.workdir();
+
+        let base = tree(wd, &[("a", "1\n2\n3\n"), ("b", "b\n"), ("c", "c\n")]);
+        let ours = tree(wd, &[("a", "1\n2\nthree\n"), ("c", "ours\n")]);
+        let theirs = tree(wd, &[("a", "one\n2\n3\n"), ("b", "theirs\n"), ("c", "c\n"), ("d", "new\n")]);
+        let merge = merge_trees(&repo, &base, &ours, &theirs, &MergeOptions::default()).unwrap();
+
+        // `c` only changed on our side, so it's left alone
+        let changes: Vec<_> = merge.changes.iter()
+            .map(|change| match change {
+                TreeChange::Added { path, entry } => format!("+{path} {}", read(wd, entry)),
+                TreeChange::Modified { path, entry } => format!("~{path} {}", read(wd, entry)),
+                TreeChange::Deleted { path } => format!("-{path}"),
+            })
+            .collect();
+        assert_eq!(changes, ["~a one\n2\nthree\n", "+d new\n"]);
+
+        let conflicts: Vec<_> = merge.conflicts.iter().map(|conflict| (conflict.path.as_str(), conflict.kind)).collect();
+        assert_eq!(conflicts, [("b", ConflictKind::DeletedByUs)]);
+        assert_eq!(merge.conflicts[0].worktree.as_ref().unwrap().1, b"theirs\n");
+
+        let theirs = tree(wd, &[("a", "1\n2\nTHREE\n")]);
+        let merge = merge_trees(&repo, &base, &ours, &theirs, &MergeOptions::default()).unwrap();
+        let conflicts: Vec<_> = merge.conflicts.iter().map(|conflict| (conflict.path.as_str(), conflict.kind)).collect();
+        assert_eq!(conflicts, [("a", ConflictKind::Content), ("c", ConflictKind::DeletedByThem)]);
+    }
+
+    #[test]
+    fn apply_records_conflict_stages() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.join(".git")).unwrap();
+        std::fs::write(temp_dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(temp_dir.join(".git/config"), "[core]\n\trepositoryformatversion = 0\n").unwrap();
+        let repo = Repository::find(temp_dir.path()).unwrap();
+        let wd = repo.workdir();
+
+        let base = tree(wd, &[("a", "1\n2\n3\n"), ("c", "c\n")]);
+        let ours = tree(wd, &[("a", "1\n2\nthree\n"), ("c", "ours\n")]);
+        let theirs = tree(wd, &[("a", "1\n2\nTHREE\n")]);
+        let merge = merge_trees(&repo, &base, &ours, &theirs, &MergeOptions::default()).unwrap();
+        let mut index = Index::new(None);
+        merge.apply(wd, &mut index).unwrap();
+
+        // The markers are only in the working tree; the index has each side's blob
+        let a = WorkPathBuf::try_from("a").unwrap();
+        assert!(std::fs::read_to_string(temp_dir.join("a")).unwrap().contains("<<<<<<<"));
+        let index = Index::parse(&index.serialize().unwrap()).unwrap();
+        let hashes: Vec<_> = (1..=3).map(|stage| index.conflict_stage(&a, stage).map(|entry| entry.hash)).collect();
+        assert_eq!(hashes, [Some(base.entries[&a].hash), Some(ours.entries[&a].hash), Some(theirs.entries[&a].hash)]);
+
+        let c = WorkPathBuf::try_from("c").unwrap();
+        let stages: Vec<_> = index.conflict_stages[&c].iter().map(|entry| entry.flags.get_stage()).collect();
+        assert_eq!(stages, [1, 2]);
+        assert_eq!(index.entries[&c].hash, ours.entries[&c].hash);
+
+        let conflicted: Vec<_> = index.conflicted_paths().map(WorkPathBuf::as_str).collect();
+        assert_eq!(conflicted, ["a", "c"]);
+    }
+}
@@ -0,0 +1,259 @@
+//! The text that commands show the user, looked up through a [`Catalog`] so that the CLI
+//! can be translated without changing the commands themselves.
+//!
+//! Each piece of text is a [`Message`], and displaying one looks it up in the catalog set
+//! with [`set_catalog`]. Anything a catalog doesn't translate falls back to English.
+//!
+//! Only the text commands write around their output is catalogued: the output itself
+//! (hashes, refs, diffs, logs) stays as it is, as does the help generated from the
+//! command-line options. Errors are shown whole through [`Message::Error`], so a catalog
+//! can translate an error's text but the individual error types aren't messages.
+
+use std::{
+    fmt,
+    path::Path,
+    sync::OnceLock,
+};
+
+use crate::{
+    merge::ConflictKind,
+    object::{ObjectFormat, ObjectHash, TreeProblem},
+    repo::InProgress,
+    sequencer::Action,
+    whitespace::WhitespaceErrorKind,
+    workdir::WorkPath,
+};
+
+/// Something to show the user, along with the values it refers to.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum Message<'a> {
+    /// A failed command's error.
+    Error(&'a anyhow::Error),
+
+    /// A file in a list of changes, e.g. `created:   src/main.rs`.
+    Change { kind: ChangeKind, path: &'a WorkPath },
+    /// A file renamed from `old_path` to `path` in a list of changes. Directories end with a
+    /// slash.
+    Rename { old_path: &'a str, path: &'a str },
+    /// An untracked directory with its own repository in a list of changes.
+    NestedRepo(&'a WorkPath),
+    /// A nested repository that `add` left out while adding a directory.
+    NestedRepoSkipped(&'a WorkPath),
+    UnmergedPaths,
+    UnmergedPathsHint,
+    ChangesStaged,
+    NoChangesStaged,
+    ChangesNotStaged,
+    NoUnstagedChanges,
+    ChangesSince(&'a str),
+    NoChangesSince(&'a str),
+
+    /// What operation is in progress, e.g. `You are currently merging.`
+    InProgress(InProgress),
+    /// How to finish the operation in progress.
+    ContinueHint { operation: InProgress, has_conflicts: bool },
+    /// How to abandon the operation in progress.
+    AbortHint(InProgress),
+
+    UnsupportedIndexExtensions,
+    WhitespaceWarning { path: &'a WorkPath, line: usize, kind: WhitespaceErrorKind },
+    AutoPackAdvice,
+    ConflictsFound(usize),
+    /// A file that couldn't be merged, e.g. `CONFLICT (content): Merge conflict in a.txt`.
+    MergeConflict { kind: ConflictKind, path: &'a WorkPath },
+    /// A commit that stopped with conflicts, shown by its abbreviated hash and subject.
+    CouldNotApply { action: Action, commit: &'a str, subject: &'a str },
+
+    Initialized(&'a Path),
+    CheckoutUnsupported,
+    /// The result of `copy-objects`.
+    ObjectsCopied { objects: usize, packs: usize, linked: usize, refs: usize },
+    /// A tag `copy-objects` left alone because it already points somewhere else.
+    SkippedTag(&'a str),
+    /// A conflict `mergetool` left for `add` or `rm`, since there's no file to merge.
+    MergetoolSkipped(&'a WorkPath),
+    MergetoolMerging(&'a WorkPath),
+    MergetoolFailed(&'a WorkPath),
+    NoLostCommits,
+    /// A stash entry removed by `stash drop`.
+    StashDropped { n: usize, hash: &'a ObjectHash },
+    /// The signer of a valid signature.
+    GoodSignature(&'a str),
+    /// A worktree `worktree prune` removes (or would remove), by its admin directory.
+    PruningWorktree { admin_dir: &'a str, reason: &'a str },
+
+    /// A loose object `fsck` couldn't read.
+    FsckUnreadable { hash: &'a ObjectHash, err: &'a anyhow::Error },
+    FsckHashMismatch { hash: &'a ObjectHash, actual: &'a ObjectHash },
+    FsckTreeProblem { hash: &'a ObjectHash, problem: TreeProblem },
+    /// An object `fsck` couldn't parse.
+    FsckInvalid { format: ObjectFormat, hash: &'a ObjectHash, err: &'a anyhow::Error },
+    /// A reference from one object to another that doesn't exist.
+    FsckBrokenLink { format: ObjectFormat, hash: &'a ObjectHash, target: &'a ObjectHash },
+}
+
+/// How a file changed, for [`Message::Change`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    Unmerged,
+}
+
+/// A translation of the messages into another language.
+pub trait Catalog: Send + Sync {
+    /// Returns the text for `message`, or `None` to use the English text.
+    fn text(&self, message: &Message) -> Option<String>;
+}
+
+static CATALOG: OnceLock<Box<dyn Catalog>> = OnceLock::new();
+
+/// Makes `catalog` the source of every message from now on. It can only be set once, before
+/// any messages are shown, so this gives it back if one was already set.
+pub fn set_catalog(catalog: Box<dyn Catalog>) -> Result<(), Box<dyn Catalog>> {
+    CATALOG.set(catalog)
+}
+
+/// How much commands say besides their actual output, e.g. the hashes `commit` prints.
+/// Chosen with `--quiet` or `--verbose` and passed to the commands that print more than
+/// their output.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum Verbosity {
+    /// Only output and errors, for scripts.
+    Quiet,
+    /// Progress and hints too.
+    #[default]
+    Normal,
+    /// Everything, as if each command's own `--verbose` was given.
+    Verbose,
+}
+
+impl Message<'_> {
+    /// Returns the English text, which is used when no catalog translates the message.
+    pub fn english(&self) -> String {
+        match *self {
+            Self::Error(err) => err.to_string(),
+
+            Self::Change { kind, path } => {
+                let label = match kind {
+                    ChangeKind::Created => "created:",
+                    ChangeKind::Modified => "modified:",
+                    ChangeKind::Deleted => "deleted:",
+                    ChangeKind::Unmerged => "unmerged:",
+                };
+                format!("{label:<11}{path}")
+            },
+            Self::Rename { old_path, path } => format!("{:<11}{old_path} -> {path}", "renamed:"),
+            Self::NestedRepo(path) => format!("{:<11}{path}/ (nested repository)", "created:"),
+            Self::NestedRepoSkipped(path) => format!("warning: skipping {path}/, which is a separate repository"),
+            Self::UnmergedPaths => "Unmerged paths:".to_owned(),
+            Self::UnmergedPathsHint => "  (use \"git add <file>...\" to mark resolution)".to_owned(),
+            Self::ChangesStaged => "Changes staged for commit:".to_owned(),
+            Self::NoChangesStaged => "No changes staged for commit".to_owned(),
+            Self::ChangesNotStaged => "Changes not staged for commit:".to_owned(),
+            Self::NoUnstagedChanges => "No unstaged changes".to_owned(),
+            Self::ChangesSince(rev) => format!("Changes since {rev}:"),
+            Self::NoChangesSince(rev) => format!("No changes since {rev}"),
+
+            Self::InProgress(operation) => match operation {
+                InProgress::Merge => "You are currently merging.",
+                InProgress::Rebase => "You are currently rebasing.",
+                InProgress::CherryPick => "You are currently cherry-picking.",
+                InProgress::Revert => "You are currently reverting a commit.",
+                InProgress::Bisect => "You are currently bisecting.",
+            }.to_owned(),
+            Self::ContinueHint { operation, has_conflicts } => match (operation, has_conflicts) {
+                (InProgress::Merge, true) => "fix conflicts and run \"git commit\"",
+                (InProgress::Merge, false) => "all conflicts fixed: run \"git commit\" to conclude the merge",
+                (InProgress::Rebase, true) => "fix conflicts and then run \"git rebase --continue\"",
+                (InProgress::Rebase, false) => "all conflicts fixed: run \"git rebase --continue\"",
+                (InProgress::CherryPick, true) => "fix conflicts and run \"git cherry-pick --continue\"",
+                (InProgress::CherryPick, false) => "all conflicts fixed: run \"git cherry-pick --continue\"",
+                (InProgress::Revert, true) => "fix conflicts and run \"git revert --continue\"",
+                (InProgress::Revert, false) => "all conflicts fixed: run \"git revert --continue\"",
+                (InProgress::Bisect, _) => "",
+            }.to_owned(),
+            Self::AbortHint(operation) => match operation {
+                InProgress::Merge => "use \"git merge --abort\" to abort the merge",
+                InProgress::Rebase => "use \"git rebase --abort\" to check out the original branch",
+                InProgress::CherryPick => "use \"git cherry-pick --abort\" to cancel the cherry-pick operation",
+                InProgress::Revert => "use \"git revert --abort\" to cancel the revert operation",
+                InProgress::Bisect => "use \"git bisect reset\" to get back to the original branch",
+            }.to_owned(),
+
+            Self::UnsupportedIndexExtensions => "Warning: index contains unsupported extensions.".to_owned(),
+            Self::WhitespaceWarning { path, line, kind } => format!("Warning: {path}:{line}: {kind}."),
+            Self::AutoPackAdvice => "Auto packing the repository is recommended. Run `git gc` to pack it.".to_owned(),
+            Self::ConflictsFound(count) => format!("{count} conflict(s) found"),
+            Self::MergeConflict { kind, path } => match kind {
+                ConflictKind::Content => format!("CONFLICT (content): Merge conflict in {path}"),
+                ConflictKind::AddAdd => format!("CONFLICT (add/add): Merge conflict in {path}"),
+                ConflictKind::DeletedByUs => format!("CONFLICT (modify/delete): {path} deleted in ours and modified in theirs."),
+                ConflictKind::DeletedByThem => format!("CONFLICT (modify/delete): {path} deleted in theirs and modified in ours."),
+                ConflictKind::DistinctTypes => format!("CONFLICT (distinct types): {path} had different types on each side."),
+            },
+            Self::CouldNotApply { action, commit, subject } => match action {
+                Action::Pick => format!("Could not apply {commit}... {subject}"),
+                Action::Revert => format!("Could not revert {commit}... {subject}"),
+            },
+
+            Self::Initialized(path) => format!("Successfully initialized git repository at {}", path.to_string_lossy()),
+            Self::CheckoutUnsupported => "wyag does not support the checkout command.\n\
+                If you want to switch branches, use the switch command.\n\
+                If you want to restore working directory files, use the restore command.".to_owned(),
+            Self::ObjectsCopied { objects, packs, linked, refs } => {
+                format!("Copied {objects} objects and {packs} packs ({linked} hard-linked), updated {refs} refs")
+            },
+            Self::SkippedTag(tag) => format!("Skipped tag `{tag}`, which already exists here with a different target"),
+            Self::MergetoolSkipped(path) => format!("Skipping {path}: it was deleted on one side, so use add or rm to resolve it."),
+            Self::MergetoolMerging(path) => format!("Merging {path}"),
+            Self::MergetoolFailed(path) => format!("Merge of {path} failed"),
+            Self::NoLostCommits => "No lost commits found.".to_owned(),
+            Self::StashDropped { n, hash } => format!("Dropped refs/stash@{{{n}}} ({hash})"),
+            Self::GoodSignature(principal) => format!("Good \"git\" signature for {principal}"),
+            Self::PruningWorktree { admin_dir, reason } => format!("Removing {admin_dir}: {reason}"),
+
+            Self::FsckUnreadable { hash, err } => format!("error: {hash}: {err:#}"),
+            Self::FsckHashMismatch { hash, actual } => format!("error: {hash}: hash mismatch (contents hash to {actual})"),
+            Self::FsckTreeProblem { hash, problem } => match problem.is_error() {
+                true => format!("error in tree {hash}: {problem}"),
+                false => format!("warning in tree {hash}: {problem}"),
+            },
+            Self::FsckInvalid { format, hash, err } => format!("error in {format} {hash}: {err:#}"),
+            Self::FsckBrokenLink { format, hash, target } => format!("error: broken link from {format} {hash} to {target}"),
+        }
+    }
+}
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let translated = CATALOG.get().and_then(|catalog| catalog.text(self));
+
+        f.write_str(&translated.unwrap_or_else(|| self.english()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn english_matches_status_layout() {
+        let path = crate::workdir::WorkPathBuf::try_from("src/main.rs").unwrap();
+
+        assert_eq!(Message::Change { kind: ChangeKind::Created, path: &path }.english(), "created:   src/main.rs");
+        assert_eq!(Message::Change { kind: ChangeKind::Unmerged, path: &path }.english(), "unmerged:  src/main.rs");
+        assert_eq!(Message::Rename { old_path: "a", path: "b" }.english(), "renamed:   a -> b");
+    }
+
+    #[test]
+    fn english_matches_fsck_layout() {
+        let hash = ObjectHash::new("a");
+
+        assert_eq!(Message::FsckTreeProblem { hash: &hash, problem: TreeProblem::NotSorted }.english(), format!("error in tree {hash}: {}", TreeProblem::NotSorted));
+        assert_eq!(Message::FsckTreeProblem { hash: &hash, problem: TreeProblem::HasDot }.english(), format!("warning in tree {hash}: {}", TreeProblem::HasDot));
+        assert_eq!(Message::FsckBrokenLink { format: ObjectFormat::Tree, hash: &hash, target: &hash }.english(), format!("error: broken link from tree {hash} to {hash}"));
+    }
+}
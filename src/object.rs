@@ -11,8 +11,10 @@ use regex::Regex;
 use crate::{
     Result,
     workdir::WorkDir,
+    consts::{HEAD, BRANCH_CATEGORY, REMOTE_CATEGORY, TAG_CATEGORY},
     refs,
     branch,
+    trace::{self, Category},
 };
 
 mod error;
@@ -28,7 +30,7 @@ mod commit;
 pub use commit::Commit;
 
 mod hash;
-pub use hash::ObjectHash;
+pub use hash::{ObjectHash, ObjectHasher};
 
 mod meta;
 pub use meta::ObjectMetadata;
@@ -36,8 +38,37 @@ pub use meta::ObjectMetadata;
 mod tag;
 pub use tag::Tag;
 
+mod signature;
+pub use signature::{SshSigner, SignatureError, verify as verify_signature};
+
+mod mode;
+pub use mode::FileMode;
+
+mod store;
+#[cfg(feature = "fuzzing")]
+pub(crate) use store::PackIndex;
+pub use store::ObjectStore;
+pub(crate) use store::forget_listing;
+
+mod bulk;
+pub use bulk::BulkWriter;
+
 mod tree;
-pub use tree::{Tree, TreeEntry};
+pub use tree::{Tree, TreeEntry, TreeChange, TreeProblem};
+pub(crate) use tree::compare_names;
+
+mod revision;
+use revision::Revision;
+
+mod graph;
+pub use graph::CommitNode;
+
+mod commit_graph;
+
+mod walk;
+pub use walk::{RevWalk, WalkedCommits, WalkedObject, WalkError};
+
+mod validate;
 
 /// An object saved to a Git repository. This may be a commit, a
 /// blob (i.e. a file), a tree (i.e. a directory), or a tag.
@@ -111,17 +142,41 @@ impl GitObject {
     /// Finds the object uniquely identified by `id`.
     /// 
     /// The identifier may be a (possibly abbreviated) hash, a branch name, a tag, or `"HEAD"`.
+    /// It may be followed by the revision suffixes `~n`, `^n`, and `^{type}`, and then by
+    /// `:path` to select an entry in the resulting tree (e.g. `HEAD~2`, `main:src`).
     pub fn find(wd: &WorkDir, id: &str) -> Result<ObjectHash> {
-        let matches = Self::resolve(wd, id)?;
-
-        match matches.len() {
-            1 => Ok(matches[0]),
-            0 => Err(ObjectError::InvalidId(id.to_owned()).into()),
-            _ => Err(ObjectError::AmbiguousId {
+        let revision = Revision::parse(id)
+            .ok_or_else(|| ObjectError::InvalidId(id.to_owned()))?;
+        let matches = Self::resolve(wd, revision.name)?;
+
+        let hash = match matches.len() {
+            1 => matches[0],
+            0 => return Err(ObjectError::InvalidId(id.to_owned()).into()),
+            _ => return Err(ObjectError::AmbiguousId {
                 id: id.to_owned(),
                 matches,
             }.into()),
-        }
+        };
+
+        revision.apply(wd, hash)
+    }
+
+    /// Finds the object identified by `id` (see [`GitObject::find`]) and peels it to an
+    /// object of type `expected`. Tags are followed, and a commit can stand in for its
+    /// tree, so this accepts a commit-ish for [`ObjectFormat::Commit`] and a tree-ish for
+    /// [`ObjectFormat::Tree`]. Anything else is an [`ObjectError::WrongKind`].
+    pub fn find_as(wd: &WorkDir, id: &str, expected: ObjectFormat) -> Result<ObjectHash> {
+        let hash = Self::find(wd, id)?;
+
+        Self::peel(wd, &hash, expected).map_err(|err| match err.downcast::<ObjectError>() {
+            Ok(ObjectError::UnexpectedFormat { format, .. }) => ObjectError::WrongKind {
+                id: id.to_owned(),
+                expected,
+                found: format,
+            }.into(),
+            Ok(err) => err.into(),
+            Err(err) => err,
+        })
     }
 
     /// Finds all object hashes that `id` could refer to.
@@ -139,22 +194,12 @@ impl GitObject {
                 }
             }
             else {
-                let dir_name = &id[..2];
-                let dir_path = wd.git_path(format!("objects/{dir_name}"));
-                if dir_path.exists() {
-                    let hashes: Vec<ObjectHash> = std::fs::read_dir(dir_path)?
-                        .collect::<core::result::Result<Vec<std::fs::DirEntry>, _>>()?
-                        .into_iter()
-                        .map(|file| format!("{dir_name}{}", file.file_name().to_string_lossy()))
-                        .filter(|hash_string| hash_string.starts_with(id))
-                        .filter_map(|hash_string| ObjectHash::try_from(&hash_string[..]).ok())
-                        .collect();
-                    candidates.extend(hashes);
-                }
+                // Loose objects, alternates, and packs can all hold matches
+                candidates.extend(ObjectStore::open(wd)?.prefix_matches(id));
             }
         }
 
-        if id == "HEAD" {
+        if id == HEAD {
             let head = branch::get_current(wd)?.tip(wd)?;
 
             if let Some(head_hash) = head {
@@ -166,28 +211,104 @@ impl GitObject {
             }
         }
 
-        if let Ok(local_branch) = refs::resolve(wd, "heads", id) {
+        if let Ok(local_branch) = refs::resolve(wd, BRANCH_CATEGORY, id) {
             candidates.push(local_branch);
         }
 
-        if let Ok(remote_branch) = refs::resolve(wd, "remotes", id) {
+        if let Ok(remote_branch) = refs::resolve(wd, REMOTE_CATEGORY, id) {
             candidates.push(remote_branch);
         }
 
-        if let Ok(tag) = refs::resolve(wd, "tags", id) {
+        if let Ok(tag) = refs::resolve(wd, TAG_CATEGORY, id) {
             candidates.push(tag);
         }
 
         Ok(candidates)
     }
 
+    /// Returns the hashes of the objects this object refers to: a commit's tree and parents,
+    /// the entries of a tree (except gitlinks, which belong to other repositories), or the
+    /// target of a tag.
+    pub fn references(&self) -> Result<Vec<ObjectHash>> {
+        match self {
+            GitObject::Blob(_) => Ok(vec![]),
+            GitObject::Commit(commit) => Ok(std::iter::once(commit.tree())
+                .chain(commit.parents())
+                .copied()
+                .collect()),
+            GitObject::Tag(tag) => Ok(vec![tag.target()?]),
+            GitObject::Tree(tree) => Ok(tree.entries.values()
+                .filter(|entry| !entry.is_gitlink())
+                .map(|entry| entry.hash)
+                .collect()),
+        }
+    }
+
     /// Reads and parses the object with the given hash from the repo.
     pub fn read(wd: &WorkDir, hash: &ObjectHash) -> Result<GitObject> {
+        let (format, data) = Self::read_raw(wd, hash)?;
+
+        Self::deserialize(data, format)
+    }
+
+    /// Reads the object with the given hash from the repo without parsing it. Returns its
+    /// format and the data that follows the header.
+    /// 
+    /// Objects that aren't in the repo's own object directory are looked up in its
+    /// alternates (see [`ObjectStore`]).
+    pub fn read_raw(wd: &WorkDir, hash: &ObjectHash) -> Result<(ObjectFormat, Vec<u8>)> {
+        let _timer = trace::start(Category::ObjectIo);
+        let path = wd.git_path(PathBuf::from("objects").join(hash.to_path()));
+        match std::fs::File::open(path) {
+            Ok(object_file) => Self::decode_loose(object_file, hash),
+            Err(_) => ObjectStore::open(wd)?.read_raw(hash),
+        }
+    }
+
+    /// Reads only the format and size of the object with the given hash. Only the header at
+    /// the start of the object is decompressed, so this is much faster than
+    /// [`GitObject::read`] for large blobs.
+    pub fn read_header(wd: &WorkDir, hash: &ObjectHash) -> Result<(ObjectFormat, usize)> {
+        let _timer = trace::start(Category::ObjectIo);
+        let path = wd.git_path(PathBuf::from("objects").join(hash.to_path()));
+        match std::fs::File::open(path) {
+            Ok(object_file) => Self::decode_loose_header(object_file, hash),
+            Err(_) => ObjectStore::open(wd)?.read_header(hash),
+        }
+    }
+
+    /// Decompresses just enough of a loose object to parse its header. `hash` is only used
+    /// in error messages.
+    fn decode_loose_header<R: Read>(object_file: R, hash: &ObjectHash) -> Result<(ObjectFormat, usize)> {
+        // Longer than any valid header, e.g. `commit ` followed by a 20-digit size
+        const MAX_HEADER_LEN: u64 = 32;
+
+        let mut start = Vec::new();
+        let timer = trace::start(Category::Compression);
+        ZlibDecoder::new(object_file)
+            .take(MAX_HEADER_LEN)
+            .read_to_end(&mut start)?;
+        drop(timer);
+
+        let header = start.split(|ch| *ch == 0)
+            .next()
+            .filter(|header_bytes| header_bytes.len() < start.len())
+            .ok_or_else(|| "missing null terminator".to_owned())
+            .and_then(Self::parse_header);
+
+        header.map_err(|problem| ObjectError::MalformedHeader {
+            hash: *hash,
+            problem,
+        }.into())
+    }
+
+    /// Decompresses a loose object and splits off its header. `hash` is only used in
+    /// error messages.
+    fn decode_loose<R: Read>(object_file: R, hash: &ObjectHash) -> Result<(ObjectFormat, Vec<u8>)> {
         // Read and decompress
         let mut bytes = {
             let mut buf = Vec::new(); // TODO perhaps reserve some capacity here?
-            let path = PathBuf::from("objects").join(hash.to_path());
-            let object_file = wd.open_git_file(path, None)?;
+            let _timer = trace::start(Category::Compression);
             let mut decoder = ZlibDecoder::new(object_file);
             decoder.read_to_end(&mut buf)?;
 
@@ -217,7 +338,30 @@ impl GitObject {
             }.into());
         }
 
-        Self::deserialize(data, format)
+        Ok((format, data))
+    }
+
+    /// Lists the hashes of all loose objects in the repo.
+    pub fn loose_hashes(wd: &WorkDir) -> Result<Vec<ObjectHash>> {
+        let mut hashes = vec![];
+
+        for dir in std::fs::read_dir(wd.git_path("objects"))? {
+            let dir = dir?;
+            let dir_name = dir.file_name().to_string_lossy().into_owned();
+            if dir_name.len() != 2 || !dir.file_type()?.is_dir() {
+                continue;
+            }
+
+            for file in std::fs::read_dir(dir.path())? {
+                let hash_string = format!("{dir_name}{}", file?.file_name().to_string_lossy());
+                if let Ok(hash) = ObjectHash::try_from(hash_string.as_str()) {
+                    hashes.push(hash);
+                }
+            }
+        }
+
+        hashes.sort_by_key(|hash| hash.raw);
+        Ok(hashes)
     }
 
     /// Parses an object header. The format is `format size\0` where
@@ -243,49 +387,58 @@ impl GitObject {
 
     /// Computes the hash for this object.
     pub fn hash(&self) -> ObjectHash {
-        let (hash, _) = self.prepare_for_storage();
-
-        hash
+        ObjectHash::for_object(self.get_format(), &self.serialize())
     }
 
     /// Store the object in the repo.
     pub fn write(&self, wd: &WorkDir) -> Result<ObjectHash> {
-        let (hash, data) = self.prepare_for_storage();
+        Self::write_raw(wd, self.get_format(), &self.serialize())
+    }
+
+    /// Stores `body` in the repo as an object of type `format`, exactly as given. Nothing
+    /// checks that it's well-formed (see [`GitObject::validate`]).
+    pub fn write_raw(wd: &WorkDir, format: ObjectFormat, body: &[u8]) -> Result<ObjectHash> {
+        let _timer = trace::start(Category::ObjectIo);
+        let hash = ObjectHash::for_object(format, body);
 
         // Skip writing if the file for this hash already exists
         // The contents will be unchanged unless the compression level is changed
         // or in the extremely unlikely event of a hash collision
+        // Its modification time is refreshed instead, so `prune` sees that it's in use again
         let path = PathBuf::from("objects").join(hash.to_path());
-        if !wd.git_path(&path).exists() {
+        if !Self::refresh_existing(wd, &path)? {
             // Compress and write to disk
-            let mut options = std::fs::OpenOptions::new();
-            options.create(true).write(true);
-            let object_file = wd.open_git_file(path, Some(&options))?;
-    
-            const COMPRESSION_LEVEL: u32 = 6;
-            let mut encoder = ZlibEncoder::new(object_file, flate2::Compression::new(COMPRESSION_LEVEL));
-            encoder.write_all(&data)?;
+            let compressed = {
+                let _timer = trace::start(Category::Compression);
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::new(Self::COMPRESSION_LEVEL));
+                encoder.write_all(&format.header(body.len() as u64))?;
+                encoder.write_all(body)?;
+                encoder.finish()?
+            };
+            wd.write_object_file(&path, &compressed)?;
+            forget_listing(&wd.git_path(&path));
         }
 
         Ok(hash)
     }
 
-    /// Transforms the object to its stored form and computes the hash.
-    fn prepare_for_storage(&self) -> (ObjectHash, Vec<u8>) {
-        let body = self.serialize();
-
-        let mut data = {
-            let format = self.get_format();
-            let size = body.len();
-
-            format!("{format} {size}\0").into_bytes()
-        };
-        data.extend(body);
-
-        let hash = ObjectHash::new(&data);
-
-        (hash, data) // TODO refactor so data buffer doesn't have to be copied
-                     // perhaps with VecDeque or have serialize return Write
+    /// The zlib compression level for loose objects, same as git's default.
+    const COMPRESSION_LEVEL: u32 = 6;
+
+    /// Refreshes the modification time of the object file at `rel_path` (within `.git`),
+    /// if it exists, so `prune` sees that it's in use again. Returns false if there is no
+    /// such file. Fails without touching anything if the repository is read-only.
+    fn refresh_existing(wd: &WorkDir, rel_path: &Path) -> Result<bool> {
+        wd.check_writable()?;
+
+        match std::fs::File::open(wd.git_path(rel_path)) {
+            Ok(existing) => {
+                // This is best effort, since the file may be read-only
+                let _ = existing.set_modified(std::time::SystemTime::now());
+                Ok(true)
+            },
+            Err(_) => Ok(false),
+        }
     }
 
 }
@@ -1,6 +1,13 @@
-use crate::{Result, workdir::WorkDir};
+use std::{
+    fs::{self, File},
+    io,
+    path::PathBuf,
+};
 
-use super::{GitObject, ObjectHash, ObjectError, ObjectFormat};
+use flate2::write::ZlibEncoder;
+use crate::{Result, workdir::{WorkDir, create_temp_file}};
+
+use super::{GitObject, ObjectHash, ObjectHasher, ObjectError, ObjectFormat};
 
 /// A blob of data (usually the contents of a file) that may be stored in and retrieved from a Git repository.
 pub struct Blob {
@@ -24,6 +31,73 @@ impl Blob {
         }
     }
 
+    /// Computes the hash of a blob containing the contents of `file` without loading
+    /// the whole file into memory. If `write` is true, the blob is also stored in the repo.
+    ///
+    /// This is meant for files over `core.bigFileThreshold`. The object is stored
+    /// without compression, since compressing large binary assets is slow and rarely
+    /// saves much space.
+    pub fn stream_file(wd: &WorkDir, file: &mut File, write: bool) -> Result<ObjectHash> {
+        let size = file.metadata()?.len();
+
+        if !write {
+            let mut hasher = ObjectHasher::new(io::sink(), ObjectFormat::Blob, size)?;
+            let copied = io::copy(file, &mut hasher)?;
+            Self::check_stream_size(size, copied)?;
+
+            return Ok(hasher.finish().0);
+        }
+
+        // Stream into a temporary file, since the hash (and so the final path)
+        // isn't known until the end
+        let (temp_file, temp_path) = create_temp_file(&wd.make_git_dir("objects")?)?;
+
+        let result = (|| {
+            let encoder = ZlibEncoder::new(temp_file, flate2::Compression::none());
+            let mut hasher = ObjectHasher::new(encoder, ObjectFormat::Blob, size)?;
+            let copied = io::copy(file, &mut hasher)?;
+            Self::check_stream_size(size, copied)?;
+
+            let (hash, encoder) = hasher.finish();
+            let temp_file = encoder.finish()?;
+            if wd.settings().fsync_object_files {
+                temp_file.sync_all()?;
+            }
+            drop(temp_file);
+
+            let path = PathBuf::from("objects").join(hash.to_path());
+            if !wd.git_path(&path).exists() {
+                if let Some(dir) = path.parent() {
+                    wd.make_git_dir(dir)?;
+                }
+                match fs::rename(&temp_path, wd.git_path(&path)) {
+                    // Another process wrote it first
+                    Err(_) if wd.git_path(&path).is_file() => (),
+                    result => result?,
+                }
+                super::forget_listing(&wd.git_path(&path));
+            }
+
+            Ok(hash)
+        })();
+
+        // The temporary file is still there if something failed or the object already
+        // existed
+        if temp_path.exists() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Makes sure a streamed file didn't change size while it was being read.
+    fn check_stream_size(expected: u64, actual: u64) -> Result<()> {
+        if expected != actual {
+            return Err(ObjectError::StreamSizeMismatch { expected, actual }.into());
+        }
+
+        Ok(())
+    }
+
     /// Parses a `Blob` from a sequence of bytes.
     pub fn deserialize(data: Vec<u8>) -> Result<Blob> {
         Ok(Blob { data })
@@ -39,3 +113,46 @@ impl Blob {
         self.data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use crate::test_util::temp_workdir;
+
+    #[test]
+    fn stream_file_matches_buffered_write() {
+        let (temp_dir, wd) = temp_workdir();
+        let contents = "big file contents\n".repeat(1000);
+        temp_dir.child("big.bin").write_str(&contents).unwrap();
+
+        let mut file = File::open(temp_dir.child("big.bin").path()).unwrap();
+        let hash = Blob::stream_file(&wd, &mut file, true).unwrap();
+
+        let expected = GitObject::Blob(Blob { data: contents.clone().into_bytes() });
+        assert_eq!(hash, expected.hash());
+
+        let blob = Blob::read(&wd, &hash).unwrap();
+        assert_eq!(blob.serialize_into(), contents.into_bytes());
+    }
+
+    #[test]
+    fn stream_file_cleans_up_after_failing() {
+        let (temp_dir, wd) = temp_workdir();
+        temp_dir.child("big.bin").write_str("big file contents\n").unwrap();
+        let mut file = File::open(temp_dir.child("big.bin").path()).unwrap();
+        let hash = Blob::stream_file(&wd, &mut file, false).unwrap();
+
+        // A file where the fan-out directory should be makes the final rename fail
+        let fanout = &hash.to_string()[..2];
+        temp_dir.child(format!(".git/objects/{fanout}")).write_str("").unwrap();
+        let mut file = File::open(temp_dir.child("big.bin").path()).unwrap();
+        assert!(Blob::stream_file(&wd, &mut file, true).is_err());
+
+        let leftovers: Vec<_> = fs::read_dir(wd.git_path("objects")).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name != fanout)
+            .collect();
+        assert!(leftovers.is_empty(), "{leftovers:?}");
+    }
+}
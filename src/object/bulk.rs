@@ -0,0 +1,169 @@
+use std::{
+    collections::HashSet,
+    io::Write,
+    num::NonZeroUsize,
+    path::PathBuf,
+    thread,
+};
+
+use flate2::write::ZlibEncoder;
+
+use crate::{Result, trace::{self, Category}, workdir::WorkDir};
+use super::{GitObject, ObjectFormat, ObjectHash};
+
+/// The fewest objects worth compressing on a separate thread.
+const MIN_OBJECTS_PER_THREAD: usize = 64;
+
+/// Writes many objects at once, e.g. every tree in a commit. Objects are hashed as they're
+/// added, then compressed and written together by [`BulkWriter::flush`].
+///
+/// Compared to calling [`GitObject::write`] for each object, each thread reuses a single
+/// zlib encoder, and compression is spread across threads.
+pub struct BulkWriter<'a> {
+    wd: &'a WorkDir,
+    /// The hash, format, and serialized body of each object that has been added but not
+    /// written yet.
+    pending: Vec<(ObjectHash, ObjectFormat, Vec<u8>)>,
+    pending_hashes: HashSet<ObjectHash>,
+    threads: NonZeroUsize,
+}
+
+impl<'a> BulkWriter<'a> {
+    /// Creates a writer for the repository at `wd` that uses as many threads as there are
+    /// processors.
+    pub fn new(wd: &'a WorkDir) -> Self {
+        Self {
+            wd,
+            pending: vec![],
+            pending_hashes: HashSet::new(),
+            threads: thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+        }
+    }
+
+    /// Limits the number of threads used to compress objects.
+    pub fn with_threads(mut self, threads: NonZeroUsize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Queues `object` to be written and returns its hash. Nothing is written if the object
+    /// is already stored or queued, though an existing object file's modification time is
+    /// refreshed, same as with [`GitObject::write`]. Fails if the repository is read-only.
+    pub fn add(&mut self, object: &GitObject) -> Result<ObjectHash> {
+        let format = object.get_format();
+        let body = object.serialize();
+        let hash = ObjectHash::for_object(format, &body);
+
+        if !self.pending_hashes.contains(&hash)
+            && !GitObject::refresh_existing(self.wd, &Self::object_path(&hash))?
+        {
+            self.pending_hashes.insert(hash);
+            self.pending.push((hash, format, body));
+        }
+
+        Ok(hash)
+    }
+
+    /// Writes every queued object. If this fails, some of the objects may not have been
+    /// written, and the queue is cleared either way.
+    pub fn flush(&mut self) -> Result<()> {
+        let _timer = trace::start(Category::ObjectIo);
+        let pending = std::mem::take(&mut self.pending);
+        self.pending_hashes.clear();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let chunk_size = pending.len()
+            .div_ceil(self.threads.get())
+            .max(MIN_OBJECTS_PER_THREAD);
+        let compressed: Vec<Vec<u8>> = match pending.len() <= chunk_size {
+            true => compress_all(&pending)?,
+            false => thread::scope(|scope| {
+                let handles: Vec<_> = pending.chunks(chunk_size)
+                    .map(|chunk| scope.spawn(|| compress_all(chunk)))
+                    .collect();
+
+                handles.into_iter()
+                    .map(|handle| handle.join().expect("compression thread should not panic"))
+                    .collect::<Result<Vec<_>>>()
+            })?.into_iter().flatten().collect(),
+        };
+
+        for ((hash, _, _), data) in pending.iter().zip(&compressed) {
+            let path = Self::object_path(hash);
+            self.wd.write_object_file(&path, data)?;
+            super::forget_listing(&self.wd.git_path(&path));
+        }
+
+        Ok(())
+    }
+
+    fn object_path(hash: &ObjectHash) -> PathBuf {
+        PathBuf::from("objects").join(hash.to_path())
+    }
+}
+
+/// Compresses each object in `objects` with the same encoder.
+fn compress_all(objects: &[(ObjectHash, ObjectFormat, Vec<u8>)]) -> Result<Vec<Vec<u8>>> {
+    let _timer = trace::start(Category::Compression);
+    let level = flate2::Compression::new(GitObject::COMPRESSION_LEVEL);
+    let mut encoder = ZlibEncoder::new(Vec::new(), level);
+
+    objects.iter()
+        .map(|(_, format, body)| {
+            encoder.write_all(&format.header(body.len() as u64))?;
+            encoder.write_all(body)?;
+            Ok(encoder.reset(Vec::new())?)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        object::Blob,
+        test_util::temp_workdir,
+        workdir::{Settings, WorkDirError},
+    };
+
+    #[test]
+    fn writes_objects_readable_by_store() {
+        let (_temp_dir, wd) = temp_workdir();
+        let mut writer = BulkWriter::new(&wd).with_threads(NonZeroUsize::new(4).unwrap());
+
+        let blobs: Vec<GitObject> = (0..200)
+            .map(|i| GitObject::Blob(Blob::deserialize(format!("blob {}\n", i % 150).into_bytes()).unwrap()))
+            .collect();
+        let hashes: Vec<ObjectHash> = blobs.iter()
+            .map(|blob| writer.add(blob).unwrap())
+            .collect();
+        assert_eq!(writer.pending.len(), 150);
+        writer.flush().unwrap();
+
+        for (blob, hash) in blobs.iter().zip(&hashes) {
+            assert_eq!(blob.hash(), *hash);
+            assert_eq!(GitObject::read(&wd, hash).unwrap().serialize(), blob.serialize());
+        }
+
+        // Objects that are already stored aren't queued again
+        writer.add(&blobs[0]).unwrap();
+        assert!(writer.pending.is_empty());
+    }
+
+    #[test]
+    fn read_only_leaves_existing_objects_alone() {
+        let (_temp_dir, mut wd) = temp_workdir();
+        let blob = GitObject::Blob(Blob::deserialize(b"blob\n".to_vec()).unwrap());
+        let path = wd.git_path(BulkWriter::object_path(&blob.write(&wd).unwrap()));
+        let old_time = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(old_time).unwrap();
+
+        wd.set_settings(Settings { read_only: true, ..Settings::default() });
+        for result in [blob.write(&wd).map(drop), BulkWriter::new(&wd).add(&blob).map(drop)] {
+            assert!(matches!(result.unwrap_err().downcast_ref(), Some(WorkDirError::ReadOnly(_))));
+        }
+        assert_eq!(std::fs::metadata(&path).unwrap().modified().unwrap(), old_time);
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use anyhow::Context;
 use ordered_multimap::ListOrderedMultimap;
 
@@ -8,7 +10,7 @@ use crate::{
     branch,
 };
 
-use super::{ObjectError, ObjectFormat, ObjectHash, GitObject, ObjectMetadata, Tree};
+use super::{ObjectError, ObjectFormat, ObjectHash, GitObject, ObjectMetadata, Tree, SignatureError, CommitNode};
 
 /// A commit is a handle to a snapshot of the working directory's state at a particular time.
 /// 
@@ -24,24 +26,34 @@ impl Commit {
     /// Creates a new commit from `index` and stores it in the repo. On success, the
     /// hash of the new commit object is returned.
     pub fn create(index: &Index, wd: &WorkDir, meta: ObjectMetadata) -> Result<ObjectHash> {
+        let parents = branch::get_current(wd)?.tip(wd)?.into_iter().collect();
+
+        Self::create_with_parents(index, wd, meta, parents)
+    }
+
+    /// Like [`Commit::create`], but with the given parents instead of the current commit,
+    /// e.g. to replace the current commit with `commit --amend`.
+    pub fn create_with_parents(index: &Index, wd: &WorkDir, meta: ObjectMetadata, parents: Vec<ObjectHash>) -> Result<ObjectHash> {
         if index.entries.is_empty() {
             return Err(ObjectError::EmptyIndex.into());
         }
 
         let (tree_hash, _) = Tree::create_from_index(index, wd)?;
-
-        let parent_hash = branch::get_current(wd)?.tip(wd)?;
-        let mut parents = Vec::new();
     
         let mut map = ListOrderedMultimap::new();
         map.insert("tree".to_owned(), tree_hash.to_string());
-        if let Some(parent_hash) = parent_hash {
-            map.insert("parent".to_owned(), parent_hash.to_string());
-            parents.push(parent_hash);
+        for parent_hash in &parents {
+            map.append("parent".to_owned(), parent_hash.to_string());
         }
         map.insert("author".to_owned(), meta.author_line());
-        map.insert("committer".to_owned(), meta.author_line());
+        map.insert("committer".to_owned(), meta.committer_line());
         map.insert("".to_owned(), meta.message);
+
+        if let Some(signer) = &meta.signer {
+            let payload = crate::kvlm::serialize(&map);
+            let signature = signer.sign(payload.as_bytes())?;
+            map.insert("gpgsig".to_owned(), signature.trim_end().to_owned());
+        }
     
         let commit = GitObject::Commit(Commit {
             map,
@@ -61,7 +73,7 @@ impl Commit {
             GitObject::Commit(commit) => Ok(commit),
             object => Err(ObjectError::UnexpectedFormat {
                 format: object.get_format(),
-                expected: ObjectFormat::Blob
+                expected: ObjectFormat::Commit,
             }.into()),
         }
     }
@@ -76,6 +88,136 @@ impl Commit {
         &self.parents
     }
     
+    /// Returns the commit message.
+    pub fn message(&self) -> &str {
+        self.map.get("").map_or("", String::as_str)
+    }
+
+    /// Returns the first line of the message.
+    pub fn subject(&self) -> &str {
+        self.message().lines().next().unwrap_or_default()
+    }
+
+    /// Returns the rest of the message after the subject and the blank lines that follow it.
+    pub fn body(&self) -> &str {
+        let message = self.message();
+        match message.split_once('\n') {
+            Some((_, rest)) => rest.trim_start_matches(['\n', '\r']),
+            None => "",
+        }
+    }
+
+    /// Returns the best common ancestors of `a` and `b`: the commits reachable from both
+    /// that aren't ancestors of another such commit. Usually there's only one, but
+    /// criss-cross merges can have several.
+    pub fn merge_bases(wd: &WorkDir, a: &ObjectHash, b: &ObjectHash) -> Result<Vec<ObjectHash>> {
+        let ancestors = |start: &ObjectHash| -> Result<HashSet<ObjectHash>> {
+            let mut seen = HashSet::new();
+            let mut pending = vec![*start];
+            while let Some(hash) = pending.pop() {
+                if seen.insert(hash) {
+                    pending.extend_from_slice(&CommitNode::get(wd, &hash)?.parents);
+                }
+            }
+            Ok(seen)
+        };
+        let a_ancestors = ancestors(a)?;
+
+        // Walk back from `b`, stopping at common ancestors (the parents of which are common too)
+        let mut common = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![*b];
+        while let Some(hash) = pending.pop() {
+            if !seen.insert(hash) {
+                continue;
+            }
+            match a_ancestors.contains(&hash) {
+                true => common.push(hash),
+                false => pending.extend_from_slice(&CommitNode::get(wd, &hash)?.parents),
+            }
+        }
+
+        // Drop the common ancestors that are reachable from other ones
+        let mut redundant = HashSet::new();
+        for hash in &common {
+            if redundant.contains(hash) {
+                continue;
+            }
+            let mut pending = CommitNode::get(wd, hash)?.parents.clone();
+            while let Some(hash) = pending.pop() {
+                if redundant.insert(hash) {
+                    pending.extend_from_slice(&CommitNode::get(wd, &hash)?.parents);
+                }
+            }
+        }
+        common.retain(|hash| !redundant.contains(hash));
+
+        Ok(common)
+    }
+
+    /// Lists the commits reachable from `include` but not from `exclude` (as in
+    /// `exclude..include`), with parents before their children.
+    pub fn list_range(wd: &WorkDir, exclude: Option<&ObjectHash>, include: &ObjectHash) -> Result<Vec<ObjectHash>> {
+        let mut excluded = HashSet::new();
+        let mut pending: Vec<ObjectHash> = exclude.into_iter().copied().collect();
+        while let Some(hash) = pending.pop() {
+            if excluded.insert(hash) {
+                pending.extend_from_slice(&CommitNode::get(wd, &hash)?.parents);
+            }
+        }
+
+        // Depth-first, adding each commit after its parents
+        let mut commits = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![(*include, false)];
+        while let Some((hash, parents_done)) = stack.pop() {
+            if parents_done {
+                commits.push(hash);
+            }
+            else if !excluded.contains(&hash) && seen.insert(hash) {
+                stack.push((hash, true));
+                let node = CommitNode::get(wd, &hash)?;
+                stack.extend(node.parents.iter().rev().map(|parent| (*parent, false)));
+            }
+        }
+
+        Ok(commits)
+    }
+
+    /// Returns the name and email of the commit's author, e.g. `Jo <jo@example.com>`,
+    /// without the timestamp.
+    pub fn author(&self) -> Option<&str> {
+        let author = self.map.get("author")?;
+
+        Some(split_identity(author).0)
+    }
+
+    /// Returns when the commit was authored in seconds since the Unix epoch, with the
+    /// author's timezone offset (e.g. `-0800`), if known.
+    pub fn author_date(&self) -> Option<(i64, &str)> {
+        let author = self.map.get("author")?;
+
+        split_identity(author).1
+    }
+
+    /// Returns the time the commit was made in seconds since the Unix epoch, if known.
+    pub fn committer_time(&self) -> Option<i64> {
+        let committer = self.map.get("committer")?;
+
+        split_identity(committer).1.map(|(timestamp, _)| timestamp)
+    }
+
+    /// Splits a signed commit into the data that was signed and the signature.
+    pub fn signed_payload(&self) -> Result<(Vec<u8>, String)> {
+        let mut map = self.map.clone();
+        let signature = match map.remove("gpgsig") {
+            Some(signature) => signature,
+            None => return Err(SignatureError::Unsigned.into()),
+        };
+
+        Ok((crate::kvlm::serialize(&map).into_bytes(), signature + "\n"))
+    }
+
     /// Parses a `Commit` from a sequence of bytes.
     pub fn deserialize(data: Vec<u8>) -> Result<Commit> {
         let data = std::str::from_utf8(&data)
@@ -111,6 +253,22 @@ impl Commit {
     }
 }
 
+/// Splits an author, committer, or tagger line (`Jo <jo@example.com> 1673643222 -0800`)
+/// into the identity and the timestamp with its timezone. The timestamp is optional, and
+/// the timezone defaults to `+0000`.
+pub(super) fn split_identity(line: &str) -> (&str, Option<(i64, &str)>) {
+    let Some(email_end) = line.rfind('>') else {
+        return (line, None);
+    };
+    let (identity, rest) = line.split_at(email_end + 1);
+    let mut fields = rest.split_whitespace();
+    let date = fields.next()
+        .and_then(|timestamp| timestamp.parse().ok())
+        .map(|timestamp| (timestamp, fields.next().unwrap_or("+0000")));
+
+    (identity, date)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -130,6 +288,23 @@ add dependencies and cli skeleton").into();
         assert_eq!(commit.tree(), &expected_hash);
     }
 
+    #[test]
+    fn splits_message() {
+        let commit_text = "\
+tree bf42a97e57f4f7e090ee62e5967e94fc4331dabb
+author spindlymist <ocrobin@gmail.com> 1673643222 -0800
+committer spindlymist <ocrobin@gmail.com> 1673643222 -0800
+
+Fix the thing
+
+It was broken in a way that took a long explanation.
+".as_bytes().to_owned();
+
+        let commit = Commit::deserialize(commit_text).unwrap();
+        assert_eq!(commit.subject(), "Fix the thing");
+        assert_eq!(commit.body(), "It was broken in a way that took a long explanation.\n");
+    }
+
     #[test]
     fn rejects_missing_tree() {
         let commit_text = "\
@@ -195,4 +370,19 @@ add tests for object::hash".to_string().into();
         let result = Commit::deserialize(commit_text);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn extracts_author_and_time() {
+        let commit_text: Vec<u8> = "\
+tree bf42a97e57f4f7e090ee62e5967e94fc4331dabb
+author spindlymist <ocrobin@gmail.com> 1673643222 -0800
+committer spindlymist <ocrobin@gmail.com> 1673643223 -0800
+
+add dependencies and cli skeleton".into();
+
+        let commit = Commit::deserialize(commit_text).unwrap();
+        assert_eq!(commit.author(), Some("spindlymist <ocrobin@gmail.com>"));
+        assert_eq!(commit.author_date(), Some((1673643222, "-0800")));
+        assert_eq!(commit.committer_time(), Some(1673643223));
+    }
 }
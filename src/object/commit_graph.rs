@@ -0,0 +1,184 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use anyhow::Context;
+
+use crate::{Result, workdir::WorkDir};
+use super::ObjectHash;
+
+/// The generation numbers from a repository's `objects/info/commit-graph` file, which git
+/// writes during `gc` or `commit-graph write`. Only the first generation number (the
+/// topological level) is used, and split commit-graph chains aren't read.
+pub(crate) struct CommitGraphFile {
+    /// `fanout[b]` is the number of hashes whose first byte is at most `b`.
+    fanout: [u32; 256],
+    hashes: Vec<[u8; 20]>,
+    /// The generation of each commit in `hashes`, or 0 if git didn't compute it.
+    generations: Vec<u32>,
+}
+
+/// The commit-graph file of each repository used so far, or `None` if it has none.
+fn cache() -> &'static Mutex<HashMap<PathBuf, Option<Arc<CommitGraphFile>>>> {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, Option<Arc<CommitGraphFile>>>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+impl CommitGraphFile {
+    const SIGNATURE: &'static [u8] = b"CGPH";
+    const FANOUT_CHUNK: [u8; 4] = *b"OIDF";
+    const HASH_CHUNK: [u8; 4] = *b"OIDL";
+    const DATA_CHUNK: [u8; 4] = *b"CDAT";
+    /// Each commit's tree, two parent positions, and generation and time.
+    const DATA_ENTRY_LEN: usize = 20 + 4 + 4 + 8;
+
+    /// Returns the commit-graph file for the repository at `wd`, reading it the first time.
+    /// A file that can't be parsed is ignored, since generations can always be computed
+    /// from the commits themselves.
+    pub(crate) fn get(wd: &WorkDir) -> Option<Arc<Self>> {
+        let path = wd.git_path("objects/info/commit-graph");
+        if let Some(graph) = cache().lock().expect("commit-graph lock should not be poisoned").get(&path) {
+            return graph.clone();
+        }
+
+        let graph = Self::read(&path).ok().map(Arc::new);
+        cache().lock().expect("commit-graph lock should not be poisoned")
+            .insert(path, graph.clone());
+
+        graph
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        Self::parse(&data)
+            .with_context(|| format!("Failed to parse commit-graph {path:?}"))
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(Self::SIGNATURE) {
+            anyhow::bail!("bad signature");
+        }
+        // Signature, version, hash function, chunk count, and base graph count
+        let header = data.get(..8).context("truncated header")?;
+        if header[4..6] != [1, 1] {
+            anyhow::bail!("unsupported version or hash function");
+        }
+
+        // Each chunk is found by its ID in a table of (ID, offset) pairs after the header
+        let chunk_count = header[6] as usize;
+        let chunk = |id: [u8; 4]| -> Result<&[u8]> {
+            for i in 0..chunk_count {
+                let entry = data.get(8 + i * 12..8 + (i + 2) * 12)
+                    .context("truncated chunk table")?;
+                if entry[..4] == id {
+                    let start = u64::from_be_bytes(entry[4..12].try_into().expect("slice should be 8 bytes")) as usize;
+                    let end = u64::from_be_bytes(entry[16..24].try_into().expect("slice should be 8 bytes")) as usize;
+                    return data.get(start..end).context("truncated chunk");
+                }
+            }
+            anyhow::bail!("missing chunk {}", String::from_utf8_lossy(&id))
+        };
+
+        let mut fanout = [0; 256];
+        let fanout_bytes = chunk(Self::FANOUT_CHUNK)?;
+        if fanout_bytes.len() != 256 * 4 {
+            anyhow::bail!("wrong fanout size");
+        }
+        for (count, bytes) in fanout.iter_mut().zip(fanout_bytes.chunks_exact(4)) {
+            *count = u32::from_be_bytes(bytes.try_into().expect("chunks should be 4 bytes"));
+        }
+
+        let count = fanout[255] as usize;
+        let hashes: Vec<[u8; 20]> = chunk(Self::HASH_CHUNK)?
+            .chunks_exact(20)
+            .map(|hash| hash.try_into().expect("chunks should be 20 bytes"))
+            .collect();
+        let commit_data = chunk(Self::DATA_CHUNK)?;
+        if hashes.len() != count || commit_data.len() != count * Self::DATA_ENTRY_LEN {
+            anyhow::bail!("commit count doesn't match fanout");
+        }
+
+        // The top 30 bits of the word after the parents
+        let generations = commit_data.chunks_exact(Self::DATA_ENTRY_LEN)
+            .map(|entry| u32::from_be_bytes(entry[28..32].try_into().expect("slice should be 4 bytes")) >> 2)
+            .collect();
+
+        Ok(Self { fanout, hashes, generations })
+    }
+
+    /// Returns the generation of the commit `hash`, if it's in the file and git computed it.
+    pub(crate) fn generation(&self, hash: &ObjectHash) -> Option<u32> {
+        let first_byte = hash.raw[0] as usize;
+        let end = (self.fanout[first_byte] as usize).min(self.hashes.len());
+        let start = match first_byte {
+            0 => 0,
+            byte => self.fanout[byte - 1] as usize,
+        }.min(end);
+
+        let position = start + self.hashes[start..end].binary_search(&hash.raw).ok()?;
+        Some(self.generations[position]).filter(|generation| *generation != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a commit-graph file with the given (hash, generation) pairs, sorted by hash.
+    fn commit_graph(commits: &[([u8; 20], u32)]) -> Vec<u8> {
+        let mut fanout = vec![];
+        for byte in 0..=255u8 {
+            let count = commits.iter().filter(|(hash, _)| hash[0] <= byte).count() as u32;
+            fanout.extend(count.to_be_bytes());
+        }
+        let hashes: Vec<u8> = commits.iter().flat_map(|(hash, _)| *hash).collect();
+        let commit_data: Vec<u8> = commits.iter()
+            .flat_map(|(_, generation)| {
+                let mut entry = vec![0; 28];
+                entry.extend((generation << 2).to_be_bytes());
+                entry.extend([0; 4]);
+                entry
+            })
+            .collect();
+
+        let chunks = [(*b"OIDF", fanout), (*b"OIDL", hashes), (*b"CDAT", commit_data)];
+        let mut data = b"CGPH\x01\x01\x03\x00".to_vec();
+        let mut offset = 8 + (chunks.len() + 1) * 12;
+        for (id, contents) in &chunks {
+            data.extend(id);
+            data.extend((offset as u64).to_be_bytes());
+            offset += contents.len();
+        }
+        data.extend([0; 4]);
+        data.extend((offset as u64).to_be_bytes());
+        for (_, contents) in chunks {
+            data.extend(contents);
+        }
+
+        data
+    }
+
+    #[test]
+    fn reads_generations() {
+        let graph = CommitGraphFile::parse(&commit_graph(&[([0x10; 20], 1), ([0x20; 20], 2), ([0x30; 20], 0)])).unwrap();
+
+        assert_eq!(graph.generation(&ObjectHash { raw: [0x10; 20] }), Some(1));
+        assert_eq!(graph.generation(&ObjectHash { raw: [0x20; 20] }), Some(2));
+        assert_eq!(graph.generation(&ObjectHash { raw: [0x30; 20] }), None);
+        assert_eq!(graph.generation(&ObjectHash { raw: [0x40; 20] }), None);
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let data = commit_graph(&[([0x10; 20], 1)]);
+
+        assert!(CommitGraphFile::parse(&data[..data.len() - 4]).is_err());
+        assert!(CommitGraphFile::parse(b"CGPH\x02\x01\x00\x00").is_err());
+        for len in 4..8 {
+            assert!(CommitGraphFile::parse(&data[..len]).is_err());
+        }
+    }
+}
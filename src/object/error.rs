@@ -1,4 +1,5 @@
 use super::{ObjectFormat, ObjectHash};
+use crate::workdir::WorkPathBuf;
 
 use thiserror::Error;
 
@@ -9,13 +10,30 @@ pub enum ObjectError {
         hash: ObjectHash,
         problem: String,
     },
+    #[error("Refusing to create a malformed {format}: {problem}")]
+    Malformed {
+        format: ObjectFormat,
+        problem: String,
+    },
     #[error("Unrecognized object format `{0}`")]
     UnrecognizedFormat(String),
+    #[error("Invalid file mode `{0}`")]
+    InvalidMode(String),
     #[error("Unexpected object format `{format}` (expected `{expected}`)")]
     UnexpectedFormat {
         format: ObjectFormat,
         expected: ObjectFormat,
     },
+    #[error("`{id}`: expected a {expected}, got a {found}")]
+    WrongKind {
+        id: String,
+        expected: ObjectFormat,
+        found: ObjectFormat,
+    },
+    #[error("Object {0} does not exist")]
+    NotFound(ObjectHash),
+    #[error("Object {0} is stored in a packfile, which is not supported")]
+    Packed(ObjectHash),
     #[error("The identifier `{0}` does not refer to an object")]
     InvalidId(String),
     #[error("The identifier `{id}` is ambiguous ({} matches)", matches.len())]
@@ -34,4 +52,21 @@ pub enum ObjectError {
     },
     #[error("A commit cannot be created from an empty index.")]
     EmptyIndex,
+    #[error("Tree entry `{0}` isn't a single file name, so it can't be checked out safely")]
+    UnsafeTreeEntryName(String),
+    #[error("The path `{path}` does not exist in tree {tree}")]
+    PathNotInTree {
+        path: WorkPathBuf,
+        tree: ObjectHash,
+    },
+    #[error("Commit {commit} does not have a parent #{n}")]
+    MissingParent {
+        commit: ObjectHash,
+        n: usize,
+    },
+    #[error("File changed size while it was being read (expected {expected} bytes, read {actual})")]
+    StreamSizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
 }
@@ -2,7 +2,7 @@ use std::fmt;
 
 use super::ObjectError;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum ObjectFormat {
     Blob,
     Commit,
@@ -10,6 +10,14 @@ pub enum ObjectFormat {
     Tree,
 }
 
+impl ObjectFormat {
+    /// Returns the header that comes before the body of an object of this type with
+    /// `size` bytes when it's hashed or stored, e.g. `blob 12\0`.
+    pub fn header(self, size: u64) -> Vec<u8> {
+        format!("{self} {size}\0").into_bytes()
+    }
+}
+
 impl fmt::Display for ObjectFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use ObjectFormat::*;
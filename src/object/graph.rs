@@ -0,0 +1,128 @@
+//! An in-memory cache of the commit graph, so that walking history reads and parses each
+//! commit at most once per process, however many walks a command does. Commits never
+//! change, but a process can open more than one repository (e.g. `copy-objects`), so the
+//! cache is keyed by object directory as well as hash, like the commit-graph cache.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::{
+    Result,
+    workdir::WorkDir,
+};
+
+use super::{Commit, ObjectHash, commit_graph::CommitGraphFile};
+
+/// What a history walk needs to know about a commit.
+#[derive(Debug)]
+pub struct CommitNode {
+    pub parents: Vec<ObjectHash>,
+    /// The committer time in seconds since the Unix epoch, or 0 if it's missing.
+    pub time: i64,
+    /// 1 for a root commit, otherwise one more than the highest generation of its parents.
+    /// Read from the commit-graph file if it has one, or else computed on demand by
+    /// [`CommitNode::generation`].
+    generation: OnceLock<u32>,
+}
+
+/// The nodes read so far, by the object directory they were read from and their hash.
+type NodeCache = Mutex<HashMap<(PathBuf, ObjectHash), Arc<CommitNode>>>;
+
+fn cache() -> &'static NodeCache {
+    static CACHE: OnceLock<NodeCache> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+impl CommitNode {
+    /// Returns the node for the commit `hash`, reading the commit if it isn't cached yet.
+    pub fn get(wd: &WorkDir, hash: &ObjectHash) -> Result<Arc<Self>> {
+        let key = (wd.git_path("objects"), *hash);
+        if let Some(node) = cache().lock().expect("commit cache lock should not be poisoned").get(&key) {
+            return Ok(Arc::clone(node));
+        }
+
+        // Read without holding the lock. If another thread gets there first, either
+        // node is as good as the other.
+        let commit = Commit::read(wd, hash)?;
+        let generation = OnceLock::new();
+        if let Some(known) = CommitGraphFile::get(wd).and_then(|graph| graph.generation(hash)) {
+            let _ = generation.set(known);
+        }
+        let node = Arc::new(Self {
+            parents: commit.parents().to_vec(),
+            time: commit.committer_time().unwrap_or(0),
+            generation,
+        });
+
+        let mut cache = cache().lock().expect("commit cache lock should not be poisoned");
+        Ok(Arc::clone(cache.entry(key).or_insert(node)))
+    }
+
+    /// Returns the generation number of the commit `hash`. A commit's generation is always
+    /// greater than its ancestors', so a commit with a lower generation can't descend from
+    /// one with a higher generation.
+    pub fn generation(wd: &WorkDir, hash: &ObjectHash) -> Result<u32> {
+        // Depth-first, computing each commit's generation after its parents'
+        let mut stack = vec![(*hash, false)];
+        while let Some((hash, parents_done)) = stack.pop() {
+            let node = Self::get(wd, &hash)?;
+            if node.generation.get().is_some() {
+                continue;
+            }
+
+            if parents_done {
+                let mut max_parent = 0;
+                for parent in &node.parents {
+                    let parent_generation = Self::get(wd, parent)?.generation.get().copied();
+                    max_parent = max_parent.max(parent_generation.expect("parent generations should be computed first"));
+                }
+                let _ = node.generation.set(max_parent + 1);
+            }
+            else {
+                stack.push((hash, true));
+                stack.extend(node.parents.iter().map(|parent| (*parent, false)));
+            }
+        }
+
+        Ok(*Self::get(wd, hash)?.generation.get().expect("generation should have been computed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{commit, temp_workdir};
+
+    #[test]
+    fn computes_generations() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        let root = commit(&wd, "root", &[]);
+        let a = commit(&wd, "a", &[root]);
+        let b = commit(&wd, "b", &[a]);
+        let side = commit(&wd, "side", &[root]);
+        let merge = commit(&wd, "merge", &[side, b]);
+
+        assert_eq!(CommitNode::generation(&wd, &merge).unwrap(), 4);
+        assert_eq!(CommitNode::generation(&wd, &side).unwrap(), 2);
+        assert_eq!(CommitNode::generation(&wd, &root).unwrap(), 1);
+
+        let node = CommitNode::get(&wd, &merge).unwrap();
+        assert_eq!(node.parents, [side, b]);
+        assert_eq!(node.time, 5);
+        assert!(Arc::ptr_eq(&node, &CommitNode::get(&wd, &merge).unwrap()));
+    }
+
+    #[test]
+    fn caches_each_repository_separately() {
+        let (_temp_dir, wd) = temp_workdir();
+        let (_other_temp_dir, other_wd) = temp_workdir();
+
+        let root = commit(&wd, "root", &[]);
+        CommitNode::get(&wd, &root).unwrap();
+        assert!(CommitNode::get(&other_wd, &root).is_err());
+    }
+}
@@ -1,11 +1,12 @@
 use std::{
+    io::{self, Write},
     path::PathBuf,
     str,
 };
 
 use sha1::{Sha1, Digest};
 
-use super::ObjectError;
+use super::{ObjectError, ObjectFormat};
 
 /// An SHA-1 hash used to identify an object stored in a Git repository.
 #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
@@ -26,6 +27,17 @@ impl ObjectHash {
         ObjectHash { raw }
     }
 
+    /// Computes the hash of an object of type `format` whose serialized form is `body`.
+    /// This is the hash of the header followed by the body, but they're hashed one after
+    /// the other instead of being copied into one buffer.
+    pub fn for_object(format: ObjectFormat, body: &[u8]) -> ObjectHash {
+        let mut hasher = ObjectHasher::new(io::sink(), format, body.len() as u64)
+            .expect("writing to a sink should not fail");
+        hasher.write_all(body).expect("writing to a sink should not fail");
+
+        hasher.finish().0
+    }
+
     /// Constructs the path to the object with this hash relative to a repo's
     /// objects directory. When converted to a hex string, the first two digits
     /// are the subdirectory name and the last 38 are the file name.
@@ -38,6 +50,51 @@ impl ObjectHash {
     }
 }
 
+/// Computes the hash of an object as its body is written, passing the header and body
+/// through to another writer, e.g. the zlib encoder for its object file. Use
+/// [`io::sink`] as the writer to only compute the hash.
+pub struct ObjectHasher<W: Write> {
+    inner: W,
+    hasher: Sha1,
+}
+
+impl<W: Write> ObjectHasher<W> {
+    /// Writes the header for an object of type `format` with a body of `size` bytes.
+    /// The body should be written next.
+    pub fn new(inner: W, format: ObjectFormat, size: u64) -> io::Result<Self> {
+        let mut hasher = Self {
+            inner,
+            hasher: Sha1::new(),
+        };
+        hasher.write_all(&format.header(size))?;
+
+        Ok(hasher)
+    }
+
+    /// Returns the hash of everything written so far, along with the inner writer.
+    pub fn finish(self) -> (ObjectHash, W) {
+        let raw = self.hasher.finalize()
+            .as_slice()
+            .try_into()
+            .expect("Sha1 hash should always be 20 bytes");
+
+        (ObjectHash { raw }, self.inner)
+    }
+}
+
+impl<W: Write> Write for ObjectHasher<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hasher.update(&buf[..len]);
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 impl std::fmt::Display for ObjectHash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let hash_string = base16ct::lower::encode_string(&self.raw);
@@ -87,6 +144,14 @@ impl TryFrom<&[u8]> for ObjectHash {
 mod tests {
     use super::*;
 
+    #[test]
+    fn for_object_hashes_header_and_body() {
+        // `echo -n "hello" | git hash-object --stdin`
+        let hash = ObjectHash::for_object(ObjectFormat::Blob, b"hello");
+        assert_eq!(hash.to_string(), "b6fc4c620b67d95f953a5c1c1230aaab5db5a1b0");
+        assert_eq!(hash, ObjectHash::new(b"blob 5\0hello"));
+    }
+
     #[test]
     fn bytes_to_string() {
         let hash = ObjectHash::try_from([
@@ -2,15 +2,32 @@ use anyhow::bail;
 
 use crate::{
     Result,
+    date,
     repo::Repository
 };
 
+use super::{Commit, SshSigner};
+
 /// Metadata about certain objects in a repository (namely, commits and annotated tags).
 /// Includes the name and email of the author as well as a descriptive message.
 pub struct ObjectMetadata {
     pub author_name: String,
     pub author_email: String,
+    /// The person creating the object, who is the author unless it was overridden.
+    pub committer_name: String,
+    pub committer_email: String,
     pub message: String,
+    /// When the object was made, in seconds since the Unix epoch. If not set, no date is
+    /// recorded.
+    pub timestamp: Option<i64>,
+    /// The timezone offset of `timestamp`, e.g. `-0700`.
+    pub timezone: String,
+    /// When the author wrote the changes, with its timezone offset, if that's earlier than
+    /// `timestamp`, e.g. for a commit replayed by `cherry-pick`. If not set, the author line
+    /// records `timestamp` like the committer line.
+    pub author_date: Option<(i64, String)>,
+    /// If present, the object will be signed.
+    pub signer: Option<SshSigner>,
 }
 
 impl ObjectMetadata {
@@ -28,13 +45,115 @@ impl ObjectMetadata {
         };
 
         Ok(ObjectMetadata {
+            committer_name: author_name.clone(),
+            committer_email: author_email.clone(),
             author_name,
             author_email,
-            message
+            message,
+            timestamp: None,
+            timezone: "+0000".to_owned(),
+            author_date: None,
+            signer: None,
         })
     }
 
+    /// Overrides the author with `identity`, which must look like `Name <email>`. The
+    /// committer is unchanged.
+    pub fn set_author(&mut self, identity: &str) -> Result<()> {
+        let Some((name, rest)) = identity.split_once('<') else {
+            bail!("Invalid author `{identity}`: expected `Name <email>`");
+        };
+        let Some(email) = rest.trim_end().strip_suffix('>') else {
+            bail!("Invalid author `{identity}`: expected `Name <email>`");
+        };
+
+        self.author_name = name.trim().to_owned();
+        self.author_email = email.to_owned();
+
+        Ok(())
+    }
+
+    /// Sets the date from either git's internal format (`1700000000 +0100`, optionally
+    /// prefixed with `@`), ISO 8601 (`2024-01-31T12:00:00+01:00` or
+    /// `2024-01-31 12:00:00 +0100`), or an approximate date like `yesterday` or
+    /// `2 days ago`. The timezone defaults to UTC.
+    pub fn set_date(&mut self, date: &str) -> Result<()> {
+        let parsed = date::parse(date)
+            .or_else(|| date::approxidate(date, date::now()).map(|timestamp| (timestamp, "+0000".to_owned())));
+        let Some((timestamp, timezone)) = parsed else {
+            bail!("Invalid date `{date}`");
+        };
+
+        self.timestamp = Some(timestamp);
+        self.timezone = timezone;
+
+        Ok(())
+    }
+
+    /// Keeps the author of `commit` and the date they wrote it, for a new commit that
+    /// replays its changes. The committer is still the current user, and the commit is
+    /// dated now unless a date was already set.
+    pub fn preserve_author(&mut self, commit: &Commit) -> Result<()> {
+        let Some(author) = commit.author() else {
+            bail!("Commit has no author");
+        };
+        self.set_author(author)?;
+        self.author_date = commit.author_date()
+            .map(|(timestamp, timezone)| (timestamp, timezone.to_owned()));
+        if self.timestamp.is_none() {
+            self.timestamp = Some(date::now());
+        }
+
+        Ok(())
+    }
+
     pub fn author_line(&self) -> String {
-        format!("{} <{}>", self.author_name, self.author_email)
+        let date = match &self.author_date {
+            Some((timestamp, timezone)) => Some((*timestamp, timezone.as_str())),
+            None => self.timestamp.map(|timestamp| (timestamp, self.timezone.as_str())),
+        };
+        identity_line(&self.author_name, &self.author_email, date)
+    }
+
+    pub fn committer_line(&self) -> String {
+        let date = self.timestamp.map(|timestamp| (timestamp, self.timezone.as_str()));
+        identity_line(&self.committer_name, &self.committer_email, date)
+    }
+}
+
+fn identity_line(name: &str, email: &str, date: Option<(i64, &str)>) -> String {
+    match date {
+        Some((timestamp, timezone)) => format!("{name} <{email}> {timestamp} {timezone}"),
+        None => format!("{name} <{email}>"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preserved_author_keeps_original_date() {
+        let commit = Commit::deserialize(b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author Ann <ann@example.com> 1000 +0200\n\
+            committer Bo <bo@example.com> 2000 +0000\n\nmessage\n".to_vec()).unwrap();
+        let mut meta = ObjectMetadata {
+            author_name: "Cy".to_owned(),
+            author_email: "cy@example.com".to_owned(),
+            committer_name: "Cy".to_owned(),
+            committer_email: "cy@example.com".to_owned(),
+            message: "message\n".to_owned(),
+            timestamp: None,
+            timezone: "+0000".to_owned(),
+            author_date: None,
+            signer: None,
+        };
+
+        meta.preserve_author(&commit).unwrap();
+
+        assert_eq!(meta.author_line(), "Ann <ann@example.com> 1000 +0200");
+        let now = meta.timestamp.unwrap();
+        assert!(now >= 2000);
+        assert_eq!(meta.committer_line(), format!("Cy <cy@example.com> {now} +0000"));
     }
 }
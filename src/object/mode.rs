@@ -0,0 +1,99 @@
+use std::fmt;
+
+use super::ObjectError;
+
+/// The kind of file recorded by a tree or index entry.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FileMode {
+    /// A regular file (`100644`).
+    Regular,
+    /// An executable file (`100755`).
+    Executable,
+    /// A symbolic link, whose blob holds the target (`120000`).
+    Symlink,
+    /// A subtree (`40000`).
+    Directory,
+    /// A submodule, which records a commit in another repository (`160000`).
+    Gitlink,
+}
+
+impl FileMode {
+    /// Interprets mode bits from a tree or index entry. As in git, only the file type and
+    /// the owner's executable bit matter for regular files, so e.g. `100664` is regular.
+    pub fn from_bits(bits: u32) -> Result<Self, ObjectError> {
+        use FileMode::*;
+
+        match bits & 0o170000 {
+            0o100000 if bits & 0o100 != 0 => Ok(Executable),
+            0o100000 => Ok(Regular),
+            0o120000 => Ok(Symlink),
+            0o040000 => Ok(Directory),
+            0o160000 => Ok(Gitlink),
+            _ => Err(ObjectError::InvalidMode(format!("{bits:o}"))),
+        }
+    }
+
+    /// Returns the canonical mode bits.
+    pub fn bits(&self) -> u32 {
+        use FileMode::*;
+
+        match self {
+            Regular => 0o100644,
+            Executable => 0o100755,
+            Symlink => 0o120000,
+            Directory => 0o040000,
+            Gitlink => 0o160000,
+        }
+    }
+
+    /// Returns the mode as it's written in a tree object, where directories don't have a
+    /// leading zero.
+    pub fn as_tree_str(&self) -> &'static str {
+        use FileMode::*;
+
+        match self {
+            Regular => "100644",
+            Executable => "100755",
+            Symlink => "120000",
+            Directory => "40000",
+            Gitlink => "160000",
+        }
+    }
+}
+
+/// Formats the mode as six octal digits, as in `ls-tree` and diff headers.
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:06o}", self.bits())
+    }
+}
+
+impl TryFrom<&str> for FileMode {
+    type Error = ObjectError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let bits = u32::from_str_radix(value, 8)
+            .map_err(|_| ObjectError::InvalidMode(value.to_owned()))?;
+
+        Self::from_bits(bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!(FileMode::try_from("40000").unwrap(), FileMode::Directory);
+        assert_eq!(FileMode::try_from("040000").unwrap(), FileMode::Directory);
+        assert_eq!(FileMode::try_from("100664").unwrap(), FileMode::Regular);
+        assert_eq!(FileMode::try_from("100744").unwrap(), FileMode::Executable);
+        assert!(FileMode::try_from("100aaa").is_err());
+        assert!(FileMode::try_from("170000").is_err());
+
+        assert_eq!(FileMode::Directory.as_tree_str(), "40000");
+        assert_eq!(FileMode::Directory.to_string(), "040000");
+        assert_eq!(FileMode::from_bits(33188).unwrap(), FileMode::Regular);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    Result,
+    workdir::{WorkDir, WorkPathBuf},
+};
+
+use super::{GitObject, ObjectError, ObjectFormat, ObjectHash, Tree};
+
+/// A parsed revision such as `HEAD~2`, `v1.0^{tree}`, or `main:src/lib.rs`.
+///
+/// See [the git docs](https://git-scm.com/docs/gitrevisions) for the full syntax.
+/// Only a subset is supported: a name (anything [`GitObject::find`] accepts), followed
+/// by any number of `~n`, `^n`, and `^{type}` suffixes, optionally followed by `:path`.
+#[derive(PartialEq, Eq, Debug)]
+pub(super) struct Revision<'a> {
+    pub name: &'a str,
+    pub ops: Vec<RevisionOp>,
+    pub path: Option<&'a str>,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub(super) enum RevisionOp {
+    /// `~n`: the nth generation ancestor, following first parents only.
+    Ancestor(usize),
+    /// `^n`: the nth parent. `^0` is the commit itself.
+    Parent(usize),
+    /// `^{type}`: peel until an object of the given type is reached. `^{}` peels tags
+    /// until something else is reached.
+    Peel(Option<ObjectFormat>),
+}
+
+impl<'a> Revision<'a> {
+    /// Parses a revision. Returns `None` if `id` is not valid revision syntax.
+    pub fn parse(id: &'a str) -> Option<Self> {
+        let (rev, path) = match id.split_once(':') {
+            Some((rev, path)) => (rev, Some(path)),
+            None => (id, None),
+        };
+
+        let name_len = rev.find(['~', '^']).unwrap_or(rev.len());
+        let (name, mut rest) = rev.split_at(name_len);
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut ops = vec![];
+        while let Some(ch) = rest.chars().next() {
+            rest = &rest[ch.len_utf8()..];
+
+            if ch == '^' && rest.starts_with('{') {
+                let end = rest.find('}')?;
+                let format = match &rest[1..end] {
+                    "" => None,
+                    format => Some(ObjectFormat::try_from(format).ok()?),
+                };
+                ops.push(RevisionOp::Peel(format));
+                rest = &rest[end + 1..];
+                continue;
+            }
+
+            let digits_len = rest.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(rest.len());
+            let count = match digits_len {
+                0 => 1,
+                _ => rest[..digits_len].parse().ok()?,
+            };
+            rest = &rest[digits_len..];
+
+            match ch {
+                '~' => ops.push(RevisionOp::Ancestor(count)),
+                '^' => ops.push(RevisionOp::Parent(count)),
+                _ => return None,
+            }
+        }
+
+        Some(Self { name, ops, path })
+    }
+
+    /// Applies the suffixes and path of this revision to `hash`, the object that the
+    /// name resolved to.
+    pub fn apply(&self, wd: &WorkDir, mut hash: ObjectHash) -> Result<ObjectHash> {
+        for op in &self.ops {
+            hash = match op {
+                RevisionOp::Ancestor(count) => {
+                    let mut hash = GitObject::peel(wd, &hash, ObjectFormat::Commit)?;
+                    for _ in 0..*count {
+                        hash = Self::nth_parent(wd, &hash, 1)?;
+                    }
+                    hash
+                },
+                RevisionOp::Parent(0) => GitObject::peel(wd, &hash, ObjectFormat::Commit)?,
+                RevisionOp::Parent(n) => {
+                    let hash = GitObject::peel(wd, &hash, ObjectFormat::Commit)?;
+                    Self::nth_parent(wd, &hash, *n)?
+                },
+                RevisionOp::Peel(Some(format)) => GitObject::peel(wd, &hash, *format)?,
+                RevisionOp::Peel(None) => GitObject::peel_tags(wd, &hash)?,
+            };
+        }
+
+        if let Some(path) = self.path {
+            let tree_hash = GitObject::peel(wd, &hash, ObjectFormat::Tree)?;
+            let path = WorkPathBuf::try_from(path)?;
+            if path.is_empty() {
+                return Ok(tree_hash);
+            }
+
+            let tree = Tree::read(wd, &tree_hash)?;
+            hash = match tree.find_entry(wd, &path)? {
+                Some(entry) => entry.hash,
+                None => return Err(ObjectError::PathNotInTree {
+                    path,
+                    tree: tree_hash,
+                }.into()),
+            };
+        }
+
+        Ok(hash)
+    }
+
+    /// Returns the nth (1-based) parent of the commit `hash`.
+    fn nth_parent(wd: &WorkDir, hash: &ObjectHash, n: usize) -> Result<ObjectHash> {
+        let commit = super::Commit::read(wd, hash)?;
+
+        commit.parents()
+            .get(n - 1)
+            .copied()
+            .ok_or_else(|| ObjectError::MissingParent {
+                commit: *hash,
+                n,
+            }.into())
+    }
+}
+
+impl GitObject {
+    /// Follows tags (and commits, if `format` is [`ObjectFormat::Tree`]) from the object
+    /// `hash` until an object of type `format` is reached.
+    pub fn peel(wd: &WorkDir, hash: &ObjectHash, format: ObjectFormat) -> Result<ObjectHash> {
+        let mut hash = *hash;
+
+        loop {
+            let object = GitObject::read(wd, &hash)?;
+            if object.get_format() == format {
+                return Ok(hash);
+            }
+
+            hash = match object {
+                GitObject::Tag(tag) => tag.target()?,
+                GitObject::Commit(commit) if format == ObjectFormat::Tree => *commit.tree(),
+                object => return Err(ObjectError::UnexpectedFormat {
+                    format: object.get_format(),
+                    expected: format,
+                }.into()),
+            };
+        }
+    }
+
+    /// Follows tags from the object `hash` until something other than a tag is reached.
+    pub fn peel_tags(wd: &WorkDir, hash: &ObjectHash) -> Result<ObjectHash> {
+        let mut hash = *hash;
+
+        while let GitObject::Tag(tag) = GitObject::read(wd, &hash)? {
+            hash = tag.target()?;
+        }
+
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_plain_name() {
+        let rev = Revision::parse("main").unwrap();
+        assert_eq!(rev, Revision { name: "main", ops: vec![], path: None });
+    }
+
+    #[test]
+    fn parse_suffixes() {
+        let rev = Revision::parse("HEAD~2^^3~^{tree}").unwrap();
+        assert_eq!(rev.name, "HEAD");
+        assert_eq!(rev.ops, vec![
+            RevisionOp::Ancestor(2),
+            RevisionOp::Parent(1),
+            RevisionOp::Parent(3),
+            RevisionOp::Ancestor(1),
+            RevisionOp::Peel(Some(ObjectFormat::Tree)),
+        ]);
+    }
+
+    #[test]
+    fn parse_path() {
+        let rev = Revision::parse("v1.0^{}:src/lib.rs").unwrap();
+        assert_eq!(rev.name, "v1.0");
+        assert_eq!(rev.ops, vec![RevisionOp::Peel(None)]);
+        assert_eq!(rev.path, Some("src/lib.rs"));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_syntax() {
+        assert!(Revision::parse("~1").is_none());
+        assert!(Revision::parse("HEAD^{bogus}").is_none());
+        assert!(Revision::parse("HEAD^{tree").is_none());
+        assert!(Revision::parse("HEAD~é").is_none());
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    repo::Repository,
+};
+
+/// The namespace git uses for SSH signatures, which prevents a signature made for some
+/// other purpose from being accepted on a commit or tag.
+const SSH_NAMESPACE: &str = "git";
+
+/// The first line of an SSH signature.
+pub const SSH_SIGNATURE_HEADER: &str = "-----BEGIN SSH SIGNATURE-----";
+
+/// Signs commits and tags with an SSH key (`gpg.format = ssh`) using `ssh-keygen`.
+pub struct SshSigner {
+    program: String,
+    key_path: PathBuf,
+}
+
+impl SshSigner {
+    /// Constructs a signer using the key in `user.signingKey`. Fails if `gpg.format`
+    /// isn't `ssh`, since OpenPGP and X.509 signatures are not supported.
+    pub fn from_config(repo: &Repository) -> Result<Self> {
+        match repo.get_config("gpg", "format") {
+            Some("ssh") => (),
+            format => {
+                let format = format.unwrap_or("openpgp").to_owned();
+                return Err(SignatureError::UnsupportedFormat(format).into());
+            },
+        }
+
+        let key_path = match repo.get_config("user", "signingKey") {
+            Some(key) => PathBuf::from(key),
+            None => return Err(SignatureError::SigningKeyMissing.into()),
+        };
+
+        Ok(Self {
+            program: ssh_program(repo),
+            key_path,
+        })
+    }
+
+    /// Signs `payload` and returns the armored signature.
+    pub fn sign(&self, payload: &[u8]) -> Result<String> {
+        let key_path = self.key_path.to_string_lossy();
+        let output = run_with_stdin(
+            &self.program,
+            &["-Y", "sign", "-n", SSH_NAMESPACE, "-f", &key_path],
+            payload,
+        )?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            return Err(SignatureError::SigningFailed(stderr).into());
+        }
+
+        let signature = String::from_utf8_lossy(&output.stdout).into_owned();
+        if !signature.starts_with(SSH_SIGNATURE_HEADER) {
+            return Err(SignatureError::SigningFailed(signature).into());
+        }
+
+        Ok(signature)
+    }
+}
+
+/// Verifies that `signature` is a valid SSH signature of `payload` made by one of the
+/// signers listed in `gpg.ssh.allowedSignersFile`. Returns the principal (usually an email
+/// address) that made the signature.
+pub fn verify(repo: &Repository, payload: &[u8], signature: &str) -> Result<String> {
+    let allowed_signers = match repo.get_config(r#"gpg "ssh""#, "allowedSignersFile") {
+        Some(path) => path.to_owned(),
+        None => return Err(SignatureError::AllowedSignersMissing.into()),
+    };
+    let program = ssh_program(repo);
+
+    // ssh-keygen only reads signatures from files. This one is created exclusively with a
+    // random name, and removed when dropped.
+    let mut signature_file = tempfile::Builder::new().prefix("wyag_signature_").tempfile()?;
+    signature_file.write_all(signature.as_bytes())?;
+    signature_file.flush()?;
+    let result = verify_with_file(&program, &allowed_signers, signature_file.path(), payload);
+    signature_file.close()?;
+
+    result
+}
+
+fn verify_with_file(program: &str, allowed_signers: &str, signature_path: &Path, payload: &[u8]) -> Result<String> {
+    let signature_path = signature_path.to_string_lossy();
+
+    let output = Command::new(program)
+        .args(["-Y", "find-principals", "-f", allowed_signers, "-s", &signature_path])
+        .output()?;
+    let principals = String::from_utf8_lossy(&output.stdout);
+    let principal = match principals.lines().next() {
+        Some(principal) if output.status.success() => principal.to_owned(),
+        _ => return Err(SignatureError::UnknownSigner.into()),
+    };
+
+    let output = run_with_stdin(
+        program,
+        &["-Y", "verify", "-n", SSH_NAMESPACE, "-f", allowed_signers, "-I", &principal, "-s", &signature_path],
+        payload,
+    )?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(SignatureError::VerificationFailed(stderr).into());
+    }
+
+    Ok(principal)
+}
+
+/// Returns the program used for SSH signing (`gpg.ssh.program`).
+fn ssh_program(repo: &Repository) -> String {
+    repo.get_config(r#"gpg "ssh""#, "program")
+        .unwrap_or("ssh-keygen")
+        .to_owned()
+}
+
+/// Runs `program` with `args`, writing `input` to its stdin, and collects its output.
+fn run_with_stdin(program: &str, args: &[&str], input: &[u8]) -> Result<std::process::Output> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| SignatureError::ProgramFailed {
+            program: program.to_owned(),
+            reason: err.to_string(),
+        })?;
+
+    child.stdin.take()
+        .expect("stdin should be piped")
+        .write_all(input)?;
+
+    Ok(child.wait_with_output()?)
+}
+
+#[derive(Error, Debug)]
+pub enum SignatureError {
+    #[error("Signature format `{0}` is not supported (set gpg.format to ssh)")]
+    UnsupportedFormat(String),
+    #[error("No signing key configured (set user.signingKey)")]
+    SigningKeyMissing,
+    #[error("No allowed signers file configured (set gpg.ssh.allowedSignersFile)")]
+    AllowedSignersMissing,
+    #[error("Failed to run `{program}`: {reason}")]
+    ProgramFailed {
+        program: String,
+        reason: String,
+    },
+    #[error("Failed to sign: {0}")]
+    SigningFailed(String),
+    #[error("The object has no signature")]
+    Unsigned,
+    #[error("The signature was not made by an allowed signer")]
+    UnknownSigner,
+    #[error("Bad signature: {0}")]
+    VerificationFailed(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use assert_fs::{prelude::*, TempDir};
+
+    use crate::{
+        index::Index,
+        object::{Commit, GitObject, ObjectMetadata, Tag},
+    };
+
+    use super::*;
+
+    /// Stands in for ssh-keygen. A "signature" holds the checksum of the signed data, so
+    /// verifying it checks that the payload was reconstructed byte for byte.
+    #[cfg(unix)]
+    const SSH_STUB: &str = r#"#!/bin/sh
+case "$2" in
+    sign)
+        printf -- '-----BEGIN SSH SIGNATURE-----\n%s\n-----END SSH SIGNATURE-----\n' "$(cksum)" ;;
+    find-principals)
+        echo test@example.com ;;
+    verify)
+        while [ $# -gt 0 ]; do
+            [ "$1" = -s ] && signature="$2"
+            shift
+        done
+        grep -qxF "$(cksum)" "$signature" || { echo "payload does not match" >&2; exit 1; } ;;
+esac
+"#;
+
+    /// Sets up a repository with one staged file whose signing program is `SSH_STUB`.
+    #[cfg(unix)]
+    fn signing_repo(temp_dir: &TempDir) -> (Repository, Index) {
+        use std::os::unix::fs::PermissionsExt;
+
+        temp_dir.child(".git/objects").create_dir_all().unwrap();
+        temp_dir.child(".git/refs/heads").create_dir_all().unwrap();
+        temp_dir.child(".git/HEAD").write_str("ref: refs/heads/main\n").unwrap();
+        temp_dir.child(".git/config").write_str("[core]\nrepositoryformatversion = 0\n").unwrap();
+        temp_dir.child("ssh-stub").write_str(SSH_STUB).unwrap();
+        fs::set_permissions(temp_dir.child("ssh-stub"), fs::Permissions::from_mode(0o755)).unwrap();
+        temp_dir.child("a.txt").write_str("a\n").unwrap();
+
+        let mut repo = Repository::find(temp_dir.path()).unwrap();
+        let program = temp_dir.child("ssh-stub").to_string_lossy().into_owned();
+        repo.set_config("user", "name", "Test User".to_owned());
+        repo.set_config("user", "email", "test@example.com".to_owned());
+        repo.set_config("gpg", "format", "ssh".to_owned());
+        repo.set_config(r#"gpg "ssh""#, "program", program);
+        repo.set_config(r#"gpg "ssh""#, "allowedSignersFile", "allowed_signers".to_owned());
+        repo.set_config("user", "signingKey", "key".to_owned());
+
+        let mut index = Index::new(None);
+        index.add(repo.workdir(), temp_dir.child("a.txt"), false).unwrap();
+
+        (repo, index)
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn signed_commit_verifies() {
+        let temp_dir = TempDir::new().unwrap();
+        let (repo, index) = signing_repo(&temp_dir);
+        let mut meta = ObjectMetadata::new(&repo, "first\n".to_owned()).unwrap();
+        meta.signer = Some(SshSigner::from_config(&repo).unwrap());
+        let hash = Commit::create(&index, repo.workdir(), meta).unwrap();
+
+        // The signature is the last header, with continuation lines indented
+        let commit = Commit::read(repo.workdir(), &hash).unwrap();
+        let raw = String::from_utf8(commit.serialize()).unwrap();
+        let (header, message) = raw.split_once("\n\n").unwrap();
+        let (unsigned_header, signature) = header.split_once("\ngpgsig ").unwrap();
+        assert!(unsigned_header.lines().last().unwrap().starts_with("committer "), "{raw}");
+        assert!(signature.starts_with("-----BEGIN SSH SIGNATURE-----\n "), "{raw}");
+        assert!(signature.ends_with("\n -----END SSH SIGNATURE-----"), "{raw}");
+        assert_eq!(message, "first\n");
+
+        let (payload, signature) = commit.signed_payload().unwrap();
+        assert_eq!(String::from_utf8(payload.clone()).unwrap(), format!("{unsigned_header}\n\nfirst\n"));
+        assert_eq!(verify(&repo, &payload, &signature).unwrap(), "test@example.com");
+        assert!(verify(&repo, b"tampered", &signature).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn signed_tag_verifies() {
+        let temp_dir = TempDir::new().unwrap();
+        let (repo, index) = signing_repo(&temp_dir);
+        let meta = ObjectMetadata::new(&repo, "first\n".to_owned()).unwrap();
+        let commit = Commit::create(&index, repo.workdir(), meta).unwrap();
+        let mut meta = ObjectMetadata::new(&repo, "release".to_owned()).unwrap();
+        meta.signer = Some(SshSigner::from_config(&repo).unwrap());
+        let tag = Tag::create(repo.workdir(), "v1", &commit, meta).unwrap();
+
+        let raw = String::from_utf8(GitObject::Tag(tag).serialize()).unwrap();
+        // Unlike a commit's, a tag's signature is appended to the message
+        assert!(raw.contains("\n\nrelease\n-----BEGIN SSH SIGNATURE-----\n"), "{raw}");
+        assert!(raw.ends_with("-----END SSH SIGNATURE-----\n"), "{raw}");
+
+        let tag = Tag::deserialize(raw.into_bytes()).unwrap();
+        let (payload, signature) = tag.signed_payload().unwrap();
+        assert!(String::from_utf8(payload.clone()).unwrap().ends_with("\n\nrelease\n"));
+        assert_eq!(verify(&repo, &payload, &signature).unwrap(), "test@example.com");
+    }
+}
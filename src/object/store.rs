@@ -0,0 +1,446 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use anyhow::Context;
+
+use crate::{Result, workdir::WorkDir};
+use super::{GitObject, ObjectError, ObjectFormat, ObjectHash};
+
+/// How deeply alternates may refer to other alternates, same as git.
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// All of the places a repository's objects may be stored: its own `objects` directory,
+/// the directories listed in `objects/info/alternates` (recursively), and the packfiles
+/// in each of them.
+///
+/// Packfiles can't be read, but their indexes are used to tell whether an object exists.
+pub struct ObjectStore {
+    /// The object directories, starting with the repository's own.
+    dirs: Vec<PathBuf>,
+    packs: Vec<PackIndex>,
+    /// Reuse the listings of fan-out directories from earlier lookups. Always set, except
+    /// by tests that compare against reading the directory each time.
+    cache_listings: bool,
+}
+
+/// The sorted file names in each fan-out directory (`objects/xx/`) listed so far by this
+/// process. Abbreviated hashes are resolved against these, so resolving many of them, e.g.
+/// the commits shown by `log --oneline`, doesn't read the same directory each time. Writing or
+/// deleting a loose object forgets the listing of its directory.
+fn listing_cache() -> &'static Mutex<HashMap<PathBuf, Arc<Vec<String>>>> {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, Arc<Vec<String>>>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Forgets the cached listing of the fan-out directory containing the loose object file
+/// at `object_path`. Call this after writing or deleting the file.
+pub(crate) fn forget_listing(object_path: &Path) {
+    if let Some(dir) = object_path.parent() {
+        listing_cache().lock().expect("listing cache lock should not be poisoned").remove(dir);
+    }
+}
+
+impl ObjectStore {
+    /// Finds the object directories and pack indexes for the repository at `wd`.
+    pub fn open(wd: &WorkDir) -> Result<Self> {
+        let mut store = Self {
+            dirs: vec![],
+            packs: vec![],
+            cache_listings: true,
+        };
+        store.add_dir(wd.git_path("objects"), 0)?;
+
+        Ok(store)
+    }
+
+    fn add_dir(&mut self, dir: PathBuf, depth: usize) -> Result<()> {
+        if self.dirs.contains(&dir) || !dir.is_dir() {
+            return Ok(());
+        }
+
+        let pack_dir = dir.join("pack");
+        if pack_dir.is_dir() {
+            let mut index_paths = vec![];
+            for entry in fs::read_dir(&pack_dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "idx") && path.with_extension("pack").is_file() {
+                    index_paths.push(path);
+                }
+            }
+            index_paths.sort();
+
+            for path in index_paths {
+                self.packs.push(PackIndex::read(&path)?);
+            }
+        }
+
+        let alternates = fs::read_to_string(dir.join("info/alternates")).unwrap_or_default();
+        self.dirs.push(dir.clone());
+
+        if depth < MAX_ALTERNATE_DEPTH {
+            let alternates = alternates.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'));
+            for alternate in alternates {
+                // Relative paths are relative to the object directory that lists them
+                self.add_dir(dir.join(alternate), depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the object directories, starting with the repository's own and followed by
+    /// its alternates.
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Returns true if the object with hash `hash` is stored anywhere in the repository.
+    pub fn contains(&self, hash: &ObjectHash) -> bool {
+        self.find_loose(hash).is_some()
+            || self.packs.iter().any(|pack| pack.contains(hash))
+    }
+
+    /// Returns the hashes in `hashes` that aren't stored anywhere, in sorted order. This is
+    /// faster than calling [`ObjectStore::contains`] for each one, because each pack index
+    /// is searched in a single pass.
+    pub fn missing(&self, hashes: &[ObjectHash]) -> Vec<ObjectHash> {
+        let mut missing: Vec<ObjectHash> = hashes.iter()
+            .filter(|hash| self.find_loose(hash).is_none())
+            .copied()
+            .collect();
+        missing.sort_by_key(|hash| hash.raw);
+        missing.dedup();
+
+        for pack in &self.packs {
+            missing = pack.missing_sorted(&missing);
+        }
+
+        missing
+    }
+
+    /// Finds every object whose hash starts with `hex_prefix`, whether it's loose or packed,
+    /// in sorted order and without duplicates. An object is often in more than one place,
+    /// e.g. loose and in a pack after `git gc --keep-unreachable`, but it's still one match.
+    pub fn prefix_matches(&self, hex_prefix: &str) -> Vec<ObjectHash> {
+        let hex_prefix = hex_prefix.to_ascii_lowercase();
+        if hex_prefix.len() < 2 || hex_prefix.len() > 40 || !hex_prefix.bytes().all(|ch| ch.is_ascii_hexdigit()) {
+            return vec![];
+        }
+
+        let mut matches = vec![];
+        let (dir_name, file_prefix) = hex_prefix.split_at(2);
+        for dir in &self.dirs {
+            let files = self.list_fanout_dir(&dir.join(dir_name));
+            let start = files.partition_point(|file| file.as_str() < file_prefix);
+            let found = files[start..].iter()
+                .take_while(|file| file.starts_with(file_prefix))
+                .filter_map(|file| ObjectHash::try_from(format!("{dir_name}{file}").as_str()).ok());
+            matches.extend(found);
+        }
+
+        for pack in &self.packs {
+            pack.prefix_matches(&hex_prefix, &mut matches);
+        }
+
+        matches.sort_by_key(|hash| hash.raw);
+        matches.dedup();
+        matches
+    }
+
+    /// Reads the object with hash `hash` without parsing it. Returns its format and the
+    /// data that follows the header.
+    pub fn read_raw(&self, hash: &ObjectHash) -> Result<(ObjectFormat, Vec<u8>)> {
+        if let Some(path) = self.find_loose(hash) {
+            let file = File::open(&path)
+                .with_context(|| format!("Failed to open object file {path:?}"))?;
+            return GitObject::decode_loose(file, hash);
+        }
+
+        match self.packs.iter().any(|pack| pack.contains(hash)) {
+            true => Err(ObjectError::Packed(*hash).into()),
+            false => Err(ObjectError::NotFound(*hash).into()),
+        }
+    }
+
+    /// Reads only the format and size of the object with hash `hash`.
+    pub fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectFormat, usize)> {
+        if let Some(path) = self.find_loose(hash) {
+            let file = File::open(&path)
+                .with_context(|| format!("Failed to open object file {path:?}"))?;
+            return GitObject::decode_loose_header(file, hash);
+        }
+
+        match self.packs.iter().any(|pack| pack.contains(hash)) {
+            true => Err(ObjectError::Packed(*hash).into()),
+            false => Err(ObjectError::NotFound(*hash).into()),
+        }
+    }
+
+    /// Reads and parses the object with hash `hash`.
+    pub fn read(&self, hash: &ObjectHash) -> Result<GitObject> {
+        let (format, data) = self.read_raw(hash)?;
+
+        GitObject::deserialize(data, format)
+    }
+
+    /// Reads and parses the objects with the given hashes, in the same order. Fails if
+    /// any of them is missing.
+    pub fn read_many(&self, hashes: &[ObjectHash]) -> Result<Vec<GitObject>> {
+        hashes.iter()
+            .map(|hash| self.read(hash))
+            .collect()
+    }
+
+    /// Returns the sorted, lowercase file names in the fan-out directory `dir`, from the
+    /// cache if it's enabled. A missing directory has no files.
+    fn list_fanout_dir(&self, dir: &Path) -> Arc<Vec<String>> {
+        if self.cache_listings {
+            if let Some(files) = listing_cache().lock().expect("listing cache lock should not be poisoned").get(dir) {
+                return Arc::clone(files);
+            }
+        }
+
+        let mut files: Vec<String> = fs::read_dir(dir).into_iter()
+            .flatten()
+            .flatten()
+            .map(|file| file.file_name().to_string_lossy().to_ascii_lowercase())
+            .collect();
+        files.sort_unstable();
+        let files = Arc::new(files);
+
+        if self.cache_listings {
+            listing_cache().lock().expect("listing cache lock should not be poisoned")
+                .insert(dir.to_owned(), Arc::clone(&files));
+        }
+
+        files
+    }
+
+    fn find_loose(&self, hash: &ObjectHash) -> Option<PathBuf> {
+        let rel_path = hash.to_path();
+
+        self.dirs.iter()
+            .map(|dir| dir.join(&rel_path))
+            .find(|path| path.is_file())
+    }
+}
+
+/// The sorted list of hashes from a pack's `.idx` file (version 1 or 2).
+pub(crate) struct PackIndex {
+    /// `fanout[b]` is the number of hashes whose first byte is at most `b`.
+    fanout: [u32; 256],
+    hashes: Vec<[u8; 20]>,
+}
+
+impl PackIndex {
+    const V2_MAGIC: &'static [u8] = b"\xfftOc";
+
+    fn read(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        Self::parse(&data)
+            .with_context(|| format!("Failed to parse pack index {path:?}"))
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> Result<Self> {
+        // Version 1 has no header, and each hash follows a 4-byte offset
+        let (fanout_start, hash_stride, hash_offset) = match data.starts_with(Self::V2_MAGIC) {
+            true => (8, 20, 0),
+            false => (0, 24, 4),
+        };
+        if fanout_start == 8 && data.get(4..8) != Some(&[0, 0, 0, 2]) {
+            anyhow::bail!("unsupported version");
+        }
+
+        let fanout_end = fanout_start + 256 * 4;
+        let fanout_bytes = data.get(fanout_start..fanout_end)
+            .context("truncated fanout table")?;
+        let mut fanout = [0; 256];
+        for (count, bytes) in fanout.iter_mut().zip(fanout_bytes.chunks_exact(4)) {
+            *count = u32::from_be_bytes(bytes.try_into().expect("chunks should be 4 bytes"));
+        }
+        // Each count includes the ones before it, and the last is the number of hashes
+        if fanout.windows(2).any(|pair| pair[0] > pair[1]) {
+            anyhow::bail!("fanout table is not in ascending order");
+        }
+
+        let count = fanout[255] as usize;
+        let table_end = count.checked_mul(hash_stride)
+            .and_then(|len| len.checked_add(fanout_end))
+            .context("fanout table counts too many hashes")?;
+        if data.len() < table_end {
+            anyhow::bail!("truncated hash table");
+        }
+        let hashes = (0..count)
+            .map(|i| {
+                let start = fanout_end + i * hash_stride + hash_offset;
+                data.get(start..start + 20)
+                    .map(|hash| hash.try_into().expect("slice should be 20 bytes"))
+            })
+            .collect::<Option<_>>()
+            .context("truncated hash table")?;
+
+        Ok(Self { fanout, hashes })
+    }
+
+    /// Returns the range of `hashes` that start with `first_byte`. The fanout table was
+    /// checked when parsing, so the range is always within `hashes`.
+    fn bucket(&self, first_byte: u8) -> std::ops::Range<usize> {
+        let end = self.fanout[first_byte as usize] as usize;
+        let start = match first_byte {
+            0 => 0,
+            byte => self.fanout[byte as usize - 1] as usize,
+        };
+
+        start..end
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> bool {
+        self.hashes[self.bucket(hash.raw[0])]
+            .binary_search(&hash.raw)
+            .is_ok()
+    }
+
+    /// Appends the hashes in this pack that start with `hex_prefix` (lowercase) to `matches`.
+    fn prefix_matches(&self, hex_prefix: &str, matches: &mut Vec<ObjectHash>) {
+        // The smallest hash with the prefix, e.g. `abc` becomes ab c0 00 00 ...
+        let mut lower_bound = [0; 20];
+        for (i, digit) in hex_prefix.bytes().enumerate() {
+            let nibble = (digit as char).to_digit(16).expect("prefix should be hex") as u8;
+            lower_bound[i / 2] |= match i % 2 {
+                0 => nibble << 4,
+                _ => nibble,
+            };
+        }
+
+        let bucket = self.bucket(lower_bound[0]);
+        let start = bucket.start + self.hashes[bucket.clone()].partition_point(|hash| *hash < lower_bound);
+        let found = self.hashes[start..bucket.end].iter()
+            .map(|raw| ObjectHash::try_from(&raw[..]).expect("hash should be 20 bytes"))
+            .take_while(|hash| hash.to_string().starts_with(hex_prefix));
+        matches.extend(found);
+    }
+
+    /// Filters `sorted` (which must be sorted) down to the hashes that aren't in this pack.
+    fn missing_sorted(&self, sorted: &[ObjectHash]) -> Vec<ObjectHash> {
+        let mut pos = 0;
+
+        sorted.iter()
+            .filter(|hash| {
+                // Both lists are sorted, so the search can resume where the last one ended
+                let bucket = self.bucket(hash.raw[0]);
+                pos = pos.clamp(bucket.start, bucket.end);
+                pos += self.hashes[pos..bucket.end].partition_point(|other| *other < hash.raw);
+                self.hashes.get(pos) != Some(&hash.raw)
+            })
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{object::Blob, test_util::temp_workdir};
+
+    fn v2_index(hashes: &[[u8; 20]]) -> Vec<u8> {
+        let mut data = PackIndex::V2_MAGIC.to_vec();
+        data.extend(2u32.to_be_bytes());
+        for byte in 0..=255u8 {
+            let count = hashes.iter().filter(|hash| hash[0] <= byte).count() as u32;
+            data.extend(count.to_be_bytes());
+        }
+        for hash in hashes {
+            data.extend(hash);
+        }
+        data
+    }
+
+    #[test]
+    fn finds_objects_in_pack_index() {
+        let mut packed: Vec<_> = ["a", "b", "c", "d"].into_iter()
+            .map(|name| ObjectHash::new(name).raw)
+            .collect();
+        packed.sort();
+        let index = PackIndex::parse(&v2_index(&packed)).unwrap();
+
+        let packed_hash = ObjectHash::try_from(&packed[2][..]).unwrap();
+        let loose_hash = ObjectHash::new("e");
+        assert!(index.contains(&packed_hash));
+        assert!(!index.contains(&loose_hash));
+
+        let mut queries: Vec<_> = packed.iter()
+            .map(|raw| ObjectHash::try_from(&raw[..]).unwrap())
+            .chain([loose_hash, ObjectHash::new("f")])
+            .collect();
+        queries.sort_by_key(|hash| hash.raw);
+        let mut expected = vec![loose_hash, ObjectHash::new("f")];
+        expected.sort_by_key(|hash| hash.raw);
+        assert_eq!(index.missing_sorted(&queries), expected);
+
+        let mut matches = vec![];
+        index.prefix_matches(&packed_hash.to_string()[..5], &mut matches);
+        assert_eq!(matches, [packed_hash]);
+    }
+
+    #[test]
+    fn rejects_bad_fanout_tables() {
+        let packed: Vec<_> = (1..=3u8).map(|byte| [byte; 20]).collect();
+        let fanout_entry = |byte: usize| 8 + byte * 4;
+        let set = |data: &mut Vec<u8>, byte: usize, count: u32| {
+            data[fanout_entry(byte)..fanout_entry(byte) + 4].copy_from_slice(&count.to_be_bytes());
+        };
+
+        // A count that goes down
+        let mut data = v2_index(&packed);
+        set(&mut data, 1, 3);
+        assert!(PackIndex::parse(&data).is_err());
+
+        // More hashes than the table holds
+        let mut data = v2_index(&packed);
+        set(&mut data, 255, 4);
+        assert!(PackIndex::parse(&data).is_err());
+        set(&mut data, 255, u32::MAX);
+        assert!(PackIndex::parse(&data).is_err());
+
+        let index = PackIndex::parse(&v2_index(&packed)).unwrap();
+        let queries: Vec<_> = (0..=4u8).map(|byte| ObjectHash::try_from(&[byte; 20][..]).unwrap()).collect();
+        assert_eq!(index.missing_sorted(&queries), [queries[0], queries[4]]);
+    }
+
+    #[test]
+    fn reads_header_without_body() {
+        let (_temp_dir, wd) = temp_workdir();
+        let data = vec![b'x'; 100_000];
+        let hash = GitObject::Blob(Blob::deserialize(data).unwrap()).write(&wd).unwrap();
+
+        assert_eq!(GitObject::read_header(&wd, &hash).unwrap(), (ObjectFormat::Blob, 100_000));
+        assert_eq!(ObjectStore::open(&wd).unwrap().read_header(&hash).unwrap(), (ObjectFormat::Blob, 100_000));
+        assert!(GitObject::read_header(&wd, &ObjectHash::new("missing")).is_err());
+    }
+
+    #[test]
+    fn caches_fanout_listings_until_objects_are_written() {
+        let (_temp_dir, wd) = temp_workdir();
+        let first = GitObject::Blob(Blob::deserialize(b"first".to_vec()).unwrap()).write(&wd).unwrap();
+        let prefix = &first.to_string()[..2];
+        let store = ObjectStore::open(&wd).unwrap();
+        assert_eq!(store.prefix_matches(prefix), [first]);
+
+        // A file that appears behind our back isn't seen until the listing is forgotten
+        let copy_path = wd.git_path("objects").join(prefix).join("0".repeat(38));
+        fs::copy(wd.git_path("objects").join(first.to_path()), &copy_path).unwrap();
+        assert_eq!(store.prefix_matches(prefix), [first]);
+
+        let uncached = ObjectStore { cache_listings: false, ..ObjectStore::open(&wd).unwrap() };
+        assert_eq!(uncached.prefix_matches(prefix).len(), 2);
+
+        forget_listing(&copy_path);
+        assert_eq!(store.prefix_matches(prefix).len(), 2);
+    }
+}
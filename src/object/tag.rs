@@ -4,10 +4,11 @@ use ordered_multimap::ListOrderedMultimap;
 use crate::{
     Result,
     workdir::WorkDir,
+    consts::TAG_CATEGORY,
     refs,
 };
 
-use super::{ObjectHash, GitObject, ObjectMetadata};
+use super::{ObjectHash, GitObject, ObjectMetadata, SignatureError, signature::SSH_SIGNATURE_HEADER, commit::split_identity};
 
 /// A tag is a named reference to a commit. This represents an annotated tag which
 /// includes a description and information about the creator.
@@ -26,6 +27,18 @@ impl Tag {
         map.insert("tag".to_owned(), name.to_owned());
         map.insert("tagger".to_owned(), meta.author_line());
         map.insert("".to_owned(), meta.message);
+
+        // Unlike a commit, a tag's signature is appended to its message
+        if let Some(signer) = &meta.signer {
+            let message = map.get_mut("").expect("message should be present");
+            if !message.ends_with('\n') {
+                message.push('\n');
+            }
+
+            let payload = crate::kvlm::serialize(&map);
+            let signature = signer.sign(payload.as_bytes())?;
+            map.get_mut("").expect("message should be present").push_str(&signature);
+        }
     
         let tag_object = GitObject::Tag(Tag {
             map
@@ -43,14 +56,59 @@ impl Tag {
     /// Creates a new lightweight tag called `name` pointing to the commit identified by `hash`.
     pub fn create_lightweight(wd: &WorkDir, name: &str, hash: &ObjectHash) -> Result<()>
     {
-        refs::create(wd, "tags", name, hash)?;
+        refs::create(wd, TAG_CATEGORY, name, hash)?;
     
         Ok(())
     }
 
     /// Deletes the tag called `name`.
     pub fn delete(wd: &WorkDir, name: &str) -> Result<()> {
-        refs::delete(wd, "tags", name)
+        refs::delete(wd, TAG_CATEGORY, name)
+    }
+
+    /// Returns the hash of the object this tag points to.
+    pub fn target(&self) -> Result<ObjectHash> {
+        let hash_string = self.map.get("object")
+            .context("Failed to parse tag (missing object)")?;
+
+        ObjectHash::try_from(hash_string.as_str())
+            .context("Failed to parse tag (invalid object hash)")
+    }
+
+    /// Returns the tag's name, as recorded in the tag object.
+    pub fn name(&self) -> Option<&str> {
+        self.map.get("tag").map(String::as_str)
+    }
+
+    /// Returns the name and email of the person who made the tag, e.g. `Jo <jo@example.com>`,
+    /// without the timestamp.
+    pub fn tagger(&self) -> Option<&str> {
+        let tagger = self.map.get("tagger")?;
+
+        Some(split_identity(tagger).0)
+    }
+
+    /// Returns when the tag was made in seconds since the Unix epoch, with the tagger's
+    /// timezone offset (e.g. `-0800`), if known.
+    pub fn tagger_date(&self) -> Option<(i64, &str)> {
+        let tagger = self.map.get("tagger")?;
+
+        split_identity(tagger).1
+    }
+
+    /// Returns the tag's message, including its signature if it's signed.
+    pub fn message(&self) -> &str {
+        self.map.get("").map_or("", String::as_str)
+    }
+
+    /// Splits a signed tag into the data that was signed and the signature.
+    pub fn signed_payload(&self) -> Result<(Vec<u8>, String)> {
+        let mut map = self.map.clone();
+        let message = map.get_mut("").ok_or(SignatureError::Unsigned)?;
+        let signature_start = message.find(SSH_SIGNATURE_HEADER).ok_or(SignatureError::Unsigned)?;
+        let signature = message.split_off(signature_start);
+
+        Ok((crate::kvlm::serialize(&map).into_bytes(), signature))
     }
 
     /// Parses a `Tag` from a sequence of bytes.
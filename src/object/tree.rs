@@ -1,11 +1,18 @@
 use std::{
-    collections::{HashSet, BTreeMap}
+    cmp::Ordering,
+    collections::{HashSet, BTreeMap},
 };
 
 use anyhow::{Context, bail};
 
 use crate::{Result, workdir::{WorkDir, WorkPathBuf, WorkPath}, index::Index};
-use super::{ObjectError, ObjectHash, ObjectFormat, GitObject, Blob};
+use super::{ObjectError, ObjectHash, ObjectFormat, GitObject, Blob, BulkWriter, FileMode};
+
+mod diff;
+pub use diff::TreeChange;
+
+mod fsck;
+pub use fsck::TreeProblem;
 
 /// A tree represents one level (directory) in a file hierarchy. Files and subdirectories are recorded
 /// as hashes which map to blobs and trees, respectively.
@@ -16,24 +23,44 @@ pub struct Tree {
 /// A single entry in a [`Tree`], which may represent a file (blob) or subdirectory (tree).
 #[derive(Clone)]
 pub struct TreeEntry {
-    pub mode: String,
+    pub mode: FileMode,
     pub hash: ObjectHash,
 }
 
 impl Tree {
-    /// Copies files from the repository to the working directory at `target`.
+    /// Constructs a tree with no entries.
+    pub fn empty() -> Tree {
+        Tree { entries: BTreeMap::new() }
+    }
+
+    /// Copies files from the repository to the working directory at `target`. Only paths
+    /// present in this tree are touched.
     fn restore_at_path(&self, wd: &WorkDir, target: &WorkPath) -> Result<()> {
-        let abs_path = wd.as_path().join(target);
-        wd.remove_path(target)?;
-        std::fs::create_dir_all(&abs_path)?;
+        let abs_path = wd.abs_path(target);
+        if abs_path.is_file() {
+            wd.remove_path(target)?;
+        }
+        wd.create_worktree_dir(target)?;
 
         for (name, entry) in &self.entries {
+            // Trees built in memory skip the checks in `deserialize`
+            if name.is_empty() || name.partition().1.is_some() {
+                return Err(ObjectError::UnsafeTreeEntryName(name.to_string()).into());
+            }
             let object_path = target.to_owned().join(name);
+
+            // Submodules aren't supported, so they're checked out as empty directories
+            if entry.is_gitlink() {
+                wd.create_worktree_dir(&object_path)?;
+                continue;
+            }
         
             match GitObject::read(wd, &entry.hash)? {
                 GitObject::Blob(blob) => {
-                    let object_abs_path = wd.as_path().join(object_path);
-                    std::fs::write(object_abs_path, blob.serialize_into())?;
+                    if wd.abs_path(&object_path).is_dir() {
+                        wd.remove_path(&object_path)?;
+                    }
+                    wd.write_worktree_file(&object_path, &blob.serialize_into(), entry.mode)?;
                 },
                 GitObject::Tree(tree) => {
                     tree.restore_at_path(wd, &object_path)?;
@@ -45,10 +72,30 @@ impl Tree {
         Ok(())
     }
 
-    /// Updates the working directory at path `target` to match the tree associated with the specified commit.
-    /// The existing file or directory at `target` (if any) will be deleted.
-    pub fn restore_from_commit(wd: &WorkDir, commit_hash: &ObjectHash, target: &WorkPath) -> Result<()> {
-        let root_tree = Tree::read_from_commit(wd, commit_hash)?;
+    /// Updates the working directory at path `target` to match the tree associated with the specified
+    /// tree-ish (a tree, or a commit or tag that leads to one).
+    /// 
+    /// In overlay mode, files are written for every path in the source, but nothing else is
+    /// deleted. Otherwise, files at `target` that are tracked in `index` but missing from the
+    /// source are deleted too, along with any directories that leaves empty. Untracked files
+    /// are never deleted.
+    pub fn restore_from_tree_ish(wd: &WorkDir, index: &Index, tree_ish: &ObjectHash, target: &WorkPath, overlay: bool) -> Result<()> {
+        let root_tree = Tree::read_tree_ish(wd, tree_ish)?;
+
+        if !overlay {
+            let mut deleted = vec![];
+            let tracked = index.entries.get_key_value(target)
+                .into_iter()
+                .chain(index.entries_in_dir(target))
+                .filter(|(_, entry)| !entry.flags.get_skip_worktree() && !entry.is_sparse_dir());
+            for (path, _) in tracked {
+                if root_tree.find_entry(wd, path)?.is_none_or(|entry| entry.is_dir()) {
+                    deleted.push(path);
+                }
+            }
+
+            wd.remove_tracked(deleted)?;
+        }
         
         if target.is_empty() {
             // Case 1: restore root tree
@@ -60,17 +107,19 @@ impl Tree {
                 let tree = Tree::read(wd, &entry.hash)?;
                 tree.restore_at_path(wd, target)?;
             }
+            else if entry.is_gitlink() {
+                // Case 3: restore submodule placeholder
+                if wd.abs_path(target).is_file() {
+                    wd.remove_path(target)?;
+                }
+                wd.create_worktree_dir(target)?;
+            }
             else {
-                // Case 3: restore file
+                // Case 4: restore file
                 wd.remove_path(target)?;
 
-                let abs_path = wd.as_path().join(target);
-                if let Some(dir_path) = abs_path.parent() {
-                    std::fs::create_dir_all(dir_path)?;
-                }
-
                 let blob = Blob::read(wd, &entry.hash)?;
-                std::fs::write(abs_path, blob.serialize_into())?;
+                wd.write_worktree_file(target, &blob.serialize_into(), entry.mode)?;
             }
         }
 
@@ -85,38 +134,77 @@ impl Tree {
         Ok(index)
     }
 
+    /// Adds the file or directory at `path` in this tree to `index`. Nothing is added
+    /// if there is no such path.
+    pub fn add_path_to_index(&self, wd: &WorkDir, index: &mut Index, path: &WorkPath) -> Result<()> {
+        if path.is_empty() {
+            self.add_to_index_recursive(wd, index, path)
+        }
+        else if let Some(entry) = self.find_entry(wd, path)? {
+            Self::add_entry_to_index(wd, index, path.to_owned(), &entry)
+        }
+        else {
+            Ok(())
+        }
+    }
+
     /// Adds the entries in this tree to `index` under the path `prefix`.
     fn add_to_index_recursive(&self, wd: &WorkDir, index: &mut Index, prefix: &WorkPath) -> Result<()> {
         for (name, entry) in &self.entries {
             let mut full_path = prefix.to_owned();
             full_path.push(name);
 
-            if entry.is_dir() {
-                let tree = Tree::read(wd, &entry.hash)?;
-                tree.add_to_index_recursive(wd, index, &full_path)?;
-            }
-            else {
-                let blob = Blob::read(wd, &entry.hash)?;
-                let size = blob.size().try_into().unwrap_or(u32::MAX);
-                index.entries.insert(full_path, crate::index::IndexEntry {
-                    stats: crate::index::stats::FileStats::from_size(size),
-                    hash: entry.hash,
-                    flags: crate::index::flags::EntryFlags::new(name.as_str()),
-                });
-            }
+            Self::add_entry_to_index(wd, index, full_path, entry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the file or directory `entry` to `index` at `path`.
+    fn add_entry_to_index(wd: &WorkDir, index: &mut Index, path: WorkPathBuf, entry: &TreeEntry) -> Result<()> {
+        if entry.is_dir() {
+            let tree = Tree::read(wd, &entry.hash)?;
+            tree.add_to_index_recursive(wd, index, &path)?;
+        }
+        else if entry.is_gitlink() {
+            // The commit belongs to the submodule's repository, so it can't be read here
+            let index_entry = crate::index::IndexEntry::gitlink(&path, entry.hash);
+            index.entries.insert(path, index_entry);
+        }
+        else {
+            // Only the size is needed, so the blob's contents aren't decompressed
+            let size = match GitObject::read_header(wd, &entry.hash)? {
+                (ObjectFormat::Blob, size) => size.try_into().unwrap_or(u32::MAX),
+                (format, _) => return Err(ObjectError::UnexpectedFormat {
+                    format,
+                    expected: ObjectFormat::Blob,
+                }.into()),
+            };
+            let flags = crate::index::flags::EntryFlags::new(path.to_bytes());
+            index.entries.insert(path, crate::index::IndexEntry {
+                stats: crate::index::stats::FileStats::from_mode_and_size(entry.mode, size),
+                hash: entry.hash,
+                flags,
+            });
         }
 
         Ok(())
     }
 
-    /// Generates a tree from `index` and stores it in the repository.
+    /// Generates a tree from `index` and stores it in the repository, along with its
+    /// subtrees.
     pub fn create_from_index(index: &Index, wd: &WorkDir) -> Result<(ObjectHash, GitObject)> {
         let prefix = WorkPathBuf::try_from("")?;
-        Self::make_subtree(index, wd, &prefix)
+        let mut writer = BulkWriter::new(wd);
+        let tree = Self::make_subtree(index, &mut writer, &prefix)?;
+        writer.flush()?;
+
+        Ok(tree)
     }
 
-    /// Generates a tree from the entries in `index` under the path `prefix` and stores it in the repository.
-    fn make_subtree(index: &Index, wd: &WorkDir, prefix: &WorkPath) -> Result<(ObjectHash, GitObject)> {
+    /// Generates a tree from the entries in `index` under the path `prefix` and queues it to
+    /// be stored in the repository.
+    fn make_subtree(index: &Index, writer: &mut BulkWriter, prefix: &WorkPath) -> Result<(ObjectHash, GitObject)> {
         let mut entries = BTreeMap::new();
         let mut subtrees_handled: HashSet<&WorkPath> = HashSet::new();
         
@@ -133,16 +221,23 @@ impl Tree {
                     continue;
                 }
 
-                let (subtree_hash, _) = Self::make_subtree(index, wd, subtree_prefix)?;
+                let (subtree_hash, _) = Self::make_subtree(index, writer, subtree_prefix)?;
                 let tree_entry = TreeEntry {
-                    mode: "40000".to_owned(), // git drops the leading 0 when storing a tree
+                    mode: FileMode::Directory,
                     hash: subtree_hash,
                 };
                 entries.insert(name.to_owned(), tree_entry);
             }
+            else if index_entry.is_sparse_dir() {
+                let tree_entry = TreeEntry {
+                    mode: FileMode::Directory,
+                    hash: index_entry.hash,
+                };
+                entries.insert(name.to_owned(), tree_entry);
+            }
             else {
                 let tree_entry = TreeEntry {
-                    mode: index_entry.stats.get_mode_string(),
+                    mode: index_entry.stats.get_mode()?,
                     hash: index_entry.hash,
                 };
                 entries.insert(name.to_owned(), tree_entry);
@@ -150,7 +245,7 @@ impl Tree {
         }
 
         let tree = GitObject::Tree(Tree { entries });
-        let hash = tree.write(wd)?;
+        let hash = writer.add(&tree)?;
 
         Ok((hash, tree))
     }
@@ -165,7 +260,8 @@ impl Tree {
             let (first, rest) = path.partition();
 
             if let Some(rest) = rest {
-                if let Some(entry) = self.entries.get(first) {
+                // Paths inside a submodule aren't tracked by this repository
+                if let Some(entry) = self.entries.get(first).filter(|entry| entry.is_dir()) {
                     let subtree = Tree::read(wd, &entry.hash)?;
                     return subtree.find_entry(wd, rest);
                 }
@@ -186,6 +282,13 @@ impl Tree {
         }
     }
 
+    /// Reads and parses the tree that `hash` refers to, following commits and tags as needed.
+    pub fn read_tree_ish(wd: &WorkDir, hash: &ObjectHash) -> Result<Tree> {
+        let tree_hash = GitObject::peel(wd, hash, ObjectFormat::Tree)?;
+
+        Self::read(wd, &tree_hash)
+    }
+
     /// Reads and parses the tree associated with the commit with the given hash from the repo.
     pub fn read_from_commit(wd: &WorkDir, commit_hash: &ObjectHash) -> Result<Tree> {
         let commit = super::Commit::read(wd, commit_hash)?;
@@ -211,11 +314,20 @@ impl Tree {
                 break;
             }
 
+            let mode = FileMode::try_from(mode.as_str())
+                .context("Failed to parse tree (invalid mode)")?;
+
             let path = {
                 let path: Vec<u8> = iter.by_ref()
                     .take_while(|ch| *ch != 0)
                     .collect();
 
+                // Each name must be one component, so it can't reach outside the tree's directory
+                if path.is_empty() || path.contains(&b'/') || path.contains(&b'\\') {
+                    let name = String::from_utf8_lossy(&path).into_owned();
+                    return Err(ObjectError::UnsafeTreeEntryName(name).into());
+                }
+
                 WorkPathBuf::try_from(&path[..])
                     .context("Failed to parse tree (invalid path)")?
             };
@@ -238,12 +350,25 @@ impl Tree {
         Ok(Tree { entries })
     }
 
+    /// Returns the entries in the order git stores them, where each subtree sorts as if
+    /// its name ended with `/`.
+    pub fn sorted_entries(&self) -> Vec<(&WorkPathBuf, &TreeEntry)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|(a_name, a), (b_name, b)| {
+            compare_names(&a_name.to_bytes(), a.is_dir(), &b_name.to_bytes(), b.is_dir())
+        });
+
+        entries
+    }
+
     /// Converts the tree into a sequence of bytes.
     pub fn serialize(&self) -> Vec<u8> {
         let mut data = vec![];
 
-        for (path, entry) in &self.entries {
-            data.extend(format!("{} {}\0", entry.mode, path).into_bytes());
+        for (path, entry) in self.sorted_entries() {
+            data.extend(format!("{} ", entry.mode.as_tree_str()).into_bytes());
+            data.extend(path.to_bytes().iter());
+            data.push(0);
             data.extend(entry.hash.raw);
         }
 
@@ -256,8 +381,26 @@ impl Tree {
     }
 }
 
+/// Compares the names of two tree entries the way git orders them: byte by byte, with `/`
+/// appended to the names of subtrees. So `foo.txt` comes before the directory `foo`, but
+/// after a file named `foo`.
+pub(crate) fn compare_names(a: &[u8], a_is_dir: bool, b: &[u8], b_is_dir: bool) -> Ordering {
+    let a = a.iter().chain(a_is_dir.then_some(&b'/'));
+    let b = b.iter().chain(b_is_dir.then_some(&b'/'));
+
+    a.cmp(b)
+}
+
 impl TreeEntry {
+    /// Returns true if this entry is a subtree. Gitlinks (submodules) don't count.
     pub fn is_dir(&self) -> bool {
-        self.mode == "40000"
+        self.mode == FileMode::Directory
+    }
+
+    /// Returns true if this entry is a gitlink, which records the commit checked out in a
+    /// submodule. The commit belongs to the submodule's repository, so it usually can't
+    /// be read.
+    pub fn is_gitlink(&self) -> bool {
+        self.mode == FileMode::Gitlink
     }
 }
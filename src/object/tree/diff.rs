@@ -0,0 +1,92 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    Result,
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+};
+
+use super::{Tree, TreeEntry};
+
+/// A change to a file between two trees.
+pub enum TreeChange {
+    Added {
+        path: WorkPathBuf,
+        entry: TreeEntry,
+    },
+    Deleted {
+        path: WorkPathBuf,
+    },
+    Modified {
+        path: WorkPathBuf,
+        entry: TreeEntry,
+    },
+}
+
+impl TreeChange {
+    pub fn path(&self) -> &WorkPath {
+        match self {
+            TreeChange::Added { path, .. } => path,
+            TreeChange::Deleted { path } => path,
+            TreeChange::Modified { path, .. } => path,
+        }
+    }
+}
+
+impl Tree {
+    /// Enumerates the files that differ between `old` and `new`. Subtrees with the same
+    /// hash are skipped without being read.
+    /// 
+    /// When a file is replaced by a directory (or vice versa), the file is reported as
+    /// deleted and each file in the directory as added (or vice versa).
+    pub fn diff(wd: &WorkDir, old: &Tree, new: &Tree) -> Result<Vec<TreeChange>> {
+        let mut changes = vec![];
+        Self::diff_trees(wd, &WorkPathBuf::root(), old, new, &mut changes)?;
+
+        Ok(changes)
+    }
+
+    /// Compares `old` and `new`, which are both located at `prefix`.
+    fn diff_trees(wd: &WorkDir, prefix: &WorkPath, old: &Tree, new: &Tree, changes: &mut Vec<TreeChange>) -> Result<()> {
+        let names: BTreeSet<&WorkPathBuf> = old.entries.keys()
+            .chain(new.entries.keys())
+            .collect();
+
+        for name in names {
+            let path = prefix.to_owned().join(name);
+            Self::diff_entries(wd, path, old.entries.get(name), new.entries.get(name), changes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Compares two entries (either of which may be missing) located at `path`.
+    fn diff_entries(wd: &WorkDir, path: WorkPathBuf, old: Option<&TreeEntry>, new: Option<&TreeEntry>, changes: &mut Vec<TreeChange>) -> Result<()> {
+        if let (Some(old), Some(new)) = (old, new) {
+            if old.hash == new.hash && old.mode == new.mode {
+                return Ok(());
+            }
+        }
+
+        // Directories are compared file by file
+        let old_tree = old.filter(|entry| entry.is_dir());
+        let new_tree = new.filter(|entry| entry.is_dir());
+        if old_tree.is_some() || new_tree.is_some() {
+            let read_or_empty = |entry: Option<&TreeEntry>| match entry {
+                Some(entry) => Tree::read(wd, &entry.hash),
+                None => Ok(Tree::empty()),
+            };
+            Self::diff_trees(wd, &path, &read_or_empty(old_tree)?, &read_or_empty(new_tree)?, changes)?;
+        }
+
+        let old_file = old.filter(|entry| !entry.is_dir());
+        let new_file = new.filter(|entry| !entry.is_dir());
+        match (old_file, new_file) {
+            (Some(_), Some(new)) => changes.push(TreeChange::Modified { path, entry: new.clone() }),
+            (Some(_), None) => changes.push(TreeChange::Deleted { path }),
+            (None, Some(new)) => changes.push(TreeChange::Added { path, entry: new.clone() }),
+            (None, None) => (),
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use std::{collections::HashSet, fmt};
+
+use super::{Tree, FileMode, compare_names};
+
+/// A problem with the way a tree object is stored. Trees that git writes never have
+/// these problems, but other tools might write them.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum TreeProblem {
+    /// The entries aren't in git's order, so the tree's hash isn't the one git computes.
+    NotSorted,
+    /// Two entries have the same name.
+    DuplicateEntries,
+    /// A directory's mode is written as `040000` instead of `40000`.
+    ZeroPaddedFilemode,
+    /// A mode isn't one that git writes, e.g. `100664`.
+    BadFilemode,
+    /// The data ends in the middle of an entry.
+    Truncated,
+    /// An entry is named `.`.
+    HasDot,
+    /// An entry is named `..`.
+    HasDotdot,
+    /// An entry is named `.git`, in any case.
+    HasDotgit,
+    /// An entry's name is empty.
+    EmptyName,
+    /// An entry's name contains a slash, so it's a path rather than a name.
+    FullPathname,
+}
+
+impl TreeProblem {
+    /// Returns the identifier git uses for this problem (e.g. in `fsck.<msg-id>`).
+    pub fn id(&self) -> &'static str {
+        use TreeProblem::*;
+
+        match self {
+            NotSorted => "treeNotSorted",
+            DuplicateEntries => "duplicateEntries",
+            ZeroPaddedFilemode => "zeroPaddedFilemode",
+            BadFilemode => "badFilemode",
+            Truncated => "badTree",
+            HasDot => "hasDot",
+            HasDotdot => "hasDotdot",
+            HasDotgit => "hasDotgit",
+            EmptyName => "emptyName",
+            FullPathname => "fullPathname",
+        }
+    }
+
+    /// Returns true if git treats this problem as an error rather than a warning. The
+    /// problems with names are only warnings, but wyag refuses to check such trees out.
+    pub fn is_error(&self) -> bool {
+        use TreeProblem::*;
+
+        matches!(self, NotSorted | DuplicateEntries | Truncated)
+    }
+}
+
+impl fmt::Display for TreeProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TreeProblem::*;
+
+        let message = match self {
+            NotSorted => "not properly sorted",
+            DuplicateEntries => "contains duplicate file entries",
+            ZeroPaddedFilemode => "contains zero-padded file modes",
+            BadFilemode => "contains bad file modes",
+            Truncated => "cannot be parsed",
+            HasDot => "contains '.'",
+            HasDotdot => "contains '..'",
+            HasDotgit => "contains '.git'",
+            EmptyName => "contains empty pathname",
+            FullPathname => "contains full pathnames",
+        };
+        write!(f, "{}: {message}", self.id())
+    }
+}
+
+impl Tree {
+    /// Checks the serialized tree `data` for problems that parsing would hide, since
+    /// [`Tree::deserialize`] accepts entries in any order and normalizes their modes.
+    /// Each kind of problem is reported at most once.
+    pub fn check(data: &[u8]) -> Vec<TreeProblem> {
+        let mut problems = vec![];
+        let mut report = |problem| {
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        };
+
+        let mut names = HashSet::new();
+        let mut previous: Option<(&[u8], bool)> = None;
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let Some(space) = rest.iter().position(|ch| *ch == b' ') else {
+                report(TreeProblem::Truncated);
+                break;
+            };
+            let Some(nul) = rest.iter().position(|ch| *ch == 0).filter(|nul| *nul > space) else {
+                report(TreeProblem::Truncated);
+                break;
+            };
+            if rest.len() < nul + 21 {
+                report(TreeProblem::Truncated);
+                break;
+            }
+
+            let mode = &rest[..space];
+            let name = &rest[space + 1..nul];
+            rest = &rest[nul + 21..];
+
+            let mode = std::str::from_utf8(mode).ok()
+                .and_then(|mode_str| Some((mode_str, FileMode::try_from(mode_str).ok()?)));
+            let is_dir = match mode {
+                Some((mode_str, mode)) => {
+                    if mode_str != mode.as_tree_str() {
+                        match mode_str.starts_with('0') {
+                            true => report(TreeProblem::ZeroPaddedFilemode),
+                            false => report(TreeProblem::BadFilemode),
+                        }
+                    }
+                    mode == FileMode::Directory
+                },
+                None => {
+                    report(TreeProblem::BadFilemode);
+                    false
+                },
+            };
+
+            match name {
+                b"" => report(TreeProblem::EmptyName),
+                b"." => report(TreeProblem::HasDot),
+                b".." => report(TreeProblem::HasDotdot),
+                _ if name.eq_ignore_ascii_case(b".git") => report(TreeProblem::HasDotgit),
+                _ if name.contains(&b'/') => report(TreeProblem::FullPathname),
+                _ => (),
+            }
+
+            if !names.insert(name) {
+                report(TreeProblem::DuplicateEntries);
+            }
+            if let Some((prev_name, prev_is_dir)) = previous {
+                if compare_names(prev_name, prev_is_dir, name, is_dir).is_gt() {
+                    report(TreeProblem::NotSorted);
+                }
+            }
+            previous = Some((name, is_dir));
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{object::{ObjectHash, TreeEntry}, workdir::WorkPathBuf};
+
+    fn entry(mode: &str, name: &str) -> Vec<u8> {
+        let mut data = format!("{mode} {name}\0").into_bytes();
+        data.extend([0xab; 20]);
+        data
+    }
+
+    #[test]
+    fn serializes_in_git_order() {
+        let mut tree = Tree::empty();
+        let hash = ObjectHash::try_from(&[0xab; 20][..]).unwrap();
+        for (name, mode) in [("foo", FileMode::Directory), ("foo.txt", FileMode::Regular), ("foo-bar", FileMode::Regular)] {
+            tree.entries.insert(WorkPathBuf::try_from(name).unwrap(), TreeEntry { mode, hash });
+        }
+
+        let expected = [entry("100644", "foo-bar"), entry("100644", "foo.txt"), entry("40000", "foo")].concat();
+        assert_eq!(tree.serialize(), expected);
+        assert!(Tree::check(&expected).is_empty());
+    }
+
+    #[test]
+    fn round_trips_non_utf8_names() {
+        let mut data = b"100644 caf\xe9.txt\0".to_vec();
+        data.extend([0xab; 20]);
+
+        let tree = Tree::deserialize(data.clone()).unwrap();
+        assert_eq!(tree.serialize(), data);
+    }
+
+    #[test]
+    fn finds_problems() {
+        let unsorted = [entry("40000", "foo"), entry("100644", "foo.txt")].concat();
+        assert_eq!(Tree::check(&unsorted), [TreeProblem::NotSorted]);
+
+        let duplicate = [entry("100644", "a"), entry("100644", "a")].concat();
+        assert_eq!(Tree::check(&duplicate), [TreeProblem::DuplicateEntries]);
+
+        let modes = [entry("040000", "a"), entry("100664", "b"), entry("100644", "c")].concat();
+        assert_eq!(Tree::check(&modes), [TreeProblem::ZeroPaddedFilemode, TreeProblem::BadFilemode]);
+
+        let truncated = &entry("100644", "a")[..10];
+        assert_eq!(Tree::check(truncated), [TreeProblem::Truncated]);
+    }
+
+    #[test]
+    fn finds_unsafe_names() {
+        let cases = [
+            ("..", TreeProblem::HasDotdot),
+            (".", TreeProblem::HasDot),
+            (".GIT", TreeProblem::HasDotgit),
+            ("", TreeProblem::EmptyName),
+            ("../../etc", TreeProblem::FullPathname),
+        ];
+        for (name, problem) in cases {
+            let data = entry("100644", name);
+            assert_eq!(Tree::check(&data), [problem]);
+            assert!(Tree::deserialize(data).is_err(), "{name:?} should be rejected");
+        }
+    }
+}
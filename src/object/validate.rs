@@ -0,0 +1,154 @@
+//! Strict checks for objects created from raw bytes (e.g. by `hash-object`), which reject
+//! anything git wouldn't write. Parsing alone is more forgiving: it accepts headers in any
+//! order, for instance.
+
+use super::{GitObject, ObjectError, ObjectFormat, ObjectHash, Tree};
+use crate::Result;
+
+impl GitObject {
+    /// Checks that `data` is a well-formed object of type `format`, the way `git hash-object`
+    /// does unless it's given `--literally`. Trees must have no problems that `fsck` would
+    /// report, and commits and tags must have git's headers in git's order.
+    pub fn validate(format: ObjectFormat, data: &[u8]) -> Result<()> {
+        let malformed = |problem: String| ObjectError::Malformed { format, problem };
+
+        match format {
+            ObjectFormat::Blob => (),
+            ObjectFormat::Tree => {
+                if let Some(problem) = Tree::check(data).first() {
+                    return Err(malformed(problem.to_string()).into());
+                }
+            },
+            ObjectFormat::Commit => check_commit(data).map_err(malformed)?,
+            ObjectFormat::Tag => check_tag(data).map_err(malformed)?,
+        }
+
+        // Anything that passed the checks above should parse, but this makes sure of it
+        GitObject::deserialize(data.to_vec(), format)?;
+
+        Ok(())
+    }
+}
+
+/// Splits the header of a commit or tag into its lines. Continuation lines (starting with a
+/// space) belong to the previous value, so they're skipped.
+fn header_lines(data: &[u8]) -> std::result::Result<Vec<(&str, &str)>, String> {
+    let text = std::str::from_utf8(data).map_err(|_| "invalid Utf-8".to_owned())?;
+    let (header, _) = text.split_once("\n\n").ok_or("missing blank line after header")?;
+
+    header.lines()
+        .filter(|line| !line.starts_with(' '))
+        .map(|line| line.split_once(' ').ok_or(format!("malformed header line `{line}`")))
+        .collect()
+}
+
+fn check_commit(data: &[u8]) -> std::result::Result<(), String> {
+    let lines = header_lines(data)?;
+    let mut lines = lines.iter().peekable();
+
+    expect_header(lines.next(), "tree", check_hash)?;
+    while let Some((_, value)) = lines.next_if(|(key, _)| *key == "parent") {
+        check_hash(value)?;
+    }
+    expect_header(lines.next(), "author", check_ident)?;
+    expect_header(lines.next(), "committer", check_ident)?;
+
+    Ok(())
+}
+
+fn check_tag(data: &[u8]) -> std::result::Result<(), String> {
+    let lines = header_lines(data)?;
+    let mut lines = lines.iter().peekable();
+
+    expect_header(lines.next(), "object", check_hash)?;
+    expect_header(lines.next(), "type", |value| {
+        ObjectFormat::try_from(value).map(|_| ()).map_err(|_| format!("invalid type `{value}`"))
+    })?;
+    expect_header(lines.next(), "tag", |value| match value.is_empty() {
+        true => Err("empty tag name".to_owned()),
+        false => Ok(()),
+    })?;
+    // Very old tags have no tagger
+    if let Some((_, value)) = lines.next_if(|(key, _)| *key == "tagger") {
+        check_ident(value)?;
+    }
+
+    Ok(())
+}
+
+/// Fails unless `line` has the key `expected` and a value that passes `check`.
+fn expect_header<F>(line: Option<&(&str, &str)>, expected: &str, check: F) -> std::result::Result<(), String>
+where
+    F: FnOnce(&str) -> std::result::Result<(), String>,
+{
+    match line {
+        Some((key, value)) if *key == expected => check(value),
+        Some((key, _)) => Err(format!("expected `{expected}` header, found `{key}`")),
+        None => Err(format!("missing `{expected}` header")),
+    }
+}
+
+fn check_hash(value: &str) -> std::result::Result<(), String> {
+    match value.len() == 40 && ObjectHash::try_from(value).is_ok() {
+        true => Ok(()),
+        false => Err(format!("invalid hash `{value}`")),
+    }
+}
+
+/// Checks an identity like `Jo <jo@example.com> 1673643222 -0800`.
+fn check_ident(value: &str) -> std::result::Result<(), String> {
+    let invalid = || format!("invalid identity `{value}`");
+
+    let (name, rest) = value.split_once(" <").ok_or_else(invalid)?;
+    let (email, rest) = rest.split_once("> ").ok_or_else(invalid)?;
+    let (timestamp, timezone) = rest.split_once(' ').ok_or_else(invalid)?;
+
+    let is_valid = !name.contains(['<', '>'])
+        && !email.contains(['<', '>'])
+        && !timestamp.is_empty()
+        && timestamp.bytes().all(|ch| ch.is_ascii_digit())
+        && timezone.len() == 5
+        && timezone.starts_with(['+', '-'])
+        && timezone[1..].bytes().all(|ch| ch.is_ascii_digit());
+
+    match is_valid {
+        true => Ok(()),
+        false => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREE: &str = "bf42a97e57f4f7e090ee62e5967e94fc4331dabb";
+    const IDENT: &str = "Jo <jo@example.com> 1673643222 -0800";
+
+    #[test]
+    fn accepts_well_formed_commit() {
+        let commit = format!("tree {TREE}\nparent {TREE}\nauthor {IDENT}\ncommitter {IDENT}\n\nmessage\n");
+        assert!(GitObject::validate(ObjectFormat::Commit, commit.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_commits() {
+        for commit in [
+            format!("author {IDENT}\ntree {TREE}\ncommitter {IDENT}\n\nmessage\n"),
+            format!("tree {TREE}\nauthor Jo jo@example.com\ncommitter {IDENT}\n\nmessage\n"),
+            format!("tree {}\nauthor {IDENT}\ncommitter {IDENT}\n\nmessage\n", &TREE[..10]),
+            format!("tree {TREE}\nauthor {IDENT}\n\nmessage\n"),
+        ] {
+            let result = GitObject::validate(ObjectFormat::Commit, commit.as_bytes());
+            assert!(matches!(result.unwrap_err().downcast_ref(), Some(ObjectError::Malformed { .. })), "{commit}");
+        }
+    }
+
+    #[test]
+    fn checks_tags() {
+        let tag = format!("object {TREE}\ntype tree\ntag v1\ntagger {IDENT}\n\nmessage\n");
+        assert!(GitObject::validate(ObjectFormat::Tag, tag.as_bytes()).is_ok());
+
+        let tag = format!("object {TREE}\ntype branch\ntag v1\n\nmessage\n");
+        assert!(GitObject::validate(ObjectFormat::Tag, tag.as_bytes()).is_err());
+    }
+}
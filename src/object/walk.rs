@@ -0,0 +1,428 @@
+//! Walking the commit history the way `git rev-list` does: the commits reachable from some
+//! commits but not from others, newest first.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    consts::HEAD,
+    workdir::WorkDir,
+};
+
+use super::{Commit, CommitNode, GitObject, ObjectFormat, ObjectHash, Tree};
+
+/// A set of commits to list, built from revisions like `B`, `^A`, `A..B`, and `A...B`.
+#[derive(Default)]
+pub struct RevWalk {
+    include: Vec<ObjectHash>,
+    exclude: Vec<ObjectHash>,
+    /// Only list commits that are descendants of an excluded commit, i.e. the ones on the
+    /// path between the ends of a range (`--ancestry-path`).
+    pub ancestry_path: bool,
+    /// Only list commits made at or after this time, in seconds since the Unix epoch
+    /// (`--since`).
+    pub since: Option<i64>,
+    /// Only list commits made at or before this time (`--until`).
+    pub until: Option<i64>,
+}
+
+/// The result of a [`RevWalk`].
+#[derive(PartialEq, Eq, Debug)]
+pub struct WalkedCommits {
+    /// The commits in the range, newest first.
+    pub commits: Vec<ObjectHash>,
+    /// The commits that were left out but are parents of listed commits (`--boundary`).
+    pub boundary: Vec<ObjectHash>,
+}
+
+/// A tree or blob that the walked commits refer to (`--objects`).
+#[derive(PartialEq, Eq, Debug)]
+pub struct WalkedObject {
+    pub hash: ObjectHash,
+    /// Where the object was first found, relative to the root of a commit's tree. The root
+    /// tree itself has an empty path.
+    pub path: String,
+}
+
+impl RevWalk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lists the commits reachable from `hash`.
+    pub fn push(&mut self, hash: ObjectHash) {
+        self.include.push(hash);
+    }
+
+    /// Leaves out the commits reachable from `hash`.
+    pub fn hide(&mut self, hash: ObjectHash) {
+        self.exclude.push(hash);
+    }
+
+    /// Adds a revision as given on the command line:
+    /// - `B` lists the commits reachable from B
+    /// - `^A` leaves out the commits reachable from A
+    /// - `A..B` is the same as `^A B`
+    /// - `A...B` lists the commits reachable from either side but not from both
+    ///
+    /// Either side of a range can be left out to mean `HEAD`.
+    pub fn push_revision(&mut self, wd: &WorkDir, revision: &str) -> Result<()> {
+        let resolve = |rev: &str| match rev {
+            "" => GitObject::find_as(wd, HEAD, ObjectFormat::Commit),
+            rev => GitObject::find_as(wd, rev, ObjectFormat::Commit),
+        };
+
+        if let Some((left, right)) = revision.split_once("...") {
+            let (left, right) = (resolve(left)?, resolve(right)?);
+            for base in Commit::merge_bases(wd, &left, &right)? {
+                self.hide(base);
+            }
+            self.push(left);
+            self.push(right);
+        }
+        else if let Some((exclude, include)) = revision.split_once("..") {
+            self.hide(resolve(exclude)?);
+            self.push(resolve(include)?);
+        }
+        else if let Some(exclude) = revision.strip_prefix('^') {
+            if exclude.is_empty() {
+                return Err(WalkError::InvalidRevision(revision.to_owned()).into());
+            }
+            self.hide(resolve(exclude)?);
+        }
+        else {
+            self.push(resolve(revision)?);
+        }
+
+        Ok(())
+    }
+
+    /// Lists the commits reachable from the included commits but not from the excluded
+    /// ones, newest first by commit time.
+    ///
+    /// Both sides are walked together, and the walk stops once every commit left to visit
+    /// is reachable from an excluded one, so only the history down to where the sides meet
+    /// is read. As in git, a commit that's older than its parents can be listed even though
+    /// an excluded commit reaches it.
+    pub fn run(&self, wd: &WorkDir) -> Result<WalkedCommits> {
+        // Newest first, and in the order they were found when the times are equal
+        let mut queue = BinaryHeap::new();
+        let mut found = Vec::new();
+        let mut queued = HashSet::new();
+        let mut uninteresting = HashSet::new();
+        for hash in self.exclude.iter().chain(&self.include) {
+            if queued.insert(*hash) {
+                queue.push((CommitNode::get(wd, hash)?.time, Reverse(found.len())));
+                found.push(*hash);
+            }
+        }
+        uninteresting.extend(self.exclude.iter().copied());
+
+        let mut commits = Vec::new();
+        let mut visited = HashSet::new();
+        while !queue.iter().all(|(_, Reverse(order))| uninteresting.contains(&found[*order])) {
+            let Some((_, Reverse(order))) = queue.pop() else { break };
+            let hash = found[order];
+            visited.insert(hash);
+
+            let parents = CommitNode::get(wd, &hash)?.parents.clone();
+            if uninteresting.contains(&hash) {
+                for parent in &parents {
+                    mark_uninteresting(wd, *parent, &visited, &mut uninteresting)?;
+                }
+            }
+            else {
+                commits.push(hash);
+            }
+            for parent in parents {
+                if queued.insert(parent) {
+                    queue.push((CommitNode::get(wd, &parent)?.time, Reverse(found.len())));
+                    found.push(parent);
+                }
+            }
+        }
+        // Commits listed before an excluded commit reached them
+        commits.retain(|hash| !uninteresting.contains(hash));
+
+        if self.ancestry_path {
+            let mut descends: HashMap<_, _> = self.exclude.iter().map(|hash| (*hash, true)).collect();
+            let mut on_path = Vec::new();
+            for hash in commits {
+                if descends_from_bottom(wd, &hash, &mut descends)? {
+                    on_path.push(hash);
+                }
+            }
+            commits = on_path;
+        }
+
+        if self.since.is_some() || self.until.is_some() {
+            let mut in_time = Vec::new();
+            for hash in commits {
+                let time = CommitNode::get(wd, &hash)?.time;
+                if self.since.is_none_or(|since| time >= since) && self.until.is_none_or(|until| time <= until) {
+                    in_time.push(hash);
+                }
+            }
+            commits = in_time;
+        }
+
+        // Parents left out by `--ancestry-path` are on the boundary too
+        let listed: HashSet<_> = commits.iter().collect();
+        let mut boundary = Vec::new();
+        for hash in &commits {
+            for parent in &CommitNode::get(wd, hash)?.parents {
+                if !listed.contains(parent) && !boundary.contains(parent) {
+                    boundary.push(*parent);
+                }
+            }
+        }
+        // Git lists the ones it found last first
+        boundary.reverse();
+
+        Ok(WalkedCommits { commits, boundary })
+    }
+
+    /// Lists the trees and blobs that the commits in `walked` refer to, leaving out the ones
+    /// the excluded and boundary commits already have. These are the objects that need to
+    /// be sent to a repository that has the excluded commits. Each commit's tree is listed
+    /// in turn, depth-first, skipping objects that were already listed.
+    pub fn objects(&self, wd: &WorkDir, walked: &WalkedCommits) -> Result<Vec<WalkedObject>> {
+        let mut seen = HashSet::new();
+        for hash in self.exclude.iter().chain(&walked.boundary) {
+            let tree = *Commit::read(wd, hash)?.tree();
+            mark_tree(wd, tree, &mut seen)?;
+        }
+
+        let mut objects = Vec::new();
+        for hash in &walked.commits {
+            let tree = *Commit::read(wd, hash)?.tree();
+            list_tree(wd, tree, String::new(), &mut seen, &mut objects)?;
+        }
+
+        Ok(objects)
+    }
+}
+
+/// Adds the tree `hash` and everything in it to `seen`.
+fn mark_tree(wd: &WorkDir, hash: ObjectHash, seen: &mut HashSet<ObjectHash>) -> Result<()> {
+    let mut pending = vec![hash];
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        for entry in Tree::read(wd, &hash)?.entries.values() {
+            match entry.is_dir() {
+                true => pending.push(entry.hash),
+                // Submodule commits belong to another repository
+                false if !entry.is_gitlink() => { seen.insert(entry.hash); },
+                false => (),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the tree `hash` found at `path`, then the entries in it, unless they're in `seen`.
+fn list_tree(wd: &WorkDir, hash: ObjectHash, path: String, seen: &mut HashSet<ObjectHash>, objects: &mut Vec<WalkedObject>) -> Result<()> {
+    if !seen.insert(hash) {
+        return Ok(());
+    }
+    let tree = Tree::read(wd, &hash)?;
+    objects.push(WalkedObject { hash, path: path.clone() });
+
+    for (name, entry) in tree.sorted_entries() {
+        let entry_path = match path.is_empty() {
+            true => name.to_string(),
+            false => format!("{path}/{name}"),
+        };
+        if entry.is_dir() {
+            list_tree(wd, entry.hash, entry_path, seen, objects)?;
+        }
+        else if !entry.is_gitlink() && seen.insert(entry.hash) {
+            objects.push(WalkedObject { hash: entry.hash, path: entry_path });
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds `hash` to `uninteresting`, along with the ancestors of it that were already
+/// visited. Unvisited ones are marked when the walk reaches them.
+fn mark_uninteresting(wd: &WorkDir, hash: ObjectHash, visited: &HashSet<ObjectHash>, uninteresting: &mut HashSet<ObjectHash>) -> Result<()> {
+    let mut pending = vec![hash];
+    while let Some(hash) = pending.pop() {
+        if uninteresting.insert(hash) && visited.contains(&hash) {
+            pending.extend_from_slice(&CommitNode::get(wd, &hash)?.parents);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns true if `hash` is a descendant of one of the commits already marked `true` in
+/// `descends`, recording the answer for every commit visited along the way.
+fn descends_from_bottom(wd: &WorkDir, hash: &ObjectHash, descends: &mut HashMap<ObjectHash, bool>) -> Result<bool> {
+    // Depth-first, deciding each commit once all of its parents have been decided
+    let mut stack = vec![(*hash, false)];
+    while let Some((hash, parents_done)) = stack.pop() {
+        if descends.contains_key(&hash) {
+            continue;
+        }
+
+        let node = CommitNode::get(wd, &hash)?;
+        if parents_done {
+            let result = node.parents.iter().any(|parent| descends.get(parent) == Some(&true));
+            descends.insert(hash, result);
+        }
+        else {
+            stack.push((hash, true));
+            stack.extend(node.parents.iter().filter(|parent| !descends.contains_key(parent)).map(|parent| (*parent, false)));
+        }
+    }
+
+    Ok(descends[hash])
+}
+
+#[derive(Error, Debug)]
+pub enum WalkError {
+    #[error("Invalid revision `{0}`")]
+    InvalidRevision(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        object::{Blob, FileMode, TreeEntry},
+        test_util::{EMPTY_TREE, commit_at, temp_workdir},
+        workdir::WorkPathBuf,
+    };
+
+    /// Writes a commit of the empty tree with the given parents, made at `time`.
+    fn commit(wd: &WorkDir, time: i64, parents: &[ObjectHash]) -> ObjectHash {
+        commit_at(wd, &time.to_string(), time, &ObjectHash::try_from(EMPTY_TREE).unwrap(), parents)
+    }
+
+    #[test]
+    fn lists_ranges() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        // base - a1 - a2 ------ merge
+        //          \           /
+        //           b1 - b2 --
+        //    \
+        //     side
+        let base = commit(&wd, 1, &[]);
+        let a1 = commit(&wd, 2, &[base]);
+        let side = commit(&wd, 3, &[base]);
+        let b1 = commit(&wd, 4, &[a1]);
+        let a2 = commit(&wd, 5, &[a1]);
+        let b2 = commit(&wd, 6, &[b1]);
+        let merge = commit(&wd, 7, &[a2, b2]);
+
+        let walk = |revisions: &[&str], ancestry_path: bool| {
+            let mut walk = RevWalk { ancestry_path, ..RevWalk::new() };
+            for revision in revisions {
+                walk.push_revision(&wd, revision).unwrap();
+            }
+            walk.run(&wd).unwrap()
+        };
+
+        let range = walk(&[&format!("{a2}..{merge}")], false);
+        assert_eq!(range.commits, [merge, b2, b1]);
+        assert_eq!(range.boundary, [a1, a2]);
+        assert_eq!(walk(&[&format!("{a1}..{merge}")], true).boundary, [a1]);
+
+        assert_eq!(walk(&[&format!("{a2}...{b2}")], false).commits, [b2, a2, b1]);
+        assert_eq!(walk(&[&merge.to_string(), &format!("^{a1}"), &side.to_string()], false).commits, [merge, b2, a2, b1, side]);
+        assert_eq!(walk(&[&format!("{b1}..{merge}")], true).commits, [merge, b2]);
+        assert_eq!(walk(&[&format!("{a1}..{merge}")], true).commits, [merge, b2, a2, b1]);
+    }
+
+    #[test]
+    fn stops_where_the_sides_meet() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        // root - base - a1 - a2
+        //           \
+        //            b1
+        let root = commit(&wd, 1, &[]);
+        let base = commit(&wd, 2, &[root]);
+        let a1 = commit(&wd, 3, &[base]);
+        let a2 = commit(&wd, 4, &[a1]);
+        let b1 = commit(&wd, 5, &[base]);
+
+        // Nothing older than the merge base is read
+        std::fs::remove_file(wd.git_path("objects").join(root.to_path())).unwrap();
+
+        let mut walk = RevWalk::new();
+        walk.push_revision(&wd, &format!("{a2}..{b1}")).unwrap();
+        assert_eq!(walk.run(&wd).unwrap().commits, [b1]);
+
+        let mut walk = RevWalk::new();
+        walk.push_revision(&wd, &format!("{b1}..{a2}")).unwrap();
+        assert_eq!(walk.run(&wd).unwrap().commits, [a2, a1]);
+    }
+
+    #[test]
+    fn excludes_commits_reached_late() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        // The excluded commit is older than its parent, so the walk lists the parent before
+        // finding out it's excluded
+        let old = commit(&wd, 1, &[]);
+        let skewed = commit(&wd, 3, &[old]);
+        let excluded = commit(&wd, 2, &[skewed]);
+        let tip = commit(&wd, 4, &[skewed]);
+
+        let mut walk = RevWalk::new();
+        walk.push_revision(&wd, &format!("{excluded}..{tip}")).unwrap();
+        assert_eq!(walk.run(&wd).unwrap().commits, [tip]);
+    }
+
+    #[test]
+    fn lists_objects() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        let blob = |data: &str| GitObject::Blob(Blob::deserialize(data.as_bytes().to_vec()).unwrap()).write(&wd).unwrap();
+        let tree = |entries: &[(&str, FileMode, ObjectHash)]| {
+            let entries = entries.iter()
+                .map(|(name, mode, hash)| (WorkPathBuf::try_from(*name).unwrap(), TreeEntry { mode: *mode, hash: *hash }))
+                .collect();
+            GitObject::Tree(Tree { entries }).write(&wd).unwrap()
+        };
+
+        let (x, y) = (blob("x"), blob("y"));
+        let sub = tree(&[("x", FileMode::Regular, x)]);
+        let first_tree = tree(&[("f", FileMode::Regular, x), ("sub", FileMode::Directory, sub)]);
+        let second_tree = tree(&[("f", FileMode::Regular, y), ("sub", FileMode::Directory, sub)]);
+        let first = commit_at(&wd, "1", 1, &first_tree, &[]);
+        let second = commit_at(&wd, "2", 2, &second_tree, &[first]);
+
+        let objects = |revision: &str| {
+            let mut walk = RevWalk::new();
+            walk.push_revision(&wd, revision).unwrap();
+            let walked = walk.run(&wd).unwrap();
+            walk.objects(&wd, &walked).unwrap()
+                .into_iter()
+                .map(|object| (object.hash, object.path))
+                .collect::<Vec<_>>()
+        };
+        let object = |hash: ObjectHash, path: &str| (hash, path.to_owned());
+
+        assert_eq!(objects(&second.to_string()), [
+            object(second_tree, ""),
+            object(y, "f"),
+            object(sub, "sub"),
+            object(x, "sub/x"),
+            object(first_tree, ""),
+        ]);
+        assert_eq!(objects(&format!("{first}..{second}")), [object(second_tree, ""), object(y, "f")]);
+    }
+}
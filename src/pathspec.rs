@@ -0,0 +1,203 @@
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    attributes::fnmatch,
+    index::{Index, UnstagedChange},
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+};
+
+#[derive(Error, Debug)]
+pub enum PathspecError {
+    #[error("Pathspec `{0}` did not match any files")]
+    NoMatch(String),
+}
+
+/// The paths given to a command that takes a pathspec. Each one is either a path, naming
+/// the file or everything under the directory there, or a glob like `src/*.rs`. As in git,
+/// `*` and `?` in a glob match `/` too, so `src/*.rs` includes `src/object/hash.rs`, and a
+/// glob also names the path it spells out literally, e.g. `[x].txt`.
+#[derive(Clone, Debug)]
+pub struct Pathspec {
+    items: Vec<PathspecItem>,
+}
+
+#[derive(Clone, Debug)]
+enum PathspecItem {
+    Path(WorkPathBuf),
+    Glob {
+        /// The deepest directory containing everything the pattern can match.
+        base: WorkPathBuf,
+        /// The pattern, relative to the top of the working tree.
+        pattern: String,
+    },
+}
+
+impl Pathspec {
+    /// Translates `paths`, which are relative to the current directory, into a pathspec.
+    /// Fails if the part of a path before any wildcards is outside the working tree.
+    pub fn new<P>(wd: &WorkDir, paths: impl IntoIterator<Item = P>) -> Result<Self>
+    where
+        P: AsRef<Path>
+    {
+        let items = paths.into_iter()
+            .map(|path| PathspecItem::new(wd, path.as_ref()))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { items })
+    }
+
+    /// Returns true if the file at `path` is named by the pathspec.
+    pub fn matches(&self, path: &WorkPath) -> bool {
+        self.items.iter().any(|item| item.matches(path))
+    }
+
+    /// Returns the leading text that every path matched by each item starts with, so that
+    /// sorted paths can be searched from there instead of from the start.
+    pub(crate) fn prefixes(&self) -> impl Iterator<Item = &str> {
+        self.items.iter().map(PathspecItem::prefix)
+    }
+
+    /// Lists the paths the pathspec names, in sorted order: each plain path, and the
+    /// tracked files matching each glob. If `include_untracked` is set, untracked files
+    /// that match and aren't ignored are listed too. Fails if a glob matches nothing.
+    pub fn expand(&self, wd: &WorkDir, index: &mut Index, include_untracked: bool) -> Result<Vec<WorkPathBuf>> {
+        let mut paths = vec![];
+        let mut globs = Self { items: vec![] };
+        for item in &self.items {
+            match item {
+                PathspecItem::Path(path) => paths.push(path.clone()),
+                PathspecItem::Glob { .. } => globs.items.push(item.clone()),
+            }
+        }
+        if globs.items.is_empty() {
+            return Ok(paths);
+        }
+
+        let mut bases: Vec<&WorkPathBuf> = globs.items.iter()
+            .filter_map(|item| match item {
+                PathspecItem::Glob { base, .. } => Some(base),
+                PathspecItem::Path(_) => None,
+            })
+            .collect();
+        bases.sort();
+        bases.dedup();
+
+        let mut matched = vec![];
+        for base in &bases {
+            index.expand_to_path(wd, base)?;
+        }
+        matched.extend(index.entries_matching(&globs).into_iter().map(|(path, _)| path.clone()));
+
+        if include_untracked {
+            for base in &bases {
+                let untracked = index.list_unstaged_changes(wd, base, false)?.into_iter()
+                    .filter_map(|change| match change {
+                        UnstagedChange::Created { path, .. } | UnstagedChange::Renamed { path, .. } => Some(path),
+                        _ => None,
+                    })
+                    .filter(|path| globs.matches(path));
+                matched.extend(untracked);
+            }
+        }
+
+        for item in &globs.items {
+            if let PathspecItem::Glob { pattern, .. } = item {
+                if !matched.iter().any(|path| item.matches(path)) {
+                    return Err(PathspecError::NoMatch(pattern.clone()).into());
+                }
+            }
+        }
+
+        paths.extend(matched);
+        paths.sort();
+        paths.dedup();
+
+        Ok(paths)
+    }
+}
+
+impl PathspecItem {
+    const WILDCARDS: [char; 3] = ['*', '?', '['];
+
+    fn new(wd: &WorkDir, path: &Path) -> Result<Self> {
+        let text = path.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/");
+        let Some(wildcard) = text.find(Self::WILDCARDS) else {
+            return Ok(Self::Path(wd.canonicalize_path(path)?));
+        };
+
+        // Everything up to the last slash before the first wildcard is a plain directory
+        let (dir, rest) = match text[..wildcard].rfind('/') {
+            Some(slash) => (&text[..slash.max(1)], &text[slash + 1..]),
+            None => (".", text.as_str()),
+        };
+        let base = wd.canonicalize_path(dir)?;
+        let pattern = match base.is_empty() {
+            true => rest.to_owned(),
+            false => format!("{base}/{rest}"),
+        };
+
+        Ok(Self::Glob { base, pattern })
+    }
+
+    fn matches(&self, path: &WorkPath) -> bool {
+        match self {
+            Self::Path(dir) => dir.is_empty() || path.as_str().strip_prefix(dir.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+            Self::Glob { pattern, .. } => {
+                // git tries the pattern as a plain path first
+                let is_literal_match = path.as_str().strip_prefix(pattern.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+                is_literal_match || fnmatch(pattern.as_bytes(), path.as_str().as_bytes())
+            },
+        }
+    }
+
+    fn prefix(&self) -> &str {
+        match self {
+            Self::Path(path) => path.as_str(),
+            Self::Glob { pattern, .. } => {
+                let end = pattern.find(Self::WILDCARDS).unwrap_or(pattern.len());
+                // A backslash escapes the next character, so the prefix stops before it
+                let end = pattern[..end].find('\\').unwrap_or(end);
+                &pattern[..end]
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_workdir;
+
+    fn work_path(path: &str) -> WorkPathBuf {
+        WorkPathBuf::try_from(path).unwrap()
+    }
+
+    #[test]
+    fn matches_paths_and_globs() {
+        let (temp_dir, wd) = temp_workdir();
+        let pathspec = Pathspec::new(&wd, [
+            temp_dir.join("docs"),
+            temp_dir.join("src/*.rs"),
+            temp_dir.join("**/test?.txt"),
+            temp_dir.join("[x].txt"),
+        ]).unwrap();
+
+        assert!(pathspec.matches(&work_path("docs")));
+        assert!(pathspec.matches(&work_path("docs/guide/intro.md")));
+        assert!(!pathspec.matches(&work_path("docs.md")));
+        assert!(pathspec.matches(&work_path("src/lib.rs")));
+        assert!(pathspec.matches(&work_path("src/object/hash.rs")));
+        assert!(!pathspec.matches(&work_path("src.rs")));
+        assert!(!pathspec.matches(&work_path("test1.txt")));
+        assert!(pathspec.matches(&work_path("a/b/test2.txt")));
+        assert!(pathspec.matches(&work_path("[x].txt")));
+        assert!(pathspec.matches(&work_path("x.txt")));
+        assert!(!pathspec.matches(&work_path("y.txt")));
+        assert_eq!(pathspec.prefixes().collect::<Vec<_>>(), ["docs", "src/", "", ""]);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{
+    date::{self, DateStyle},
+    object::{Commit, ObjectHash, Tag},
+};
+
+/// Renders a commit the way `git log` shows it by default: the hash, the parents if it's
+/// a merge, the author and date, then the message indented by four spaces.
+pub fn format_commit(hash: &ObjectHash, commit: &Commit, date_style: &DateStyle) -> String {
+    let mut text = format!("commit {hash}\n");
+    if commit.parents().len() > 1 {
+        let parents: Vec<_> = commit.parents().iter()
+            .map(|parent| parent.to_string()[..7].to_owned())
+            .collect();
+        text.push_str(&format!("Merge: {}\n", parents.join(" ")));
+    }
+    if let Some(author) = commit.author() {
+        text.push_str(&format!("Author: {author}\n"));
+    }
+    if let Some((timestamp, zone)) = commit.author_date() {
+        text.push_str(&format!("Date:   {}\n", date::format(timestamp, zone, date_style)));
+    }
+    text.push('\n');
+    for line in commit.message().lines() {
+        text.push_str(&format!("    {line}\n"));
+    }
+
+    text
+}
+
+/// Renders an annotated tag the way `git show` does: the name, the tagger and date, then
+/// the message as-is.
+pub fn format_tag(tag: &Tag, date_style: &DateStyle) -> String {
+    let mut text = format!("tag {}\n", tag.name().unwrap_or_default());
+    if let Some(tagger) = tag.tagger() {
+        text.push_str(&format!("Tagger: {tagger}\n"));
+    }
+    if let Some((timestamp, zone)) = tag.tagger_date() {
+        text.push_str(&format!("Date:   {}\n", date::format(timestamp, zone, date_style)));
+    }
+    text.push('\n');
+    text.push_str(tag.message());
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_commit_with_indented_message() {
+        let commit = Commit::deserialize("\
+tree bf42a97e57f4f7e090ee62e5967e94fc4331dabb
+author Jo <jo@example.com> 0 +0000
+committer Jo <jo@example.com> 0 +0000
+
+Subject
+
+Body".into()).unwrap();
+        let hash = ObjectHash::try_from("bf42a97e57f4f7e090ee62e5967e94fc4331dabb").unwrap();
+
+        assert_eq!(format_commit(&hash, &commit, &DateStyle::Default), "\
+commit bf42a97e57f4f7e090ee62e5967e94fc4331dabb
+Author: Jo <jo@example.com>
+Date:   Thu Jan 1 00:00:00 1970 +0000
+
+    Subject
+    \n    Body
+");
+    }
+
+    #[test]
+    fn formats_tag() {
+        let tag = Tag::deserialize("\
+object bf42a97e57f4f7e090ee62e5967e94fc4331dabb
+type commit
+tag v1.0
+tagger Jo <jo@example.com> 86400 +0000
+
+Release\n".into()).unwrap();
+
+        assert_eq!(format_tag(&tag, &DateStyle::Iso), "\
+tag v1.0
+Tagger: Jo <jo@example.com>
+Date:   1970-01-02 00:00:00 +0000
+
+Release
+");
+    }
+}
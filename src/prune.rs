@@ -0,0 +1,282 @@
+use std::{collections::HashSet, fs, path::{Path, PathBuf}, time::SystemTime};
+
+use crate::{
+    Result,
+    consts::{HEAD, ORIG_HEAD, MERGE_HEAD, CHERRY_PICK_HEAD, REVERT_HEAD},
+    index::Index,
+    repo::Repository,
+    reflog,
+    workdir::WorkDir,
+    worktree::LinkedWorktree,
+    object::{GitObject, ObjectError, ObjectHash, ObjectStore, forget_listing},
+};
+
+/// How long an unreachable object is kept after it was last written, if gc.pruneExpire
+/// isn't set. This is the same as git.
+pub const DEFAULT_EXPIRE: &str = "2.weeks.ago";
+
+/// Files in `.git` that may name commits that no ref points to, e.g. during a merge.
+const PSEUDO_REFS: &[&str] = &[HEAD, ORIG_HEAD, MERGE_HEAD, CHERRY_PICK_HEAD, REVERT_HEAD, "FETCH_HEAD", "AUTO_MERGE"];
+
+/// Deletes the loose objects that are unreachable and haven't been written since `cutoff`
+/// (seconds since the Unix epoch). If `dry_run` is true, nothing is deleted. Returns the
+/// hashes of the pruned objects, in sorted order.
+///
+/// Another process may be making a commit at the same time, and its new objects aren't
+/// reachable until it updates a ref. The grace period protects those objects. In case a
+/// ref, reflog, or the index changed during the walk, reachability is checked again just
+/// before deleting, and so is each object's modification time, since writing an object
+/// that already exists refreshes it.
+pub fn prune(repo: &Repository, cutoff: i64, dry_run: bool) -> Result<Vec<ObjectHash>> {
+    let wd = repo.workdir();
+    if !dry_run {
+        wd.check_writable()?;
+    }
+    let store = ObjectStore::open(wd)?;
+    let mut walk = ReachableWalk::new(&store);
+    walk.mark(roots(repo)?)?;
+
+    let object_path = |hash: &ObjectHash| wd.git_path("objects").join(hash.to_path());
+    let mut candidates = vec![];
+    for hash in GitObject::loose_hashes(wd)? {
+        if !walk.reachable.contains(&hash) && is_expired(&object_path(&hash), cutoff)? {
+            candidates.push(hash);
+        }
+    }
+    if candidates.is_empty() {
+        return Ok(candidates);
+    }
+
+    walk.mark(roots(repo)?)?;
+
+    let mut pruned = vec![];
+    for hash in candidates {
+        let path = object_path(&hash);
+        if walk.reachable.contains(&hash) || !is_expired(&path, cutoff)? {
+            continue;
+        }
+
+        if !dry_run {
+            fs::remove_file(&path)?;
+            forget_listing(&path);
+            // Only succeeds once the fan-out directory is empty
+            let _ = fs::remove_dir(path.parent().expect("object path should have a parent"));
+        }
+        pruned.push(hash);
+    }
+
+    Ok(pruned)
+}
+
+/// Finds reachable objects. Objects never change, so an object only has to be walked
+/// once, even if the roots are checked again.
+pub(crate) struct ReachableWalk<'a> {
+    store: &'a ObjectStore,
+    pub reachable: HashSet<ObjectHash>,
+}
+
+impl<'a> ReachableWalk<'a> {
+    pub fn new(store: &'a ObjectStore) -> Self {
+        Self {
+            store,
+            reachable: HashSet::new(),
+        }
+    }
+
+    /// Marks the objects in `roots` and everything they refer to as reachable.
+    pub fn mark(&mut self, roots: Vec<ObjectHash>) -> Result<()> {
+        let mut pending = roots;
+
+        while let Some(hash) = pending.pop() {
+            if !self.reachable.insert(hash) {
+                continue;
+            }
+
+            match self.store.read(&hash) {
+                Ok(object) => pending.extend(object.references()?),
+                // A broken link is for fsck to report, and there's nothing beyond it
+                Err(err) if matches!(err.downcast_ref(), Some(ObjectError::NotFound(_))) => (),
+                Err(err) => return Err(err.context(format!("Failed to find the objects reachable from {hash}"))),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists the objects that are in use: the targets of every ref (in any namespace, loose or
+/// packed), the pseudo-refs like `MERGE_HEAD`, every reflog entry, and the index. Linked
+/// worktrees have their own HEAD, index, and reflogs, which are included too.
+fn roots(repo: &Repository) -> Result<Vec<ObjectHash>> {
+    let wd = repo.workdir();
+    let mut roots = ref_roots(wd)?;
+    reflog_roots(wd, &mut roots)?;
+    index_roots(&repo.index()?, &mut roots);
+
+    for worktree in LinkedWorktree::list(wd)? {
+        let admin_dir = worktree.admin_dir(wd);
+        let worktree_wd = WorkDir::with_git_dir(worktree.display_path(wd), &admin_dir)?;
+        roots.extend(ref_roots(&worktree_wd)?);
+        reflog_roots(&worktree_wd, &mut roots)?;
+
+        let index_path = admin_dir.join("index");
+        if index_path.is_file() {
+            let mut index = Index::parse(&fs::read(&index_path)?)?;
+            index.load_shared(&worktree_wd)?;
+            index_roots(&index, &mut roots);
+        }
+    }
+
+    // Reflogs use the null hash for a ref that didn't exist
+    let null_hash = ObjectHash::try_from([0; 20].as_slice()).expect("null hash should be valid");
+    roots.retain(|hash| *hash != null_hash);
+
+    Ok(roots)
+}
+
+/// Adds the old and new hash of every reflog entry in the git directory of `wd`.
+fn reflog_roots(wd: &WorkDir, roots: &mut Vec<ObjectHash>) -> Result<()> {
+    for ref_name in reflog::list(wd)? {
+        for entry in reflog::read(wd, &ref_name)? {
+            roots.push(entry.old);
+            roots.push(entry.new);
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the blob of every entry in `index`. Gitlinks are skipped, since their commits
+/// belong to another repository.
+fn index_roots(index: &Index, roots: &mut Vec<ObjectHash>) {
+    roots.extend(index.entries.values()
+        .filter(|entry| !entry.is_gitlink())
+        .map(|entry| entry.hash));
+}
+
+/// Lists the targets of every ref (in any namespace, loose or packed) and the pseudo-refs
+/// like `MERGE_HEAD`.
+pub(crate) fn ref_roots(wd: &WorkDir) -> Result<Vec<ObjectHash>> {
+    let mut roots = vec![];
+
+    collect_ref_files(&wd.git_path("refs"), &mut roots)?;
+    for name in std::iter::once(&"packed-refs").chain(PSEUDO_REFS) {
+        if let Ok(contents) = fs::read_to_string(wd.git_path(name)) {
+            roots.extend(parse_hashes(&contents));
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Reads the hash from every loose ref file under `dir`. Symbolic refs are skipped, since
+/// their targets are found on their own.
+fn collect_ref_files(dir: &Path, roots: &mut Vec<ObjectHash>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            collect_ref_files(&entry.path(), roots)?;
+        }
+        else {
+            roots.extend(parse_hashes(&fs::read_to_string(entry.path())?));
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the hash at the start of each line, including peeled lines (`^hash`) in
+/// `packed-refs`.
+fn parse_hashes(contents: &str) -> impl Iterator<Item = ObjectHash> + '_ {
+    contents.lines()
+        .filter_map(|line| line.strip_prefix('^').unwrap_or(line).get(..40))
+        .filter_map(|hex| ObjectHash::try_from(hex).ok())
+}
+
+/// Returns true if the file at `path` was last modified at or before `cutoff`.
+fn is_expired(path: &PathBuf, cutoff: i64) -> Result<bool> {
+    let modified = fs::metadata(path)?.modified()?;
+    let modified = match modified.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(_) => 0,
+    };
+
+    Ok(modified <= cutoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Blob;
+
+    #[test]
+    fn prunes_only_old_unreachable_objects() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.join(".git/refs/tags")).unwrap();
+        fs::write(temp_dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(temp_dir.join(".git/config"), "[core]\n\trepositoryformatversion = 0\n").unwrap();
+        let repo = Repository::find(temp_dir.path()).unwrap();
+        let wd = repo.workdir();
+
+        let blob = |data: &str| GitObject::Blob(Blob::deserialize(data.as_bytes().to_vec()).unwrap());
+        let old = blob("old").write(wd).unwrap();
+        let new = blob("new").write(wd).unwrap();
+        let referenced = blob("referenced").write(wd).unwrap();
+        fs::write(wd.git_path("refs/tags/keep"), format!("{referenced}\n")).unwrap();
+
+        let past = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+        for hash in [old, referenced] {
+            let file = fs::File::open(wd.git_path("objects").join(hash.to_path())).unwrap();
+            file.set_modified(past).unwrap();
+        }
+
+        assert_eq!(prune(&repo, 2000, true).unwrap(), [old]);
+        assert!(wd.git_path("objects").join(old.to_path()).is_file());
+
+        assert_eq!(prune(&repo, 2000, false).unwrap(), [old]);
+        assert!(!wd.git_path("objects").join(old.to_path()).exists());
+        assert!(wd.git_path("objects").join(new.to_path()).is_file());
+        assert!(wd.git_path("objects").join(referenced.to_path()).is_file());
+    }
+
+    #[test]
+    fn keeps_objects_used_by_linked_worktrees() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.join(".git/refs")).unwrap();
+        fs::write(temp_dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(temp_dir.join(".git/config"), "[core]\n\trepositoryformatversion = 0\n").unwrap();
+        let repo = Repository::find(temp_dir.path()).unwrap();
+        let wd = repo.workdir();
+
+        let admin_dir = wd.git_path("worktrees/wt");
+        fs::create_dir_all(admin_dir.join("logs")).unwrap();
+        fs::write(admin_dir.join("gitdir"), format!("{}\n", temp_dir.join("wt/.git").display())).unwrap();
+        let worktree_wd = WorkDir::with_git_dir(temp_dir.join("wt"), &admin_dir).unwrap();
+
+        // The index of the worktree is the only thing that refers to `staged`
+        fs::write(temp_dir.join("s"), "staged").unwrap();
+        let mut index = Index::new(None);
+        index.add(wd, temp_dir.join("s"), false).unwrap();
+        index.write(&worktree_wd).unwrap();
+        let staged = index.entries.values().next().unwrap().hash;
+
+        let blob = |data: &str| GitObject::Blob(Blob::deserialize(data.as_bytes().to_vec()).unwrap());
+        let head = blob("head").write(wd).unwrap();
+        let logged = blob("logged").write(wd).unwrap();
+        fs::write(admin_dir.join("HEAD"), format!("{head}\n")).unwrap();
+        fs::write(admin_dir.join("logs/HEAD"), format!("{logged} {head} User <user@example.com> 0 +0000\tcheckout\n")).unwrap();
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        assert_eq!(prune(&repo, now + 1, false).unwrap(), []);
+        for hash in [staged, head, logged] {
+            assert!(wd.git_path("objects").join(hash.to_path()).is_file());
+        }
+    }
+}
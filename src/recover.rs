@@ -0,0 +1,104 @@
+//! Finding lost commits: the tips of history that no ref points to any more, e.g. after a
+//! branch is deleted or reset. These are the dangling commits `git fsck --no-reflogs`
+//! reports, and the reflogs usually say how they were lost.
+
+use std::collections::HashSet;
+
+use crate::{
+    Result,
+    object::{Commit, GitObject, ObjectFormat, ObjectHash, ObjectStore},
+    prune::{self, ReachableWalk},
+    reflog::{self, ReflogEntry},
+    workdir::WorkDir,
+};
+
+/// A commit that isn't reachable from any ref, and isn't the parent of another such commit.
+pub struct LostCommit {
+    pub hash: ObjectHash,
+    pub commit: Commit,
+    /// The reflog entries that moved a ref to this commit, as the selector (e.g. `HEAD@{2}`)
+    /// and the entry, newest first.
+    pub reflog_entries: Vec<(String, ReflogEntry)>,
+}
+
+/// Finds the lost commits among the loose objects, newest first. Reflogs don't count as
+/// refs here, since they expire.
+pub fn find_lost_commits(wd: &WorkDir) -> Result<Vec<LostCommit>> {
+    let store = ObjectStore::open(wd)?;
+    let mut walk = ReachableWalk::new(&store);
+    walk.mark(prune::ref_roots(wd)?)?;
+
+    let mut unreachable = Vec::new();
+    for hash in GitObject::loose_hashes(wd)? {
+        if walk.reachable.contains(&hash) {
+            continue;
+        }
+        let (format, data) = GitObject::read_raw(wd, &hash)?;
+        if format == ObjectFormat::Commit {
+            unreachable.push((hash, Commit::deserialize(data)?));
+        }
+    }
+
+    // Only the newest commit of a lost line of history is interesting
+    let parents: HashSet<_> = unreachable.iter()
+        .flat_map(|(_, commit)| commit.parents())
+        .copied()
+        .collect();
+    let mut lost: Vec<_> = unreachable.into_iter()
+        .filter(|(hash, _)| !parents.contains(hash))
+        .map(|(hash, commit)| LostCommit { hash, commit, reflog_entries: Vec::new() })
+        .collect();
+    lost.sort_by_key(|lost| std::cmp::Reverse(lost.commit.committer_time()));
+
+    for ref_name in reflog::list(wd)? {
+        let entries = reflog::read(wd, &ref_name)?;
+        for (n, entry) in entries.into_iter().rev().enumerate() {
+            if let Some(lost) = lost.iter_mut().find(|lost| lost.hash == entry.new) {
+                lost.reflog_entries.push((format!("{ref_name}@{{{n}}}"), entry));
+            }
+        }
+    }
+    for lost in &mut lost {
+        lost.reflog_entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.timestamp));
+    }
+
+    Ok(lost)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_util::commit;
+
+    #[test]
+    fn finds_tips_of_lost_history() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.join(".git/refs/heads")).unwrap();
+        fs::write(temp_dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        let wd = WorkDir::new(temp_dir.path()).unwrap();
+
+        let root = commit(&wd, "root", &[]);
+        let main = commit(&wd, "main", &[root]);
+        let lost_parent = commit(&wd, "lost parent", &[root]);
+        let lost_tip = commit(&wd, "lost tip", &[lost_parent]);
+        fs::write(wd.git_path("refs/heads/main"), format!("{main}\n")).unwrap();
+
+        let entry = ReflogEntry {
+            old: main,
+            new: lost_tip,
+            identity: "Jo <jo@example.com>".to_owned(),
+            timestamp: 2,
+            timezone: "+0000".to_owned(),
+            message: "commit: lost tip".to_owned(),
+        };
+        let reset = ReflogEntry { old: lost_tip, new: main, message: "reset: moving to main".to_owned(), ..entry.clone() };
+        reflog::write(&wd, "HEAD", &[entry.clone(), reset]).unwrap();
+
+        let lost = find_lost_commits(&wd).unwrap();
+        assert_eq!(lost.len(), 1);
+        assert_eq!(lost[0].hash, lost_tip);
+        assert_eq!(lost[0].reflog_entries, [("HEAD@{1}".to_owned(), entry)]);
+    }
+}
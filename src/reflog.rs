@@ -0,0 +1,243 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use thiserror::Error;
+
+use crate::{
+    Result,
+    repo::Repository,
+    workdir::WorkDir,
+    consts::{HEAD, BRANCH_PREFIX, TAG_PREFIX, REMOTE_PREFIX},
+    object::ObjectHash,
+};
+
+/// How long reflog entries are kept if gc.reflogExpire isn't set. This is the same as git.
+pub const DEFAULT_EXPIRE: &str = "90.days.ago";
+
+/// A single line in a reflog, recording one update to a ref.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ReflogEntry {
+    pub old: ObjectHash,
+    pub new: ObjectHash,
+    /// The name and email of the person who made the change, e.g. `Jo <jo@example.com>`.
+    pub identity: String,
+    /// When the change was made, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// The timezone offset of `timestamp`, e.g. `-0700`.
+    pub timezone: String,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// Parses a line of a reflog (without the trailing newline).
+    pub fn parse(line: &str) -> Result<Self> {
+        let corrupt = || ReflogError::CorruptEntry(line.to_owned());
+
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old, rest) = header.split_once(' ').ok_or_else(corrupt)?;
+        let (new, rest) = rest.split_once(' ').ok_or_else(corrupt)?;
+        let (rest, timezone) = rest.rsplit_once(' ').ok_or_else(corrupt)?;
+        let (identity, timestamp) = rest.rsplit_once(' ').ok_or_else(corrupt)?;
+
+        Ok(Self {
+            old: ObjectHash::try_from(old).map_err(|_| corrupt())?,
+            new: ObjectHash::try_from(new).map_err(|_| corrupt())?,
+            identity: identity.to_owned(),
+            timestamp: timestamp.parse().map_err(|_| corrupt())?,
+            timezone: timezone.to_owned(),
+            message: message.to_owned(),
+        })
+    }
+
+    /// Converts the entry into a line of a reflog, including the trailing newline.
+    pub fn serialize(&self) -> String {
+        format!("{} {} {} {} {}\t{}\n",
+            self.old, self.new, self.identity, self.timestamp, self.timezone, self.message)
+    }
+}
+
+/// Reads the reflog of the ref `ref_name` (e.g. `refs/heads/main` or `HEAD`), oldest entry
+/// first. A ref with no reflog has no entries.
+pub fn read(wd: &WorkDir, ref_name: &str) -> Result<Vec<ReflogEntry>> {
+    let path = wd.git_path(Path::new("logs").join(ref_name));
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read reflog at `{path:?}`"))?;
+
+    contents.lines()
+        .filter(|line| !line.is_empty())
+        .map(ReflogEntry::parse)
+        .collect()
+}
+
+/// Replaces the reflog of the ref `ref_name` with `entries`.
+pub fn write(wd: &WorkDir, ref_name: &str, entries: &[ReflogEntry]) -> Result<()> {
+    wd.check_writable()?;
+
+    let path = wd.git_path(Path::new("logs").join(ref_name));
+    if let Some(dir_path) = path.parent() {
+        fs::create_dir_all(dir_path)?;
+    }
+
+    let contents: String = entries.iter()
+        .map(ReflogEntry::serialize)
+        .collect();
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+/// Lists the names of all refs that have a reflog.
+pub fn list(wd: &WorkDir) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+
+    let head_path = wd.git_path("logs/HEAD");
+    if head_path.is_file() {
+        names.push(HEAD.to_owned());
+    }
+
+    let refs_path = wd.git_path("logs/refs");
+    if refs_path.is_dir() {
+        list_recursive(&refs_path, "refs", &mut names)?;
+    }
+
+    Ok(names)
+}
+
+/// Lists the names of the reflogs in the directory at `abs_path`, whose name is `name`.
+fn list_recursive(abs_path: &Path, name: &str, names: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(abs_path)? {
+        let entry = entry?;
+        let entry_name = format!("{name}/{}", entry.file_name().to_string_lossy());
+
+        if entry.file_type()?.is_dir() {
+            list_recursive(&entry.path(), &entry_name, names)?;
+        }
+        else {
+            names.push(entry_name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Determines the full name of the ref whose reflog is referred to by `name`, e.g.
+/// `refs/heads/main` for `main`.
+pub fn full_name(wd: &WorkDir, name: &str) -> String {
+    if name == HEAD || name.starts_with("refs/") {
+        return name.to_owned();
+    }
+
+    [BRANCH_PREFIX, TAG_PREFIX, REMOTE_PREFIX].iter()
+        .map(|prefix| format!("{prefix}{name}"))
+        .find(|full_name| wd.git_path(Path::new("logs").join(full_name)).is_file())
+        .unwrap_or_else(|| format!("{BRANCH_PREFIX}{name}"))
+}
+
+/// Parses a reflog selector like `main@{2}` into the ref name and entry number.
+pub fn parse_selector(selector: &str) -> Result<(&str, usize)> {
+    let invalid = || ReflogError::InvalidSelector(selector.to_owned());
+
+    let (name, rest) = selector.rsplit_once("@{").ok_or_else(invalid)?;
+    let n = rest.strip_suffix('}')
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(invalid)?;
+    let name = match name {
+        "" => HEAD,
+        name => name,
+    };
+
+    Ok((name, n))
+}
+
+/// Returns the expiry time set by `gc.reflogExpire`, or [`DEFAULT_EXPIRE`].
+///
+/// In git, `gc` expires every reflog with this. wyag has no `gc` command, so only
+/// `reflog expire` reads it for now, but a `gc` would get its expiry time here too.
+pub fn configured_expiry(repo: &Repository) -> &str {
+    repo.get_config("gc", "reflogExpire").unwrap_or(DEFAULT_EXPIRE)
+}
+
+/// Removes the entries in the reflog of `ref_name` that are older than `cutoff` (seconds
+/// since the Unix epoch). Returns the number of entries removed.
+pub fn expire(wd: &WorkDir, ref_name: &str, cutoff: i64) -> Result<usize> {
+    let mut entries = read(wd, ref_name)?;
+    let original_len = entries.len();
+
+    entries.retain(|entry| entry.timestamp >= cutoff);
+
+    let removed = original_len - entries.len();
+    if removed > 0 {
+        write(wd, ref_name, &entries)?;
+    }
+
+    Ok(removed)
+}
+
+/// Removes the `n`th most recent entry (0 is the newest) from the reflog of `ref_name`.
+pub fn delete(wd: &WorkDir, ref_name: &str, n: usize) -> Result<()> {
+    let mut entries = read(wd, ref_name)?;
+    if n >= entries.len() {
+        return Err(ReflogError::NoSuchEntry {
+            ref_name: ref_name.to_owned(),
+            n,
+        }.into());
+    }
+
+    let index = entries.len() - 1 - n;
+    entries.remove(index);
+
+    write(wd, ref_name, &entries)
+}
+
+#[derive(Error, Debug)]
+pub enum ReflogError {
+    #[error("Invalid reflog entry `{0}`")]
+    CorruptEntry(String),
+    #[error("The reflog for `{ref_name}` has no entry {n}")]
+    NoSuchEntry {
+        ref_name: String,
+        n: usize,
+    },
+    #[error("Invalid reflog entry selector `{0}` (expected something like `main@{{1}}`)")]
+    InvalidSelector(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_round_trip() {
+        let line = "0000000000000000000000000000000000000000 0123456789abcdef0123456789abcdef01234567 \
+            Jo Smith <jo@example.com> 1700000000 -0700\tcommit (initial): First";
+        let entry = ReflogEntry::parse(line).unwrap();
+
+        assert_eq!(entry.identity, "Jo Smith <jo@example.com>");
+        assert_eq!(entry.timestamp, 1700000000);
+        assert_eq!(entry.timezone, "-0700");
+        assert_eq!(entry.message, "commit (initial): First");
+        assert_eq!(entry.serialize(), format!("{line}\n"));
+    }
+
+    #[test]
+    fn configured_expiry_reads_gc_config() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let mut repo = Repository::init(temp_dir.path()).unwrap();
+        assert_eq!(configured_expiry(&repo), DEFAULT_EXPIRE);
+
+        repo.set_config("gc", "reflogExpire", "never".to_owned());
+        assert_eq!(configured_expiry(&repo), "never");
+    }
+
+    #[test]
+    fn parse_selectors() {
+        assert_eq!(parse_selector("main@{2}").unwrap(), ("main", 2));
+        assert_eq!(parse_selector("@{0}").unwrap(), ("HEAD", 0));
+        assert!(parse_selector("main").is_err());
+        assert!(parse_selector("main@{x}").is_err());
+    }
+}
@@ -6,17 +6,23 @@ use thiserror::Error;
 use crate::{
     Result,
     workdir::WorkDir,
+    consts::{BRANCH_PREFIX, TAG_PREFIX, REMOTE_PREFIX, SYMREF_PREFIX},
     object::ObjectHash,
 };
 
+mod format;
+pub use format::{RefFormat, RefAtom, RefSortKey, sort_refs, matches_pattern};
+mod packed;
+mod snapshot;
+pub use snapshot::RefSnapshot;
+mod transaction;
+pub use transaction::{RefTransaction, check_name};
+
 /// Creates a new ref at refs/prefix/name that points to `hash`.
 pub fn create(wd: &WorkDir, prefix: &str, name: &str, hash: &ObjectHash) -> Result<()>
 {
     let rel_path: PathBuf = ["refs", prefix, name].iter().collect();
-    let abs_path = wd.git_path(rel_path);
-    fs::write(abs_path, format!("{hash}\n"))?;
-
-    Ok(())
+    wd.write_git_file(namespaced_path(wd, &rel_path), format!("{hash}\n").as_bytes())
 }
 
 /// Determines the hash pointed to by the ref located at refs/prefix/name.
@@ -32,7 +38,25 @@ where
     P: AsRef<Path>
 {
     let rel_path = rel_path.as_ref();
-    let abs_path = wd.git_path(rel_path);
+
+    match read_target(wd, rel_path)? {
+        RefTarget::Direct(hash) => Ok(hash),
+        RefTarget::Symbolic(indirect_path) => {
+            resolve_path(wd, indirect_path)
+                .map_err(|err| match err.downcast::<RefError>() {
+                    Ok(next_err) => RefError::BadChain {
+                        ref_path: rel_path.to_owned(),
+                        next: Box::new(next_err),
+                    }.into(),
+                    Err(err) => err,
+                })
+        },
+    }
+}
+
+/// Reads the ref located at `rel_path` without following it if it's symbolic.
+fn read_target(wd: &WorkDir, rel_path: &Path) -> Result<RefTarget> {
+    let abs_path = wd.git_path(namespaced_path(wd, rel_path));
 
     if !abs_path.is_file() {
         return Err(RefError::Nonexistent(rel_path.to_owned()).into());
@@ -43,7 +67,7 @@ where
     let ref_contents = ref_contents.trim();
 
     // A valid ref is either a hash or the name of another ref
-    if let Some(indirect_path) = ref_contents.strip_prefix("ref: ") {
+    if let Some(indirect_path) = ref_contents.strip_prefix(SYMREF_PREFIX) {
         if indirect_path.is_empty() {
             return Err(RefError::Corrupt {
                 ref_path: rel_path.to_owned(),
@@ -51,17 +75,10 @@ where
             }.into());
         }
 
-        resolve_path(wd, indirect_path)
-            .map_err(|err| match err.downcast::<RefError>() {
-                Ok(next_err) => RefError::BadChain {
-                    ref_path: rel_path.to_owned(),
-                    next: Box::new(next_err),
-                }.into(),
-                Err(err) => err,
-            })
+        Ok(RefTarget::Symbolic(indirect_path.to_owned()))
     }
     else if let Ok(hash) = ObjectHash::try_from(ref_contents) {
-        Ok(hash)
+        Ok(RefTarget::Direct(hash))
     }
     else {
         Err(RefError::Corrupt {
@@ -71,45 +88,124 @@ where
     }
 }
 
-/// Enumerates all of the refs defined in the repo.
-pub fn list(wd: &WorkDir) -> Result<Vec<(String, ObjectHash)>> {
-    let prev_working_dir = std::env::current_dir()?;
-    std::env::set_current_dir(wd.git_path("."))?;
+/// A ref defined in the repo, such as a branch or tag.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Ref {
+    /// The full name of the ref, e.g. `refs/heads/main`.
+    pub name: String,
+    pub target: RefTarget,
+}
 
-    let mut refs = Vec::new();
-    list_recursive(wd, "refs", &mut refs)?;
+/// What a [`Ref`] points to.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RefTarget {
+    /// The ref contains the hash of an object.
+    Direct(ObjectHash),
+    /// The ref contains the name of another ref (`ref: refs/heads/main`).
+    Symbolic(String),
+}
 
-    std::env::set_current_dir(prev_working_dir)?;
+impl Ref {
+    /// Determines the hash this ref points to, following symbolic refs as needed.
+    pub fn resolve(&self, wd: &WorkDir) -> Result<ObjectHash> {
+        match &self.target {
+            RefTarget::Direct(hash) => Ok(*hash),
+            RefTarget::Symbolic(_) => resolve_path(wd, &self.name),
+        }
+    }
 
-    Ok(refs)
+    /// Returns the name without its category, e.g. `main` instead of `refs/heads/main`.
+    pub fn short_name(&self) -> &str {
+        [BRANCH_PREFIX, TAG_PREFIX, REMOTE_PREFIX, "refs/"].iter()
+            .find_map(|prefix| self.name.strip_prefix(prefix))
+            .unwrap_or(&self.name)
+    }
 }
 
-/// Enumerates all of the refs defined in the directory at `rel_path`.
-fn list_recursive<P>(wd: &WorkDir, rel_path: P, refs: &mut Vec<(String, ObjectHash)>) -> Result<()>
-where
-    P: AsRef<Path>
-{
-    for entry in fs::read_dir(&rel_path)? {
-        let path = entry?.path();
+/// Enumerates the refs defined in the repo whose full names start with `prefix` (e.g.
+/// `refs/tags/`), sorted by name. Use an empty prefix to enumerate every ref.
+pub fn iter<'a>(wd: &'a WorkDir, prefix: &str) -> Result<RefIter<'a>> {
+    let mut iter = RefIter {
+        wd,
+        prefix: prefix.to_owned(),
+        pending: Vec::new(),
+    };
 
-        if path.is_dir() {
-            list_recursive(wd, path, refs)?;
-        }
-        else {
-            let hash = resolve_path(wd, &path)?;
-            refs.push((
-                path.to_string_lossy().replace('\\', "/"),
-                hash,
-            ));
+    let root_path = wd.git_path(namespaced_path(wd, Path::new("refs")));
+    if root_path.is_dir() {
+        iter.push_dir(&root_path, "refs")?;
+    }
+
+    Ok(iter)
+}
+
+/// An iterator over the refs in a repo. See [`iter`].
+pub struct RefIter<'a> {
+    wd: &'a WorkDir,
+    prefix: String,
+    /// Files and directories yet to be visited, in reverse order by name.
+    pending: Vec<(PathBuf, String)>,
+}
+
+impl RefIter<'_> {
+    /// Queues the contents of the directory at `abs_path`, whose name (ignoring the
+    /// namespace) is `name`.
+    fn push_dir(&mut self, abs_path: &Path, name: &str) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(abs_path)? {
+            let entry = entry?;
+            let entry_name = format!("{name}/{}", entry.file_name().to_string_lossy());
+            let is_dir = entry.file_type()?.is_dir();
+
+            // A directory sorts as if its name ends with a slash, since that's how the
+            // full names of the refs inside it will compare
+            let sort_key = match is_dir {
+                true => format!("{entry_name}/"),
+                false => entry_name.clone(),
+            };
+            entries.push((sort_key, entry.path(), entry_name));
         }
+
+        entries.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        self.pending.extend(entries.into_iter().map(|(_, path, name)| (path, name)));
+
+        Ok(())
     }
 
-    Ok(())
+    /// Returns true if the directory `name` could contain refs that match the prefix.
+    fn could_match_dir(&self, name: &str) -> bool {
+        name.starts_with(&self.prefix) || self.prefix.starts_with(&format!("{name}/"))
+    }
+}
+
+impl Iterator for RefIter<'_> {
+    type Item = Result<Ref>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((abs_path, name)) = self.pending.pop() {
+            if abs_path.is_dir() {
+                if !self.could_match_dir(&name) {
+                    continue;
+                }
+                if let Err(err) = self.push_dir(&abs_path, &name) {
+                    return Some(Err(err));
+                }
+            }
+            else if name.starts_with(&self.prefix) {
+                let target = read_target(self.wd, Path::new(&name));
+                return Some(target.map(|target| Ref { name, target }));
+            }
+        }
+
+        None
+    }
 }
 
 pub fn delete(wd: &WorkDir, prefix: &str, name: &str) -> Result<()> {
+    wd.check_writable()?;
+
     let rel_path: PathBuf = ["refs", prefix, name].iter().collect();
-    let abs_path = wd.git_path(rel_path);
+    let abs_path = wd.git_path(namespaced_path(wd, &rel_path));
 
     if abs_path.is_file() {
         std::fs::remove_file(abs_path)?;
@@ -118,6 +214,30 @@ pub fn delete(wd: &WorkDir, prefix: &str, name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Maps the ref at `rel_path` (e.g. `refs/heads/main`) to the place where it's stored,
+/// which differs when a namespace is active. Paths outside of `refs` aren't affected.
+/// 
+/// Nested namespaces (`a/b`) are stored as `refs/namespaces/a/refs/namespaces/b/`.
+fn namespaced_path(wd: &WorkDir, rel_path: &Path) -> PathBuf {
+    let Some(namespace) = &wd.settings().namespace else {
+        return rel_path.to_owned();
+    };
+    let Ok(rest) = rel_path.strip_prefix("refs") else {
+        return rel_path.to_owned();
+    };
+
+    let mut path = PathBuf::new();
+    for component in namespace.split('/').filter(|component| !component.is_empty()) {
+        path.push("refs");
+        path.push("namespaces");
+        path.push(component);
+    }
+    path.push("refs");
+    path.push(rest);
+
+    path
+}
+
 #[derive(Error, Debug)]
 pub enum RefError {
     #[error("No ref found at `{0:?}`")]
@@ -131,5 +251,71 @@ pub enum RefError {
     BadChain {
         ref_path: PathBuf,
         next: Box<RefError>,
+    },
+    #[error("The format `{0}` is invalid")]
+    InvalidFormat(String),
+    #[error("Unknown field name `{0}`")]
+    UnknownField(String),
+    #[error("`{0}` is not a valid ref name")]
+    InvalidName(String),
+    #[error("Line {line_number} of the refs snapshot is invalid: `{line}`")]
+    InvalidSnapshotLine {
+        line_number: usize,
+        line: String,
+    },
+    #[error("The ref `{name}` points to {hash}, which is not in the repository")]
+    MissingObject {
+        name: String,
+        hash: ObjectHash,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workdir::Settings;
+
+    #[test]
+    fn namespaced_path_nests_namespaces() {
+        let mut wd = WorkDir::new(".").unwrap();
+        let path = Path::new("refs/heads/main");
+        assert_eq!(namespaced_path(&wd, path), path);
+
+        wd.set_settings(Settings {
+            namespace: Some("a/b".to_owned()),
+            ..Settings::default()
+        });
+        assert_eq!(
+            namespaced_path(&wd, path),
+            Path::new("refs/namespaces/a/refs/namespaces/b/refs/heads/main"),
+        );
+        assert_eq!(namespaced_path(&wd, Path::new("HEAD")), Path::new("HEAD"));
+    }
+
+    #[test]
+    fn iter_filters_and_sorts_refs() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let hash = "0123456789abcdef0123456789abcdef01234567";
+        for (name, contents) in [
+            ("refs/heads/a/b", hash),
+            ("refs/heads/a-b", hash),
+            ("refs/heads/head", "ref: refs/heads/a-b"),
+            ("refs/tags/v1", hash),
+        ] {
+            let path = temp_dir.path().join(".git").join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("{contents}\n")).unwrap();
+        }
+        let wd = WorkDir::new(temp_dir.path()).unwrap();
+
+        let refs: Vec<Ref> = iter(&wd, "refs/heads/").unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        let names: Vec<&str> = refs.iter().map(Ref::short_name).collect();
+        assert_eq!(names, ["a-b", "a/b", "head"]);
+        assert_eq!(refs[2].target, RefTarget::Symbolic("refs/heads/a-b".to_owned()));
+        assert_eq!(refs[2].resolve(&wd).unwrap(), ObjectHash::try_from(hash).unwrap());
+
+        assert_eq!(iter(&wd, "").unwrap().count(), 4);
     }
 }
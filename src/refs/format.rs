@@ -0,0 +1,221 @@
+use crate::{
+    Result,
+    workdir::WorkDir,
+    object::GitObject,
+};
+
+use super::{Ref, RefError};
+
+/// A format string for displaying refs, e.g. `%(refname:short) %(objectname)`.
+///
+/// Besides the atoms listed in [`RefAtom`], `%%` is a literal `%` and `%xx` is the
+/// character with hex code `xx`.
+pub struct RefFormat {
+    parts: Vec<FormatPart>,
+}
+
+enum FormatPart {
+    Literal(String),
+    Atom(RefAtom),
+}
+
+/// A field of a ref that can be used in a [`RefFormat`] or as a sort key.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RefAtom {
+    /// `refname`: the full name of the ref.
+    RefName,
+    /// `refname:short`: the name without `refs/heads/`, `refs/tags/`, etc.
+    RefNameShort,
+    /// `objectname`: the hash of the object the ref points to.
+    ObjectName,
+    /// `objectname:short`: the abbreviated hash.
+    ObjectNameShort,
+    /// `objecttype`: the type of the object the ref points to.
+    ObjectType,
+}
+
+/// A sort key for refs, e.g. `-refname` (descending by name).
+pub struct RefSortKey {
+    pub atom: RefAtom,
+    pub descending: bool,
+}
+
+impl RefFormat {
+    /// The format used when none is specified, same as git.
+    pub const DEFAULT: &'static str = "%(objectname) %(objecttype)\t%(refname)";
+
+    /// Parses a format string.
+    pub fn parse(format: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = format;
+
+        while let Some(index) = rest.find('%') {
+            literal.push_str(&rest[..index]);
+            rest = &rest[index + 1..];
+
+            if let Some(after) = rest.strip_prefix('%') {
+                literal.push('%');
+                rest = after;
+            }
+            else if let Some(after) = rest.strip_prefix('(') {
+                let end = after.find(')')
+                    .ok_or_else(|| RefError::InvalidFormat(format.to_owned()))?;
+                if !literal.is_empty() {
+                    parts.push(FormatPart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(FormatPart::Atom(RefAtom::parse(&after[..end])?));
+                rest = &after[end + 1..];
+            }
+            else if let Some(byte) = rest.get(..2).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                literal.push(char::from(byte));
+                rest = &rest[2..];
+            }
+            else {
+                return Err(RefError::InvalidFormat(format.to_owned()).into());
+            }
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(FormatPart::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Formats `git_ref` according to this format.
+    pub fn format(&self, wd: &WorkDir, git_ref: &Ref) -> Result<String> {
+        let mut output = String::new();
+
+        for part in &self.parts {
+            match part {
+                FormatPart::Literal(text) => output.push_str(text),
+                FormatPart::Atom(atom) => output.push_str(&atom.value(wd, git_ref)?),
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+impl RefAtom {
+    /// Parses the name of an atom (the part between `%(` and `)`).
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "refname" => Ok(Self::RefName),
+            "refname:short" => Ok(Self::RefNameShort),
+            "objectname" => Ok(Self::ObjectName),
+            "objectname:short" => Ok(Self::ObjectNameShort),
+            "objecttype" => Ok(Self::ObjectType),
+            name => Err(RefError::UnknownField(name.to_owned()).into()),
+        }
+    }
+
+    /// Returns the value of this field for `git_ref`.
+    pub fn value(&self, wd: &WorkDir, git_ref: &Ref) -> Result<String> {
+        let value = match self {
+            Self::RefName => git_ref.name.clone(),
+            Self::RefNameShort => git_ref.short_name().to_owned(),
+            Self::ObjectName => git_ref.resolve(wd)?.to_string(),
+            Self::ObjectNameShort => git_ref.resolve(wd)?.to_string()[..7].to_owned(),
+            Self::ObjectType => {
+                let hash = git_ref.resolve(wd)?;
+                GitObject::read(wd, &hash)?.get_format().to_string()
+            },
+        };
+
+        Ok(value)
+    }
+}
+
+impl RefSortKey {
+    /// Parses a sort key, which is the name of an atom optionally preceded by `-`.
+    pub fn parse(key: &str) -> Result<Self> {
+        let (name, descending) = match key.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (key, false),
+        };
+
+        Ok(Self {
+            atom: RefAtom::parse(name)?,
+            descending,
+        })
+    }
+}
+
+/// Sorts `refs` by `keys`. Later keys take precedence, so earlier keys only break ties,
+/// same as repeating `--sort` in git.
+pub fn sort_refs(wd: &WorkDir, refs: &mut Vec<Ref>, keys: &[RefSortKey]) -> Result<()> {
+    for key in keys {
+        let mut keyed = refs.drain(..)
+            .map(|git_ref| Ok((key.atom.value(wd, &git_ref)?, git_ref)))
+            .collect::<Result<Vec<_>>>()?;
+
+        keyed.sort_by(|(a, _), (b, _)| match key.descending {
+            true => b.cmp(a),
+            false => a.cmp(b),
+        });
+
+        refs.extend(keyed.into_iter().map(|(_, git_ref)| git_ref));
+    }
+
+    Ok(())
+}
+
+/// Returns true if `name` matches `pattern` the way git's `for-each-ref` does: the
+/// pattern is either a prefix ending at a `/` or a glob where `*` and `?` don't match `/`.
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    if let Some(rest) = name.strip_prefix(pattern) {
+        if pattern.ends_with('/') || rest.is_empty() || rest.starts_with('/') {
+            return true;
+        }
+    }
+
+    glob_matches(name.as_bytes(), pattern.as_bytes())
+}
+
+fn glob_matches(name: &[u8], pattern: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (None, Some(_)) => false,
+        (Some(b'*'), _) => {
+            // Try every possible length for the `*`, stopping at the next slash
+            let max_len = name.iter().position(|ch| *ch == b'/').unwrap_or(name.len());
+            (0..=max_len).any(|len| glob_matches(&name[len..], &pattern[1..]))
+        },
+        (Some(b'?'), Some(ch)) if *ch != b'/' => glob_matches(&name[1..], &pattern[1..]),
+        (Some(expected), Some(ch)) if expected == ch => glob_matches(&name[1..], &pattern[1..]),
+        (Some(_), _) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_format_string() {
+        let format = RefFormat::parse("%(refname:short)%09%%%(objectname)").unwrap();
+        assert!(matches!(&format.parts[..], [
+            FormatPart::Atom(RefAtom::RefNameShort),
+            FormatPart::Literal(text),
+            FormatPart::Atom(RefAtom::ObjectName),
+        ] if text == "\t%"));
+
+        assert!(RefFormat::parse("%(refname").is_err());
+        assert!(RefFormat::parse("%(bogus)").is_err());
+        assert!(RefFormat::parse("100%").is_err());
+    }
+
+    #[test]
+    fn match_patterns() {
+        assert!(matches_pattern("refs/heads/main", "refs/heads"));
+        assert!(matches_pattern("refs/heads/main", "refs/heads/"));
+        assert!(matches_pattern("refs/heads/main", "refs/heads/main"));
+        assert!(!matches_pattern("refs/heads/main", "refs/heads/ma"));
+        assert!(matches_pattern("refs/heads/main", "refs/*/main"));
+        assert!(matches_pattern("refs/tags/v1.0", "refs/tags/v?.*"));
+        assert!(!matches_pattern("refs/heads/feature/x", "refs/heads/*"));
+    }
+}
@@ -0,0 +1,124 @@
+//! The `packed-refs` file, where git moves refs to save space (e.g. in `git pack-refs` or
+//! `git gc`). wyag never packs refs, but it reads them where a command needs every ref, and
+//! removes them where a command deletes refs wholesale.
+
+use std::{fs, io::ErrorKind, path::{Path, PathBuf}};
+
+use crate::{
+    Result,
+    object::ObjectHash,
+    workdir::WorkDir,
+};
+
+use super::{Ref, RefError, RefTarget, namespaced_path};
+
+const PACKED_REFS: &str = "packed-refs";
+
+/// Reads the packed refs in the current namespace, by their names within it. Peeled tags
+/// (`^<hash>` lines) are skipped.
+pub(super) fn read(wd: &WorkDir) -> Result<Vec<Ref>> {
+    let Some(contents) = read_file(wd)? else {
+        return Ok(vec![]);
+    };
+
+    let prefix = storage_prefix(wd);
+    let mut refs = vec![];
+    for line in contents.lines() {
+        if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+            continue;
+        }
+        let corrupt = || RefError::Corrupt {
+            ref_path: PathBuf::from(PACKED_REFS),
+            ref_contents: line.to_owned(),
+        };
+        let (hash, name) = line.split_once(' ').ok_or_else(corrupt)?;
+        let hash = ObjectHash::try_from(hash).map_err(|_| corrupt())?;
+
+        if let Some(rest) = name.strip_prefix(&prefix) {
+            refs.push(Ref { name: format!("refs/{rest}"), target: RefTarget::Direct(hash) });
+        }
+    }
+
+    Ok(refs)
+}
+
+/// Removes every packed ref in the current namespace, along with their peeled lines.
+pub(super) fn remove_all(wd: &WorkDir) -> Result<()> {
+    let Some(contents) = read_file(wd)? else {
+        return Ok(());
+    };
+
+    let prefix = storage_prefix(wd);
+    let mut kept = String::new();
+    let mut removing = false;
+    for line in contents.lines() {
+        if !line.starts_with('^') {
+            removing = line.split_once(' ')
+                .is_some_and(|(_, name)| !line.starts_with('#') && name.starts_with(&prefix));
+        }
+        if !removing {
+            kept += line;
+            kept.push('\n');
+        }
+    }
+
+    if kept != contents {
+        wd.write_git_file(PACKED_REFS, kept.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Reads the `packed-refs` file, or returns `None` if there isn't one.
+fn read_file(wd: &WorkDir) -> Result<Option<String>> {
+    match fs::read_to_string(wd.git_path(PACKED_REFS)) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The start of the stored names of the refs in the current namespace, e.g. `refs/` or
+/// `refs/namespaces/a/refs/`.
+fn storage_prefix(wd: &WorkDir) -> String {
+    let path = namespaced_path(wd, Path::new("refs"));
+    let components: Vec<_> = path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+
+    format!("{}/", components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::temp_workdir, workdir::Settings};
+
+    #[test]
+    fn reads_and_removes_namespaced_refs() {
+        let (temp_dir, mut wd) = temp_workdir();
+        let (a, b) = (ObjectHash::new("a"), ObjectHash::new("b"));
+        let contents = format!(
+            "# pack-refs with: peeled fully-peeled sorted \n\
+            {a} refs/heads/main\n\
+            {b} refs/namespaces/ns/refs/tags/v1\n\
+            ^{a}\n"
+        );
+        fs::write(temp_dir.join(".git/packed-refs"), &contents).unwrap();
+
+        let names: Vec<_> = read(&wd).unwrap().into_iter().map(|git_ref| git_ref.name).collect();
+        assert_eq!(names, ["refs/heads/main", "refs/namespaces/ns/refs/tags/v1"]);
+
+        wd.set_settings(Settings {
+            namespace: Some("ns".to_owned()),
+            ..Settings::default()
+        });
+        let refs = read(&wd).unwrap();
+        assert_eq!(refs, [Ref { name: "refs/tags/v1".to_owned(), target: RefTarget::Direct(b) }]);
+
+        remove_all(&wd).unwrap();
+        assert!(read(&wd).unwrap().is_empty());
+        let remaining = fs::read_to_string(temp_dir.join(".git/packed-refs")).unwrap();
+        assert_eq!(remaining, format!("# pack-refs with: peeled fully-peeled sorted \n{a} refs/heads/main\n"));
+    }
+}
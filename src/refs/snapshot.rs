@@ -0,0 +1,181 @@
+use std::{collections::HashSet, fmt, path::Path};
+
+use crate::{
+    Result,
+    consts::{HEAD, SYMREF_PREFIX},
+    object::{ObjectHash, ObjectStore},
+    workdir::WorkDir,
+};
+
+use super::{Ref, RefError, RefTarget, RefTransaction, check_name, iter, packed, read_target};
+
+/// HEAD and every ref under `refs/` at one point in time, which can be written out as text
+/// and restored later, possibly in another repository.
+///
+/// The text form has one ref per line: its target followed by its full name, e.g.
+/// `<hash> refs/heads/main` or `ref: refs/heads/main HEAD`. Blank lines and lines starting
+/// with `#` are ignored.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RefSnapshot {
+    /// HEAD first (if it exists), followed by the other refs sorted by name.
+    pub refs: Vec<Ref>,
+}
+
+impl RefSnapshot {
+    /// Reads HEAD and the refs under `refs/` without following symbolic refs. Packed refs
+    /// are included, unless a loose ref of the same name overrides them.
+    pub fn capture(wd: &WorkDir) -> Result<Self> {
+        let mut refs = iter(wd, "refs/")?.collect::<Result<Vec<_>>>()?;
+        let loose: HashSet<String> = refs.iter().map(|git_ref| git_ref.name.clone()).collect();
+        refs.extend(packed::read(wd)?.into_iter().filter(|git_ref| !loose.contains(&git_ref.name)));
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        if wd.git_path(HEAD).is_file() {
+            refs.insert(0, Ref {
+                name: HEAD.to_owned(),
+                target: read_target(wd, Path::new(HEAD))?,
+            });
+        }
+
+        Ok(Self { refs })
+    }
+
+    /// Parses the text form of a snapshot, as written by its `Display` impl.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut refs = vec![];
+        let mut names = HashSet::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid_line = || RefError::InvalidSnapshotLine {
+                line_number: i + 1,
+                line: line.to_owned(),
+            };
+            let (target, name) = line.rsplit_once(' ').ok_or_else(invalid_line)?;
+            let target = match target.strip_prefix(SYMREF_PREFIX) {
+                Some(target) => RefTarget::Symbolic(target.to_owned()),
+                None => RefTarget::Direct(ObjectHash::try_from(target).map_err(|_| invalid_line())?),
+            };
+            check_name(name)?;
+            if !names.insert(name) {
+                return Err(invalid_line().into());
+            }
+
+            refs.push(Ref { name: name.to_owned(), target });
+        }
+
+        Ok(Self { refs })
+    }
+
+    /// Points every ref in the snapshot to its target in one [`RefTransaction`]. If `prune`
+    /// is set, refs under `refs/` that aren't in the snapshot are deleted too, and so are
+    /// packed refs once the snapshot's refs have been written. Fails without changing
+    /// anything if a ref points to an object that isn't in the repo.
+    pub fn restore(&self, wd: &WorkDir, prune: bool) -> Result<()> {
+        let hashes: Vec<ObjectHash> = self.refs.iter()
+            .filter_map(|git_ref| match git_ref.target {
+                RefTarget::Direct(hash) => Some(hash),
+                RefTarget::Symbolic(_) => None,
+            })
+            .collect();
+        let store = ObjectStore::open(wd)?;
+        if let Some(&hash) = store.missing(&hashes).first() {
+            let name = self.refs.iter()
+                .find(|git_ref| git_ref.target == RefTarget::Direct(hash))
+                .map(|git_ref| git_ref.name.clone())
+                .unwrap_or_default();
+            return Err(RefError::MissingObject { name, hash }.into());
+        }
+
+        let mut transaction = RefTransaction::new();
+        if prune {
+            for git_ref in iter(wd, "refs/")? {
+                transaction.delete(&git_ref?.name);
+            }
+        }
+        for git_ref in &self.refs {
+            transaction.update(&git_ref.name, git_ref.target.clone());
+        }
+        transaction.commit(wd)?;
+
+        // Every ref that's kept is now loose, so none of the packed ones are needed
+        if prune {
+            packed::remove_all(wd)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for RefSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for git_ref in &self.refs {
+            match &git_ref.target {
+                RefTarget::Direct(hash) => writeln!(f, "{hash} {}", git_ref.name)?,
+                RefTarget::Symbolic(target) => writeln!(f, "{SYMREF_PREFIX}{target} {}", git_ref.name)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        object::{Blob, GitObject},
+        test_util::temp_workdir,
+    };
+
+    #[test]
+    fn round_trips_through_text() {
+        let (temp_dir, wd) = temp_workdir();
+        let hash = ObjectHash::new("a");
+        fs::create_dir_all(temp_dir.join(".git/refs/tags")).unwrap();
+        fs::write(temp_dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(temp_dir.join(".git/refs/tags/v1"), format!("{hash}\n")).unwrap();
+
+        let snapshot = RefSnapshot::capture(&wd).unwrap();
+        let text = snapshot.to_string();
+        assert_eq!(text, format!("ref: refs/heads/main HEAD\n{hash} refs/tags/v1\n"));
+        assert_eq!(RefSnapshot::parse(&format!("# backup\n\n{text}")).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn includes_and_prunes_packed_refs() {
+        let (temp_dir, wd) = temp_workdir();
+        let (a, b) = (ObjectHash::new("a"), ObjectHash::new("b"));
+        fs::create_dir_all(temp_dir.join(".git/refs/heads")).unwrap();
+        fs::write(temp_dir.join(".git/refs/heads/main"), format!("{b}\n")).unwrap();
+        fs::write(temp_dir.join(".git/packed-refs"), format!("{a} refs/heads/main\n{a} refs/tags/v1\n")).unwrap();
+
+        // The loose ref overrides the packed one
+        let snapshot = RefSnapshot::capture(&wd).unwrap();
+        assert_eq!(snapshot.to_string(), format!("{b} refs/heads/main\n{a} refs/tags/v1\n"));
+
+        let store_blob = |data: &str| GitObject::Blob(Blob::deserialize(data.as_bytes().to_vec()).unwrap()).write(&wd).unwrap();
+        let hash = store_blob("kept");
+        let snapshot = RefSnapshot::parse(&format!("{hash} refs/heads/main\n")).unwrap();
+        snapshot.restore(&wd, true).unwrap();
+        assert_eq!(RefSnapshot::capture(&wd).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn parse_rejects_bad_lines() {
+        let hash = ObjectHash::new("a");
+        for text in [
+            format!("{hash}"),
+            "abc refs/heads/main".to_owned(),
+            format!("{hash} main"),
+            format!("{hash} refs/heads/main\n{hash} refs/heads/main"),
+        ] {
+            assert!(RefSnapshot::parse(&text).is_err(), "{text}");
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}};
+
+use crate::{
+    Result,
+    consts::{HEAD, SYMREF_PREFIX},
+    workdir::WorkDir,
+};
+
+use super::{RefError, RefTarget, namespaced_path};
+
+/// A set of ref updates and deletions that are applied together by
+/// [`RefTransaction::commit`]. Nothing changes unless every updated ref can be locked, so a
+/// transaction that fails partway through (e.g. because another command holds a lock)
+/// leaves the refs as they were.
+#[derive(Clone, Default, Debug)]
+pub struct RefTransaction {
+    /// The new target of each ref by full name, or `None` if it's being deleted.
+    changes: BTreeMap<String, Option<RefTarget>>,
+}
+
+impl RefTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Points the ref `name` (e.g. `refs/heads/main` or `HEAD`) to `target`, replacing
+    /// any earlier change to it in this transaction.
+    pub fn update(&mut self, name: &str, target: RefTarget) {
+        self.changes.insert(name.to_owned(), Some(target));
+    }
+
+    /// Deletes the ref `name` if it exists, replacing any earlier change to it in this
+    /// transaction.
+    pub fn delete(&mut self, name: &str) {
+        self.changes.insert(name.to_owned(), None);
+    }
+
+    /// Applies the changes. Every name (and symbolic target) is checked first, then every
+    /// updated ref is locked and written before any of them is replaced. Deletions happen
+    /// once all of the updates are in place.
+    pub fn commit(self, wd: &WorkDir) -> Result<()> {
+        wd.check_writable()?;
+
+        for (name, target) in &self.changes {
+            check_name(name)?;
+            if let Some(RefTarget::Symbolic(target)) = target {
+                check_name(target)?;
+            }
+        }
+
+        let updates: Vec<(PathBuf, Vec<u8>)> = self.changes.iter()
+            .filter_map(|(name, target)| {
+                let contents = match target.as_ref()? {
+                    RefTarget::Direct(hash) => format!("{hash}\n"),
+                    RefTarget::Symbolic(target) => format!("{SYMREF_PREFIX}{target}\n"),
+                };
+                Some((namespaced_path(wd, Path::new(name)), contents.into_bytes()))
+            })
+            .collect();
+        wd.write_git_files(updates.iter().map(|(path, data)| (path.as_path(), data.as_slice())))?;
+
+        for (name, _) in self.changes.iter().filter(|(_, target)| target.is_none()) {
+            let abs_path = wd.git_path(namespaced_path(wd, Path::new(name)));
+            if abs_path.is_file() {
+                fs::remove_file(abs_path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Fails unless `name` is `HEAD` or a full ref name under `refs/` that git would accept.
+pub fn check_name(name: &str) -> Result<()> {
+    let is_valid_component = |component: &str| !component.is_empty()
+        && !component.starts_with('.')
+        && !component.ends_with(".lock")
+        && !component.contains("..")
+        && !component.contains("@{")
+        && !component.contains(|c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c));
+
+    let is_valid = name == HEAD || name.strip_prefix("refs/")
+        .is_some_and(|rest| !rest.ends_with('.') && rest.split('/').all(is_valid_component));
+
+    match is_valid {
+        true => Ok(()),
+        false => Err(RefError::InvalidName(name.to_owned()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{object::ObjectHash, test_util::temp_workdir};
+
+    #[test]
+    fn commit_writes_and_deletes_refs() {
+        let (temp_dir, wd) = temp_workdir();
+        let hash = ObjectHash::new("a");
+        fs::create_dir_all(temp_dir.join(".git/refs/heads")).unwrap();
+        fs::write(temp_dir.join(".git/refs/heads/old"), format!("{hash}\n")).unwrap();
+
+        let mut transaction = RefTransaction::new();
+        transaction.update("refs/heads/new", RefTarget::Direct(hash));
+        transaction.update("HEAD", RefTarget::Symbolic("refs/heads/new".to_owned()));
+        transaction.delete("refs/heads/old");
+        transaction.commit(&wd).unwrap();
+
+        assert_eq!(crate::refs::resolve_path(&wd, "HEAD").unwrap(), hash);
+        assert!(!temp_dir.join(".git/refs/heads/old").exists());
+    }
+
+    #[test]
+    fn commit_changes_nothing_if_a_ref_is_locked() {
+        let (temp_dir, wd) = temp_workdir();
+        fs::create_dir_all(temp_dir.join(".git/refs/heads")).unwrap();
+        fs::write(temp_dir.join(".git/refs/heads/b.lock"), "").unwrap();
+
+        let mut transaction = RefTransaction::new();
+        transaction.update("refs/heads/a", RefTarget::Direct(ObjectHash::new("a")));
+        transaction.update("refs/heads/b", RefTarget::Direct(ObjectHash::new("b")));
+        assert!(transaction.commit(&wd).is_err());
+
+        assert!(!temp_dir.join(".git/refs/heads/a").exists());
+        assert!(!temp_dir.join(".git/refs/heads/a.lock").exists());
+    }
+
+    #[test]
+    fn check_name_rejects_invalid_names() {
+        for name in ["HEAD", "refs/heads/main", "refs/tags/v1.0", "refs/heads/feature/x"] {
+            assert!(check_name(name).is_ok(), "{name}");
+        }
+        for name in ["main", "refs/heads/", "refs/heads/a..b", "refs/heads/.hidden", "refs/heads/a.lock", "refs/heads/a b", "refs//x", "ORIG_HEAD"] {
+            assert!(check_name(name).is_err(), "{name}");
+        }
+    }
+}
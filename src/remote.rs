@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+use path_absolutize::Absolutize;
+use thiserror::Error;
+
+use crate::{Result, workdir::WorkDir};
+
+/// Where a remote repository is, as given by `remote.<name>.url` or on the command line.
+#[derive(PartialEq, Eq, Debug)]
+pub enum RemoteLocation {
+    /// A repository on this machine, from a `file://` URL or a plain path. The path is
+    /// absolute and normalized.
+    Local(PathBuf),
+    /// A repository that can only be reached over the network, e.g. with `https://` or
+    /// `host:path` (ssh). These aren't supported yet.
+    Network(String),
+}
+
+impl RemoteLocation {
+    /// Interprets `url` for the repository at `wd`. As in git, a relative path is relative
+    /// to the top of the working directory, so `../sibling` names a repository next to it.
+    pub fn parse(wd: &WorkDir, url: &str) -> Result<Self> {
+        if let Some((scheme, rest)) = url.split_once("://") {
+            if !scheme.eq_ignore_ascii_case("file") {
+                return Ok(Self::Network(url.to_owned()));
+            }
+
+            // `file:///path`, `file://localhost/path`, or `file:///C:/path` on Windows
+            let host_len = rest.find('/').unwrap_or(rest.len());
+            let (host, path) = rest.split_at(host_len);
+            if !host.is_empty() && !host.eq_ignore_ascii_case("localhost") {
+                return Err(RemoteError::FileUrlHost(url.to_owned()).into());
+            }
+            let path = match path.strip_prefix('/') {
+                Some(drive_path) if has_drive_letter(drive_path) => drive_path,
+                _ => path,
+            };
+            return Self::local(wd, Path::new(path));
+        }
+
+        if is_scp_like(url) {
+            return Ok(Self::Network(url.to_owned()));
+        }
+
+        Self::local(wd, Path::new(url))
+    }
+
+    fn local(wd: &WorkDir, path: &Path) -> Result<Self> {
+        let path = path.absolutize_from(wd.as_path())?;
+
+        Ok(Self::Local(path.into_owned()))
+    }
+}
+
+/// Returns true for ssh's `[user@]host:path` syntax. A colon only counts if no slash comes
+/// before it, and a single letter before it is a Windows drive instead.
+fn is_scp_like(url: &str) -> bool {
+    match url.find(':') {
+        Some(colon) => !url[..colon].contains(['/', '\\']) && !has_drive_letter(url),
+        None => false,
+    }
+}
+
+/// Returns true if `path` starts with a Windows drive like `C:`.
+fn has_drive_letter(path: &str) -> bool {
+    let bytes = path.as_bytes();
+
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+#[derive(Error, Debug)]
+pub enum RemoteError {
+    #[error("`{0}` names another machine, but file:// URLs can only name this one (with no host or `localhost`)")]
+    FileUrlHost(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_local_urls() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let wd = WorkDir::new(temp_dir.join("repo")).unwrap();
+        let sibling = temp_dir.join("sibling");
+
+        let parse = |url: &str| RemoteLocation::parse(&wd, url).unwrap();
+        assert_eq!(parse("../sibling"), RemoteLocation::Local(sibling.clone()));
+        assert_eq!(parse("../repo/../sibling/"), RemoteLocation::Local(sibling.clone()));
+        assert_eq!(parse(sibling.to_str().unwrap()), RemoteLocation::Local(sibling.clone()));
+        assert_eq!(parse(&format!("file://{}", sibling.display())), RemoteLocation::Local(sibling.clone()));
+        assert_eq!(parse(&format!("file://localhost{}", sibling.display())), RemoteLocation::Local(sibling));
+
+        for url in ["https://example.com/repo.git", "git@example.com:repo.git", "example.com:repo"] {
+            assert_eq!(parse(url), RemoteLocation::Network(url.to_owned()));
+        }
+    }
+
+    #[test]
+    fn rejects_file_urls_with_other_hosts() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let wd = WorkDir::new(temp_dir.join("repo")).unwrap();
+
+        for url in ["file://example.com/repo", "file://localhost.example.com/repo", "file://relative"] {
+            let err = RemoteLocation::parse(&wd, url).unwrap_err();
+            assert!(matches!(err.downcast_ref(), Some(RemoteError::FileUrlHost(_))), "{url}");
+        }
+        assert!(RemoteLocation::parse(&wd, "FILE://LOCALHOST/repo").is_ok());
+    }
+}
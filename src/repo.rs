@@ -5,34 +5,85 @@ use std::{
 };
 use anyhow::Context;
 use ini::Ini;
+use memmap2::Mmap;
 use path_absolutize::Absolutize;
 use thiserror::Error;
 
 use crate::{
     Result,
-    workdir::WorkDir,
-    index::Index,
+    workdir::{WorkDir, Settings},
+    consts::{HEAD, MERGE_HEAD, CHERRY_PICK_HEAD, REVERT_HEAD},
+    index::{Index, SplitIndex},
     branch,
+    date,
+    refs,
+    messages::Message,
+    sequencer::{self, Sequencer},
+    summary::RepoStats,
 };
 
+mod lock;
+pub use lock::{RepoLock, REPO_LOCK};
+
+mod safe_directory;
+
+mod integrity;
+pub use integrity::Diagnostic;
+
 /// A Git repository.
 pub struct Repository {
     workdir: WorkDir,
     config: Ini,
+    /// The per-worktree config in `config.worktree`, which takes precedence over `config`.
+    /// Only read if `extensions.worktreeConfig` is set.
+    worktree_config: Option<Ini>,
 }
 
 impl Repository {
+    /// The default value of `gc.auto`, same as git.
+    pub const DEFAULT_GC_AUTO: u64 = 6700;
+    /// The default value of `gc.autoPackLimit`, same as git.
+    pub const DEFAULT_GC_AUTO_PACK_LIMIT: u64 = 50;
+
     /// Initializes a new git repository in an empty directory.
     pub fn init<P>(dir: P) -> Result<Repository>
     where
         P: AsRef<Path>
     {
-        let repo = {
-            if !WorkDir::is_valid_path(&dir)? {
-                return Err(RepoError::InitPathExists(dir.as_ref().to_owned()).into());
+        if !WorkDir::is_valid_path(&dir)? {
+            return Err(RepoError::InitPathExists(dir.as_ref().to_owned()).into());
+        }
+
+        Repository::init_in(WorkDir::new(dir)?)
+    }
+
+    /// Initializes a new git repository in an empty directory, with its git directory at
+    /// `git_dir` instead of in `dir`. `dir` gets a `.git` file that points to it, which
+    /// [`Repository::find`] follows.
+    pub fn init_separate<P, Q>(dir: P, git_dir: Q) -> Result<Repository>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        for path in [dir.as_ref(), git_dir.as_ref()] {
+            if !WorkDir::is_valid_path(path)? {
+                return Err(RepoError::InitPathExists(path.to_owned()).into());
             }
-            let workdir = WorkDir::new(dir)?;
-            
+        }
+
+        let repo = Repository::init_in(WorkDir::with_git_dir(dir, git_dir)?)?;
+        fs::create_dir_all(repo.workdir.as_path())?;
+        fs::write(
+            repo.workdir.as_path().join(".git"),
+            format!("gitdir: {}\n", repo.workdir.git_dir().display()),
+        )?;
+
+        Ok(repo)
+    }
+
+    /// Creates the git directory and its initial files for a new repository.
+    fn init_in(workdir: WorkDir) -> Result<Repository> {
+        let repo = {
             // Initialize config
             let mut config = Ini::new();
             config.with_section(Some("core"))
@@ -43,11 +94,13 @@ impl Repository {
             Repository {
                 workdir,
                 config,
+                worktree_config: None,
             }
         };
         
         // Create directories
-        fs::create_dir_all(repo.workdir.git_path("."))?;
+        // Not `git_path(".")`: creating a path that ends in `.` fails if its parent is missing
+        fs::create_dir_all(repo.workdir.git_path(""))?;
         repo.workdir.make_git_dir("objects")?;
         repo.workdir.make_git_dir("refs/tags")?;
         repo.workdir.make_git_dir("refs/heads")?;
@@ -62,7 +115,7 @@ impl Repository {
             repo.workdir.open_git_file("description", Some(&options))?
                 .write_all(b"Unnamed repository; edit this file 'description' to name the repository.\n")?;
 
-            repo.workdir.open_git_file("HEAD", Some(&options))?
+            repo.workdir.open_git_file(HEAD, Some(&options))?
                 .write_all(b"ref: refs/heads/master\n")?;
 
             let mut config_file = repo.workdir.open_git_file("config", Some(&options))?;
@@ -80,7 +133,13 @@ impl Repository {
         if !dir.as_ref().is_dir() {
             return Err(RepoError::UninitializedDirectory(dir.as_ref().to_owned()).into());
         }
-        let workdir = WorkDir::new(dir)?;
+        let git_file = dir.as_ref().join(".git");
+        let workdir = match git_file.is_file() {
+            true => WorkDir::with_git_dir(&dir, read_git_file(&git_file)?)?,
+            false => WorkDir::new(dir)?,
+        };
+        // Before reading its config, which could run another user's commands
+        safe_directory::check(&workdir)?;
 
         let config_file = workdir.git_path("config");
         let config = Ini::load_from_file(config_file)?;
@@ -91,10 +150,61 @@ impl Repository {
             None => return Err(RepoError::FmtVersionMissing.into()),
         };
 
-        Ok(Repository {
+        let mut repo = Repository {
             workdir,
             config,
-        })
+            worktree_config: None,
+        };
+        // `git sparse-checkout` turns this on and keeps its settings in config.worktree
+        if repo.get_config_bool("extensions", "worktreeConfig") == Some(true) {
+            let worktree_config_file = repo.workdir.git_path("config.worktree");
+            if worktree_config_file.is_file() {
+                repo.worktree_config = Some(Ini::load_from_file(worktree_config_file)?);
+            }
+        }
+        repo.load_settings()?;
+
+        Ok(repo)
+    }
+
+    /// Applies config values (and environment variables) that affect how the working
+    /// directory is handled.
+    fn load_settings(&mut self) -> Result<()> {
+        let mut settings = Settings::default();
+
+        if let Some(threshold) = self.get_config_int("core", "bigFileThreshold")? {
+            settings.big_file_threshold = threshold;
+        }
+        settings.ignore_case = self.get_config_bool("core", "ignoreCase").unwrap_or(false);
+        settings.fsync_object_files = self.get_config_bool("core", "fsyncObjectFiles").unwrap_or(false);
+        settings.trust_ctime = self.get_config_bool("core", "trustctime").unwrap_or(true);
+        settings.protect_ntfs = self.get_config_bool("core", "protectNTFS").unwrap_or(settings.protect_ntfs);
+        settings.symlinks = cfg!(unix) && self.get_config_bool("core", "symlinks").unwrap_or(true);
+        settings.sparse_checkout = self.get_config_bool("core", "sparseCheckout").unwrap_or(false);
+        settings.sparse_checkout_cone = self.get_config_bool("core", "sparseCheckoutCone").unwrap_or(false);
+        settings.shared_index_expire = match self.get_config("splitIndex", "sharedIndexExpire") {
+            Some(expire) => Some(date::parse_expiry(expire, date::now())
+                .with_context(|| format!("Invalid value `{expire}` for splitIndex.sharedIndexExpire"))?),
+            None => None,
+        };
+        settings.minimal_stat_check = match self.get_config("core", "checkStat") {
+            Some(value) => value.eq_ignore_ascii_case("minimal"),
+            None => false,
+        };
+
+        settings.namespace = std::env::var("GIT_NAMESPACE").ok()
+            .filter(|namespace| !namespace.is_empty());
+        if let Some(namespace) = &settings.namespace {
+            // Each component becomes part of a ref's path, so it has to be valid in one
+            for component in namespace.split('/').filter(|component| !component.is_empty()) {
+                refs::check_name(&format!("refs/namespaces/{component}"))
+                    .with_context(|| format!("Invalid GIT_NAMESPACE `{namespace}`"))?;
+            }
+        }
+
+        self.workdir.set_settings(settings);
+
+        Ok(())
     }
 
     /// Finds the git repository that contains `path` (if it exists).
@@ -104,9 +214,10 @@ impl Repository {
     {
         let abs_path = path.as_ref().absolutize()?;
 
-        // The existence of a .git directory is considered sufficient
-        // evidence of a repository
-        if abs_path.join(".git").is_dir() {
+        // The existence of a .git directory (or a .git file pointing to one)
+        // is considered sufficient evidence of a repository
+        let git_path = abs_path.join(".git");
+        if git_path.is_dir() || git_path.is_file() {
             return Repository::from_existing(&abs_path);
         }
 
@@ -120,6 +231,44 @@ impl Repository {
         }
     }
 
+    /// Finds the repository that contains `path`, as with [`Repository::find`], but opens it
+    /// read-only. Writing objects, refs, the index, or files in the working directory fails
+    /// with [`WorkDirError::ReadOnly`](crate::workdir::WorkDirError::ReadOnly), so the
+    /// repository can be inspected without any risk of changing it.
+    pub fn find_read_only<P>(path: P) -> Result<Repository>
+    where
+        P: AsRef<Path>
+    {
+        let mut repo = Repository::find(path)?;
+        let mut settings = repo.workdir.settings().clone();
+        settings.read_only = true;
+        repo.workdir.set_settings(settings);
+
+        Ok(repo)
+    }
+
+    /// Finds the repository that contains `path`, as with [`Repository::find`], but fails
+    /// with [`RepoError::IntegrityCheckFailed`] if [`Repository::check_integrity`] finds any
+    /// problems, as with `wyag --strict`.
+    pub fn find_strict<P>(path: P) -> Result<Repository>
+    where
+        P: AsRef<Path>
+    {
+        let repo = Repository::find(path)?;
+        let diagnostics = repo.check_integrity()?;
+        if !diagnostics.is_empty() {
+            return Err(RepoError::IntegrityCheckFailed(diagnostics).into());
+        }
+
+        Ok(repo)
+    }
+
+    /// Counts the repository's objects, packs, refs, index entries, and working directory
+    /// files.
+    pub fn stats(&self) -> Result<RepoStats> {
+        RepoStats::collect(self)
+    }
+
     /// Parses (or creates) the repo's index.
     pub fn index(&self) -> Result<Index> {
         let index_path = self.workdir.git_path("index");
@@ -127,9 +276,18 @@ impl Repository {
         if index_path.is_file() {
             let file = std::fs::File::open(&index_path)
                 .with_context(|| format!("Failed to open index file at `{index_path:?}`"))?;
-            let mut reader = std::io::BufReader::new(file);
-    
-            Index::parse(&mut reader)
+
+            // Safety: the map is only read while parsing, and the resulting `Index`
+            // owns all of its data. Concurrent modification of the index file by
+            // another process is undefined behavior, which is also true of git.
+            let data = unsafe { Mmap::map(&file) }
+                .with_context(|| format!("Failed to map index file at `{index_path:?}`"))?;
+
+            let mut index = Index::parse(&data)?;
+            index.load_shared(&self.workdir)?;
+            self.apply_split_config(&mut index);
+
+            Ok(index)
         }
         else if branch::get_current(&self.workdir)?
             .tip(&self.workdir)?
@@ -142,23 +300,212 @@ impl Repository {
         else {
             // Repo was just created and there are no commits yet
             // Create an empty index
-            Ok(Index::new(None))
+            let mut index = Index::new(None);
+            self.apply_split_config(&mut index);
+
+            Ok(index)
+        }
+    }
+
+    /// Switches the index into or out of split mode according to `core.splitIndex`.
+    /// If the setting is absent, the index is left in whichever mode it was written.
+    fn apply_split_config(&self, index: &mut Index) {
+        match self.get_config_bool("core", "splitIndex") {
+            Some(true) if index.split.is_none() => index.split = Some(SplitIndex::new()),
+            Some(false) => index.split = None,
+            _ => (),
         }
     }
 
+    /// Looks up a config value, preferring the per-worktree config if there is one. Keys
+    /// are case-insensitive, as in git.
     pub fn get_config(&self, section: &str, key: &str) -> Option<&str> {
         // TODO support global config
-        self.config.get_from(Some(section), key)
+        self.worktree_config.as_ref()
+            .and_then(|config| lookup_config(config, section, key))
+            .or_else(|| lookup_config(&self.config, section, key))
+    }
+
+    /// Looks up a config value and interprets it as a boolean. Returns `None` if the
+    /// key is missing or the value is not a recognized boolean.
+    pub fn get_config_bool(&self, section: &str, key: &str) -> Option<bool> {
+        match self.get_config(section, key)?.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" | "" => Some(true),
+            "false" | "no" | "off" | "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Looks up a config value and interprets it as a non-negative integer. As in git,
+    /// the value may have a suffix of `k`, `m`, or `g` to scale it by 1024, 1024^2,
+    /// or 1024^3.
+    pub fn get_config_int(&self, section: &str, key: &str) -> Result<Option<u64>> {
+        let value = match self.get_config(section, key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let invalid = || RepoError::InvalidConfigValue {
+            key: format!("{section}.{key}"),
+            value: value.to_owned(),
+        };
+
+        let (digits, scale) = match value.chars().last().map(|ch| ch.to_ascii_lowercase()) {
+            Some('k') => (&value[..value.len() - 1], 1 << 10),
+            Some('m') => (&value[..value.len() - 1], 1 << 20),
+            Some('g') => (&value[..value.len() - 1], 1 << 30),
+            _ => (value, 1),
+        };
+
+        let number: u64 = digits.trim().parse().map_err(|_| invalid())?;
+        let number = number.checked_mul(scale).ok_or_else(invalid)?;
+
+        Ok(Some(number))
     }
 
     pub fn set_config(&mut self, section: &str, key: &str, value: String) {
+        // Otherwise a value in config.worktree would still win
+        if let Some(worktree_config) = &mut self.worktree_config {
+            worktree_config.delete_from(Some(section), key);
+        }
         self.config.set_to(Some(section), key.to_owned(), value)
     }
 
+    /// Takes the repository lock for a command that changes refs, the index, or the working
+    /// tree, waiting for another command to finish if needed. See [`RepoLock`].
+    pub fn lock(&self) -> Result<RepoLock> {
+        RepoLock::acquire(&self.workdir, RepoLock::TIMEOUT)
+    }
+
     pub fn workdir(&self) -> &WorkDir {
         &self.workdir
     }
 
+    /// Returns true if the repo has accumulated enough loose objects (`gc.auto`) or packs
+    /// (`gc.autoPackLimit`) that git's `gc --auto` would repack it.
+    /// 
+    /// Like git, the loose object count is estimated from the `objects/17` directory alone.
+    pub fn needs_gc(&self) -> Result<bool> {
+        let loose_limit = self.get_config_int("gc", "auto")?.unwrap_or(Self::DEFAULT_GC_AUTO);
+        let pack_limit = self.get_config_int("gc", "autoPackLimit")?.unwrap_or(Self::DEFAULT_GC_AUTO_PACK_LIMIT);
+        if loose_limit == 0 {
+            return Ok(false);
+        }
+
+        let sample_limit = loose_limit.div_ceil(256);
+        let sample_count = count_files(&self.workdir.git_path("objects/17"), |name| name.len() == 38)?;
+        if sample_count > sample_limit {
+            return Ok(true);
+        }
+
+        let pack_count = count_files(&self.workdir.git_path("objects/pack"), |name| name.ends_with(".pack"))?;
+
+        Ok(pack_limit > 0 && pack_count > pack_limit)
+    }
+}
+
+/// An operation that was started (by git or wyag) but stopped partway through, usually
+/// to let the user resolve conflicts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InProgress {
+    Merge,
+    Rebase,
+    CherryPick,
+    Revert,
+    Bisect,
+}
+
+impl Repository {
+    /// Detects the operations in progress by checking for their state files.
+    pub fn in_progress(&self) -> Result<Vec<InProgress>> {
+        let exists = |name: &str| self.workdir.git_path(name).exists();
+        let mut operations = Vec::new();
+
+        if exists(MERGE_HEAD) {
+            operations.push(InProgress::Merge);
+        }
+        if exists("rebase-merge") || exists("rebase-apply") {
+            operations.push(InProgress::Rebase);
+        }
+        if exists(CHERRY_PICK_HEAD) {
+            operations.push(InProgress::CherryPick);
+        }
+        if exists(REVERT_HEAD) {
+            operations.push(InProgress::Revert);
+        }
+        if exists("BISECT_LOG") {
+            operations.push(InProgress::Bisect);
+        }
+
+        if let Some(sequencer) = Sequencer::load(&self.workdir)? {
+            let operation = match sequencer.operation {
+                sequencer::Operation::CherryPick => InProgress::CherryPick,
+                sequencer::Operation::Revert => InProgress::Revert,
+                sequencer::Operation::Rebase => InProgress::Rebase,
+            };
+            if !operations.contains(&operation) {
+                operations.push(operation);
+            }
+        }
+
+        Ok(operations)
+    }
+}
+
+impl InProgress {
+    /// Describes the operation, e.g. `You are currently merging.`
+    pub fn description(&self) -> Message<'static> {
+        Message::InProgress(*self)
+    }
+
+    /// Suggests how to proceed, depending on whether there are unresolved conflicts.
+    pub fn hints(&self, has_conflicts: bool) -> Vec<Message<'static>> {
+        let mut hints = Vec::new();
+
+        if *self != Self::Bisect {
+            hints.push(Message::ContinueHint { operation: *self, has_conflicts });
+        }
+        hints.push(Message::AbortHint(*self));
+
+        hints
+    }
+}
+
+/// Counts the files in the directory at `path` whose names satisfy `predicate`. A missing
+/// directory has no files.
+fn count_files<F>(path: &Path, predicate: F) -> Result<u64>
+where
+    F: Fn(&str) -> bool
+{
+    if !path.is_dir() {
+        return Ok(0);
+    }
+
+    let mut count = 0;
+    for entry in fs::read_dir(path)? {
+        if predicate(&entry?.file_name().to_string_lossy()) {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Reads a `.git` file (`gitdir: <path>`) and returns the git directory it points to. A
+/// relative path is relative to the directory containing the file.
+fn read_git_file(path: &Path) -> Result<PathBuf> {
+    let contents = fs::read_to_string(path)?;
+    let Some(git_dir) = contents.trim_end().strip_prefix("gitdir: ") else {
+        return Err(RepoError::InvalidGitFile(path.to_owned()).into());
+    };
+    let git_dir = path.parent().unwrap_or(Path::new("")).join(git_dir);
+
+    // A linked worktree's git directory only has its own HEAD and index
+    if git_dir.join("commondir").exists() {
+        return Err(RepoError::LinkedWorktree(path.to_owned()).into());
+    }
+
+    Ok(git_dir)
 }
 
 #[derive(Error, Debug)]
@@ -167,10 +514,90 @@ pub enum RepoError {
     InitPathExists(PathBuf),
     #[error("No git repo contains `{0:?}`")]
     UninitializedDirectory(PathBuf),
+    #[error("`{0:?}` is not a valid .git file (expected `gitdir: <path>`)")]
+    InvalidGitFile(PathBuf),
+    #[error("`{0:?}` belongs to a linked worktree, which can't be opened directly")]
+    LinkedWorktree(PathBuf),
     #[error("No repo format version was specified")]
     FmtVersionMissing,
     #[error("Repo format version `{0}` is not supported")]
     FmtVersionUnsupported(String),
     #[error("The index file is missing")]
     IndexMissing,
+    #[error(
+        "Detected dubious ownership in the repository at `{}`. To trust it anyway, run:\n\n    git config --global --add safe.directory {}",
+        .0.display(), .0.display()
+    )]
+    DubiousOwnership(PathBuf),
+    #[error("Another command is changing the repository (`{0:?}` exists). If none is running, remove the file and try again.")]
+    Locked(PathBuf),
+    #[error("Invalid value `{value}` for config key `{key}`")]
+    InvalidConfigValue {
+        key: String,
+        value: String,
+    },
+    #[error("The repository failed its integrity check:{}", .0.iter().map(|diagnostic| format!("\n  {diagnostic}")).collect::<String>())]
+    IntegrityCheckFailed(Vec<Diagnostic>),
+}
+
+fn lookup_config<'a>(config: &'a Ini, section: &str, key: &str) -> Option<&'a str> {
+    config.section(Some(section))?
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn finds_repo_with_separate_git_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let work_path = temp_dir.join("work");
+        let git_path = temp_dir.join("repo.git");
+        Repository::init_separate(&work_path, &git_path).unwrap();
+
+        assert!(git_path.join("objects").is_dir());
+        assert!(work_path.join(".git").is_file());
+
+        fs::create_dir(work_path.join("src")).unwrap();
+        let repo = Repository::find(work_path.join("src")).unwrap();
+        assert_eq!(repo.workdir().as_path(), work_path);
+        assert_eq!(repo.workdir().git_path("HEAD"), git_path.join("HEAD"));
+    }
+
+    #[test]
+    fn reads_worktree_config() {
+        let temp_dir = TempDir::new().unwrap();
+        Repository::init(temp_dir.path()).unwrap();
+        // The layout `git sparse-checkout set` leaves behind
+        let mut config = fs::read_to_string(temp_dir.join(".git/config")).unwrap();
+        config.push_str("[extensions]\n\tworktreeConfig = true\n");
+        fs::write(temp_dir.join(".git/config"), config).unwrap();
+        fs::write(temp_dir.join(".git/config.worktree"), "[core]\n\tsparseCheckout = true\n\tsparseCheckoutCone = true\n\tfilemode = true\n").unwrap();
+
+        let repo = Repository::from_existing(temp_dir.path()).unwrap();
+        assert!(repo.workdir().settings().sparse_checkout);
+        assert!(repo.workdir().settings().sparse_checkout_cone);
+        assert_eq!(repo.get_config("core", "filemode"), Some("true"));
+        assert_eq!(repo.get_config("core", "bare"), Some("false"));
+
+        // Without the extension, config.worktree is ignored
+        let config = fs::read_to_string(temp_dir.join(".git/config")).unwrap();
+        fs::write(temp_dir.join(".git/config"), config.replace("worktreeConfig = true", "worktreeConfig = false")).unwrap();
+        let repo = Repository::from_existing(temp_dir.path()).unwrap();
+        assert!(!repo.workdir().settings().sparse_checkout);
+    }
+
+    #[test]
+    fn rejects_invalid_git_file() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.join(".git"), "not a gitdir\n").unwrap();
+
+        let err = Repository::from_existing(temp_dir.path()).err().unwrap();
+        assert!(matches!(err.downcast_ref(), Some(RepoError::InvalidGitFile(_))));
+    }
 }
@@ -0,0 +1,194 @@
+//! Checks that a repository is in a usable state before a command runs, for callers like
+//! backup tools that would rather stop than work with a damaged repository. See
+//! [`Repository::check_integrity`].
+
+use std::{fmt, fs};
+
+use crate::{
+    Result,
+    branch::{self, Branch},
+    consts::HEAD,
+    index::Index,
+    object::{ObjectFormat, ObjectHash, ObjectStore},
+};
+
+use super::Repository;
+
+/// Config keys in the `core` section that must be booleans if present.
+const BOOL_KEYS: &[&str] = &[
+    "bare",
+    "filemode",
+    "fsyncObjectFiles",
+    "ignoreCase",
+    "protectNTFS",
+    "sparseCheckout",
+    "sparseCheckoutCone",
+    "splitIndex",
+    "symlinks",
+    "trustctime",
+];
+
+/// A problem found by [`Repository::check_integrity`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Diagnostic {
+    /// HEAD is missing or isn't a ref or a commit hash.
+    InvalidHead(String),
+    /// HEAD, or the branch it's on, points to an object that is missing or isn't a commit.
+    BadHeadTarget {
+        name: String,
+        hash: ObjectHash,
+    },
+    /// The index file can't be read or parsed.
+    InvalidIndex(String),
+    /// The checksum at the end of the index file doesn't match its contents.
+    IndexChecksumMismatch {
+        stored: ObjectHash,
+        actual: ObjectHash,
+    },
+    /// A `core` config value isn't valid for its key.
+    InvalidConfig {
+        key: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHead(reason) => write!(f, "HEAD is invalid: {reason}"),
+            Self::BadHeadTarget { name, hash } => write!(f, "{name} points to {hash}, which is not a commit in the repository"),
+            Self::InvalidIndex(reason) => write!(f, "The index is invalid: {reason}"),
+            Self::IndexChecksumMismatch { stored, actual } => write!(f, "The index checksum is {stored}, but its contents hash to {actual}"),
+            Self::InvalidConfig { key, value } => write!(f, "Invalid value `{value}` for config key `{key}`"),
+        }
+    }
+}
+
+impl Repository {
+    /// Checks that HEAD points to a valid ref or commit, that the index parses and its
+    /// checksum (if it has one) matches, and that the `core` config values are valid.
+    /// Returns every problem found, or an empty list if there are none. Errors are only
+    /// returned for failures to read the repository that aren't problems with it.
+    pub fn check_integrity(&self) -> Result<Vec<Diagnostic>> {
+        let mut diagnostics = vec![];
+        self.check_head(&mut diagnostics)?;
+        self.check_index(&mut diagnostics);
+        self.check_config(&mut diagnostics);
+
+        Ok(diagnostics)
+    }
+
+    fn check_head(&self, diagnostics: &mut Vec<Diagnostic>) -> Result<()> {
+        let wd = self.workdir();
+        let branch = match branch::get_current(wd) {
+            Ok(branch) => branch,
+            Err(err) => {
+                diagnostics.push(Diagnostic::InvalidHead(format!("{err:#}")));
+                return Ok(());
+            },
+        };
+
+        let name = match &branch {
+            Branch::Named(name) => name.clone(),
+            Branch::Ref(ref_path) => ref_path.clone(),
+            Branch::Headless(_) | Branch::Unborn(_) => HEAD.to_owned(),
+        };
+        let tip = match branch.tip(wd) {
+            Ok(Some(tip)) => tip,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                diagnostics.push(Diagnostic::InvalidHead(format!("{err:#}")));
+                return Ok(());
+            },
+        };
+
+        // Packed objects can't be read, so their type is taken on trust
+        let store = ObjectStore::open(wd)?;
+        let is_commit = store.contains(&tip) && store.read_header(&tip)
+            .map_or(true, |(format, _)| format == ObjectFormat::Commit);
+        if !is_commit {
+            diagnostics.push(Diagnostic::BadHeadTarget { name, hash: tip });
+        }
+
+        Ok(())
+    }
+
+    fn check_index(&self, diagnostics: &mut Vec<Diagnostic>) {
+        let index_path = self.workdir().git_path("index");
+        if !index_path.is_file() {
+            return;
+        }
+
+        let checksum = fs::read(&index_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Index::checksum(&data));
+        match checksum {
+            Ok(Some((stored, actual))) if stored != actual => {
+                diagnostics.push(Diagnostic::IndexChecksumMismatch { stored, actual });
+            },
+            Ok(_) => (),
+            Err(err) => diagnostics.push(Diagnostic::InvalidIndex(format!("{err:#}"))),
+        }
+    }
+
+    fn check_config(&self, diagnostics: &mut Vec<Diagnostic>) {
+        let mut invalid = |key: &str, value: &str| diagnostics.push(Diagnostic::InvalidConfig {
+            key: format!("core.{key}"),
+            value: value.to_owned(),
+        });
+
+        for key in BOOL_KEYS {
+            if let Some(value) = self.get_config("core", key) {
+                if self.get_config_bool("core", key).is_none() {
+                    invalid(key, value);
+                }
+            }
+        }
+
+        if let Some(value) = self.get_config("core", "bigFileThreshold") {
+            if self.get_config_int("core", "bigFileThreshold").is_err() {
+                invalid("bigFileThreshold", value);
+            }
+        }
+
+        if let Some(value) = self.get_config("core", "checkStat") {
+            if !["default", "minimal"].iter().any(|valid| value.eq_ignore_ascii_case(valid)) {
+                invalid("checkStat", value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn reports_bad_head_index_and_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut repo = Repository::init(temp_dir.path()).unwrap();
+        assert_eq!(repo.check_integrity().unwrap(), []);
+
+        let missing = ObjectHash::new("missing");
+        fs::write(temp_dir.join(".git/HEAD"), format!("{missing}\n")).unwrap();
+        let mut index = Index::new(None).serialize().unwrap();
+        index.extend([0xff; 20]);
+        fs::write(temp_dir.join(".git/index"), &index).unwrap();
+        repo.set_config("core", "trustctime", "sometimes".to_owned());
+
+        let diagnostics = repo.check_integrity().unwrap();
+        assert_eq!(diagnostics, [
+            Diagnostic::BadHeadTarget { name: "HEAD".to_owned(), hash: missing },
+            Diagnostic::IndexChecksumMismatch {
+                stored: ObjectHash { raw: [0xff; 20] },
+                actual: ObjectHash::new(&index[..index.len() - 20]),
+            },
+            Diagnostic::InvalidConfig { key: "core.trustctime".to_owned(), value: "sometimes".to_owned() },
+        ]);
+
+        fs::write(temp_dir.join(".git/HEAD"), "ref: HEAD\n").unwrap();
+        assert!(matches!(repo.check_integrity().unwrap()[0], Diagnostic::InvalidHead(_)));
+    }
+}
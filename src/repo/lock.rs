@@ -0,0 +1,144 @@
+//! A lock on the whole repository. Commands that change refs, the index, and the working
+//! tree together (e.g. `commit` and `switch`) hold it, so that two of them take turns
+//! instead of interleaving their writes. Commands that only read don't take it. A lock left
+//! behind by a process that no longer exists (e.g. one that was killed) is broken.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Result,
+    workdir::WorkDir,
+};
+
+use super::RepoError;
+
+/// The lock file in `.git`, which holds the ID of the process that took the lock.
+pub const REPO_LOCK: &str = "wyag.lock";
+
+/// Holds the repository lock until it's dropped.
+#[derive(Debug)]
+pub struct RepoLock {
+    path: PathBuf,
+}
+
+impl RepoLock {
+    /// How long to wait for another command to release the lock.
+    pub const TIMEOUT: Duration = Duration::from_secs(10);
+    const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Takes the lock, waiting up to `timeout` if another process has it.
+    pub(super) fn acquire(workdir: &WorkDir, timeout: Duration) -> Result<Self> {
+        workdir.check_writable()?;
+
+        let path = workdir.git_path(REPO_LOCK);
+        let start = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let lock = Self { path };
+                    writeln!(file, "{}", std::process::id())?;
+                    return Ok(lock);
+                },
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    if is_stale(&path) {
+                        match fs::remove_file(&path) {
+                            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                            _ => continue,
+                        }
+                    }
+                    if start.elapsed() >= timeout {
+                        return Err(RepoError::Locked(path).into());
+                    }
+                    thread::sleep(Self::RETRY_INTERVAL);
+                },
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Whether the lock file at `path` was left by a process that has since exited.
+fn is_stale(path: &Path) -> bool {
+    match fs::read_to_string(path).map(|contents| contents.trim().parse::<u32>()) {
+        Ok(Ok(pid)) => !process_exists(pid),
+        // The owner may not have written its ID yet
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return true;
+    };
+    // Signal 0 only checks whether the process could be signaled. EPERM means it exists
+    // but belongs to someone else.
+    let signaled = unsafe { libc::kill(pid, 0) == 0 };
+    signaled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// Without a way to check, every lock is assumed to be held
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    true
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_workdir;
+
+    #[test]
+    fn waits_for_the_lock() {
+        let (temp_dir, wd) = temp_workdir();
+
+        let lock = RepoLock::acquire(&wd, Duration::ZERO).unwrap();
+        assert!(matches!(
+            RepoLock::acquire(&wd, Duration::from_millis(100)).unwrap_err().downcast_ref(),
+            Some(RepoError::Locked(_))
+        ));
+
+        let waiter = thread::spawn({
+            let wd = WorkDir::new(temp_dir.path()).unwrap();
+            move || RepoLock::acquire(&wd, Duration::from_secs(5)).map(drop)
+        });
+        thread::sleep(Duration::from_millis(100));
+        drop(lock);
+        waiter.join().unwrap().unwrap();
+        assert!(!wd.git_path(REPO_LOCK).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn breaks_a_lock_left_by_a_dead_process() {
+        let (_temp_dir, wd) = temp_workdir();
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        fs::write(wd.git_path(REPO_LOCK), format!("{}\n", child.id())).unwrap();
+
+        let lock = RepoLock::acquire(&wd, Duration::ZERO).unwrap();
+        assert_eq!(fs::read_to_string(wd.git_path(REPO_LOCK)).unwrap(), format!("{}\n", std::process::id()));
+        drop(lock);
+
+        // A live process keeps its lock, as does one that hasn't written its ID yet
+        for contents in [format!("{}\n", std::process::id()), String::new()] {
+            fs::write(wd.git_path(REPO_LOCK), contents).unwrap();
+            assert!(matches!(
+                RepoLock::acquire(&wd, Duration::ZERO).unwrap_err().downcast_ref(),
+                Some(RepoError::Locked(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! The `safe.directory` check. A repository's config can run commands (e.g. hooks or
+//! core.fsmonitor), so as in git, a repository owned by another user is refused unless it's
+//! listed in safe.directory. That setting only counts in config its owner can't plant: the
+//! system and global config files, and the `GIT_CONFIG_COUNT` environment variables.
+//! Ownership is only checked on Unix.
+
+use std::path::{Path, PathBuf};
+
+use ini::Ini;
+
+use crate::{
+    Result,
+    workdir::WorkDir,
+};
+
+use super::RepoError;
+
+/// Fails if the repository at `workdir` (or its `.git` directory) is owned by someone other
+/// than the current user and isn't listed in safe.directory.
+pub(super) fn check(workdir: &WorkDir) -> Result<()> {
+    if is_owned_by_current_user(workdir.as_path()) && is_owned_by_current_user(&workdir.git_path("")) {
+        return Ok(());
+    }
+
+    let path = workdir.as_path().canonicalize().unwrap_or_else(|_| workdir.as_path().to_owned());
+    if is_listed(&path, &safe_directories()?) {
+        return Ok(());
+    }
+
+    Err(RepoError::DubiousOwnership(path).into())
+}
+
+#[cfg(unix)]
+fn is_owned_by_current_user(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    // SAFETY: geteuid has no preconditions and always succeeds
+    let mut uid = unsafe { libc::geteuid() };
+
+    // Under sudo, the repository is expected to belong to the user who ran it
+    if uid == 0 {
+        if let Some(sudo_uid) = std::env::var("SUDO_UID").ok().and_then(|value| value.parse().ok()) {
+            uid = sudo_uid;
+        }
+    }
+
+    // A missing path can't belong to anyone else
+    path.metadata().map_or(true, |meta| meta.uid() == uid)
+}
+
+#[cfg(not(unix))]
+fn is_owned_by_current_user(_path: &Path) -> bool {
+    true
+}
+
+/// Returns true if `path` is allowed by `safe_directories`. As in git, `*` allows every
+/// directory, a value ending in `/*` allows every directory under it (but not the directory
+/// itself), and an empty value forgets the values before it.
+fn is_listed(path: &Path, safe_directories: &[String]) -> bool {
+    let start = safe_directories.iter().rposition(String::is_empty).map_or(0, |index| index + 1);
+
+    safe_directories[start..].iter().any(|value| {
+        if value == "*" {
+            return true;
+        }
+
+        let (value, includes_subdirs) = match value.strip_suffix("/*") {
+            Some(prefix) => (prefix, true),
+            None => (value.as_str(), false),
+        };
+        let listed = expand_home(value);
+        let listed = listed.canonicalize().unwrap_or(listed);
+
+        match includes_subdirs {
+            true => path.starts_with(&listed) && path != listed,
+            false => path == listed,
+        }
+    })
+}
+
+/// Expands a leading `~/` to the home directory.
+fn expand_home(value: &str) -> PathBuf {
+    match (value.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(value),
+    }
+}
+
+/// Returns the values of safe.directory in the order git reads them: from the system config
+/// file, the global config files, and then the environment.
+fn safe_directories() -> Result<Vec<String>> {
+    let env = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+
+    let mut files = Vec::new();
+    if env("GIT_CONFIG_NOSYSTEM").is_none() {
+        files.push(env("GIT_CONFIG_SYSTEM").map_or_else(|| PathBuf::from("/etc/gitconfig"), PathBuf::from));
+    }
+    match env("GIT_CONFIG_GLOBAL") {
+        Some(path) => files.push(PathBuf::from(path)),
+        None => {
+            let xdg_dir = env("XDG_CONFIG_HOME").map(PathBuf::from)
+                .or_else(|| env("HOME").map(|home| Path::new(&home).join(".config")));
+            files.extend(xdg_dir.map(|dir| dir.join("git/config")));
+            files.extend(env("HOME").map(|home| Path::new(&home).join(".gitconfig")));
+        },
+    }
+
+    let mut values = Vec::new();
+    for file in files {
+        if !file.is_file() {
+            continue;
+        }
+        let config = Ini::load_from_file(&file)?;
+        for section in config.section_all(Some("safe")) {
+            values.extend(section.get_all("directory").map(str::to_owned));
+        }
+    }
+
+    // Like `git -c safe.directory=...`, which is passed along to subprocesses this way
+    let count: usize = std::env::var("GIT_CONFIG_COUNT").ok().and_then(|count| count.parse().ok()).unwrap_or(0);
+    for n in 0..count {
+        let key = std::env::var(format!("GIT_CONFIG_KEY_{n}")).unwrap_or_default();
+        if key.eq_ignore_ascii_case("safe.directory") {
+            values.push(std::env::var(format!("GIT_CONFIG_VALUE_{n}")).unwrap_or_default());
+        }
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_listed_directories() {
+        let listed = |values: &[&str]| {
+            let values: Vec<_> = values.iter().map(|value| value.to_string()).collect();
+            is_listed(Path::new("/srv/repos/project"), &values)
+        };
+
+        assert!(listed(&["/srv/repos/project"]));
+        assert!(listed(&["/elsewhere", "*"]));
+        assert!(listed(&["/srv/repos/*"]));
+        assert!(!listed(&["/srv/repos"]));
+        assert!(!listed(&["/srv/repos/proj/*"]));
+        assert!(!listed(&["/srv/repos/project/*"]));
+        assert!(!listed(&["*", ""]));
+        assert!(listed(&["", "/srv/repos/project"]));
+        assert!(!listed(&[]));
+    }
+}
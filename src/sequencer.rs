@@ -0,0 +1,463 @@
+use std::{fmt, fs};
+
+use anyhow::Context;
+use ini::Ini;
+use thiserror::Error;
+
+use crate::{
+    Result,
+    workdir::WorkDir,
+    object::ObjectHash,
+};
+
+/// The directory (relative to .git) where the sequencer state is stored.
+const SEQUENCER_DIR: &str = "sequencer";
+
+/// A multi-step operation that applies commits one at a time, such as a cherry-pick of
+/// several commits. The remaining steps are persisted under `.git/sequencer` so that the
+/// operation can be resumed (`--continue`), resumed without the current step (`--skip`),
+/// or abandoned (`--abort`) after it stops on a conflict.
+pub struct Sequencer {
+    pub operation: Operation,
+    /// The commit that was checked out when the operation started.
+    pub original_head: Option<ObjectHash>,
+    pub options: SequencerOptions,
+    /// The steps that have yet to be completed. The first is the current step.
+    todo: Vec<Step>,
+    /// The steps that have been completed.
+    done: Vec<Step>,
+}
+
+/// The command that started a sequencer operation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operation {
+    CherryPick,
+    Revert,
+    Rebase,
+}
+
+/// Options that apply to every step of an operation, so they're kept when it's resumed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct SequencerOptions {
+    /// Append "(cherry picked from commit ...)" to the message of each picked commit (`-x`).
+    pub record_origin: bool,
+    /// For merge commits, the parent (numbered from 1) whose changes are the ones the commit
+    /// introduced (`-m`).
+    pub mainline: Option<usize>,
+}
+
+/// A single step of a sequencer operation, i.e. a line of the todo list.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Step {
+    pub action: Action,
+    pub commit: ObjectHash,
+    /// The subject of the commit, for display purposes only.
+    pub subject: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Apply the changes introduced by the commit.
+    Pick,
+    /// Apply the inverse of the changes introduced by the commit.
+    Revert,
+}
+
+/// The result of applying a single step.
+pub enum StepOutcome {
+    /// The step was applied and committed.
+    Applied,
+    /// The step couldn't be completed (e.g. because of conflicts). The operation stops
+    /// until the user resolves the problem and continues, skips, or aborts.
+    Stopped,
+}
+
+/// The state of a sequencer operation after running as many steps as possible.
+#[derive(PartialEq, Eq, Debug)]
+pub enum SequencerStatus {
+    /// Every step was applied and the state has been removed.
+    Finished,
+    /// The current step stopped. The state is saved so the operation can be resumed.
+    Stopped(Step),
+}
+
+impl Sequencer {
+    /// Starts a new operation with the steps in `todo`. Fails if an operation is already
+    /// in progress. Nothing is applied until [`Sequencer::run`] is called.
+    pub fn start(wd: &WorkDir, operation: Operation, original_head: Option<ObjectHash>, options: SequencerOptions, todo: Vec<Step>) -> Result<Self> {
+        if let Some(existing) = Self::load(wd)? {
+            return Err(SequencerError::InProgress(existing.operation).into());
+        }
+
+        let sequencer = Self {
+            operation,
+            original_head,
+            options,
+            todo,
+            done: Vec::new(),
+        };
+        sequencer.save(wd)?;
+
+        Ok(sequencer)
+    }
+
+    /// Loads the operation in progress, if any.
+    pub fn load(wd: &WorkDir) -> Result<Option<Self>> {
+        let dir_path = wd.git_path(SEQUENCER_DIR);
+        if !dir_path.is_dir() {
+            return Ok(None);
+        }
+
+        let read = |name: &str| {
+            let path = dir_path.join(name);
+            fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read sequencer state at `{path:?}`"))
+        };
+
+        let operation = Operation::parse(read("operation")?.trim())?;
+        let original_head = match read("head")?.trim() {
+            "" => None,
+            hash => Some(ObjectHash::try_from(hash)?),
+        };
+        // Same format as git's, which is a config file
+        let opts = Ini::load_from_str(&read("opts")?)
+            .context("Failed to parse sequencer options")?;
+        let options = SequencerOptions {
+            record_origin: opts.get_from(Some("options"), "record-origin") == Some("true"),
+            // Parents are numbered from 1
+            mainline: opts.get_from(Some("options"), "mainline")
+                .map(|n| match n.parse() {
+                    Ok(0) | Err(_) => Err(SequencerError::InvalidMainline(n.to_owned())),
+                    Ok(n) => Ok(n),
+                })
+                .transpose()?,
+        };
+
+        Ok(Some(Self {
+            operation,
+            original_head,
+            options,
+            todo: parse_steps(&read("todo")?)?,
+            done: parse_steps(&read("done")?)?,
+        }))
+    }
+
+    /// Loads the operation in progress. Fails if there isn't one or if it was started by
+    /// a different command than `operation`.
+    pub fn load_expecting(wd: &WorkDir, operation: Operation) -> Result<Self> {
+        match Self::load(wd)? {
+            Some(sequencer) if sequencer.operation == operation => Ok(sequencer),
+            Some(sequencer) => Err(SequencerError::InProgress(sequencer.operation).into()),
+            None => Err(SequencerError::NotInProgress(operation).into()),
+        }
+    }
+
+    /// Returns true if a sequencer operation is in progress.
+    pub fn is_in_progress(wd: &WorkDir) -> bool {
+        wd.git_path(SEQUENCER_DIR).is_dir()
+    }
+
+    /// Returns the current step, if any.
+    pub fn current(&self) -> Option<&Step> {
+        self.todo.first()
+    }
+
+    /// Returns the steps that haven't been completed yet, starting with the current one.
+    pub fn todo(&self) -> &[Step] {
+        &self.todo
+    }
+
+    /// Returns the steps that have been completed.
+    pub fn done(&self) -> &[Step] {
+        &self.done
+    }
+
+    /// Applies the remaining steps in order with `apply`, stopping at the first step that
+    /// doesn't complete. The state is saved after every step, and removed once every step
+    /// has been applied.
+    pub fn run<F>(&mut self, wd: &WorkDir, mut apply: F) -> Result<SequencerStatus>
+    where
+        F: FnMut(&Step) -> Result<StepOutcome>
+    {
+        while let Some(step) = self.todo.first() {
+            match apply(step)? {
+                StepOutcome::Applied => {
+                    let step = self.todo.remove(0);
+                    self.done.push(step);
+                    self.save(wd)?;
+                },
+                StepOutcome::Stopped => {
+                    self.save(wd)?;
+                    return Ok(SequencerStatus::Stopped(step.clone()));
+                },
+            }
+        }
+
+        remove_state(wd)?;
+
+        Ok(SequencerStatus::Finished)
+    }
+
+    /// Marks the current step as done (after the user has resolved and committed it),
+    /// then runs the remaining steps. This is `--continue`.
+    pub fn resume<F>(&mut self, wd: &WorkDir, apply: F) -> Result<SequencerStatus>
+    where
+        F: FnMut(&Step) -> Result<StepOutcome>
+    {
+        if !self.todo.is_empty() {
+            let step = self.todo.remove(0);
+            self.done.push(step);
+        }
+
+        self.run(wd, apply)
+    }
+
+    /// Drops the current step, then runs the remaining steps. This is `--skip`. The
+    /// caller is responsible for discarding any changes made by the current step.
+    pub fn skip<F>(&mut self, wd: &WorkDir, apply: F) -> Result<SequencerStatus>
+    where
+        F: FnMut(&Step) -> Result<StepOutcome>
+    {
+        if !self.todo.is_empty() {
+            self.todo.remove(0);
+        }
+
+        self.run(wd, apply)
+    }
+
+    /// Abandons the operation and removes its state. This is `--abort`. Returns the
+    /// commit that was checked out when the operation started, which the caller should
+    /// restore.
+    pub fn abort(self, wd: &WorkDir) -> Result<Option<ObjectHash>> {
+        remove_state(wd)?;
+
+        Ok(self.original_head)
+    }
+
+    /// Writes the state to `.git/sequencer`.
+    fn save(&self, wd: &WorkDir) -> Result<()> {
+        let dir_path = wd.make_git_dir(SEQUENCER_DIR)?;
+        let head = self.original_head.map_or_else(String::new, |hash| hash.to_string());
+
+        fs::write(dir_path.join("operation"), format!("{}\n", self.operation))?;
+        fs::write(dir_path.join("head"), format!("{head}\n"))?;
+        let mut opts = Ini::new();
+        if self.options.record_origin {
+            opts.with_section(Some("options")).set("record-origin", "true");
+        }
+        if let Some(mainline) = self.options.mainline {
+            opts.with_section(Some("options")).set("mainline", mainline.to_string());
+        }
+        opts.write_to_file(dir_path.join("opts"))?;
+        fs::write(dir_path.join("todo"), serialize_steps(&self.todo))?;
+        fs::write(dir_path.join("done"), serialize_steps(&self.done))?;
+
+        Ok(())
+    }
+}
+
+impl Operation {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "cherry-pick" => Ok(Self::CherryPick),
+            "revert" => Ok(Self::Revert),
+            "rebase" => Ok(Self::Rebase),
+            name => Err(SequencerError::UnknownOperation(name.to_owned()).into()),
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CherryPick => write!(f, "cherry-pick"),
+            Self::Revert => write!(f, "revert"),
+            Self::Rebase => write!(f, "rebase"),
+        }
+    }
+}
+
+impl Step {
+    /// Parses a line of a todo list, e.g. `pick 0123abc Fix the thing`.
+    pub fn parse(line: &str) -> Result<Self> {
+        let invalid = || SequencerError::InvalidStep(line.to_owned());
+
+        let mut parts = line.splitn(3, ' ');
+        let action = match parts.next() {
+            Some("pick" | "p") => Action::Pick,
+            Some("revert") => Action::Revert,
+            _ => return Err(invalid().into()),
+        };
+        let commit = parts.next()
+            .and_then(|hash| ObjectHash::try_from(hash).ok())
+            .ok_or_else(invalid)?;
+        let subject = parts.next().unwrap_or("").to_owned();
+
+        Ok(Self { action, commit, subject })
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+        };
+
+        write!(f, "{action} {} {}", self.commit, self.subject)
+    }
+}
+
+/// Appends `(cherry picked from commit <hash>)` to `message`, separated by a blank line
+/// unless the message already ends with trailers like `Signed-off-by: ...`.
+pub fn append_origin_line(message: &str, hash: &ObjectHash) -> String {
+    let message = message.trim_end();
+    let last_paragraph = message.rsplit("\n\n").next().unwrap_or_default();
+    let ends_with_trailers = !message.is_empty()
+        && message.contains("\n\n")
+        && last_paragraph.lines().all(|line| line.starts_with("(cherry picked from commit ") || is_trailer(line));
+    let separator = match (message.is_empty(), ends_with_trailers) {
+        (true, _) => "",
+        (false, true) => "\n",
+        (false, false) => "\n\n",
+    };
+
+    format!("{message}{separator}(cherry picked from commit {hash})\n")
+}
+
+/// Builds the message for a commit that reverts `hash`, whose subject is `subject`. Reverting
+/// a merge also names the parent whose side was kept.
+pub fn revert_message(subject: &str, hash: &ObjectHash, mainline_parent: Option<&ObjectHash>) -> String {
+    match mainline_parent {
+        Some(parent) => format!("Revert \"{subject}\"\n\nThis reverts commit {hash}, reversing\nchanges made to {parent}.\n"),
+        None => format!("Revert \"{subject}\"\n\nThis reverts commit {hash}.\n"),
+    }
+}
+
+/// Returns true if `line` looks like `Key: value`, where the key has no spaces.
+fn is_trailer(line: &str) -> bool {
+    line.split_once(": ")
+        .is_some_and(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+}
+
+fn parse_steps(contents: &str) -> Result<Vec<Step>> {
+    contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Step::parse)
+        .collect()
+}
+
+fn serialize_steps(steps: &[Step]) -> String {
+    steps.iter()
+        .map(|step| format!("{step}\n"))
+        .collect()
+}
+
+fn remove_state(wd: &WorkDir) -> Result<()> {
+    let dir_path = wd.git_path(SEQUENCER_DIR);
+    if dir_path.is_dir() {
+        fs::remove_dir_all(dir_path)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+pub enum SequencerError {
+    #[error("A {0} is already in progress (use --continue, --skip, or --abort)")]
+    InProgress(Operation),
+    #[error("No {0} in progress")]
+    NotInProgress(Operation),
+    #[error("Unknown sequencer operation `{0}`")]
+    UnknownOperation(String),
+    #[error("Invalid sequencer step `{0}`")]
+    InvalidStep(String),
+    #[error("Invalid mainline `{0}` in sequencer options")]
+    InvalidMainline(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_workdir;
+
+    fn step(n: u8) -> Step {
+        Step {
+            action: Action::Pick,
+            commit: ObjectHash::try_from(format!("{n:02x}").repeat(20).as_str()).unwrap(),
+            subject: format!("Commit {n}"),
+        }
+    }
+
+    #[test]
+    fn step_round_trip() {
+        let line = "revert 0123456789abcdef0123456789abcdef01234567 Fix the thing";
+        let step = Step::parse(line).unwrap();
+        assert_eq!(step.action, Action::Revert);
+        assert_eq!(step.subject, "Fix the thing");
+        assert_eq!(step.to_string(), line);
+
+        assert!(Step::parse("squash 0123456789abcdef0123456789abcdef01234567").is_err());
+    }
+
+    #[test]
+    fn appends_origin_line() {
+        let hash = step(1).commit;
+        let line = format!("(cherry picked from commit {hash})\n");
+        assert_eq!(append_origin_line("Fix it\n", &hash), format!("Fix it\n\n{line}"));
+        assert_eq!(append_origin_line("Fix it\n\nSigned-off-by: Jo <jo@example.com>\n", &hash), format!("Fix it\n\nSigned-off-by: Jo <jo@example.com>\n{line}"));
+        assert_eq!(append_origin_line("Fix it\n\nThe docs say: no\n", &hash), format!("Fix it\n\nThe docs say: no\n\n{line}"));
+    }
+
+    #[test]
+    fn builds_revert_message() {
+        let (hash, parent) = (step(1).commit, step(2).commit);
+        assert_eq!(revert_message("Fix it", &hash, None), format!("Revert \"Fix it\"\n\nThis reverts commit {hash}.\n"));
+        assert_eq!(
+            revert_message("Merge branch 'topic'", &hash, Some(&parent)),
+            format!("Revert \"Merge branch 'topic'\"\n\nThis reverts commit {hash}, reversing\nchanges made to {parent}.\n"),
+        );
+    }
+
+    #[test]
+    fn stops_resumes_and_finishes() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        let options = SequencerOptions { record_origin: true, mainline: Some(2) };
+        let mut sequencer = Sequencer::start(&wd, Operation::CherryPick, None, options, vec![step(1), step(2), step(3)]).unwrap();
+        assert!(Sequencer::start(&wd, Operation::Revert, None, options, vec![]).is_err());
+
+        let status = sequencer.run(&wd, |step| match step.subject.as_str() {
+            "Commit 2" => Ok(StepOutcome::Stopped),
+            _ => Ok(StepOutcome::Applied),
+        }).unwrap();
+        assert_eq!(status, SequencerStatus::Stopped(step(2)));
+
+        let mut sequencer = Sequencer::load_expecting(&wd, Operation::CherryPick).unwrap();
+        assert_eq!(sequencer.done(), [step(1)]);
+        assert_eq!(sequencer.todo(), [step(2), step(3)]);
+        assert_eq!(sequencer.options, options);
+        assert!(Sequencer::load_expecting(&wd, Operation::Revert).is_err());
+
+        let status = sequencer.resume(&wd, |_| Ok(StepOutcome::Applied)).unwrap();
+        assert_eq!(status, SequencerStatus::Finished);
+        assert!(!Sequencer::is_in_progress(&wd));
+    }
+
+    #[test]
+    fn rejects_invalid_mainline() {
+        let (_temp_dir, wd) = temp_workdir();
+        let options = SequencerOptions { record_origin: false, mainline: Some(1) };
+        Sequencer::start(&wd, Operation::CherryPick, None, options, vec![step(1)]).unwrap();
+
+        for mainline in ["0", "-1", "one"] {
+            let path = wd.git_path(SEQUENCER_DIR).join("opts");
+            fs::write(path, format!("[options]\nmainline = {mainline}\n")).unwrap();
+            let Err(err) = Sequencer::load(&wd) else {
+                panic!("mainline {mainline} should be rejected");
+            };
+            assert!(matches!(err.downcast_ref(), Some(SequencerError::InvalidMainline(n)) if n == mainline));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{fs, path::Path};
+
+use crate::{
+    Result,
+    refs,
+    object::GitObject,
+    workdir::WorkDir,
+};
+
+/// Writes the files that let clients fetch from the repository over the "dumb" HTTP
+/// protocol, which can't ask the server anything: `info/refs` lists every ref, and
+/// `objects/info/packs` lists the packfiles. They need to be rewritten whenever refs or
+/// packs change.
+pub fn update(wd: &WorkDir) -> Result<()> {
+    wd.check_writable()?;
+
+    write_atomically(&wd.git_path("info/refs"), &list_refs(wd)?)?;
+    write_atomically(&wd.git_path("objects/info/packs"), &list_packs(wd)?)?;
+
+    Ok(())
+}
+
+/// Lists each ref as `hash\tname`. An annotated tag is followed by the object it points
+/// to, as `hash\tname^{}`, so clients don't have to download the tag to find it.
+fn list_refs(wd: &WorkDir) -> Result<String> {
+    let mut contents = String::new();
+
+    for git_ref in refs::iter(wd, "refs/")? {
+        let git_ref = git_ref?;
+        let hash = git_ref.resolve(wd)?;
+        contents.push_str(&format!("{hash}\t{}\n", git_ref.name));
+
+        let peeled = GitObject::peel_tags(wd, &hash)?;
+        if peeled != hash {
+            contents.push_str(&format!("{peeled}\t{}^{{}}\n", git_ref.name));
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Lists each packfile that has an index as `P pack-<hash>.pack`, followed by a blank line.
+fn list_packs(wd: &WorkDir) -> Result<String> {
+    let mut names = vec![];
+
+    let pack_dir = wd.git_path("objects/pack");
+    if pack_dir.is_dir() {
+        for entry in fs::read_dir(&pack_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "pack") && path.with_extension("idx").is_file() {
+                names.push(path.file_name().expect("pack should have a file name").to_string_lossy().into_owned());
+            }
+        }
+    }
+    names.sort();
+
+    let mut contents: String = names.iter()
+        .map(|name| format!("P {name}\n"))
+        .collect();
+    contents.push('\n');
+
+    Ok(contents)
+}
+
+/// Replaces the file at `path` so that a client reading it at the same time never sees
+/// it half written.
+fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    fs::create_dir_all(path.parent().expect("server info file should have a parent"))?;
+
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_packs_with_indexes() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let pack_dir = temp_dir.join(".git/objects/pack");
+        fs::create_dir_all(&pack_dir).unwrap();
+        for name in ["pack-b.pack", "pack-b.idx", "pack-a.pack", "pack-a.idx", "pack-c.pack"] {
+            fs::write(pack_dir.join(name), "").unwrap();
+        }
+
+        let wd = WorkDir::new(temp_dir.path()).unwrap();
+        assert_eq!(list_packs(&wd).unwrap(), "P pack-a.pack\nP pack-b.pack\n\n");
+    }
+}
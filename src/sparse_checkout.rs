@@ -0,0 +1,208 @@
+use std::{collections::HashMap, fs};
+
+use anyhow::Context;
+
+use crate::{
+    Result,
+    ignore::IgnoreFile,
+    workdir::{WorkDir, WorkPath, WorkPathBuf},
+};
+
+/// The file in the git directory that lists the paths in the sparse checkout.
+pub const SPARSE_CHECKOUT_FILE: &str = "info/sparse-checkout";
+
+/// Decides which paths are in the sparse checkout, using `.git/info/sparse-checkout` if
+/// `core.sparseCheckout` is set. Tracked files outside of it aren't expected to be in the
+/// working directory, and `add` refuses to stage paths there.
+pub struct SparseCheckout {
+    patterns: Patterns,
+}
+
+enum Patterns {
+    /// Sparse checkout is off, so every path is in it.
+    All,
+    /// Cone mode (`core.sparseCheckoutCone`): the patterns only name directories.
+    Cone(ConeDir),
+    /// The patterns use the full `.gitignore` syntax, where a match includes the path.
+    Full(IgnoreFile),
+}
+
+/// A directory named by cone mode patterns, and the ones named inside it. Files directly
+/// in any directory in the trie are included, and so is everything below a recursive one.
+#[derive(Default, Debug, PartialEq, Eq)]
+struct ConeDir {
+    /// Everything below this directory is included (`/dir/` without `!/dir/*/`).
+    recursive: bool,
+    children: HashMap<String, ConeDir>,
+}
+
+impl SparseCheckout {
+    /// Reads the sparse checkout patterns for the repository at `wd`. If sparse checkout
+    /// is off or there is no patterns file, every path is included.
+    pub fn load(wd: &WorkDir) -> Result<Self> {
+        let abs_path = wd.git_path(SPARSE_CHECKOUT_FILE);
+        if !wd.settings().sparse_checkout || !abs_path.is_file() {
+            return Ok(Self::all());
+        }
+
+        let contents = fs::read_to_string(&abs_path)
+            .with_context(|| format!("Failed to read sparse checkout file at `{abs_path:?}`"))?;
+
+        Ok(Self::parse(&contents, wd.settings().sparse_checkout_cone))
+    }
+
+    /// Creates a sparse checkout that includes every path, as for `add --sparse`.
+    pub fn all() -> Self {
+        Self {
+            patterns: Patterns::All,
+        }
+    }
+
+    /// Parses the contents of a sparse checkout file. As in git, if `cone` is set but a
+    /// pattern doesn't fit cone mode, the full syntax is used instead.
+    pub fn parse(contents: &str, cone: bool) -> Self {
+        let cone_patterns = match cone {
+            true => parse_cone(contents),
+            false => None,
+        };
+        let patterns = match cone_patterns {
+            Some(root) => Patterns::Cone(root),
+            None => Patterns::Full(IgnoreFile::parse(contents, &WorkPathBuf::root())),
+        };
+
+        Self { patterns }
+    }
+
+    /// Returns true if the file at `path` is in the sparse checkout.
+    pub fn contains(&self, path: &WorkPath) -> bool {
+        match &self.patterns {
+            Patterns::All => true,
+            Patterns::Cone(root) => {
+                let Some(parent) = path.parent().filter(|parent| !parent.is_empty()) else {
+                    // Files at the top level are always included in cone mode
+                    return true;
+                };
+
+                let mut dir = root;
+                for name in parent.as_str().split('/') {
+                    if dir.recursive {
+                        return true;
+                    }
+                    match dir.children.get(name) {
+                        Some(child) => dir = child,
+                        None => return false,
+                    }
+                }
+
+                true
+            },
+            Patterns::Full(file) => {
+                // Like git, the path itself decides first. If no pattern matches it, the
+                // nearest directory containing it that some pattern matches decides.
+                let dirs = path.as_str().rmatch_indices('/').map(|(index, _)| {
+                    WorkPath::from_canonical(&path.as_str()[..index])
+                        .expect("ancestor of a canonical path should be canonical")
+                });
+                std::iter::once((path, false))
+                    .chain(dirs.map(|dir| (dir, true)))
+                    .find_map(|(path, is_dir)| file.matches(path, is_dir))
+                    .unwrap_or(false)
+            },
+        }
+    }
+}
+
+/// Builds the directory trie for cone mode patterns, which look like this:
+///
+/// ```text
+/// /*
+/// !/*/
+/// /docs/
+/// !/docs/*/
+/// /docs/guide/
+/// ```
+///
+/// Returns `None` if any pattern isn't one of these forms.
+fn parse_cone(contents: &str) -> Option<ConeDir> {
+    let mut root = ConeDir::default();
+
+    for line in contents.lines().map(str::trim_end) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line {
+            "/*" => root.recursive = true,
+            "!/*/" => root.recursive = false,
+            _ => {
+                let (path, recursive) = match line.strip_prefix('!') {
+                    Some(rest) => (rest.strip_suffix("/*/")?, false),
+                    None => (line.strip_suffix('/')?, true),
+                };
+                let path = path.strip_prefix('/')?;
+                if path.is_empty() || path.contains(['*', '?', '[', '\\']) {
+                    return None;
+                }
+
+                let mut dir = &mut root;
+                for name in path.split('/') {
+                    dir = dir.children.entry(name.to_owned()).or_default();
+                }
+                dir.recursive = recursive;
+            },
+        }
+    }
+
+    Some(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(sparse: &SparseCheckout, path: &str) -> bool {
+        sparse.contains(&WorkPathBuf::try_from(path).unwrap())
+    }
+
+    #[test]
+    fn cone_mode_includes_parents_and_recursive_dirs() {
+        let sparse = SparseCheckout::parse("/*\n!/*/\n/docs/\n!/docs/*/\n/docs/guide/\n", true);
+        assert!(matches!(sparse.patterns, Patterns::Cone(_)));
+
+        assert!(contains(&sparse, "README.md"));
+        assert!(contains(&sparse, "docs/index.md"));
+        assert!(!contains(&sparse, "docs/api/index.md"));
+        assert!(contains(&sparse, "docs/guide/intro.md"));
+        assert!(contains(&sparse, "docs/guide/deep/er.md"));
+        assert!(!contains(&sparse, "src/main.rs"));
+    }
+
+    #[test]
+    fn non_cone_patterns_fall_back_to_full_matching() {
+        let sparse = SparseCheckout::parse("/*\n!/*/\n*.md\n", true);
+        assert!(matches!(sparse.patterns, Patterns::Full(_)));
+
+        assert!(contains(&sparse, "Cargo.toml"));
+        assert!(contains(&sparse, "src/lib.md"));
+        assert!(!contains(&sparse, "src/main.rs"));
+
+        let sparse = SparseCheckout::parse("/src/\n", false);
+        assert!(contains(&sparse, "src/a/b.rs"));
+        assert!(!contains(&sparse, "Cargo.toml"));
+
+        // A pattern for the file itself overrides one for its directory
+        let sparse = SparseCheckout::parse("/src/\n!/src/secret.txt\n", false);
+        assert!(contains(&sparse, "src/main.rs"));
+        assert!(!contains(&sparse, "src/secret.txt"));
+    }
+
+    #[test]
+    fn disabled_includes_everything() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.join(".git/info")).unwrap();
+        fs::write(temp_dir.join(".git").join(SPARSE_CHECKOUT_FILE), "/src/\n").unwrap();
+
+        let wd = WorkDir::new(temp_dir.path()).unwrap();
+        assert!(contains(&SparseCheckout::load(&wd).unwrap(), "docs/a.md"));
+    }
+}
@@ -0,0 +1,91 @@
+//! The stash, which git keeps as the reflog of `refs/stash`. Each entry is a commit of the
+//! working tree whose first parent is the commit it was stashed on.
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    object::ObjectHash,
+    reflog::{self, ReflogEntry},
+    refs::{RefTarget, RefTransaction},
+    workdir::WorkDir,
+};
+
+pub const STASH_REF: &str = "refs/stash";
+
+/// Parses a stash entry given as `n`, `stash@{n}`, or `refs/stash@{n}`.
+pub fn parse_entry(entry: &str) -> Result<usize> {
+    if let Ok(n) = entry.parse() {
+        return Ok(n);
+    }
+
+    match reflog::parse_selector(entry) {
+        Ok(("stash" | STASH_REF, n)) => Ok(n),
+        _ => Err(StashError::InvalidEntry(entry.to_owned()).into()),
+    }
+}
+
+/// Returns the commit of stash entry `n`, where 0 is the most recent.
+pub fn get(wd: &WorkDir, n: usize) -> Result<ObjectHash> {
+    let entries = reflog::read(wd, STASH_REF)?;
+    if entries.is_empty() {
+        return Err(StashError::NoStash.into());
+    }
+
+    match n < entries.len() {
+        true => Ok(entries[entries.len() - 1 - n].new),
+        false => Err(StashError::NoSuchEntry(n).into()),
+    }
+}
+
+/// Lists the stash entries, most recent first, so entry `n` is at index `n`.
+pub fn list(wd: &WorkDir) -> Result<Vec<ReflogEntry>> {
+    let mut entries = reflog::read(wd, STASH_REF)?;
+    entries.reverse();
+
+    Ok(entries)
+}
+
+/// Removes stash entry `n` and returns its commit. `refs/stash` is moved to the most recent
+/// entry left, or deleted along with its reflog if none are.
+pub fn drop_entry(wd: &WorkDir, n: usize) -> Result<ObjectHash> {
+    let hash = get(wd, n)?;
+    reflog::delete(wd, STASH_REF, n)?;
+
+    let mut transaction = RefTransaction::new();
+    match reflog::read(wd, STASH_REF)?.last() {
+        Some(newest) => transaction.update(STASH_REF, RefTarget::Direct(newest.new)),
+        None => transaction.delete(STASH_REF),
+    }
+    transaction.commit(wd)?;
+
+    if !wd.git_path(STASH_REF).is_file() {
+        std::fs::remove_file(wd.git_path(format!("logs/{STASH_REF}")))?;
+    }
+
+    Ok(hash)
+}
+
+#[derive(Error, Debug)]
+pub enum StashError {
+    #[error("No stash entries found")]
+    NoStash,
+    #[error("stash@{{{0}}} is not a valid stash entry")]
+    NoSuchEntry(usize),
+    #[error("Invalid stash entry `{0}` (expected something like `1` or `stash@{{1}}`)")]
+    InvalidEntry(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entries() {
+        assert_eq!(parse_entry("2").unwrap(), 2);
+        assert_eq!(parse_entry("stash@{1}").unwrap(), 1);
+        assert_eq!(parse_entry("refs/stash@{0}").unwrap(), 0);
+        assert!(parse_entry("main@{1}").is_err());
+        assert!(parse_entry("stash").is_err());
+    }
+}
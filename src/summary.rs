@@ -0,0 +1,316 @@
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt,
+    fs,
+    path::Path,
+};
+
+use crate::{
+    Result,
+    repo::Repository,
+    workdir::WorkDir,
+    object::{Commit, GitObject, ObjectFormat, ObjectHash},
+    consts::{BRANCH_PREFIX, TAG_PREFIX},
+    refs,
+};
+
+/// The number of tags listed in a summary.
+const LATEST_TAG_COUNT: usize = 5;
+
+/// Statistics about a repository, such as the number of commits on each branch and the
+/// number of objects in the object store.
+pub struct RepoSummary {
+    /// The number of commits reachable from each branch.
+    pub branches: BTreeMap<String, usize>,
+    /// The number of commits made by each author, across all branches.
+    pub contributors: BTreeMap<String, usize>,
+    /// The number of loose objects of each type.
+    pub object_counts: BTreeMap<String, usize>,
+    /// The total size in bytes of the loose object files.
+    pub disk_usage: u64,
+    /// The most recent tags, newest first.
+    pub latest_tags: Vec<String>,
+}
+
+impl RepoSummary {
+    /// Collects statistics about the repository at `wd`.
+    pub fn collect(wd: &WorkDir) -> Result<Self> {
+        let mut branches = BTreeMap::new();
+        let mut contributors = BTreeMap::new();
+        let mut seen = HashSet::new();
+
+        for branch_ref in refs::iter(wd, BRANCH_PREFIX)? {
+            let branch_ref = branch_ref?;
+            let tip = branch_ref.resolve(wd)?;
+
+            let mut commit_count = 0;
+            walk_history(wd, &tip, |hash, commit| {
+                commit_count += 1;
+                if seen.insert(*hash) {
+                    let author = commit.author().unwrap_or("(unknown)").to_owned();
+                    *contributors.entry(author).or_insert(0) += 1;
+                }
+            })?;
+
+            branches.insert(branch_ref.short_name().to_owned(), commit_count);
+        }
+
+        let (object_counts, disk_usage) = count_objects(wd)?;
+
+        Ok(Self {
+            branches,
+            contributors,
+            object_counts,
+            disk_usage,
+            latest_tags: latest_tags(wd)?,
+        })
+    }
+}
+
+impl fmt::Display for RepoSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Branches:")?;
+        for (name, count) in &self.branches {
+            writeln!(f, "    {name}: {count} commits")?;
+        }
+
+        writeln!(f, "Contributors:")?;
+        let mut contributors: Vec<_> = self.contributors.iter().collect();
+        contributors.sort_by(|a, b| b.1.cmp(a.1));
+        for (author, count) in contributors {
+            writeln!(f, "    {author}: {count} commits")?;
+        }
+
+        let total: usize = self.object_counts.values().sum();
+        let counts = self.object_counts.iter()
+            .map(|(format, count)| format!("{count} {format}s"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(f, "Objects: {total} ({counts})")?;
+        writeln!(f, "Disk usage: {}", format_size(self.disk_usage))?;
+
+        writeln!(f, "Latest tags:")?;
+        for tag in &self.latest_tags {
+            writeln!(f, "    {tag}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts of the things that make a repository grow, for tools that monitor it. Unlike
+/// [`RepoSummary`], collecting these doesn't read any objects.
+#[derive(PartialEq, Eq, Debug)]
+pub struct RepoStats {
+    /// The number of loose objects.
+    pub loose_objects: usize,
+    /// The total size in bytes of the loose object files.
+    pub loose_size: u64,
+    /// The number of packfiles.
+    pub packs: usize,
+    /// The number of loose refs under `refs/`.
+    pub refs: usize,
+    /// The number of entries in the index.
+    pub index_entries: usize,
+    /// The number of files in the working directory, outside of `.git`.
+    pub worktree_files: usize,
+}
+
+impl RepoStats {
+    /// Collects the statistics for `repo`.
+    pub fn collect(repo: &Repository) -> Result<Self> {
+        let wd = repo.workdir();
+
+        let mut loose_size = 0;
+        let loose_hashes = GitObject::loose_hashes(wd)?;
+        for hash in &loose_hashes {
+            loose_size += fs::metadata(wd.git_path("objects").join(hash.to_path()))?.len();
+        }
+
+        let pack_dir = wd.git_path("objects/pack");
+        let mut packs = 0;
+        if pack_dir.is_dir() {
+            for entry in fs::read_dir(pack_dir)? {
+                if entry?.path().extension().is_some_and(|ext| ext == "pack") {
+                    packs += 1;
+                }
+            }
+        }
+
+        let mut refs = 0;
+        for git_ref in refs::iter(wd, "refs/")? {
+            git_ref?;
+            refs += 1;
+        }
+
+        Ok(Self {
+            loose_objects: loose_hashes.len(),
+            loose_size,
+            packs,
+            refs,
+            index_entries: repo.index()?.entries.len(),
+            worktree_files: count_files(wd.as_path())?,
+        })
+    }
+
+    /// Formats the statistics as a JSON object.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"loose_objects\":{},\"loose_size\":{},\"packs\":{},\"refs\":{},\"index_entries\":{},\"worktree_files\":{}}}",
+            self.loose_objects, self.loose_size, self.packs, self.refs, self.index_entries, self.worktree_files,
+        )
+    }
+}
+
+impl fmt::Display for RepoStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Loose objects: {}", self.loose_objects)?;
+        writeln!(f, "Loose object size: {}", format_size(self.loose_size))?;
+        writeln!(f, "Packs: {}", self.packs)?;
+        writeln!(f, "Refs: {}", self.refs)?;
+        writeln!(f, "Index entries: {}", self.index_entries)?;
+        writeln!(f, "Working directory files: {}", self.worktree_files)
+    }
+}
+
+/// Counts the files in the directory at `path` and its subdirectories, skipping `.git`.
+fn count_files(path: &Path) -> Result<usize> {
+    let mut count = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if entry.file_name() != ".git" {
+                count += count_files(&entry.path())?;
+            }
+        }
+        else {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Visits every commit reachable from `tip` exactly once, in breadth-first order.
+fn walk_history<F>(wd: &WorkDir, tip: &ObjectHash, mut visit: F) -> Result<()>
+where
+    F: FnMut(&ObjectHash, &Commit)
+{
+    let mut seen = HashSet::new();
+    let mut open_hashes = VecDeque::from([*tip]);
+
+    while let Some(hash) = open_hashes.pop_front() {
+        if !seen.insert(hash) {
+            continue;
+        }
+
+        let commit = Commit::read(wd, &hash)?;
+        visit(&hash, &commit);
+        open_hashes.extend(commit.parents());
+    }
+
+    Ok(())
+}
+
+/// Counts the loose objects of each type and totals the size of their files.
+fn count_objects(wd: &WorkDir) -> Result<(BTreeMap<String, usize>, u64)> {
+    let mut counts = BTreeMap::new();
+    let mut disk_usage = 0;
+
+    let objects_path = wd.git_path("objects");
+    for dir in fs::read_dir(&objects_path)? {
+        let dir = dir?;
+        let dir_name = dir.file_name().to_string_lossy().into_owned();
+        if dir_name.len() != 2 || !dir.file_type()?.is_dir() {
+            continue;
+        }
+
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            let hash_string = format!("{dir_name}{}", file.file_name().to_string_lossy());
+            let Ok(hash) = ObjectHash::try_from(hash_string.as_str()) else {
+                continue;
+            };
+
+            let format = GitObject::read(wd, &hash)?.get_format();
+            *counts.entry(format.to_string()).or_insert(0) += 1;
+            disk_usage += file.metadata()?.len();
+        }
+    }
+
+    Ok((counts, disk_usage))
+}
+
+/// Returns the names of the most recent tags, judged by the time of the commit they tag.
+fn latest_tags(wd: &WorkDir) -> Result<Vec<String>> {
+    let mut tags = Vec::new();
+
+    for tag_ref in refs::iter(wd, TAG_PREFIX)? {
+        let tag_ref = tag_ref?;
+        let hash = tag_ref.resolve(wd)?;
+        let time = match GitObject::peel(wd, &hash, ObjectFormat::Commit) {
+            Ok(commit_hash) => Commit::read(wd, &commit_hash)?.committer_time(),
+            Err(_) => None,
+        };
+
+        tags.push((time, tag_ref.short_name().to_owned()));
+    }
+
+    tags.sort_by(|a, b| b.cmp(a));
+
+    Ok(tags.into_iter()
+        .take(LATEST_TAG_COUNT)
+        .map(|(_, name)| name)
+        .collect())
+}
+
+/// Formats a number of bytes with a binary unit, e.g. `1.5 KiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} bytes");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next_unit;
+    }
+
+    format!("{size:.1} {unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(512), "512 bytes");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn formats_stats_as_json() {
+        let stats = RepoStats {
+            loose_objects: 3,
+            loose_size: 120,
+            packs: 1,
+            refs: 2,
+            index_entries: 4,
+            worktree_files: 5,
+        };
+        assert_eq!(
+            stats.to_json(),
+            r#"{"loose_objects":3,"loose_size":120,"packs":1,"refs":2,"index_entries":4,"worktree_files":5}"#,
+        );
+    }
+}
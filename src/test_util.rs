@@ -0,0 +1,38 @@
+//! Fixtures shared by the unit tests.
+
+use assert_fs::TempDir;
+
+use crate::{
+    object::{Commit, GitObject, ObjectHash},
+    workdir::WorkDir,
+};
+
+/// The hash of a tree with no entries.
+pub const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+/// Creates a working tree with an empty `.git` directory in a new temporary directory,
+/// which is deleted when the `TempDir` is dropped.
+pub fn temp_workdir() -> (TempDir, WorkDir) {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::create_dir(temp_dir.join(".git")).unwrap();
+    let wd = WorkDir::new(temp_dir.path()).unwrap();
+
+    (temp_dir, wd)
+}
+
+/// Writes a commit of the empty tree with `message` and `parents`. Every commit made this
+/// way has the same author, committer, and time.
+pub fn commit(wd: &WorkDir, message: &str, parents: &[ObjectHash]) -> ObjectHash {
+    commit_at(wd, message, 5, &ObjectHash::try_from(EMPTY_TREE).unwrap(), parents)
+}
+
+/// Writes a commit of `tree` with `message` and `parents`, authored and committed at
+/// `time` (in seconds since the epoch).
+pub fn commit_at(wd: &WorkDir, message: &str, time: i64, tree: &ObjectHash, parents: &[ObjectHash]) -> ObjectHash {
+    let mut text = format!("tree {tree}\n");
+    for parent in parents {
+        text += &format!("parent {parent}\n");
+    }
+    text += &format!("author Jo <jo@example.com> {time} +0000\ncommitter Jo <jo@example.com> {time} +0000\n\n{message}");
+    GitObject::Commit(Commit::deserialize(text.into_bytes()).unwrap()).write(wd).unwrap()
+}
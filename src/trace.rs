@@ -0,0 +1,147 @@
+//! Performance tracing, like git's `GIT_TRACE_PERFORMANCE`. When it's set, time spent in
+//! each kind of work below is added up, and the totals are reported when the command ends.
+//! The kinds can overlap: reading an object includes decompressing it, and walking the
+//! working directory includes hashing the files it finds.
+//!
+//! `GIT_TRACE_PERFORMANCE` can be `1`, `2`, or `true` to report to stderr, or an absolute
+//! path to append the report to a file. Anything else (including `0` and `false`) leaves
+//! tracing off.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// The environment variable that turns tracing on.
+pub const TRACE_VAR: &str = "GIT_TRACE_PERFORMANCE";
+
+/// A kind of work that's timed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    /// Reading and writing objects, loose or packed.
+    ObjectIo,
+    /// Compressing and decompressing loose objects.
+    Compression,
+    /// Parsing the index file.
+    IndexParse,
+    /// Serializing and writing the index file.
+    IndexWrite,
+    /// Comparing the working directory to the index.
+    DirWalk,
+}
+
+impl Category {
+    const ALL: [Self; 5] = [Self::ObjectIo, Self::Compression, Self::IndexParse, Self::IndexWrite, Self::DirWalk];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::ObjectIo => "object I/O",
+            Self::Compression => "compression",
+            Self::IndexParse => "index parse",
+            Self::IndexWrite => "index write",
+            Self::DirWalk => "directory walk",
+        }
+    }
+}
+
+/// The total nanoseconds and number of timers for each category.
+static TOTALS: [(AtomicU64, AtomicU64); Category::ALL.len()] = [const { (AtomicU64::new(0), AtomicU64::new(0)) }; Category::ALL.len()];
+
+/// Where the report goes.
+enum Target {
+    Stderr,
+    File(PathBuf),
+}
+
+fn target() -> Option<&'static Target> {
+    static TARGET: OnceLock<Option<Target>> = OnceLock::new();
+    TARGET.get_or_init(|| parse_target(&std::env::var(TRACE_VAR).ok()?)).as_ref()
+}
+
+fn parse_target(value: &str) -> Option<Target> {
+    match value {
+        "1" | "2" => Some(Target::Stderr),
+        _ if value.eq_ignore_ascii_case("true") => Some(Target::Stderr),
+        _ if PathBuf::from(value).is_absolute() => Some(Target::File(PathBuf::from(value))),
+        _ => None,
+    }
+}
+
+/// Returns true if tracing is on.
+pub fn is_enabled() -> bool {
+    target().is_some()
+}
+
+/// Times the work in `category` until it's dropped. Does nothing if tracing is off.
+#[must_use = "the timer stops as soon as it's dropped"]
+pub struct Timer {
+    category: Category,
+    start: Option<Instant>,
+}
+
+/// Starts timing work in `category`.
+pub fn start(category: Category) -> Timer {
+    Timer {
+        category,
+        start: is_enabled().then(Instant::now),
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let (nanos, count) = &TOTALS[self.category as usize];
+            nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Reports the totals so far and `elapsed`, the time the whole command took. `command` is
+/// the command line. Errors writing the report are ignored, so they can't fail the command.
+pub fn report(command: &str, elapsed: Duration) {
+    let Some(target) = target() else {
+        return;
+    };
+
+    let mut report = String::new();
+    for category in Category::ALL {
+        let (nanos, count) = &TOTALS[category as usize];
+        let count = count.load(Ordering::Relaxed);
+        if count > 0 {
+            let seconds = Duration::from_nanos(nanos.load(Ordering::Relaxed)).as_secs_f64();
+            let calls = if count == 1 { "call" } else { "calls" };
+            report += &format!("performance: {seconds:.9} s: {} ({count} {calls})\n", category.label());
+        }
+    }
+    report += &format!("performance: {:.9} s: wyag command: {command}\n", elapsed.as_secs_f64());
+
+    let _ = match target {
+        Target::Stderr => std::io::stderr().write_all(report.as_bytes()),
+        Target::File(path) => OpenOptions::new().create(true).append(true).open(path)
+            .and_then(|mut file| file.write_all(report.as_bytes())),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_targets() {
+        assert!(matches!(parse_target("1"), Some(Target::Stderr)));
+        assert!(matches!(parse_target("TRUE"), Some(Target::Stderr)));
+        assert!(parse_target("0").is_none());
+        assert!(parse_target("false").is_none());
+        assert!(parse_target("relative/file").is_none());
+        if cfg!(unix) {
+            assert!(matches!(parse_target("/tmp/trace"), Some(Target::File(_))));
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use std::{collections::HashSet, fmt};
+
+use anyhow::bail;
+
+use crate::{
+    Result,
+    repo::Repository,
+    workdir::{WorkDir, WorkPathBuf},
+    index::{Index, StagedChange},
+    object::{Blob, ObjectHash, Tree},
+};
+
+/// The kinds of whitespace errors detected per `core.whitespace`, same as git, plus
+/// `incomplete-line` (a missing newline at the end of the file).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WhitespaceRules {
+    /// `blank-at-eol`: whitespace at the end of a line.
+    pub blank_at_eol: bool,
+    /// `space-before-tab`: a space immediately before a tab in the indentation.
+    pub space_before_tab: bool,
+    /// `indent-with-non-tab`: indentation with `tab_width` or more spaces.
+    pub indent_with_non_tab: bool,
+    /// `tab-in-indent`: a tab in the indentation.
+    pub tab_in_indent: bool,
+    /// `blank-at-eof`: blank lines at the end of the file.
+    pub blank_at_eof: bool,
+    /// `cr-at-eol`: don't treat a carriage return at the end of a line as whitespace.
+    pub cr_at_eol: bool,
+    /// `incomplete-line`: the last line doesn't end with a newline.
+    pub incomplete_line: bool,
+    /// `tabwidth=<n>`: the number of columns a tab occupies.
+    pub tab_width: usize,
+}
+
+/// A whitespace error on a particular line (counting from 1).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WhitespaceError {
+    pub line: usize,
+    pub kind: WhitespaceErrorKind,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WhitespaceErrorKind {
+    TrailingWhitespace,
+    SpaceBeforeTab,
+    IndentWithNonTab,
+    TabInIndent,
+    BlankAtEof,
+    IncompleteLine,
+}
+
+impl Default for WhitespaceRules {
+    fn default() -> Self {
+        Self {
+            blank_at_eol: true,
+            space_before_tab: true,
+            indent_with_non_tab: false,
+            tab_in_indent: false,
+            blank_at_eof: true,
+            cr_at_eol: false,
+            incomplete_line: false,
+            tab_width: 8,
+        }
+    }
+}
+
+impl WhitespaceRules {
+    /// Reads the rules from `core.whitespace`.
+    pub fn from_config(repo: &Repository) -> Result<Self> {
+        match repo.get_config("core", "whitespace") {
+            Some(value) => Self::parse(value),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Parses a comma-separated list of rules like `trailing-space,-space-before-tab`.
+    /// Rules are added to (or with a `-` prefix, removed from) the defaults. Unknown rules
+    /// are ignored, but as in git, `tabwidth` must be between 1 and 63.
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut rules = Self::default();
+
+        for rule in value.split(',').map(str::trim) {
+            let (name, enabled) = match rule.strip_prefix('-') {
+                Some(name) => (name, false),
+                None => (rule, true),
+            };
+
+            match name {
+                "trailing-space" => {
+                    rules.blank_at_eol = enabled;
+                    rules.blank_at_eof = enabled;
+                },
+                "blank-at-eol" => rules.blank_at_eol = enabled,
+                "space-before-tab" => rules.space_before_tab = enabled,
+                "indent-with-non-tab" => rules.indent_with_non_tab = enabled,
+                "tab-in-indent" => rules.tab_in_indent = enabled,
+                "blank-at-eof" => rules.blank_at_eof = enabled,
+                "cr-at-eol" => rules.cr_at_eol = enabled,
+                "incomplete-line" => rules.incomplete_line = enabled,
+                _ => {
+                    if let Some(width) = name.strip_prefix("tabwidth=") {
+                        rules.tab_width = match width.parse() {
+                            Ok(width @ 1..=63) => width,
+                            _ => bail!("core.whitespace: tabwidth must be between 1 and 63, not `{width}`"),
+                        };
+                    }
+                },
+            }
+        }
+
+        Ok(rules)
+    }
+
+    /// Finds the whitespace errors in `contents`.
+    pub fn check(&self, contents: &str) -> Vec<WhitespaceError> {
+        let mut errors = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            for kind in self.check_line(line) {
+                errors.push(WhitespaceError { line: index + 1, kind });
+            }
+        }
+
+        if self.blank_at_eof {
+            if let Some(line) = self.trailing_blank_lines(contents) {
+                errors.push(WhitespaceError { line, kind: WhitespaceErrorKind::BlankAtEof });
+            }
+        }
+
+        if self.incomplete_line && !contents.is_empty() && !contents.ends_with('\n') {
+            let line = contents.lines().count();
+            errors.push(WhitespaceError { line, kind: WhitespaceErrorKind::IncompleteLine });
+        }
+
+        errors
+    }
+
+    /// Finds the whitespace errors in a single line (without the trailing newline).
+    pub fn check_line(&self, line: &str) -> Vec<WhitespaceErrorKind> {
+        let mut errors = Vec::new();
+        let line = self.strip_cr(line);
+        let indent = indentation(line);
+
+        if self.blank_at_eol && line.ends_with(|ch: char| ch.is_ascii_whitespace()) {
+            errors.push(WhitespaceErrorKind::TrailingWhitespace);
+        }
+        if self.space_before_tab && indent.contains(" \t") {
+            errors.push(WhitespaceErrorKind::SpaceBeforeTab);
+        }
+        if self.indent_with_non_tab && indent.contains(&" ".repeat(self.tab_width)) {
+            errors.push(WhitespaceErrorKind::IndentWithNonTab);
+        }
+        if self.tab_in_indent && indent.contains('\t') {
+            errors.push(WhitespaceErrorKind::TabInIndent);
+        }
+
+        errors
+    }
+
+    /// Returns a copy of `contents` with the whitespace errors fixed.
+    pub fn fix(&self, contents: &str) -> String {
+        let mut fixed = String::with_capacity(contents.len());
+
+        for line in contents.split_inclusive('\n') {
+            let (line, newline) = match line.strip_suffix('\n') {
+                Some(line) => (line, "\n"),
+                None => (line, ""),
+            };
+            fixed.push_str(&self.fix_line(line));
+            fixed.push_str(newline);
+        }
+
+        if self.blank_at_eof {
+            if let Some(line) = self.trailing_blank_lines(&fixed) {
+                let keep_len: usize = fixed.split_inclusive('\n')
+                    .take(line - 1)
+                    .map(str::len)
+                    .sum();
+                fixed.truncate(keep_len);
+            }
+        }
+
+        if self.incomplete_line && !fixed.is_empty() && !fixed.ends_with('\n') {
+            fixed.push('\n');
+        }
+
+        fixed
+    }
+
+    /// Returns a copy of `line` (without the trailing newline) with the whitespace
+    /// errors fixed.
+    pub fn fix_line(&self, line: &str) -> String {
+        let errors = self.check_line(line);
+        let (line, cr) = match self.cr_at_eol {
+            true => match line.strip_suffix('\r') {
+                Some(line) => (line, "\r"),
+                None => (line, ""),
+            },
+            false => (line, ""),
+        };
+
+        let mut body = line;
+        if errors.contains(&WhitespaceErrorKind::TrailingWhitespace) {
+            body = body.trim_end_matches(|ch: char| ch.is_ascii_whitespace());
+        }
+
+        let indent = indentation(body);
+        let rest = &body[indent.len()..];
+        let width = self.indent_width(indent);
+
+        let indent = if errors.contains(&WhitespaceErrorKind::TabInIndent) {
+            " ".repeat(width)
+        }
+        else if errors.contains(&WhitespaceErrorKind::SpaceBeforeTab)
+            || errors.contains(&WhitespaceErrorKind::IndentWithNonTab)
+        {
+            format!("{}{}", "\t".repeat(width / self.tab_width), " ".repeat(width % self.tab_width))
+        }
+        else {
+            indent.to_owned()
+        };
+
+        format!("{indent}{rest}{cr}")
+    }
+
+    /// Returns the line number of the first of the blank lines at the end of `contents`,
+    /// if there are any.
+    fn trailing_blank_lines(&self, contents: &str) -> Option<usize> {
+        let lines: Vec<&str> = contents.lines().collect();
+        let blank_count = lines.iter()
+            .rev()
+            .take_while(|line| self.strip_cr(line).trim().is_empty())
+            .count();
+
+        match blank_count {
+            0 => None,
+            _ => Some(lines.len() - blank_count + 1),
+        }
+    }
+
+    /// Removes a trailing carriage return from `line` if `cr-at-eol` is enabled.
+    fn strip_cr<'a>(&self, line: &'a str) -> &'a str {
+        match self.cr_at_eol {
+            true => line.strip_suffix('\r').unwrap_or(line),
+            false => line,
+        }
+    }
+
+    /// Calculates the number of columns occupied by `indent`.
+    fn indent_width(&self, indent: &str) -> usize {
+        indent.chars().fold(0, |width, ch| match ch {
+            '\t' => (width / self.tab_width + 1) * self.tab_width,
+            _ => width + 1,
+        })
+    }
+}
+
+impl fmt::Display for WhitespaceErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Self::TrailingWhitespace => "trailing whitespace",
+            Self::SpaceBeforeTab => "space before tab in indent",
+            Self::IndentWithNonTab => "indent with spaces",
+            Self::TabInIndent => "tab in indent",
+            Self::BlankAtEof => "new blank line at EOF",
+            Self::IncompleteLine => "no newline at end of file",
+        };
+
+        write!(f, "{message}")
+    }
+}
+
+/// Finds the whitespace errors introduced by the changes staged in `index` relative to
+/// the commit `head`. Binary files are skipped.
+///
+/// A line counts as introduced if it doesn't appear anywhere in the old version of the file.
+pub fn check_staged(wd: &WorkDir, index: &Index, head: Option<&ObjectHash>, rules: &WhitespaceRules) -> Result<Vec<(WorkPathBuf, WhitespaceError)>> {
+    let head_tree = head.map(|hash| Tree::read_tree_ish(wd, hash)).transpose()?;
+    let mut errors = Vec::new();
+
+    for change in index.list_staged_changes(wd, head, &WorkPathBuf::root())? {
+        let path = match change {
+            StagedChange::Created { path } | StagedChange::Modified { path } => path,
+            StagedChange::Deleted { .. } => continue,
+        };
+        let Some(entry) = index.entries.get(&path).filter(|entry| !entry.is_gitlink()) else {
+            continue;
+        };
+        let Some(new_contents) = read_text(wd, &entry.hash)? else {
+            continue;
+        };
+
+        let old_entry = match &head_tree {
+            Some(tree) => tree.find_entry(wd, &path)?,
+            None => None,
+        };
+        let old_contents = match old_entry {
+            Some(entry) if !entry.is_gitlink() => read_text(wd, &entry.hash)?.unwrap_or_default(),
+            _ => String::new(),
+        };
+        let old_lines: HashSet<&str> = old_contents.lines().collect();
+        let old_errors = rules.check(&old_contents);
+
+        let new_lines: Vec<&str> = new_contents.lines().collect();
+        for error in rules.check(&new_contents) {
+            let is_new = match error.kind {
+                WhitespaceErrorKind::BlankAtEof | WhitespaceErrorKind::IncompleteLine => {
+                    !old_errors.iter().any(|old_error| old_error.kind == error.kind)
+                },
+                _ => !old_lines.contains(new_lines[error.line - 1]),
+            };
+
+            if is_new {
+                errors.push((path.clone(), error));
+            }
+        }
+    }
+
+    Ok(errors)
+}
+
+/// Reads the blob `hash` as text. Returns `None` if it looks like a binary file.
+fn read_text(wd: &WorkDir, hash: &ObjectHash) -> Result<Option<String>> {
+    let data = Blob::read(wd, hash)?.serialize_into();
+    if data.contains(&0) {
+        return Ok(None);
+    }
+
+    Ok(String::from_utf8(data).ok())
+}
+
+/// Returns the leading spaces and tabs of `line`.
+fn indentation(line: &str) -> &str {
+    let len = line.find(|ch| ch != ' ' && ch != '\t').unwrap_or(line.len());
+    &line[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use WhitespaceErrorKind::*;
+
+    #[test]
+    fn check_finds_errors() {
+        let rules = WhitespaceRules::parse("tab-in-indent,incomplete-line").unwrap();
+        let errors = rules.check("ok\ntrailing \n \tmixed\n\n\nend");
+        let kinds: Vec<_> = errors.iter().map(|error| (error.line, error.kind)).collect();
+
+        assert_eq!(kinds, [
+            (2, TrailingWhitespace),
+            (3, SpaceBeforeTab),
+            (3, TabInIndent),
+            (6, IncompleteLine),
+        ]);
+
+        let errors = WhitespaceRules::default().check("a\n\n\n");
+        assert_eq!(errors, [WhitespaceError { line: 2, kind: BlankAtEof }]);
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rules = WhitespaceRules::parse("-trailing-space,indent-with-non-tab,tabwidth=4,bogus").unwrap();
+        assert!(!rules.blank_at_eol);
+        assert!(!rules.blank_at_eof);
+        assert!(rules.space_before_tab);
+        assert!(rules.indent_with_non_tab);
+        assert_eq!(rules.tab_width, 4);
+
+        assert_eq!(WhitespaceRules::parse("tabwidth=63").unwrap().tab_width, 63);
+        for width in ["0", "64", "-1", "four", ""] {
+            assert!(WhitespaceRules::parse(&format!("tabwidth={width}")).is_err(), "{width}");
+        }
+    }
+
+    #[test]
+    fn fix_corrects_errors() {
+        let rules = WhitespaceRules::parse("indent-with-non-tab,incomplete-line").unwrap();
+        assert_eq!(rules.fix("a  \n  \tb\n        c\n\n\nd"), "a\n\tb\n\tc\n\n\nd\n");
+        assert_eq!(rules.fix("a\n\n \n"), "a\n");
+
+        let rules = WhitespaceRules::parse("tab-in-indent,cr-at-eol").unwrap();
+        assert_eq!(rules.fix_line("\tx \r"), "        x\r");
+    }
+}
@@ -1,10 +1,12 @@
 use std::{
     path::{Path, PathBuf},
     fs::{self, File, OpenOptions},
+    io::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 use path_absolutize::Absolutize;
 
-use crate::Result;
+use crate::{Result, object::FileMode};
 
 mod error;
 pub use error::WorkDirError;
@@ -13,22 +15,74 @@ mod workpath;
 pub use workpath::WorkPath;
 pub use workpath::WorkPathBuf;
 
+mod settings;
+pub use settings::Settings;
+
+mod windows;
+
 /// The working directory of a Git repository.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct WorkDir(PathBuf);
+pub struct WorkDir {
+    path: PathBuf,
+    /// Usually `<path>/.git`, unless `.git` is a file pointing somewhere else.
+    git_dir: PathBuf,
+    settings: Settings,
+}
 
 impl WorkDir {
     pub fn new<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path>
     {
-        Ok(Self(
-            path.as_ref().absolutize()?.into()
-        ))
+        let path: PathBuf = path.as_ref().absolutize()?.into();
+
+        Ok(Self {
+            git_dir: path.join(".git"),
+            path,
+            settings: Settings::default(),
+        })
+    }
+
+    /// Constructs a `WorkDir` whose git directory is at `git_dir` instead of in `path`, as
+    /// with `init --separate-git-dir`.
+    pub fn with_git_dir<P, Q>(path: P, git_dir: Q) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        Ok(Self {
+            path: path.as_ref().absolutize()?.into(),
+            git_dir: git_dir.as_ref().absolutize()?.into(),
+            settings: Settings::default(),
+        })
     }
 
     pub fn as_path(&self) -> &Path {
-        &self.0
+        &self.path
+    }
+
+    /// Returns the path of the repo's git directory.
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// Returns the settings (usually loaded from the repo's config) that affect how
+    /// files are handled.
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    /// Fails with [`WorkDirError::ReadOnly`] if the repository was opened read-only. Every
+    /// function that modifies the repository or the working directory calls this first.
+    pub fn check_writable(&self) -> Result<()> {
+        match self.settings.read_only {
+            true => Err(WorkDirError::ReadOnly(self.path.clone()).into()),
+            false => Ok(()),
+        }
     }
 
     /// Returns true if `path` is suitable for creating a new repository (empty or
@@ -58,7 +112,7 @@ impl WorkDir {
         P: AsRef<Path>
     {
         let abs_path = path.as_ref().absolutize()?;
-        let rel_path = match abs_path.strip_prefix(&self.0) {
+        let rel_path = match abs_path.strip_prefix(&self.path) {
             Ok(val) => val,
             Err(_) => return Err(WorkDirError::OutsideWorkingDir(path.as_ref().to_owned()).into()),
         };
@@ -66,12 +120,74 @@ impl WorkDir {
         WorkPathBuf::try_from(rel_path)
     }
 
+    /// Returns the absolute path of `path` on the file system. On Windows, a path that's
+    /// too long for the usual APIs is given the `\\?\` prefix.
+    pub fn abs_path(&self, path: &WorkPath) -> PathBuf {
+        let abs_path = self.path.join(path.to_fs_path());
+        match cfg!(windows) && abs_path.as_os_str().len() >= windows::MAX_PATH {
+            true => windows::extended_length_path(&abs_path),
+            false => abs_path,
+        }
+    }
+
+    /// Fails if `path` shouldn't be written to the working directory. With
+    /// [`Settings::protect_ntfs`], that's any path with a component that isn't safe on NTFS.
+    pub fn check_worktree_path(&self, path: &WorkPath) -> Result<()> {
+        if !self.settings.protect_ntfs {
+            return Ok(());
+        }
+
+        match windows::unsafe_ntfs_component(path) {
+            Some(component) => Err(WorkDirError::UnsafeOnNtfs {
+                path: path.as_ref().to_owned(),
+                component: component.to_owned(),
+            }.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Creates the directory at `path` in the working directory, along with its parents.
+    pub fn create_worktree_dir(&self, path: &WorkPath) -> Result<()> {
+        self.check_writable()?;
+        self.check_worktree_path(path)?;
+        self.create_leading_dirs(path, true)
+    }
+
+    /// Creates the directories leading to `path`, and `path` itself if `include_last` is
+    /// set. Like git, a symlink in the way is replaced with a directory rather than
+    /// followed, so that a tree can't write outside the working directory through it.
+    fn create_leading_dirs(&self, path: &WorkPath, include_last: bool) -> Result<()> {
+        let fs_path = path.to_fs_path();
+        let mut components: Vec<_> = fs_path.components().collect();
+        if !include_last {
+            components.pop();
+        }
+
+        let mut abs_path = self.path.clone();
+        for component in components {
+            abs_path.push(component);
+            match fs::symlink_metadata(&abs_path) {
+                Ok(metadata) if metadata.is_dir() => continue,
+                Ok(metadata) if metadata.is_symlink() => {
+                    // A symlink to a directory is a directory on Windows
+                    fs::remove_file(&abs_path).or_else(|_| fs::remove_dir(&abs_path))?;
+                },
+                Ok(_) => {},
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+                Err(err) => return Err(err.into()),
+            }
+            fs::create_dir(&abs_path)?;
+        }
+
+        Ok(())
+    }
+
     /// Appends a relative path to the repo's .git directory.
     pub fn git_path<P>(&self, rel_path: P) -> PathBuf
     where
         P: AsRef<Path>
     {
-        let mut path = self.0.join(".git");
+        let mut path = self.git_dir.clone();
         path.push(rel_path);
 
         path
@@ -101,24 +217,204 @@ impl WorkDir {
     where
         P: AsRef<Path>
     {
+        self.check_writable()?;
+
         let abs_path = self.git_path(rel_path);
         fs::create_dir_all(&abs_path)?;
         
         Ok(abs_path)
     }
 
+    /// Replaces the file at `rel_path` in the repo's .git directory with `data`, creating
+    /// its parent directories if needed.
+    ///
+    /// The data is written to `<file>.lock` first and then renamed into place, so readers
+    /// never see a partly written file, and two processes can't write the same file at once.
+    /// With `core.fsyncObjectFiles`, the data is also flushed to disk before the rename.
+    pub fn write_git_file<P>(&self, rel_path: P, data: &[u8]) -> Result<()>
+    where
+        P: AsRef<Path>
+    {
+        self.write_git_files([(rel_path.as_ref(), data)])
+    }
+
+    /// Replaces several files in the repo's .git directory, as with
+    /// [`WorkDir::write_git_file`]. Every lock file is written (and closed) before any of
+    /// them is renamed, so either all of the files are replaced or none of them are.
+    pub fn write_git_files<'a, I>(&self, files: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a Path, &'a [u8])>
+    {
+        self.check_writable()?;
+
+        // The lock files created so far, with the paths they'll be renamed to
+        let mut locked: Vec<(PathBuf, PathBuf)> = vec![];
+        let mut renamed = 0;
+        let result = (|| {
+            for (rel_path, data) in files {
+                let abs_path = self.git_path(rel_path);
+                if let Some(dir_path) = abs_path.parent() {
+                    fs::create_dir_all(dir_path)?;
+                }
+
+                let mut lock_path = abs_path.clone().into_os_string();
+                lock_path.push(".lock");
+                let lock_path = PathBuf::from(lock_path);
+
+                let mut lock_file = match OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+                    Ok(file) => file,
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                        return Err(WorkDirError::Locked(lock_path).into());
+                    },
+                    Err(err) => return Err(err.into()),
+                };
+                locked.push((lock_path, abs_path));
+
+                // Each file is closed once written, so a large batch can't run out of
+                // file descriptors
+                lock_file.write_all(data)?;
+                if self.settings.fsync_object_files {
+                    lock_file.sync_all()?;
+                }
+            }
+
+            for (lock_path, abs_path) in &locked {
+                fs::rename(lock_path, abs_path)?;
+                renamed += 1;
+            }
+
+            Ok(())
+        })();
+
+        if result.is_err() {
+            for (lock_path, _) in &locked[renamed..] {
+                let _ = fs::remove_file(lock_path);
+            }
+        }
+
+        result
+    }
+
+    /// Writes the loose object file at `rel_path` in the repo's .git directory, creating its
+    /// parent directories if needed.
+    ///
+    /// Unlike [`WorkDir::write_git_file`], there's no lock file: the data goes to a uniquely
+    /// named temporary file beside the object, which is then renamed into place. An object's
+    /// contents are determined by its name, so processes writing the same object at once
+    /// can all succeed, and one that finds the object already there is done.
+    pub fn write_object_file<P>(&self, rel_path: P, data: &[u8]) -> Result<()>
+    where
+        P: AsRef<Path>
+    {
+        self.check_writable()?;
+
+        let abs_path = self.git_path(rel_path);
+        let dir_path = abs_path.parent().expect("object paths should have a fan-out directory");
+        fs::create_dir_all(dir_path)?;
+
+        let (mut temp_file, temp_path) = create_temp_file(dir_path)?;
+        let result = (|| {
+            temp_file.write_all(data)?;
+            if self.settings.fsync_object_files {
+                temp_file.sync_all()?;
+            }
+            drop(temp_file);
+
+            match fs::rename(&temp_path, &abs_path) {
+                // Another process wrote it first
+                Err(_) if abs_path.is_file() => Ok(()),
+                result => result,
+            }
+        })();
+
+        // The temporary file is still there if something failed or another process won
+        if temp_path.exists() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        Ok(result?)
+    }
+
+    /// Replaces the file at `path` in the working directory with `data`, creating its
+    /// parent directories if needed. On Unix, the file is made executable if `mode` is
+    /// [`FileMode::Executable`], and not executable otherwise (subject to the umask). Fails
+    /// if [`check_worktree_path`](Self::check_worktree_path) rejects `path`.
+    pub fn write_worktree_file(&self, path: &WorkPath, data: &[u8], mode: FileMode) -> Result<()> {
+        self.check_writable()?;
+        self.check_worktree_path(path)?;
+
+        self.create_leading_dirs(path, false)?;
+        let abs_path = self.abs_path(path);
+
+        // An old symlink is replaced rather than written through, and an old file has to
+        // make way for a new symlink
+        let is_link = mode == FileMode::Symlink && self.settings.symlinks;
+        if let Ok(metadata) = fs::symlink_metadata(&abs_path) {
+            if metadata.is_symlink() || (is_link && metadata.is_file()) {
+                fs::remove_file(&abs_path)?;
+            }
+        }
+
+        if is_link {
+            create_symlink(data, &abs_path)?;
+            return Ok(());
+        }
+
+        fs::write(&abs_path, data)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mut permissions = fs::metadata(&abs_path)?.permissions();
+            let bits = match mode {
+                FileMode::Executable => permissions.mode() | (permissions.mode() & 0o444) >> 2,
+                _ => permissions.mode() & !0o111,
+            };
+            if bits != permissions.mode() {
+                permissions.set_mode(bits);
+                fs::set_permissions(&abs_path, permissions)?;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        Ok(())
+    }
+
+    /// Reads the file at `path` in the working directory. If it's a symlink, its target is
+    /// returned instead, which is what git stores for it.
+    pub fn read_worktree_file(&self, path: &WorkPath) -> Result<Vec<u8>> {
+        let abs_path = self.abs_path(path);
+        if !fs::symlink_metadata(&abs_path)?.is_symlink() {
+            return Ok(fs::read(&abs_path)?);
+        }
+
+        let target = fs::read_link(&abs_path)?;
+        #[cfg(unix)]
+        let target = {
+            use std::os::unix::ffi::OsStrExt;
+            target.as_os_str().as_bytes().to_vec()
+        };
+        #[cfg(not(unix))]
+        let target = target.to_string_lossy().replace('\\', "/").into_bytes();
+
+        Ok(target)
+    }
+
     /// Removes the file or directory at `path` from the file system.
     pub fn remove_path(&self, path: &WorkPath) -> Result<()> {
-        let abs_path = self.0.join(path);
+        self.check_writable()?;
+
+        let abs_path = self.abs_path(path);
 
         if path.is_empty() {
-            // Delete everything except the .git directory (if present)
+            // Delete everything except the .git directory or file (if present)
             // Note that any .git directories in subdirectories will be deleted
             for entry in abs_path.read_dir()? {
                 let entry = entry?;
                 let entry_path = entry.path();
                 
-                if entry_path.is_file() {
+                if entry_path.is_file() && entry.file_name() != ".git" {
                     std::fs::remove_file(&entry_path)?;
                 }
                 else if entry_path.is_dir() && entry.file_name() != ".git" {
@@ -135,6 +431,93 @@ impl WorkDir {
 
         Ok(())
     }
+
+    /// Deletes the tracked files at `paths`, then any directories left empty by their
+    /// removal. Unlike [`WorkDir::remove_path`], untracked and ignored files are never
+    /// touched, so a directory that still has one (such as a `.gitkeep` that was never
+    /// added) is kept. A directory found where a file was tracked (e.g. a submodule's
+    /// placeholder) is only removed if it's empty.
+    pub fn remove_tracked<'a, I>(&self, paths: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a WorkPathBuf>,
+    {
+        self.check_writable()?;
+
+        for path in paths {
+            let abs_path = self.abs_path(path);
+
+            match fs::symlink_metadata(&abs_path) {
+                Ok(metadata) if metadata.is_dir() => {
+                    if is_empty_dir(&abs_path)? {
+                        fs::remove_dir(&abs_path)?;
+                    }
+                },
+                Ok(_) => fs::remove_file(&abs_path)?,
+                Err(_) => (),
+            }
+
+            self.prune_empty_dirs(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the directories containing `path` that are empty, starting with the
+    /// innermost one. The working directory itself is never removed.
+    pub fn prune_empty_dirs(&self, path: &WorkPath) -> Result<()> {
+        let mut current = path;
+        while let Some(parent) = current.parent() {
+            if parent.is_empty() {
+                break;
+            }
+
+            let abs_path = self.abs_path(parent);
+            if !abs_path.is_dir() || !is_empty_dir(&abs_path)? {
+                break;
+            }
+
+            fs::remove_dir(&abs_path)?;
+            current = parent;
+        }
+
+        Ok(())
+    }
+}
+
+fn is_empty_dir(path: &Path) -> Result<bool> {
+    Ok(path.read_dir()?.next().is_none())
+}
+
+/// Creates a symlink at `link` that points to `target`.
+/// Creates a new file with a name no other thread or process is using in `dir`, for data
+/// that will be renamed into place once it's written.
+pub(crate) fn create_temp_file(dir: &Path) -> io::Result<(File, PathBuf)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    loop {
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("tmp_obj_{}_{count}", std::process::id()));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            // Left behind by an earlier process with the same ID
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &[u8], link: &Path) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), link)
+}
+
+/// Symlinks are only supported on Unix, so elsewhere the link is written as a file
+/// containing its target.
+#[cfg(not(unix))]
+fn create_symlink(target: &[u8], link: &Path) -> io::Result<()> {
+    fs::write(link, target)
 }
 
 impl TryFrom<PathBuf> for WorkDir {
@@ -172,6 +555,7 @@ impl TryFrom<&str> for WorkDir {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::temp_workdir;
 
     #[test]
     fn canonicalize_rel_path() {
@@ -213,4 +597,168 @@ mod tests {
 
         assert_eq!(components, expected_components);
     }
+
+    #[test]
+    fn remove_tracked_prunes_empty_dirs() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        for path in ["a/b/tracked.txt", "a/c/tracked.txt", "a/c/untracked.txt", "d/tracked.txt"] {
+            let path = temp_dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::create_dir(temp_dir.join("empty")).unwrap();
+        let wd = WorkDir::new(temp_dir.path()).unwrap();
+
+        let tracked: Vec<WorkPathBuf> = ["a/b/tracked.txt", "a/c/tracked.txt", "empty", "missing/file.txt"]
+            .into_iter()
+            .map(|path| path.try_into().unwrap())
+            .collect();
+        wd.remove_tracked(&tracked).unwrap();
+
+        assert!(!temp_dir.join("a/b").exists());
+        assert!(!temp_dir.join("a/c/tracked.txt").exists());
+        assert!(temp_dir.join("a/c/untracked.txt").exists());
+        assert!(!temp_dir.join("empty").exists());
+        assert!(temp_dir.join("d/tracked.txt").exists());
+    }
+
+    #[test]
+    fn write_git_file_respects_lock() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        wd.write_git_file("refs/heads/main", b"old\n").unwrap();
+        wd.write_git_file("refs/heads/main", b"new\n").unwrap();
+        assert_eq!(fs::read_to_string(wd.git_path("refs/heads/main")).unwrap(), "new\n");
+        assert!(!wd.git_path("refs/heads/main.lock").exists());
+
+        fs::write(wd.git_path("refs/heads/main.lock"), "").unwrap();
+        let err = wd.write_git_file("refs/heads/main", b"newer\n").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(WorkDirError::Locked(_))));
+        assert_eq!(fs::read_to_string(wd.git_path("refs/heads/main")).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn write_object_file_tolerates_concurrent_writers() {
+        let (_temp_dir, wd) = temp_workdir();
+        let path = Path::new("objects/ab/cdef");
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| wd.write_object_file(path, b"data").unwrap());
+            }
+        });
+        // A leftover lock file from a crashed writer doesn't get in the way either
+        fs::write(wd.git_path("objects/ab/cdef.lock"), "").unwrap();
+        wd.write_object_file(path, b"data").unwrap();
+
+        assert_eq!(fs::read(wd.git_path(path)).unwrap(), b"data");
+        let mut names: Vec<_> = fs::read_dir(wd.git_path("objects/ab")).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["cdef", "cdef.lock"]);
+    }
+
+    #[test]
+    fn write_git_files_closes_each_file() {
+        let (_temp_dir, wd) = temp_workdir();
+
+        // More files than the usual limit on open file descriptors
+        let paths: Vec<PathBuf> = (0..25_000).map(|i| PathBuf::from(format!("many/{i}"))).collect();
+        wd.write_git_files(paths.iter().map(|path| (path.as_path(), &b"data\n"[..]))).unwrap();
+        assert_eq!(fs::read_dir(wd.git_path("many")).unwrap().count(), paths.len());
+    }
+
+    #[test]
+    fn read_only_refuses_writes() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let mut wd = WorkDir::new(temp_dir.path()).unwrap();
+        wd.set_settings(Settings {
+            read_only: true,
+            ..Settings::default()
+        });
+
+        let path = WorkPathBuf::try_from("file.txt").unwrap();
+        for result in [
+            wd.write_git_file("HEAD", b"ref: refs/heads/main\n"),
+            wd.write_worktree_file(&path, b"data\n", FileMode::Regular),
+            wd.make_git_dir("objects").map(|_| ()),
+        ] {
+            assert!(matches!(result.unwrap_err().downcast_ref(), Some(WorkDirError::ReadOnly(_))));
+        }
+        assert!(!temp_dir.join(".git").exists());
+        assert!(!temp_dir.join("file.txt").exists());
+    }
+
+    #[test]
+    fn protect_ntfs_refuses_unsafe_names() {
+        let (temp_dir, mut wd) = temp_workdir();
+        let path = WorkPathBuf::try_from("src/aux.c").unwrap();
+
+        wd.set_settings(Settings { protect_ntfs: true, ..Settings::default() });
+        for result in [
+            wd.write_worktree_file(&path, b"data\n", FileMode::Regular),
+            wd.create_worktree_dir(&path),
+        ] {
+            assert!(matches!(result.unwrap_err().downcast_ref(), Some(WorkDirError::UnsafeOnNtfs { .. })));
+        }
+        assert!(!temp_dir.join("src").exists());
+
+        #[cfg(unix)]
+        {
+            wd.set_settings(Settings { protect_ntfs: false, ..Settings::default() });
+            wd.write_worktree_file(&path, b"data\n", FileMode::Regular).unwrap();
+            assert!(temp_dir.join("src/aux.c").is_file());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_worktree_file_sets_executable_bit() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (temp_dir, wd) = temp_workdir();
+        let path = WorkPathBuf::try_from("bin/run.sh").unwrap();
+        let is_executable = || fs::metadata(temp_dir.join("bin/run.sh")).unwrap().permissions().mode() & 0o100 != 0;
+
+        wd.write_worktree_file(&path, b"#!/bin/sh\n", FileMode::Executable).unwrap();
+        assert!(is_executable());
+
+        wd.write_worktree_file(&path, b"data\n", FileMode::Regular).unwrap();
+        assert!(!is_executable());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_worktree_file_creates_symlinks() {
+        let (temp_dir, wd) = temp_workdir();
+        let path = WorkPathBuf::try_from("link").unwrap();
+
+        wd.write_worktree_file(&path, b"target.txt", FileMode::Symlink).unwrap();
+        assert_eq!(fs::read_link(temp_dir.join("link")).unwrap(), Path::new("target.txt"));
+        assert_eq!(wd.read_worktree_file(&path).unwrap(), b"target.txt");
+
+        // A file replaces the symlink rather than being written through it
+        wd.write_worktree_file(&path, b"data\n", FileMode::Regular).unwrap();
+        assert!(fs::symlink_metadata(temp_dir.join("link")).unwrap().is_file());
+        assert!(!temp_dir.join("target.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_worktree_file_replaces_symlinked_dirs() {
+        let (temp_dir, wd) = temp_workdir();
+        fs::create_dir(temp_dir.join("outside")).unwrap();
+        std::os::unix::fs::symlink(temp_dir.join("outside"), temp_dir.join("dir")).unwrap();
+        std::os::unix::fs::symlink(temp_dir.join("outside"), temp_dir.join("other")).unwrap();
+
+        wd.write_worktree_file(&WorkPathBuf::try_from("dir/file.txt").unwrap(), b"data\n", FileMode::Regular).unwrap();
+        wd.create_worktree_dir(&WorkPathBuf::try_from("other/sub").unwrap()).unwrap();
+
+        assert!(!temp_dir.join("outside/file.txt").exists());
+        assert!(!temp_dir.join("outside/sub").exists());
+        assert!(fs::symlink_metadata(temp_dir.join("dir")).unwrap().is_dir());
+        assert_eq!(fs::read(temp_dir.join("dir/file.txt")).unwrap(), b"data\n");
+        assert!(temp_dir.join("other/sub").is_dir());
+    }
 }
@@ -9,10 +9,19 @@ pub enum WorkDirError {
         path: PathBuf,
         component: String,
     },
+    #[error("Refusing to check out `{path}` because `{component}` isn't a safe file name on NTFS (see core.protectNTFS)")]
+    UnsafeOnNtfs {
+        path: PathBuf,
+        component: String,
+    },
     #[error("Invalid unicode in path `{0:?}`")]
     InvalidUnicode(OsString),
     #[error("Workpaths must be relative, but `{0:?}` is absolute")]
     AbsolutePath(PathBuf),
     #[error("The path `{0:?}` is outside of the working directory")]
     OutsideWorkingDir(PathBuf),
+    #[error("Unable to create `{0:?}` because it already exists. Another process may be writing to the repository; if not, remove the file and try again.")]
+    Locked(PathBuf),
+    #[error("The repository at `{0:?}` was opened read-only, so it can't be modified")]
+    ReadOnly(PathBuf),
 }
@@ -0,0 +1,69 @@
+/// Settings that affect how files in the repository are handled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// Files larger than this many bytes are streamed into the object store
+    /// without compression (`core.bigFileThreshold`).
+    pub big_file_threshold: u64,
+    /// If set, refs are read from and written to `refs/namespaces/<namespace>/`
+    /// instead of `refs/` (`GIT_NAMESPACE`).
+    pub namespace: Option<String>,
+    /// If set, the file system is assumed to be case-insensitive, so a file whose name
+    /// only differs in case from a tracked path is treated as that path renamed
+    /// (`core.ignoreCase`).
+    pub ignore_case: bool,
+    /// If set, files written to the `.git` directory are flushed to disk before they
+    /// replace the old version (`core.fsyncObjectFiles`).
+    pub fsync_object_files: bool,
+    /// If unset, differences in ctime don't make a file look modified, for file systems
+    /// that change it behind git's back (`core.trustctime`).
+    pub trust_ctime: bool,
+    /// If set, only whole seconds of the timestamps, the size, and the mode are compared
+    /// when checking whether a file changed, ignoring the inode, device, and owner
+    /// (`core.checkStat = minimal`).
+    pub minimal_stat_check: bool,
+    /// If set, anything that would modify the repository or the working directory fails
+    /// with [`WorkDirError::ReadOnly`](super::WorkDirError::ReadOnly) instead.
+    pub read_only: bool,
+    /// If set, checking out a path that isn't safe on NTFS, like a reserved device name
+    /// or an alias of `.git`, fails instead of writing it (`core.protectNTFS`). On by
+    /// default on Windows.
+    pub protect_ntfs: bool,
+    /// If set, symlinks are checked out as symlinks. Otherwise, and always on platforms
+    /// other than Unix, each one is checked out as a file containing its target, and the
+    /// index keeps recording it as a symlink (`core.symlinks`).
+    pub symlinks: bool,
+    /// If set, `.git/info/sparse-checkout` decides which tracked files belong in the
+    /// working directory (`core.sparseCheckout`).
+    pub sparse_checkout: bool,
+    /// If set, the sparse checkout patterns only name directories, which is faster to
+    /// match (`core.sparseCheckoutCone`).
+    pub sparse_checkout_cone: bool,
+    /// Shared index files last used at or before this time (seconds since the Unix epoch)
+    /// are deleted when a new one is written (`splitIndex.sharedIndexExpire`). `None` means
+    /// the default of two weeks before the write.
+    pub shared_index_expire: Option<i64>,
+}
+
+impl Settings {
+    /// The default value of `core.bigFileThreshold` (512 MiB), same as git.
+    pub const DEFAULT_BIG_FILE_THRESHOLD: u64 = 512 * 1024 * 1024;
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            big_file_threshold: Self::DEFAULT_BIG_FILE_THRESHOLD,
+            namespace: None,
+            ignore_case: false,
+            fsync_object_files: false,
+            trust_ctime: true,
+            minimal_stat_check: false,
+            read_only: false,
+            protect_ntfs: cfg!(windows),
+            symlinks: cfg!(unix),
+            sparse_checkout: false,
+            sparse_checkout_cone: false,
+            shared_index_expire: None,
+        }
+    }
+}
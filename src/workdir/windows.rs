@@ -0,0 +1,92 @@
+//! File names that Windows handles specially, and paths too long for the usual APIs.
+
+use std::path::{Path, PathBuf};
+
+use super::WorkPath;
+
+/// Paths this long or longer need the `\\?\` prefix to be opened on Windows.
+pub const MAX_PATH: usize = 260;
+
+/// Device names that refer to a device instead of a file in any directory, even with an
+/// extension (e.g. `aux.c`).
+const RESERVED_NAMES: [&str; 24] = [
+    "con", "prn", "aux", "nul", "conin$", "conout$",
+    "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Returns the first component of `path` that isn't safe to write on NTFS, if any. That's
+/// a name Windows would treat as `.git` (like `GIT~1` or `.git.`), a reserved device name,
+/// a name ending in a dot or space (which Windows strips), or a name containing a
+/// character Windows doesn't allow.
+pub fn unsafe_ntfs_component(path: &WorkPath) -> Option<&str> {
+    path.as_str().split('/')
+        .filter(|name| !name.is_empty())
+        .find(|name| !is_ntfs_safe_name(name))
+}
+
+fn is_ntfs_safe_name(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    if lower == "git~1" || lower.trim_end_matches(['.', ' ']) == ".git" {
+        return false;
+    }
+
+    if name.ends_with(['.', ' ']) {
+        return false;
+    }
+
+    if name.chars().any(|ch| ch < ' ' || "<>:\"\\|?*".contains(ch)) {
+        return false;
+    }
+
+    let base = lower.split('.').next().unwrap_or_default().trim_end_matches(' ');
+    !RESERVED_NAMES.contains(&base)
+}
+
+/// Converts an absolute path to its extended-length form, which isn't limited to
+/// [`MAX_PATH`] characters. Paths that already have the prefix are returned as-is.
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    let Some(path) = path.to_str() else {
+        return path.to_owned();
+    };
+    if path.starts_with(r"\\?\") {
+        return PathBuf::from(path);
+    }
+
+    // The prefix turns off normalization, so every separator has to be a backslash
+    let path = path.replace('/', "\\");
+    match path.strip_prefix(r"\\") {
+        Some(share) => PathBuf::from(format!(r"\\?\UNC\{share}")),
+        None => PathBuf::from(format!(r"\\?\{path}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workdir::WorkPathBuf;
+
+    fn find(path: &str) -> Option<String> {
+        let path = WorkPathBuf::try_from(path).unwrap();
+        unsafe_ntfs_component(&path).map(str::to_owned)
+    }
+
+    #[test]
+    fn finds_unsafe_components() {
+        assert_eq!(find("src/main.rs"), None);
+        assert_eq!(find("docs/auxiliary.md"), None);
+        assert_eq!(find("src/aux.c"), Some("aux.c".to_owned()));
+        assert_eq!(find("COM1/readme"), Some("COM1".to_owned()));
+        assert_eq!(find("GIT~1/config"), Some("GIT~1".to_owned()));
+        assert_eq!(find(".git. /hooks"), Some(".git. ".to_owned()));
+        assert_eq!(find("notes."), Some("notes.".to_owned()));
+        assert_eq!(find("what?.txt"), Some("what?.txt".to_owned()));
+    }
+
+    #[test]
+    fn adds_extended_length_prefix() {
+        assert_eq!(extended_length_path(Path::new(r"C:\repo/src/main.rs")), Path::new(r"\\?\C:\repo\src\main.rs"));
+        assert_eq!(extended_length_path(Path::new(r"\\server\share\repo")), Path::new(r"\\?\UNC\server\share\repo"));
+        assert_eq!(extended_length_path(Path::new(r"\\?\C:\repo")), Path::new(r"\\?\C:\repo"));
+    }
+}
@@ -1,18 +1,30 @@
-use std::{ops::Deref, borrow::Borrow, path::{Path, PathBuf}, fmt, ffi::OsString};
+use std::{ops::Deref, borrow::{Borrow, Cow}, path::{Path, PathBuf}, fmt, ffi::OsString};
 
 use super::WorkDirError;
 
+/// Bytes in a path that aren't valid Utf-8 are stored as the character at this code point
+/// plus the byte, which puts them in the range U+EF80 to U+EFFF of the private use area.
+const ESCAPE_BASE: u32 = 0xEF00;
+
 /// A normalized path relative to a working directory.
 /// 
 /// Viewed as a string, a `WorkPath` is always valid Utf-8, always uses `/` as a path separator,
-/// never begins or ends with a slash, and never contains the components `.git`, `.`, or `..`.
+/// never begins or ends with a slash, and never contains the components `.`, `..`, or `.git`
+/// (in any case, since `.GIT` is the same directory on a case-insensitive file system).
+///
+/// Paths from trees, the index, or the file system aren't always Utf-8. Each byte that isn't
+/// part of a valid character is escaped as a private use character (see [`ESCAPE_BASE`]), and
+/// [`to_bytes`](Self::to_bytes) turns it back into the original byte.
+///
+/// Paths are ordered by those original bytes, the way git sorts them, rather than as strings,
+/// which would sort escaped bytes after every other character.
 #[repr(transparent)]
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug)]
 pub struct WorkPath(str);
 
 /// The owned variant of a [`WorkPath`].
 #[repr(transparent)]
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct WorkPathBuf(String);
 
 impl WorkPath {
@@ -22,11 +34,91 @@ impl WorkPath {
         std::mem::transmute(slice)
     }
 
+    /// Borrows `slice` as a `WorkPath` without copying it, provided it is already in
+    /// canonical form. Returns `None` if normalization would be required.
+    pub fn from_canonical(slice: &str) -> Option<&Self> {
+        if slice.is_empty() {
+            return unsafe { Some(Self::from_str(slice)) };
+        }
+
+        if slice.contains(['\\', ':']) {
+            return None;
+        }
+
+        let is_canonical = slice.split('/')
+            .all(|part| !part.is_empty() && !is_forbidden_component(part));
+
+        if is_canonical {
+            unsafe { Some(Self::from_str(slice)) }
+        }
+        else {
+            None
+        }
+    }
+
+    /// Like [`from_canonical`](Self::from_canonical), but for a path as it's stored in trees
+    /// and the index. Returns `None` if any bytes would need to be escaped.
+    pub fn from_canonical_bytes(bytes: &[u8]) -> Option<&Self> {
+        std::str::from_utf8(bytes).ok()
+            .filter(|slice| slice.is_ascii() || !slice.chars().any(|ch| unescape_char(ch).is_some()))
+            .and_then(Self::from_canonical)
+    }
+
     /// Returns true if this is the empty (or root) path.
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
 
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the path as it's stored in trees and the index, with any escaped bytes
+    /// restored.
+    pub fn to_bytes(&self) -> Cow<'_, [u8]> {
+        if !self.has_escapes() {
+            return Cow::Borrowed(self.0.as_bytes());
+        }
+
+        let mut bytes = Vec::with_capacity(self.0.len());
+        for ch in self.0.chars() {
+            match unescape_char(ch) {
+                Some(byte) => bytes.push(byte),
+                None => bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+
+        Cow::Owned(bytes)
+    }
+
+    /// Returns the path as the file system sees it. On Unix, escaped bytes are restored, so a
+    /// file can be written with the same name it has in the tree.
+    pub fn to_fs_path(&self) -> Cow<'_, Path> {
+        #[cfg(unix)]
+        if self.has_escapes() {
+            use std::os::unix::ffi::OsStringExt;
+
+            let bytes = self.to_bytes().into_owned();
+            return Cow::Owned(PathBuf::from(OsString::from_vec(bytes)));
+        }
+
+        Cow::Borrowed(self.0.as_ref())
+    }
+
+    /// Formats the path with `prefix` in front. If the path is quoted, the prefix goes inside
+    /// the quotes, the way git writes the `a/` and `b/` paths in a diff.
+    pub fn to_string_with_prefix(&self, prefix: &str) -> String {
+        match self.has_escapes() {
+            true => format!("\"{prefix}{}", &self.to_string()[1..]),
+            false => format!("{prefix}{}", &self.0),
+        }
+    }
+
+    /// Returns true if the path contains bytes that weren't valid Utf-8.
+    fn has_escapes(&self) -> bool {
+        !self.0.is_ascii() && self.0.chars().any(|ch| unescape_char(ch).is_some())
+    }
+
     pub fn strip_prefix(&self, prefix: &WorkPath) -> Option<&Self> {
         if prefix.is_empty() {
             Some(self)
@@ -106,9 +198,15 @@ impl WorkPath {
     }
 }
 
-impl Borrow<str> for WorkPath {
-    fn borrow(&self) -> &str {
-        &self.0
+impl Ord for WorkPath {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.to_bytes().cmp(&other.to_bytes())
+    }
+}
+
+impl PartialOrd for WorkPath {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -132,12 +230,81 @@ impl PartialEq<str> for WorkPath {
     }
 }
 
+/// Displays the path as-is, unless it contains escaped bytes. Then it's quoted the way git
+/// quotes paths when `core.quotePath` is set, with each escaped byte written in octal.
 impl fmt::Display for WorkPath {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", &self.0)
+        if !self.has_escapes() {
+            return write!(f, "{}", &self.0);
+        }
+
+        write!(f, "\"")?;
+        for ch in self.0.chars() {
+            match (unescape_char(ch), ch) {
+                (Some(byte), _) => write!(f, "\\{byte:03o}")?,
+                (None, '"' | '\\') => write!(f, "\\{ch}")?,
+                (None, _) => write!(f, "{ch}")?,
+            }
+        }
+        write!(f, "\"")
     }
 }
 
+/// Returns true if `part` can't be a component of a `WorkPath`.
+fn is_forbidden_component(part: &str) -> bool {
+    part == "." || part == ".." || part.eq_ignore_ascii_case(".git")
+}
+
+/// Returns the byte that `ch` stands for, if it's an escaped byte.
+fn unescape_char(ch: char) -> Option<u8> {
+    (ch as u32).checked_sub(ESCAPE_BASE)
+        .filter(|byte| (0x80..=0xFF).contains(byte))
+        .map(|byte| byte as u8)
+}
+
+/// Converts `bytes` to a string, escaping each byte that isn't part of a valid character.
+/// Characters that are themselves in the escape range are escaped byte by byte too, so that
+/// [`WorkPath::to_bytes`] always gives back the original bytes.
+fn escape_bytes(mut bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        if !text.chars().any(|ch| unescape_char(ch).is_some()) {
+            return Cow::Borrowed(text);
+        }
+    }
+
+    let escape = |byte: u8| char::from_u32(ESCAPE_BASE + byte as u32)
+        .expect("escape range should be valid characters");
+    let mut text = String::with_capacity(bytes.len());
+    while !bytes.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(bytes) {
+            Ok(valid) => (valid, &[][..]),
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                let invalid_len = err.error_len().unwrap_or(rest.len());
+                bytes = &rest[invalid_len..];
+                // The prefix was just checked, so this can't fail
+                (std::str::from_utf8(valid).unwrap_or_default(), &rest[..invalid_len])
+            }
+        };
+
+        for ch in valid.chars() {
+            if unescape_char(ch).is_some() {
+                text.extend(ch.encode_utf8(&mut [0; 4]).bytes().map(escape));
+            }
+            else {
+                text.push(ch);
+            }
+        }
+        text.extend(invalid.iter().copied().map(escape));
+
+        if invalid.is_empty() {
+            break;
+        }
+    }
+
+    Cow::Owned(text)
+}
+
 impl WorkPathBuf {
     /// Creates a new `WorkPathBuf` from the empty (or root) path.
     pub fn root() -> Self {
@@ -194,15 +361,21 @@ impl Deref for WorkPathBuf {
     }
 }
 
-impl Borrow<WorkPath> for WorkPathBuf {
-    fn borrow(&self) -> &WorkPath {
-        self
+impl Ord for WorkPathBuf {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (**self).cmp(&**other)
     }
 }
 
-impl Borrow<String> for WorkPathBuf {
-    fn borrow(&self) -> &String {
-        &self.0
+impl PartialOrd for WorkPathBuf {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Borrow<WorkPath> for WorkPathBuf {
+    fn borrow(&self) -> &WorkPath {
+        self
     }
 }
 
@@ -234,7 +407,7 @@ impl TryFrom<&str> for WorkPathBuf {
                 if part.is_empty() {
                     None
                 }
-                else if [".", "..", ".git"].contains(&part) {
+                else if is_forbidden_component(part) {
                     Some(Err(WorkDirError::ForbiddenComponent {
                         path: PathBuf::from(value),
                         component: part.to_owned(),
@@ -263,25 +436,26 @@ impl TryFrom<OsString> for WorkPathBuf {
     type Error = anyhow::Error;
 
     fn try_from(value: OsString) -> Result<Self, Self::Error> {
-        if let Some(value) = value.to_str() {
-            Self::try_from(value)
-        }
-        else {
-            Err(WorkDirError::InvalidUnicode(value).into())
-        }
+        Self::try_from(Path::new(&value))
     }
 }
 
+/// On Unix, a path that isn't valid Utf-8 has its bytes escaped. Elsewhere, it's rejected.
 impl TryFrom<&Path> for WorkPathBuf {
     type Error = anyhow::Error;
 
     fn try_from(value: &Path) -> Result<Self, Self::Error> {
         if let Some(path) = value.to_str() {
-            Self::try_from(path)
+            return Self::try_from(path);
         }
-        else {
-            Err(WorkDirError::InvalidUnicode(value.as_os_str().to_owned()).into())
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            Self::try_from(value.as_os_str().as_bytes())
         }
+        #[cfg(not(unix))]
+        Err(WorkDirError::InvalidUnicode(value.as_os_str().to_owned()).into())
     }
 }
 
@@ -297,14 +471,13 @@ impl TryFrom<&[u8]> for WorkPathBuf {
     type Error = anyhow::Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let path = std::str::from_utf8(value)?;
-        Self::try_from(path)
+        Self::try_from(escape_bytes(value).as_ref())
     }
 }
 
 impl fmt::Display for WorkPathBuf {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        fmt::Display::fmt(&**self, f)
     }
 }
 
@@ -358,6 +531,42 @@ mod tests {
             let result = WorkPathBuf::try_from(r"path/to/.git/directory");
             assert!(result.is_err());
         }
+        {
+            let result = WorkPathBuf::try_from(r".GIT/config");
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_round_trips() {
+        let bytes = b"caf\xe9/na\xefve\xee\xbe\x80.txt";
+        let path = WorkPathBuf::try_from(&bytes[..]).unwrap();
+
+        assert_eq!(path.to_bytes().as_ref(), bytes);
+        assert_eq!(path.file_name().to_bytes().as_ref(), b"na\xefve\xee\xbe\x80.txt");
+        assert_eq!(path.to_string(), r#""caf\351/na\357ve\356\276\200.txt""#);
+        assert_eq!(path.to_string_with_prefix("a/"), r#""a/caf\351/na\357ve\356\276\200.txt""#);
+    }
+
+    #[test]
+    fn paths_sort_by_their_bytes() {
+        let escaped = WorkPathBuf::try_from(&b"caf\x80"[..]).unwrap();
+        let accented = WorkPathBuf::try_from("caf\u{e9}").unwrap();
+        assert!(escaped.as_str() > accented.as_str());
+        assert!(escaped < accented);
+
+        let mut paths = vec![accented.clone(), escaped.clone()];
+        paths.sort();
+        assert_eq!(paths, [escaped, accented]);
+    }
+
+    #[test]
+    fn utf8_paths_are_unchanged() {
+        let path = WorkPathBuf::try_from("caf\u{e9}/\"quoted\"".as_bytes()).unwrap();
+
+        assert_eq!(path.as_str(), "caf\u{e9}/\"quoted\"");
+        assert!(matches!(path.to_bytes(), Cow::Borrowed(_)));
+        assert_eq!(path.to_string(), "caf\u{e9}/\"quoted\"");
     }
 
     #[test]
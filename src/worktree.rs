@@ -0,0 +1,336 @@
+//! Linked worktrees: extra working trees that share this repository, as made by
+//! `git worktree add`. Each has an administrative directory in `.git/worktrees/<id>`, whose
+//! `gitdir` file holds the path of the worktree's `.git` file, and whose `locked` file (if
+//! any) keeps it from being pruned or moved.
+
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    Result,
+    workdir::WorkDir,
+};
+
+/// The directory in `.git` that holds the administrative directories.
+pub const WORKTREES_DIR: &str = "worktrees";
+
+/// A linked worktree, as recorded in its administrative directory.
+pub struct LinkedWorktree {
+    /// The name of the administrative directory.
+    pub id: String,
+    /// The root of the worktree, or `None` if the `gitdir` file is missing or invalid.
+    pub path: Option<PathBuf>,
+    /// Why the worktree is locked, if it is. The reason can be empty.
+    pub lock_reason: Option<String>,
+}
+
+impl LinkedWorktree {
+    /// Lists the linked worktrees, ordered by id.
+    pub fn list(wd: &WorkDir) -> Result<Vec<Self>> {
+        let entries = match fs::read_dir(wd.git_path(WORKTREES_DIR)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut worktrees = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                worktrees.push(Self::read(wd, entry.file_name().to_string_lossy().into_owned())?);
+            }
+        }
+        worktrees.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(worktrees)
+    }
+
+    fn read(wd: &WorkDir, id: String) -> Result<Self> {
+        let admin_dir = wd.git_path(WORKTREES_DIR).join(&id);
+        let path = fs::read_to_string(admin_dir.join("gitdir")).ok()
+            .map(|contents| PathBuf::from(contents.trim_end()))
+            .filter(|git_file| git_file.is_absolute())
+            .and_then(|git_file| git_file.parent().map(Path::to_owned));
+        let lock_reason = match fs::read_to_string(admin_dir.join("locked")) {
+            Ok(reason) => Some(reason.trim_end().to_owned()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self { id, path, lock_reason })
+    }
+
+    /// Finds the worktree at `path` (relative to the current directory), or, as in git,
+    /// the only one whose path ends with `path` (e.g. its directory name).
+    pub fn find(wd: &WorkDir, path: &Path) -> Result<Self> {
+        let abs_path = std::path::absolute(path)?;
+        let real_path = fs::canonicalize(&abs_path).unwrap_or_else(|_| abs_path.clone());
+
+        if fs::canonicalize(wd.as_path()).is_ok_and(|root| root == real_path) {
+            return Err(WorktreeError::MainWorktree.into());
+        }
+
+        let worktrees = Self::list(wd)?;
+        let is_at = |worktree: &Self| worktree.path.as_ref()
+            .is_some_and(|worktree_path| *worktree_path == abs_path || *worktree_path == real_path);
+        if let Some(index) = worktrees.iter().position(is_at) {
+            return Ok(worktrees.into_iter().nth(index).expect("index should be in bounds"));
+        }
+
+        if path.is_relative() {
+            let mut matches = worktrees.into_iter()
+                .filter(|worktree| worktree.path.as_ref().is_some_and(|worktree_path| worktree_path.ends_with(path)));
+            if let (Some(found), None) = (matches.next(), matches.next()) {
+                return Ok(found);
+            }
+        }
+
+        Err(WorktreeError::NotAWorktree(path.to_owned()).into())
+    }
+
+    /// Returns the administrative directory.
+    pub fn admin_dir(&self, wd: &WorkDir) -> PathBuf {
+        wd.git_path(WORKTREES_DIR).join(&self.id)
+    }
+
+    /// Returns the path to show for the worktree: its root, or its administrative directory
+    /// if it doesn't know where its root is.
+    pub fn display_path(&self, wd: &WorkDir) -> PathBuf {
+        self.path.clone().unwrap_or_else(|| self.admin_dir(wd))
+    }
+
+    /// Returns why the worktree should be pruned, if it should. Locked worktrees are kept
+    /// even if they're missing, e.g. because they're on a removable drive.
+    pub fn prune_reason(&self, wd: &WorkDir) -> Option<&'static str> {
+        if self.lock_reason.is_some() {
+            return None;
+        }
+
+        match &self.path {
+            None if !self.admin_dir(wd).join("gitdir").exists() => Some("gitdir file does not exist"),
+            None => Some("invalid gitdir file"),
+            Some(path) if !path.join(".git").exists() => Some("gitdir file points to non-existent location"),
+            Some(_) => None,
+        }
+    }
+
+    /// Locks the worktree, so it won't be pruned or moved.
+    pub fn lock(&mut self, wd: &WorkDir, reason: &str) -> Result<()> {
+        wd.check_writable()?;
+        if let Some(existing) = &self.lock_reason {
+            return Err(WorktreeError::AlreadyLocked {
+                path: self.display_path(wd),
+                reason: existing.clone(),
+            }.into());
+        }
+
+        fs::write(self.admin_dir(wd).join("locked"), reason)?;
+        self.lock_reason = Some(reason.to_owned());
+
+        Ok(())
+    }
+
+    /// Unlocks the worktree.
+    pub fn unlock(&mut self, wd: &WorkDir) -> Result<()> {
+        wd.check_writable()?;
+        if self.lock_reason.is_none() {
+            return Err(WorktreeError::NotLocked(self.display_path(wd)).into());
+        }
+
+        fs::remove_file(self.admin_dir(wd).join("locked"))?;
+        self.lock_reason = None;
+
+        Ok(())
+    }
+
+    /// Moves the worktree to `new_path`, or into it if it's an existing directory. Its
+    /// `.git` file points at the administrative directory, which stays where it is, so
+    /// only the `gitdir` file has to change.
+    pub fn move_to(&mut self, wd: &WorkDir, new_path: &Path) -> Result<()> {
+        wd.check_writable()?;
+        if let Some(reason) = &self.lock_reason {
+            return Err(WorktreeError::MoveLocked {
+                path: self.display_path(wd),
+                reason: reason.clone(),
+            }.into());
+        }
+        let path = match &self.path {
+            Some(path) if path.join(".git").is_file() => path.clone(),
+            _ => return Err(WorktreeError::Missing(self.display_path(wd)).into()),
+        };
+
+        let mut new_path = std::path::absolute(new_path)?;
+        if new_path.is_dir() {
+            new_path.push(path.file_name().expect("worktree path should have a name"));
+        }
+        if new_path.exists() {
+            return Err(WorktreeError::AlreadyExists(new_path).into());
+        }
+
+        fs::rename(&path, &new_path)?;
+        let new_path = fs::canonicalize(&new_path)?;
+        let git_file = new_path.join(".git");
+        fs::write(self.admin_dir(wd).join("gitdir"), format!("{}\n", git_file.display()))?;
+        self.path = Some(new_path);
+
+        Ok(())
+    }
+}
+
+/// Removes the administrative directories of the worktrees that no longer exist, unless
+/// `dry_run` is set. Returns the ones that were (or would be) removed, with the reasons.
+pub fn prune(wd: &WorkDir, dry_run: bool) -> Result<Vec<(LinkedWorktree, &'static str)>> {
+    if !dry_run {
+        wd.check_writable()?;
+    }
+
+    let mut pruned = Vec::new();
+    for worktree in LinkedWorktree::list(wd)? {
+        if let Some(reason) = worktree.prune_reason(wd) {
+            if !dry_run {
+                fs::remove_dir_all(worktree.admin_dir(wd))?;
+            }
+            pruned.push((worktree, reason));
+        }
+    }
+
+    if !dry_run {
+        // Only succeeds once the last worktree is gone
+        let _ = fs::remove_dir(wd.git_path(WORKTREES_DIR));
+    }
+
+    Ok(pruned)
+}
+
+#[derive(Error, Debug)]
+pub enum WorktreeError {
+    #[error("`{0}` is not a linked worktree")]
+    NotAWorktree(PathBuf),
+    #[error("The main worktree can't be locked, unlocked, or moved")]
+    MainWorktree,
+    #[error("`{path}` is already locked{}", lock_reason_suffix(.reason))]
+    AlreadyLocked {
+        path: PathBuf,
+        reason: String,
+    },
+    #[error("`{0}` is not locked")]
+    NotLocked(PathBuf),
+    #[error("Can't move the locked worktree `{path}`{} (unlock it first)", lock_reason_suffix(.reason))]
+    MoveLocked {
+        path: PathBuf,
+        reason: String,
+    },
+    #[error("The worktree `{0}` is missing, so it can't be moved")]
+    Missing(PathBuf),
+    #[error("`{0}` already exists")]
+    AlreadyExists(PathBuf),
+}
+
+fn lock_reason_suffix(reason: &str) -> String {
+    match reason.is_empty() {
+        true => String::new(),
+        false => format!(", reason: {reason}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workdir::{Settings, WorkDirError};
+
+    /// Makes the administrative directory for a worktree at `path`, and the worktree itself
+    /// if `create` is set.
+    fn add_worktree(wd: &WorkDir, id: &str, path: &Path, create: bool) {
+        let admin_dir = wd.git_path(WORKTREES_DIR).join(id);
+        fs::create_dir_all(&admin_dir).unwrap();
+        fs::write(admin_dir.join("gitdir"), format!("{}\n", path.join(".git").display())).unwrap();
+        if create {
+            fs::create_dir_all(path).unwrap();
+            fs::write(path.join(".git"), format!("gitdir: {}\n", admin_dir.display())).unwrap();
+        }
+    }
+
+    #[test]
+    fn prunes_missing_worktrees() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        fs::create_dir_all(root.join("repo/.git")).unwrap();
+        let wd = WorkDir::new(root.join("repo")).unwrap();
+
+        add_worktree(&wd, "kept", &root.join("kept"), true);
+        add_worktree(&wd, "gone", &root.join("gone"), false);
+        add_worktree(&wd, "locked", &root.join("locked"), false);
+        fs::write(wd.git_path("worktrees/locked/locked"), "on a usb drive").unwrap();
+        fs::create_dir(wd.git_path("worktrees/broken")).unwrap();
+
+        let pruned = |dry_run| -> Vec<_> {
+            prune(&wd, dry_run).unwrap().into_iter().map(|(worktree, reason)| (worktree.id, reason)).collect()
+        };
+        let expected = [
+            ("broken".to_owned(), "gitdir file does not exist"),
+            ("gone".to_owned(), "gitdir file points to non-existent location"),
+        ];
+        assert_eq!(pruned(true), expected);
+        assert_eq!(pruned(false), expected);
+        assert_eq!(pruned(false), []);
+
+        let ids: Vec<_> = LinkedWorktree::list(&wd).unwrap().into_iter().map(|worktree| worktree.id).collect();
+        assert_eq!(ids, ["kept", "locked"]);
+    }
+
+    #[test]
+    fn locks_and_moves_worktrees() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        fs::create_dir_all(root.join("repo/.git")).unwrap();
+        fs::create_dir(root.join("elsewhere")).unwrap();
+        let wd = WorkDir::new(root.join("repo")).unwrap();
+        add_worktree(&wd, "feature", &root.join("feature"), true);
+
+        let mut worktree = LinkedWorktree::find(&wd, Path::new("feature")).unwrap();
+        worktree.lock(&wd, "busy").unwrap();
+        assert!(worktree.lock(&wd, "").is_err());
+        assert!(worktree.move_to(&wd, &root.join("elsewhere")).is_err());
+        worktree.unlock(&wd).unwrap();
+        assert!(worktree.unlock(&wd).is_err());
+
+        worktree.move_to(&wd, &root.join("elsewhere")).unwrap();
+        let moved = LinkedWorktree::find(&wd, &root.join("elsewhere/feature")).unwrap();
+        assert_eq!(moved.id, "feature");
+        assert_eq!(moved.prune_reason(&wd), None);
+        let Err(err) = LinkedWorktree::find(&wd, &root.join("repo")) else {
+            panic!("the main worktree should not be found");
+        };
+        assert!(matches!(err.downcast().unwrap(), WorktreeError::MainWorktree));
+    }
+
+    #[test]
+    fn read_only_refuses_changes() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        fs::create_dir_all(root.join("repo/.git")).unwrap();
+        let mut wd = WorkDir::new(root.join("repo")).unwrap();
+        add_worktree(&wd, "feature", &root.join("feature"), true);
+        add_worktree(&wd, "gone", &root.join("gone"), false);
+        fs::write(wd.git_path("worktrees/feature/locked"), "").unwrap();
+        wd.set_settings(Settings { read_only: true, ..Settings::default() });
+
+        let mut worktree = LinkedWorktree::find(&wd, Path::new("feature")).unwrap();
+        for result in [
+            worktree.unlock(&wd),
+            worktree.lock(&wd, "busy"),
+            worktree.move_to(&wd, &root.join("elsewhere")),
+            prune(&wd, false).map(drop),
+        ] {
+            assert!(matches!(result.unwrap_err().downcast_ref(), Some(WorkDirError::ReadOnly(_))));
+        }
+        assert_eq!(prune(&wd, true).unwrap().len(), 1);
+        assert_eq!(LinkedWorktree::list(&wd).unwrap().len(), 2);
+        assert!(wd.git_path("worktrees/feature/locked").exists());
+    }
+}
@@ -1,72 +1,178 @@
 mod common;
 use common::*;
 
-use std::path::PathBuf;
-
-use wyag::commands::{cmd_add, AddArgs};
+use wyag::index::Index;
+
+/// The files most of these tests start with, staged in one step.
+const FILES: [(&str, &str); 8] = [
+    ("x.txt", "x"),
+    ("a/b.txt", "a/b"),
+    ("a/c.txt", "a/c"),
+    ("a/b/c.txt", "a/b/c"),
+    ("a/b/d.txt", "a/b/d"),
+    ("a/b/c/d.txt", "a/b/c/d"),
+    ("y/x.txt", "y/x"),
+    ("y/z.txt", "y/z"),
+];
+
+fn repo_with_files() -> TestRepo {
+    let repo = TestRepo::new();
+    for (path, contents) in FILES {
+        repo.write(path, contents);
+    }
+
+    repo
+}
 
 #[test]
 fn add_all() {
-    let test_dir = setup("before_add_all", false).unwrap();
-
-    cmd_add(AddArgs {
-        path: PathBuf::from(".")
-    }).unwrap();
-
-    assert_matches_snapshot(test_dir, "after_add_all");
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.write("b.txt", "b");
+    repo.write("c/d/e.txt", "c/d/e");
+    repo.write("f/g.txt", "f/g");
+    std::fs::create_dir(repo.abs_path("h")).unwrap();
+
+    repo.wyag_ok(&["add", "."]);
+
+    let expected = entries(&[("a.txt", "a"), ("b.txt", "b"), ("c/d/e.txt", "c/d/e"), ("f/g.txt", "f/g")]);
+    assert_eq!(repo.index_entries(), expected);
+    assert!(expected.iter().all(|(_, hash)| repo.has_object(hash)));
 }
 
 #[test]
 fn add_file() {
-    let test_dir = setup("before_add_file", false).unwrap();
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.write("b.txt", "b");
+    repo.write("c/d/e.txt", "c/d/e");
 
-    cmd_add(AddArgs {
-        path: PathBuf::from("c/d/e.txt")
-    }).unwrap();
+    repo.wyag_ok(&["add", "c/d/e.txt"]);
 
-    assert_matches_snapshot(test_dir, "after_add_file");
+    assert_eq!(repo.index_entries(), entries(&[("c/d/e.txt", "c/d/e")]));
+    assert!(repo.has_object(&blob_hash("c/d/e")));
+    assert!(!repo.has_object(&blob_hash("a")));
 }
 
 #[test]
 fn add_directory() {
-    let test_dir = setup("before_add_directory", false).unwrap();
+    let repo = repo_with_files();
+
+    repo.wyag_ok(&["add", "a/b"]);
+
+    assert_eq!(repo.index_entries(), entries(&[("a/b/c.txt", "a/b/c"), ("a/b/c/d.txt", "a/b/c/d"), ("a/b/d.txt", "a/b/d")]));
+}
+
+#[test]
+fn add_glob() {
+    let repo = repo_with_files();
 
-    cmd_add(AddArgs {
-        path: PathBuf::from("a/b")
-    }).unwrap();
+    repo.wyag_ok(&["add", "a/*c.txt", "y/?.txt"]);
 
-    assert_matches_snapshot(test_dir, "after_add_directory");
+    assert_eq!(repo.index_paths(), ["a/b/c.txt", "a/c.txt", "y/x.txt", "y/z.txt"]);
+}
+
+#[test]
+fn add_glob_without_matches() {
+    let repo = repo_with_files();
+
+    let output = repo.wyag(&["add", "*.md"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("did not match any files"));
 }
 
 #[test]
 fn add_all_removed() {
-    let test_dir = setup("before_add_all_removed", false).unwrap();
+    let repo = repo_with_files();
+    repo.add(".");
+    repo.remove("x.txt");
+    repo.remove("a/b/c.txt");
+    repo.remove("a/b/d.txt");
 
-    cmd_add(AddArgs {
-        path: PathBuf::from(".")
-    }).unwrap();
+    repo.wyag_ok(&["add", "."]);
 
-    assert_matches_snapshot(test_dir, "after_add_all_removed");
+    assert_eq!(repo.index_paths(), ["a/b.txt", "a/b/c/d.txt", "a/c.txt", "y/x.txt", "y/z.txt"]);
 }
 
 #[test]
 fn add_file_removed() {
-    let test_dir = setup("before_add_file_removed", false).unwrap();
+    let repo = repo_with_files();
+    repo.add(".");
+    repo.remove("x.txt");
+    repo.remove("a/b/c.txt");
 
-    cmd_add(AddArgs {
-        path: PathBuf::from("x.txt")
-    }).unwrap();
+    repo.wyag_ok(&["add", "x.txt"]);
 
-    assert_matches_snapshot(test_dir, "after_add_file_removed");
+    // Only the named path is unstaged
+    assert_eq!(repo.index_paths(), ["a/b.txt", "a/b/c.txt", "a/b/c/d.txt", "a/b/d.txt", "a/c.txt", "y/x.txt", "y/z.txt"]);
 }
 
 #[test]
 fn add_directory_removed() {
-    let test_dir = setup("before_add_directory_removed", false).unwrap();
+    let repo = repo_with_files();
+    repo.add(".");
+    repo.remove("a/b");
+
+    repo.wyag_ok(&["add", "a/b"]);
+
+    assert_eq!(repo.index_paths(), ["a/b.txt", "a/c.txt", "x.txt", "y/x.txt", "y/z.txt"]);
+}
+
+#[test]
+fn add_with_split_index_is_readable_by_git() {
+    let git = || std::process::Command::new("git");
+    if git().arg("--version").output().is_err() {
+        // git isn't installed, so there's nothing to compare to
+        return;
+    }
+
+    let repo = repo_with_files();
+    let config = repo.read_file(".git/config").replace("[core]\n", "[core]\nsplitIndex=true\n");
+    repo.write(".git/config", &config);
+    repo.wyag_ok(&["add", "."]);
+    // Only one of the eight entries changes, so the shared index is reused
+    repo.write("x.txt", "changed");
+    repo.wyag_ok(&["add", "x.txt"]);
+
+    let output = git().args(["ls-files", "--stage"]).current_dir(repo.path()).output().unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut expected = entries(&FILES);
+    expected.sort_by(|a, b| a.0.cmp(&b.0));
+    for (path, hash) in &mut expected {
+        if path == "x.txt" {
+            *hash = blob_hash("changed");
+        }
+    }
+    assert_eq!(repo.index_entries(), expected);
+    let expected: String = expected.iter()
+        .map(|(path, hash)| format!("100644 {hash} 0\t{path}\n"))
+        .collect();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    let (stored, actual) = Index::checksum(&std::fs::read(repo.abs_path(".git/index")).unwrap()).unwrap().unwrap();
+    assert_eq!(stored, actual);
+
+    // git can update the index on top of the shared index wyag wrote
+    repo.write("new.txt", "new");
+    let status = git().args(["update-index", "--add", "new.txt"]).current_dir(repo.path()).status().unwrap();
+    assert!(status.success());
+    assert_eq!(repo.index_entries().len(), FILES.len() + 1);
+}
+
+#[test]
+fn add_skips_nested_repos_with_a_warning() {
+    let repo = TestRepo::new();
+    repo.write("main.rs", "main");
+    repo.write("vendor/lib/lib.rs", "lib");
+    std::fs::create_dir(repo.abs_path("vendor/lib/.git")).unwrap();
+
+    let output = repo.wyag_ok(&["add", "."]);
 
-    cmd_add(AddArgs {
-        path: PathBuf::from("a/b")
-    }).unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("skipping vendor/lib/"));
+    assert_eq!(repo.index_paths(), ["main.rs"]);
 
-    assert_matches_snapshot(test_dir, "after_add_directory_removed");
+    // Naming a file in the nested repository is still an error
+    assert!(!repo.wyag(&["add", "vendor/lib/lib.rs"]).status.success());
+    assert_eq!(repo.index_paths(), ["main.rs"]);
 }
@@ -1,77 +1,104 @@
 mod common;
 use common::*;
 
-use wyag::{commands::{cmd_branch, BranchArgs}, branch::BranchError};
+use wyag::{
+    branch::{self, Branch, BranchError},
+    object::{GitObject, ObjectError, ObjectFormat, Tag},
+};
+
+/// A repository with one commit on master.
+fn committed_repo() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.write("b.txt", "b");
+    repo.add(".");
+    repo.commit("initial commit");
+
+    repo
+}
 
 #[test]
 fn create_branch() {
-    let test_dir = setup("before_create_branch", false).unwrap();
+    let repo = committed_repo();
 
-    cmd_branch(BranchArgs {
-        delete: false,
-        branch_name: Some("test_branch".to_owned()),
-        start_point: "HEAD".to_owned(),
-    }).unwrap();
+    let hash = GitObject::find_as(repo.wd(), "HEAD", ObjectFormat::Commit).unwrap();
+    branch::create("test_branch", repo.wd(), &hash).unwrap();
 
-    assert_matches_snapshot(test_dir, "after_create_branch");
+    assert_eq!(repo.branch("test_branch"), repo.head());
+    assert_eq!(repo.current_branch().as_deref(), Some("master"));
 }
 
 #[test]
 fn create_branch_with_starting_point() {
-    let test_dir = setup("before_create_branch_with_starting_point", false).unwrap();
+    let repo = committed_repo();
+    let first = repo.head().unwrap();
+    Tag::create_lightweight(repo.wd(), "starting_point", &first).unwrap();
+    repo.write("c/d/e.txt", "c/d/e");
+    repo.add(".");
+    repo.commit("second commit to master");
+
+    let hash = GitObject::find_as(repo.wd(), "starting_point", ObjectFormat::Commit).unwrap();
+    branch::create("test_branch", repo.wd(), &hash).unwrap();
+
+    assert_eq!(repo.branch("test_branch"), Some(first));
+    assert_ne!(repo.branch("master"), Some(first));
+}
 
-    cmd_branch(BranchArgs {
-        delete: false,
-        branch_name: Some("test_branch".to_owned()),
-        start_point: "starting_point".to_owned(),
-    }).unwrap();
+#[test]
+fn create_fails_with_tree_starting_point() {
+    let repo = committed_repo();
 
-    assert_matches_snapshot(test_dir, "after_create_branch_with_starting_point");
+    let err = GitObject::find_as(repo.wd(), "HEAD^{tree}", ObjectFormat::Commit)
+        .unwrap_err()
+        .downcast::<ObjectError>()
+        .unwrap();
+
+    assert!(matches!(err, ObjectError::WrongKind { expected: ObjectFormat::Commit, found: ObjectFormat::Tree, .. }));
+    assert_eq!(repo.branch("test_branch"), None);
 }
 
 #[test]
 fn delete_branch() {
-    let test_dir = setup("before_delete_branch", false).unwrap();
+    let repo = committed_repo();
+    branch::create("test_branch", repo.wd(), &repo.head().unwrap()).unwrap();
 
-    cmd_branch(BranchArgs {
-        delete: true,
-        branch_name: Some("test_branch".to_owned()),
-        start_point: "HEAD".to_owned(),
-    }).unwrap();
+    branch::delete("test_branch", repo.wd()).unwrap();
 
-    assert_matches_snapshot(test_dir, "after_delete_branch");
+    assert_eq!(repo.branch("test_branch"), None);
+    assert!(repo.branch("master").is_some());
 }
 
 #[test]
 fn delete_fails_with_unmerged_branch() {
-    let test_dir = setup("before_delete_fails_with_unmerged_branch", false).unwrap();
-
-    let err = cmd_branch(BranchArgs {
-            delete: true,
-            branch_name: Some("test_branch".to_owned()),
-            start_point: "HEAD".to_owned(),
-        })
+    let repo = committed_repo();
+    let first = repo.head().unwrap();
+    branch::create("test_branch", repo.wd(), &first).unwrap();
+    branch::switch(repo.wd(), &Branch::Named("test_branch".to_owned())).unwrap();
+    repo.write("c/d/e.txt", "c/d/e");
+    repo.add(".");
+    let second = repo.commit("second commit to test_branch");
+    branch::switch(repo.wd(), &Branch::Named("master".to_owned())).unwrap();
+
+    let err = branch::delete("test_branch", repo.wd())
         .unwrap_err()
         .downcast::<BranchError>()
         .unwrap();
 
     assert!(matches!(err, BranchError::PossiblyUnmerged(_)));
-    assert_matches_snapshot(test_dir, "after_delete_fails_with_unmerged_branch");
+    assert_eq!(repo.branch("test_branch"), Some(second));
 }
 
 #[test]
 fn delete_fails_with_current_branch() {
-    let test_dir = setup("before_delete_fails_with_current_branch", false).unwrap();
+    let repo = committed_repo();
+    branch::create("test_branch", repo.wd(), &repo.head().unwrap()).unwrap();
+    branch::switch(repo.wd(), &Branch::Named("test_branch".to_owned())).unwrap();
 
-    let err = cmd_branch(BranchArgs {
-            delete: true,
-            branch_name: Some("test_branch".to_owned()),
-            start_point: "HEAD".to_owned(),
-        })
+    let err = branch::delete("test_branch", repo.wd())
         .unwrap_err()
         .downcast::<BranchError>()
         .unwrap();
 
     assert!(matches!(err, BranchError::CheckedOut(_)));
-    assert_matches_snapshot(test_dir, "after_delete_fails_with_current_branch");
+    assert!(repo.branch("test_branch").is_some());
 }
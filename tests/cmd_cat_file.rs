@@ -0,0 +1,22 @@
+mod common;
+use common::*;
+
+#[test]
+fn print_shows_the_commit_as_stored() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.add("a.txt");
+    let hash = repo.commit("First\n");
+
+    let raw = repo.wyag_ok(&["cat-file", "commit", &hash.to_string()]);
+    let printed = repo.wyag_ok(&["cat-file", "-p", "commit", &hash.to_string()]);
+    assert_eq!(printed.stdout, raw.stdout);
+    assert!(String::from_utf8_lossy(&printed.stdout).starts_with("tree "));
+
+    let rendered = repo.wyag_ok(&["cat-file", "--rendered", "commit", &hash.to_string()]);
+    let rendered = String::from_utf8_lossy(&rendered.stdout);
+    assert!(rendered.starts_with(&format!("commit {hash}\n")), "{rendered}");
+    assert!(rendered.contains("\n    First\n"), "{rendered}");
+
+    assert!(!repo.wyag(&["cat-file", "-p", "--rendered", "commit", &hash.to_string()]).status.success());
+}
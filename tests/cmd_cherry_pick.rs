@@ -0,0 +1,124 @@
+mod common;
+use common::*;
+
+use wyag::object::Commit;
+
+/// A repository where `topic` changes a.txt and then adds c.txt, while master changes a.txt
+/// differently. master is checked out, so picking the first `topic` commit conflicts.
+fn repo_with_conflict() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "base\n");
+    repo.add(".");
+    repo.commit("initial commit");
+    repo.wyag_ok(&["branch", "topic"]);
+    repo.wyag_ok(&["switch", "topic"]);
+    repo.write("a.txt", "topic\n");
+    repo.add(".");
+    repo.commit("change a on topic");
+    repo.write("c.txt", "c\n");
+    repo.add(".");
+    repo.commit("add c");
+    repo.wyag_ok(&["switch", "master"]);
+    repo.write("a.txt", "master\n");
+    repo.add(".");
+    repo.commit("change a on master");
+
+    repo
+}
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn stops_at_a_conflict_and_continues() {
+    let repo = repo_with_conflict();
+    let master = repo.head().unwrap();
+
+    let output = repo.wyag(&["cherry-pick", "master..topic"]);
+
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(stderr(&output).contains("CONFLICT (content): Merge conflict in a.txt"));
+    assert!(stderr(&output).contains("Could not apply"));
+    assert_eq!(repo.head(), Some(master));
+    assert!(repo.abs_path(".git/CHERRY_PICK_HEAD").is_file());
+    assert!(repo.read_file("a.txt").contains("<<<<<<< HEAD\nmaster\n=======\ntopic\n>>>>>>>"));
+    // Starting another one isn't allowed until this one is dealt with
+    assert!(stderr(&repo.wyag(&["cherry-pick", "topic"])).contains("already in progress"));
+    // Nor is continuing before the conflict is resolved
+    assert!(!repo.wyag(&["cherry-pick", "--continue"]).status.success());
+
+    repo.write("a.txt", "resolved\n");
+    repo.wyag_ok(&["add", "a.txt"]);
+    repo.wyag_ok(&["cherry-pick", "--continue"]);
+
+    let added_c = Commit::read(repo.wd(), &repo.head().unwrap()).unwrap();
+    assert_eq!(added_c.message(), "add c");
+    let resolved = Commit::read(repo.wd(), &added_c.parents()[0]).unwrap();
+    assert_eq!(resolved.message(), "change a on topic");
+    assert_eq!(resolved.parents(), [master]);
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "resolved\n"), ("c.txt", "c\n")]));
+    assert!(!repo.abs_path(".git/CHERRY_PICK_HEAD").exists());
+    assert!(!repo.abs_path(".git/sequencer").exists());
+}
+
+#[test]
+fn abort_restores_the_original_commit() {
+    let repo = repo_with_conflict();
+    let master = repo.head().unwrap();
+    assert_eq!(repo.wyag(&["cherry-pick", "master..topic"]).status.code(), Some(1));
+
+    repo.wyag_ok(&["cherry-pick", "--abort"]);
+
+    assert_eq!(repo.head(), Some(master));
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "master\n")]));
+    assert_eq!(repo.read_file("a.txt"), "master\n");
+    assert!(!repo.abs_path(".git/CHERRY_PICK_HEAD").exists());
+    assert!(!repo.abs_path(".git/sequencer").exists());
+    assert!(stderr(&repo.wyag(&["cherry-pick", "--abort"])).contains("No cherry-pick in progress"));
+}
+
+#[test]
+fn skip_moves_on_to_the_next_commit() {
+    let repo = repo_with_conflict();
+    let master = repo.head().unwrap();
+    assert_eq!(repo.wyag(&["cherry-pick", "master..topic"]).status.code(), Some(1));
+
+    repo.wyag_ok(&["cherry-pick", "--skip"]);
+
+    let head = Commit::read(repo.wd(), &repo.head().unwrap()).unwrap();
+    assert_eq!(head.message(), "add c");
+    assert_eq!(head.parents(), [master]);
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "master\n"), ("c.txt", "c\n")]));
+    assert_eq!(repo.read_file("a.txt"), "master\n");
+    assert!(!repo.abs_path(".git/sequencer").exists());
+}
+
+#[test]
+fn merges_need_a_mainline() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n");
+    repo.add(".");
+    let initial = repo.commit("initial commit");
+    repo.wyag_ok(&["branch", "side"]);
+    repo.wyag_ok(&["branch", "other"]);
+    repo.wyag_ok(&["switch", "side"]);
+    repo.write("d.txt", "d\n");
+    repo.add(".");
+    repo.commit("add d");
+    repo.wyag_ok(&["switch", "master"]);
+    repo.wyag_ok(&["merge", "--no-ff", "side"]);
+    let merge = repo.head().unwrap().to_string();
+    repo.wyag_ok(&["switch", "other"]);
+
+    assert!(stderr(&repo.wyag(&["cherry-pick", &merge])).contains("no -m option was given"));
+    assert!(stderr(&repo.wyag(&["cherry-pick", "-m", "3", &merge])).contains("does not have parent 3"));
+    assert!(!repo.wyag(&["cherry-pick", "-m", "0", &merge]).status.success());
+    assert_eq!(repo.head(), Some(initial));
+
+    repo.wyag_ok(&["cherry-pick", "-m", "1", &merge]);
+
+    let head = Commit::read(repo.wd(), &repo.head().unwrap()).unwrap();
+    assert_eq!(head.parents(), [initial]);
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "a\n"), ("d.txt", "d\n")]));
+}
@@ -1,26 +1,145 @@
 mod common;
 use common::*;
 
-use wyag::commands::{cmd_commit, CommitArgs};
+use wyag::object::{Commit, Tree};
 
 #[test]
 fn commit_to_pristine_repo() {
-    let test_dir = setup("before_commit_to_pristine_repo", false).unwrap();
+    let repo = TestRepo::new();
+    repo.write("x.txt", "x");
+    repo.write("a/b/c/d.txt", "a/b/c/d");
+    repo.add(".");
 
-    cmd_commit(CommitArgs {
-        message: "initial commit".to_owned()
-    }).unwrap();
+    let output = repo.wyag_ok(&["commit", "-m", "initial commit"]);
 
-    assert_matches_snapshot(test_dir, "after_commit_to_pristine_repo");
+    let hash = repo.branch("master").unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{hash}\n"));
+    let commit = Commit::read(repo.wd(), &hash).unwrap();
+    assert_eq!(commit.message(), "initial commit");
+    assert!(commit.parents().is_empty());
+    assert_eq!(commit.author(), Some("User Name <user@example.com>"));
+    assert!(repo.has_object(commit.tree()));
 }
 
 #[test]
 fn commit() {
-    let test_dir = setup("before_commit", false).unwrap();
+    let repo = TestRepo::new();
+    repo.write("x.txt", "x");
+    repo.write("a/b/c/d.txt", "a/b/c/d");
+    repo.add(".");
+    let first = repo.commit("initial commit");
+    repo.write("a/b/c.txt", "a/b/c");
+    repo.add(".");
 
-    cmd_commit(CommitArgs {
-        message: "second commit".to_owned()
-    }).unwrap();
+    repo.wyag_ok(&["commit", "-m", "second commit"]);
 
-    assert_matches_snapshot(test_dir, "after_commit");
+    let second = repo.branch("master").unwrap();
+    assert_ne!(second, first);
+    let commit = Commit::read(repo.wd(), &second).unwrap();
+    assert_eq!(commit.parents(), [first]);
+
+    // The tree holds everything that was staged
+    let index = Tree::read(repo.wd(), commit.tree()).unwrap().to_index(repo.wd(), None).unwrap();
+    let paths: Vec<_> = index.entries.keys().map(|path| path.to_string()).collect();
+    assert_eq!(paths, repo.index_paths());
+}
+
+/// Adds `key = value` to `[section]` in the repository's config.
+fn set_config(repo: &TestRepo, section: &str, key: &str, value: &str) {
+    let header = format!("[{section}]\n");
+    let mut config = repo.read_file(".git/config");
+    if !config.contains(&header) {
+        config.push_str(&header);
+    }
+    let config = config.replacen(&header, &format!("{header}\t{key} = {value}\n"), 1);
+    repo.write(".git/config", &config);
+}
+
+/// Uses the shell script `script` as the editor, with the file to edit as `$1`.
+fn set_editor(repo: &TestRepo, script: &str) {
+    repo.write(".git/editor.sh", script);
+    set_config(repo, "core", "editor", "sh .git/editor.sh");
+}
+
+#[cfg(unix)]
+#[test]
+fn editor_runs_without_the_repo_lock() {
+    let repo = TestRepo::new();
+    repo.write("x.txt", "x");
+    repo.add(".");
+    // The editor fails if the lock is held, and otherwise writes the message
+    set_editor(&repo, "test ! -e .git/wyag.lock && echo edited > \"$1\"\n");
+
+    repo.wyag_ok(&["commit"]);
+
+    let commit = Commit::read(repo.wd(), &repo.head().unwrap()).unwrap();
+    assert_eq!(commit.message(), "edited\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn editor_starts_from_the_template() {
+    let repo = TestRepo::new();
+    repo.write("x.txt", "x");
+    repo.add(".");
+    repo.write("template.txt", "Subject\n\n# Explain why\n");
+    set_config(&repo, "commit", "template", "template.txt");
+
+    // The instructions are added below the template, and comments are removed afterward
+    set_editor(&repo, "grep -q '^# Please enter the commit message' \"$1\" && echo 'More detail' >> \"$1\"\n");
+    repo.wyag_ok(&["commit"]);
+    let commit = Commit::read(repo.wd(), &repo.head().unwrap()).unwrap();
+    assert_eq!(commit.message(), "Subject\n\nMore detail\n");
+
+    // Leaving the template as it is aborts the commit
+    let head = repo.head();
+    repo.write("x.txt", "changed");
+    repo.add(".");
+    set_config(&repo, "core", "editor", ":");
+    let output = repo.wyag(&["commit"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("you did not edit the message"));
+    assert_eq!(repo.head(), head);
+}
+
+#[cfg(unix)]
+#[test]
+fn empty_message_aborts() {
+    let repo = TestRepo::new();
+    repo.write("x.txt", "x");
+    repo.add(".");
+    // Only comments and blank lines are left
+    set_editor(&repo, "printf '\\n# nothing\\n\\n' > \"$1\"\n");
+
+    let output = repo.wyag(&["commit"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Aborting commit due to empty commit message."));
+    assert_eq!(repo.head(), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn prepare_commit_msg_hook() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let repo = TestRepo::new();
+    repo.write("x.txt", "x");
+    repo.add(".");
+    // The hook records its arguments after the first, and adds a line to the message
+    repo.write(".git/hooks/prepare-commit-msg", "#!/bin/sh\nshift\necho \"$@\" > .git/hook-args\necho 'From the hook' >> .git/COMMIT_EDITMSG\n");
+    let hook = repo.abs_path(".git/hooks/prepare-commit-msg");
+    std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    repo.wyag_ok(&["commit", "-m", "First\n"]);
+    assert_eq!(repo.read_file(".git/hook-args"), "message\n");
+    let first = repo.head().unwrap();
+    assert_eq!(Commit::read(repo.wd(), &first).unwrap().message(), "First\nFrom the hook\n");
+
+    // Only a reused message comes with the hash of the amended commit
+    repo.wyag_ok(&["commit", "--amend", "-m", "Amended\n"]);
+    assert_eq!(repo.read_file(".git/hook-args"), "message\n");
+    let amended = repo.head().unwrap();
+    repo.wyag_ok(&["commit", "--amend"]);
+    assert_eq!(repo.read_file(".git/hook-args"), format!("commit {amended}\n"));
 }
@@ -0,0 +1,48 @@
+mod common;
+use common::*;
+
+use wyag::{
+    consts::{REMOTE_CATEGORY, TAG_CATEGORY},
+    refs,
+};
+
+#[test]
+fn copies_objects_and_refs() {
+    let source = TestRepo::new();
+    source.write("a.txt", "a");
+    source.add(".");
+    let first = source.commit("initial commit");
+    source.write("b.txt", "b");
+    source.add(".");
+    let second = source.commit("second commit");
+    source.wyag_ok(&["branch", "old", &first.to_string()]);
+    source.wyag_ok(&["tag", "v1", &first.to_string()]);
+    source.wyag_ok(&["tag", "v2"]);
+
+    let repo = TestRepo::new();
+    // A tag that's already here with another target is left alone
+    repo.write("c.txt", "c");
+    repo.add(".");
+    let own = repo.commit("unrelated commit");
+    repo.wyag_ok(&["tag", "v2"]);
+    let source_path = source.path().to_str().unwrap();
+
+    let output = repo.wyag_ok(&["copy-objects", "--origin", "upstream", source_path]);
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Skipped tag `v2`"));
+    let remote = |name: &str| refs::resolve(repo.wd(), REMOTE_CATEGORY, name).ok();
+    assert_eq!(remote("upstream/master"), Some(second));
+    assert_eq!(remote("upstream/old"), Some(first));
+    assert_eq!(remote("origin/master"), None);
+    assert_eq!(refs::resolve(repo.wd(), TAG_CATEGORY, "v1").ok(), Some(first));
+    assert_eq!(repo.tag("v2"), Some(own));
+    assert_eq!(repo.head(), Some(own));
+    for hash in [first, second, blob_hash("a"), blob_hash("b")] {
+        assert!(repo.has_object(&hash), "{hash}");
+    }
+
+    // Copying again finds nothing new
+    let output = repo.wyag_ok(&["copy-objects", "--origin", "upstream", source_path]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Copied 0 objects and 0 packs (0 hard-linked), updated 0 refs"));
+    assert!(!repo.wyag(&["copy-objects", "."]).status.success());
+}
@@ -0,0 +1,83 @@
+mod common;
+use common::*;
+
+#[test]
+fn exit_code() {
+    let repo = TestRepo::new();
+    repo.write("x.txt", "x");
+    repo.add(".");
+    repo.commit("initial commit");
+    let index = repo.index_entries();
+
+    assert!(repo.wyag(&["diff", "--quiet"]).status.success());
+    assert!(repo.wyag(&["diff", "--exit-code"]).status.success());
+    // Comparing doesn't touch the repository
+    assert_eq!(repo.index_entries(), index);
+
+    repo.write("x.txt", "changed");
+    for args in [["diff", "--quiet"], ["diff", "--exit-code"]] {
+        let output = repo.wyag(&args);
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+    }
+    assert!(repo.wyag(&["diff"]).status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn textconv() {
+    let repo = TestRepo::new();
+    repo.write(".gitattributes", "*.bin diff=strip\n");
+    repo.write("x.bin", "old\n\0");
+    repo.add(".");
+    repo.commit("initial commit");
+    let config = std::fs::read_to_string(repo.abs_path(".git/config")).unwrap();
+    repo.write(".git/config", &format!("{config}[diff \"strip\"]\n\ttextconv = tr -d '\\\\000' <\n"));
+    repo.write("x.bin", "new\n\0");
+
+    let output = repo.wyag(&["diff"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout.contains("-old\n+new\n"), "{stdout}");
+
+    let output = repo.wyag(&["cat-file", "--textconv", "HEAD:x.bin"]);
+    assert_eq!(output.stdout, b"old\n");
+}
+
+#[test]
+fn rev_and_path_arguments() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n");
+    repo.write("b.txt", "b\n");
+    repo.add(".");
+    repo.commit("initial commit");
+    repo.write("a.txt", "a2\n");
+    repo.write("b.txt", "b2\n");
+    repo.add(".");
+    repo.commit("second commit");
+    repo.write("a.txt", "a3\n");
+    repo.write("b.txt", "b3\n");
+
+    let diff = |args: &[&str]| {
+        let output = repo.wyag(args);
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // A lone revision compares the working tree to it
+    let stdout = diff(&["diff", "HEAD~1"]);
+    assert!(stdout.contains("-a\n+a3\n"), "{stdout}");
+    assert!(stdout.contains("-b\n+b3\n"), "{stdout}");
+
+    // A lone argument that isn't a revision is a path, compared to the index
+    let stdout = diff(&["diff", "a.txt"]);
+    assert!(stdout.contains("-a2\n+a3\n"), "{stdout}");
+    assert!(!stdout.contains("b.txt"), "{stdout}");
+
+    // Everything after `--` is a path
+    let stdout = diff(&["diff", "HEAD~1", "--", "b.txt"]);
+    assert!(stdout.contains("-b\n+b3\n"), "{stdout}");
+    assert!(!stdout.contains("a.txt"), "{stdout}");
+    let stdout = diff(&["diff", "--", "b.txt"]);
+    assert!(stdout.contains("-b2\n+b3\n"), "{stdout}");
+    assert!(!stdout.contains("a.txt"), "{stdout}");
+}
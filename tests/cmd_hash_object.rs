@@ -1,17 +1,28 @@
 mod common;
 use common::*;
 
-use wyag::commands::{cmd_hash_object, HashObjectArgs, ClapObjectFormat};
+use wyag::object::{GitObject, ObjectFormat, ObjectHash};
 
 #[test]
 fn hash_blob() {
-    let test_dir = setup("before_hash_blob", false).unwrap();
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
 
-    cmd_hash_object(HashObjectArgs {
-        write: true,
-        format: ClapObjectFormat::Blob,
-        path: "a.txt".into(),
-    }).unwrap();
+    let output = repo.wyag(&["hash-object", "-w", "a.txt"]);
 
-    assert_matches_snapshot(test_dir, "after_hash_blob");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), blob_hash("a").to_string());
+    assert!(repo.has_object(&blob_hash("a")));
+}
+
+#[test]
+fn refuse_malformed_commit() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+
+    assert!(GitObject::validate(ObjectFormat::Commit, b"a").is_err());
+    let output = repo.wyag(&["hash-object", "-w", "-t", "commit", "a.txt"]);
+
+    assert!(!output.status.success());
+    assert!(!repo.has_object(&ObjectHash::for_object(ObjectFormat::Commit, b"a")));
 }
@@ -1,61 +1,71 @@
 mod common;
 use common::*;
 
-use wyag::{
-    commands::{cmd_init, InitArgs},
-    repo::RepoError
-};
+use wyag::repo::{Repository, RepoError};
 
 #[test]
 fn init_fails_on_nonempty_directory() {
-    let test_dir = setup("uninitialized", false).unwrap();
+    let dir = TempDir::new().unwrap();
+    dir.child("a.txt").write_str("a").unwrap();
 
-    let err = cmd_init(InitArgs {
-            path: None,
-        })
-        .unwrap_err()
-        .downcast::<RepoError>()
-        .unwrap();
+    let Err(err) = Repository::init(dir.path()) else {
+        panic!("init should fail");
+    };
+    let err = err.downcast::<RepoError>().unwrap();
     assert!(matches!(err, RepoError::InitPathExists(_)));
 
-    // The init directory should be unchanged
-    assert_matches_snapshot(test_dir, "uninitialized");
+    // The directory should be unchanged
+    assert!(!dir.child(".git").exists());
 }
 
 #[test]
-fn init_fails_on_nonempty_subdirectory() {
-    let test_dir = setup("uninitialized", true).unwrap();
-
-    let err = cmd_init(InitArgs {
-            path: Some("uninitialized".into()),
-        })
-        .unwrap_err()
-        .downcast::<RepoError>()
-        .unwrap();
-    assert!(matches!(err, RepoError::InitPathExists(_)));
+fn init_fails_on_file() {
+    let dir = TempDir::new().unwrap();
+    dir.child("uninitialized").write_str("a").unwrap();
 
-    // The init directory should be unchanged
-    assert_matches_snapshot(test_dir.child("uninitialized"), "uninitialized");
+    let Err(err) = Repository::init(dir.child("uninitialized")) else {
+        panic!("init should fail");
+    };
+    let err = err.downcast::<RepoError>().unwrap();
+    assert!(matches!(err, RepoError::InitPathExists(_)));
 }
 
 #[test]
 fn init_succeeds_on_empty_directory() {
-    let test_dir = setup_empty().unwrap();
+    let dir = TempDir::new().unwrap();
 
-    cmd_init(InitArgs {
-        path: None
-    }).unwrap();
+    Repository::init(dir.path()).unwrap();
 
-    assert_matches_snapshot(&test_dir, "initialized");
+    let repo = Repository::find(dir.path()).unwrap();
+    assert_eq!(repo.get_config("core", "repositoryformatversion"), Some("0"));
+    assert_eq!(std::fs::read_to_string(dir.child(".git/HEAD")).unwrap(), "ref: refs/heads/master\n");
+    assert!(dir.child(".git/objects").is_dir());
+    assert!(dir.child(".git/refs/heads").is_dir());
+    assert!(dir.child(".git/refs/tags").is_dir());
 }
 
 #[test]
 fn init_succeeds_on_empty_subdirectory() {
-    let test_dir = setup_empty().unwrap();
+    let dir = TempDir::new().unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_wyag"))
+        .args(["init", "empty"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(Repository::find(dir.child("empty")).is_ok());
+    assert!(!dir.child(".git").exists());
+}
+
+#[test]
+fn init_with_separate_git_dir() {
+    let dir = TempDir::new().unwrap();
 
-    cmd_init(InitArgs {
-        path: Some("empty".into())
-    }).unwrap();
+    Repository::init_separate(dir.child("work"), dir.child("repo.git")).unwrap();
 
-    assert_matches_snapshot(test_dir.child("empty"), "initialized");
+    let repo = Repository::find(dir.child("work")).unwrap();
+    assert_eq!(repo.workdir().git_dir(), dir.child("repo.git").path());
+    assert!(dir.child("repo.git/objects").is_dir());
 }
@@ -0,0 +1,98 @@
+mod common;
+use common::*;
+
+use wyag::{
+    branch,
+    object::{Commit, ObjectMetadata, Tree},
+};
+
+/// A repository with an initial commit on master, and a `feature` branch one commit ahead
+/// of it that adds b.txt. master is checked out.
+fn repo_with_feature() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.add(".");
+    let initial = repo.commit("initial commit");
+    branch::create("feature", repo.wd(), &initial).unwrap();
+    repo.wyag_ok(&["switch", "feature"]);
+    repo.write("b.txt", "b");
+    repo.add(".");
+    repo.commit("add b");
+    repo.wyag_ok(&["switch", "master"]);
+
+    repo
+}
+
+#[test]
+fn fast_forward() {
+    let repo = repo_with_feature();
+    let feature = repo.branch("feature").unwrap();
+
+    let output = repo.wyag_ok(&["merge", "feature"]);
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Fast-forward"));
+    assert_eq!(repo.current_branch().as_deref(), Some("master"));
+    assert_eq!(repo.branch("master"), Some(feature));
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "a"), ("b.txt", "b")]));
+    assert_eq!(repo.read_file("b.txt"), "b");
+
+    let output = repo.wyag_ok(&["merge", "feature"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Already up to date."));
+    assert_eq!(repo.branch("master"), Some(feature));
+}
+
+#[test]
+fn no_fast_forward_makes_a_merge_commit() {
+    let repo = repo_with_feature();
+    let initial = repo.branch("master").unwrap();
+    let feature = repo.branch("feature").unwrap();
+
+    let output = repo.wyag_ok(&["merge", "--no-ff", "feature"]);
+
+    let merge = repo.branch("master").unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{merge}\n"));
+    let commit = Commit::read(repo.wd(), &merge).unwrap();
+    assert_eq!(commit.parents(), [initial, feature]);
+    assert_eq!(commit.message(), "Merge branch 'feature'");
+    assert_eq!(commit.tree(), Commit::read(repo.wd(), &feature).unwrap().tree());
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "a"), ("b.txt", "b")]));
+}
+
+#[test]
+fn fast_forward_only() {
+    let repo = repo_with_feature();
+    repo.write("c.txt", "c");
+    repo.add(".");
+    let diverged = repo.commit("add c");
+
+    let output = repo.wyag(&["merge", "--ff-only", "feature"]);
+
+    assert!(!output.status.success());
+    assert_eq!(repo.branch("master"), Some(diverged));
+    assert_eq!(repo.index_paths(), ["a.txt", "c.txt"]);
+    assert!(!repo.abs_path("b.txt").exists());
+
+    // Without the diverging commit, it fast-forwards as usual
+    let repo = repo_with_feature();
+    repo.wyag_ok(&["merge", "--ff-only", "feature"]);
+    assert_eq!(repo.branch("master"), repo.branch("feature"));
+}
+
+#[test]
+fn create_with_parents_records_every_parent() {
+    let repo = repo_with_feature();
+    let initial = repo.branch("master").unwrap();
+    let feature = repo.branch("feature").unwrap();
+    repo.write("c.txt", "c");
+    repo.add(".");
+    let third = repo.commit("add c");
+    let meta = ObjectMetadata::new(repo.repo(), "octopus".to_owned()).unwrap();
+
+    let hash = Commit::create_with_parents(&repo.index(), repo.wd(), meta, vec![third, feature, initial]).unwrap();
+
+    assert_eq!(repo.branch("master"), Some(hash));
+    let commit = Commit::read(repo.wd(), &hash).unwrap();
+    assert_eq!(commit.parents(), [third, feature, initial]);
+    let tree = Tree::read(repo.wd(), commit.tree()).unwrap().to_index(repo.wd(), None).unwrap();
+    assert_eq!(tree.entries.len(), 2);
+}
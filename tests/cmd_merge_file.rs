@@ -0,0 +1,18 @@
+mod common;
+use common::*;
+
+#[test]
+fn exit_status_is_conflict_count() {
+    let repo = TestRepo::new();
+    repo.write("base.txt", "a\nb\nc\nd\ne\nf\ng\n");
+    repo.write("ours.txt", "A\nb\nc\nd\ne\nf\nG\n");
+    repo.write("theirs.txt", "a2\nb\nc\nd\ne\nf\ng2\n");
+
+    let output = repo.wyag(&["merge-file", "-p", "ours.txt", "base.txt", "theirs.txt"]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+
+    repo.write("theirs.txt", "a\nb\nc\nD\ne\nf\ng\n");
+    let output = repo.wyag(&["merge-file", "ours.txt", "base.txt", "theirs.txt"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(repo.read_file("ours.txt"), "A\nb\nc\nD\ne\nf\nG\n");
+}
@@ -0,0 +1,83 @@
+mod common;
+use common::*;
+
+use wyag::{branch, object::Tag};
+
+/// A repository with one commit on master, a `feature` branch, and a `v1` tag.
+fn committed_repo() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.add(".");
+    let hash = repo.commit("initial commit");
+    branch::create("feature", repo.wd(), &hash).unwrap();
+    Tag::create_lightweight(repo.wd(), "v1", &hash).unwrap();
+
+    repo
+}
+
+#[test]
+fn export_lists_head_and_refs() {
+    let repo = committed_repo();
+    let hash = repo.head().unwrap();
+
+    let output = repo.wyag(&["refs", "export"]);
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!(
+        "ref: refs/heads/master HEAD\n\
+        {hash} refs/heads/feature\n\
+        {hash} refs/heads/master\n\
+        {hash} refs/tags/v1\n"
+    ));
+}
+
+#[test]
+fn import_restores_exported_refs() {
+    let repo = committed_repo();
+    let first = repo.head().unwrap();
+    assert!(repo.wyag(&["refs", "export", "-o", "snapshot.txt"]).status.success());
+    repo.write("b.txt", "b");
+    repo.add(".");
+    let second = repo.commit("second commit");
+    branch::create("later", repo.wd(), &second).unwrap();
+
+    let output = repo.wyag(&["refs", "import", "--prune", "snapshot.txt"]);
+
+    assert!(output.status.success());
+    assert_eq!(repo.branch("master"), Some(first));
+    assert_eq!(repo.branch("feature"), Some(first));
+    assert_eq!(repo.tag("v1"), Some(first));
+    assert_eq!(repo.branch("later"), None);
+    assert_eq!(repo.current_branch().as_deref(), Some("master"));
+}
+
+#[test]
+fn import_fails_without_objects() {
+    let repo = committed_repo();
+    let missing = blob_hash("not in the repo");
+    repo.write("snapshot.txt", &format!("{missing} refs/heads/master\n{missing} refs/heads/new\n"));
+
+    let output = repo.wyag(&["refs", "import", "snapshot.txt"]);
+
+    assert!(!output.status.success());
+    assert_eq!(repo.branch("master"), repo.head());
+    assert_eq!(repo.branch("new"), None);
+}
+
+#[test]
+fn rejects_invalid_namespaces() {
+    let repo = committed_repo();
+    let run = |namespace: &str| std::process::Command::new(env!("CARGO_BIN_EXE_wyag"))
+        .args(["show-ref"])
+        .env("GIT_NAMESPACE", namespace)
+        .current_dir(repo.path())
+        .output()
+        .unwrap();
+
+    for namespace in ["..", "a/../b", "a b", "x.lock", "a:b"] {
+        let output = run(namespace);
+        assert!(!output.status.success(), "{namespace}");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("GIT_NAMESPACE"), "{output:?}");
+    }
+    assert!(run("a/b").status.success());
+}
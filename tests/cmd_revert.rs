@@ -0,0 +1,90 @@
+mod common;
+use common::*;
+
+use wyag::object::Commit;
+
+#[test]
+fn revert_commits_newest_first() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n");
+    repo.add(".");
+    let initial = repo.commit("initial commit");
+    repo.write("b.txt", "b\n");
+    repo.add(".");
+    let add_b = repo.commit("add b");
+    repo.write("b.txt", "b2\n");
+    repo.add(".");
+    let change_b = repo.commit("change b");
+
+    repo.wyag_ok(&["revert", &format!("{initial}..HEAD")]);
+
+    let last = Commit::read(repo.wd(), &repo.head().unwrap()).unwrap();
+    assert_eq!(last.message(), format!("Revert \"add b\"\n\nThis reverts commit {add_b}.\n"));
+    let first = Commit::read(repo.wd(), &last.parents()[0]).unwrap();
+    assert_eq!(first.message(), format!("Revert \"change b\"\n\nThis reverts commit {change_b}.\n"));
+    assert_eq!(first.parents(), [change_b]);
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "a\n")]));
+    assert!(!repo.abs_path("b.txt").exists());
+}
+
+#[test]
+fn conflict_then_abort_or_continue() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "one\n");
+    repo.add(".");
+    repo.commit("initial commit");
+    repo.write("a.txt", "two\n");
+    repo.add(".");
+    let two = repo.commit("two");
+    repo.write("a.txt", "three\n");
+    repo.add(".");
+    let three = repo.commit("three");
+
+    // Undoing "two" conflicts with "three", which changed the same line
+    let output = repo.wyag(&["revert", &two.to_string()]);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Could not revert"));
+    assert!(repo.abs_path(".git/REVERT_HEAD").is_file());
+
+    repo.wyag_ok(&["revert", "--abort"]);
+    assert_eq!(repo.head(), Some(three));
+    assert_eq!(repo.read_file("a.txt"), "three\n");
+    assert!(!repo.abs_path(".git/REVERT_HEAD").exists());
+
+    assert_eq!(repo.wyag(&["revert", &two.to_string()]).status.code(), Some(1));
+    repo.write("a.txt", "one\nthree\n");
+    repo.wyag_ok(&["add", "a.txt"]);
+    repo.wyag_ok(&["revert", "--continue"]);
+
+    let head = Commit::read(repo.wd(), &repo.head().unwrap()).unwrap();
+    assert_eq!(head.message(), format!("Revert \"two\"\n\nThis reverts commit {two}.\n"));
+    assert_eq!(head.parents(), [three]);
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "one\nthree\n")]));
+    assert!(!repo.abs_path(".git/sequencer").exists());
+}
+
+#[test]
+fn revert_merge_with_mainline() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n");
+    repo.add(".");
+    let initial = repo.commit("initial commit");
+    repo.wyag_ok(&["branch", "side"]);
+    repo.wyag_ok(&["switch", "side"]);
+    repo.write("d.txt", "d\n");
+    repo.add(".");
+    repo.commit("add d");
+    repo.wyag_ok(&["switch", "master"]);
+    repo.wyag_ok(&["merge", "--no-ff", "side"]);
+    let merge = repo.head().unwrap();
+
+    assert!(!repo.wyag(&["revert", &merge.to_string()]).status.success());
+    repo.wyag_ok(&["revert", "-m", "1", &merge.to_string()]);
+
+    let head = Commit::read(repo.wd(), &repo.head().unwrap()).unwrap();
+    assert_eq!(
+        head.message(),
+        format!("Revert \"Merge branch 'side'\"\n\nThis reverts commit {merge}, reversing\nchanges made to {initial}.\n"),
+    );
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "a\n")]));
+}
@@ -1,58 +1,95 @@
 mod common;
 use common::*;
 
-use std::path::PathBuf;
+use wyag::index::IndexError;
 
-use wyag::{commands::{cmd_rm, RmArgs}, index::IndexError};
+/// A repository with these files committed.
+fn committed_repo() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write("x.txt", "x");
+    repo.write("a/b.txt", "a/b");
+    repo.write("a/c.txt", "a/c");
+    repo.write("y/x.txt", "y/x");
+    repo.add(".");
+    repo.commit("initial commit");
+
+    repo
+}
+
+/// Runs `wyag rm` on `path` through the library, like the command does.
+fn rm(repo: &TestRepo, path: &str) -> wyag::Result<()> {
+    let mut index = repo.index();
+    index.remove(repo.wd(), repo.abs_path(path))?;
+    index.write(repo.wd())
+}
 
 #[test]
 fn rm_file() {
-    let test_dir = setup("before_rm_file", false).unwrap();
+    let repo = committed_repo();
 
-    cmd_rm(RmArgs {
-        path: PathBuf::from("x.txt")
-    }).unwrap();
+    rm(&repo, "x.txt").unwrap();
 
-    assert_matches_snapshot(test_dir, "after_rm_file");
+    assert_eq!(repo.index_paths(), ["a/b.txt", "a/c.txt", "y/x.txt"]);
+    assert!(!repo.abs_path("x.txt").exists());
 }
 
 #[test]
 fn rm_directory() {
-    let test_dir = setup("before_rm_directory", false).unwrap();
+    let repo = committed_repo();
+    repo.write("a/b/c.txt", "a/b/c");
+    repo.write("a/b/c/d.txt", "a/b/c/d");
+    repo.add(".");
+    repo.commit("second commit");
 
-    cmd_rm(RmArgs {
-        path: PathBuf::from("a/b")
-    }).unwrap();
+    rm(&repo, "a/b").unwrap();
 
-    assert_matches_snapshot(test_dir, "after_rm_directory");
+    assert_eq!(repo.index_paths(), ["a/b.txt", "a/c.txt", "x.txt", "y/x.txt"]);
+    assert!(!repo.abs_path("a/b").exists());
+    assert!(repo.abs_path("a/b.txt").exists());
 }
 
 #[test]
 fn rm_rejects_unstaged_changes() {
-    let test_dir = setup("before_rm_rejects_unstaged_changes", false).unwrap();
+    let repo = committed_repo();
+    repo.write("a/b/c.txt", "a/b/c");
+    repo.write("a/b/d.txt", "a/b/d");
+    let index = repo.index_entries();
 
-    let err = cmd_rm(RmArgs {
-        path: PathBuf::from("a/b")
-    })
+    let err = rm(&repo, "a/b")
         .unwrap_err()
         .downcast::<IndexError>()
         .unwrap();
 
     assert!(matches!(err, IndexError::UncommittedChanges));
-    assert_matches_snapshot(test_dir, "after_rm_rejects_unstaged_changes");
+    assert_eq!(repo.index_entries(), index);
+    assert_eq!(repo.read_file("a/b/c.txt"), "a/b/c");
 }
 
 #[test]
 fn rm_rejects_staged_changes() {
-    let test_dir = setup("before_rm_rejects_staged_changes", false).unwrap();
+    let repo = committed_repo();
+    repo.write("a/b/c.txt", "a/b/c");
+    repo.write("a/b/d.txt", "a/b/d");
+    repo.add(".");
+    let index = repo.index_entries();
 
-    let err = cmd_rm(RmArgs {
-        path: PathBuf::from("a/b")
-    })
+    let err = rm(&repo, "a/b")
         .unwrap_err()
         .downcast::<IndexError>()
         .unwrap();
 
     assert!(matches!(err, IndexError::UncommittedChanges));
-    assert_matches_snapshot(test_dir, "after_rm_rejects_staged_changes");
+    assert_eq!(repo.index_entries(), index);
+    assert_eq!(repo.read_file("a/b/c.txt"), "a/b/c");
+}
+
+#[test]
+fn rm_glob() {
+    let repo = committed_repo();
+
+    let output = repo.wyag(&["rm", "*/x.txt"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(repo.index_paths(), ["a/b.txt", "a/c.txt", "x.txt"]);
+    assert!(!repo.abs_path("y/x.txt").exists());
 }
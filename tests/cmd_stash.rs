@@ -0,0 +1,93 @@
+mod common;
+use common::*;
+
+use wyag::{
+    object::{Commit, GitObject, ObjectHash, Tree},
+    reflog::{self, ReflogEntry},
+};
+
+/// A repository with one commit of `a.txt`.
+fn committed_repo() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "one\n");
+    repo.add(".");
+    repo.commit("initial commit");
+
+    repo
+}
+
+/// Stashes `a.txt` with `contents` on top of HEAD, the way `git stash` records it: a
+/// commit whose first parent is HEAD, at `refs/stash` with a reflog entry. The working
+/// tree and index are left as they were.
+fn stash(repo: &TestRepo, contents: &str, message: &str) -> ObjectHash {
+    let mut index = repo.index();
+    repo.write("a.txt", contents);
+    index.add(repo.wd(), repo.abs_path("a.txt"), false).unwrap();
+    let (tree, _) = Tree::create_from_index(&index, repo.wd()).unwrap();
+    repo.write("a.txt", "one\n");
+
+    let head = repo.head().unwrap();
+    let text = format!("tree {tree}\nparent {head}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\n{message}\n");
+    let hash = GitObject::Commit(Commit::deserialize(text.into_bytes()).unwrap()).write(repo.wd()).unwrap();
+
+    let mut entries = reflog::read(repo.wd(), "refs/stash").unwrap();
+    entries.push(ReflogEntry {
+        old: entries.last().map_or(ObjectHash::new([]), |entry| entry.new),
+        new: hash,
+        identity: "A <a@example.com>".to_owned(),
+        timestamp: 0,
+        timezone: "+0000".to_owned(),
+        message: message.to_owned(),
+    });
+    reflog::write(repo.wd(), "refs/stash", &entries).unwrap();
+    repo.write(".git/refs/stash", &format!("{hash}\n"));
+
+    hash
+}
+
+fn stdout(output: &std::process::Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn list_stashes_most_recent_first() {
+    let repo = committed_repo();
+    stash(&repo, "two\n", "On main: first");
+    stash(&repo, "three\n", "On main: second");
+
+    assert_eq!(stdout(&repo.wyag(&["stash", "list"])), "stash@{0}: On main: second\nstash@{1}: On main: first\n");
+}
+
+#[test]
+fn show_stash_as_stat_and_patch() {
+    let repo = committed_repo();
+    stash(&repo, "two\n", "On main: first");
+    stash(&repo, "one\nthree\n", "On main: second");
+
+    let stat = stdout(&repo.wyag(&["stash", "show"]));
+    assert!(stat.contains("a.txt | 1 +"), "{stat}");
+
+    let patch = stdout(&repo.wyag(&["stash", "show", "-p", "stash@{1}"]));
+    assert!(patch.contains("-one\n+two\n"), "{patch}");
+
+    assert!(!repo.wyag(&["stash", "show", "2"]).status.success());
+    assert!(!repo.wyag(&["stash", "show", "18446744073709551615"]).status.success());
+}
+
+#[test]
+fn drop_stash_moves_ref_to_next_entry() {
+    let repo = committed_repo();
+    let first = stash(&repo, "two\n", "On main: first");
+    let second = stash(&repo, "three\n", "On main: second");
+
+    assert_eq!(stdout(&repo.wyag(&["stash", "drop"])), format!("Dropped refs/stash@{{0}} ({second})\n"));
+    assert_eq!(repo.read_file(".git/refs/stash"), format!("{first}\n"));
+    assert_eq!(stdout(&repo.wyag(&["stash", "list"])), "stash@{0}: On main: first\n");
+
+    // Dropping the last entry deletes the stash
+    assert!(repo.wyag(&["stash", "drop", "stash@{0}"]).status.success());
+    assert!(!repo.abs_path(".git/refs/stash").exists());
+    assert!(!repo.abs_path(".git/logs/refs/stash").exists());
+    assert!(!repo.wyag(&["stash", "drop"]).status.success());
+}
@@ -0,0 +1,53 @@
+mod common;
+use common::*;
+
+use wyag::summary::RepoStats;
+
+#[test]
+fn counts_objects_refs_and_files() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.write("sub/b.txt", "b");
+    repo.add("a.txt");
+    repo.add("sub/b.txt");
+    repo.commit("First");
+    repo.wyag_ok(&["tag", "v1"]);
+    repo.write("untracked.txt", "c");
+    repo.write(".git/objects/pack/pack-1.pack", "");
+    repo.write(".git/objects/pack/pack-1.idx", "");
+
+    let stats = repo.repo().stats().unwrap();
+    // Two blobs, two trees, and the commit
+    assert_eq!(stats.loose_objects, 5);
+    let loose_size: u64 = std::fs::read_dir(repo.abs_path(".git/objects")).unwrap()
+        .map(|dir| dir.unwrap())
+        .filter(|dir| dir.file_name().len() == 2)
+        .flat_map(|dir| std::fs::read_dir(dir.path()).unwrap())
+        .map(|file| file.unwrap().metadata().unwrap().len())
+        .sum();
+    assert_eq!(stats.loose_size, loose_size);
+    assert_eq!(stats.packs, 1);
+    assert_eq!(stats.refs, 2);
+    assert_eq!(stats.index_entries, 2);
+    assert_eq!(stats.worktree_files, 3);
+}
+
+#[test]
+fn empty_repository() {
+    let repo = TestRepo::new();
+
+    assert_eq!(RepoStats::collect(repo.repo()).unwrap(), RepoStats {
+        loose_objects: 0,
+        loose_size: 0,
+        packs: 0,
+        refs: 0,
+        index_entries: 0,
+        worktree_files: 0,
+    });
+
+    let output = repo.wyag_ok(&["stats", "--json"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        r#"{"loose_objects":0,"loose_size":0,"packs":0,"refs":0,"index_entries":0,"worktree_files":0}"#,
+    );
+}
@@ -0,0 +1,29 @@
+mod common;
+use common::*;
+
+#[test]
+fn strict_refuses_a_damaged_repository() {
+    let repo = TestRepo::new();
+    repo.wyag_ok(&["--strict", "status"]);
+
+    let config = repo.read_file(".git/config").replacen("[core]", "[core]\nignoreCase = maybe", 1);
+    repo.write(".git/config", &config);
+
+    repo.wyag_ok(&["status"]);
+    let output = repo.wyag(&["--strict", "status"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("integrity check"), "{output:?}");
+}
+
+#[test]
+fn strict_runs_commands_outside_a_repository() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_wyag"))
+        .args(["--strict", "init", "repo"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(dir.path().join("repo/.git").is_dir());
+}
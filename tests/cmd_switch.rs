@@ -1,68 +1,109 @@
 mod common;
 use common::*;
 
-use wyag::commands::{cmd_switch, SwitchArgs};
+use wyag::branch;
+
+/// A repository with one commit on master and a `test_branch` at the same commit.
+fn repo_with_branch() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.write("b.txt", "b");
+    repo.add(".");
+    let head = repo.commit("initial commit");
+    branch::create("test_branch", repo.wd(), &head).unwrap();
+
+    repo
+}
 
-/***** These tests fail because of creation/modification time differences in the index -_-
 #[test]
 fn switch_to_new_branch() {
-    let test_dir = setup("before_switch_to_new_branch", false).unwrap();
+    let repo = repo_with_branch();
 
-    cmd_switch(SwitchArgs {
-        detach: false,
-        branch_or_commit: "test_branch".to_owned(),
-    }).unwrap();
+    let output = repo.wyag(&["switch", "test_branch"]);
 
-    assert_matches_snapshot(test_dir, "after_switch_to_new_branch");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(repo.current_branch().as_deref(), Some("test_branch"));
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "a"), ("b.txt", "b")]));
 }
 
 #[test]
 fn switch_to_existing_branch() {
-    let test_dir = setup("before_switch_to_existing_branch", false).unwrap();
-
-    cmd_switch(SwitchArgs {
-        detach: false,
-        branch_or_commit: "test_branch".to_owned(),
-    }).unwrap();
-
-    assert_matches_snapshot(test_dir, "after_switch_to_existing_branch");
+    let repo = repo_with_branch();
+    assert!(repo.wyag(&["switch", "test_branch"]).status.success());
+    repo.write("c/d/e.txt", "c/d/e");
+    repo.add(".");
+    repo.commit("second commit to test_branch");
+    assert!(repo.wyag(&["switch", "master"]).status.success());
+    assert!(!repo.abs_path("c").exists());
+
+    let output = repo.wyag(&["switch", "test_branch"]);
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(repo.current_branch().as_deref(), Some("test_branch"));
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "a"), ("b.txt", "b"), ("c/d/e.txt", "c/d/e")]));
+    assert_eq!(repo.read_file("c/d/e.txt"), "c/d/e");
 }
 
 #[test]
 fn switch_to_headless() {
-    let test_dir = setup("before_switch_to_headless", false).unwrap();
-
-    cmd_switch(SwitchArgs {
-        detach: true,
-        branch_or_commit: "starting_point".to_owned(),
-    }).unwrap();
-
-    assert_matches_snapshot(test_dir, "after_switch_to_headless");
+    let repo = repo_with_branch();
+    let first = repo.head().unwrap();
+    repo.write("c/d/e.txt", "c/d/e");
+    repo.add(".");
+    repo.commit("second commit to master");
+
+    let output = repo.wyag(&["switch", "--detach", &first.to_string()]);
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(repo.current_branch(), None);
+    assert_eq!(repo.head(), Some(first));
+    assert!(!repo.abs_path("c/d/e.txt").exists());
 }
-*****/
 
 #[test]
 fn switch_fails_with_unstaged_changes() {
-    let test_dir = setup("before_switch_fails_with_unstaged_changes", false).unwrap();
+    let repo = repo_with_branch();
+    repo.write("a.txt", "a*");
 
-    let result = cmd_switch(SwitchArgs {
-        detach: false,
-        branch_or_commit: "test_branch".to_owned(),
-    });
+    let output = repo.wyag(&["switch", "test_branch"]);
 
-    assert!(result.is_err());
-    assert_matches_snapshot(test_dir, "after_switch_fails_with_unstaged_changes");
+    assert!(!output.status.success());
+    assert_eq!(repo.current_branch().as_deref(), Some("master"));
+    assert_eq!(repo.read_file("a.txt"), "a*");
 }
 
 #[test]
 fn switch_fails_with_staged_changes() {
-    let test_dir = setup("before_switch_fails_with_staged_changes", false).unwrap();
+    let repo = repo_with_branch();
+    repo.write("a.txt", "a*");
+    repo.add(".");
+
+    let output = repo.wyag(&["switch", "test_branch"]);
 
-    let result = cmd_switch(SwitchArgs {
-        detach: false,
-        branch_or_commit: "test_branch".to_owned(),
-    });
+    assert!(!output.status.success());
+    assert_eq!(repo.current_branch().as_deref(), Some("master"));
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "a*"), ("b.txt", "b")]));
+}
 
-    assert!(result.is_err());
-    assert_matches_snapshot(test_dir, "after_switch_fails_with_staged_changes");
+#[test]
+fn switch_without_checkout() {
+    let repo = repo_with_branch();
+    repo.wyag_ok(&["switch", "test_branch"]);
+    repo.write("c.txt", "c");
+    repo.add(".");
+    repo.commit("add c on test_branch");
+    repo.wyag_ok(&["switch", "master"]);
+    // Uncommitted changes don't get in the way
+    repo.write("a.txt", "a*");
+
+    repo.wyag_ok(&["switch", "--no-checkout", "test_branch"]);
+
+    assert_eq!(repo.current_branch().as_deref(), Some("test_branch"));
+    assert_eq!(repo.index_entries(), entries(&[("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")]));
+    // The working tree is left exactly as it was
+    assert_eq!(repo.read_file("a.txt"), "a*");
+    assert!(!repo.abs_path("c.txt").exists());
+
+    assert!(!repo.wyag(&["switch", "--no-checkout", "missing"]).status.success());
+    assert_eq!(repo.current_branch().as_deref(), Some("test_branch"));
 }
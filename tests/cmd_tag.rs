@@ -1,50 +1,55 @@
 mod common;
 use common::*;
 
-use wyag::commands::{cmd_tag, TagArgs};
+use wyag::object::{GitObject, ObjectMetadata, Tag};
+
+/// A repository with one commit on master.
+fn committed_repo() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.add(".");
+    repo.commit("initial commit");
+
+    repo
+}
 
 #[test]
 fn create_lightweight_tag() {
-    let test_dir = setup("before_create_lightweight_tag", false).unwrap();
+    let repo = committed_repo();
 
-    cmd_tag(TagArgs {
-        annotate: false,
-        delete: false,
-        name: Some("test_tag".to_owned()),
-        object: "HEAD".to_owned(),
-        message: "".to_owned(),
-    }).unwrap();
+    Tag::create_lightweight(repo.wd(), "test_tag", &repo.head().unwrap()).unwrap();
 
-    assert_matches_snapshot(test_dir, "after_create_lightweight_tag");
+    assert_eq!(repo.tag("test_tag"), repo.head());
 }
 
-
 #[test]
 fn create_annotated_tag() {
-    let test_dir = setup("before_create_annotated_tag", false).unwrap();
-
-    cmd_tag(TagArgs {
-        annotate: true,
-        delete: false,
-        name: Some("test_tag".to_owned()),
-        object: "HEAD".to_owned(),
-        message: "this is the message".to_owned(),
-    }).unwrap();
-
-    assert_matches_snapshot(test_dir, "after_create_annotated_tag");
+    let repo = committed_repo();
+    let meta = ObjectMetadata::new(repo.repo(), "this is the message".to_owned()).unwrap();
+
+    Tag::create(repo.wd(), "test_tag", &repo.head().unwrap(), meta).unwrap();
+
+    let hash = repo.tag("test_tag").unwrap();
+    assert!(repo.has_object(&hash));
+    let GitObject::Tag(tag) = GitObject::read(repo.wd(), &hash).unwrap() else {
+        panic!("test_tag doesn't point to a tag object");
+    };
+    assert_eq!(tag.target().unwrap(), repo.head().unwrap());
+    assert_eq!(tag.name(), Some("test_tag"));
+    assert_eq!(tag.tagger(), Some("User Name <user@example.com>"));
+    assert_eq!(tag.message(), "this is the message");
 }
 
 #[test]
 fn delete_tag() {
-    let test_dir = setup("before_delete_tag", false).unwrap();
+    let repo = committed_repo();
+    let meta = ObjectMetadata::new(repo.repo(), "this is the message".to_owned()).unwrap();
+    Tag::create(repo.wd(), "test_tag", &repo.head().unwrap(), meta).unwrap();
+    let hash = repo.tag("test_tag").unwrap();
 
-    cmd_tag(TagArgs {
-        annotate: false,
-        delete: true,
-        name: Some("test_tag".to_owned()),
-        object: "HEAD".to_owned(),
-        message: "".to_owned(),
-    }).unwrap();
+    Tag::delete(repo.wd(), "test_tag").unwrap();
 
-    assert_matches_snapshot(test_dir, "after_delete_tag");
+    assert_eq!(repo.tag("test_tag"), None);
+    // The tag object itself is left for prune
+    assert!(repo.has_object(&hash));
 }
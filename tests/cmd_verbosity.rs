@@ -0,0 +1,40 @@
+mod common;
+use common::*;
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn quiet_leaves_out_progress() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.add("a.txt");
+    repo.commit("First");
+    repo.wyag_ok(&["branch", "topic"]);
+    repo.write("a.txt", "b");
+    repo.add("a.txt");
+    repo.commit("Second");
+    repo.wyag_ok(&["switch", "topic"]);
+
+    let output = repo.wyag_ok(&["-q", "merge", "master"]);
+    assert_eq!(stdout(&output), "");
+    let output = repo.wyag_ok(&["merge", "master"]);
+    assert!(stdout(&output).contains("Already up to date."), "{output:?}");
+}
+
+#[test]
+fn verbose_lists_what_prune_removes() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.wyag_ok(&["hash-object", "-w", "a.txt"]);
+    let output = repo.wyag_ok(&["prune", "--expire", "all"]);
+    assert_eq!(stdout(&output), "");
+    assert!(!repo.has_object(&blob_hash("a")));
+
+    repo.write("b.txt", "b");
+    repo.wyag_ok(&["hash-object", "-w", "b.txt"]);
+    let output = repo.wyag_ok(&["-v", "prune", "--expire", "all"]);
+    assert_eq!(stdout(&output).trim(), blob_hash("b").to_string());
+    assert!(!repo.has_object(&blob_hash("b")));
+}
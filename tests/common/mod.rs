@@ -1,79 +1,177 @@
-use std::{fs::{self, File}, path::{PathBuf, Path}};
+//! Builds repositories step by step in temporary directories and reads back their state
+//! (index entries, refs, and objects) for tests to assert on. Nothing here changes the
+//! process's current directory, so tests can run in parallel.
+
+#![allow(dead_code)] // each test binary only uses some of the helpers
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Output,
+};
 
 pub use assert_fs::{prelude::*, TempDir};
 
-use anyhow::{Result, Context};
+use wyag::{
+    branch::{self, Branch},
+    consts::{BRANCH_CATEGORY, TAG_CATEGORY},
+    index::Index,
+    object::{Commit, ObjectFormat, ObjectHash, ObjectMetadata, ObjectStore},
+    refs,
+    repo::Repository,
+    workdir::WorkDir,
+};
 
-pub fn assert_matches_snapshot<P>(actual: P, snapshot: &str)
-where
-    P: AsRef<Path>
-{
-    let snapshot = unpack_snapshot(snapshot, false).unwrap();
-    assert_paths_match(actual, &snapshot);
+/// A repository in a temporary directory, deleted when it's dropped.
+pub struct TestRepo {
+    dir: TempDir,
+    repo: Repository,
 }
 
-pub fn assert_paths_match<P1, P2>(actual: P1, expected: P2)
-where
-    P1: AsRef<Path>,
-    P2: AsRef<Path>,
-{
-    assert!(!dir_diff::is_different(actual, expected).unwrap());
-}
+impl TestRepo {
+    /// Initializes an empty repository with `user.name` and `user.email` set, so commands
+    /// run in it can make commits and tags.
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        Repository::init(dir.path()).unwrap();
 
-/// Creates a temporary directory containing a copy of the named snapshot and `cd`s into it.
-/// 
-/// If `make_subdir` is true, the snapshot will be copied into the subdirectory `name` instead.
-/// However, it will still `cd` into the enclosing temporary directory.
-pub fn setup(name: &str, make_subdir: bool) -> Result<TempDir> {
-    let temp_dir = unpack_snapshot(name, make_subdir)
-        .context("Error setting up test environment: failed to unpack snapshot")?;
-    std::env::set_current_dir(&temp_dir)
-        .context("Error setting up test environment: failed to cd into temporary directory")?;
-
-    Ok(temp_dir)
-}
+        let mut config = fs::read_to_string(dir.join(".git/config")).unwrap();
+        config.push_str("[user]\nname = User Name\nemail = user@example.com\n");
+        fs::write(dir.join(".git/config"), config).unwrap();
+        let repo = Repository::from_existing(dir.path()).unwrap();
+
+        Self { dir, repo }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Returns the absolute path of `path`, which is relative to the working directory.
+    pub fn abs_path(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    pub fn repo(&self) -> &Repository {
+        &self.repo
+    }
+
+    pub fn wd(&self) -> &WorkDir {
+        self.repo.workdir()
+    }
+
+    /// Writes a file in the working directory, creating its parent directories.
+    pub fn write(&self, path: &str, contents: &str) {
+        self.dir.child(path).write_str(contents).unwrap();
+    }
+
+    /// Deletes a file or directory in the working directory.
+    pub fn remove(&self, path: &str) {
+        let abs_path = self.abs_path(path);
+        match abs_path.is_dir() {
+            true => fs::remove_dir_all(abs_path).unwrap(),
+            false => fs::remove_file(abs_path).unwrap(),
+        }
+    }
+
+    /// Stages `path`, like `wyag add`.
+    pub fn add(&self, path: &str) {
+        let mut index = self.index();
+        index.add(self.wd(), self.abs_path(path), false).unwrap();
+        index.write(self.wd()).unwrap();
+    }
+
+    /// Commits what's staged to the current branch, like `wyag commit -m`.
+    pub fn commit(&self, message: &str) -> ObjectHash {
+        let meta = ObjectMetadata::new(&self.repo, message.to_owned()).unwrap();
+        Commit::create(&self.index(), self.wd(), meta).unwrap()
+    }
+
+    /// Runs wyag in the working directory as a separate process, for commands whose logic
+    /// isn't exposed by the library. The editor is only taken from core.editor, not from the
+    /// environment the tests run in.
+    pub fn wyag(&self, args: &[&str]) -> Output {
+        std::process::Command::new(env!("CARGO_BIN_EXE_wyag"))
+            .args(args)
+            .env_remove("GIT_EDITOR")
+            .env_remove("VISUAL")
+            .env_remove("EDITOR")
+            .current_dir(self.path())
+            .output()
+            .unwrap()
+    }
 
-/// Creates an empty temporary directory and `cd`s into it.
-#[allow(dead_code)] // not actually dead, but `cargo test` thinks it is
-pub fn setup_empty() -> Result<TempDir> {
-    let temp_dir = TempDir::new()
-        .with_context(|| "Error setting up test environment: failed to create temporary directory".to_string())?;
-    std::env::set_current_dir(&temp_dir)
-        .context("Error setting up test environment: failed to cd into temporary directory")?;
+    /// Runs wyag as with [`TestRepo::wyag`], failing the test (with its stderr) if the
+    /// command fails.
+    pub fn wyag_ok(&self, args: &[&str]) -> Output {
+        let output = self.wyag(args);
+        assert!(output.status.success(), "wyag {}: {}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+        output
+    }
+
+    /// Reads the index, or returns an empty one if nothing has been staged yet.
+    pub fn index(&self) -> Index {
+        match self.abs_path(".git/index").is_file() {
+            true => self.repo.index().unwrap(),
+            false => Index::new(None),
+        }
+    }
 
-    Ok(temp_dir)
+    /// Lists the paths in the index and the blobs staged for them, in order.
+    pub fn index_entries(&self) -> Vec<(String, ObjectHash)> {
+        self.index().entries.iter()
+            .map(|(path, entry)| (path.to_string(), entry.hash))
+            .collect()
+    }
+
+    /// Lists the paths in the index, in order.
+    pub fn index_paths(&self) -> Vec<String> {
+        self.index_entries().into_iter().map(|(path, _)| path).collect()
+    }
+
+    /// Returns the commit at the tip of a branch, if it exists.
+    pub fn branch(&self, name: &str) -> Option<ObjectHash> {
+        refs::resolve(self.wd(), BRANCH_CATEGORY, name).ok()
+    }
+
+    /// Returns the object a tag points to, if it exists.
+    pub fn tag(&self, name: &str) -> Option<ObjectHash> {
+        refs::resolve(self.wd(), TAG_CATEGORY, name).ok()
+    }
+
+    /// Returns the name of the checked-out branch, or `None` if HEAD is detached.
+    pub fn current_branch(&self) -> Option<String> {
+        match branch::get_current(self.wd()).unwrap() {
+            Branch::Named(name) | Branch::Unborn(name) => Some(name),
+            Branch::Ref(_) | Branch::Headless(_) => None,
+        }
+    }
+
+    /// Returns the commit HEAD points to, if any.
+    pub fn head(&self) -> Option<ObjectHash> {
+        branch::get_current(self.wd()).unwrap().tip(self.wd()).unwrap()
+    }
+
+    /// Returns true if the object is stored in the repository.
+    pub fn has_object(&self, hash: &ObjectHash) -> bool {
+        ObjectStore::open(self.wd()).unwrap().contains(hash)
+    }
+
+    /// Reads a file in the working directory.
+    pub fn read_file(&self, path: &str) -> String {
+        fs::read_to_string(self.abs_path(path)).unwrap()
+    }
 }
 
-/// Unpacks the snapshot from `name`.7z in the snapshots directory to a temporary directory.
-/// 
-/// If `make_subdir` is true, the snapshot will be unpacked into a subdirectory called `name`.
-pub fn unpack_snapshot(name: &str, make_subdir: bool) -> Result<TempDir> {
-    let temp_dir = TempDir::new()
-        .with_context(|| format!("Error unpacking snapshot `{name}`: failed to create temporary directory"))?;
-
-    let archive_path: PathBuf = snapshot_path(name);
-    let archive_file = File::open(archive_path)
-        .with_context(|| format!("Error unpacking snapshot `{name}`: no archive with that name"))?;
-
-    if make_subdir {
-        let subdir = temp_dir.child(name);
-        fs::create_dir(&subdir)
-            .with_context(|| format!("Error unpacking snapshot `{name}`: failed to create subdirectory"))?;
-        sevenz_rust::decompress(archive_file, &subdir)
-            .with_context(|| format!("Error unpacking snapshot `{name}`: failed to extract archive"))?;
-    } else {
-        sevenz_rust::decompress(archive_file, &temp_dir)
-            .with_context(|| format!("Error unpacking snapshot `{name}`: failed to extract archive"))?;
-    }
-
-    Ok(temp_dir)
+/// Returns the hash a blob with these contents would have.
+pub fn blob_hash(contents: &str) -> ObjectHash {
+    ObjectHash::for_object(ObjectFormat::Blob, contents.as_bytes())
 }
 
-/// Appends the path "snapshots/`name`.7z" to the directory containing Cargo.toml.
-fn snapshot_path(name: &str) -> PathBuf {
-    [
-        env!("CARGO_MANIFEST_DIR"),
-        "snapshots",
-        &format!("{name}.7z"),
-    ].iter().collect()
+/// Pairs each path with the hash of a blob with the given contents, for comparing to
+/// [`TestRepo::index_entries`].
+pub fn entries(files: &[(&str, &str)]) -> Vec<(String, ObjectHash)> {
+    files.iter()
+        .map(|(path, contents)| (path.to_string(), blob_hash(contents)))
+        .collect()
 }